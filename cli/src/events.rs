@@ -0,0 +1,61 @@
+use db::WatchEvent;
+use std::io::{self, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+/// Where watch events are written, as one line of JSON per event.
+#[derive(Clone)]
+pub enum EventStream {
+    Stdout,
+    /// Every client connected to a Unix socket. Clients that disconnect
+    /// are dropped the next time an event is sent.
+    Socket(Arc<Mutex<Vec<UnixStream>>>),
+}
+
+impl EventStream {
+    /// Stream to stdout for `-`, otherwise listen on a Unix socket at
+    /// `destination`, replacing any socket that's already there.
+    pub fn open(destination: &str) -> io::Result<Self> {
+        if destination == "-" {
+            return Ok(EventStream::Stdout);
+        }
+        let path = Path::new(destination);
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        let listener = UnixListener::bind(path)?;
+        let clients = Arc::new(Mutex::new(vec![]));
+        let accepted = clients.clone();
+        std::thread::spawn(move || {
+            for client in listener.incoming().flatten() {
+                accepted.lock().unwrap().push(client);
+            }
+        });
+        Ok(EventStream::Socket(clients))
+    }
+
+    /// Whether events are written to stdout, so nothing else should be.
+    pub fn is_stdout(&self) -> bool {
+        matches!(self, EventStream::Stdout)
+    }
+
+    pub fn send(&self, event: WatchEvent) {
+        let line = format!("{}\n", event.to_json());
+        match self {
+            EventStream::Stdout => {
+                let mut stdout = io::stdout();
+                // There's nowhere left to report it if stdout is gone
+                let _ = stdout
+                    .write_all(line.as_bytes())
+                    .and_then(|_| stdout.flush());
+            }
+            EventStream::Socket(clients) => {
+                clients
+                    .lock()
+                    .unwrap()
+                    .retain_mut(|client| client.write_all(line.as_bytes()).is_ok());
+            }
+        }
+    }
+}
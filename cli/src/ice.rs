@@ -0,0 +1,183 @@
+//! Internal compiler errors, which are panics in the compiler instead of
+//! problems with the code it's compiling. They're written up as a report
+//! that can be attached to an issue, instead of leaving users with a raw
+//! panic message.
+use db::Phase;
+use std::backtrace::Backtrace;
+use std::fmt::Display;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+#[derive(Default)]
+struct State {
+    /// The module being compiled
+    module: Option<PathBuf>,
+    /// The phase it's in
+    phase: Option<Phase>,
+    /// The definition in it that's being compiled, and where it is
+    definition: Option<(String, Range<usize>)>,
+    /// Where the report was written, once there's been a panic
+    report: Option<Result<PathBuf, String>>,
+}
+
+/// Keeps track of where the compiler is, from the events it reports, so a
+/// panic can say what it was doing.
+#[derive(Clone, Default)]
+pub struct IceContext {
+    state: Arc<Mutex<State>>,
+}
+
+impl IceContext {
+    pub fn phase_started(&self, path: &Path, phase: Phase) {
+        let mut state = self.lock();
+        if state.module.as_deref() != Some(path) {
+            state.module = Some(path.to_path_buf());
+            state.definition = None;
+        }
+        state.phase = Some(phase);
+    }
+
+    pub fn definition_started(&self, path: &Path, name: &str, range: Range<usize>) {
+        let mut state = self.lock();
+        if state.module.as_deref() != Some(path) {
+            state.module = Some(path.to_path_buf());
+            state.phase = None;
+        }
+        state.definition = Some((name.to_string(), range));
+    }
+
+    /// Replace the panic message with a report written to a file. The
+    /// backtrace is captured here, since it's gone once the panic unwinds.
+    pub fn install_hook(&self) {
+        let context = self.clone();
+        std::panic::set_hook(Box::new(move |info| {
+            let message = info
+                .payload()
+                .downcast_ref::<&str>()
+                .map(|message| message.to_string())
+                .or_else(|| info.payload().downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "Box<dyn Any>".to_string());
+            let location = info
+                .location()
+                .map_or("unknown".to_string(), |location| location.to_string());
+            let mut state = context.lock();
+            let report = state.render_report(&message, &location, Backtrace::force_capture());
+            state.report = Some(write_report(&report).map_err(|_| report));
+        }));
+    }
+
+    /// Tell the user the compiler crashed, and where the report is.
+    pub fn report(&self) {
+        let state = self.lock();
+        match &state.module {
+            Some(path) => eprintln!(
+                "The compiler ran into a bug while compiling '{}'. Sorry about that!",
+                path.display()
+            ),
+            None => eprintln!("The compiler ran into a bug. Sorry about that!"),
+        }
+        match &state.report {
+            Some(Ok(path)) => eprintln!(
+                "A report was written to '{}', please include it when you open an issue.",
+                path.display()
+            ),
+            // There's nowhere else to put it
+            Some(Err(report)) => eprintln!(
+                "Please include this report when you open an issue:\n\n{}",
+                report
+            ),
+            None => {}
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        // A panic while the state is locked still leaves it usable
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl State {
+    /// The report for a panic with `message` at `location`.
+    fn render_report(&self, message: &str, location: &str, backtrace: impl Display) -> String {
+        let file = self
+            .module
+            .as_ref()
+            .map_or("unknown".to_string(), |path| path.display().to_string());
+        let phase = self
+            .phase
+            .map_or("unknown".to_string(), |phase| format!("{:?}", phase));
+        // Imports, and anything before the first definition, have no name
+        let definition = match &self.definition {
+            Some((name, range)) if name.is_empty() => format!("imports at {:?}", range),
+            Some((name, range)) => format!("'{}' at {:?}", name, range),
+            None => "unknown".to_string(),
+        };
+        format!(
+            "Internal compiler error: {}\n\n\
+             Compiler version: {}\n\
+             File: {}\n\
+             Phase: {}\n\
+             Definition: {}\n\
+             Panicked at: {}\n\n\
+             Backtrace:\n{}\n",
+            message,
+            env!("CARGO_PKG_VERSION"),
+            file,
+            phase,
+            definition,
+            location,
+            backtrace
+        )
+    }
+}
+
+/// Write `report` to a new file in the temporary directory.
+fn write_report(report: &str) -> std::io::Result<PathBuf> {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |time| time.as_millis());
+    let path = std::env::temp_dir().join(format!("ws-ice-{}.txt", time));
+    std::fs::write(&path, report)?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IceContext;
+    use db::Phase;
+    use std::path::Path;
+
+    #[test]
+    fn render_report() {
+        let context = IceContext::default();
+        let report = || {
+            context
+                .lock()
+                .render_report("oops", "src/lib.rs:1:1", "<backtrace>")
+        };
+        assert!(report().contains("File: unknown\nPhase: unknown\nDefinition: unknown\n"));
+        let path = Path::new("ui/button.ws");
+        context.definition_started(path, "Button", 12..80);
+        context.phase_started(path, Phase::Codegen);
+        assert_eq!(
+            report(),
+            format!(
+                "Internal compiler error: oops\n\n\
+                 Compiler version: {}\n\
+                 File: ui/button.ws\n\
+                 Phase: Codegen\n\
+                 Definition: 'Button' at 12..80\n\
+                 Panicked at: src/lib.rs:1:1\n\n\
+                 Backtrace:\n<backtrace>\n",
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+        // The definition was in another module
+        context.phase_started(Path::new("ui/list.ws"), Phase::Parse);
+        assert!(report().contains("File: ui/list.ws\nPhase: Parse\nDefinition: unknown\n"));
+    }
+}
@@ -0,0 +1,65 @@
+use async_std::fs;
+use db::OUTPUT_PATH;
+use std::io;
+use std::path::Path;
+
+/// The files a new project starts with. `{name}` is replaced with the
+/// project's name and `{output}` with where its compiled output goes.
+const PROJECT_FILES: &[(&str, &str)] = &[
+    ("ws.toml", include_str!("../templates/ws.toml")),
+    ("main.ws", include_str!("../templates/main.ws")),
+    ("index.html", include_str!("../templates/index.html")),
+];
+
+/// Create a project named `name` in a new directory of the same name.
+pub async fn create_project(name: &str) -> io::Result<()> {
+    let root = Path::new(name);
+    if root.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("'{}' already exists", root.display()),
+        ));
+    }
+    fs::create_dir_all(root).await?;
+    let name = root
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(name);
+    for (filename, template) in PROJECT_FILES {
+        let contents = template
+            .replace("{name}", name)
+            .replace("{output}", OUTPUT_PATH);
+        fs::write(root.join(filename), contents).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::create_project;
+    use async_std::task::block_on;
+    use db::OUTPUT_PATH;
+    use std::io;
+
+    #[test]
+    fn create_projects() {
+        let directory = std::env::temp_dir().join(format!("ws-init-{}", std::process::id()));
+        let root = directory.join("counter");
+        let name = root.to_str().unwrap();
+        block_on(create_project(name)).unwrap();
+        let read = |filename: &str| std::fs::read_to_string(root.join(filename)).unwrap();
+        // The project's named after its directory, not the whole path
+        assert!(read("ws.toml").contains("name = \"counter\""));
+        assert!(read("main.ws").contains("Hello from counter!"));
+        let index = read("index.html");
+        assert!(index.contains("<title>counter</title>"));
+        assert!(index.contains(&format!("from \"./{}\"", OUTPUT_PATH)));
+        assert!(!index.contains("{name}") && !index.contains("{output}"));
+        // Nothing in an existing directory is overwritten
+        std::fs::write(root.join("main.ws"), "fn a() { 1 }").unwrap();
+        let error = block_on(create_project(name)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(read("main.ws"), "fn a() { 1 }");
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -1,21 +1,40 @@
 use async_std::fs;
 use clap::Clap;
 use db::*;
+use diagnostics::color::ColorMode;
+use diagnostics::error::{report_diagnostic_to_term, Diagnostic, Error, Severity};
 use log::debug;
+use std::ops::Range;
 /// The CLI binary for invoking the compiler.
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::mpsc;
-
-use walkdir::WalkDir;
+use std::time::Instant;
 
 use notify::{RecommendedWatcher, RecursiveMode, Watcher};
 
-/// The canonical entry point for a program
+mod events;
+mod ice;
+mod init;
+mod terminal;
+
+use events::EventStream;
+use ice::IceContext;
+use terminal::{BuildSummary, DiagnosticBuffer, Output, RenderedDiagnostic, WatchScreen};
+
+/// The entry point for a program, unless another one is given
 const ENTRYPOINT_FILENAME: &'static str = "main.ws";
 
 #[derive(Clap)]
 
 struct Opts {
+    /// Trace what each phase of the compiler is doing. Repeat for more
+    /// detail, or set RUST_LOG to filter the trace.
+    #[clap(short, long, parse(from_occurrences))]
+    verbose: u8,
+    /// Whether to use colors: always, never, or auto to use them on a
+    /// terminal unless NO_COLOR is set
+    #[clap(long, default_value = "auto")]
+    color: ColorMode,
     #[clap(subcommand)]
     subcmd: Commands,
 }
@@ -24,58 +43,558 @@ struct Opts {
 enum Commands {
     Build(BuildOptions),
     Watch(WatchOptions),
+    Init(InitOptions),
+    Clean(CleanOptions),
 }
 
 #[derive(Clap)]
 struct BuildOptions {
+    /// The project's directory, or the module to start compiling from
     #[clap(short, long)]
     path: String,
+    /// The file to start compiling from, relative to the path. Defaults to main.ws
+    #[clap(short, long)]
+    entry: Option<String>,
+    /// Compile every module in the path as a library, with an index that
+    /// re-exports their public definitions. Libraries don't have an entry.
+    #[clap(long, conflicts_with = "entry")]
+    lib: bool,
+    /// Annotate the compiled output with the source line of each statement
+    #[clap(long)]
+    debug_comments: bool,
+    /// Where the output runs, either browser or node. Node can't have components
+    #[clap(long, default_value = "browser")]
+    target: Target,
+    /// Leave calls to log out of the compiled output, like for a release build
+    #[clap(long)]
+    strip_logs: bool,
+    /// Check components at runtime and log state updates, which is left out
+    /// of the output otherwise
+    #[clap(long)]
+    dev: bool,
+    /// How much to optimize, from 0 for nothing to 2 for everything
+    #[clap(short = 'O', long, default_value = "2")]
+    opt_level: OptLevel,
+    /// Print the instructions for each block after an optimization pass runs
+    #[clap(long)]
+    print_after: Option<String>,
+    /// Don't warn about function parameters and component props that are
+    /// never used
+    #[clap(long)]
+    allow_unused_parameters: bool,
+    /// Warn about if statements whose condition is always true or always
+    /// false, since only one of their branches is compiled
+    #[clap(long)]
+    warn_constant_conditions: bool,
+    /// Report writes to state from functions that aren't event handlers,
+    /// like callbacks, as errors instead of warnings
+    #[clap(long)]
+    deny_escaping_state_writes: bool,
+    /// Stop compiling a module after this many errors, or 0 to report
+    /// every one
+    #[clap(long, default_value = "20")]
+    max_errors: usize,
+    /// Name output files after a hash of their code, like main.1a2b3c4d.js,
+    /// and write a manifest.json mapping each module to its file
+    #[clap(long)]
+    hash_filenames: bool,
+    /// Instead of compiling, report how much of each component's templates
+    /// is static, and which state updates the rest
+    #[clap(long)]
+    analyze: bool,
+    /// Print the report from --analyze as JSON instead of a table
+    #[clap(long, requires = "analyze")]
+    json: bool,
+    /// Instead of compiling, print a graph of which expressions in each
+    /// component's templates read each piece of state, and what they update.
+    /// Either dot, for Graphviz, or json
+    #[clap(long, conflicts_with = "analyze")]
+    emit_reactivity_graph: Option<GraphFormat>,
+    /// Only report errors, leaving out warnings and the summary at the end
+    #[clap(short, long)]
+    quiet: bool,
 }
 
 #[derive(Clap)]
 struct WatchOptions {
+    /// The project's directory, or the module to start compiling from
     #[clap(short, long)]
     path: String,
+    /// The file to start compiling from, relative to the path. Defaults to main.ws
+    #[clap(short, long)]
+    entry: Option<String>,
+    /// Annotate the compiled output with the source line of each statement
+    #[clap(long)]
+    debug_comments: bool,
+    /// Where the output runs, either browser or node. Node can't have components
+    #[clap(long, default_value = "browser")]
+    target: Target,
+    /// Leave calls to log out of the compiled output, like for a release build
+    #[clap(long)]
+    strip_logs: bool,
+    /// Check components at runtime and log state updates, which is left out
+    /// of the output otherwise
+    #[clap(long)]
+    dev: bool,
+    /// How much to optimize, from 0 for nothing to 2 for everything
+    #[clap(short = 'O', long, default_value = "2")]
+    opt_level: OptLevel,
+    /// Print the instructions for each block after an optimization pass runs
+    #[clap(long)]
+    print_after: Option<String>,
+    /// Don't warn about function parameters and component props that are
+    /// never used
+    #[clap(long)]
+    allow_unused_parameters: bool,
+    /// Warn about if statements whose condition is always true or always
+    /// false, since only one of their branches is compiled
+    #[clap(long)]
+    warn_constant_conditions: bool,
+    /// Report writes to state from functions that aren't event handlers,
+    /// like callbacks, as errors instead of warnings
+    #[clap(long)]
+    deny_escaping_state_writes: bool,
+    /// Stop compiling a module after this many errors, or 0 to report
+    /// every one
+    #[clap(long, default_value = "20")]
+    max_errors: usize,
+    /// Write build events as lines of JSON, to stdout for '-' or otherwise to
+    /// the clients of a Unix socket created at this path
+    #[clap(long)]
+    events: Option<String>,
+}
+
+#[derive(Clap)]
+struct InitOptions {
+    /// The name of the project, which is also the directory it's created in
+    name: String,
+}
+
+/// Remove the files earlier builds wrote, which are listed in their manifest
+#[derive(Clap)]
+struct CleanOptions {
+    /// Don't list the files that were removed
+    #[clap(short, long)]
+    quiet: bool,
+}
+
+/// How a build ended, which is the exit code of the process. When there's
+/// more than one status, like for each module in a library, the build ends
+/// with the worst of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum BuildStatus {
+    Success = 0,
+    /// Something didn't compile, and there are diagnostics saying why
+    Diagnostics = 1,
+    /// The compiler couldn't finish, like when it couldn't read a module or
+    /// failed without saying why
+    Internal = 2,
 }
 
+/// Reports compiler events to the terminal, and to the watch event stream if
+/// there is one.
+#[derive(Default)]
+struct TerminalEventSink {
+    color: ColorMode,
+    events: Option<EventStream>,
+    /// Where diagnostics are kept to be shown with the rest of the build,
+    /// instead of being reported as they happen.
+    buffer: Option<DiagnosticBuffer>,
+    summary: BuildSummary,
+    /// Leave warnings out of the terminal
+    quiet: bool,
+    ice: IceContext,
+}
+
+impl CompilerEventSink for TerminalEventSink {
+    fn phase_started(&self, path: &Path, phase: Phase) {
+        self.ice.phase_started(path, phase);
+        debug!("{:?}: {}", phase, path.display());
+    }
+
+    fn definition_started(&self, path: &Path, name: &str, range: Range<usize>) {
+        self.ice.definition_started(path, name, range);
+    }
+
+    fn diagnostic(&self, path: &Path, source: &str, diagnostic: &Diagnostic) {
+        self.summary.count_diagnostic(diagnostic);
+        match &self.buffer {
+            Some(buffer) => buffer.push(path, source, diagnostic),
+            None if self.quiet && diagnostic.severity() == Severity::Warning => {}
+            None => {
+                let path_str = path.to_str().unwrap_or("Unknown File");
+                report_diagnostic_to_term(diagnostic.clone(), path_str, source, self.color);
+            }
+        }
+        if let Some(events) = &self.events {
+            events.send(WatchEvent::Diagnostic {
+                path,
+                source,
+                diagnostic,
+            });
+        }
+    }
+
+    fn output_written(&self, path: &Path, output: &Path) {
+        self.summary.count_output();
+        debug!("Wrote {} to {}", path.display(), output.display());
+    }
+
+    fn pass_printed(&self, path: &Path, pass: &str, part: &str) {
+        eprintln!("// After {} in {}\n{}", pass, path.display(), part);
+    }
+}
+
+/// Exit if `--print-after` names a pass that doesn't exist.
+fn check_pass_name(pass: Option<String>) -> Option<String> {
+    let passes = optimization_passes();
+    match pass {
+        Some(pass) if !passes.contains(&pass.as_str()) => {
+            eprintln!(
+                "There's no pass named '{}', the passes are: {}",
+                pass,
+                passes.join(", ")
+            );
+            std::process::exit(1);
+        }
+        pass => pass,
+    }
+}
+
+/// `path` made absolute, with symlinks resolved. It doesn't have to exist
+/// yet, so a missing module is reported when it's read.
 fn resolve_path(path: &str) -> PathBuf {
-    use std::fs::canonicalize;
-    let path = PathBuf::from(path);
-    canonicalize(path).expect("Unable to resolve provided path")
+    paths::canonicalize(Path::new(path)).unwrap_or_else(|error| {
+        eprintln!("Unable to resolve '{}': {}", path, error);
+        std::process::exit(1);
+    })
 }
 
-async fn build(options: BuildOptions) {
-    let mut db = Database::default();
+/// The project at `path`, from `--path`, and the module compiling starts
+/// from. The path is usually the project's directory, but it can be the
+/// module to start from too, for projects with a single module.
+fn project(path: PathBuf, entry: Option<&str>) -> (PathBuf, PathBuf) {
+    if !is_module(&path) {
+        let entry_point = entry_point(&path, entry);
+        return (path, entry_point);
+    }
+    if entry.is_some() {
+        eprintln!("--entry can't be used when --path is a module");
+        std::process::exit(1);
+    }
+    let root = path.parent().map_or_else(PathBuf::new, Path::to_path_buf);
+    (root, path)
+}
+
+/// The edition and features from the `ws.toml` of the project at `path`,
+/// which is the directory a module is in. Projects don't need one.
+fn project_features(path: &Path) -> Features {
+    let root = match path.parent() {
+        Some(parent) if is_module(path) => parent,
+        _ => path,
+    };
+    let text = match std::fs::read_to_string(root.join(PROJECT_FILE)) {
+        Ok(text) => text,
+        Err(_) => return Features::default(),
+    };
+    match ProjectConfig::parse(&text) {
+        Ok(config) => config.options.features,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// The module compiling starts from, in the project at `root`.
+fn entry_point(root: &Path, entry: Option<&str>) -> PathBuf {
+    root.join(entry.unwrap_or(ENTRYPOINT_FILENAME))
+}
+
+/// Report an error that stopped `path` from compiling, returning how the
+/// build ended because of it.
+fn report_error(
+    db: &Database,
+    path: &Path,
+    error: &Error,
+    color: ColorMode,
+    summary: &BuildSummary,
+) -> BuildStatus {
+    if error.diagnostics().is_empty() {
+        eprintln!(
+            "Internal error while compiling '{}': {:?}",
+            path.display(),
+            error
+        );
+        return BuildStatus::Internal;
+    }
+    let path_str = path.to_str().unwrap_or("Unknown File");
+    let source = db.file_text(path.to_path_buf());
+    for diagnostic in error.diagnostics() {
+        summary.count_diagnostic(diagnostic);
+        report_diagnostic_to_term(diagnostic.clone(), path_str, &source, color);
+    }
+    BuildStatus::Diagnostics
+}
+
+/// Read each of `files` into the database.
+async fn read_files(db: &mut Database, files: &[PathBuf]) -> std::result::Result<(), BuildStatus> {
+    for file in files {
+        match fs::read_to_string(file).await {
+            Ok(text) => db.set_file_text(file.clone(), text.into()),
+            Err(error) => {
+                eprintln!("Unable to read '{}': {}", file.display(), error);
+                return Err(BuildStatus::Internal);
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn build(options: BuildOptions, color: ColorMode, ice: IceContext) -> BuildStatus {
+    let started = Instant::now();
+    let summary = BuildSummary::default();
+    let mut db = Database::with_event_sink(TerminalEventSink {
+        color,
+        summary: summary.clone(),
+        quiet: options.quiet,
+        ice,
+        ..Default::default()
+    });
     let path = resolve_path(&options.path);
-    let entry_point = path.join(ENTRYPOINT_FILENAME);
-    let text = fs::read_to_string(entry_point.clone()).await.unwrap();
-    db.set_file_text(entry_point.clone(), text.into());
-    // Compile the entry point module so we can start building up
-    // the import graph.
-    let compiled = db.compile(entry_point.clone());
+    db.set_codegen_options(CodegenOptions {
+        debug_comments: options.debug_comments,
+        target: options.target,
+        strip_logs: options.strip_logs,
+        opt_level: options.opt_level,
+        print_after: check_pass_name(options.print_after),
+        allow_unused_parameters: options.allow_unused_parameters,
+        warn_constant_conditions: options.warn_constant_conditions,
+        deny_escaping_state_writes: options.deny_escaping_state_writes,
+        max_errors: Some(options.max_errors).filter(|max| *max > 0),
+        dev: options.dev,
+        hash_filenames: options.hash_filenames,
+        features: project_features(&path),
+        ..Default::default()
+    });
+    // A module on its own is analyzed on its own
+    if options.analyze {
+        return analyze(db, &path, options.json, color, &summary).await;
+    }
+    if let Some(format) = options.emit_reactivity_graph {
+        return emit_reactivity_graph(db, &path, format, color, &summary).await;
+    }
+    let status = if options.lib {
+        if is_module(&path) {
+            eprintln!("--lib compiles every module in a directory, so --path can't be a module");
+            std::process::exit(1);
+        }
+        build_library(db, &path, color, &summary).await
+    } else {
+        let (_, entry_point) = project(path, options.entry.as_deref());
+        match read_files(&mut db, &[entry_point.clone()]).await {
+            // Compile the entry point module so we can start building up
+            // the import graph. Its definitions are compiled across threads
+            // first, so writing it out only has to put them together.
+            Ok(()) => match db
+                .compile_module_parallel(entry_point.clone())
+                .and_then(|_| db.compile(entry_point.clone()))
+            {
+                Ok(_) => BuildStatus::Success,
+                Err(error) => report_error(&db, &entry_point, &error, color, &summary),
+            },
+            Err(status) => status,
+        }
+    };
+    if !options.quiet {
+        summary.show(started.elapsed(), color);
+    }
+    status
+}
+
+/// Compile every module under `root` and write them out as a library. Nothing
+/// is written unless they all compile.
+async fn build_library(
+    mut db: Database,
+    root: &Path,
+    color: ColorMode,
+    summary: &BuildSummary,
+) -> BuildStatus {
+    let files = db.module_files(root.to_path_buf()).to_vec();
+    if let Err(status) = read_files(&mut db, &files).await {
+        return status;
+    }
+    let mut modules = vec![];
+    let mut status = BuildStatus::Success;
+    for file in files {
+        match db.compile_module_parallel(file.clone()) {
+            Ok(module) => modules.push((file, module)),
+            Err(error) => {
+                status = status.max(report_error(&db, &file, &error, color, summary));
+            }
+        }
+    }
+    if status != BuildStatus::Success {
+        return status;
+    }
+    let output = PathBuf::from(LIBRARY_OUTPUT_PATH);
+    if let Err(error) = write_library(&db, root, &modules, &output, Path::new(MANIFEST_PATH)) {
+        if error.diagnostics().is_empty() {
+            eprintln!("Unable to write '{}': {:?}", output.display(), error);
+            return BuildStatus::Internal;
+        }
+        // These are about the library as a whole, not any one module's source
+        let output_str = output.to_str().unwrap_or("Unknown File");
+        for diagnostic in error.diagnostics() {
+            summary.count_diagnostic(diagnostic);
+            report_diagnostic_to_term(diagnostic.clone(), output_str, "", color);
+        }
+        return BuildStatus::Diagnostics;
+    }
+    BuildStatus::Success
+}
+
+/// Report on the templates of every component under `root`.
+async fn analyze(
+    mut db: Database,
+    root: &Path,
+    json: bool,
+    color: ColorMode,
+    summary: &BuildSummary,
+) -> BuildStatus {
+    let files = db.module_files(root.to_path_buf()).to_vec();
+    if let Err(status) = read_files(&mut db, &files).await {
+        return status;
+    }
+    let mut reports = vec![];
+    let mut status = BuildStatus::Success;
+    for file in files {
+        match db.template_report(file.clone()) {
+            Ok(report) => reports.extend(report.iter().cloned()),
+            Err(error) => {
+                status = status.max(report_error(&db, &file, &error, color, summary));
+            }
+        }
+    }
+    if status != BuildStatus::Success {
+        return status;
+    }
+    if json {
+        print!("{}", format_report_json(&reports));
+    } else {
+        print!("{}", format_report_table(&reports));
+    }
+    BuildStatus::Success
+}
+
+/// Graph the state in the templates of every component under `root`.
+async fn emit_reactivity_graph(
+    mut db: Database,
+    root: &Path,
+    format: GraphFormat,
+    color: ColorMode,
+    summary: &BuildSummary,
+) -> BuildStatus {
+    let files = db.module_files(root.to_path_buf()).to_vec();
+    if let Err(status) = read_files(&mut db, &files).await {
+        return status;
+    }
+    let mut graphs = vec![];
+    let mut status = BuildStatus::Success;
+    for file in files {
+        match db.reactivity_graph(file.clone()) {
+            Ok(graph) => graphs.extend(graph.iter().cloned()),
+            Err(error) => {
+                status = status.max(report_error(&db, &file, &error, color, summary));
+            }
+        }
+    }
+    if status != BuildStatus::Success {
+        return status;
+    }
+    print!("{}", format_graph(&graphs, format));
+    BuildStatus::Success
+}
 
-    match compiled {
-        Ok(ast) => {
-            debug!("AST: {:?}", ast);
+/// Compile `entry_point` in watch mode, reporting when the build starts and
+/// how it went, and showing it on `screen` along with the diagnostics kept
+/// in `buffer` while it ran.
+fn build_watched(
+    db: &Database,
+    entry_point: &Path,
+    events: Option<&EventStream>,
+    screen: &mut WatchScreen,
+    buffer: &DiagnosticBuffer,
+) {
+    let path = entry_point;
+    if let Some(events) = events {
+        events.send(WatchEvent::BuildStarted { path });
+    }
+    let started = Instant::now();
+    let compiled = db.compile(entry_point.to_path_buf());
+    let elapsed = started.elapsed();
+    let mut diagnostics = buffer.take();
+    match &compiled {
+        Ok(_) => {
+            if let Some(events) = events {
+                events.send(WatchEvent::BuildSucceeded { path });
+            }
         }
         Err(error) => {
-            println!("Error: {:?}", error);
-            let path_str = entry_point.to_str().unwrap_or("Unknown File");
-            use diagnostics::error::{report_diagnostic_to_term, Error};
-            if let Error::Diagnostic(diagnostic) = error {
-                let source = db.file_text(entry_point.clone());
-                report_diagnostic_to_term(diagnostic, path_str, &source);
-            } else {
-                panic!("Unexpected error: {:?}", error);
+            let source = db.file_text(entry_point.to_path_buf());
+            if let Some(events) = events {
+                events.send(WatchEvent::BuildFailed {
+                    path,
+                    source: &source,
+                    diagnostics: error.diagnostics(),
+                });
             }
+            diagnostics.extend(error.diagnostics().iter().map(|diagnostic| {
+                RenderedDiagnostic::new(path, &source, diagnostic, screen.has_color())
+            }));
         }
     }
+    debug!("{:?}", db.compilation_stats());
+    screen.show_build(path, elapsed, diagnostics);
 }
 
-async fn watch(options: WatchOptions) {
-    let mut db = Database::default();
-    let root = resolve_path(&options.path);
-    let entry_point = root.join(ENTRYPOINT_FILENAME);
+async fn watch(options: WatchOptions, color: ColorMode, ice: IceContext) {
+    let events = options.events.map(|destination| {
+        EventStream::open(&destination).unwrap_or_else(|error| {
+            eprintln!("Unable to stream events to '{}': {}", destination, error);
+            std::process::exit(1);
+        })
+    });
+    // Events written to stdout can't be mixed with anything else
+    let output = match &events {
+        Some(events) if events.is_stdout() => Output::Stderr,
+        _ => Output::Stdout,
+    };
+    let mut screen = WatchScreen::new(output, color);
+    let buffer = DiagnosticBuffer::new(screen.has_color());
+    let mut db = Database::with_event_sink(TerminalEventSink {
+        color,
+        events: events.clone(),
+        buffer: Some(buffer.clone()),
+        ice,
+        ..Default::default()
+    });
+    let path = resolve_path(&options.path);
+    db.set_codegen_options(CodegenOptions {
+        debug_comments: options.debug_comments,
+        target: options.target,
+        strip_logs: options.strip_logs,
+        opt_level: options.opt_level,
+        print_after: check_pass_name(options.print_after),
+        allow_unused_parameters: options.allow_unused_parameters,
+        warn_constant_conditions: options.warn_constant_conditions,
+        deny_escaping_state_writes: options.deny_escaping_state_writes,
+        max_errors: Some(options.max_errors).filter(|max| *max > 0),
+        dev: options.dev,
+        features: project_features(&path),
+        ..Default::default()
+    });
+    let (root, entry_point) = project(path, options.entry.as_deref());
     debug!("watching {:#?}", root);
 
     let text = fs::read_to_string(entry_point.clone()).await.unwrap();
@@ -83,26 +602,16 @@ async fn watch(options: WatchOptions) {
 
     // Compile the entry point module so we can start building up
     // the import graph.
-    let _ = db.compile(entry_point.clone());
-
-    for entry in
-        WalkDir::new(&root)
-            .into_iter()
-            .filter_entry(|entry| match entry.path().extension() {
-                Some(extension) => match extension.to_str() {
-                    Some("ws") => true,
-                    _ => false,
-                },
-                None => true,
-            })
-    {
-        let entry = entry.unwrap();
-        let path = entry.path();
-        if path.is_file() {
-            let text = fs::read_to_string(path).await.unwrap();
-            let pathbuf = path.to_path_buf();
-            db.set_file_text(pathbuf.clone(), text.into());
+    build_watched(&db, &entry_point, events.as_ref(), &mut screen, &buffer);
+
+    let mut modules = vec![entry_point.clone()];
+    for path in db.module_files(root.clone()).iter().cloned() {
+        if modules.iter().any(|module| paths::same_path(module, &path)) {
+            continue;
         }
+        let text = fs::read_to_string(&path).await.unwrap();
+        db.set_file_text(path.clone(), text.into());
+        modules.push(path);
     }
 
     let (tx, rx) = mpsc::channel();
@@ -119,54 +628,125 @@ async fn watch(options: WatchOptions) {
                 use notify::event::{EventKind, ModifyKind};
                 if let EventKind::Modify(modified) = event.kind {
                     if let ModifyKind::Data(_) = modified {
-                        std::process::Command::new("clear").status().unwrap();
-                        // Content of file has changed, recompile
-                        let text = fs::read_to_string(entry_point.clone()).await.unwrap();
-                        db.set_file_text(entry_point.clone(), text.into());
-                        // Compile the entry point module so we can start building up
-                        // the import graph.
-                        let compiled = db.compile(entry_point.clone());
-                        // let ast = {
-                        //     let text = fs::read_to_string(entry_point.clone()).await.unwrap();
-                        //     db.set_file_text(entry_point.clone(), text.into());
-                        //     db.parse(entry_point.clone())
-                        // };
-                        match compiled {
-                            Ok(_ast) => {
-                                use diagnostics::termcolor::{
-                                    Color, ColorChoice, ColorSpec, StandardStream, WriteColor,
-                                };
-                                use std::io::Write;
-                                let mut stdout = StandardStream::stdout(ColorChoice::Always);
-                                stdout
-                                    .set_color(ColorSpec::new().set_fg(Some(Color::Green)))
-                                    .unwrap();
-                                writeln!(&mut stdout, "Compiled Successfully!").unwrap();
+                        if let Some(events) = &events {
+                            for path in &event.paths {
+                                events.send(WatchEvent::FileChanged { path });
                             }
-                            Err(error) => {
-                                std::process::Command::new("clear").status().unwrap();
-                                let path_str = entry_point.to_str().unwrap_or("Unknown File");
-                                use diagnostics::error::{report_diagnostic_to_term, Error};
-                                if let Error::Diagnostic(diagnostic) = error {
-                                    let source = db.file_text(entry_point.clone());
-                                    report_diagnostic_to_term(diagnostic, path_str, &source);
+                        }
+                        // Content of file has changed, recompile. Nothing holds onto
+                        // the symbols from the last build, so free them first.
+                        db.release_symbols(&entry_point);
+                        // Modules added or removed on disk change what's listed
+                        disk_changed(&mut db);
+                        for path in event.paths.iter().filter(|path| is_module(path)) {
+                            // Events can spell paths differently than they
+                            // were read, like with another case on macOS
+                            let path = paths::canonicalize(path).unwrap_or_else(|_| path.clone());
+                            let module = match modules
+                                .iter()
+                                .find(|module| paths::same_path(module, &path))
+                            {
+                                Some(module) => module.clone(),
+                                None => {
+                                    modules.push(path.clone());
+                                    path
                                 }
+                            };
+                            // Each module interns its symbols in its own
+                            // scope, which is rebuilt along with it
+                            db.release_symbols(&module);
+                            if let Ok(text) = fs::read_to_string(&module).await {
+                                db.set_file_text(module, text.into());
                             }
                         }
+                        // Compile the entry point module so we can start building up
+                        // the import graph.
+                        build_watched(&db, &entry_point, events.as_ref(), &mut screen, &buffer);
                     }
                 }
             }
-            Err(err) => println!("err: {:#?}", err),
+            Err(err) => eprintln!("err: {:#?}", err),
         }
     }
 }
 
-#[async_std::main]
-async fn main() {
-    pretty_env_logger::init();
-    let opts: Opts = Opts::parse();
+async fn init(options: InitOptions) {
+    if let Err(error) = init::create_project(&options.name).await {
+        eprintln!("Unable to create '{}': {}", options.name, error);
+        std::process::exit(1);
+    }
+    println!("Created {}. To try it out, run:", options.name);
+    println!();
+    println!("  cd {}", options.name);
+    println!("  ws build --path .");
+    println!();
+    println!("and open index.html from a local web server.");
+}
+
+async fn clean(options: CleanOptions) -> BuildStatus {
+    let cleaned = match clean_artifacts(Path::new(MANIFEST_PATH)) {
+        Ok(cleaned) => cleaned,
+        Err(error) => {
+            eprintln!("Unable to clean '{}': {}", MANIFEST_PATH, error);
+            return BuildStatus::Internal;
+        }
+    };
+    if !options.quiet {
+        for path in &cleaned.removed {
+            println!("Removed {}", path.display());
+        }
+    }
+    // Files that were edited after they were written might have changes
+    // worth keeping
+    for path in &cleaned.modified {
+        eprintln!(
+            "Kept {}, which changed since it was written",
+            path.display()
+        );
+    }
+    BuildStatus::Success
+}
+
+/// Report traces to stderr, so they never end up mixed into compiled output.
+fn init_tracing(verbose: u8) {
+    use tracing_subscriber::EnvFilter;
+    let level = match verbose {
+        0 => "warn",
+        1 => "info",
+        2 => "debug",
+        _ => "trace",
+    };
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level));
+    tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(std::io::stderr)
+        .init();
+}
+
+async fn run(opts: Opts, ice: IceContext) -> BuildStatus {
     match opts.subcmd {
-        Commands::Build(options) => build(options).await,
-        Commands::Watch(options) => watch(options).await,
+        Commands::Build(options) => return build(options, opts.color, ice).await,
+        Commands::Watch(options) => watch(options, opts.color, ice).await,
+        Commands::Init(options) => init(options).await,
+        Commands::Clean(options) => return clean(options).await,
+    }
+    BuildStatus::Success
+}
+
+fn main() {
+    use std::panic::{catch_unwind, AssertUnwindSafe};
+    let opts: Opts = Opts::parse();
+    init_tracing(opts.verbose);
+    let ice = IceContext::default();
+    ice.install_hook();
+    // The watch screen is put back while the panic unwinds, so the report
+    // is only shown after that, where it can be seen.
+    let run = AssertUnwindSafe(|| async_std::task::block_on(run(opts, ice.clone())));
+    match catch_unwind(run) {
+        Ok(status) => std::process::exit(status as i32),
+        Err(_) => {
+            ice.report();
+            std::process::exit(BuildStatus::Internal as i32);
+        }
     }
 }
@@ -0,0 +1,332 @@
+use diagnostics::color::ColorMode;
+use diagnostics::error::{render_diagnostic, Diagnostic, Severity};
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const ENTER_ALTERNATE_SCREEN: &str = "\x1b[?1049h";
+const LEAVE_ALTERNATE_SCREEN: &str = "\x1b[?1049l";
+const CLEAR_SCREEN: &str = "\x1b[H\x1b[2J";
+const CLEAR_FIRST_LINE: &str = "\x1b[H\x1b[2K";
+
+/// A diagnostic rendered the way it's shown in the terminal. Builds are
+/// compared by their rendered diagnostics, so the same error in the same
+/// place counts as the same diagnostic.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RenderedDiagnostic {
+    severity: Severity,
+    text: String,
+}
+
+impl RenderedDiagnostic {
+    pub fn new(path: &Path, source: &str, diagnostic: &Diagnostic, color: bool) -> Self {
+        let file_name = path.to_str().unwrap_or("Unknown File");
+        Self {
+            severity: diagnostic.severity(),
+            text: render_diagnostic(diagnostic.clone(), file_name, source, color),
+        }
+    }
+}
+
+/// Diagnostics reported while a build runs, like warnings, kept to be shown
+/// with the rest of the build.
+#[derive(Clone, Default)]
+pub struct DiagnosticBuffer {
+    color: bool,
+    diagnostics: Arc<Mutex<Vec<RenderedDiagnostic>>>,
+}
+
+impl DiagnosticBuffer {
+    pub fn new(color: bool) -> Self {
+        Self {
+            color,
+            diagnostics: Default::default(),
+        }
+    }
+
+    pub fn push(&self, path: &Path, source: &str, diagnostic: &Diagnostic) {
+        let diagnostic = RenderedDiagnostic::new(path, source, diagnostic, self.color);
+        self.diagnostics.lock().unwrap().push(diagnostic);
+    }
+
+    pub fn take(&self) -> Vec<RenderedDiagnostic> {
+        std::mem::take(&mut *self.diagnostics.lock().unwrap())
+    }
+}
+
+/// Counts what a build reported and wrote, for the summary shown when it's
+/// done.
+#[derive(Clone, Default)]
+pub struct BuildSummary {
+    errors: Arc<AtomicUsize>,
+    warnings: Arc<AtomicUsize>,
+    outputs: Arc<AtomicUsize>,
+}
+
+impl BuildSummary {
+    pub fn count_diagnostic(&self, diagnostic: &Diagnostic) {
+        let count = match diagnostic.severity() {
+            Severity::Error => &self.errors,
+            Severity::Warning => &self.warnings,
+        };
+        count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn count_output(&self) {
+        self.outputs.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Print a line like "Finished with 0 errors, 1 warning, 2 files written
+    /// (12ms)" to stderr, so it's never mixed into anything piped from stdout.
+    pub fn show(&self, elapsed: Duration, color: ColorMode) {
+        let errors = self.errors.load(Ordering::Relaxed);
+        let mut summary = format!(
+            "Finished with {}, {}, {} written ({}ms)",
+            plural(errors, "error"),
+            plural(self.warnings.load(Ordering::Relaxed), "warning"),
+            plural(self.outputs.load(Ordering::Relaxed), "file"),
+            elapsed.as_millis()
+        );
+        if color.enabled(io::stderr().is_terminal()) {
+            let color = if errors > 0 { "\x1b[31m" } else { "\x1b[32m" };
+            summary = format!("{}{}\x1b[0m", color, summary);
+        }
+        eprintln!("{}", summary);
+    }
+}
+
+/// Where the screen is drawn. Watch events can take over stdout, in which
+/// case builds are shown on stderr instead.
+#[derive(Clone, Copy)]
+pub enum Output {
+    Stdout,
+    Stderr,
+}
+
+impl Output {
+    fn is_terminal(self) -> bool {
+        match self {
+            Output::Stdout => io::stdout().is_terminal(),
+            Output::Stderr => io::stderr().is_terminal(),
+        }
+    }
+
+    fn write(self, text: &str) {
+        // Nothing is lost if the terminal is gone, there's no one to show it to
+        let _ = match self {
+            Output::Stdout => {
+                let mut stdout = io::stdout();
+                stdout
+                    .write_all(text.as_bytes())
+                    .and_then(|_| stdout.flush())
+            }
+            Output::Stderr => io::stderr().write_all(text.as_bytes()),
+        };
+    }
+}
+
+/// Shows each build in watch mode. On a terminal, builds are drawn on the
+/// alternate screen, which is put back when watching stops, and the screen is
+/// only redrawn when the diagnostics change. Otherwise, like when the output
+/// is piped to a file, each build is a summary line followed by only the
+/// diagnostics that are new since the last build.
+pub struct WatchScreen {
+    output: Output,
+    interactive: bool,
+    color: bool,
+    /// The diagnostics from the last build, if there's been one
+    shown: Option<Vec<RenderedDiagnostic>>,
+}
+
+impl WatchScreen {
+    pub fn new(output: Output, color: ColorMode) -> Self {
+        let interactive = output.is_terminal();
+        let color = color.enabled(interactive);
+        if interactive {
+            output.write(ENTER_ALTERNATE_SCREEN);
+            // Ctrl-C stops watching, which has to leave the alternate screen
+            // or the terminal is left showing the last build.
+            let _ = ctrlc::set_handler(move || {
+                output.write(LEAVE_ALTERNATE_SCREEN);
+                std::process::exit(130);
+            });
+        }
+        Self {
+            output,
+            interactive,
+            color,
+            shown: None,
+        }
+    }
+
+    /// Whether diagnostics shown on the screen should have colors.
+    pub fn has_color(&self) -> bool {
+        self.color
+    }
+
+    pub fn show_build(
+        &mut self,
+        path: &Path,
+        elapsed: Duration,
+        diagnostics: Vec<RenderedDiagnostic>,
+    ) {
+        let screen = self.render_build(path, elapsed, diagnostics);
+        self.output.write(&screen);
+    }
+
+    /// What's written to show a build, which depends on what the last one
+    /// showed.
+    fn render_build(
+        &mut self,
+        path: &Path,
+        elapsed: Duration,
+        diagnostics: Vec<RenderedDiagnostic>,
+    ) -> String {
+        let shown = self.shown.take();
+        let new: Vec<&RenderedDiagnostic> = diagnostics
+            .iter()
+            .filter(|diagnostic| {
+                shown
+                    .as_ref()
+                    .is_none_or(|shown| !shown.contains(diagnostic))
+            })
+            .collect();
+        let fixed = shown.as_ref().map_or(0, |shown| {
+            shown
+                .iter()
+                .filter(|diagnostic| !diagnostics.contains(diagnostic))
+                .count()
+        });
+        let mut summary = summary_line(path, elapsed, &diagnostics);
+        if shown.is_some() && (!new.is_empty() || fixed > 0) {
+            summary.push_str(&format!(" ({} new, {} fixed)", new.len(), fixed));
+        }
+        if self.color {
+            let has_errors = diagnostics
+                .iter()
+                .any(|diagnostic| diagnostic.severity == Severity::Error);
+            let color = if has_errors { "\x1b[31m" } else { "\x1b[32m" };
+            summary = format!("{}{}\x1b[0m", color, summary);
+        }
+        let screen = if self.interactive {
+            if shown.as_ref() == Some(&diagnostics) {
+                // Only the summary changed, like how long the build took
+                format!("{}{}", CLEAR_FIRST_LINE, summary)
+            } else {
+                let mut screen = format!("{}{}\n", CLEAR_SCREEN, summary);
+                for diagnostic in &diagnostics {
+                    screen.push('\n');
+                    screen.push_str(&diagnostic.text);
+                }
+                screen
+            }
+        } else {
+            let mut lines = format!("{}\n", summary);
+            for diagnostic in new {
+                lines.push_str(&diagnostic.text);
+            }
+            lines
+        };
+        self.shown = Some(diagnostics);
+        screen
+    }
+}
+
+impl Drop for WatchScreen {
+    fn drop(&mut self) {
+        if self.interactive {
+            self.output.write(LEAVE_ALTERNATE_SCREEN);
+        }
+    }
+}
+
+/// A line like "Compiled main.ws in 12ms", or "main.ws: 2 errors, 1 warning
+/// (12ms)".
+fn summary_line(path: &Path, elapsed: Duration, diagnostics: &[RenderedDiagnostic]) -> String {
+    let name = path
+        .file_name()
+        .unwrap_or(path.as_os_str())
+        .to_string_lossy();
+    let count = |severity: Severity| {
+        diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.severity == severity)
+            .count()
+    };
+    let (errors, warnings) = (count(Severity::Error), count(Severity::Warning));
+    let elapsed = elapsed.as_millis();
+    if errors == 0 && warnings == 0 {
+        format!("Compiled {} in {}ms", name, elapsed)
+    } else {
+        format!(
+            "{}: {}, {} ({}ms)",
+            name,
+            plural(errors, "error"),
+            plural(warnings, "warning"),
+            elapsed
+        )
+    }
+}
+
+/// A count of `noun`, like "1 error" or "2 errors".
+fn plural(count: usize, noun: &str) -> String {
+    match count {
+        1 => format!("1 {}", noun),
+        count => format!("{} {}s", count, noun),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Output, RenderedDiagnostic, WatchScreen};
+    use diagnostics::error::Severity;
+    use std::path::Path;
+    use std::time::Duration;
+
+    fn diagnostic(severity: Severity, text: &str) -> RenderedDiagnostic {
+        RenderedDiagnostic {
+            severity,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn show_new_diagnostics_when_piped() {
+        let mut screen = WatchScreen {
+            output: Output::Stderr,
+            interactive: false,
+            color: false,
+            shown: None,
+        };
+        let mut show = |diagnostics: &[RenderedDiagnostic]| {
+            screen.render_build(
+                Path::new("src/main.ws"),
+                Duration::from_millis(12),
+                diagnostics.to_vec(),
+            )
+        };
+        let unused = diagnostic(Severity::Warning, "warning: unused\n");
+        let missing = diagnostic(Severity::Error, "error: missing\n");
+        let mismatch = diagnostic(Severity::Error, "error: mismatch\n");
+        let builds = vec![
+            show(&[unused.clone(), missing.clone()]),
+            show(&[unused.clone(), missing.clone()]),
+            show(&[unused, mismatch]),
+            show(&[]),
+        ];
+        assert_eq!(
+            builds,
+            vec![
+                "main.ws: 1 error, 1 warning (12ms)\nwarning: unused\nerror: missing\n",
+                // Diagnostics that were already shown aren't shown again
+                "main.ws: 1 error, 1 warning (12ms)\n",
+                "main.ws: 1 error, 1 warning (12ms) (1 new, 1 fixed)\nerror: mismatch\n",
+                "Compiled main.ws in 12ms (0 new, 2 fixed)\n",
+            ]
+        );
+        // Without a terminal there's no screen to clear or switch to
+        assert!(builds.iter().all(|build| !build.contains('\x1b')));
+    }
+}
@@ -0,0 +1,323 @@
+//! The files codegen writes, and the manifest that keeps track of them so
+//! a later build or `ws clean` knows what's there.
+use crate::lower::escape_js_string;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// The file next to outputs with hashed names that maps each module to the
+/// file it was written to.
+pub const ASSET_MANIFEST_FILE: &str = "manifest.json";
+
+/// Write `contents` to `path`, unless it already has them apart from when
+/// it was generated. Files that are left alone keep their modification
+/// time, so bundlers watching the output don't rebuild for nothing. Returns
+/// whether the file was written.
+pub fn write_output(path: &Path, contents: &str) -> io::Result<bool> {
+    if let Ok(existing) = std::fs::read_to_string(path) {
+        if same_output(&existing, contents) {
+            return Ok(false);
+        }
+    }
+    if let Some(directory) = path.parent() {
+        std::fs::create_dir_all(directory)?;
+    }
+    std::fs::write(path, contents)?;
+    Ok(true)
+}
+
+/// Whether two outputs are the same, not counting the time in their headers.
+fn same_output(a: &str, b: &str) -> bool {
+    untimed_lines(a).eq(untimed_lines(b))
+}
+
+/// The lines of an output, without the one in its header saying when it was
+/// generated.
+fn untimed_lines(output: &str) -> impl Iterator<Item = &str> {
+    output
+        .lines()
+        .filter(|line| !line.trim_start().starts_with("* Generated at "))
+}
+
+/// A hash of an output that only changes when the code in it does, and not
+/// the time it was generated.
+pub fn output_hash(output: &str) -> u64 {
+    let lines: Vec<&str> = untimed_lines(output).collect();
+    content_hash(&lines.join("\n"))
+}
+
+/// The part of a hash put in file names, like the `1a2b3c4d` in
+/// `main.1a2b3c4d.js`.
+pub fn short_hash(hash: u64) -> String {
+    format!("{:08x}", hash >> 32)
+}
+
+/// `path` with `hash` before its extension, like `main.1a2b3c4d.js` for
+/// `main.js`.
+pub fn hashed_path(path: &Path, hash: u64) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(extension) => format!(
+            "{}.{}.{}",
+            stem,
+            short_hash(hash),
+            extension.to_string_lossy()
+        ),
+        None => format!("{}.{}", stem, short_hash(hash)),
+    };
+    path.with_file_name(name)
+}
+
+/// The JSON for an asset manifest, an object from each module's name to the
+/// file it was written to.
+pub fn render_asset_manifest(assets: &[(String, String)]) -> String {
+    let quote = |value: &str| format!("\"{}\"", escape_js_string(value));
+    let entries: Vec<String> = assets
+        .iter()
+        .map(|(module, file)| format!("  {}: {}", quote(module), quote(file)))
+        .collect();
+    format!("{{\n{}\n}}\n", entries.join(",\n"))
+}
+
+/// A hash of `contents` that's the same on every platform and version of
+/// the compiler, since manifests outlive the build that wrote them. This is
+/// 64 bit FNV-1a.
+pub fn content_hash(contents: &str) -> u64 {
+    contents.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// A file written by codegen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Artifact {
+    /// The hash of what was written, to tell if it's been changed since.
+    pub hash: u64,
+    /// The module it was compiled from and the hash of its text, or `None`
+    /// for files that aren't compiled from a single module, like the index
+    /// of a library.
+    pub source: Option<(PathBuf, u64)>,
+}
+
+/// Every file codegen has written, by where it was written. Outputs are
+/// kept in the manifest until they're cleaned, even once they're no longer
+/// written, so that nothing a build left behind is forgotten.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Manifest {
+    artifacts: BTreeMap<PathBuf, Artifact>,
+}
+
+impl Manifest {
+    /// Read the manifest at `path`. It's empty if there isn't one yet, and
+    /// lines that can't be read are left out.
+    pub fn load(path: &Path) -> Manifest {
+        std::fs::read_to_string(path)
+            .map(|text| Manifest::parse(&text))
+            .unwrap_or_default()
+    }
+
+    /// Write the manifest to `path`, if anything in it changed.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        write_output(path, &self.render())?;
+        Ok(())
+    }
+
+    /// Write `contents` to `output` and record it, along with the module at
+    /// `source` whose text was `source_text`, if it was compiled from one.
+    /// Returns whether the file was written, which it isn't when it already
+    /// had those contents.
+    pub fn write(
+        &mut self,
+        output: &Path,
+        source: Option<(&Path, &str)>,
+        contents: &str,
+    ) -> io::Result<bool> {
+        let written = write_output(output, contents)?;
+        // Unchanged files still have the time they were first written
+        let hash = if written {
+            content_hash(contents)
+        } else {
+            content_hash(&std::fs::read_to_string(output)?)
+        };
+        let source = source.map(|(path, text)| (path.to_path_buf(), content_hash(text)));
+        self.artifacts
+            .insert(output.to_path_buf(), Artifact { hash, source });
+        Ok(written)
+    }
+
+    pub fn artifacts(&self) -> impl Iterator<Item = (&Path, &Artifact)> {
+        self.artifacts
+            .iter()
+            .map(|(output, artifact)| (output.as_path(), artifact))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.artifacts.is_empty()
+    }
+
+    /// Each artifact is a line of its hash, its source's hash, where it was
+    /// written and where its source is, separated by tabs. Artifacts without
+    /// a source have `-` for its hash and nothing for its path.
+    fn render(&self) -> String {
+        let mut text = String::from("# Files written by the compiler, removed by `ws clean`\n");
+        for (output, artifact) in &self.artifacts {
+            let (source, source_hash) = match &artifact.source {
+                Some((source, hash)) => (source.display().to_string(), format!("{:016x}", hash)),
+                None => (String::new(), "-".to_string()),
+            };
+            let _ = writeln!(
+                text,
+                "{:016x}\t{}\t{}\t{}",
+                artifact.hash,
+                source_hash,
+                output.display(),
+                source
+            );
+        }
+        text
+    }
+
+    fn parse(text: &str) -> Manifest {
+        let artifacts = text
+            .lines()
+            .filter(|line| !line.starts_with('#'))
+            .filter_map(|line| {
+                let mut fields = line.split('\t');
+                let hash = u64::from_str_radix(fields.next()?, 16).ok()?;
+                let source_hash = fields.next()?;
+                let output = PathBuf::from(fields.next()?);
+                let source = match source_hash {
+                    "-" => None,
+                    hash => Some((
+                        PathBuf::from(fields.next()?),
+                        u64::from_str_radix(hash, 16).ok()?,
+                    )),
+                };
+                Some((output, Artifact { hash, source }))
+            })
+            .collect();
+        Manifest { artifacts }
+    }
+}
+
+/// What `clean_artifacts` removed, and what it left.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Cleaned {
+    pub removed: Vec<PathBuf>,
+    /// Artifacts that were changed after they were written, which are left
+    /// alone in case the changes are wanted.
+    pub modified: Vec<PathBuf>,
+}
+
+/// Remove every artifact in the manifest at `path`, along with directories
+/// in the manifest's directory that are left empty. The modified artifacts
+/// that are left stay in the manifest, and it's removed once there are none.
+pub fn clean_artifacts(path: &Path) -> io::Result<Cleaned> {
+    let output_directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut manifest = Manifest::load(path);
+    let mut cleaned = Cleaned::default();
+    let mut directories = vec![];
+    manifest.artifacts.retain(|output, artifact| {
+        directories.extend(output.parent().map(Path::to_path_buf));
+        let contents = match std::fs::read_to_string(output) {
+            Ok(contents) => contents,
+            // Already gone
+            Err(_) => return false,
+        };
+        if content_hash(&contents) != artifact.hash {
+            cleaned.modified.push(output.clone());
+            return true;
+        }
+        match std::fs::remove_file(output) {
+            Ok(()) => {
+                cleaned.removed.push(output.clone());
+                false
+            }
+            Err(_) => true,
+        }
+    });
+    if manifest.is_empty() {
+        if path.exists() {
+            std::fs::remove_file(path)?;
+        }
+        directories.push(output_directory.to_path_buf());
+    } else {
+        manifest.save(path)?;
+    }
+    // The deepest directories first, so their parents can be empty by the
+    // time they're tried. Directories that aren't empty stay.
+    directories.sort_by_key(|directory| std::cmp::Reverse(directory.components().count()));
+    directories.dedup();
+    for directory in directories {
+        for directory in directory.ancestors() {
+            let outside = !directory.starts_with(output_directory);
+            if outside
+                || directory.as_os_str().is_empty()
+                || std::fs::remove_dir(directory).is_err()
+            {
+                break;
+            }
+        }
+    }
+    Ok(cleaned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn write_record_and_clean_outputs() {
+        let root = std::env::temp_dir().join(format!("ws-artifacts-{}", std::process::id()));
+        let manifest_path = root.join("output/.ws-manifest");
+        let app = root.join("output/app.js");
+        let index = root.join("output/lib/index.js");
+        let header = |time: &str| format!("/**\n * Generated at {}\n */\nlet a = 1;\n", time);
+
+        let mut manifest = Manifest::default();
+        let source = (root.join("main.ws"), "let a = 1");
+        assert!(manifest
+            .write(&app, Some((&source.0, source.1)), &header("1"))
+            .unwrap());
+        assert!(manifest.write(&index, None, "export {};\n").unwrap());
+        // Only the time is different
+        assert!(!manifest
+            .write(&app, Some((&source.0, source.1)), &header("2"))
+            .unwrap());
+        assert_eq!(std::fs::read_to_string(&app).unwrap(), header("1"));
+        manifest.save(&manifest_path).unwrap();
+        assert_eq!(Manifest::load(&manifest_path), manifest);
+
+        std::fs::write(&index, "export {a} from './a.js';\n").unwrap();
+        let cleaned = clean_artifacts(&manifest_path).unwrap();
+        assert_eq!(cleaned.removed, vec![app.clone()]);
+        assert_eq!(cleaned.modified, vec![index.clone()]);
+        assert!(!app.exists() && index.exists());
+
+        std::fs::remove_file(&index).unwrap();
+        let cleaned = clean_artifacts(&manifest_path).unwrap();
+        assert_eq!(cleaned, Cleaned::default());
+        assert!(!root.join("output").exists() && root.exists());
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    #[test]
+    fn hash_file_names() {
+        let header = |time: &str| format!("/**\n * Generated at {}\n */\nlet a = 1;\n", time);
+        let hash = output_hash(&header("1"));
+        assert_eq!(hash, output_hash(&header("2")));
+        assert_ne!(hash, output_hash("let a = 2;\n"));
+        assert_eq!(
+            hashed_path(Path::new("output/app.compiled.js"), 0x1a2b3c4d_00000000),
+            PathBuf::from("output/app.compiled.1a2b3c4d.js")
+        );
+        assert_eq!(
+            render_asset_manifest(&[
+                ("main".to_string(), "main.1a2b3c4d.js".to_string()),
+                ("ui/button".to_string(), "ui/button.5e6f7a8b.js".to_string()),
+            ]),
+            "{\n  \"main\": \"main.1a2b3c4d.js\",\n  \"ui/button\": \"ui/button.5e6f7a8b.js\"\n}\n"
+        );
+    }
+}
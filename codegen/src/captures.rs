@@ -0,0 +1,178 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+use diagnostics::result::Result;
+use syntax::ast::{
+    AstArena, Binding, BlockId, Else, Expression, ExpressionId, FunctionId, If, MatchPattern,
+    ModuleId, Statement, StatementId,
+};
+use syntax::visit::{walk_expression, walk_function, walk_statement, Visitor};
+
+/// The bindings a function closes over, keyed by the function that captures them.
+pub type CaptureMap = HashMap<FunctionId, HashSet<Binding>>;
+
+/// Records which bindings each function closes over. A binding is captured
+/// when it is referenced inside a function but defined outside of it, like
+/// a `state` binding used by an event handler declared in a component.
+pub struct CaptureAnalysis<'a> {
+    arena: &'a AstArena,
+    // The functions we're currently inside of, innermost last, along with
+    // the bindings each one defines locally.
+    function_stack: RefCell<Vec<(FunctionId, HashSet<Binding>)>>,
+    captures: RefCell<CaptureMap>,
+}
+
+impl<'a> CaptureAnalysis<'a> {
+    pub fn new(arena: &'a AstArena) -> Self {
+        Self {
+            arena,
+            function_stack: Default::default(),
+            captures: Default::default(),
+        }
+    }
+
+    pub fn analyze(self, module_id: ModuleId) -> Result<CaptureMap> {
+        self.visit_module(module_id)?;
+        Ok(self.captures.into_inner())
+    }
+
+    /// Collect every binding that is defined by this function: its parameters
+    /// and any `let` or `state` statements in its body.
+    fn local_bindings(&self, function_id: FunctionId) -> HashSet<Binding> {
+        let function = self.arena.functions.get(function_id).unwrap().borrow();
+        let mut bindings = HashSet::new();
+        if let Some(parameters) = &function.parameters {
+            for parameter in parameters {
+                bindings.insert(Binding::Parameter(*parameter));
+            }
+        }
+        if let Some(body) = function.body {
+            self.collect_block_bindings(body, &mut bindings);
+        }
+        bindings
+    }
+
+    fn collect_block_bindings(&self, block_id: BlockId, bindings: &mut HashSet<Binding>) {
+        let block = self.arena.blocks.get(block_id).unwrap();
+        for statement_id in &block.statements {
+            match self.arena.statements.get(*statement_id).unwrap() {
+                Statement::Let { value, .. } => {
+                    bindings.insert(Binding::Let(*statement_id));
+                    self.collect_expression_bindings(*value, bindings);
+                }
+                Statement::State(_) => {
+                    bindings.insert(Binding::State(*statement_id));
+                }
+                Statement::If(if_) => self.collect_if_bindings(if_, bindings),
+                Statement::While { body, .. } => self.collect_block_bindings(*body, bindings),
+                Statement::Expression(expression_id)
+                | Statement::Discard(expression_id)
+                | Statement::Return(expression_id) => {
+                    self.collect_expression_bindings(*expression_id, bindings)
+                }
+                // Contexts are only provided in component bodies
+                Statement::Assignment { .. } | Statement::Provide { .. } => {}
+            }
+        }
+    }
+
+    // If-expressions have blocks of their own that can define bindings, and
+    // match cases bind the values of the variants they match.
+    fn collect_expression_bindings(
+        &self,
+        expression_id: ExpressionId,
+        bindings: &mut HashSet<Binding>,
+    ) {
+        let expression = self.arena.expressions.get(expression_id).unwrap().borrow();
+        match &*expression {
+            Expression::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.collect_block_bindings(*then_branch, bindings);
+                if let Some(else_branch) = else_branch {
+                    self.collect_block_bindings(*else_branch, bindings);
+                }
+            }
+            Expression::Match { cases, .. } => {
+                for case in cases {
+                    if let MatchPattern::Variant {
+                        bindings: pattern_bindings,
+                        ..
+                    } = &case.pattern
+                    {
+                        bindings.extend(pattern_bindings.iter().map(|id| Binding::Pattern(*id)));
+                    }
+                    self.collect_expression_bindings(case.body, bindings);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn collect_if_bindings(&self, if_: &If, bindings: &mut HashSet<Binding>) {
+        self.collect_block_bindings(if_.body, bindings);
+        if let Some(else_) = &if_.alternate {
+            match &**else_ {
+                Else::If(if_) => self.collect_if_bindings(if_, bindings),
+                Else::Block(block_id) => self.collect_block_bindings(*block_id, bindings),
+            }
+        }
+    }
+
+    /// Record a reference to `binding` against every enclosing function that
+    /// doesn't define it. Module-level definitions are never captured.
+    fn record_reference(&self, binding: Binding) {
+        match binding {
+            Binding::Let(_) | Binding::State(_) | Binding::Parameter(_) | Binding::Pattern(_) => {}
+            Binding::Const(_)
+            | Binding::Variant(_)
+            | Binding::Function(_)
+            | Binding::Component(_)
+            | Binding::Context(_)
+            | Binding::Import(_)
+            | Binding::Global(_) => return,
+        }
+        let function_stack = self.function_stack.borrow();
+        let mut captures = self.captures.borrow_mut();
+        for (function_id, locals) in function_stack.iter().rev() {
+            if locals.contains(&binding) {
+                break;
+            }
+            captures.entry(*function_id).or_default().insert(binding);
+        }
+    }
+}
+
+impl<'a> Visitor for CaptureAnalysis<'a> {
+    fn context(&self) -> &AstArena {
+        self.arena
+    }
+
+    fn visit_function(&self, function_id: FunctionId) -> Result<()> {
+        let locals = self.local_bindings(function_id);
+        self.function_stack.borrow_mut().push((function_id, locals));
+        let result = walk_function(self, function_id);
+        self.function_stack.borrow_mut().pop();
+        result
+    }
+
+    fn visit_expression(&self, expression_id: ExpressionId) -> Result<()> {
+        let expression = self.arena.expressions.get(expression_id).unwrap().borrow();
+        if let Expression::Reference(binding) = *expression {
+            self.record_reference(binding);
+        }
+        drop(expression);
+        walk_expression(self, expression_id)
+    }
+
+    fn visit_statement(&self, statement_id: StatementId) -> Result<()> {
+        // Assigning to a binding from an outer scope captures it too
+        if let Statement::Assignment { name, .. } = self.arena.statements.get(statement_id).unwrap()
+        {
+            self.record_reference(*name);
+        }
+        walk_statement(self, statement_id)
+    }
+}
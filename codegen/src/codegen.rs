@@ -1,21 +1,33 @@
 use indexmap::IndexSet;
-use log::debug;
 use std::{
-    borrow::Borrow,
-    cell::RefCell,
+    cell::{Cell, RefCell},
     collections::{HashMap, HashSet},
+    rc::Rc,
     vec,
 };
+use tracing::{debug, debug_span, info_span, trace};
 use Direction::{Incoming, Outgoing};
 
-use crate::templates::{generate_template_instructions, TemplateInstruction};
+use crate::artifacts::write_output;
+use crate::captures::{CaptureAnalysis, CaptureMap};
+use crate::ir::{self, Instruction};
+use crate::loops::{BindingAccess, LoopInvariants};
+use crate::lower::JsExpression;
+use crate::purity::PurityAnalysis;
+use crate::templates::{
+    decode_html_entities, generate_template_instructions, Fragment, TemplateInstruction,
+    TemplateInstructionSet,
+};
 
 use common::petgraph::dot::Dot;
 use common::petgraph::graph::DiGraph;
 
 use common::control_flow_graph::{
-    ControlFlowEdge, ControlFlowGraph, ControlFlowMap, ControlFlowMapKey, ControlFlowNode,
+    BlockIndex, ControlFlowEdge, ControlFlowGraph, ControlFlowMap, ControlFlowMapKey,
+    ControlFlowNode,
 };
+use common::passes::OptLevel;
+use common::target::Target;
 use diagnostics::result::Result;
 use evaluate::Value;
 use petgraph::{
@@ -23,7 +35,11 @@ use petgraph::{
     visit::{EdgeRef, IntoEdgeReferences, NodeRef},
     Direction,
 };
-use syntax::ast_::*;
+use syntax::ast::*;
+use syntax::builtins::{parse_format, Builtin, FormatPart};
+use syntax::events::handled_event;
+use syntax::features::Features;
+use syntax::Span;
 
 type AstControlFlowGraph = ControlFlowGraph<StatementId, ExpressionId, Value>;
 
@@ -65,6 +81,7 @@ enum CodegenModuleLevelDefinition {
     Function {
         name: String,
         is_public: bool,
+        is_async: bool,
         params: Vec<String>,
         body: String,
     },
@@ -81,28 +98,186 @@ enum CodegenModuleLevelDefinition {
         is_public: bool,
         value: String,
     },
+    /// A context is a signal holding its default value. The signal itself is
+    /// the key that providers store their value under.
+    Context {
+        name: String,
+        is_public: bool,
+        value: String,
+    },
+    /// Either some of a module's exports, each like `helper` or `helper as h`,
+    /// or the whole module as a namespace.
+    Import {
+        path: String,
+        names: Vec<String>,
+        namespace: Option<String>,
+    },
 }
 
 #[derive(Default)]
 struct Minifier {
     offset: usize,
-    bindings: HashMap<Binding, String>,
+    bindings: HashMap<BindingRef, String>,
 }
 
 impl Minifier {
     fn get_minified_binding(&mut self, binding: &Binding) -> &str {
-        if self.bindings.contains_key(binding) {
-            self.bindings.get(binding).unwrap()
+        let binding = binding.reference();
+        if self.bindings.contains_key(&binding) {
+            self.bindings.get(&binding).unwrap()
         } else {
             let minified_binding =
                 format!("${}_", char::from_u32(97 + self.offset as u32).unwrap());
             self.offset += 1;
-            self.bindings.insert(*binding, minified_binding);
-            self.bindings.get(binding).unwrap()
+            self.bindings.insert(binding, minified_binding);
+            self.bindings.get(&binding).unwrap()
         }
     }
 }
 
+/// Names bindings can't be emitted with: JavaScript's reserved words, and
+/// the globals and imports that generated code relies on.
+const RESERVED_NAMES: &[&str] = &[
+    "arguments",
+    "await",
+    "break",
+    "case",
+    "catch",
+    "class",
+    "computed",
+    "const",
+    "continue",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "document",
+    "else",
+    "enum",
+    "eval",
+    "export",
+    "extends",
+    "false",
+    "finally",
+    "for",
+    "function",
+    "if",
+    "implements",
+    "import",
+    "in",
+    "instanceof",
+    "interface",
+    "let",
+    "new",
+    "null",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "return",
+    "signal",
+    "static",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "true",
+    "try",
+    "typeof",
+    "undefined",
+    "var",
+    "void",
+    "while",
+    "with",
+    "yield",
+];
+
+/// The names of the optimization passes, in the order they run.
+pub fn optimization_passes() -> Vec<&'static str> {
+    ir::passes(OptLevel::O2).names()
+}
+
+/// Options that change the output of codegen.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct CodegenOptions {
+    /// Emit template text exactly as it was written, instead of collapsing
+    /// whitespace like HTML does.
+    pub preserve_whitespace: bool,
+    /// Annotate generated statements with the line they were compiled from,
+    /// like `/* main.ws:12 */`, and name the output after the source file.
+    pub debug_comments: bool,
+    /// Where the output runs, which decides the globals it can use and
+    /// whether components can be compiled.
+    pub target: Target,
+    /// Leave out calls to `log`, like for a release build.
+    pub strip_logs: bool,
+    pub opt_level: OptLevel,
+    /// The optimization pass to print each block after, for debugging it.
+    pub print_after: Option<String>,
+    /// Don't warn about parameters that are never used.
+    pub allow_unused_parameters: bool,
+    /// Warn about if statements whose condition is always true or always
+    /// false, like one that checks a constant.
+    pub warn_constant_conditions: bool,
+    /// Report state written from a function that isn't an event handler as
+    /// an error, instead of warning about it.
+    pub deny_escaping_state_writes: bool,
+    /// Stop compiling a module once it has more errors than this, instead
+    /// of reporting every one. There's no limit if it's `None`.
+    pub max_errors: Option<usize>,
+    /// Check templates at runtime, like that they're mounted to a DOM node
+    /// and aren't updated after they're removed, and log each state update.
+    /// None of it is in the output otherwise.
+    pub dev: bool,
+    /// Name output files after a hash of their code, like `main.1a2b3c4d.js`,
+    /// with a `manifest.json` next to them mapping each module to its file.
+    /// Files can then be cached for as long as they exist.
+    pub hash_filenames: bool,
+    /// The edition modules are compiled with, and the experimental features
+    /// every module can use, along with the ones a module enables itself.
+    pub features: Features,
+}
+
+/// Where each line of the source starts, for mapping spans to lines. Spans are
+/// usually byte offsets, but they can be anything that increases through the
+/// source, like the index of each token.
+pub struct LineIndex {
+    /// The offset each line starts at, and its one-based line number.
+    line_starts: Vec<(usize, usize)>,
+}
+
+impl LineIndex {
+    pub fn new(source: &str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(index, _)| index + 1))
+            .zip(1..)
+            .collect();
+        Self { line_starts }
+    }
+
+    /// Build an index from the line each offset is on, starting at offset zero.
+    pub fn from_lines(lines: impl IntoIterator<Item = usize>) -> Self {
+        let mut line_starts: Vec<(usize, usize)> = vec![];
+        for (offset, line) in lines.into_iter().enumerate() {
+            if line_starts.last().map(|(_, last)| *last) != Some(line) {
+                line_starts.push((offset, line));
+            }
+        }
+        if line_starts.is_empty() {
+            line_starts.push((0, 1));
+        }
+        Self { line_starts }
+    }
+
+    /// The one-based line number that contains `offset`.
+    fn line(&self, offset: usize) -> usize {
+        let index = self
+            .line_starts
+            .partition_point(|(start, _)| *start <= offset);
+        self.line_starts[index.saturating_sub(1)].1
+    }
+}
+
 struct CodegenContext {
     block_depth: usize,
 }
@@ -112,6 +287,8 @@ struct CodegenContext {
  */
 pub struct Codegen<'a> {
     module_name: String,
+    /// The file debug comments point at.
+    source_path: String,
     control_flow_map:
         ControlFlowMap<FunctionId, ComponentId, StatementId, ExpressionId, evaluate::Value>,
     arena: &'a mut AstArena,
@@ -119,8 +296,29 @@ pub struct Codegen<'a> {
     scope: CodegenScope,
     definitions: RefCell<IndexSet<CodegenModuleLevelDefinition>>,
     template_function_map: RefCell<HashMap<TemplateId, String>>,
+    template_counts: RefCell<HashMap<String, usize>>,
     minifier: RefCell<Minifier>,
     completed_functions: RefCell<HashSet<FunctionId>>,
+    completed_components: RefCell<HashSet<ComponentId>>,
+    captures: RefCell<CaptureMap>,
+    /// The names of the functions that are pure, so calls to them can be
+    /// shared.
+    pure_functions: RefCell<Rc<HashSet<String>>>,
+    pure_function_ids: RefCell<HashSet<FunctionId>>,
+    /// Expressions computed ahead of a loop, and the names they were
+    /// assigned to.
+    hoisted: RefCell<HashMap<ExpressionId, String>>,
+    /// The first register for the next block lowered to IR. Registers are
+    /// numbered from zero in each function, so blocks in the same function
+    /// don't declare the same one.
+    next_register: Cell<usize>,
+    /// Each block as it was after the pass in `options.print_after` ran.
+    printed_passes: RefCell<Vec<String>>,
+    options: CodegenOptions,
+    line_index: Option<LineIndex>,
+    /// Where each import's module is, once resolved to a file. Imports that
+    /// aren't in here are imported from the path they're written with.
+    import_paths: HashMap<ImportId, String>,
 }
 
 impl<'a> Codegen<'a> {
@@ -136,20 +334,103 @@ impl<'a> Codegen<'a> {
         >,
     ) -> Self {
         Self {
+            source_path: format!("{}.ws", module_name),
             module_name,
             arena,
             scope: CodegenScope::default(),
             definitions: Default::default(),
             template_function_map: Default::default(),
+            template_counts: Default::default(),
             minifier: Default::default(),
             control_flow_map,
             completed_functions: Default::default(),
+            completed_components: Default::default(),
+            captures: Default::default(),
+            pure_functions: Default::default(),
+            pure_function_ids: Default::default(),
+            hoisted: Default::default(),
+            next_register: Default::default(),
+            printed_passes: Default::default(),
+            options: Default::default(),
+            line_index: None,
+            import_paths: Default::default(),
         }
     }
 
+    pub fn with_options(self, options: CodegenOptions) -> Self {
+        Self { options, ..self }
+    }
+
+    pub fn with_import_paths(self, import_paths: HashMap<ImportId, String>) -> Self {
+        Self {
+            import_paths,
+            ..self
+        }
+    }
+
+    /// Provide the source being compiled, so spans can be mapped to lines.
+    pub fn with_source(self, source: &str) -> Self {
+        self.with_line_index(LineIndex::new(source))
+    }
+
+    /// Like `with_source`, for spans that aren't byte offsets.
+    pub fn with_line_index(self, line_index: LineIndex) -> Self {
+        let line_index = Some(line_index);
+        Self { line_index, ..self }
+    }
+
+    /// Provide the path of the file being compiled, for debug comments.
+    /// Without one, they point at a file named after the module.
+    pub fn with_source_path(self, source_path: String) -> Self {
+        Self {
+            source_path,
+            ..self
+        }
+    }
+
+    /// A comment pointing at the line `span` starts on, if debug comments
+    /// are enabled.
+    fn debug_comment(&self, span: Option<Span>) -> Option<String> {
+        if !self.options.debug_comments {
+            return None;
+        }
+        let line_index = self.line_index.as_ref()?;
+        let range: std::ops::Range<usize> = span?.into();
+        Some(format!(
+            "/* {}:{} */",
+            self.source_path,
+            line_index.line(range.start)
+        ))
+    }
+
     pub fn codegen_module(&self, module_id: ModuleId) -> Result<()> {
+        self.codegen_definitions(module_id, |_| true)
+    }
+
+    /// Generate code for the definitions in a module that `include` accepts.
+    /// Definitions they reference are still generated, whether or not they're
+    /// included themselves.
+    pub fn codegen_definitions(
+        &self,
+        module_id: ModuleId,
+        include: impl Fn(&Definition) -> bool,
+    ) -> Result<()> {
+        let _span = info_span!("codegen", module = %self.module_name).entered();
+        let captures = CaptureAnalysis::new(self.arena).analyze(module_id)?;
+        let pure_function_ids = PurityAnalysis::new(self.arena, &captures).analyze(module_id)?;
+        let pure_functions = pure_function_ids
+            .iter()
+            .map(|function_id| self.binding_name(Binding::Function(*function_id)))
+            .collect();
+        self.captures.replace(captures);
+        self.pure_functions.replace(Rc::new(pure_functions));
+        self.pure_function_ids.replace(pure_function_ids);
         let module = self.arena.modules.get(module_id).unwrap();
-        for definition in &module.definitions {
+        for definition in module
+            .definitions
+            .iter()
+            .filter(|definition| include(definition))
+        {
             match definition.kind {
                 DefinitionKind::Function(function_id) => {
                     if definition.public {
@@ -169,9 +450,34 @@ impl<'a> Codegen<'a> {
                         self.codegen_component(component_id, cfg, true)?;
                     }
                 }
-                DefinitionKind::Const(_) => {
-                    // ...
+                DefinitionKind::Const(const_id) => {
+                    // Private constants are folded where they're used, or
+                    // defined once something refers to them by name.
+                    if definition.public {
+                        self.codegen_const(const_id, true)?;
+                    }
+                }
+                DefinitionKind::Context(context_id) => {
+                    let context = self.arena.contexts.get(context_id).unwrap();
+                    let name = self.binding_name(Binding::Context(context_id));
+                    let value = self.codegen_expression(context.value)?;
+                    self.definitions
+                        .borrow_mut()
+                        .insert(CodegenModuleLevelDefinition::Context {
+                            name,
+                            is_public: definition.public,
+                            value,
+                        });
                 }
+                DefinitionKind::Import(import_id) => self.codegen_import(import_id),
+                // Effects are only checked, there's nothing to run
+                DefinitionKind::Effect(_) => {}
+                // Variants are created where they're used, so enums have
+                // nothing to define
+                DefinitionKind::Enum(_) => {}
+                // Aliases are expanded where they're used, and types aren't
+                // checked at runtime
+                DefinitionKind::TypeAlias(_) => {}
                 DefinitionKind::Struct(_) => todo!(),
             }
         }
@@ -179,12 +485,70 @@ impl<'a> Codegen<'a> {
         Ok(())
     }
 
-    fn define_function(&self, name: String, is_public: bool, params: Vec<String>, body: String) {
+    /// Define a constant at the module level. Its value is lowered first, so
+    /// any constants it reads are defined before it is.
+    fn codegen_const(&self, const_id: ConstId, is_public: bool) -> Result<()> {
+        let const_ = &self.arena.consts[const_id];
+        let name = self.binding_name(Binding::Const(const_id));
+        let value = self.codegen_expression(const_.value)?;
+        self.definitions
+            .borrow_mut()
+            .insert(CodegenModuleLevelDefinition::Constant {
+                name,
+                is_public,
+                value,
+            });
+        Ok(())
+    }
+
+    fn codegen_import(&self, import_id: ImportId) {
+        let import = &self.arena.imports[import_id];
+        let path = match self.import_paths.get(&import_id) {
+            Some(path) => path.clone(),
+            None => {
+                let path: Vec<String> = import
+                    .path
+                    .iter()
+                    .map(|part| part.symbol.to_string())
+                    .collect();
+                format!("./{}.js", path.join("/"))
+            }
+        };
+        let mut names = vec![];
+        let mut namespace = None;
+        for specifier_id in &import.specifiers {
+            let specifier = &self.arena.import_specifiers[*specifier_id];
+            // The local name can be renamed, but the export has to keep its name
+            let local = self.binding_name(Binding::Import(*specifier_id));
+            match specifier.name {
+                Some(name) if name.symbol.to_string() == local => names.push(local),
+                Some(name) => names.push(format!("{} as {}", name.symbol, local)),
+                None => namespace = Some(local),
+            }
+        }
+        self.definitions
+            .borrow_mut()
+            .insert(CodegenModuleLevelDefinition::Import {
+                path,
+                names,
+                namespace,
+            });
+    }
+
+    fn define_function(
+        &self,
+        name: String,
+        is_public: bool,
+        is_async: bool,
+        params: Vec<String>,
+        body: String,
+    ) {
         self.definitions
             .borrow_mut()
             .insert(CodegenModuleLevelDefinition::Function {
                 name,
                 is_public,
+                is_async,
                 params,
                 body,
             });
@@ -212,23 +576,30 @@ impl<'a> Codegen<'a> {
     }
 
     pub fn write(&mut self, path: std::path::PathBuf) -> Result<()> {
-        use std::fmt::Write;
-        let mut output = String::new();
-        self.write_header(&mut output)?;
-
-        writeln!(output, "import {{signal}} from '@preact/signals-core';")?;
+        let definitions = self.rendered_definitions()?;
+        write_module(path, &self.module_name, &self.options, &definitions)
+    }
 
+    /// The module-level definitions generated so far, each rendered as JavaScript.
+    pub fn rendered_definitions(&self) -> Result<Vec<String>> {
+        use std::fmt::Write;
+        let mut definitions = vec![];
         for definition in self.definitions.borrow().iter() {
+            let mut output = String::new();
             match definition {
                 CodegenModuleLevelDefinition::Function {
                     name,
                     is_public,
+                    is_async,
                     params,
                     body,
                 } => {
                     if *is_public {
                         write!(output, "export ")?;
                     }
+                    if *is_async {
+                        write!(output, "async ")?;
+                    }
                     writeln!(output, "function {}({}) {{", name, params.join(", "))?;
                     writeln!(output, "{}", body)?;
                     writeln!(output, "}}")?;
@@ -241,7 +612,17 @@ impl<'a> Codegen<'a> {
                     if *is_public {
                         write!(output, "export ")?;
                     }
-                    writeln!(output, "const {}: {} = {};", name, "Value", value)?;
+                    writeln!(output, "const {} = {};", name, value)?;
+                }
+                CodegenModuleLevelDefinition::Context {
+                    name,
+                    is_public,
+                    value,
+                } => {
+                    if *is_public {
+                        write!(output, "export ")?;
+                    }
+                    writeln!(output, "const {} = signal({});", name, value)?;
                 }
                 CodegenModuleLevelDefinition::Class {
                     name,
@@ -268,26 +649,80 @@ impl<'a> Codegen<'a> {
                     }
                     writeln!(output, "}}")?;
                 }
+                CodegenModuleLevelDefinition::Import {
+                    path,
+                    names,
+                    namespace,
+                } => match namespace {
+                    Some(namespace) => {
+                        writeln!(output, "import * as {} from '{}';", namespace, path)?
+                    }
+                    None => writeln!(output, "import {{{}}} from '{}';", names.join(", "), path)?,
+                },
             }
+            definitions.push(output);
         }
+        Ok(definitions)
+    }
 
-        std::fs::write(path, output)?;
-        Ok(())
+    /// The names of the public definitions generated so far, which the
+    /// module exports.
+    /// The blocks printed after the pass in `print_after` ran, in the
+    /// order they were generated.
+    pub fn take_printed_passes(&self) -> Vec<String> {
+        self.printed_passes.take()
     }
 
-    pub fn write_header(&self, output: &mut String) -> Result<()> {
-        let header = format! {r"
-          /**
-           * GENERATED FILE - DO NOT EDIT
-           * Compiled from module: {}.ws
-           * Generated at {}
-           */
-        ",
-            self.module_name,
-            chrono::Utc::now().to_rfc3339()
-        };
-        output.push_str(&header);
-        Ok(())
+    pub fn exported_names(&self) -> Vec<String> {
+        self.definitions
+            .borrow()
+            .iter()
+            .filter_map(|definition| match definition {
+                CodegenModuleLevelDefinition::Function {
+                    name, is_public, ..
+                }
+                | CodegenModuleLevelDefinition::Class {
+                    name, is_public, ..
+                }
+                | CodegenModuleLevelDefinition::Constant {
+                    name, is_public, ..
+                }
+                | CodegenModuleLevelDefinition::Context {
+                    name, is_public, ..
+                } => is_public.then(|| name.clone()),
+                CodegenModuleLevelDefinition::Import { .. } => None,
+            })
+            .collect()
+    }
+
+    /// Whether this function closes over bindings from an enclosing scope.
+    /// Closures can't be hoisted to the module level, since the bindings
+    /// they reference only exist inside the function or component that
+    /// declares them.
+    fn is_closure(&self, function_id: FunctionId) -> bool {
+        self.captures
+            .borrow()
+            .get(&function_id)
+            .is_some_and(|captures| !captures.is_empty())
+    }
+
+    /// The name `binding` is emitted with. Bindings named after a reserved
+    /// word or a name the generated code uses get their reference appended,
+    /// so they can't clash with it or with each other.
+    fn binding_name(&self, binding: Binding) -> String {
+        let name = binding.to_string(self.arena);
+        // Globals are defined by the target, so they have to keep their name
+        if let Binding::Global(global_id) = binding {
+            return match self.arena.globals[global_id].builtin {
+                Some(Builtin::Log) => "console.log".to_string(),
+                None => name,
+            };
+        }
+        if RESERVED_NAMES.contains(&name.as_str()) {
+            format!("{}${}", name, binding.reference())
+        } else {
+            name
+        }
     }
 
     // TODO - This should be a symbol
@@ -311,11 +746,25 @@ impl<'a> Codegen<'a> {
         cfg: &AstControlFlowGraph,
         is_public: bool,
     ) -> Result<()> {
+        if self.completed_components.borrow().contains(&component_id) {
+            return Ok(());
+        }
         self.scope.set_scope(component_id.into());
 
         let component = self.arena.components.get(component_id).unwrap().borrow();
+        let _span = debug_span!("codegen_component", name = %component.name.symbol).entered();
+        // Templates are built with the DOM, so targets without one can't
+        // have components.
+        if !self.options.target.has_dom() {
+            use diagnostics::error::component_without_dom;
+            return component_without_dom(
+                component.name.span,
+                component.name.symbol,
+                self.options.target,
+            );
+        }
 
-        let component_parameters = if let Some(parameters) = &component.parameters {
+        let mut component_parameters = if let Some(parameters) = &component.parameters {
             parameters
                 .iter()
                 .map(|parameter| {
@@ -323,22 +772,22 @@ impl<'a> Codegen<'a> {
                     //     .borrow_mut()
                     //     .get_minified_binding(&Binding::Parameter(*parameter))
                     //     .to_string()
-                    self.arena
-                        .parameters
-                        .get(*parameter)
-                        .unwrap()
-                        .borrow()
-                        .name
-                        .symbol
-                        .to_string()
+                    self.binding_name(Binding::Parameter(*parameter))
                 })
                 .collect()
         } else {
             vec![]
         };
 
+        // Children passed to the component are rendered by its `<slot />`
+        component_parameters.push(String::from("$children"));
+        // The contexts provided by the components that mounted this one
+        component_parameters.push(String::from("$context = new Map()"));
+
+        let registers = self.next_register.replace(0);
         let component_body = self.codegen_from_cfg(cfg, None, None, &Default::default())?;
-        let component_name = self.current_scope_name();
+        self.next_register.set(registers);
+        let component_name = self.binding_name(Binding::Component(component_id));
 
         self.define_class(
             component_name,
@@ -348,6 +797,7 @@ impl<'a> Codegen<'a> {
             component_parameters,
             vec![],
         );
+        self.completed_components.borrow_mut().insert(component_id);
         Ok(())
     }
 
@@ -361,30 +811,28 @@ impl<'a> Codegen<'a> {
             return Ok(());
         }
         let function = self.arena.functions.get(function_id).unwrap().borrow();
-        let function_name = function.name.symbol.to_string();
-        println!("codegen_function {}", function_name);
+        let function_name = self.binding_name(Binding::Function(function_id));
+        let _span = debug_span!("codegen_function", name = %function_name).entered();
         let function_parameters = if let Some(parameters) = &function.parameters {
             parameters
                 .iter()
-                .map(|parameter| {
-                    self.arena
-                        .parameters
-                        .get(*parameter)
-                        .unwrap()
-                        .borrow()
-                        .name
-                        .symbol
-                        .to_string()
-                })
+                .map(|parameter| self.binding_name(Binding::Parameter(*parameter)))
                 .collect()
         } else {
             vec![]
         };
-        println!("codegen_function_expression: {}", function_name);
-        cfg.print();
+        trace!(cfg = %cfg.format());
 
+        let registers = self.next_register.replace(0);
         let codegen_body = self.codegen_from_cfg(cfg, None, None, &Default::default())?;
-        self.define_function(function_name, is_public, function_parameters, codegen_body);
+        self.next_register.set(registers);
+        self.define_function(
+            function_name,
+            is_public,
+            function.is_async,
+            function_parameters,
+            codegen_body,
+        );
         self.completed_functions.borrow_mut().insert(function_id);
         Ok(())
     }
@@ -392,20 +840,11 @@ impl<'a> Codegen<'a> {
     pub fn codegen_function_expression(&self, function_id: FunctionId) -> Result<String> {
         use std::fmt::Write;
         let function = self.arena.functions.get(function_id).unwrap().borrow();
-        let function_name = function.name.symbol.to_string();
+        let function_name = self.binding_name(Binding::Function(function_id));
         let function_parameters = if let Some(parameters) = &function.parameters {
             parameters
                 .iter()
-                .map(|parameter| {
-                    self.arena
-                        .parameters
-                        .get(*parameter)
-                        .unwrap()
-                        .borrow()
-                        .name
-                        .symbol
-                        .to_string()
-                })
+                .map(|parameter| self.binding_name(Binding::Parameter(*parameter)))
                 .collect()
         } else {
             vec![]
@@ -417,14 +856,17 @@ impl<'a> Codegen<'a> {
             .get(&ControlFlowMapKey::Function(function_id))
             .unwrap();
 
-        println!("codegen_function_expression: {}", function_name);
-        cfg.print();
+        let _span = debug_span!("codegen_function_expression", name = %function_name).entered();
+        trace!(cfg = %cfg.format());
 
+        let registers = self.next_register.replace(0);
         let codegen_body = self.codegen_from_cfg(cfg, None, None, &Default::default())?;
+        self.next_register.set(registers);
 
         writeln!(
             output,
-            "function {}({}) {{",
+            "{}function {}({}) {{",
+            if function.is_async { "async " } else { "" },
             function_name,
             function_parameters.join(",")
         )?;
@@ -433,23 +875,79 @@ impl<'a> Codegen<'a> {
         Ok(output)
     }
 
-    fn codegen_branch(
+    /// Generate the if statement for the branch at `condition_index`, along
+    /// with any else-ifs and else chained onto it, returning it and where
+    /// code continues after it. `is_else_if` is whether it's chained onto
+    /// the else of another if statement.
+    fn codegen_if(
         &self,
         cfg: &AstControlFlowGraph,
-        start: NodeIndex,
-        end: NodeIndex,
+        condition_index: NodeIndex,
+        end: Option<NodeIndex>,
+        is_else_if: bool,
         visited: &RefCell<HashSet<NodeIndex>>,
-    ) -> Result<String> {
+    ) -> Result<(String, Option<NodeIndex>)> {
         use std::fmt::Write;
-        // let node = cfg.graph.node_weight(node_index).unwrap();
-        let mut branch_code = String::new();
+        visited.borrow_mut().insert(condition_index);
+        let condition = match cfg.graph.node_weight(condition_index) {
+            Some(ControlFlowNode::BranchCondition(condition)) => *condition,
+            _ => unreachable!("if statements are generated from branch conditions"),
+        };
+        let (consequent, alternate) = cfg.branch_targets(BlockIndex(condition_index));
+        let (consequent, alternate) = (consequent.0, alternate.0);
+        let merge = cfg
+            .merge_point(
+                BlockIndex(consequent),
+                BlockIndex(alternate),
+                end.map(BlockIndex),
+            )
+            .map(|merge| merge.0);
+        // Branches that never meet again both go on to the end
+        let next = merge.or(end);
 
-        writeln!(branch_code, "// ...")?;
-        let code = self.codegen_from_cfg(cfg, Some(start), Some(end), visited)?;
-        // ...
-        Ok(code)
+        let mut codegen = String::new();
+        let span = self.arena.expression_span(condition);
+        if let Some(comment) = self.debug_comment(span) {
+            write!(codegen, "{} ", comment)?;
+        }
+        let codegen_condition = self.codegen_expression(condition)?;
+        let codegen_body = self.codegen_from_cfg(cfg, Some(consequent), next, visited)?;
+        trace!(%codegen_condition, "generated branch");
+        write!(codegen, "if ({}) {{\n{}}}", codegen_condition, codegen_body)?;
+
+        if Some(alternate) == merge {
+            // There's no else
+            return Ok((codegen, next));
+        }
+        if !is_else_if
+            && merge.is_none()
+            && cfg.always_returns(BlockIndex(consequent), end.map(BlockIndex))
+        {
+            // The rest only runs when the condition is false, so it doesn't
+            // need to be in an else
+            return Ok((codegen, Some(alternate)));
+        }
+        if let Some(ControlFlowNode::BranchCondition(_)) = cfg.graph.node_weight(alternate) {
+            let (codegen_else_if, after) = self.codegen_if(cfg, alternate, next, true, visited)?;
+            if after == next {
+                write!(codegen, " else {}", codegen_else_if)?;
+            } else {
+                let rest = match after {
+                    Some(after) => self.codegen_from_cfg(cfg, Some(after), next, visited)?,
+                    None => String::new(),
+                };
+                write!(codegen, " else {{\n{}\n{}}}", codegen_else_if, rest)?;
+            }
+        } else {
+            let codegen_else = self.codegen_from_cfg(cfg, Some(alternate), next, visited)?;
+            write!(codegen, " else {{\n{}}}", codegen_else)?;
+        }
+        Ok((codegen, next))
     }
 
+    /// Generate the code for the graph from `start`, or its first block, up to
+    /// `end`, or its exit. Each kind of node says where control goes after
+    /// it, so the nodes are generated in the order they run.
     pub fn codegen_from_cfg(
         &self,
         cfg: &AstControlFlowGraph,
@@ -457,144 +955,251 @@ impl<'a> Codegen<'a> {
         end: Option<NodeIndex>,
         visited: &RefCell<HashSet<NodeIndex>>,
     ) -> Result<String> {
-        use petgraph::visit::Dfs;
         use std::fmt::Write;
 
         let start = start.unwrap_or(cfg.first_index().unwrap_or(cfg.entry_index()).0);
-        debug!("codegen_from_cfg, start: {:?}", start);
-        cfg.print();
-
-        let mut visitor = Dfs::new(&cfg.graph, start);
+        trace!(?start, "codegen_from_cfg");
+        trace!(cfg = %cfg.format());
 
         let mut codegen = String::new();
+        let mut next = Some(start);
 
-        while let Some(node_index) = visitor.next(&cfg.graph) {
-            if let Some(end) = end {
-                if node_index == end {
-                    break;
-                }
-            }
-            if visited.borrow().contains(&node_index) {
-                continue;
+        while let Some(node_index) = next {
+            if Some(node_index) == end || visited.borrow().contains(&node_index) {
+                break;
             }
             let node = cfg.graph.node_weight(node_index).unwrap();
-            debug!("codegen_from_cfg, node: {:?}", node);
-            match node {
+            trace!(?node, "codegen_from_cfg node");
+            next = match node {
                 ControlFlowNode::BasicBlock(block) => {
                     visited.borrow_mut().insert(node_index);
+                    let mut ir_block = self.ir_block();
                     for statement_id in block.statements.iter() {
-                        let code = self.codegen_statement(*statement_id)?;
-                        writeln!(codegen, "{}", code)?;
+                        self.lower_statement(&mut ir_block, *statement_id)?;
+                    }
+                    for line in self.finish_ir_block(ir_block) {
+                        writeln!(codegen, "{}", line)?;
                     }
+                    // Blocks either return or fall through to one other node
+                    cfg.graph.neighbors(node_index).next()
+                }
+                ControlFlowNode::BranchCondition(_) => {
+                    let (codegen_if, next) =
+                        self.codegen_if(cfg, node_index, end, false, visited)?;
+                    writeln!(codegen, "{}", codegen_if)?;
+                    next
                 }
-                ControlFlowNode::BranchCondition(condition) => {
+                ControlFlowNode::LoopCondition(condition) => {
                     visited.borrow_mut().insert(node_index);
-                    debug!("BranchCondition");
-                    // This is a branching condition, which will have edges to the blocks
-                    // that are executed if the condition is true and false.
-                    // The order in which we encounter these edges does not match the order
-                    // we generate the code in (false edges come first due to how the graph
-                    // is constructed).
-
-                    let directed_edges = cfg.graph.edges_directed(node_index, Direction::Outgoing);
-                    let (true_edge_target, false_edge_target) = {
-                        let mut true_edge_target = None;
-                        let mut false_edge_targe = None;
-                        for edge in directed_edges {
-                            let edge_target = edge.target();
-                            let edge_weight = edge.weight();
-                            match edge_weight {
-                                ControlFlowEdge::ConditionTrue => {
-                                    true_edge_target = Some(edge_target);
-                                }
-                                ControlFlowEdge::ConditionFalse => {
-                                    false_edge_targe = Some(edge_target)
-                                }
-                                _ => {}
-                            }
-                        }
-                        (true_edge_target.unwrap(), false_edge_targe.unwrap())
+                    // The body ends with edges back to the condition, so it's
+                    // generated up to there. The false edge is where we continue.
+                    let target = |weight: ControlFlowEdge| {
+                        cfg.graph
+                            .edges_directed(node_index, Direction::Outgoing)
+                            .find(|edge| *edge.weight() == weight)
+                            .map(|edge| edge.target())
                     };
-
+                    let body_start = target(ControlFlowEdge::ConditionTrue).unwrap();
+                    if self.options.opt_level >= OptLevel::O2 {
+                        for line in self.hoist_loop_invariants(cfg, node_index)? {
+                            writeln!(codegen, "{}", line)?;
+                        }
+                    }
                     let codegen_condition = self.codegen_expression(*condition)?;
-                    let codegen_branch_block_code =
-                        self.codegen_branch(cfg, true_edge_target, false_edge_target, visited)?;
-
-                    debug!("codegen_condition: {}", codegen_condition);
-                    debug!("codegen_branch_block_code: {}", codegen_branch_block_code);
-
-                    let condition_codegen = format!(
-                        r"if ({}) {{
-                                {}
-                            }}",
-                        codegen_condition, codegen_branch_block_code
-                    );
-                    writeln!(codegen, "{}", condition_codegen)?;
-                }
-                // ...
-                ControlFlowNode::LoopCondition(_) => todo!(),
-                ControlFlowNode::Entry | ControlFlowNode::Exit => {
-                    // Nothing for now
+                    let codegen_body =
+                        self.codegen_from_cfg(cfg, Some(body_start), Some(node_index), visited)?;
+                    let span = self.arena.expression_span(*condition);
+                    if let Some(comment) = self.debug_comment(span) {
+                        write!(codegen, "{} ", comment)?;
+                    }
+                    writeln!(
+                        codegen,
+                        "while ({}) {{\n{}}}",
+                        codegen_condition, codegen_body
+                    )?;
+                    target(ControlFlowEdge::ConditionFalse)
                 }
-            }
+                ControlFlowNode::Entry => cfg.graph.neighbors(node_index).next(),
+                ControlFlowNode::Exit => None,
+            };
         }
         Ok(codegen)
     }
 
-    fn codegen_statement(&self, statement: StatementId) -> Result<String> {
-        let statement = self.arena.statements.get(statement).unwrap();
-        match statement {
-            Statement::Let { name, value } => {
-                let expression_id = *value;
-                drop(statement);
-                let value = self.codegen_expression(expression_id)?;
-                Ok(format!("let {} = {};", name.symbol, value))
+    /// Compute the expressions in a loop that are the same every time around
+    /// it ahead of the loop, returning the declarations for them.
+    fn hoist_loop_invariants(
+        &self,
+        cfg: &AstControlFlowGraph,
+        condition: NodeIndex,
+    ) -> Result<Vec<String>> {
+        // Calling an impure function could run a closure, which could write
+        // anything mutable it captures.
+        let captured = self
+            .captures
+            .borrow()
+            .values()
+            .flatten()
+            .filter(|binding| binding.is_mutable(self.arena))
+            .copied()
+            .collect();
+        let pure_function_ids = self.pure_function_ids.borrow();
+        let access = BindingAccess::new(self.arena, &pure_function_ids, captured);
+        let invariants = LoopInvariants::new(&access, cfg, condition)
+            .find(|expression_id| self.hoisted.borrow().contains_key(&expression_id));
+        let mut declarations = vec![];
+        for expression_id in invariants {
+            let value = self.codegen_expression(expression_id)?;
+            let name = format!("$h{}", expression_id.index());
+            declarations.push(format!("const {} = {};", name, value));
+            self.hoisted.borrow_mut().insert(expression_id, name);
+        }
+        Ok(declarations)
+    }
+
+    fn ir_block(&self) -> ir::Block {
+        ir::Block::new(self.next_register.get())
+            .with_pure_functions(self.pure_functions.borrow().clone())
+    }
+
+    /// Optimize the instructions for a block and emit them.
+    fn finish_ir_block(&self, mut block: ir::Block) -> Vec<String> {
+        self.next_register.set(block.next_register());
+        let printed = ir::passes(self.options.opt_level)
+            .with_print_after(self.options.print_after.clone())
+            .run(&mut block);
+        self.printed_passes.borrow_mut().extend(printed);
+        block.emit()
+    }
+
+    /// Lower a statement into instructions at the end of `block`.
+    fn lower_statement(&self, block: &mut ir::Block, statement_id: StatementId) -> Result<()> {
+        let span = self.arena.statement_span(statement_id);
+        if let Some(comment) = self.debug_comment(span) {
+            block.push(Instruction::Comment(comment));
+        }
+        let statement = self.arena.statements.get(statement_id).unwrap();
+        let instruction = match statement {
+            Statement::Let { value, .. } if self.returns_from_branch(*value) => {
+                let name = self.binding_name(Binding::Let(statement_id));
+                block.push(Instruction::Code(format!("let {};", name)));
+                return self.lower_if_statement(block, *value, &|value| Instruction::Assign {
+                    name: name.clone(),
+                    value,
+                });
+            }
+            Statement::Let { value, .. } => {
+                let name = self.binding_name(Binding::Let(statement_id));
+                let value = block.lower(self.lower_expression(*value)?);
+                Instruction::Let { name, value }
+            }
+            Statement::Return(value) if self.returns_from_branch(*value) => {
+                return self.lower_if_statement(block, *value, &Instruction::Return);
             }
             Statement::Return(value) => {
-                drop(statement);
-                let value = self.codegen_expression(*value)?;
-                Ok(format!("return {};", value))
+                Instruction::Return(block.lower(self.lower_expression(*value)?))
+            }
+            Statement::Expression(expression_id) | Statement::Discard(expression_id)
+                if self.returns_from_branch(*expression_id) =>
+            {
+                return self.lower_if_statement(block, *expression_id, &Instruction::Evaluate);
             }
             Statement::State(state_id) => {
-                let State { name, value } = self.arena.states.get(*state_id).unwrap();
-                drop(statement);
-                let value = self.codegen_expression(*value)?;
-                Ok(format!("let {} = signal({});", name.symbol, value))
+                let State { value, .. } = self.arena.states.get(*state_id).unwrap();
+                let name = self.binding_name(Binding::State(statement_id));
+                let value = block.lower(self.lower_expression(*value)?);
+                Instruction::Signal { name, value }
             }
             Statement::Expression(expression_id) => {
-                drop(statement);
-                let expression = self.codegen_expression(*expression_id)?;
-                Ok(format!("{};", expression))
+                if self.options.strip_logs && self.is_log_call(*expression_id) {
+                    return Ok(());
+                }
+                Instruction::Evaluate(block.lower(self.lower_expression(*expression_id)?))
+            }
+            Statement::Discard(expression_id) => {
+                Instruction::Evaluate(block.lower(self.lower_expression(*expression_id)?))
+            }
+            Statement::Provide { context, value } => {
+                // Providing a context creates a new context map, so only the
+                // components mounted from here on see the value.
+                let name = self.binding_name(Binding::Context(*context));
+                let value = self.codegen_expression(*value)?;
+                Instruction::Code(format!(
+                    "$context = new Map($context).set({}, computed(() => {}));",
+                    name, value
+                ))
             }
             Statement::Assignment { name, value } => {
-                drop(statement);
+                let value = block.lower(self.lower_expression(*value)?);
                 if let Binding::State(_) = name {
-                    let name = name.to_string(&self.arena);
-                    let value = self.codegen_expression(*value)?;
-                    Ok(format!("{}.value = {};", name, value))
+                    let signal = self.binding_name(*name);
+                    if !self.options.dev {
+                        Instruction::Store { signal, value }
+                    } else {
+                        let log = format!(
+                            "console.debug(\"[dev] {} =\", {}.value);",
+                            name.to_string(self.arena),
+                            signal
+                        );
+                        block.push(Instruction::Store { signal, value });
+                        Instruction::Code(log)
+                    }
                 } else {
-                    let name = name.to_string(&self.arena);
-                    let value = self.codegen_expression(*value)?;
-                    Ok(format!("{} = {};", name, value))
+                    let name = self.binding_name(*name);
+                    Instruction::Assign { name, value }
                 }
             }
-            Statement::If(_) => todo!(),
-            Statement::While { .. } => todo!(),
-        }
+            // Functions are generated from their control flow graph, so these
+            // are only lowered here when they're nested in an if-expression
+            Statement::If(if_) => Instruction::Code(self.lower_if(if_)?),
+            Statement::While { condition, body } => {
+                let condition = self.codegen_expression(*condition)?;
+                let body = self.lower_block(*body)?;
+                Instruction::Code(format!("while ({}) {{ {} }}", condition, body))
+            }
+        };
+        block.push(instruction);
+        Ok(())
     }
 
     fn codegen_expression(&self, expression_id: ExpressionId) -> Result<String> {
+        Ok(self.lower_expression(expression_id)?.to_string())
+    }
+
+    /// Lower an expression into a `JsExpression` that can be emitted directly.
+    fn lower_expression(&self, expression_id: ExpressionId) -> Result<JsExpression> {
+        if let Some(name) = self.hoisted.borrow().get(&expression_id) {
+            return Ok(JsExpression::Identifier(name.clone()));
+        }
         let expression = self.arena.expressions.get(expression_id).unwrap().borrow();
         match &*expression {
-            Expression::Number(value) => Ok(format!("{}", value)),
-            Expression::Template(template_id) => self.codegen_template(*template_id),
-            // Expression::Binary { left, right, op } => todo!(),
-            Expression::Boolean(value) => Ok(format!("{}", value)),
-            Expression::String(value) => Ok(format!("\"{}\"", value)),
+            Expression::Number {
+                literal: Some(literal),
+                ..
+            } => Ok(JsExpression::Literal(number_literal(&literal.to_string()))),
+            Expression::Number { value, .. } => Ok(JsExpression::Literal(format!("{}", value))),
+            Expression::Template(template_id) => {
+                Ok(JsExpression::Code(self.codegen_template(*template_id)?))
+            }
+            Expression::Boolean(value) => Ok(JsExpression::Literal(format!("{}", value))),
+            Expression::String(value) => Ok(JsExpression::String(value.to_string())),
             Expression::Reference(binding) => {
+                let name = self.binding_name(*binding);
                 match binding {
-                    Binding::State(_) => Ok(format!("{}.value", binding.to_string(&self.arena))),
-                    _ => Ok(binding.to_string(&self.arena)),
+                    Binding::State(_) => Ok(JsExpression::SignalRead(name)),
+                    // Constants that couldn't be folded, like ones that read
+                    // an import, are read from their definition
+                    Binding::Const(const_id) => {
+                        let is_public = self.arena.modules.iter().any(|(_, module)| {
+                            module.definitions.iter().any(|definition| {
+                                definition.public
+                                    && definition.kind == DefinitionKind::Const(*const_id)
+                            })
+                        });
+                        self.codegen_const(*const_id, is_public)?;
+                        Ok(JsExpression::Identifier(name))
+                    }
+                    _ => Ok(JsExpression::Identifier(name)),
                 }
                 // ...
                 // Ok(self
@@ -603,24 +1208,39 @@ impl<'a> Codegen<'a> {
                 //     .get_minified_binding(binding)
                 //     .to_string())
             }
-            Expression::Function(function_id) => self.codegen_function_expression(*function_id),
+            Expression::Function(function_id) => Ok(JsExpression::Code(
+                self.codegen_function_expression(*function_id)?,
+            )),
+            Expression::UseContext(context_id) => {
+                Ok(JsExpression::SignalRead(self.context_signal(*context_id)))
+            }
             Expression::Binary { left, right, op } => {
-                let left = self.codegen_expression(*left)?;
-                let right = self.codegen_expression(*right)?;
-                Ok(format!("{} {} {}", left, op, right))
+                let left = self.lower_expression(*left)?;
+                let right = self.lower_expression(*right)?;
+                let expression = JsExpression::binary(left, op.clone(), right);
+                match op {
+                    BinOp::And | BinOp::Or => Ok(expression.memoize_signal_reads()),
+                    _ => Ok(expression),
+                }
+            }
+            Expression::Call { arguments, .. } if self.is_log_call(expression_id) => {
+                self.lower_log(arguments)
             }
             Expression::Call { callee, arguments } => {
                 // Make sure this function gets compiled.
                 let callee_expression = self.arena.expressions.get(*callee).unwrap().borrow();
-                println!("callee_expression: {:?}", callee_expression);
                 if let Expression::Reference(binding) = &*callee_expression {
                     if let Binding::Function(function_id) = binding {
-                        let cfg = self
-                            .control_flow_map
-                            .get(&ControlFlowMapKey::Function(*function_id))
-                            .unwrap();
-                        println!("calle_expression");
-                        self.codegen_function(*function_id, cfg, false)?;
+                        // Closures are emitted where they are declared, since the
+                        // bindings they capture don't exist at the module level.
+                        if !self.is_closure(*function_id) {
+                            let cfg = self
+                                .control_flow_map
+                                .get(&ControlFlowMapKey::Function(*function_id))
+                                .unwrap();
+                            debug!(?function_id, "compiling called function");
+                            self.codegen_function(*function_id, cfg, false)?;
+                        }
                         // if function.is_builtin {
                         //     let arguments = arguments
                         //         .iter()
@@ -634,42 +1254,361 @@ impl<'a> Codegen<'a> {
 
                 let arguments = arguments
                     .iter()
-                    .map(|argument| self.codegen_expression(argument.value))
-                    .collect::<Result<Vec<_>>>()?
-                    .join(", ");
-                let callee = self.codegen_expression(*callee)?;
-                Ok(format!("{}({})", callee, arguments))
+                    .map(|argument| self.lower_expression(argument.value))
+                    .collect::<Result<Vec<_>>>()?;
+                let callee = self.lower_expression(*callee)?.into();
+                Ok(JsExpression::Call { callee, arguments })
             }
-            _ => Ok(String::from("$value")),
+            Expression::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                let condition = self.lower_expression(*condition)?.into();
+                let else_branch = else_branch.expect("if-expressions always have an else branch");
+                let then_block = self.arena.blocks.get(*then_branch).unwrap();
+                let else_block = self.arena.blocks.get(else_branch).unwrap();
+                // Branches that are just a value can use a ternary, otherwise
+                // each branch is wrapped in an IIFE that returns its value.
+                if then_block.statements.len() == 1 && else_block.statements.len() == 1 {
+                    if let (Some(then_value), Some(else_value)) = (
+                        self.arena.block_value(*then_branch),
+                        self.arena.block_value(else_branch),
+                    ) {
+                        return Ok(JsExpression::Conditional {
+                            condition,
+                            consequent: self.lower_expression(then_value)?.into(),
+                            alternate: self.lower_expression(else_value)?.into(),
+                        });
+                    }
+                }
+                Ok(JsExpression::Conditional {
+                    condition,
+                    consequent: JsExpression::Code(self.codegen_block_value(*then_branch)?).into(),
+                    alternate: JsExpression::Code(self.codegen_block_value(else_branch)?).into(),
+                })
+            }
+            Expression::Variant { variant, arguments } => {
+                let name = self.arena.variants[*variant].name.symbol;
+                let values = arguments
+                    .iter()
+                    .map(|argument| Ok(self.lower_expression(*argument)?.to_string()))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(JsExpression::Code(format!(
+                    "({{ tag: \"{}\", values: [{}] }})",
+                    name,
+                    values.join(", ")
+                )))
+            }
+            Expression::Array(elements) => {
+                let elements = elements
+                    .iter()
+                    .map(|element| Ok(self.lower_expression(*element)?.to_string()))
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(JsExpression::Code(format!("[{}]", elements.join(", "))))
+            }
+            Expression::Match { value, cases } => self.lower_match(*value, cases),
+            Expression::Await(value) => {
+                Ok(JsExpression::Await(self.lower_expression(*value)?.into()))
+            }
+            _ => Ok(JsExpression::Code(String::from("$value"))),
             // Expression::Call { callee, arguments } => todo!(),
-            // Expression::If {
-            //     condition,
-            //     then_branch,
-            //     else_branch,
-            // } => todo!(),
         }
     }
 
+    /// Lower a match to a function that's called with the matched value and
+    /// returns the body of the first case that matches it. Variants are
+    /// objects with a `tag` naming the variant and the `values` it holds.
+    fn lower_match(&self, value: ExpressionId, cases: &[MatchCase]) -> Result<JsExpression> {
+        let matched = || JsExpression::Identifier("$match".to_string());
+        let mut body = String::new();
+        for case in cases {
+            let value = self.lower_expression(case.body)?;
+            match &case.pattern {
+                MatchPattern::Wildcard => body.push_str(&format!("return {}; ", value)),
+                MatchPattern::Expression(pattern) => {
+                    let pattern = self.lower_expression(*pattern)?;
+                    let condition = JsExpression::binary(matched(), BinOp::DoubleEquals, pattern);
+                    body.push_str(&format!("if ({}) {{ return {}; }} ", condition, value));
+                }
+                MatchPattern::Variant { variant, bindings } => {
+                    let name = self.arena.variants[*variant].name.symbol;
+                    let bindings: Vec<String> = bindings
+                        .iter()
+                        .enumerate()
+                        .map(|(index, binding_id)| {
+                            let name = self.binding_name(Binding::Pattern(*binding_id));
+                            format!("const {} = $match.values[{}]; ", name, index)
+                        })
+                        .collect();
+                    body.push_str(&format!(
+                        "if ($match.tag === \"{}\") {{ {}return {}; }} ",
+                        name,
+                        bindings.concat(),
+                        value
+                    ));
+                }
+            }
+        }
+        Ok(JsExpression::Call {
+            callee: JsExpression::Code(format!("(($match) => {{ {}}})", body)).into(),
+            arguments: vec![self.lower_expression(value)?],
+        })
+    }
+
+    /// Whether an expression is a call to `log`.
+    fn is_log_call(&self, expression_id: ExpressionId) -> bool {
+        let expression = self.arena.expressions[expression_id].borrow();
+        let callee = match &*expression {
+            Expression::Call { callee, .. } => *callee,
+            _ => return false,
+        };
+        let callee = self.arena.expressions[callee].borrow();
+        match &*callee {
+            Expression::Reference(Binding::Global(global_id)) => {
+                self.arena.globals[*global_id].builtin == Some(Builtin::Log)
+            }
+            _ => false,
+        }
+    }
+
+    /// Lower a call to `log` to `console.log`, joining a format string with
+    /// the arguments it formats. Stripped logs are left as `undefined`, in
+    /// case their value is used.
+    fn lower_log(&self, arguments: &[Argument]) -> Result<JsExpression> {
+        if self.options.strip_logs {
+            return Ok(JsExpression::Literal("undefined".to_string()));
+        }
+        let mut arguments = arguments.iter().map(|argument| argument.value);
+        let mut lowered = vec![];
+        if let Some(first) = arguments.next() {
+            let parts = match &*self.arena.expressions[first].borrow() {
+                Expression::String(format) => parse_format(&format.to_string()).ok(),
+                _ => None,
+            };
+            match parts {
+                Some(parts) => {
+                    // Formatting starts from a string, so the arguments are
+                    // concatenated instead of added.
+                    let mut message = JsExpression::String(String::new());
+                    for (index, part) in parts.into_iter().enumerate() {
+                        let part = match part {
+                            FormatPart::Text(text) if index == 0 => {
+                                message = JsExpression::String(text);
+                                continue;
+                            }
+                            FormatPart::Text(text) => JsExpression::String(text),
+                            FormatPart::Placeholder => match arguments.next() {
+                                Some(argument) => self.lower_expression(argument)?,
+                                None => break,
+                            },
+                        };
+                        message = JsExpression::binary(message, BinOp::Add, part);
+                    }
+                    lowered.push(message);
+                }
+                None => lowered.push(self.lower_expression(first)?),
+            }
+        }
+        for argument in arguments {
+            lowered.push(self.lower_expression(argument)?);
+        }
+        Ok(JsExpression::Call {
+            callee: JsExpression::Identifier("console.log".to_string()).into(),
+            arguments: lowered,
+        })
+    }
+
+    /// Whether `expression_id` is an if-expression with a branch that returns
+    /// from the function. Branches in an IIFE could only return from the
+    /// IIFE, so these are lowered to an if-statement instead.
+    fn returns_from_branch(&self, expression_id: ExpressionId) -> bool {
+        let expression = self.arena.expressions.get(expression_id).unwrap().borrow();
+        match &*expression {
+            Expression::If {
+                then_branch,
+                else_branch: Some(else_branch),
+                ..
+            } => self.block_returns(*then_branch) || self.block_returns(*else_branch),
+            _ => false,
+        }
+    }
+
+    /// Whether a `return` is reachable anywhere in `block_id`, including in
+    /// the blocks of the `if`s and `while`s nested inside it.
+    fn block_returns(&self, block_id: BlockId) -> bool {
+        let block = self.arena.blocks.get(block_id).unwrap();
+        block
+            .statements
+            .iter()
+            .any(|statement_id| match &self.arena.statements[*statement_id] {
+                Statement::Return(_) => true,
+                Statement::If(if_) => self.if_returns(if_),
+                Statement::While { body, .. } => self.block_returns(*body),
+                Statement::Expression(value)
+                | Statement::Discard(value)
+                | Statement::Let { value, .. }
+                | Statement::Assignment { value, .. } => self.expression_returns(*value),
+                Statement::State(_) | Statement::Provide { .. } => false,
+            })
+    }
+
+    fn if_returns(&self, if_: &If) -> bool {
+        self.block_returns(if_.body)
+            || if_.alternate.as_deref().is_some_and(|else_| match else_ {
+                Else::If(if_) => self.if_returns(if_),
+                Else::Block(block_id) => self.block_returns(*block_id),
+            })
+    }
+
+    fn expression_returns(&self, expression_id: ExpressionId) -> bool {
+        let expression = self.arena.expressions.get(expression_id).unwrap().borrow();
+        match &*expression {
+            Expression::If {
+                then_branch,
+                else_branch,
+                ..
+            } => {
+                self.block_returns(*then_branch)
+                    || else_branch.is_some_and(|else_branch| self.block_returns(else_branch))
+            }
+            _ => false,
+        }
+    }
+
+    /// Lower an if-expression into an if-statement at the end of `block`.
+    /// `finish` makes the instruction that uses the value of each branch
+    /// that has one, like assigning it to the binding it initializes.
+    fn lower_if_statement(
+        &self,
+        block: &mut ir::Block,
+        expression_id: ExpressionId,
+        finish: &dyn Fn(ir::Register) -> Instruction,
+    ) -> Result<()> {
+        let (condition, then_branch, else_branch) =
+            match &*self.arena.expressions.get(expression_id).unwrap().borrow() {
+                Expression::If {
+                    condition,
+                    then_branch,
+                    else_branch: Some(else_branch),
+                } => (*condition, *then_branch, *else_branch),
+                _ => unreachable!("only if-expressions are lowered to if-statements"),
+            };
+        let condition = self.codegen_expression(condition)?;
+        let then_branch = self.lower_branch(then_branch, finish)?;
+        let else_branch = self.lower_branch(else_branch, finish)?;
+        block.push(Instruction::Code(format!(
+            "if ({}) {{ {} }} else {{ {} }}",
+            condition, then_branch, else_branch
+        )));
+        Ok(())
+    }
+
+    /// Emit a branch of an if-expression as statements, finishing its value
+    /// with `finish`.
+    fn lower_branch(
+        &self,
+        block_id: BlockId,
+        finish: &dyn Fn(ir::Register) -> Instruction,
+    ) -> Result<String> {
+        let block = self.arena.blocks.get(block_id).unwrap();
+        let value = self.arena.block_value(block_id);
+        let body = match value {
+            Some(_) => &block.statements[..block.statements.len() - 1],
+            None => &block.statements[..],
+        };
+        let mut ir_block = self.ir_block();
+        for statement_id in body {
+            self.lower_statement(&mut ir_block, *statement_id)?;
+        }
+        match value {
+            Some(value) if self.returns_from_branch(value) => {
+                self.lower_if_statement(&mut ir_block, value, finish)?
+            }
+            Some(value) => {
+                let value = ir_block.lower(self.lower_expression(value)?);
+                ir_block.push(finish(value));
+            }
+            None => {}
+        }
+        Ok(self.finish_ir_block(ir_block).join(" "))
+    }
+
+    /// Emit an if-statement, with any else-ifs and else chained onto it.
+    fn lower_if(&self, if_: &If) -> Result<String> {
+        let condition = self.codegen_expression(if_.condition)?;
+        let body = self.lower_block(if_.body)?;
+        let alternate = match if_.alternate.as_deref() {
+            Some(Else::If(else_if)) => format!(" else {}", self.lower_if(else_if)?),
+            Some(Else::Block(block_id)) => format!(" else {{ {} }}", self.lower_block(*block_id)?),
+            None => String::new(),
+        };
+        Ok(format!("if ({}) {{ {} }}{}", condition, body, alternate))
+    }
+
+    /// Emit the statements in a block.
+    fn lower_block(&self, block_id: BlockId) -> Result<String> {
+        let block = self.arena.blocks.get(block_id).unwrap();
+        let mut ir_block = self.ir_block();
+        for statement_id in &block.statements {
+            self.lower_statement(&mut ir_block, *statement_id)?;
+        }
+        Ok(self.finish_ir_block(ir_block).join(" "))
+    }
+
+    /// Generate an IIFE for a block that evaluates to its trailing expression.
+    fn codegen_block_value(&self, block_id: BlockId) -> Result<String> {
+        let block = self.arena.blocks.get(block_id).unwrap();
+        let value = self.arena.block_value(block_id);
+        // The trailing expression statement becomes the return value
+        let body = match value {
+            Some(_) => &block.statements[..block.statements.len() - 1],
+            None => &block.statements[..],
+        };
+        let mut ir_block = self.ir_block();
+        for statement_id in body {
+            self.lower_statement(&mut ir_block, *statement_id)?;
+        }
+        if let Some(value) = value {
+            let value = ir_block.lower(self.lower_expression(value)?);
+            ir_block.push(Instruction::Return(value));
+        }
+        let statements = self.finish_ir_block(ir_block);
+        Ok(format!("(() => {{ {} }})()", statements.join(" ")))
+    }
+
     fn codegen_template(&self, template_id: TemplateId) -> Result<String> {
-        let template = self.arena.templates.get(template_id).unwrap().borrow();
-        let instruction_set = generate_template_instructions(&template, self.arena);
-
-        let template_gen_function_name = format!(
-            "{}${}$create_fragment_{}",
-            self.module_name,
-            self.current_scope_name(),
-            template_id.index()
+        let instruction_set = generate_template_instructions(
+            template_id,
+            self.arena,
+            self.options.preserve_whitespace,
         );
 
-        let mut fragment_variable_declarations = String::new();
-        let mut fragment_create_statements = String::new();
-        let mut fragment_mount_statements = vec![];
-        let mut fragment_subscription_statements = HashMap::new();
+        let template_gen_function_name = self.template_function_name("create_fragment");
 
         self.template_function_map
             .borrow_mut()
             .insert(template_id, template_gen_function_name.clone());
 
+        self.codegen_fragment(template_gen_function_name, instruction_set)
+    }
+
+    /// Define a function that creates and mounts the nodes for a set of
+    /// template instructions, returning the code that calls it.
+    fn codegen_fragment(
+        &self,
+        template_gen_function_name: String,
+        instruction_set: TemplateInstructionSet,
+    ) -> Result<String> {
+        let mut fragment_variable_declarations = String::new();
+        let mut fragment_create_statements = String::new();
+        let mut fragment_mount_statements = vec![];
+        let mut fragment_subscription_statements = HashMap::new();
+        // Nodes that are fragments themselves, like mounted components, which
+        // are mounted through their own `mount` method. Slots might not have
+        // any children to render, so they're accessed optionally.
+        let mut fragment_nodes: HashMap<i32, &str> = HashMap::new();
+
         // The monotonically increasing index of the current element.
         let mut node_offset = 0;
         // The current depth of the tree.
@@ -685,7 +1624,7 @@ impl<'a> Codegen<'a> {
         let template_graph_root = template_graph.add_node(node_offset);
         let mut current_node = template_graph_root;
 
-        debug!("instruction_set: {:#?}", instruction_set);
+        trace!(?instruction_set, "generated template instructions");
         for embedded_expression in instruction_set.embedded_expressions {
             let expression = self
                 .arena
@@ -701,16 +1640,22 @@ impl<'a> Codegen<'a> {
                     //     .borrow_mut()
                     //     .get_minified_binding(&binding)
                     //     .to_string();
-                    let parameter_name = binding.to_string(&self.arena);
+                    let parameter_name = self.binding_name(binding);
                     template_gen_function_parameters.push(parameter_name);
                 }
             }
         }
+        if instruction_set.renders_slot {
+            template_gen_function_parameters.push(String::from("$children"));
+        }
+        if instruction_set.uses_context {
+            template_gen_function_parameters.push(String::from("$context"));
+        }
 
         for instruction in instruction_set.instructions {
             use std::fmt::Write;
             match instruction {
-                TemplateInstruction::CreateElement(element_name) => {
+                TemplateInstruction::CreateElement(element_name, namespace) => {
                     node_offset += 1;
                     let template_graph_node_index = template_graph.add_node(node_offset);
                     template_graph.add_edge(current_node, template_graph_node_index, -node_offset);
@@ -721,11 +1666,20 @@ impl<'a> Codegen<'a> {
                     writeln!(fragment_variable_declarations, "let ${};", node_offset)?;
 
                     // Create the element
-                    writeln!(
-                        fragment_create_statements,
-                        "${} = document.createElement(\"{}\");",
-                        node_offset, element_name
-                    )?;
+                    match namespace {
+                        Some(namespace) => writeln!(
+                            fragment_create_statements,
+                            "${} = document.createElementNS(\"{}\", \"{}\");",
+                            node_offset,
+                            namespace.uri(),
+                            element_name
+                        )?,
+                        None => writeln!(
+                            fragment_create_statements,
+                            "${} = document.createElement(\"{}\");",
+                            node_offset, element_name
+                        )?,
+                    }
 
                     // Add the element to the parent
                     parent_child_node_map
@@ -733,15 +1687,46 @@ impl<'a> Codegen<'a> {
                         .or_insert(vec![])
                         .push(node_offset);
                 }
-                TemplateInstruction::SetAttribute(name, value) => {
-                    let value = self.codegen_expression(value)?;
+                TemplateInstruction::SetAttribute(name, value_id, namespace) => {
+                    let value = self.codegen_expression(value_id)?;
                     let name = name.to_string();
-                    if name.starts_with("on") {
+                    let event = handled_event(&name);
+                    // Boolean attributes are set by being there at all, no
+                    // matter their value
+                    let is_boolean = event.is_none()
+                        && namespace.is_none()
+                        && self.arena.expression_type(value_id) == Some(Type::Boolean);
+                    let constant = match *self.arena.expressions[value_id].borrow() {
+                        Expression::Boolean(constant) => Some(constant),
+                        _ => None,
+                    };
+                    if is_boolean {
+                        match constant {
+                            Some(true) => writeln!(
+                                fragment_create_statements,
+                                "${}.setAttribute(\"{}\", \"\");",
+                                node_offset, name
+                            )?,
+                            Some(false) => {}
+                            None => writeln!(
+                                fragment_create_statements,
+                                "${}.toggleAttribute(\"{}\", {});",
+                                node_offset, name, value
+                            )?,
+                        }
+                    } else if let Some(event) = event {
                         writeln!(
                             fragment_create_statements,
                             "${}.addEventListener(\"{}\", {});",
+                            node_offset, event, value
+                        )?;
+                    } else if let Some(namespace) = namespace {
+                        writeln!(
+                            fragment_create_statements,
+                            "${}.setAttributeNS(\"{}\", \"{}\", {});",
                             node_offset,
-                            &name[2..].to_lowercase(),
+                            namespace.uri(),
+                            name,
                             value
                         )?;
                     } else {
@@ -806,13 +1791,16 @@ impl<'a> Codegen<'a> {
                         node_offset, expression_value
                     )?;
 
-                    if let Expression::Reference(binding) = *expression {
-                        if let Binding::State(_statement_id) = binding {
-                            fragment_subscription_statements
-                                .entry(binding)
-                                .or_insert(vec![])
-                                .push(format!("${}.textContent = v;", node_offset).to_string());
-                        }
+                    let subscription = match *expression {
+                        Expression::Reference(binding @ Binding::State(_)) => Some(binding),
+                        Expression::UseContext(context_id) => Some(Binding::Context(context_id)),
+                        _ => None,
+                    };
+                    if let Some(binding) = subscription {
+                        fragment_subscription_statements
+                            .entry(binding)
+                            .or_insert(vec![])
+                            .push(format!("${}.textContent = v;", node_offset).to_string());
                     }
 
                     // writeln!(
@@ -825,11 +1813,13 @@ impl<'a> Codegen<'a> {
                     node_offset += 1;
                     let template_graph_node_index = template_graph.add_node(node_offset);
                     template_graph.add_edge(current_node, template_graph_node_index, -node_offset);
+                    writeln!(fragment_variable_declarations, "let ${};", node_offset)?;
 
                     // Create the text element
+                    let text = JsExpression::String(decode_html_entities(&text.to_string()));
                     writeln!(
                         fragment_create_statements,
-                        "${} = document.createTextNode(\"{}\");",
+                        "${} = document.createTextNode({});",
                         node_offset, text
                     )?;
 
@@ -839,19 +1829,77 @@ impl<'a> Codegen<'a> {
                         .push(node_offset);
                     // ...
                 }
-                TemplateInstruction::MountComponent(_component_id) => {
-                    // let component = self.arena.components.get(component_id).unwrap().borrow();
-                    // let component_name = component.name.symbol.to_string();
-                    // current_element_offset += 1;
-                    // element_offset_stack.push(current_element_offset);
-                    // writeln!(
-                    //     fragment_create_statements,
-                    //     "${} = new {}({});",
-                    //     current_element_offset,
-                    //     component_name,
-                    //     template_gen_function_parameters.join(", ")
-                    // )?;
-                    // ...
+                TemplateInstruction::MountComponent {
+                    component_id,
+                    arguments,
+                    children,
+                } => {
+                    node_offset += 1;
+                    let template_graph_node_index = template_graph.add_node(node_offset);
+                    template_graph.add_edge(current_node, template_graph_node_index, -node_offset);
+                    writeln!(fragment_variable_declarations, "let ${};", node_offset)?;
+
+                    let mut component_arguments = arguments
+                        .into_iter()
+                        .map(|argument| match argument {
+                            Some(expression_id) => self.codegen_expression(expression_id),
+                            None => Ok(String::from("undefined")),
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    let children = match children {
+                        Some(fragment) => self.codegen_children_fragment(fragment)?,
+                        None => String::from("null"),
+                    };
+                    component_arguments.push(children);
+                    component_arguments.push(String::from("$context"));
+
+                    // Make sure the component gets compiled, without losing
+                    // track of the scope we're generating this template in.
+                    let scope = self.scope.get_scope();
+                    let cfg = self
+                        .control_flow_map
+                        .get(&ControlFlowMapKey::Component(component_id))
+                        .unwrap();
+                    self.codegen_component(component_id, cfg, false)?;
+                    self.scope.set_scope(scope);
+
+                    writeln!(
+                        fragment_create_statements,
+                        "${} = new {}({});",
+                        node_offset,
+                        self.binding_name(Binding::Component(component_id)),
+                        component_arguments.join(", ")
+                    )?;
+                    writeln!(fragment_create_statements, "${}.create();", node_offset)?;
+                    fragment_nodes.insert(node_offset, ".");
+                }
+                TemplateInstruction::RenderSlot(fallback) => {
+                    node_offset += 1;
+                    let template_graph_node_index = template_graph.add_node(node_offset);
+                    template_graph.add_edge(current_node, template_graph_node_index, -node_offset);
+                    writeln!(fragment_variable_declarations, "let ${};", node_offset)?;
+
+                    let accessor = match fallback {
+                        Some(fragment) => {
+                            let fallback = self.codegen_children_fragment(fragment)?;
+                            writeln!(
+                                fragment_create_statements,
+                                "${} = $children ?? {};",
+                                node_offset, fallback
+                            )?;
+                            "."
+                        }
+                        None => {
+                            writeln!(fragment_create_statements, "${} = $children;", node_offset)?;
+                            "?."
+                        }
+                    };
+                    writeln!(
+                        fragment_create_statements,
+                        "${}{}create();",
+                        node_offset, accessor
+                    )?;
+                    fragment_nodes.insert(node_offset, accessor);
                 }
                 TemplateInstruction::StartChildren => {
                     node_offset_to_depth_map.insert(node_depth, node_offset);
@@ -863,10 +1911,26 @@ impl<'a> Codegen<'a> {
             }
         }
 
+        // The first element mounted to the target, which is removed along
+        // with the rest of the template
+        let mut root_element = None;
         for edge in template_graph.raw_edges() {
             let source = edge.source();
             let target = edge.target();
-            if source == template_graph_root {
+            if let Some(accessor) = fragment_nodes.get(&(target.index() as i32)) {
+                let parent = if source == template_graph_root {
+                    String::from("target")
+                } else {
+                    format!("${}", source.index())
+                };
+                fragment_mount_statements.push(format!(
+                    "${}{}mount({});",
+                    target.index(),
+                    accessor,
+                    parent
+                ));
+            } else if source == template_graph_root {
+                root_element.get_or_insert(target.index());
                 fragment_mount_statements
                     .push(format!("target.appendChild(${});", target.index()).to_string());
             } else {
@@ -879,15 +1943,35 @@ impl<'a> Codegen<'a> {
         let fragment_subscription_statements = fragment_subscription_statements
             .into_iter()
             .map(|(binding, statements)| {
-                let binding = binding.to_string(&self.arena);
+                let name = binding.to_string(self.arena);
+                let binding = match binding {
+                    Binding::Context(context_id) => self.context_signal(context_id),
+                    binding => self.binding_name(binding),
+                };
+                let check = match root_element {
+                    Some(root) if self.options.dev => format!(
+                        "if ($mounted && !${}.isConnected) console.warn(\"[dev] '{}' was updated after its template was removed from the document\");\n",
+                        root, name
+                    ),
+                    _ => String::new(),
+                };
                 format!(
-                    "{}.subscribe((v) => {{ {} }});",
+                    "{}.subscribe((v) => {{ {}{} }});",
                     binding,
+                    check,
                     statements.join("\n")
                 )
             })
             .collect::<Vec<_>>()
             .join("\n");
+        if self.options.dev {
+            fragment_variable_declarations.push_str("let $mounted = false;\n");
+            fragment_mount_statements.insert(
+                0,
+                "if (!(target instanceof Node)) throw new Error(\"[dev] Templates can only be mounted to a DOM node, not \" + target);".to_string(),
+            );
+            fragment_mount_statements.push("$mounted = true;".to_string());
+        }
 
         let template_gen_function_body = format!(
             r"
@@ -912,6 +1996,7 @@ impl<'a> Codegen<'a> {
         self.define_function(
             template_gen_function_name.clone(),
             false,
+            false,
             template_gen_function_parameters.clone(),
             template_gen_function_body,
         );
@@ -922,4 +2007,174 @@ impl<'a> Codegen<'a> {
             template_gen_function_parameters.join(", ")
         ))
     }
+
+    /// The signal for the closest provided value of a context, falling back
+    /// to the context itself, which holds its default value.
+    fn context_signal(&self, context_id: ContextId) -> String {
+        let name = self.binding_name(Binding::Context(context_id));
+        format!("($context.get({}) ?? {})", name, name)
+    }
+
+    /// Generate the fragment for the children of a component or the fallback
+    /// content of a slot, named after the template they're written in.
+    fn codegen_children_fragment(&self, fragment: Fragment) -> Result<String> {
+        let function_name = self.template_function_name("create_children");
+        self.codegen_fragment(function_name, fragment.instruction_set)
+    }
+
+    /// Name a function generated for a template in the current scope. These
+    /// are numbered in the order they're generated, rather than by template id,
+    /// so a component's output doesn't depend on what else is in the module.
+    /// Identifiers can't contain `$`, so these can't clash with a name from
+    /// the source, and the scope's name keeps them apart from other scopes.
+    fn template_function_name(&self, kind: &str) -> String {
+        let scope_name = self.current_scope_name();
+        let mut template_counts = self.template_counts.borrow_mut();
+        let count = template_counts.entry(scope_name.clone()).or_insert(0);
+        let name = format!("{}${}${}_{}", self.module_name, scope_name, kind, count);
+        *count += 1;
+        name
+    }
+}
+
+/// A number literal as it was written, so it keeps its precision and digits
+/// like `0.10`. JavaScript only allows `_` between two digits, so literals
+/// that use it anywhere else have their separators left out.
+fn number_literal(literal: &str) -> String {
+    let bytes = literal.as_bytes();
+    let separators_allowed = bytes.iter().enumerate().all(|(index, byte)| {
+        *byte != b'_'
+            || (index > 0
+                && bytes[index - 1].is_ascii_digit()
+                && bytes.get(index + 1).is_some_and(u8::is_ascii_digit))
+    });
+    if separators_allowed {
+        literal.to_string()
+    } else {
+        literal.replace('_', "")
+    }
+}
+
+/// Write a module's rendered definitions to `path`, deduplicating any that
+/// were generated more than once. The file is left alone if it already has
+/// them.
+pub fn write_module(
+    path: std::path::PathBuf,
+    module_name: &str,
+    options: &CodegenOptions,
+    definitions: &[String],
+) -> Result<()> {
+    let output = render_module(module_name, options, definitions)?;
+    write_output(&path, &output)?;
+    Ok(())
+}
+
+/// The JavaScript `write_module` writes for a module's rendered definitions.
+pub fn render_module(
+    module_name: &str,
+    options: &CodegenOptions,
+    definitions: &[String],
+) -> Result<String> {
+    use std::fmt::Write;
+    let mut output = String::new();
+    write_header(
+        &format!("Compiled from module: {}.ws", module_name),
+        &mut output,
+    )?;
+
+    writeln!(
+        output,
+        "import {{signal, computed}} from '@preact/signals-core';"
+    )?;
+
+    let definitions: IndexSet<&String> = definitions.iter().collect();
+    for definition in definitions {
+        output.push_str(definition);
+    }
+
+    if options.debug_comments {
+        writeln!(output, "//# sourceURL={}.ws", module_name)?;
+    }
+
+    Ok(output)
+}
+
+/// Write the index for a library, which re-exports the modules in it.
+/// `modules` pairs the path of each module, relative to the index, with the
+/// names it exports.
+pub fn write_index(path: std::path::PathBuf, modules: &[(String, Vec<String>)]) -> Result<()> {
+    let output = render_index(modules)?;
+    write_output(&path, &output)?;
+    Ok(())
+}
+
+/// The JavaScript `write_index` writes for a library's modules.
+pub fn render_index(modules: &[(String, Vec<String>)]) -> Result<String> {
+    use std::fmt::Write;
+    let mut output = String::new();
+    write_header("Re-exports the modules in this library", &mut output)?;
+    for (path, exports) in modules {
+        if exports.is_empty() {
+            continue;
+        }
+        writeln!(
+            output,
+            "export {{{}}} from './{}';",
+            exports.join(", "),
+            path
+        )?;
+    }
+    Ok(output)
+}
+
+/// The comment at the top of every generated file. `source` says where it
+/// was generated from.
+fn write_header(source: &str, output: &mut String) -> Result<()> {
+    let header = format! {r"
+          /**
+           * GENERATED FILE - DO NOT EDIT
+           * {}
+           * Generated at {}
+           */
+        ",
+        source,
+        chrono::Utc::now().to_rfc3339()
+    };
+    output.push_str(&header);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::render_index;
+
+    #[test]
+    fn render_library_index() {
+        let modules = vec![
+            (
+                "math.js".to_string(),
+                vec!["double".to_string(), "half".to_string()],
+            ),
+            (
+                "ui/button.a1b2c3d4.js".to_string(),
+                vec!["Button".to_string()],
+            ),
+            ("internal.js".to_string(), vec![]),
+        ];
+        let index = render_index(&modules).unwrap();
+        assert!(index.contains("Re-exports the modules in this library"));
+        let exports: Vec<&str> = index
+            .lines()
+            .map(str::trim)
+            .filter(|line| line.starts_with("export"))
+            .collect();
+        // Modules without exports aren't in the index
+        assert_eq!(
+            exports,
+            vec![
+                "export {double, half} from './math.js';",
+                "export {Button} from './ui/button.a1b2c3d4.js';",
+            ]
+        );
+    }
 }
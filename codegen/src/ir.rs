@@ -0,0 +1,679 @@
+//! A small instruction-based IR for the statements in a basic block. Every
+//! value an expression computes goes in its own virtual register, and state
+//! signals are read with explicit loads, so optimizations can work on a flat
+//! list of instructions instead of nested expressions.
+//!
+//! Registers that are used once are folded back into the expression that
+//! uses them when the block is emitted, so the output still reads like the
+//! source. Only registers that optimizations have shared get declared.
+use crate::lower::JsExpression;
+use common::passes::{OptLevel, Pass, PassManager};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+use syntax::ast::BinOp;
+
+/// The passes that optimize a block, in the order they run.
+pub fn passes(level: OptLevel) -> PassManager<Block> {
+    PassManager::new(level)
+        .with_pass(Pass {
+            name: "forward-loads",
+            level: OptLevel::O2,
+            after: &[],
+            run: Block::forward_loads,
+        })
+        .with_pass(Pass {
+            name: "eliminate-common-subexpressions",
+            level: OptLevel::O2,
+            after: &["forward-loads"],
+            run: Block::eliminate_common_subexpressions,
+        })
+        .with_pass(Pass {
+            name: "remove-unused-values",
+            level: OptLevel::O1,
+            after: &["forward-loads"],
+            run: Block::remove_unused_values,
+        })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Register(usize);
+
+impl std::fmt::Display for Register {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "$r{}", self.0)
+    }
+}
+
+/// What a register holds.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    /// A number or boolean
+    Literal(String),
+    String(String),
+    /// A binding that isn't a signal
+    Identifier(String),
+    /// The current value of a signal
+    Load(String),
+    Binary {
+        left: Register,
+        op: BinOp,
+        right: Register,
+    },
+    /// A call, which is pure when the callee is a function that can't change
+    /// anything.
+    Call {
+        callee: Register,
+        arguments: Vec<Register>,
+        pure: bool,
+    },
+    /// An expression that only evaluates some of its operands, like `a && b`,
+    /// or code that was generated elsewhere, like a template. These are
+    /// kept whole, and are assumed to have side effects.
+    Opaque(JsExpression),
+}
+
+impl Value {
+    /// Whether computing this value can change anything, like a signal.
+    fn has_effects(&self) -> bool {
+        matches!(self, Value::Call { pure: false, .. } | Value::Opaque(_))
+    }
+
+    fn registers_mut(&mut self) -> Vec<&mut Register> {
+        match self {
+            Value::Binary { left, right, .. } => vec![left, right],
+            Value::Call {
+                callee, arguments, ..
+            } => std::iter::once(callee)
+                .chain(arguments.iter_mut())
+                .collect(),
+            _ => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+    Define {
+        target: Register,
+        value: Value,
+    },
+    /// `let name = value`
+    Let {
+        name: String,
+        value: Register,
+    },
+    /// `let name = signal(value)`
+    Signal {
+        name: String,
+        value: Register,
+    },
+    /// Assign to a binding that isn't a signal
+    Assign {
+        name: String,
+        value: Register,
+    },
+    /// Write the value of a signal
+    Store {
+        signal: String,
+        value: Register,
+    },
+    /// Evaluate a value for its side effects
+    Evaluate(Register),
+    Return(Register),
+    /// A statement that's emitted as is
+    Code(String),
+    /// A comment for the statement after it
+    Comment(String),
+}
+
+impl Instruction {
+    fn registers_mut(&mut self) -> Vec<&mut Register> {
+        match self {
+            Instruction::Define { value, .. } => value.registers_mut(),
+            Instruction::Let { value, .. }
+            | Instruction::Signal { value, .. }
+            | Instruction::Assign { value, .. }
+            | Instruction::Store { value, .. }
+            | Instruction::Evaluate(value)
+            | Instruction::Return(value) => vec![value],
+            Instruction::Code(_) | Instruction::Comment(_) => vec![],
+        }
+    }
+}
+
+/// The instructions for a basic block, built up one statement at a time.
+pub struct Block {
+    instructions: Vec<Instruction>,
+    next_register: usize,
+    /// The functions that are pure, by name.
+    pure_functions: Rc<HashSet<String>>,
+}
+
+impl Block {
+    /// Start a block whose registers are numbered from `first_register`, so
+    /// they don't clash with those of other blocks in the same function.
+    pub fn new(first_register: usize) -> Self {
+        Block {
+            instructions: vec![],
+            next_register: first_register,
+            pure_functions: Default::default(),
+        }
+    }
+
+    pub fn with_pure_functions(self, pure_functions: Rc<HashSet<String>>) -> Self {
+        Self {
+            pure_functions,
+            ..self
+        }
+    }
+
+    pub fn next_register(&self) -> usize {
+        self.next_register
+    }
+
+    pub fn push(&mut self, instruction: Instruction) {
+        self.instructions.push(instruction);
+    }
+
+    pub fn define(&mut self, value: Value) -> Register {
+        let target = Register(self.next_register);
+        self.next_register += 1;
+        self.push(Instruction::Define { target, value });
+        target
+    }
+
+    /// Lower an expression into instructions, returning the register that
+    /// holds its value. Operands are defined in the order they're evaluated.
+    pub fn lower(&mut self, expression: JsExpression) -> Register {
+        let value = match expression {
+            JsExpression::Literal(literal) => Value::Literal(literal),
+            JsExpression::String(string) => Value::String(string),
+            JsExpression::Identifier(name) => Value::Identifier(name),
+            JsExpression::SignalRead(signal) => Value::Load(signal),
+            JsExpression::Binary { left, op, right } => {
+                let left = self.lower(*left);
+                let right = self.lower(*right);
+                Value::Binary { left, op, right }
+            }
+            JsExpression::Call { callee, arguments } => {
+                let pure = match &*callee {
+                    JsExpression::Identifier(name) => self.pure_functions.contains(name),
+                    _ => false,
+                };
+                let callee = self.lower(*callee);
+                let arguments = arguments
+                    .into_iter()
+                    .map(|argument| self.lower(argument))
+                    .collect();
+                Value::Call {
+                    callee,
+                    arguments,
+                    pure,
+                }
+            }
+            // Other code can run while waiting, so awaiting is opaque too
+            expression @ JsExpression::Code(_)
+            | expression @ JsExpression::Logical { .. }
+            | expression @ JsExpression::Conditional { .. }
+            | expression @ JsExpression::Await(_)
+            | expression @ JsExpression::Memoized { .. } => Value::Opaque(expression),
+        };
+        self.define(value)
+    }
+
+    /// Reuse the value of a signal that's already been loaded or stored,
+    /// rather than loading it again, as long as nothing in between could
+    /// have changed it.
+    fn forward_loads(&mut self) {
+        let mut known: HashMap<String, Register> = HashMap::new();
+        let mut replaced: HashMap<Register, Register> = HashMap::new();
+        let mut instructions = Vec::with_capacity(self.instructions.len());
+        for mut instruction in self.instructions.drain(..) {
+            for register in instruction.registers_mut() {
+                if let Some(replacement) = replaced.get(register) {
+                    *register = *replacement;
+                }
+            }
+            match &instruction {
+                Instruction::Define {
+                    target,
+                    value: Value::Load(signal),
+                } => match known.get(signal) {
+                    Some(register) => {
+                        replaced.insert(*target, *register);
+                        continue;
+                    }
+                    None => {
+                        known.insert(signal.clone(), *target);
+                    }
+                },
+                Instruction::Define { value, .. } if value.has_effects() => known.clear(),
+                Instruction::Store { signal, value } => {
+                    known.clear();
+                    known.insert(signal.clone(), *value);
+                }
+                Instruction::Code(_) => known.clear(),
+                _ => {}
+            }
+            instructions.push(instruction);
+        }
+        self.instructions = instructions;
+    }
+
+    /// Compute each pure value once, sharing its register with the places
+    /// that compute it again. Values are compared by what they compute, so
+    /// reading a binding only matches reads since the last thing that could
+    /// have changed it.
+    fn eliminate_common_subexpressions(&mut self) {
+        // What each register computes, written out with operands replaced
+        // by what they compute
+        let mut keys: HashMap<Register, String> = HashMap::new();
+        let mut known: HashMap<String, Register> = HashMap::new();
+        let mut replaced: HashMap<Register, Register> = HashMap::new();
+        // Bumped whenever a binding might change, so reads before it don't
+        // match reads after it
+        let mut generation = 0;
+        let mut instructions = Vec::with_capacity(self.instructions.len());
+        for mut instruction in self.instructions.drain(..) {
+            for register in instruction.registers_mut() {
+                if let Some(replacement) = replaced.get(register) {
+                    *register = *replacement;
+                }
+            }
+            let key = |register: &Register| match keys.get(register) {
+                Some(key) => key.clone(),
+                None => register.to_string(),
+            };
+            match &instruction {
+                Instruction::Define { target, value } => {
+                    let computed = match value {
+                        Value::Literal(literal) => Some(literal.clone()),
+                        Value::String(string) => Some(format!("{:?}", string)),
+                        Value::Identifier(name) => Some(format!("{}@{}", name, generation)),
+                        Value::Binary { left, op, right } if *op != BinOp::Equals => Some(format!(
+                            "({} {} {})",
+                            key(left),
+                            op.to_js().unwrap_or("?"),
+                            key(right)
+                        )),
+                        Value::Call {
+                            callee,
+                            arguments,
+                            pure: true,
+                        } => {
+                            let arguments: Vec<String> = arguments.iter().map(key).collect();
+                            Some(format!(
+                                "{}({})@{}",
+                                key(callee),
+                                arguments.join(", "),
+                                generation
+                            ))
+                        }
+                        _ => None,
+                    };
+                    if value.has_effects() {
+                        generation += 1;
+                    }
+                    if let Some(computed) = computed {
+                        // Only share values that are worth a temporary,
+                        // references and literals are as short as one.
+                        let shareable = matches!(value, Value::Binary { .. } | Value::Call { .. });
+                        match known.get(&computed) {
+                            Some(register) if shareable => {
+                                replaced.insert(*target, *register);
+                                continue;
+                            }
+                            _ => {
+                                known.insert(computed.clone(), *target);
+                                keys.insert(*target, computed);
+                            }
+                        }
+                    }
+                }
+                // A `let` only declares a new binding, which can't have been
+                // read already.
+                Instruction::Assign { .. } | Instruction::Store { .. } | Instruction::Code(_) => {
+                    generation += 1
+                }
+                _ => {}
+            }
+            instructions.push(instruction);
+        }
+        self.instructions = instructions;
+    }
+
+    /// Remove values that nothing uses and that can be computed without
+    /// side effects.
+    fn remove_unused_values(&mut self) {
+        loop {
+            let uses = self.uses();
+            let before = self.instructions.len();
+            self.instructions.retain(|instruction| match instruction {
+                Instruction::Define { target, value } => {
+                    value.has_effects() || uses.contains_key(target)
+                }
+                _ => true,
+            });
+            if self.instructions.len() == before {
+                break;
+            }
+        }
+    }
+
+    fn uses(&mut self) -> HashMap<Register, usize> {
+        let mut uses = HashMap::new();
+        for instruction in &mut self.instructions {
+            for register in instruction.registers_mut() {
+                *uses.entry(*register).or_insert(0) += 1;
+            }
+        }
+        uses
+    }
+
+    /// The registers to declare, instead of folding them into the one
+    /// instruction that uses them. These are the registers that are used
+    /// more than once, along with any values with side effects that would
+    /// otherwise be emitted after one of those, out of order.
+    fn declared(&mut self) -> HashSet<Register> {
+        let uses = self.uses();
+        let mut declared = HashSet::new();
+        // Values with side effects that are waiting to be folded into the
+        // instruction that uses them
+        let mut pending: Vec<Register> = vec![];
+        for instruction in &mut self.instructions {
+            let operands: Vec<Register> = instruction
+                .registers_mut()
+                .into_iter()
+                .map(|r| *r)
+                .collect();
+            let carries_effects = pending.iter().any(|r| operands.contains(r));
+            pending.retain(|r| !operands.contains(r));
+            if let Instruction::Define { target, value } = instruction {
+                if uses.get(target) != Some(&1) {
+                    declared.insert(*target);
+                    declared.extend(pending.drain(..));
+                } else if carries_effects || value.has_effects() {
+                    pending.push(*target);
+                }
+            }
+        }
+        declared
+    }
+
+    /// Emit the block as JavaScript, one statement per line.
+    pub fn emit(mut self) -> Vec<String> {
+        let uses = self.uses();
+        let declared = self.declared();
+        let mut values: HashMap<Register, Value> = HashMap::new();
+        let mut lines = vec![];
+        let mut comment: Option<String> = None;
+        for instruction in self.instructions {
+            let code = {
+                let expression = |register: &Register| expression(*register, &values, &declared);
+                match &instruction {
+                    Instruction::Define { target, value } => {
+                        values.insert(*target, value.clone());
+                        let value = value_expression(value, &values, &declared);
+                        if !declared.contains(target) {
+                            // Emitted where it's used
+                            continue;
+                        } else if uses.contains_key(target) {
+                            format!("const {} = {};", target, value)
+                        } else {
+                            format!("{};", value)
+                        }
+                    }
+                    Instruction::Let { name, value } => {
+                        format!("let {} = {};", name, expression(value))
+                    }
+                    Instruction::Signal { name, value } => {
+                        format!("let {} = signal({});", name, expression(value))
+                    }
+                    Instruction::Assign { name, value } => {
+                        format!("{} = {};", name, expression(value))
+                    }
+                    Instruction::Store { signal, value } => {
+                        format!("{}.value = {};", signal, expression(value))
+                    }
+                    Instruction::Evaluate(value) => format!("{};", expression(value)),
+                    Instruction::Return(value) => format!("return {};", expression(value)),
+                    Instruction::Code(code) => code.clone(),
+                    Instruction::Comment(text) => {
+                        comment = Some(text.clone());
+                        continue;
+                    }
+                }
+            };
+            match comment.take() {
+                Some(comment) => lines.push(format!("{} {}", comment, code)),
+                None => lines.push(code),
+            }
+        }
+        lines
+    }
+}
+
+/// The instructions one per line, for debugging passes.
+impl fmt::Display for Block {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for instruction in &self.instructions {
+            writeln!(f, "{}", instruction)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Instruction::Define { target, value } => write!(f, "{} = {}", target, value),
+            Instruction::Let { name, value } => write!(f, "let {} = {}", name, value),
+            Instruction::Signal { name, value } => write!(f, "signal {} = {}", name, value),
+            Instruction::Assign { name, value } => write!(f, "{} = {}", name, value),
+            Instruction::Store { signal, value } => write!(f, "store {} {}", signal, value),
+            Instruction::Evaluate(value) => write!(f, "evaluate {}", value),
+            Instruction::Return(value) => write!(f, "return {}", value),
+            Instruction::Code(code) => write!(f, "code {}", code),
+            Instruction::Comment(text) => write!(f, "{}", text),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Literal(literal) => write!(f, "{}", literal),
+            Value::String(string) => write!(f, "{:?}", string),
+            Value::Identifier(name) => write!(f, "{}", name),
+            Value::Load(signal) => write!(f, "load {}", signal),
+            Value::Binary { left, op, right } => {
+                write!(f, "{} {} {}", left, op.to_js().unwrap_or("?"), right)
+            }
+            Value::Call {
+                callee,
+                arguments,
+                pure,
+            } => {
+                let arguments: Vec<String> = arguments.iter().map(Register::to_string).collect();
+                let call = if *pure { "call pure" } else { "call" };
+                write!(f, "{} {}({})", call, callee, arguments.join(", "))
+            }
+            Value::Opaque(expression) => write!(f, "opaque {}", expression),
+        }
+    }
+}
+
+/// The expression for a register: the register itself if it's declared,
+/// or else its value.
+fn expression(
+    register: Register,
+    values: &HashMap<Register, Value>,
+    declared: &HashSet<Register>,
+) -> JsExpression {
+    if declared.contains(&register) {
+        JsExpression::Identifier(register.to_string())
+    } else {
+        value_expression(&values[&register], values, declared)
+    }
+}
+
+fn value_expression(
+    value: &Value,
+    values: &HashMap<Register, Value>,
+    declared: &HashSet<Register>,
+) -> JsExpression {
+    let expression = |register: &Register| expression(*register, values, declared);
+    match value {
+        Value::Literal(literal) => JsExpression::Literal(literal.clone()),
+        Value::String(string) => JsExpression::String(string.clone()),
+        Value::Identifier(name) => JsExpression::Identifier(name.clone()),
+        Value::Load(signal) => JsExpression::SignalRead(signal.clone()),
+        Value::Binary { left, op, right } => JsExpression::Binary {
+            left: expression(left).into(),
+            op: op.clone(),
+            right: expression(right).into(),
+        },
+        Value::Call {
+            callee, arguments, ..
+        } => JsExpression::Call {
+            callee: expression(callee).into(),
+            arguments: arguments.iter().map(expression).collect(),
+        },
+        Value::Opaque(expression) => expression.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn emit(mut block: Block) -> String {
+        passes(OptLevel::O2).run(&mut block);
+        block.emit().join("\n")
+    }
+
+    fn signal(name: &str) -> JsExpression {
+        JsExpression::SignalRead(name.into())
+    }
+
+    #[test]
+    fn fold_registers_into_expressions() {
+        let mut block = Block::new(0);
+        let sum = JsExpression::binary(
+            signal("count"),
+            BinOp::Add,
+            JsExpression::Literal("1".into()),
+        );
+        let value = block.lower(sum);
+        block.push(Instruction::Let {
+            name: "next".into(),
+            value,
+        });
+        assert_eq!(emit(block), "let next = count.value + 1;");
+    }
+
+    #[test]
+    fn forward_loads_until_a_call() {
+        let mut block = Block::new(0);
+        for name in ["a", "b"] {
+            let value = block.lower(signal("count"));
+            block.push(Instruction::Let {
+                name: name.into(),
+                value,
+            });
+        }
+        let callee = JsExpression::Identifier("f".into());
+        let call = block.lower(JsExpression::Call {
+            callee: callee.into(),
+            arguments: vec![],
+        });
+        block.push(Instruction::Evaluate(call));
+        let value = block.lower(signal("count"));
+        block.push(Instruction::Return(value));
+        assert_eq!(
+            emit(block),
+            "const $r0 = count.value;\nlet a = $r0;\nlet b = $r0;\nf();\nreturn count.value;"
+        );
+    }
+
+    #[test]
+    fn forward_stored_values() {
+        let mut block = Block::new(0);
+        let sum = JsExpression::binary(
+            signal("count"),
+            BinOp::Add,
+            JsExpression::Literal("1".into()),
+        );
+        let value = block.lower(sum);
+        block.push(Instruction::Store {
+            signal: "count".into(),
+            value,
+        });
+        let value = block.lower(signal("count"));
+        block.push(Instruction::Return(value));
+        assert_eq!(
+            emit(block),
+            "const $r2 = count.value + 1;\ncount.value = $r2;\nreturn $r2;"
+        );
+    }
+
+    #[test]
+    fn keep_side_effects_in_order() {
+        let mut block = Block::new(0);
+        let callee = JsExpression::Identifier("f".into());
+        let call = JsExpression::Call {
+            callee: callee.into(),
+            arguments: vec![],
+        };
+        let value = block.lower(JsExpression::binary(call, BinOp::Add, signal("count")));
+        block.push(Instruction::Let {
+            name: "a".into(),
+            value,
+        });
+        let value = block.lower(signal("count"));
+        block.push(Instruction::Let {
+            name: "b".into(),
+            value,
+        });
+        assert_eq!(
+            emit(block),
+            "const $r1 = f();\nconst $r2 = count.value;\nlet a = $r1 + $r2;\nlet b = $r2;"
+        );
+    }
+
+    #[test]
+    fn share_pure_expressions() {
+        let pure = ["pure".to_string()].into_iter().collect();
+        let mut block = Block::new(0).with_pure_functions(Rc::new(pure));
+        let identifier = |name: &str| JsExpression::Identifier(name.into());
+        let call = |callee: &str| JsExpression::Call {
+            callee: identifier(callee).into(),
+            arguments: vec![JsExpression::binary(
+                identifier("a"),
+                BinOp::Add,
+                identifier("b"),
+            )],
+        };
+        for (name, callee) in [("x", "pure"), ("y", "pure"), ("z", "impure")] {
+            let value = block.lower(call(callee));
+            block.push(Instruction::Let {
+                name: name.into(),
+                value,
+            });
+        }
+        // Assigning `a` means `a + b` has to be computed again
+        let value = block.lower(JsExpression::Literal("1".into()));
+        block.push(Instruction::Assign {
+            name: "a".into(),
+            value,
+        });
+        let value = block.lower(call("pure"));
+        block.push(Instruction::Return(value));
+        assert_eq!(
+            emit(block),
+            "const $r3 = a + b;\nconst $r4 = pure($r3);\nlet x = $r4;\nlet y = $r4;\n\
+             let z = impure($r3);\na = 1;\nreturn pure(a + b);"
+        );
+    }
+}
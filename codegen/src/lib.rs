@@ -1,5 +1,24 @@
+mod artifacts;
 mod call_graph;
+mod captures;
 mod codegen;
+mod ir;
+mod loops;
+mod lower;
+mod purity;
+mod reactivity;
+mod report;
 mod templates;
 
+pub use artifacts::{
+    clean_artifacts, content_hash, hashed_path, output_hash, render_asset_manifest, short_hash,
+    write_output, Artifact, Cleaned, Manifest, ASSET_MANIFEST_FILE,
+};
 pub use codegen::*;
+pub use reactivity::{
+    format_graph, format_graph_dot, format_graph_json, reactivity_graph, GraphFormat,
+    ReactiveBinding, ReactivityGraph,
+};
+pub use report::{
+    format_report_json, format_report_table, template_report, ComponentReport, StateBindings,
+};
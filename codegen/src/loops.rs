@@ -0,0 +1,313 @@
+use std::collections::HashSet;
+
+use common::control_flow_graph::data_flow::{Access, ReachingDefinitions, VariableAccess};
+use common::control_flow_graph::{ControlFlowEdge, ControlFlowGraph, ControlFlowNode};
+use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
+use petgraph::Direction;
+use syntax::ast::{
+    AstArena, Binding, Expression, ExpressionId, FunctionId, Statement, StatementId,
+};
+
+/// The bindings each statement reads and writes. Calling an impure function
+/// might write any mutable binding a closure captures, since the function
+/// could be that closure.
+pub struct BindingAccess<'a> {
+    arena: &'a AstArena,
+    pure_functions: &'a HashSet<FunctionId>,
+    captured: HashSet<Binding>,
+}
+
+impl<'a> BindingAccess<'a> {
+    pub fn new(
+        arena: &'a AstArena,
+        pure_functions: &'a HashSet<FunctionId>,
+        captured: HashSet<Binding>,
+    ) -> Self {
+        Self {
+            arena,
+            pure_functions,
+            captured,
+        }
+    }
+
+    fn is_pure_call(&self, callee: ExpressionId) -> bool {
+        match &*self.arena.expressions[callee].borrow() {
+            Expression::Reference(Binding::Function(function_id)) => {
+                self.pure_functions.contains(function_id)
+            }
+            _ => false,
+        }
+    }
+
+    fn expression(&self, expression_id: ExpressionId, access: &mut Access<Binding>) {
+        match &*self.arena.expressions[expression_id].borrow() {
+            Expression::Reference(binding) => access.reads.push(*binding),
+            Expression::Binary { left, right, .. } => {
+                self.expression(*left, access);
+                self.expression(*right, access);
+            }
+            Expression::Unary { operand, .. } | Expression::Await(operand) => {
+                self.expression(*operand, access)
+            }
+            Expression::Variant { arguments, .. } | Expression::Array(arguments) => {
+                for argument in arguments {
+                    self.expression(*argument, access);
+                }
+            }
+            Expression::Call { callee, arguments } => {
+                self.expression(*callee, access);
+                for argument in arguments {
+                    self.expression(argument.value, access);
+                }
+                if !self.is_pure_call(*callee) {
+                    access.writes.extend(self.captured.iter().copied());
+                }
+            }
+            // Branches of if-expressions are blocks of their own, so
+            // assume they could read or write anything a call could. The
+            // same goes for the cases of a match.
+            Expression::If {
+                condition: value, ..
+            }
+            | Expression::Match { value, .. } => {
+                self.expression(*value, access);
+                access.writes.extend(self.captured.iter().copied());
+            }
+            Expression::Number { .. }
+            | Expression::Boolean(_)
+            | Expression::String(_)
+            | Expression::Template(_)
+            | Expression::Function(_)
+            | Expression::UseContext(_) => {}
+        }
+    }
+}
+
+impl<'a> VariableAccess<StatementId, ExpressionId> for BindingAccess<'a> {
+    type Variable = Binding;
+
+    fn statement(&self, statement_id: &StatementId) -> Access<Binding> {
+        let mut access = Access {
+            reads: vec![],
+            writes: vec![],
+        };
+        match &self.arena.statements[*statement_id] {
+            Statement::Let { value, .. } => {
+                self.expression(*value, &mut access);
+                access.writes.push(Binding::Let(*statement_id));
+            }
+            Statement::State(state_id) => {
+                self.expression(self.arena.states[*state_id].value, &mut access);
+                access.writes.push(Binding::State(*statement_id));
+            }
+            Statement::Assignment { name, value } => {
+                self.expression(*value, &mut access);
+                access.writes.push(*name);
+            }
+            Statement::Expression(value)
+            | Statement::Discard(value)
+            | Statement::Return(value)
+            | Statement::Provide { value, .. } => self.expression(*value, &mut access),
+            // These are split into separate nodes in the graph
+            Statement::If(_) | Statement::While { .. } => {}
+        }
+        access
+    }
+
+    fn condition(&self, condition: &ExpressionId) -> Access<Binding> {
+        let mut access = Access {
+            reads: vec![],
+            writes: vec![],
+        };
+        self.expression(*condition, &mut access);
+        access
+    }
+}
+
+/// Finds the expressions in a `while` loop that compute the same value every
+/// time around it, so they can be computed once before the loop instead.
+///
+/// An expression is invariant if every binding it reads is only ever
+/// defined outside the loop, going by the definitions that reach it. Only
+/// arithmetic and calls to pure functions are moved, since those are the
+/// only expressions that can't do anything besides produce a value.
+pub struct LoopInvariants<'a, V> {
+    access: &'a BindingAccess<'a>,
+    cfg: &'a ControlFlowGraph<StatementId, ExpressionId, V>,
+    condition: NodeIndex,
+    // The nodes in the loop body, not including the condition
+    body: HashSet<NodeIndex>,
+    body_statements: HashSet<StatementId>,
+}
+
+impl<'a, V> LoopInvariants<'a, V> {
+    pub fn new(
+        access: &'a BindingAccess<'a>,
+        cfg: &'a ControlFlowGraph<StatementId, ExpressionId, V>,
+        condition: NodeIndex,
+    ) -> Self {
+        let mut body = HashSet::new();
+        let mut stack: Vec<NodeIndex> = cfg
+            .graph
+            .edges_directed(condition, Direction::Outgoing)
+            .filter(|edge| matches!(edge.weight(), ControlFlowEdge::ConditionTrue))
+            .map(|edge| edge.target())
+            .collect();
+        while let Some(node) = stack.pop() {
+            if node == condition || !body.insert(node) {
+                continue;
+            }
+            stack.extend(cfg.graph.neighbors_directed(node, Direction::Outgoing));
+        }
+        // The loop can only leave through its condition, or by returning.
+        body.retain(|node| !matches!(cfg.graph[*node], ControlFlowNode::Exit));
+        let body_statements = body
+            .iter()
+            .filter_map(|node| match &cfg.graph[*node] {
+                ControlFlowNode::BasicBlock(block) => Some(block.statements.iter().copied()),
+                _ => None,
+            })
+            .flatten()
+            .collect();
+        Self {
+            access,
+            cfg,
+            condition,
+            body,
+            body_statements,
+        }
+    }
+
+    /// The largest invariant expressions in the loop, with the ones in its
+    /// condition first. `skip` are expressions that have already been moved.
+    pub fn find(&self, skip: impl Fn(ExpressionId) -> bool) -> Vec<ExpressionId> {
+        let reaching_definitions = ReachingDefinitions(self.access);
+        let results = self.cfg.analyze(&reaching_definitions);
+        let mut invariants = vec![];
+        let mut nodes: Vec<NodeIndex> = self.body.iter().copied().collect();
+        nodes.sort();
+        nodes.insert(0, self.condition);
+        for node in nodes {
+            let mut reaching = results.before(node).clone();
+            match &self.cfg.graph[node] {
+                ControlFlowNode::BasicBlock(block) => {
+                    for statement_id in &block.statements {
+                        let is_invariant =
+                            |binding: &Binding| self.is_invariant_binding(&reaching, binding);
+                        for expression_id in self.statement_expressions(*statement_id) {
+                            self.collect(expression_id, &is_invariant, &skip, &mut invariants);
+                        }
+                        reaching_definitions.define(&mut reaching, statement_id);
+                    }
+                }
+                ControlFlowNode::BranchCondition(condition)
+                | ControlFlowNode::LoopCondition(condition) => {
+                    let is_invariant =
+                        |binding: &Binding| self.is_invariant_binding(&reaching, binding);
+                    self.collect(*condition, &is_invariant, &skip, &mut invariants);
+                }
+                ControlFlowNode::Entry | ControlFlowNode::Exit => {}
+            }
+        }
+        invariants
+    }
+
+    fn statement_expressions(&self, statement_id: StatementId) -> Vec<ExpressionId> {
+        match &self.access.arena.statements[statement_id] {
+            Statement::Let { value, .. }
+            | Statement::Assignment { value, .. }
+            | Statement::Expression(value)
+            | Statement::Discard(value)
+            | Statement::Return(value) => vec![*value],
+            Statement::State(state_id) => vec![self.access.arena.states[*state_id].value],
+            // Context values are wrapped in a `computed`, so they stay where they are
+            Statement::Provide { .. } | Statement::If(_) | Statement::While { .. } => vec![],
+        }
+    }
+
+    fn is_invariant_binding(
+        &self,
+        reaching: &HashSet<(Binding, StatementId)>,
+        binding: &Binding,
+    ) -> bool {
+        // Bindings declared in the loop are new each time around it
+        if let Binding::Let(statement_id) | Binding::State(statement_id) = binding {
+            if self.body_statements.contains(statement_id) {
+                return false;
+            }
+        }
+        !reaching.iter().any(|(defined, statement_id)| {
+            defined == binding && self.body_statements.contains(statement_id)
+        })
+    }
+
+    /// Collect the largest invariant expressions in `expression_id` that are
+    /// worth computing ahead of time.
+    fn collect(
+        &self,
+        expression_id: ExpressionId,
+        is_invariant_binding: &impl Fn(&Binding) -> bool,
+        skip: &impl Fn(ExpressionId) -> bool,
+        invariants: &mut Vec<ExpressionId>,
+    ) {
+        if skip(expression_id) {
+            return;
+        }
+        let expression = self.access.arena.expressions[expression_id].borrow();
+        let worth_moving = match &*expression {
+            Expression::Binary { .. } | Expression::Unary { .. } => true,
+            Expression::Call { callee, .. } => self.access.is_pure_call(*callee),
+            _ => false,
+        };
+        if worth_moving && self.is_invariant(expression_id, is_invariant_binding) {
+            invariants.push(expression_id);
+            return;
+        }
+        match &*expression {
+            Expression::Binary { left, right, .. } => {
+                self.collect(*left, is_invariant_binding, skip, invariants);
+                self.collect(*right, is_invariant_binding, skip, invariants);
+            }
+            Expression::Unary { operand, .. } => {
+                self.collect(*operand, is_invariant_binding, skip, invariants)
+            }
+            Expression::Call { arguments, .. } => {
+                for argument in arguments {
+                    self.collect(argument.value, is_invariant_binding, skip, invariants);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn is_invariant(
+        &self,
+        expression_id: ExpressionId,
+        is_invariant_binding: &impl Fn(&Binding) -> bool,
+    ) -> bool {
+        match &*self.access.arena.expressions[expression_id].borrow() {
+            Expression::Number { .. } | Expression::Boolean(_) | Expression::String(_) => true,
+            Expression::Reference(binding) => is_invariant_binding(binding),
+            Expression::Binary { left, right, .. } => {
+                self.is_invariant(*left, is_invariant_binding)
+                    && self.is_invariant(*right, is_invariant_binding)
+            }
+            Expression::Unary { operand, .. } => self.is_invariant(*operand, is_invariant_binding),
+            Expression::Call { callee, arguments } => {
+                self.access.is_pure_call(*callee)
+                    && arguments
+                        .iter()
+                        .all(|argument| self.is_invariant(argument.value, is_invariant_binding))
+            }
+            Expression::If { .. }
+            | Expression::Match { .. }
+            | Expression::Await(_)
+            | Expression::Variant { .. }
+            | Expression::Array(_)
+            | Expression::Template(_)
+            | Expression::Function(_)
+            | Expression::UseContext(_) => false,
+        }
+    }
+}
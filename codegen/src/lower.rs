@@ -0,0 +1,429 @@
+use std::fmt::{Display, Formatter, Result};
+use syntax::ast::BinOp;
+
+/// A JavaScript expression, lowered from the AST before it's emitted. This
+/// is where source-level operators are mapped onto their JavaScript
+/// equivalents, so emission doesn't have to reason about semantics.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsExpression {
+    /// Code that has already been generated, like a template
+    Code(String),
+    /// A number or boolean literal
+    Literal(String),
+    /// A string literal, which is escaped when emitted
+    String(String),
+    /// A reference to a binding by name
+    Identifier(String),
+    /// Reading the current value of a signal
+    SignalRead(String),
+    Binary {
+        left: Box<JsExpression>,
+        op: BinOp,
+        right: Box<JsExpression>,
+    },
+    /// `&&` and `||`, which only evaluate their right operand when needed
+    Logical {
+        left: Box<JsExpression>,
+        op: LogicalOp,
+        right: Box<JsExpression>,
+    },
+    Conditional {
+        condition: Box<JsExpression>,
+        consequent: Box<JsExpression>,
+        alternate: Box<JsExpression>,
+    },
+    Call {
+        callee: Box<JsExpression>,
+        arguments: Vec<JsExpression>,
+    },
+    Await(Box<JsExpression>),
+    /// An expression that reads each of `signals` once, before anything
+    /// else, and refers to them as `$name` in `body`
+    Memoized {
+        signals: Vec<String>,
+        body: Box<JsExpression>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogicalOp {
+    And,
+    Or,
+}
+
+impl JsExpression {
+    /// Lower a binary operation, separating out the logical operators since
+    /// they short-circuit. Pipelines become a call, so `a |> f` is `f(a)`.
+    pub fn binary(left: JsExpression, op: BinOp, right: JsExpression) -> Self {
+        if op == BinOp::Pipeline {
+            return JsExpression::Call {
+                callee: right.into(),
+                arguments: vec![left],
+            };
+        }
+        let (left, right) = (Box::new(left), Box::new(right));
+        match op {
+            BinOp::And => JsExpression::Logical {
+                left,
+                op: LogicalOp::And,
+                right,
+            },
+            BinOp::Or => JsExpression::Logical {
+                left,
+                op: LogicalOp::Or,
+                right,
+            },
+            op => JsExpression::Binary { left, op, right },
+        }
+    }
+
+    /// Read the signals that this expression reads more than once a single
+    /// time, so `a or (a and b)` only reads `a.value` once. Expressions with
+    /// calls are left alone, since a call could write to a signal between
+    /// the reads.
+    pub fn memoize_signal_reads(self) -> Self {
+        let mut reads = vec![];
+        if !self.count_signal_reads(&mut reads) {
+            return self;
+        }
+        let signals: Vec<String> = reads
+            .into_iter()
+            .filter(|(_, count)| *count > 1)
+            .map(|(signal, _)| signal)
+            .collect();
+        if signals.is_empty() {
+            return self;
+        }
+        JsExpression::Memoized {
+            body: self.replace_signal_reads(&signals).into(),
+            signals,
+        }
+    }
+
+    /// Count the reads of each signal, in the order they're first read.
+    /// Returns false if the expression has code that could write a signal.
+    fn count_signal_reads(&self, reads: &mut Vec<(String, usize)>) -> bool {
+        let mut read = |signal: &String| match reads.iter_mut().find(|(name, _)| name == signal) {
+            Some((_, count)) => *count += 1,
+            None => reads.push((signal.clone(), 1)),
+        };
+        match self {
+            JsExpression::Literal(_) | JsExpression::String(_) | JsExpression::Identifier(_) => {
+                true
+            }
+            JsExpression::SignalRead(signal) => {
+                read(signal);
+                true
+            }
+            JsExpression::Memoized { signals, body } => {
+                signals.iter().for_each(read);
+                body.count_signal_reads(reads)
+            }
+            JsExpression::Binary { left, right, .. }
+            | JsExpression::Logical { left, right, .. } => {
+                left.count_signal_reads(reads) && right.count_signal_reads(reads)
+            }
+            JsExpression::Conditional {
+                condition,
+                consequent,
+                alternate,
+            } => {
+                condition.count_signal_reads(reads)
+                    && consequent.count_signal_reads(reads)
+                    && alternate.count_signal_reads(reads)
+            }
+            JsExpression::Code(_) | JsExpression::Call { .. } | JsExpression::Await(_) => false,
+        }
+    }
+
+    /// Replace the reads of `signals` with the names they're memoized as.
+    fn replace_signal_reads(self, signals: &[String]) -> Self {
+        let replace = |expression: Box<JsExpression>| -> Box<JsExpression> {
+            expression.replace_signal_reads(signals).into()
+        };
+        match self {
+            JsExpression::SignalRead(signal) if signals.contains(&signal) => {
+                JsExpression::Identifier(format!("${}", signal))
+            }
+            JsExpression::Memoized {
+                signals: inner,
+                body,
+            } => {
+                // The body refers to the signals read here by the same
+                // names, so they can be dropped from the inner expression
+                let inner: Vec<String> = inner
+                    .into_iter()
+                    .filter(|signal| !signals.contains(signal))
+                    .collect();
+                let body = replace(body);
+                if inner.is_empty() {
+                    *body
+                } else {
+                    JsExpression::Memoized {
+                        signals: inner,
+                        body,
+                    }
+                }
+            }
+            JsExpression::Binary { left, op, right } => JsExpression::Binary {
+                left: replace(left),
+                op,
+                right: replace(right),
+            },
+            JsExpression::Logical { left, op, right } => JsExpression::Logical {
+                left: replace(left),
+                op,
+                right: replace(right),
+            },
+            JsExpression::Conditional {
+                condition,
+                consequent,
+                alternate,
+            } => JsExpression::Conditional {
+                condition: replace(condition),
+                consequent: replace(consequent),
+                alternate: replace(alternate),
+            },
+            expression => expression,
+        }
+    }
+
+    /// How tightly this expression binds, following JavaScript's operator
+    /// precedence. Higher values bind more tightly.
+    fn precedence(&self) -> u8 {
+        match self {
+            JsExpression::Code(_)
+            | JsExpression::Literal(_)
+            | JsExpression::String(_)
+            | JsExpression::Identifier(_) => 20,
+            JsExpression::SignalRead(_)
+            | JsExpression::Call { .. }
+            | JsExpression::Memoized { .. } => 17,
+            JsExpression::Await(_) => 14,
+            JsExpression::Binary { op, .. } => binary_precedence(op),
+            JsExpression::Logical {
+                op: LogicalOp::And, ..
+            } => 4,
+            JsExpression::Logical {
+                op: LogicalOp::Or, ..
+            } => 3,
+            JsExpression::Conditional { .. } => 2,
+        }
+    }
+
+    /// Emit an operand, adding parentheses if it binds less tightly than
+    /// `precedence`. Operators are left-associative, so an operand on the
+    /// right also needs them when it binds just as tightly, like `a - (b - c)`.
+    fn fmt_operand(&self, f: &mut Formatter<'_>, precedence: u8, is_right: bool) -> Result {
+        let operand_precedence = self.precedence();
+        if operand_precedence < precedence || (is_right && operand_precedence == precedence) {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+/// Escape a string so it can be emitted inside a double-quoted JS string literal.
+pub fn escape_js_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for ch in value.chars() {
+        match ch {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            // These are valid in JSON but terminate lines in older JS engines
+            '\u{2028}' => escaped.push_str("\\u2028"),
+            '\u{2029}' => escaped.push_str("\\u2029"),
+            ch if ch.is_control() => escaped.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+fn binary_precedence(op: &BinOp) -> u8 {
+    match op {
+        BinOp::Equals => 1,
+        BinOp::Pipeline => unreachable!("pipelines are lowered to calls"),
+        BinOp::Or => 3,
+        BinOp::And => 4,
+        BinOp::BinOr => 5,
+        BinOp::BinAnd => 7,
+        BinOp::DoubleEquals => 8,
+        BinOp::GreaterThan | BinOp::GreaterThanEquals | BinOp::LessThan | BinOp::LessThanEquals => {
+            9
+        }
+        BinOp::Add | BinOp::Sub | BinOp::Sum => 11,
+        BinOp::Mul | BinOp::Div | BinOp::Mod => 12,
+    }
+}
+
+impl Display for LogicalOp {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        match self {
+            LogicalOp::And => write!(f, "&&"),
+            LogicalOp::Or => write!(f, "||"),
+        }
+    }
+}
+
+impl Display for JsExpression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        let precedence = self.precedence();
+        match self {
+            JsExpression::Code(code) | JsExpression::Literal(code) => write!(f, "{}", code),
+            JsExpression::String(value) => write!(f, "\"{}\"", escape_js_string(value)),
+            JsExpression::Identifier(name) => write!(f, "{}", name),
+            JsExpression::SignalRead(name) => write!(f, "{}.value", name),
+            JsExpression::Binary { left, op, right } => {
+                left.fmt_operand(f, precedence, false)?;
+                let op = op
+                    .to_js()
+                    .expect("binary operator should have a JS equivalent");
+                write!(f, " {} ", op)?;
+                right.fmt_operand(f, precedence, true)
+            }
+            JsExpression::Logical { left, op, right } => {
+                left.fmt_operand(f, precedence, false)?;
+                write!(f, " {} ", op)?;
+                right.fmt_operand(f, precedence, true)
+            }
+            JsExpression::Conditional {
+                condition,
+                consequent,
+                alternate,
+            } => {
+                // The branches of a conditional can be any expression, but
+                // a conditional in the condition itself has to be grouped.
+                condition.fmt_operand(f, precedence, true)?;
+                write!(f, " ? ")?;
+                consequent.fmt_operand(f, precedence, false)?;
+                write!(f, " : ")?;
+                alternate.fmt_operand(f, precedence, false)
+            }
+            JsExpression::Call { callee, arguments } => {
+                callee.fmt_operand(f, precedence, false)?;
+                let arguments: Vec<String> = arguments.iter().map(|a| a.to_string()).collect();
+                write!(f, "({})", arguments.join(", "))
+            }
+            JsExpression::Await(value) => {
+                write!(f, "await ")?;
+                value.fmt_operand(f, precedence, false)
+            }
+            JsExpression::Memoized { signals, body } => {
+                let parameters: Vec<String> = signals.iter().map(|s| format!("${}", s)).collect();
+                let reads: Vec<String> = signals.iter().map(|s| format!("{}.value", s)).collect();
+                write!(
+                    f,
+                    "(({}) => {})({})",
+                    parameters.join(", "),
+                    body,
+                    reads.join(", ")
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identifier(name: &str) -> JsExpression {
+        JsExpression::Identifier(name.into())
+    }
+
+    #[test]
+    fn parenthesize_lower_precedence_operand() {
+        let sum = JsExpression::binary(identifier("a"), BinOp::Add, identifier("b"));
+        let product = JsExpression::binary(sum, BinOp::Mul, identifier("c"));
+        assert_eq!(product.to_string(), "(a + b) * c");
+    }
+
+    #[test]
+    fn omit_parentheses_for_higher_precedence_operand() {
+        let product = JsExpression::binary(identifier("b"), BinOp::Mul, identifier("c"));
+        let sum = JsExpression::binary(identifier("a"), BinOp::Add, product);
+        assert_eq!(sum.to_string(), "a + b * c");
+    }
+
+    #[test]
+    fn parenthesize_right_operand_with_equal_precedence() {
+        let left = JsExpression::binary(identifier("a"), BinOp::Sub, identifier("b"));
+        let expression = JsExpression::binary(left, BinOp::Sub, identifier("c"));
+        assert_eq!(expression.to_string(), "a - b - c");
+
+        let right = JsExpression::binary(identifier("b"), BinOp::Sub, identifier("c"));
+        let expression = JsExpression::binary(identifier("a"), BinOp::Sub, right);
+        assert_eq!(expression.to_string(), "a - (b - c)");
+    }
+
+    #[test]
+    fn parenthesize_logical_operands() {
+        let or = JsExpression::binary(identifier("a"), BinOp::Or, identifier("b"));
+        let and = JsExpression::binary(or, BinOp::And, JsExpression::SignalRead("c".into()));
+        assert_eq!(and.to_string(), "(a || b) && c.value");
+    }
+
+    #[test]
+    fn memoize_repeated_signal_reads() {
+        let read = |name: &str| JsExpression::SignalRead(name.into());
+        // Signals that are only read once are left alone
+        let and = JsExpression::binary(read("a"), BinOp::And, read("b"));
+        assert_eq!(and.clone().memoize_signal_reads(), and);
+
+        let and = and.memoize_signal_reads();
+        let or = JsExpression::binary(read("a"), BinOp::Or, and).memoize_signal_reads();
+        assert_eq!(or.to_string(), "(($a) => $a || $a && b.value)(a.value)");
+
+        // Memoized reads are shared with the expressions around them
+        let inner = JsExpression::binary(read("b"), BinOp::Or, read("b")).memoize_signal_reads();
+        assert_eq!(inner.to_string(), "(($b) => $b || $b)(b.value)");
+        let and = JsExpression::binary(inner, BinOp::And, read("a"));
+        let or = JsExpression::binary(and, BinOp::Or, read("b")).memoize_signal_reads();
+        assert_eq!(
+            or.to_string(),
+            "(($b) => ($b || $b) && a.value || $b)(b.value)"
+        );
+
+        // A call could change a signal between reads
+        let call = JsExpression::Call {
+            callee: identifier("f").into(),
+            arguments: vec![],
+        };
+        let and = JsExpression::binary(read("a"), BinOp::And, call);
+        let or = JsExpression::binary(and, BinOp::Or, read("a"));
+        assert_eq!(or.clone().memoize_signal_reads(), or);
+    }
+
+    #[test]
+    fn escape_string_literals() {
+        let string = JsExpression::String("say \"hi\"\\\n".into());
+        assert_eq!(string.to_string(), r#""say \"hi\"\\\n""#);
+    }
+
+    #[test]
+    fn lower_pipeline_to_call() {
+        let sum = JsExpression::binary(identifier("a"), BinOp::Add, identifier("b"));
+        let pipeline = JsExpression::binary(sum, BinOp::Pipeline, identifier("f"));
+        assert_eq!(pipeline.to_string(), "f(a + b)");
+    }
+
+    #[test]
+    fn parenthesize_nested_conditional_condition() {
+        let inner = JsExpression::Conditional {
+            condition: identifier("a").into(),
+            consequent: identifier("b").into(),
+            alternate: identifier("c").into(),
+        };
+        let outer = JsExpression::Conditional {
+            condition: inner.clone().into(),
+            consequent: identifier("d").into(),
+            alternate: inner.into(),
+        };
+        assert_eq!(outer.to_string(), "(a ? b : c) ? d : a ? b : c");
+    }
+}
@@ -0,0 +1,104 @@
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+
+use diagnostics::result::Result;
+use syntax::ast::{AstArena, Binding, Expression, ExpressionId, FunctionId, ModuleId};
+use syntax::visit::{walk_expression, walk_function, Visitor};
+
+use crate::captures::CaptureMap;
+
+/// Finds the functions that are pure: calling them can't change anything,
+/// and calling them again with the same arguments returns the same value,
+/// so calls to them can be shared like any other expression.
+///
+/// A function is pure when it doesn't close over anything mutable, like
+/// `state`, and only calls other pure functions. Calling anything that isn't
+/// a function in the module, like a parameter or a global, makes it impure.
+pub struct PurityAnalysis<'a> {
+    arena: &'a AstArena,
+    captures: &'a CaptureMap,
+    // The functions called directly from each function
+    calls: RefCell<HashMap<FunctionId, Vec<FunctionId>>>,
+    // Functions that call something that isn't a function in the module
+    impure: RefCell<HashSet<FunctionId>>,
+    // The functions we're currently inside of, innermost last
+    function_stack: RefCell<Vec<FunctionId>>,
+}
+
+impl<'a> PurityAnalysis<'a> {
+    pub fn new(arena: &'a AstArena, captures: &'a CaptureMap) -> Self {
+        Self {
+            arena,
+            captures,
+            calls: Default::default(),
+            impure: Default::default(),
+            function_stack: Default::default(),
+        }
+    }
+
+    pub fn analyze(self, module_id: ModuleId) -> Result<HashSet<FunctionId>> {
+        self.visit_module(module_id)?;
+        let calls = self.calls.take();
+        let impure = self.impure.take();
+        let mut pure: HashSet<FunctionId> = self
+            .arena
+            .functions
+            .iter()
+            .map(|(function_id, _)| function_id)
+            .filter(|function_id| !impure.contains(function_id))
+            .filter(|function_id| {
+                self.captures.get(function_id).is_none_or(|captures| {
+                    !captures
+                        .iter()
+                        .any(|binding| binding.is_mutable(self.arena))
+                })
+            })
+            .collect();
+        // Calling an impure function makes the caller impure too
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (function_id, callees) in &calls {
+                if pure.contains(function_id) && callees.iter().any(|callee| !pure.contains(callee))
+                {
+                    pure.remove(function_id);
+                    changed = true;
+                }
+            }
+        }
+        Ok(pure)
+    }
+}
+
+impl<'a> Visitor for PurityAnalysis<'a> {
+    fn context(&self) -> &AstArena {
+        self.arena
+    }
+
+    fn visit_function(&self, function_id: FunctionId) -> Result<()> {
+        self.function_stack.borrow_mut().push(function_id);
+        let result = walk_function(self, function_id);
+        self.function_stack.borrow_mut().pop();
+        result
+    }
+
+    fn visit_expression(&self, expression_id: ExpressionId) -> Result<()> {
+        let expression = self.arena.expressions[expression_id].borrow();
+        let caller = self.function_stack.borrow().last().copied();
+        if let (Expression::Call { callee, .. }, Some(caller)) = (&*expression, caller) {
+            match &*self.arena.expressions[*callee].borrow() {
+                Expression::Reference(Binding::Function(callee_id)) => self
+                    .calls
+                    .borrow_mut()
+                    .entry(caller)
+                    .or_default()
+                    .push(*callee_id),
+                _ => {
+                    self.impure.borrow_mut().insert(caller);
+                }
+            }
+        }
+        drop(expression);
+        walk_expression(self, expression_id)
+    }
+}
@@ -0,0 +1,290 @@
+//! Graphs of how state flows through each component's templates: which
+//! expressions read each piece of state, and what those expressions update
+//! when it changes. These are for debugging why something re-renders.
+use std::fmt::Write;
+use std::str::FromStr;
+
+use diagnostics::result::Result;
+use syntax::ast::{AstArena, Definition, DefinitionKind, Expression, ExpressionId, ModuleId};
+
+use crate::lower::escape_js_string;
+use crate::report::count_nodes;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReactivityGraph {
+    pub component: String,
+    /// Each piece of state the templates read, in the order it's first read
+    pub states: Vec<String>,
+    pub bindings: Vec<ReactiveBinding>,
+}
+
+/// An expression in a template that's updated whenever the state it reads
+/// changes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReactiveBinding {
+    /// The expression as it was written, like `count + 1`
+    pub expression: String,
+    /// The indices in `states` of the state it reads
+    pub states: Vec<usize>,
+    /// What it updates, like "text in <p>" or "`class` on <div>"
+    pub target: String,
+}
+
+/// How graphs are written out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// The DOT language, for rendering with Graphviz
+    Dot,
+    Json,
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> std::result::Result<Self, Self::Err> {
+        match format {
+            "dot" => Ok(GraphFormat::Dot),
+            "json" => Ok(GraphFormat::Json),
+            _ => Err(format!(
+                "unknown graph format '{}', expected 'dot' or 'json'",
+                format
+            )),
+        }
+    }
+}
+
+/// Format graphs as `format`.
+pub fn format_graph(graphs: &[ReactivityGraph], format: GraphFormat) -> String {
+    match format {
+        GraphFormat::Dot => format_graph_dot(graphs),
+        GraphFormat::Json => format_graph_json(graphs),
+    }
+}
+
+/// Graph the components in a module that `include` accepts.
+pub fn reactivity_graph(
+    arena: &AstArena,
+    module_id: ModuleId,
+    preserve_whitespace: bool,
+    include: impl Fn(&Definition) -> bool,
+) -> Result<Vec<ReactivityGraph>> {
+    let module = arena.modules.get(module_id).unwrap();
+    let mut graphs = vec![];
+    for definition in module
+        .definitions
+        .iter()
+        .filter(|definition| include(definition))
+    {
+        if let DefinitionKind::Component(component_id) = definition.kind {
+            let counter = count_nodes(arena, component_id, preserve_whitespace)?;
+            let mut states = vec![];
+            let mut bindings = vec![];
+            for binding in counter.bindings {
+                let reads = binding
+                    .states
+                    .iter()
+                    .map(|state_id| {
+                        let name = arena.states[*state_id].name.symbol.to_string();
+                        match states.iter().position(|state| *state == name) {
+                            Some(index) => index,
+                            None => {
+                                states.push(name);
+                                states.len() - 1
+                            }
+                        }
+                    })
+                    .collect();
+                bindings.push(ReactiveBinding {
+                    expression: describe_expression(arena, binding.expression_id),
+                    states: reads,
+                    target: binding.target,
+                });
+            }
+            let component = arena.components.get(component_id).unwrap().borrow();
+            graphs.push(ReactivityGraph {
+                component: component.name.symbol.to_string(),
+                states,
+                bindings,
+            });
+        }
+    }
+    Ok(graphs)
+}
+
+/// Write an expression out roughly as it appears in the source. Anything
+/// longer than a line, like a function or a nested template, is elided.
+fn describe_expression(arena: &AstArena, expression_id: ExpressionId) -> String {
+    let describe = |expression_id| describe_expression(arena, expression_id);
+    match &*arena.expressions[expression_id].borrow() {
+        Expression::Binary { left, right, op } => {
+            format!("{} {} {}", describe(*left), op, describe(*right))
+        }
+        Expression::Unary { op, operand } => format!("{}{}", op, describe(*operand)),
+        Expression::Number { value, literal } => match literal {
+            Some(literal) => literal.to_string(),
+            None => value.to_string(),
+        },
+        Expression::Boolean(value) => value.to_string(),
+        Expression::String(value) => format!("\"{}\"", value),
+        Expression::Reference(binding) => binding.identifier(arena).symbol.to_string(),
+        Expression::Call { callee, arguments } => {
+            let arguments: Vec<String> = arguments
+                .iter()
+                .map(|argument| match &argument.name {
+                    Some(name) => format!("{}: {}", name.symbol, describe(argument.value)),
+                    None => describe(argument.value),
+                })
+                .collect();
+            format!("{}({})", describe(*callee), arguments.join(", "))
+        }
+        Expression::Variant { variant, arguments } => {
+            let name = arena.variants[*variant].name.symbol.to_string();
+            if arguments.is_empty() {
+                return name;
+            }
+            let arguments: Vec<String> = arguments.iter().map(|id| describe(*id)).collect();
+            format!("{}({})", name, arguments.join(", "))
+        }
+        Expression::Await(value) => format!("await {}", describe(*value)),
+        Expression::Array(values) => {
+            let values: Vec<String> = values.iter().map(|id| describe(*id)).collect();
+            format!("[{}]", values.join(", "))
+        }
+        Expression::UseContext(context_id) => {
+            format!("use {}", arena.contexts[*context_id].name.symbol)
+        }
+        Expression::If { condition, .. } => format!("if {} {{ … }}", describe(*condition)),
+        Expression::Match { value, .. } => format!("match {} {{ … }}", describe(*value)),
+        Expression::Template(_) => "<…>".to_string(),
+        Expression::Function(_) => "fn { … }".to_string(),
+    }
+}
+
+/// Format graphs in the DOT language, with a cluster for each component.
+/// State is drawn as ellipses, the expressions that read it as boxes, and
+/// what those expressions update as notes.
+pub fn format_graph_dot(graphs: &[ReactivityGraph]) -> String {
+    let quote = |value: &str| format!("\"{}\"", escape_js_string(value));
+    let mut dot = String::from("digraph reactivity {\n");
+    for (component_index, graph) in graphs.iter().enumerate() {
+        let id = |kind: &str, index: usize| format!("c{}_{}{}", component_index, kind, index);
+        writeln!(dot, "  subgraph cluster_{} {{", component_index).unwrap();
+        writeln!(dot, "    label={};", quote(&graph.component)).unwrap();
+        for (index, state) in graph.states.iter().enumerate() {
+            writeln!(
+                dot,
+                "    {} [label={}, shape=ellipse];",
+                id("s", index),
+                quote(state)
+            )
+            .unwrap();
+        }
+        for (index, binding) in graph.bindings.iter().enumerate() {
+            writeln!(
+                dot,
+                "    {} [label={}, shape=box];",
+                id("e", index),
+                quote(&binding.expression)
+            )
+            .unwrap();
+            writeln!(
+                dot,
+                "    {} [label={}, shape=note];",
+                id("t", index),
+                quote(&binding.target)
+            )
+            .unwrap();
+            for state in &binding.states {
+                writeln!(dot, "    {} -> {};", id("s", *state), id("e", index)).unwrap();
+            }
+            writeln!(dot, "    {} -> {};", id("e", index), id("t", index)).unwrap();
+        }
+        dot.push_str("  }\n");
+    }
+    dot.push_str("}\n");
+    dot
+}
+
+/// Format graphs as a JSON array, with an object for each component.
+pub fn format_graph_json(graphs: &[ReactivityGraph]) -> String {
+    let quote = |value: &str| format!("\"{}\"", escape_js_string(value));
+    let components: Vec<String> = graphs
+        .iter()
+        .map(|graph| {
+            let states: Vec<String> = graph.states.iter().map(|state| quote(state)).collect();
+            let bindings: Vec<String> = graph
+                .bindings
+                .iter()
+                .map(|binding| {
+                    let states: Vec<String> = binding
+                        .states
+                        .iter()
+                        .map(|state| quote(&graph.states[*state]))
+                        .collect();
+                    format!(
+                        "{{\"expression\": {}, \"reads\": [{}], \"updates\": {}}}",
+                        quote(&binding.expression),
+                        states.join(", "),
+                        quote(&binding.target)
+                    )
+                })
+                .collect();
+            format!(
+                "  {{\"component\": {}, \"state\": [{}], \"bindings\": [{}]}}",
+                quote(&graph.component),
+                states.join(", "),
+                bindings.join(", ")
+            )
+        })
+        .collect();
+    format!("[\n{}\n]\n", components.join(",\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_graph_dot, format_graph_json, ReactiveBinding, ReactivityGraph};
+
+    #[test]
+    fn format_graphs() {
+        let graphs = vec![ReactivityGraph {
+            component: "Counter".to_string(),
+            states: vec!["count".to_string(), "step".to_string()],
+            bindings: vec![
+                ReactiveBinding {
+                    expression: "count + step".to_string(),
+                    states: vec![0, 1],
+                    target: "text in <p>".to_string(),
+                },
+                ReactiveBinding {
+                    expression: "count".to_string(),
+                    states: vec![0],
+                    target: "`class` on <p>".to_string(),
+                },
+            ],
+        }];
+        assert_eq!(
+            format_graph_dot(&graphs),
+            "digraph reactivity {
+  subgraph cluster_0 {
+    label=\"Counter\";
+    c0_s0 [label=\"count\", shape=ellipse];
+    c0_s1 [label=\"step\", shape=ellipse];
+    c0_e0 [label=\"count + step\", shape=box];
+    c0_t0 [label=\"text in <p>\", shape=note];
+    c0_s0 -> c0_e0;
+    c0_s1 -> c0_e0;
+    c0_e0 -> c0_t0;
+    c0_e1 [label=\"count\", shape=box];
+    c0_t1 [label=\"`class` on <p>\", shape=note];
+    c0_s0 -> c0_e1;
+    c0_e1 -> c0_t1;
+  }
+}
+"
+        );
+        assert_eq!(
+            format_graph_json(&graphs),
+            "[\n  {\"component\": \"Counter\", \"state\": [\"count\", \"step\"], \"bindings\": [{\"expression\": \"count + step\", \"reads\": [\"count\", \"step\"], \"updates\": \"text in <p>\"}, {\"expression\": \"count\", \"reads\": [\"count\"], \"updates\": \"`class` on <p>\"}]}\n]\n"
+        );
+    }
+}
@@ -0,0 +1,376 @@
+//! Reports on what each component's templates render: how many nodes are
+//! created once and never touched again, how many are updated, and which
+//! state updates them.
+use std::cell::RefCell;
+use std::fmt::Write;
+
+use indexmap::IndexMap;
+
+use diagnostics::result::Result;
+use syntax::ast::{
+    AstArena, ComponentId, Definition, DefinitionKind, Expression, ExpressionId, ModuleId, StateId,
+    TemplateId,
+};
+use syntax::visit::{walk_expression, Visitor};
+
+use crate::lower::escape_js_string;
+use crate::templates::{
+    generate_template_instructions, TemplateExpressionVisitor, TemplateInstruction,
+    TemplateInstructionSet,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ComponentReport {
+    pub name: String,
+    /// Nodes that are created once and never updated, like text and
+    /// elements whose attributes are all constant.
+    pub static_nodes: usize,
+    /// Nodes with something to update: embedded expressions, elements with
+    /// attributes bound to expressions, and mounted components or slots.
+    pub dynamic_nodes: usize,
+    /// The bindings each piece of state updates, in the order the state is
+    /// first used.
+    pub state_bindings: Vec<StateBindings>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateBindings {
+    pub state: String,
+    /// What the state updates, like "text in <p>" or "`class` on <div>"
+    pub bindings: Vec<String>,
+}
+
+impl ComponentReport {
+    /// An estimate of the work it takes to keep the component up to date:
+    /// how many bindings are updated if every piece of state changes once.
+    pub fn update_cost(&self) -> usize {
+        self.state_bindings
+            .iter()
+            .map(|state| state.bindings.len())
+            .sum()
+    }
+}
+
+/// Report on the components in a module that `include` accepts.
+pub fn template_report(
+    arena: &AstArena,
+    module_id: ModuleId,
+    preserve_whitespace: bool,
+    include: impl Fn(&Definition) -> bool,
+) -> Result<Vec<ComponentReport>> {
+    let module = arena.modules.get(module_id).unwrap();
+    let mut reports = vec![];
+    for definition in module
+        .definitions
+        .iter()
+        .filter(|definition| include(definition))
+    {
+        if let DefinitionKind::Component(component_id) = definition.kind {
+            reports.push(component_report(arena, component_id, preserve_whitespace)?);
+        }
+    }
+    Ok(reports)
+}
+
+fn component_report(
+    arena: &AstArena,
+    component_id: ComponentId,
+    preserve_whitespace: bool,
+) -> Result<ComponentReport> {
+    let counter = count_nodes(arena, component_id, preserve_whitespace)?;
+    let component = arena.components.get(component_id).unwrap().borrow();
+    Ok(ComponentReport {
+        name: component.name.symbol.to_string(),
+        static_nodes: counter.static_nodes,
+        dynamic_nodes: counter.dynamic_nodes,
+        state_bindings: counter
+            .state_bindings
+            .into_iter()
+            .map(|(state_id, bindings)| StateBindings {
+                state: arena.states[state_id].name.symbol.to_string(),
+                bindings,
+            })
+            .collect(),
+    })
+}
+
+/// Count the nodes rendered by every template in a component.
+pub(crate) fn count_nodes(
+    arena: &AstArena,
+    component_id: ComponentId,
+    preserve_whitespace: bool,
+) -> Result<NodeCounter<'_>> {
+    let collector = TemplateCollector {
+        arena,
+        templates: Default::default(),
+    };
+    collector.visit_component(component_id)?;
+    let mut counter = NodeCounter {
+        arena,
+        static_nodes: 0,
+        dynamic_nodes: 0,
+        state_bindings: IndexMap::new(),
+        bindings: vec![],
+    };
+    for template_id in collector.templates.take() {
+        let instruction_set =
+            generate_template_instructions(template_id, arena, preserve_whitespace);
+        counter.count(&instruction_set, &mut vec![]);
+    }
+    Ok(counter)
+}
+
+/// Finds every template a component renders, including ones embedded in
+/// other templates.
+struct TemplateCollector<'a> {
+    arena: &'a AstArena,
+    templates: RefCell<Vec<TemplateId>>,
+}
+
+impl<'a> Visitor for TemplateCollector<'a> {
+    fn context(&self) -> &AstArena {
+        self.arena
+    }
+
+    fn visit_expression(&self, expression_id: ExpressionId) -> Result<()> {
+        if let Expression::Template(template_id) = &*self.arena.expressions[expression_id].borrow()
+        {
+            self.templates.borrow_mut().push(*template_id);
+        }
+        walk_expression(self, expression_id)
+    }
+}
+
+pub(crate) struct NodeCounter<'a> {
+    arena: &'a AstArena,
+    static_nodes: usize,
+    dynamic_nodes: usize,
+    state_bindings: IndexMap<StateId, Vec<String>>,
+    /// Every binding that reads state, in the order they're rendered
+    pub bindings: Vec<StatefulBinding>,
+}
+
+/// An expression in a template that's updated when the state it reads does.
+pub(crate) struct StatefulBinding {
+    pub expression_id: ExpressionId,
+    pub states: Vec<StateId>,
+    /// What the expression updates, like "text in <p>"
+    pub target: String,
+}
+
+impl<'a> NodeCounter<'a> {
+    /// Count the nodes created by a set of instructions. `parents` are the
+    /// names of the elements or components they're rendered into.
+    fn count(&mut self, instruction_set: &TemplateInstructionSet, parents: &mut Vec<String>) {
+        // Whether each open element has an attribute bound to an expression
+        let mut is_dynamic = vec![];
+        for instruction in &instruction_set.instructions {
+            match instruction {
+                TemplateInstruction::CreateElement(name, _) => {
+                    parents.push(format!("<{}>", name));
+                    is_dynamic.push(false);
+                }
+                TemplateInstruction::SetAttribute(name, value, _) => {
+                    let expression = self.arena.expressions[*value].borrow();
+                    // Listeners are added once, when the element is created
+                    if expression.is_constant() || matches!(*expression, Expression::Function(_)) {
+                        continue;
+                    }
+                    *is_dynamic.last_mut().unwrap() = true;
+                    let binding = format!("`{}` on {}", name, parents.last().unwrap());
+                    self.bind(*value, binding);
+                }
+                TemplateInstruction::FinishElementAttributes => {
+                    if is_dynamic.last() == Some(&true) {
+                        self.dynamic_nodes += 1;
+                    } else {
+                        self.static_nodes += 1;
+                    }
+                }
+                TemplateInstruction::CloseElement => {
+                    parents.pop();
+                    is_dynamic.pop();
+                }
+                TemplateInstruction::SetText(_) => self.static_nodes += 1,
+                TemplateInstruction::EmbedExpression(expression_id) => {
+                    self.dynamic_nodes += 1;
+                    let binding = match parents.last() {
+                        Some(parent) => format!("text in {}", parent),
+                        None => "text".to_string(),
+                    };
+                    self.bind(*expression_id, binding);
+                }
+                TemplateInstruction::MountComponent {
+                    component_id,
+                    arguments,
+                    children,
+                } => {
+                    self.dynamic_nodes += 1;
+                    let component = self.arena.components.get(*component_id).unwrap().borrow();
+                    let name = format!("<{}>", component.name.symbol);
+                    let parameters = component.parameters.as_deref().unwrap_or_default();
+                    for (parameter, argument) in parameters.iter().zip(arguments) {
+                        if let Some(argument) = argument {
+                            let parameter = self.arena.parameters.get(*parameter).unwrap();
+                            let binding = format!("`{}` of {}", parameter.name.symbol, name);
+                            self.bind(*argument, binding);
+                        }
+                    }
+                    if let Some(children) = children {
+                        parents.push(name);
+                        self.count(&children.instruction_set, parents);
+                        parents.pop();
+                    }
+                }
+                TemplateInstruction::RenderSlot(fallback) => {
+                    self.dynamic_nodes += 1;
+                    if let Some(fallback) = fallback {
+                        parents.push("<slot>".to_string());
+                        self.count(&fallback.instruction_set, parents);
+                        parents.pop();
+                    }
+                }
+                TemplateInstruction::StartChildren | TemplateInstruction::EndChildren => {}
+            }
+        }
+    }
+
+    /// Record that the state `expression_id` reads updates `binding`.
+    fn bind(&mut self, expression_id: ExpressionId, binding: String) {
+        let visitor = TemplateExpressionVisitor::new(expression_id, self.arena);
+        let mut states: Vec<StateId> = visitor
+            .stateful_expressions()
+            .unwrap_or_default()
+            .into_values()
+            .collect();
+        states.sort_by_key(|state_id| state_id.index());
+        states.dedup();
+        if states.is_empty() {
+            return;
+        }
+        for state_id in &states {
+            self.state_bindings
+                .entry(*state_id)
+                .or_default()
+                .push(binding.clone());
+        }
+        self.bindings.push(StatefulBinding {
+            expression_id,
+            states,
+            target: binding,
+        });
+    }
+}
+
+/// Format reports as a table, one row for each component, with the
+/// bindings each piece of state updates listed under it.
+pub fn format_report_table(reports: &[ComponentReport]) -> String {
+    let headers = ["Component", "Static", "Dynamic", "Update cost"];
+    let rows: Vec<[String; 4]> = reports
+        .iter()
+        .map(|report| {
+            [
+                report.name.clone(),
+                report.static_nodes.to_string(),
+                report.dynamic_nodes.to_string(),
+                report.update_cost().to_string(),
+            ]
+        })
+        .collect();
+    let widths: Vec<usize> = (0..headers.len())
+        .map(|column| {
+            rows.iter()
+                .map(|row| row[column].len())
+                .chain(Some(headers[column].len()))
+                .max()
+                .unwrap()
+        })
+        .collect();
+    let mut table = String::new();
+    let format_row = |table: &mut String, cells: &[&str]| {
+        let cells: Vec<String> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        writeln!(table, "{}", cells.join("  ").trim_end()).unwrap();
+    };
+    format_row(&mut table, &headers);
+    let rule: Vec<String> = widths.iter().map(|width| "-".repeat(*width)).collect();
+    writeln!(table, "{}", rule.join("  ")).unwrap();
+    for (report, row) in reports.iter().zip(&rows) {
+        let cells: Vec<&str> = row.iter().map(String::as_str).collect();
+        format_row(&mut table, &cells);
+        for state in &report.state_bindings {
+            writeln!(
+                table,
+                "  {} updates {}",
+                state.state,
+                state.bindings.join(", ")
+            )
+            .unwrap();
+        }
+    }
+    table
+}
+
+/// Format reports as a JSON array, with an object for each component.
+pub fn format_report_json(reports: &[ComponentReport]) -> String {
+    let quote = |value: &str| format!("\"{}\"", escape_js_string(value));
+    let components: Vec<String> = reports
+        .iter()
+        .map(|report| {
+            let states: Vec<String> = report
+                .state_bindings
+                .iter()
+                .map(|state| {
+                    let bindings: Vec<String> =
+                        state.bindings.iter().map(|binding| quote(binding)).collect();
+                    format!(
+                        "{{\"state\": {}, \"bindings\": [{}]}}",
+                        quote(&state.state),
+                        bindings.join(", ")
+                    )
+                })
+                .collect();
+            format!(
+                "  {{\"component\": {}, \"static_nodes\": {}, \"dynamic_nodes\": {}, \"update_cost\": {}, \"state\": [{}]}}",
+                quote(&report.name),
+                report.static_nodes,
+                report.dynamic_nodes,
+                report.update_cost(),
+                states.join(", ")
+            )
+        })
+        .collect();
+    format!("[\n{}\n]\n", components.join(",\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format_report_json, format_report_table, ComponentReport, StateBindings};
+
+    #[test]
+    fn format_reports() {
+        let reports = vec![ComponentReport {
+            name: "Counter".to_string(),
+            static_nodes: 2,
+            dynamic_nodes: 1,
+            state_bindings: vec![StateBindings {
+                state: "count".to_string(),
+                bindings: vec!["text in <p>".to_string(), "`class` on <p>".to_string()],
+            }],
+        }];
+        assert_eq!(
+            format_report_table(&reports),
+            "Component  Static  Dynamic  Update cost\n\
+             ---------  ------  -------  -----------\n\
+             Counter    2       1        2\n  \
+             count updates text in <p>, `class` on <p>\n"
+        );
+        assert_eq!(
+            format_report_json(&reports),
+            "[\n  {\"component\": \"Counter\", \"static_nodes\": 2, \"dynamic_nodes\": 1, \"update_cost\": 2, \"state\": [{\"state\": \"count\", \"bindings\": [\"text in <p>\", \"`class` on <p>\"]}]}\n]\n"
+        );
+    }
+}
@@ -1,19 +1,21 @@
 use std::cell::RefCell;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
+
+use indexmap::IndexSet;
 
 use common::symbol::Symbol;
 use diagnostics::result::Result;
-use log::debug;
-use syntax::ast_::{
+use syntax::ast::{
     AstArena, Binding, ComponentId, Expression, ExpressionId, StateId, Template, TemplateAttribute,
-    TemplateChild,
+    TemplateChild, TemplateId,
 };
 
-use syntax::visit_::{walk_expression, Visitor};
+use syntax::visit::{walk_expression, Visitor};
 
 pub struct TemplateExpressionVisitor<'a> {
     expression_id: ExpressionId,
     stateful_expressions: RefCell<Option<HashMap<ExpressionId, StateId>>>,
+    uses_context: RefCell<bool>,
     arena: &'a AstArena,
 }
 
@@ -22,6 +24,7 @@ impl<'a> TemplateExpressionVisitor<'a> {
         Self {
             expression_id,
             stateful_expressions: Default::default(),
+            uses_context: Default::default(),
             arena,
         }
     }
@@ -30,6 +33,12 @@ impl<'a> TemplateExpressionVisitor<'a> {
         self.visit_expression(self.expression_id).unwrap();
         self.stateful_expressions.take()
     }
+
+    /// Whether the expression reads a context, once it's been visited by
+    /// `stateful_expressions`.
+    pub fn uses_context(&self) -> bool {
+        *self.uses_context.borrow()
+    }
 }
 
 impl<'a> Visitor for TemplateExpressionVisitor<'a> {
@@ -40,6 +49,9 @@ impl<'a> Visitor for TemplateExpressionVisitor<'a> {
     fn visit_expression(&self, expression_id: ExpressionId) -> Result<()> {
         let expression = self.arena.expressions.get(expression_id).unwrap();
         let expression = expression.borrow();
+        if let Expression::UseContext(_) = *expression {
+            *self.uses_context.borrow_mut() = true;
+        }
         if let Expression::Reference(binding) = *expression {
             if let Binding::State(_) = binding {
                 let state_id = binding.to_state(self.arena).unwrap();
@@ -59,18 +71,37 @@ impl<'a> Visitor for TemplateExpressionVisitor<'a> {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct TemplateInstructionSet {
     pub instructions: Vec<TemplateInstruction>,
-    pub embedded_expressions: HashSet<ExpressionId>,
+    /// In the order they appear, so fragment parameters are always generated
+    /// in the same order.
+    pub embedded_expressions: IndexSet<ExpressionId>,
     pub stateful_expressions: HashMap<ExpressionId, StateId>,
+    /// Whether a `<slot />` renders the component's children
+    pub renders_slot: bool,
+    /// Whether the template needs the context map, either to read a context
+    /// or to pass it to the components it mounts
+    pub uses_context: bool,
+}
+
+/// Markup that's compiled separately from the template it's written in, like
+/// the children passed to a component.
+#[derive(Debug, Clone)]
+pub struct Fragment {
+    pub instruction_set: TemplateInstructionSet,
 }
 
 #[derive(Debug, Clone)]
 pub enum TemplateInstruction {
-    CreateElement(Symbol),
-    MountComponent(ComponentId),
-    SetAttribute(Symbol, ExpressionId),
+    CreateElement(Symbol, Option<Namespace>),
+    MountComponent {
+        component_id: ComponentId,
+        arguments: Vec<Option<ExpressionId>>,
+        children: Option<Fragment>,
+    },
+    RenderSlot(Option<Fragment>),
+    SetAttribute(Symbol, ExpressionId, Option<Namespace>),
     FinishElementAttributes,
     CloseElement,
     StartChildren,
@@ -79,75 +110,466 @@ pub enum TemplateInstruction {
     SetText(Symbol),
 }
 
+/// An XML namespace for elements and attributes that aren't HTML.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Namespace {
+    Svg,
+    XLink,
+    Xml,
+    Xmlns,
+}
+
+impl Namespace {
+    pub fn uri(&self) -> &'static str {
+        match self {
+            Namespace::Svg => "http://www.w3.org/2000/svg",
+            Namespace::XLink => "http://www.w3.org/1999/xlink",
+            Namespace::Xml => "http://www.w3.org/XML/1998/namespace",
+            Namespace::Xmlns => "http://www.w3.org/2000/xmlns/",
+        }
+    }
+}
+
+/// Resolve the namespace of an element, returning its name without any
+/// `svg:` prefix. An `<svg>` element starts the SVG namespace and its
+/// children inherit it, except for the contents of `<foreignObject>`, which
+/// are HTML again.
+pub fn element_namespace(
+    name: &str,
+    parent_namespace: Option<Namespace>,
+) -> (&str, Option<Namespace>) {
+    if let Some(name) = name.strip_prefix("svg:") {
+        return (name, Some(Namespace::Svg));
+    }
+    if name == "svg" {
+        return (name, Some(Namespace::Svg));
+    }
+    (name, parent_namespace)
+}
+
+/// The namespace of an attribute, like `xlink:href`. Attributes without a
+/// prefix don't have a namespace, even on SVG elements.
+pub fn attribute_namespace(name: &str) -> Option<Namespace> {
+    match name.split_once(':') {
+        Some(("xlink", _)) => Some(Namespace::XLink),
+        Some(("xml", _)) => Some(Namespace::Xml),
+        Some(("xmlns", _)) => Some(Namespace::Xmlns),
+        None if name == "xmlns" => Some(Namespace::Xmlns),
+        _ => None,
+    }
+}
+
+/// Collapse whitespace in template text the way HTML would render it. Each
+/// run of whitespace becomes a single space, except at the edges of the text
+/// where runs that span lines are removed entirely, so indentation between
+/// tags doesn't create text nodes. The first and last children of an element
+/// are trimmed at the element's boundaries too. Returns `None` if nothing is
+/// left of the text.
+pub fn collapse_whitespace(
+    text: &str,
+    is_first_child: bool,
+    is_last_child: bool,
+) -> Option<String> {
+    let mut collapsed = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    let mut is_start = true;
+    while let Some(ch) = chars.next() {
+        if !ch.is_whitespace() {
+            collapsed.push(ch);
+            is_start = false;
+            continue;
+        }
+        let mut has_newline = ch == '\n';
+        while let Some(ch) = chars.peek() {
+            if !ch.is_whitespace() {
+                break;
+            }
+            has_newline |= *ch == '\n';
+            chars.next();
+        }
+        let is_end = chars.peek().is_none();
+        let is_trimmed = (is_start && (has_newline || is_first_child))
+            || (is_end && (has_newline || is_last_child));
+        if !is_trimmed {
+            collapsed.push(' ');
+        }
+    }
+    if collapsed.is_empty() {
+        None
+    } else {
+        Some(collapsed)
+    }
+}
+
+/// Decode the HTML character references in template text, like `&amp;` or
+/// `&#169;`, since text nodes are created from the literal characters.
+/// Unknown references are left as they are.
+pub fn decode_html_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('&') {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let reference = rest.find(';').map(|end| (&rest[1..end], end));
+        let character = reference.and_then(|(name, end)| {
+            let character = match name {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some('\u{a0}'),
+                _ => {
+                    let code = if let Some(hex) = name.strip_prefix("#x") {
+                        u32::from_str_radix(hex, 16).ok()
+                    } else if let Some(decimal) = name.strip_prefix('#') {
+                        decimal.parse().ok()
+                    } else {
+                        None
+                    };
+                    code.and_then(char::from_u32)
+                }
+            };
+            character.map(|character| (character, end))
+        });
+        match character {
+            Some((character, end)) => {
+                decoded.push(character);
+                rest = &rest[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
 pub fn generate_template_instructions(
-    template: &Template,
+    template_id: TemplateId,
     arena: &AstArena,
+    preserve_whitespace: bool,
 ) -> TemplateInstructionSet {
+    let mut instruction_set = TemplateInstructionSet::default();
+    generate_node_instructions(
+        template_id,
+        arena,
+        preserve_whitespace,
+        None,
+        &mut instruction_set,
+    );
+    instruction_set
+}
+
+/// Generate the instructions for a template, which is either an element, a
+/// component being mounted, or the `<slot />` a component renders its
+/// children into.
+fn generate_node_instructions(
+    template_id: TemplateId,
+    arena: &AstArena,
+    preserve_whitespace: bool,
+    parent_namespace: Option<Namespace>,
+    instruction_set: &mut TemplateInstructionSet,
+) {
+    let template = arena.templates.get(template_id).unwrap().borrow();
+    if let Some(binding) = template.open_tag.reference {
+        generate_component_instructions(
+            &template,
+            binding.into(),
+            arena,
+            preserve_whitespace,
+            parent_namespace,
+            instruction_set,
+        );
+    } else if template.open_tag.name.symbol.to_string() == "slot" {
+        // Anything inside of the slot is rendered when there are no children
+        let fallback = generate_fragment(
+            &template,
+            arena,
+            preserve_whitespace,
+            parent_namespace,
+            instruction_set,
+        );
+        instruction_set.renders_slot = true;
+        instruction_set
+            .instructions
+            .push(TemplateInstruction::RenderSlot(fallback));
+    } else {
+        generate_element_instructions(
+            &template,
+            arena,
+            preserve_whitespace,
+            parent_namespace,
+            instruction_set,
+        );
+    }
+}
+
+fn generate_element_instructions(
+    template: &Template,
+    arena: &AstArena,
+    preserve_whitespace: bool,
+    parent_namespace: Option<Namespace>,
+    instruction_set: &mut TemplateInstructionSet,
+) {
     let Template {
         open_tag, children, ..
     } = template;
 
-    let mut instructions = Vec::new();
-    let mut embedded_expressions = HashSet::new();
-    let mut stateful_expressions = HashMap::new();
-
-    instructions.push(TemplateInstruction::CreateElement(open_tag.name.symbol));
+    let tag_name = open_tag.name.symbol.to_string();
+    let (tag_name, namespace) = element_namespace(&tag_name, parent_namespace);
+    instruction_set
+        .instructions
+        .push(TemplateInstruction::CreateElement(
+            Symbol::intern(tag_name),
+            namespace,
+        ));
+    let children_namespace = if tag_name == "foreignObject" {
+        None
+    } else {
+        namespace
+    };
 
     for TemplateAttribute { name, value } in &open_tag.attributes {
-        instructions.push(TemplateInstruction::SetAttribute(name.symbol, *value));
-        let expression = arena.expressions.get(*value).unwrap().borrow();
-        if !expression.is_constant() {
-            embedded_expressions.insert(*value);
-        }
-
-        if let Some(s) = TemplateExpressionVisitor::new(*value, arena).stateful_expressions() {
-            println!("GOT SOME");
-            stateful_expressions.extend(s);
-        }
+        let attribute_namespace = attribute_namespace(&name.symbol.to_string());
+        instruction_set
+            .instructions
+            .push(TemplateInstruction::SetAttribute(
+                name.symbol,
+                *value,
+                attribute_namespace,
+            ));
+        embed_attribute_value(*value, arena, instruction_set);
     }
 
-    instructions.push(TemplateInstruction::FinishElementAttributes);
+    instruction_set
+        .instructions
+        .push(TemplateInstruction::FinishElementAttributes);
 
+    // Void elements are closed right after their attributes. The parser
+    // rejects any children, but they can still have whitespace.
+    let children = if template.is_void() { &None } else { children };
     if let Some(children) = children {
-        for child in children {
-            match child {
-                TemplateChild::String(symbol) => {
-                    instructions.push(TemplateInstruction::SetText(*symbol));
-                }
-                TemplateChild::Expression(expression_id) => {
-                    embedded_expressions.insert(*expression_id);
-                    if let Some(s) =
-                        TemplateExpressionVisitor::new(*expression_id, arena).stateful_expressions()
-                    {
-                        stateful_expressions.extend(s);
-                    }
-                    instructions.push(TemplateInstruction::EmbedExpression(*expression_id));
+        generate_children_instructions(
+            children,
+            arena,
+            preserve_whitespace,
+            children_namespace,
+            instruction_set,
+        );
+    }
+
+    instruction_set
+        .instructions
+        .push(TemplateInstruction::CloseElement);
+}
+
+/// Mount a component, passing its attributes as arguments in the order of
+/// its parameters. Any children are compiled into a fragment that's passed
+/// to the component, which renders it wherever it has a `<slot />`.
+fn generate_component_instructions(
+    template: &Template,
+    component_id: ComponentId,
+    arena: &AstArena,
+    preserve_whitespace: bool,
+    namespace: Option<Namespace>,
+    instruction_set: &mut TemplateInstructionSet,
+) {
+    let component = arena.components.get(component_id).unwrap().borrow();
+    let parameters = component.parameters.as_deref().unwrap_or_default();
+    let arguments = parameters
+        .iter()
+        .map(|parameter| {
+            let parameter = arena.parameters.get(*parameter).unwrap();
+            template
+                .open_tag
+                .attributes
+                .iter()
+                .find(|attribute| attribute.name.symbol == parameter.name.symbol)
+                .map(|attribute| attribute.value)
+        })
+        .collect::<Vec<_>>();
+    for value in arguments.iter().flatten() {
+        embed_attribute_value(*value, arena, instruction_set);
+    }
+    instruction_set.uses_context = true;
+    let children = generate_fragment(
+        template,
+        arena,
+        preserve_whitespace,
+        namespace,
+        instruction_set,
+    );
+    instruction_set
+        .instructions
+        .push(TemplateInstruction::MountComponent {
+            component_id,
+            arguments,
+            children,
+        });
+}
+
+/// Compile the children of a template into their own fragment, if it has any.
+/// The fragment is rendered in the scope of the parent template, so anything
+/// it embeds is embedded by the parent as well.
+fn generate_fragment(
+    template: &Template,
+    arena: &AstArena,
+    preserve_whitespace: bool,
+    namespace: Option<Namespace>,
+    instruction_set: &mut TemplateInstructionSet,
+) -> Option<Fragment> {
+    let children = template.children.as_ref()?;
+    let mut fragment_instruction_set = TemplateInstructionSet::default();
+    generate_children_instructions(
+        children,
+        arena,
+        preserve_whitespace,
+        namespace,
+        &mut fragment_instruction_set,
+    );
+    if fragment_instruction_set.instructions.is_empty() {
+        return None;
+    }
+    instruction_set.embedded_expressions.extend(
+        fragment_instruction_set
+            .embedded_expressions
+            .iter()
+            .copied(),
+    );
+    instruction_set.renders_slot |= fragment_instruction_set.renders_slot;
+    instruction_set.uses_context |= fragment_instruction_set.uses_context;
+    Some(Fragment {
+        instruction_set: fragment_instruction_set,
+    })
+}
+
+fn generate_children_instructions(
+    children: &[TemplateChild],
+    arena: &AstArena,
+    preserve_whitespace: bool,
+    namespace: Option<Namespace>,
+    instruction_set: &mut TemplateInstructionSet,
+) {
+    let last_index = children.len().saturating_sub(1);
+    for (index, child) in children.iter().enumerate() {
+        match child {
+            TemplateChild::String(symbol) => {
+                if preserve_whitespace {
+                    instruction_set
+                        .instructions
+                        .push(TemplateInstruction::SetText(*symbol));
+                } else if let Some(text) =
+                    collapse_whitespace(&symbol.to_string(), index == 0, index == last_index)
+                {
+                    instruction_set
+                        .instructions
+                        .push(TemplateInstruction::SetText(Symbol::intern(&text)));
                 }
-                TemplateChild::Template(template_id) => {
-                    let template = arena.templates.get(*template_id).unwrap().borrow();
-                    if let Some(binding) = template.open_tag.reference {
-                        instructions.push(TemplateInstruction::MountComponent(binding.into()));
-                        println!("Referencing another component")
-                    }
-
-                    let child_instructions = generate_template_instructions(&template, arena);
-                    println!("Child instructions: {:#?}", child_instructions);
-                    drop(template);
-                    instructions.push(TemplateInstruction::StartChildren);
-                    instructions.extend(child_instructions.instructions);
-                    instructions.push(TemplateInstruction::EndChildren);
-                    embedded_expressions.extend(child_instructions.embedded_expressions);
-                    stateful_expressions.extend(child_instructions.stateful_expressions);
+            }
+            TemplateChild::Expression(expression_id) => {
+                instruction_set.embedded_expressions.insert(*expression_id);
+                let visitor = TemplateExpressionVisitor::new(*expression_id, arena);
+                if let Some(s) = visitor.stateful_expressions() {
+                    instruction_set.stateful_expressions.extend(s);
                 }
+                instruction_set.uses_context |= visitor.uses_context();
+                instruction_set
+                    .instructions
+                    .push(TemplateInstruction::EmbedExpression(*expression_id));
+            }
+            TemplateChild::Template(template_id) => {
+                instruction_set
+                    .instructions
+                    .push(TemplateInstruction::StartChildren);
+                generate_node_instructions(
+                    *template_id,
+                    arena,
+                    preserve_whitespace,
+                    namespace,
+                    instruction_set,
+                );
+                instruction_set
+                    .instructions
+                    .push(TemplateInstruction::EndChildren);
             }
         }
     }
+}
+
+fn embed_attribute_value(
+    value: ExpressionId,
+    arena: &AstArena,
+    instruction_set: &mut TemplateInstructionSet,
+) {
+    let expression = arena.expressions.get(value).unwrap().borrow();
+    if !expression.is_constant() {
+        instruction_set.embedded_expressions.insert(value);
+    }
+    let visitor = TemplateExpressionVisitor::new(value, arena);
+    if let Some(s) = visitor.stateful_expressions() {
+        instruction_set.stateful_expressions.extend(s);
+    }
+    instruction_set.uses_context |= visitor.uses_context();
+}
 
-    instructions.push(TemplateInstruction::CloseElement);
+#[cfg(test)]
+mod tests {
+    use super::{
+        attribute_namespace, collapse_whitespace, decode_html_entities, element_namespace,
+        Namespace,
+    };
+
+    #[test]
+    fn collapse_inline_whitespace() {
+        let text = collapse_whitespace("Hello,   world ", false, false);
+        assert_eq!(text.as_deref(), Some("Hello, world "));
+    }
+
+    #[test]
+    fn trim_whitespace_spanning_lines() {
+        let text = collapse_whitespace("\n    Hello\n  ", false, false);
+        assert_eq!(text.as_deref(), Some("Hello"));
+        assert_eq!(collapse_whitespace("\n    ", false, false), None);
+    }
+
+    #[test]
+    fn decode_entities() {
+        assert_eq!(decode_html_entities("a &amp; b &lt;3"), "a & b <3");
+        assert_eq!(decode_html_entities("&#169; &#x41;"), "\u{a9} A");
+        assert_eq!(decode_html_entities("&unknown; & done"), "&unknown; & done");
+    }
+
+    #[test]
+    fn trim_whitespace_at_element_boundaries() {
+        let text = collapse_whitespace(" Hello ", true, true);
+        assert_eq!(text.as_deref(), Some("Hello"));
+        assert_eq!(collapse_whitespace(" ", false, false).as_deref(), Some(" "));
+    }
+
+    #[test]
+    fn resolve_element_namespaces() {
+        assert_eq!(
+            element_namespace("svg", None),
+            ("svg", Some(Namespace::Svg))
+        );
+        assert_eq!(
+            element_namespace("svg:path", None),
+            ("path", Some(Namespace::Svg))
+        );
+        assert_eq!(
+            element_namespace("circle", Some(Namespace::Svg)),
+            ("circle", Some(Namespace::Svg))
+        );
+        assert_eq!(element_namespace("div", None), ("div", None));
+    }
 
-    TemplateInstructionSet {
-        instructions,
-        embedded_expressions,
-        stateful_expressions,
+    #[test]
+    fn resolve_attribute_namespaces() {
+        assert_eq!(attribute_namespace("xlink:href"), Some(Namespace::XLink));
+        assert_eq!(attribute_namespace("xmlns"), Some(Namespace::Xmlns));
+        assert_eq!(attribute_namespace("viewBox"), None);
     }
 }
@@ -1,7 +1,9 @@
+pub mod data_flow;
+
 use petgraph::dot::Dot;
 use petgraph::graph::{DiGraph, NodeIndex};
-use petgraph::visit::{ControlFlow, DfsEvent};
-use std::collections::{HashMap, VecDeque};
+use petgraph::visit::{ControlFlow, Dfs, DfsEvent, EdgeRef};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fmt::Debug;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -48,6 +50,69 @@ pub struct PartialEdge {
     pub edge: ControlFlowEdge,
 }
 
+/// Where a subgraph is entered from when it's consumed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubgraphEntry {
+    /// The subgraph follows whatever was added before it, like a statement
+    /// in a block. It's entered by the edges waiting in the queue or, when
+    /// there are none, from the last block. If every path has already
+    /// returned, nothing enters it.
+    Sequential,
+    /// The subgraph is entered from `source` along `edge`, like the body of
+    /// an if or while statement.
+    Branch {
+        source: BlockIndex,
+        edge: ControlFlowEdge,
+    },
+}
+
+/// Where the edges leaving a subgraph go when it's consumed. Return edges
+/// always go to the exit of the graph consuming it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SubgraphExit {
+    /// To whatever is added next, by way of the edge queue.
+    Continue,
+    /// Back to the condition of the loop the subgraph is the body of.
+    Loop(BlockIndex),
+}
+
+/// How `ControlFlowGraph::consume_subgraph` attaches a subgraph.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConsumeOptions {
+    pub entry: SubgraphEntry,
+    pub exit: SubgraphExit,
+}
+
+impl ConsumeOptions {
+    /// A subgraph that comes after what's already in the graph.
+    pub fn sequential() -> Self {
+        ConsumeOptions {
+            entry: SubgraphEntry::Sequential,
+            exit: SubgraphExit::Continue,
+        }
+    }
+
+    /// A branch taken from `source` along `edge`, which continues on to
+    /// whatever comes after the branching statement.
+    pub fn branch(source: BlockIndex, edge: ControlFlowEdge) -> Self {
+        ConsumeOptions {
+            entry: SubgraphEntry::Branch { source, edge },
+            exit: SubgraphExit::Continue,
+        }
+    }
+
+    /// The body of a loop, which goes back to `condition` when it's done.
+    pub fn loop_body(condition: BlockIndex) -> Self {
+        ConsumeOptions {
+            entry: SubgraphEntry::Branch {
+                source: condition,
+                edge: ControlFlowEdge::ConditionTrue,
+            },
+            exit: SubgraphExit::Loop(condition),
+        }
+    }
+}
+
 impl<T, E> Debug for ControlFlowNode<T, E> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -60,6 +125,18 @@ impl<T, E> Debug for ControlFlowNode<T, E> {
     }
 }
 
+/// A branch that was left out of the graph because its condition could be
+/// evaluated while the graph was built.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConstantCondition<T, E> {
+    pub condition: E,
+    /// Whether the condition is always true or always false
+    pub value: bool,
+    /// The statements in the branches that never run, which is every
+    /// branch but the one the condition decided on.
+    pub removed: Vec<T>,
+}
+
 #[derive(Clone)]
 pub struct BasicBlock<T> {
     pub statements: Vec<T>,
@@ -100,6 +177,15 @@ pub fn walk_control_flow_graph<T, E, F, C>(
     depth_first_search(graph, Some(entry.0), f);
 }
 
+/// A control flow graph, built up one block at a time in the order the
+/// statements run. While it's being built:
+///
+/// - The entry node has a single edge out, to the first block.
+/// - Edges into the exit node either return, or come from the end of the graph.
+/// - The edge queue holds the edges that fall through to whatever block is
+///   added next. When it's empty, the next block follows the last one.
+/// - `has_early_return` means every path through the graph has returned, so
+///   nothing added after that point is reachable.
 pub struct ControlFlowGraph<T, E, V> {
     pub graph: DiGraph<ControlFlowNode<T, E>, ControlFlowEdge>,
     pub edge_queue: VecDeque<PartialEdge>,
@@ -109,6 +195,9 @@ pub struct ControlFlowGraph<T, E, V> {
     first_index: Option<BlockIndex>,
     last_index: Option<BlockIndex>,
     pub value: Option<V>,
+    /// Branches left out because their condition was constant, including
+    /// those in consumed subgraphs.
+    pub constant_conditions: Vec<ConstantCondition<T, E>>,
 }
 
 impl<T, E, V> Default for ControlFlowGraph<T, E, V> {
@@ -125,6 +214,7 @@ impl<T, E, V> Default for ControlFlowGraph<T, E, V> {
             first_index: None,
             last_index: None,
             value: None,
+            constant_conditions: vec![],
         }
     }
 }
@@ -139,10 +229,6 @@ where
         format!("{:?}", Dot::with_config(&self.graph, &[]))
     }
 
-    pub fn print(&self) {
-        println!("{:?}", Dot::with_config(&self.graph, &[]));
-    }
-
     pub fn set_has_early_return(&mut self, has_early_return: bool) {
         self.has_early_return = has_early_return;
     }
@@ -155,124 +241,98 @@ where
     /// it into this one. This lets us recursively construct a CFG for an AST that
     /// might have arbitrarily nested control flow, like a bunch of nested if/else
     /// statements.
-    pub fn consume_subgraph(
-        &mut self,
-        other: Self,
-        entry_edge: Option<ControlFlowEdge>,
-        entry_index: BlockIndex,
-        flush_edge_queue: bool,
-    ) {
+    ///
+    /// The subgraph's entry node is replaced by whatever `options.entry` says
+    /// enters it, and the edges to its exit node are sent where
+    /// `options.exit` says, apart from return edges, which go straight to
+    /// this graph's exit. A sequential subgraph that always returns makes
+    /// this graph always return too.
+    pub fn consume_subgraph(&mut self, mut other: Self, options: ConsumeOptions) {
         if let Some(value) = &other.value {
             self.value = Some(value.clone());
         }
-        let other_has_early_return = other.has_early_return();
-        let mut edges_to_enqueue: Vec<PartialEdge> = vec![];
-
-        let other_graph = other.graph;
-        let other_node_indicies = other_graph.node_indices();
-        let other_raw_edges = other_graph.raw_edges();
-
-        let other_entry_index = other.entry_index;
-        let other_exit_index = other.exit_index;
-
-        let mut node_index_hash_map: HashMap<NodeIndex, NodeIndex> = HashMap::new();
-
-        for other_node_index in other_node_indicies {
-            // Iterate through every node in the subgraph. If its an exit or entry node,
-            // we ignore it since we don't want to include those in our subgraph.
-            let other_node = &other_graph[other_node_index];
-            if let ControlFlowNode::BasicBlock(_)
-            | ControlFlowNode::LoopCondition(_)
-            | ControlFlowNode::BranchCondition(_) = other_node
-            {
-                // Create a clone of the node from the other graph, so we can include it in this graph
-                let node = other_node.clone();
-                // Add it to the graph
-                let node_index = self.graph.add_node(node);
-                let block_index = BlockIndex(node_index);
-                // Add it to the hash map so we can map the old node index to the new one
-                // when we add the edges
-                node_index_hash_map.insert(other_node_index, node_index);
-                self.last_index = Some(block_index);
-                if self.first_index().is_none() {
-                    self.first_index = Some(BlockIndex(node_index));
+        self.constant_conditions
+            .append(&mut other.constant_conditions);
+
+        // The edges that take the place of the subgraph's entry edge
+        let predecessors = match options.entry {
+            SubgraphEntry::Sequential => {
+                let predecessors = self.take_predecessors();
+                if other.has_early_return {
+                    self.has_early_return = true;
                 }
+                predecessors
+            }
+            SubgraphEntry::Branch { source, edge } => vec![PartialEdge { source, edge }],
+        };
 
-                if let Some(other_first_index) = other.first_index {
-                    if other_first_index.0 == other_node_index && flush_edge_queue {
-                        // There may have been previous edges in the queue that need to be flushed.
-                        // For example, an if statement followed by another if statement will have a ConditionFalse
-                        // edge from BranchCondition and Normal edge from the body of the statement.
-                        // Those should point to the *next* node, which in this case will be the BranchCondition
-                        while let Some(PartialEdge { source, edge }) = self.edge_queue.pop_front() {
-                            self.add_edge(source, block_index, edge);
-                        }
+        // Map each node in the subgraph to its clone in this graph, leaving
+        // out the entry and exit nodes.
+        let mut node_index_hash_map: HashMap<NodeIndex, BlockIndex> = HashMap::new();
+        for other_node_index in other.graph.node_indices() {
+            match &other.graph[other_node_index] {
+                ControlFlowNode::Entry | ControlFlowNode::Exit => continue,
+                other_node => {
+                    let block_index = BlockIndex(self.graph.add_node(other_node.clone()));
+                    node_index_hash_map.insert(other_node_index, block_index);
+                    if self.first_index.is_none() {
+                        self.first_index = Some(block_index);
                     }
                 }
             }
         }
+        if let Some(other_last_index) = other.last_index {
+            self.last_index = Some(node_index_hash_map[&other_last_index.0]);
+        }
 
-        // Now all the nodes from the subgraph have a clone in this graph, but they are no
-        // edges. We need to add the edges, and handle the entry and exit edges specially.
-
-        for other_raw_edge in other_raw_edges {
-            // Where the edge *starts* in the subgraph
-            let other_source_index = other_raw_edge.source();
-            // Where the edge *ends* in the subgraph
-            let other_target_index = other_raw_edge.target();
-
-            // A copy of this edge's weight, to be used in this graph
-            let edge_weight = if other_source_index == other_entry_index.0 {
-                // If the edge starts at the entry node, use the provided entry edge
-                // instead of the subgraph's.
-                entry_edge.clone().unwrap_or(other_raw_edge.weight.clone())
-            } else {
-                other_raw_edge.weight.clone()
-            };
-
-            // If the SOURCE node is the subgraph's ENTRY node, we need to retarget it to
-            // entry_index that was provided.
-            let source_index = if other_source_index == other_entry_index.0 {
-                entry_index.0
+        for other_raw_edge in other.graph.raw_edges() {
+            let sources = if other_raw_edge.source() == other.entry_index.0 {
+                predecessors.clone()
             } else {
-                // Otherwise, we need to map the source node index to the new one
-                node_index_hash_map[&other_source_index]
+                vec![PartialEdge {
+                    source: node_index_hash_map[&other_raw_edge.source()],
+                    edge: other_raw_edge.weight.clone(),
+                }]
             };
-
-            // if the TARGET node is the subgraph's EXIT node, then this edge needs to be
-            // removed and added to the edge queue for this graph. Since this should be
-            // pointing to the *next* block.
-            let target_index = if other_target_index == other_exit_index.0 {
-                // Return edges are extra special, since we *know* they should
-                // point to the exit node of this graph.
-                if let ControlFlowEdge::Return = edge_weight {
-                    self.exit_index.0
-                } else {
-                    let partial_edge = PartialEdge {
-                        source: BlockIndex(source_index),
-                        edge: edge_weight,
-                    };
-                    edges_to_enqueue.push(partial_edge);
-                    continue;
+            let other_target_index = other_raw_edge.target();
+            for PartialEdge { source, edge } in sources {
+                if other_target_index != other.exit_index.0 {
+                    let target = node_index_hash_map[&other_target_index];
+                    self.add_edge(source, target, edge);
+                } else if edge == ControlFlowEdge::Return {
+                    self.add_edge(source, self.exit_index, edge);
+                } else if !other.has_early_return {
+                    // Every path through a subgraph that returns early has
+                    // returned, so any other edges leaving it are from dead code.
+                    match options.exit {
+                        SubgraphExit::Continue => self.enqueue_edge(source, edge),
+                        SubgraphExit::Loop(condition) => self.add_edge(source, condition, edge),
+                    }
                 }
-            } else {
-                // Otherwise, we need to map the source node index to the new one
-                node_index_hash_map[&other_target_index]
-            };
+            }
+        }
+    }
 
-            // Now we can add the edge to the graph
-            self.add_edge(
-                BlockIndex(source_index),
-                BlockIndex(target_index),
-                edge_weight,
-            );
+    /// Take the edges into whatever is added next: the edges waiting in the
+    /// queue or, when there are none, a normal edge from the last block.
+    fn take_predecessors(&mut self) -> Vec<PartialEdge> {
+        if !self.edge_queue.is_empty() {
+            self.edge_queue.drain(..).collect()
+        } else if self.has_early_return {
+            vec![]
+        } else {
+            vec![PartialEdge {
+                source: self.last_index(),
+                edge: ControlFlowEdge::Normal,
+            }]
         }
-        if !other_has_early_return {
-            for PartialEdge { source, edge } in edges_to_enqueue {
-                self.enqueue_edge(source, edge);
-            }
+    }
+
+    /// Connect `target` to whatever was added before it.
+    pub fn continue_to(&mut self, target: BlockIndex) {
+        for PartialEdge { source, edge } in self.take_predecessors() {
+            self.add_edge(source, target, edge);
         }
-        // new_subgraph_entry_index.unwrap()
     }
 
     pub fn entry_index(&self) -> BlockIndex {
@@ -300,8 +360,8 @@ where
     }
 
     fn add_block_index(&mut self, index: BlockIndex) {
+        self.continue_to(index);
         if self.first_index.is_none() {
-            self.add_edge(self.entry_index, index, ControlFlowEdge::Normal);
             self.first_index = Some(index);
         }
         self.last_index = Some(index);
@@ -310,7 +370,6 @@ where
     pub fn add_block(&mut self, block: BasicBlock<T>) -> BlockIndex {
         let index = BlockIndex(self.graph.add_node(ControlFlowNode::BasicBlock(block)));
         self.add_block_index(index);
-        self.flush_edge_queue(index);
         index
     }
 
@@ -416,4 +475,328 @@ where
         }
         unreachable_blocks
     }
+
+    /// The reachable nodes that go on to the exit without returning. These
+    /// are where each path that falls off the end of the graph leaves it.
+    pub fn find_fall_through_blocks(&self) -> Vec<BlockIndex> {
+        let mut reachable = HashSet::new();
+        let mut dfs = Dfs::new(&self.graph, self.entry_index.0);
+        while let Some(node_index) = dfs.next(&self.graph) {
+            reachable.insert(node_index);
+        }
+        let mut fall_through_blocks: Vec<BlockIndex> = self
+            .graph
+            .edges_directed(self.exit_index.0, petgraph::Incoming)
+            .filter(|edge| *edge.weight() != ControlFlowEdge::Return)
+            .map(|edge| edge.source())
+            .filter(|node_index| reachable.contains(node_index))
+            .map(BlockIndex)
+            .collect();
+        fall_through_blocks.sort_by_key(|block_index| block_index.0);
+        fall_through_blocks.dedup();
+        fall_through_blocks
+    }
+
+    /// Where the `true` and `false` edges out of `condition` go.
+    pub fn branch_targets(&self, condition: BlockIndex) -> (BlockIndex, BlockIndex) {
+        let target = |weight: ControlFlowEdge| {
+            self.graph
+                .edges_directed(condition.0, petgraph::Outgoing)
+                .find(|edge| *edge.weight() == weight)
+                .map(|edge| BlockIndex(edge.target()))
+                .expect("conditions have a true and a false edge")
+        };
+        (
+            target(ControlFlowEdge::ConditionTrue),
+            target(ControlFlowEdge::ConditionFalse),
+        )
+    }
+
+    /// The nodes that control can get to from `start` without returning, in
+    /// the order they're found, stopping at `end`.
+    fn reachable_without_returning(
+        &self,
+        start: BlockIndex,
+        end: Option<BlockIndex>,
+    ) -> Vec<BlockIndex> {
+        let mut reachable = vec![start];
+        let mut seen: HashSet<BlockIndex> = reachable.iter().copied().collect();
+        let mut next = 0;
+        while let Some(&index) = reachable.get(next) {
+            next += 1;
+            if Some(index) == end {
+                continue;
+            }
+            for edge in self.graph.edges_directed(index.0, petgraph::Outgoing) {
+                let target = BlockIndex(edge.target());
+                if *edge.weight() != ControlFlowEdge::Return && seen.insert(target) {
+                    reachable.push(target);
+                }
+            }
+        }
+        reachable
+    }
+
+    /// Where the branches starting at `consequent` and `alternate` meet again,
+    /// which is the first node both of them go on to without returning. They
+    /// don't meet if they only do after `end`, or if one of them always returns.
+    pub fn merge_point(
+        &self,
+        consequent: BlockIndex,
+        alternate: BlockIndex,
+        end: Option<BlockIndex>,
+    ) -> Option<BlockIndex> {
+        let alternate: HashSet<BlockIndex> = self
+            .reachable_without_returning(alternate, end)
+            .into_iter()
+            .collect();
+        let shared: Vec<BlockIndex> = self
+            .reachable_without_returning(consequent, end)
+            .into_iter()
+            .filter(|index| alternate.contains(index))
+            .collect();
+        // The first one is the one that leads to all the others
+        shared.iter().copied().find(|index| {
+            let reachable: HashSet<BlockIndex> = self
+                .reachable_without_returning(*index, end)
+                .into_iter()
+                .collect();
+            shared.iter().all(|other| reachable.contains(other))
+        })
+    }
+
+    /// Whether every path from `start` returns, instead of falling through
+    /// to `end` or off the end of the graph.
+    pub fn always_returns(&self, start: BlockIndex, end: Option<BlockIndex>) -> bool {
+        !self
+            .reachable_without_returning(start, end)
+            .into_iter()
+            .any(|index| index == self.exit_index || Some(index) == end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Graphs of numbered statements, with named conditions.
+    type Graph = ControlFlowGraph<u32, &'static str, ()>;
+
+    fn block(statement: u32) -> Graph {
+        let mut cfg = Graph::default();
+        cfg.add_block(BasicBlock {
+            statements: vec![statement],
+        });
+        cfg.continue_to(cfg.exit_index());
+        cfg
+    }
+
+    fn returning(statement: u32) -> Graph {
+        let mut cfg = Graph::default();
+        let index = cfg.add_block(BasicBlock {
+            statements: vec![statement],
+        });
+        cfg.add_edge_to_exit(index, ControlFlowEdge::Return);
+        cfg.set_has_early_return(true);
+        cfg
+    }
+
+    fn if_(condition: &'static str, body: Graph, alternate: Option<Graph>) -> Graph {
+        let mut cfg = Graph::default();
+        let index = cfg.add_branch_condition(condition);
+        let body_returns = body.has_early_return();
+        cfg.consume_subgraph(
+            body,
+            ConsumeOptions::branch(index, ControlFlowEdge::ConditionTrue),
+        );
+        match alternate {
+            Some(alternate) => {
+                let returns = body_returns && alternate.has_early_return();
+                let options = ConsumeOptions::branch(index, ControlFlowEdge::ConditionFalse);
+                cfg.consume_subgraph(alternate, options);
+                cfg.set_has_early_return(returns);
+            }
+            None => cfg.add_edge(index, cfg.exit_index(), ControlFlowEdge::ConditionFalse),
+        }
+        cfg.flush_edge_queue(cfg.exit_index());
+        cfg
+    }
+
+    fn while_(condition: &'static str, body: Graph) -> Graph {
+        let mut cfg = Graph::default();
+        let index = cfg.add_loop_condition(condition);
+        cfg.consume_subgraph(body, ConsumeOptions::loop_body(index));
+        cfg.enqueue_edge(index, ControlFlowEdge::ConditionFalse);
+        cfg.continue_to(cfg.exit_index());
+        cfg
+    }
+
+    fn sequence(subgraphs: Vec<Graph>) -> Graph {
+        let mut cfg = Graph::default();
+        for subgraph in subgraphs {
+            cfg.consume_subgraph(subgraph, ConsumeOptions::sequential());
+        }
+        cfg.continue_to(cfg.exit_index());
+        cfg
+    }
+
+    fn name(cfg: &Graph, index: NodeIndex) -> String {
+        match &cfg.graph[index] {
+            ControlFlowNode::Entry => "entry".to_string(),
+            ControlFlowNode::Exit => "exit".to_string(),
+            ControlFlowNode::BasicBlock(block) => format!("{:?}", block.statements),
+            ControlFlowNode::BranchCondition(condition)
+            | ControlFlowNode::LoopCondition(condition) => condition.to_string(),
+        }
+    }
+
+    fn edges(cfg: &Graph) -> Vec<String> {
+        let mut edges = cfg
+            .graph
+            .raw_edges()
+            .iter()
+            .map(|edge| {
+                format!(
+                    "{} -> {} {:?}",
+                    name(cfg, edge.source()),
+                    name(cfg, edge.target()),
+                    edge.weight
+                )
+            })
+            .collect::<Vec<_>>();
+        edges.sort();
+        edges
+    }
+
+    #[test]
+    fn consecutive_branches() {
+        let cfg = sequence(vec![
+            block(1),
+            if_("a", block(2), None),
+            if_("b", block(3), None),
+            block(4),
+        ]);
+        assert_eq!(
+            edges(&cfg),
+            vec![
+                "[1] -> a Normal",
+                "[2] -> b Normal",
+                "[3] -> [4] Normal",
+                "[4] -> exit Normal",
+                "a -> [2] ConditionTrue",
+                "a -> b ConditionFalse",
+                "b -> [3] ConditionTrue",
+                "b -> [4] ConditionFalse",
+                "entry -> [1] Normal",
+            ]
+        );
+    }
+
+    #[test]
+    fn loop_body_goes_back_to_condition() {
+        let cfg = sequence(vec![
+            while_("a", sequence(vec![if_("b", block(1), None)])),
+            block(2),
+        ]);
+        assert_eq!(
+            edges(&cfg),
+            vec![
+                "[1] -> a Normal",
+                "[2] -> exit Normal",
+                "a -> [2] ConditionFalse",
+                "a -> b ConditionTrue",
+                "b -> [1] ConditionTrue",
+                "b -> a ConditionFalse",
+                "entry -> a Normal",
+            ]
+        );
+    }
+
+    #[test]
+    fn early_return_from_loop_body() {
+        let cfg = sequence(vec![while_(
+            "a",
+            sequence(vec![if_("b", returning(1), None), block(2)]),
+        )]);
+        assert!(!cfg.has_early_return());
+        assert_eq!(
+            edges(&cfg),
+            vec![
+                "[1] -> exit Return",
+                "[2] -> a Normal",
+                "a -> b ConditionTrue",
+                "a -> exit ConditionFalse",
+                "b -> [1] ConditionTrue",
+                "b -> [2] ConditionFalse",
+                "entry -> a Normal",
+            ]
+        );
+    }
+
+    #[test]
+    fn code_after_returning_branches_is_unreachable() {
+        let cfg = sequence(vec![if_("a", returning(1), Some(returning(2))), block(3)]);
+        assert!(cfg.has_early_return());
+        assert_eq!(
+            edges(&cfg),
+            vec![
+                "[1] -> exit Return",
+                "[2] -> exit Return",
+                "[3] -> exit Normal",
+                "a -> [1] ConditionTrue",
+                "a -> [2] ConditionFalse",
+                "entry -> a Normal",
+            ]
+        );
+        let unreachable = cfg.find_unreachable_blocks();
+        assert_eq!(unreachable.len(), 1);
+        assert_eq!(name(&cfg, unreachable[0].0), "[3]");
+        assert!(cfg.find_fall_through_blocks().is_empty());
+    }
+
+    #[test]
+    fn branches_merge_where_they_meet_again() {
+        let merge = |cfg: &Graph| {
+            let condition = cfg
+                .graph
+                .node_indices()
+                .find(|index| matches!(cfg.graph[*index], ControlFlowNode::BranchCondition("a")))
+                .unwrap();
+            let (consequent, alternate) = cfg.branch_targets(BlockIndex(condition));
+            cfg.merge_point(consequent, alternate, None)
+                .map(|index| name(cfg, index.0))
+        };
+        // Without an else, the false edge goes to where they meet
+        let cfg = sequence(vec![if_("a", block(1), None), block(2)]);
+        assert_eq!(merge(&cfg), Some("[2]".to_string()));
+        let cfg = sequence(vec![if_("a", block(1), Some(block(2))), block(3)]);
+        assert_eq!(merge(&cfg), Some("[3]".to_string()));
+        // Branches that return don't meet anywhere
+        let cfg = sequence(vec![if_("a", returning(1), Some(returning(2)))]);
+        assert_eq!(merge(&cfg), None);
+        // Only some of the paths through an else-if return
+        let cfg = sequence(vec![
+            if_("a", block(1), Some(if_("b", returning(2), None))),
+            block(3),
+        ]);
+        assert_eq!(merge(&cfg), Some("[3]".to_string()));
+        let cfg = sequence(vec![if_("a", returning(1), None), block(2)]);
+        assert_eq!(merge(&cfg), None);
+        let consequent = cfg.branch_targets(cfg.first_index().unwrap()).0;
+        assert!(cfg.always_returns(consequent, None));
+    }
+
+    #[test]
+    fn fall_through_from_branches_and_loops() {
+        let cfg = sequence(vec![
+            if_("a", returning(1), None),
+            while_("b", returning(2)),
+        ]);
+        let names: Vec<String> = cfg
+            .find_fall_through_blocks()
+            .iter()
+            .map(|block_index| name(&cfg, block_index.0))
+            .collect();
+        assert_eq!(names, vec!["b"]);
+    }
 }
@@ -0,0 +1,344 @@
+//! Data-flow analyses over a `ControlFlowGraph`. An analysis says what facts
+//! it tracks and how each node changes them, and `ControlFlowGraph::analyze`
+//! finds the facts that hold before and after every node, iterating over
+//! loops until nothing changes.
+use super::{ControlFlowGraph, ControlFlowNode};
+use petgraph::graph::NodeIndex;
+use petgraph::Direction as EdgeDirection;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+
+/// The facts an analysis tracks at each point in the graph. Joining the
+/// facts from different paths can only ever add to them, which is what
+/// makes an analysis of a graph with loops finish.
+pub trait Lattice: Clone + PartialEq {
+    /// The facts before anything is known.
+    fn bottom() -> Self;
+
+    /// Merge in the facts from another path.
+    fn join(&mut self, other: &Self);
+}
+
+impl<T: Clone + Eq + Hash> Lattice for HashSet<T> {
+    fn bottom() -> Self {
+        HashSet::new()
+    }
+
+    fn join(&mut self, other: &Self) {
+        self.extend(other.iter().cloned());
+    }
+}
+
+/// Which way facts flow through the graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// From the entry to the exit, like reaching definitions.
+    Forward,
+    /// From the exit back to the entry, like liveness.
+    Backward,
+}
+
+pub trait DataFlowAnalysis<T, E> {
+    type Fact: Lattice;
+
+    const DIRECTION: Direction;
+
+    /// The facts where the analysis starts: entering the entry node of a
+    /// forward analysis, or leaving the exit node of a backward one.
+    fn boundary(&self) -> Self::Fact {
+        Self::Fact::bottom()
+    }
+
+    /// The facts on the other side of `node`, given the facts flowing into it.
+    fn transfer(&self, node: &ControlFlowNode<T, E>, fact: &Self::Fact) -> Self::Fact;
+}
+
+/// The facts an analysis found, before and after each node in the order
+/// the program runs, whichever way the analysis went.
+pub struct DataFlowResults<F> {
+    before: HashMap<NodeIndex, F>,
+    after: HashMap<NodeIndex, F>,
+}
+
+impl<F> DataFlowResults<F> {
+    pub fn before(&self, index: impl Into<NodeIndex>) -> &F {
+        &self.before[&index.into()]
+    }
+
+    pub fn after(&self, index: impl Into<NodeIndex>) -> &F {
+        &self.after[&index.into()]
+    }
+}
+
+impl<T, E, V> ControlFlowGraph<T, E, V> {
+    /// Run `analysis` over this graph with a worklist: a node is visited
+    /// again whenever the facts flowing into it change.
+    pub fn analyze<A>(&self, analysis: &A) -> DataFlowResults<A::Fact>
+    where
+        A: DataFlowAnalysis<T, E>,
+    {
+        let (start, incoming, outgoing) = match A::DIRECTION {
+            Direction::Forward => (
+                self.entry_index.0,
+                EdgeDirection::Incoming,
+                EdgeDirection::Outgoing,
+            ),
+            Direction::Backward => (
+                self.exit_index.0,
+                EdgeDirection::Outgoing,
+                EdgeDirection::Incoming,
+            ),
+        };
+
+        // The facts flowing into and out of each node, in the direction of
+        // the analysis.
+        let mut inputs = HashMap::new();
+        let mut outputs: HashMap<NodeIndex, A::Fact> = self
+            .graph
+            .node_indices()
+            .map(|index| (index, A::Fact::bottom()))
+            .collect();
+
+        let mut worklist: VecDeque<NodeIndex> = match A::DIRECTION {
+            Direction::Forward => self.graph.node_indices().collect(),
+            Direction::Backward => self.graph.node_indices().rev().collect(),
+        };
+        let mut queued: HashSet<NodeIndex> = worklist.iter().copied().collect();
+
+        while let Some(index) = worklist.pop_front() {
+            queued.remove(&index);
+            let mut input = if index == start {
+                analysis.boundary()
+            } else {
+                A::Fact::bottom()
+            };
+            for neighbor in self.graph.neighbors_directed(index, incoming) {
+                input.join(&outputs[&neighbor]);
+            }
+            let output = analysis.transfer(&self.graph[index], &input);
+            inputs.insert(index, input);
+            if output != outputs[&index] {
+                outputs.insert(index, output);
+                for neighbor in self.graph.neighbors_directed(index, outgoing) {
+                    if queued.insert(neighbor) {
+                        worklist.push_back(neighbor);
+                    }
+                }
+            }
+        }
+
+        let (before, after) = match A::DIRECTION {
+            Direction::Forward => (inputs, outputs),
+            Direction::Backward => (outputs, inputs),
+        };
+        DataFlowResults { before, after }
+    }
+}
+
+/// The variables a statement or condition reads, and the ones it writes.
+pub struct Access<X> {
+    pub reads: Vec<X>,
+    pub writes: Vec<X>,
+}
+
+/// What a graph's statements and conditions do with its variables, for the
+/// analyses that need to know.
+pub trait VariableAccess<T, E> {
+    type Variable: Clone + Eq + Hash;
+
+    fn statement(&self, statement: &T) -> Access<Self::Variable>;
+
+    fn condition(&self, condition: &E) -> Access<Self::Variable>;
+}
+
+impl<T, E, A: VariableAccess<T, E>> VariableAccess<T, E> for &A {
+    type Variable = A::Variable;
+
+    fn statement(&self, statement: &T) -> Access<Self::Variable> {
+        (*self).statement(statement)
+    }
+
+    fn condition(&self, condition: &E) -> Access<Self::Variable> {
+        (*self).condition(condition)
+    }
+}
+
+/// Finds the variables that are live at each point: the ones that might
+/// still be read before they're written again.
+pub struct Liveness<A>(pub A);
+
+impl<A> Liveness<A> {
+    fn apply<X: Clone + Eq + Hash>(live: &mut HashSet<X>, access: Access<X>) {
+        for variable in &access.writes {
+            live.remove(variable);
+        }
+        live.extend(access.reads);
+    }
+}
+
+impl<T, E, A> DataFlowAnalysis<T, E> for Liveness<A>
+where
+    A: VariableAccess<T, E>,
+{
+    type Fact = HashSet<A::Variable>;
+
+    const DIRECTION: Direction = Direction::Backward;
+
+    fn transfer(&self, node: &ControlFlowNode<T, E>, live: &Self::Fact) -> Self::Fact {
+        let mut live = live.clone();
+        match node {
+            ControlFlowNode::BasicBlock(block) => {
+                for statement in block.statements.iter().rev() {
+                    Self::apply(&mut live, self.0.statement(statement));
+                }
+            }
+            ControlFlowNode::BranchCondition(condition)
+            | ControlFlowNode::LoopCondition(condition) => {
+                Self::apply(&mut live, self.0.condition(condition));
+            }
+            ControlFlowNode::Entry | ControlFlowNode::Exit => {}
+        }
+        live
+    }
+}
+
+/// Finds the definitions that reach each point: the statements that wrote
+/// a variable, along paths where it hasn't been written again since. Only
+/// statements define variables, so conditions are assumed not to write any.
+pub struct ReachingDefinitions<A>(pub A);
+
+impl<A> ReachingDefinitions<A> {
+    /// Update the definitions reaching past `statement`.
+    pub fn define<T, E>(&self, reaching: &mut HashSet<(A::Variable, T)>, statement: &T)
+    where
+        A: VariableAccess<T, E>,
+        T: Clone + Eq + Hash,
+    {
+        for variable in self.0.statement(statement).writes {
+            reaching.retain(|(defined, _)| *defined != variable);
+            reaching.insert((variable, statement.clone()));
+        }
+    }
+}
+
+impl<T, E, A> DataFlowAnalysis<T, E> for ReachingDefinitions<A>
+where
+    A: VariableAccess<T, E>,
+    T: Clone + Eq + Hash,
+{
+    type Fact = HashSet<(A::Variable, T)>;
+
+    const DIRECTION: Direction = Direction::Forward;
+
+    fn transfer(&self, node: &ControlFlowNode<T, E>, reaching: &Self::Fact) -> Self::Fact {
+        let mut reaching = reaching.clone();
+        if let ControlFlowNode::BasicBlock(block) = node {
+            for statement in &block.statements {
+                self.define(&mut reaching, statement);
+            }
+        }
+        reaching
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::control_flow_graph::{BasicBlock, ConsumeOptions, ControlFlowEdge};
+
+    /// Statements are written `"a = b c"` to write `a` after reading `b` and
+    /// `c`, and conditions are the variables they read.
+    struct Variables;
+
+    impl VariableAccess<&'static str, &'static str> for Variables {
+        type Variable = &'static str;
+
+        fn statement(&self, statement: &&'static str) -> Access<&'static str> {
+            let (writes, reads) = statement.split_once('=').unwrap();
+            Access {
+                reads: reads.split_whitespace().collect(),
+                writes: writes.split_whitespace().collect(),
+            }
+        }
+
+        fn condition(&self, condition: &&'static str) -> Access<&'static str> {
+            Access {
+                reads: condition.split_whitespace().collect(),
+                writes: vec![],
+            }
+        }
+    }
+
+    type Graph = ControlFlowGraph<&'static str, &'static str, ()>;
+
+    fn block(statements: Vec<&'static str>) -> BasicBlock<&'static str> {
+        BasicBlock { statements }
+    }
+
+    fn live(variables: &[&'static str]) -> HashSet<&'static str> {
+        variables.iter().copied().collect()
+    }
+
+    #[test]
+    fn liveness_through_loop() {
+        let mut cfg = Graph::default();
+        let start = cfg.add_block(block(vec!["a =", "b ="]));
+        let condition = cfg.add_loop_condition("a");
+        let mut body = Graph::default();
+        body.add_block(block(vec!["c = b", "a = a"]));
+        body.continue_to(body.exit_index());
+        cfg.consume_subgraph(body, ConsumeOptions::loop_body(condition));
+        cfg.enqueue_edge(condition, ControlFlowEdge::ConditionFalse);
+        let end = cfg.add_block(block(vec!["= c"]));
+        cfg.continue_to(cfg.exit_index());
+
+        let results = cfg.analyze(&Liveness(Variables));
+        // `c` is only written in the loop, so it's live before it runs.
+        assert_eq!(results.before(start), &live(&["c"]));
+        assert_eq!(results.after(start), &live(&["a", "b", "c"]));
+        assert_eq!(results.before(condition), &live(&["a", "b", "c"]));
+        assert_eq!(results.before(end), &live(&["c"]));
+        assert_eq!(results.after(end), &live(&[]));
+    }
+
+    #[test]
+    fn definitions_reach_around_loop() {
+        let mut cfg = Graph::default();
+        cfg.add_block(block(vec!["a =", "b ="]));
+        let condition = cfg.add_loop_condition("a");
+        let mut body = Graph::default();
+        body.add_block(block(vec!["a = a b"]));
+        body.continue_to(body.exit_index());
+        cfg.consume_subgraph(body, ConsumeOptions::loop_body(condition));
+        cfg.enqueue_edge(condition, ControlFlowEdge::ConditionFalse);
+        cfg.continue_to(cfg.exit_index());
+
+        let results = cfg.analyze(&ReachingDefinitions(Variables));
+        let reaching: HashSet<(&str, &str)> = vec![("a", "a ="), ("b", "b ="), ("a", "a = a b")]
+            .into_iter()
+            .collect();
+        assert_eq!(results.before(condition), &reaching);
+    }
+
+    #[test]
+    fn liveness_across_branches() {
+        let mut cfg = Graph::default();
+        let start = cfg.add_block(block(vec!["a =", "b ="]));
+        let condition = cfg.add_branch_condition("a");
+        let mut body = Graph::default();
+        body.add_block(block(vec!["= b"]));
+        body.continue_to(body.exit_index());
+        cfg.consume_subgraph(
+            body,
+            ConsumeOptions::branch(condition, ControlFlowEdge::ConditionTrue),
+        );
+        cfg.enqueue_edge(condition, ControlFlowEdge::ConditionFalse);
+        cfg.add_block(block(vec!["= a"]));
+        cfg.continue_to(cfg.exit_index());
+
+        let results = cfg.analyze(&Liveness(Variables));
+        assert_eq!(results.before(start), &live(&[]));
+        assert_eq!(results.after(condition), &live(&["a", "b"]));
+        assert_eq!(results.before(cfg.exit_index()), &live(&[]));
+    }
+}
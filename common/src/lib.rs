@@ -1,4 +1,6 @@
 pub mod control_flow_graph;
 pub mod scope_map;
+pub mod passes;
 pub mod symbol;
+pub mod target;
 pub use petgraph;
@@ -0,0 +1,151 @@
+//! Runs a sequence of optimization passes over some part of a program, like
+//! the instructions for a block. Each pass has a name, the lowest
+//! optimization level it runs at, and the passes that have to run before it.
+use std::fmt;
+use std::str::FromStr;
+
+/// How much to optimize, from `-O0` for none to `-O2` for everything.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum OptLevel {
+    O0,
+    O1,
+    #[default]
+    O2,
+}
+
+impl FromStr for OptLevel {
+    type Err = String;
+
+    fn from_str(level: &str) -> Result<Self, Self::Err> {
+        match level {
+            "0" => Ok(OptLevel::O0),
+            "1" => Ok(OptLevel::O1),
+            "2" => Ok(OptLevel::O2),
+            _ => Err(format!(
+                "unknown optimization level '{}', expected 0, 1 or 2",
+                level
+            )),
+        }
+    }
+}
+
+pub struct Pass<T> {
+    pub name: &'static str,
+    /// The lowest level the pass runs at.
+    pub level: OptLevel,
+    /// The passes that have to run before this one, if they run at all.
+    pub after: &'static [&'static str],
+    pub run: fn(&mut T),
+}
+
+/// The passes for one kind of program part, in the order they run.
+pub struct PassManager<T> {
+    passes: Vec<Pass<T>>,
+    level: OptLevel,
+    print_after: Option<String>,
+}
+
+impl<T: fmt::Display> PassManager<T> {
+    pub fn new(level: OptLevel) -> Self {
+        Self {
+            passes: vec![],
+            level,
+            print_after: None,
+        }
+    }
+
+    /// Add a pass. It runs after the passes added before it, unless it's a
+    /// dependency of one of them.
+    pub fn with_pass(mut self, pass: Pass<T>) -> Self {
+        // Move it before the first pass that depends on it, as long as it
+        // stays after its own dependencies.
+        let earliest = pass
+            .after
+            .iter()
+            .filter_map(|dependency| self.position(dependency))
+            .max()
+            .map_or(0, |index| index + 1);
+        let dependent = self
+            .passes
+            .iter()
+            .position(|other| other.after.contains(&pass.name));
+        let index = match dependent {
+            Some(index) => {
+                assert!(
+                    index >= earliest,
+                    "passes '{}' and '{}' depend on each other",
+                    pass.name,
+                    self.passes[index].name
+                );
+                index
+            }
+            None => self.passes.len(),
+        };
+        self.passes.insert(index, pass);
+        self
+    }
+
+    /// Hand back what the part looks like after the pass named `name` runs.
+    pub fn with_print_after(self, name: Option<String>) -> Self {
+        Self {
+            print_after: name,
+            ..self
+        }
+    }
+
+    /// The names of the passes, in the order they run.
+    pub fn names(&self) -> Vec<&'static str> {
+        self.passes.iter().map(|pass| pass.name).collect()
+    }
+
+    /// Run the passes for the optimization level on `part`, handing back
+    /// what it looked like after the pass to print after, if that ran.
+    pub fn run(&self, part: &mut T) -> Option<String> {
+        let mut printed = None;
+        for pass in &self.passes {
+            if pass.level > self.level {
+                continue;
+            }
+            (pass.run)(part);
+            if self.print_after.as_deref() == Some(pass.name) {
+                printed = Some(part.to_string());
+            }
+        }
+        printed
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.passes.iter().position(|pass| pass.name == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{OptLevel, Pass, PassManager};
+
+    fn pass(name: &'static str, level: OptLevel, after: &'static [&'static str]) -> Pass<String> {
+        Pass {
+            name,
+            level,
+            after,
+            run: |part| part.push('.'),
+        }
+    }
+
+    #[test]
+    fn order_and_levels() {
+        let passes = PassManager::new(OptLevel::O1)
+            .with_pass(pass("b", OptLevel::O1, &["a"]))
+            .with_pass(pass("c", OptLevel::O2, &[]))
+            .with_pass(pass("a", OptLevel::O0, &[]));
+        assert_eq!(passes.names(), vec!["a", "b", "c"]);
+        let mut part = String::new();
+        assert_eq!(passes.run(&mut part), None);
+        // `c` is only for `-O2`
+        assert_eq!(part, "..");
+
+        let passes = passes.with_print_after(Some("a".to_string()));
+        assert_eq!(passes.run(&mut part).as_deref(), Some("..."));
+        assert_eq!(part, "....");
+    }
+}
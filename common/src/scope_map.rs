@@ -43,12 +43,15 @@ impl<K: Reference, V: Referant> Scope<K, V> {
 pub struct ScopeMap<K: Reference, V: Referant> {
     unique_id: u16,
     scopes: Vec<Scope<K, V>>,
+    // How many unique names have been allocated for each identifier
+    unique_names: HashMap<K, u32>,
 }
 
 impl<K: Reference, V: Referant> Default for ScopeMap<K, V> {
     fn default() -> Self {
         ScopeMap {
             unique_id: 0,
+            unique_names: HashMap::default(),
             scopes: vec![
                 // ScopeMap has a default scope, which ends up
                 // being the top-level module scope since we create
@@ -88,6 +91,19 @@ impl<K: Reference, V: Referant> ScopeMap<K, V> {
         self.unique_id += 1;
         unique_reference
     }
+    /// Allocate a unique name for an identifier that's about to be defined.
+    /// This is 0 unless the new definition shadows a binding that's still
+    /// in scope, in which case each shadowing definition gets the next number
+    /// so they don't collide once emitted.
+    pub fn unique_name(&mut self, identifier: &K) -> u32 {
+        if self.resolve(identifier).is_none() {
+            return 0;
+        }
+        let count = self.unique_names.entry(identifier.clone()).or_insert(0);
+        *count += 1;
+        *count
+    }
+
     pub fn resolve(&mut self, identifer: &K) -> Option<&(V, UniqueReference<K>)> {
         for scope in self.scopes.iter().rev() {
             match scope.bindings.get(identifer) {
@@ -98,3 +114,34 @@ impl<K: Reference, V: Referant> ScopeMap<K, V> {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Referant, ScopeMap};
+    use crate::symbol::Symbol;
+
+    impl Referant for u32 {}
+
+    #[test]
+    fn unique_names_for_shadowing_definitions() {
+        let mut scope_map: ScopeMap<Symbol, u32> = ScopeMap::default();
+        let count = Symbol::intern("count");
+        let other = Symbol::intern("other");
+        // Nothing is shadowed yet
+        assert_eq!(scope_map.unique_name(&count), 0);
+        scope_map.define(count, 0);
+        scope_map.extend();
+        assert_eq!(scope_map.unique_name(&count), 1);
+        scope_map.define(count, 1);
+        assert_eq!(scope_map.unique_name(&other), 0);
+        scope_map.extend();
+        assert_eq!(scope_map.unique_name(&count), 2);
+        scope_map.pop();
+        scope_map.pop();
+        // Names aren't reused once a scope ends, so siblings can't collide
+        scope_map.extend();
+        assert_eq!(scope_map.unique_name(&count), 3);
+        scope_map.pop();
+        assert_eq!(scope_map.resolve(&count).map(|(value, _)| *value), Some(0));
+    }
+}
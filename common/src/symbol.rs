@@ -1,32 +1,54 @@
 use crate::scope_map::Reference;
+use once_cell::sync::Lazy;
+use std::cell::Cell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
 use std::fmt::{Debug, Display};
-use std::sync::Mutex;
-use std::{collections::HashMap, mem};
+use std::hash::{Hash, Hasher};
+use std::sync::RwLock;
+
+/// Symbols are shared by every thread, so a symbol interned while parsing on
+/// one thread can be read on another.
+static SYMBOL_INTERNER: Lazy<RwLock<SymbolInterner>> = Lazy::new(Default::default);
 
 thread_local! {
-    pub static SYMBOL_INTERNER : Mutex<SymbolInterner> = Mutex::new(SymbolInterner::default())
+    /// The scope that symbols interned on this thread belong to.
+    static CURRENT_SCOPE: Cell<Option<SymbolScope>> = const { Cell::new(None) };
 }
 
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct Symbol(u32);
+pub struct Symbol {
+    index: u32,
+    /// Slots are reused once their symbol is freed, so this tells a stale
+    /// symbol apart from the one that replaced it.
+    generation: u32,
+}
 
 impl Reference for Symbol {}
 
 impl Symbol {
     pub fn intern(name: &str) -> Symbol {
-        SYMBOL_INTERNER.with(|interner| {
-            let mut gaurd = interner.lock().unwrap();
-            gaurd.intern(name)
-        })
+        let scope = CURRENT_SCOPE.with(|scope| scope.get());
+        {
+            let interner = SYMBOL_INTERNER.read().unwrap();
+            if let Some(symbol) = interner.get(name, scope) {
+                return symbol;
+            }
+        }
+        SYMBOL_INTERNER.write().unwrap().intern(name, scope)
+    }
+
+    fn with_str<T>(self, f: impl FnOnce(&str) -> T) -> T {
+        let interner = SYMBOL_INTERNER.read().unwrap();
+        f(interner.lookup(self))
     }
 }
 
 impl Into<f64> for Symbol {
     fn into(self) -> f64 {
-        SYMBOL_INTERNER.with(|interner| {
-            let interner = interner.lock().unwrap();
+        self.with_str(|string| {
             // TODO(aweary) is this really where we should strip the separator characters?
-            let string = interner.lookup(self).replace("_", "");
+            let string = string.replace("_", "");
             string.parse::<f64>().unwrap()
         })
     }
@@ -34,82 +56,202 @@ impl Into<f64> for Symbol {
 
 impl Debug for Symbol {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        SYMBOL_INTERNER.with(|interner| {
-            let interner = interner.lock().unwrap();
-            let string = interner.lookup(*self);
-            write!(f, "{}", string)
-        })
+        self.with_str(|string| write!(f, "{}", string))
     }
 }
 
 impl Display for Symbol {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        SYMBOL_INTERNER.with(|interner| {
-            let interner = interner.lock().unwrap();
-            let string = interner.lookup(*self);
-            write!(f, "{}", string)
-        })
+        self.with_str(|string| write!(f, "{}", string))
     }
 }
 
-pub struct SymbolInterner {
-    map: HashMap<&'static str, Symbol>,
-    vec: Vec<&'static str>,
-    buf: String,
-    full: Vec<String>,
+/// A group of symbols that are freed together, like the symbols interned
+/// while compiling a file. Symbols interned outside of any scope are never freed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SymbolScope(u64);
+
+impl SymbolScope {
+    pub fn new(key: impl Hash) -> Self {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        SymbolScope(hasher.finish())
+    }
+
+    /// Intern symbols on this thread into the scope until the guard is dropped.
+    pub fn enter(self) -> SymbolScopeGuard {
+        let previous = CURRENT_SCOPE.with(|scope| scope.replace(Some(self)));
+        SymbolScopeGuard { previous }
+    }
+
+    /// Free the symbols that only this scope has interned, returning how
+    /// many were freed. Those symbols can't be used afterwards.
+    pub fn release(self) -> usize {
+        SYMBOL_INTERNER.write().unwrap().release(self)
+    }
+}
+
+/// Restores the previous scope when dropped.
+pub struct SymbolScopeGuard {
+    previous: Option<SymbolScope>,
 }
 
-impl Default for SymbolInterner {
-    fn default() -> Self {
-        SymbolInterner::with_capacity(2 ^ 8)
+impl Drop for SymbolScopeGuard {
+    fn drop(&mut self) {
+        CURRENT_SCOPE.with(|scope| scope.set(self.previous));
     }
 }
 
+struct Slot {
+    /// Boxed so the string doesn't move when `slots` grows, which lets the
+    /// map borrow it. `None` once the symbol is freed.
+    name: Option<Box<str>>,
+    generation: u32,
+    /// The scopes that interned the symbol, or `None` if it's never freed.
+    scopes: Option<Vec<SymbolScope>>,
+}
+
+#[derive(Default)]
+pub struct SymbolInterner {
+    map: HashMap<&'static str, u32>,
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+    /// The slots interned by each scope.
+    scopes: HashMap<SymbolScope, Vec<u32>>,
+}
+
 impl SymbolInterner {
-    pub fn with_capacity(cap: usize) -> SymbolInterner {
-        let cap = cap.next_power_of_two();
-        SymbolInterner {
-            map: HashMap::default(),
-            vec: Vec::new(),
-            buf: String::with_capacity(cap),
-            full: Vec::new(),
+    /// The symbol for `name`, if it's interned and already belongs to `scope`.
+    fn get(&self, name: &str, scope: Option<SymbolScope>) -> Option<Symbol> {
+        let index = *self.map.get(name)?;
+        let slot = &self.slots[index as usize];
+        match (&slot.scopes, scope) {
+            (None, _) => {}
+            (Some(scopes), Some(scope)) if scopes.contains(&scope) => {}
+            _ => return None,
         }
+        Some(Symbol {
+            index,
+            generation: slot.generation,
+        })
     }
 
-    pub fn intern(&mut self, name: &str) -> Symbol {
-        if let Some(&id) = self.map.get(name) {
-            return id;
+    pub fn intern(&mut self, name: &str, scope: Option<SymbolScope>) -> Symbol {
+        let index = match self.map.get(name) {
+            Some(&index) => index,
+            None => self.alloc(name),
+        };
+        let slot = &mut self.slots[index as usize];
+        match (&mut slot.scopes, scope) {
+            (None, _) => {}
+            (scopes @ Some(_), None) => *scopes = None,
+            (Some(scopes), Some(scope)) => {
+                if !scopes.contains(&scope) {
+                    scopes.push(scope);
+                    self.scopes.entry(scope).or_default().push(index);
+                }
+            }
         }
-        let name = unsafe { self.alloc(name) };
-        let id = Symbol(self.map.len() as u32);
-        self.map.insert(name, id);
-        self.vec.push(name);
+        let symbol = Symbol {
+            index,
+            generation: slot.generation,
+        };
 
-        debug_assert!(self.lookup(id) == name);
-        debug_assert!(self.intern(name) == id);
+        debug_assert!(self.lookup(symbol) == name);
 
-        id
+        symbol
     }
 
-    pub fn lookup(&self, id: Symbol) -> &str {
-        self.vec[id.0 as usize]
+    pub fn lookup(&self, symbol: Symbol) -> &str {
+        let slot = &self.slots[symbol.index as usize];
+        match &slot.name {
+            Some(name) if slot.generation == symbol.generation => name,
+            _ => panic!("Symbol used after its scope was released"),
+        }
     }
 
-    unsafe fn alloc(&mut self, name: &str) -> &'static str {
-        let cap = self.buf.capacity();
-        if cap < self.buf.len() + name.len() {
-            let new_cap = (cap.max(name.len()) + 1).next_power_of_two();
-            let new_buf = String::with_capacity(new_cap);
-            let old_buf = mem::replace(&mut self.buf, new_buf);
-            self.full.push(old_buf);
+    fn alloc(&mut self, name: &str) -> u32 {
+        let name: Box<str> = name.into();
+        // Safe since the map entry is removed before the box is dropped.
+        let key = unsafe { &*(&*name as *const str) };
+        let index = match self.free.pop() {
+            Some(index) => {
+                let slot = &mut self.slots[index as usize];
+                slot.name = Some(name);
+                slot.generation += 1;
+                slot.scopes = Some(vec![]);
+                index
+            }
+            None => {
+                self.slots.push(Slot {
+                    name: Some(name),
+                    generation: 0,
+                    scopes: Some(vec![]),
+                });
+                self.slots.len() as u32 - 1
+            }
+        };
+        self.map.insert(key, index);
+        index
+    }
+
+    fn release(&mut self, scope: SymbolScope) -> usize {
+        let mut freed = 0;
+        for index in self.scopes.remove(&scope).unwrap_or_default() {
+            let slot = &mut self.slots[index as usize];
+            let scopes = match &mut slot.scopes {
+                Some(scopes) => scopes,
+                None => continue,
+            };
+            scopes.retain(|other| *other != scope);
+            if scopes.is_empty() {
+                if let Some(name) = &slot.name {
+                    self.map.remove(&**name);
+                }
+                slot.name = None;
+                self.free.push(index);
+                freed += 1;
+            }
         }
+        freed
+    }
+}
 
-        let interned = {
-            let start = self.buf.len();
-            self.buf.push_str(name);
-            &self.buf[start..]
+#[cfg(test)]
+mod tests {
+    use super::{Symbol, SymbolScope};
+
+    #[test]
+    fn release_scoped_symbols() {
+        let permanent = Symbol::intern("permanent");
+        let scope = SymbolScope::new("release_scoped_symbols");
+        let (scoped, shared) = {
+            let _guard = scope.enter();
+            (Symbol::intern("scoped only"), Symbol::intern("permanent"))
         };
+        assert_eq!(shared, permanent);
+        assert_eq!(scoped.to_string(), "scoped only");
+        assert_eq!(scope.release(), 1);
+        assert_eq!(permanent.to_string(), "permanent");
+        let reinterned = Symbol::intern("scoped only");
+        assert_ne!(reinterned, scoped);
+        assert_eq!(reinterned.to_string(), "scoped only");
+    }
 
-        &*(interned as *const str)
+    #[test]
+    fn shared_scopes_keep_symbols() {
+        let first = SymbolScope::new("first");
+        let second = SymbolScope::new("second");
+        let symbol = {
+            let _guard = first.enter();
+            Symbol::intern("shared by scopes")
+        };
+        {
+            let _guard = second.enter();
+            assert_eq!(Symbol::intern("shared by scopes"), symbol);
+        }
+        assert_eq!(first.release(), 0);
+        assert_eq!(symbol.to_string(), "shared by scopes");
+        assert_eq!(second.release(), 1);
     }
 }
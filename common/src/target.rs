@@ -0,0 +1,104 @@
+//! The environments compiled modules run in. Each one has its own built-in
+//! globals, and only browsers have a DOM for components to render to.
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Target {
+    #[default]
+    Browser,
+    Node,
+}
+
+/// Globals every target has.
+const SHARED_GLOBALS: &[&str] = &[
+    "console",
+    "Math",
+    "JSON",
+    "Date",
+    "setTimeout",
+    "clearTimeout",
+    "setInterval",
+    "clearInterval",
+];
+
+const BROWSER_GLOBALS: &[&str] = &[
+    "document",
+    "window",
+    "alert",
+    "localStorage",
+    "navigator",
+    "location",
+];
+
+const NODE_GLOBALS: &[&str] = &["process", "Buffer", "global"];
+
+impl Target {
+    pub const ALL: [Target; 2] = [Target::Browser, Target::Node];
+
+    /// Whether `name` is a global that's always defined in this target.
+    pub fn has_global(self, name: &str) -> bool {
+        let globals = match self {
+            Target::Browser => BROWSER_GLOBALS,
+            Target::Node => NODE_GLOBALS,
+        };
+        SHARED_GLOBALS.contains(&name) || globals.contains(&name)
+    }
+
+    /// Whether there's a DOM, which components need to render.
+    pub fn has_dom(self) -> bool {
+        match self {
+            Target::Browser => true,
+            Target::Node => false,
+        }
+    }
+
+    /// The targets that have `name` as a global, for telling people which
+    /// target a global they can't use is from.
+    pub fn with_global(name: &str) -> Vec<Target> {
+        Target::ALL
+            .iter()
+            .copied()
+            .filter(|target| target.has_global(name))
+            .collect()
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Browser => write!(f, "browser"),
+            Target::Node => write!(f, "node"),
+        }
+    }
+}
+
+impl FromStr for Target {
+    type Err = String;
+
+    fn from_str(target: &str) -> Result<Self, Self::Err> {
+        match target {
+            "browser" => Ok(Target::Browser),
+            "node" => Ok(Target::Node),
+            _ => Err(format!(
+                "unknown target '{}', expected 'browser' or 'node'",
+                target
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Target;
+
+    #[test]
+    fn globals_by_target() {
+        assert!(Target::Browser.has_global("console"));
+        assert!(Target::Node.has_global("console"));
+        assert!(!Target::Node.has_global("document"));
+        assert_eq!(Target::with_global("document"), vec![Target::Browser]);
+        assert_eq!(Target::with_global("process"), vec![Target::Node]);
+        assert!(Target::with_global("nothing").is_empty());
+    }
+}
@@ -1,12 +1,479 @@
+//! Compiling modules from another Rust program, without going through the
+//! CLI or the database's queries.
 use db::*;
+use diagnostics::error::{Diagnostic, Severity};
+use diagnostics::events::CompilerEventSink;
+use diagnostics::result::Result;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// A module compiled to JavaScript.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Artifact {
+    /// The module it was compiled from.
+    pub path: PathBuf,
+    /// The JavaScript it compiled to.
+    pub code: String,
+    /// The names the module exports.
+    pub exports: Vec<String>,
+}
+
+/// A diagnostic reported while compiling the module at `path`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleDiagnostic {
+    pub path: PathBuf,
+    pub diagnostic: Diagnostic,
+}
+
+/// Keeps the warnings queries report, since they're only reported when a
+/// module is actually compiled and not when its output is reused.
+#[derive(Clone, Default)]
+struct WarningCollector(Arc<Mutex<Vec<ModuleDiagnostic>>>);
+
+impl CompilerEventSink for WarningCollector {
+    fn diagnostic(&self, path: &Path, _source: &str, diagnostic: &Diagnostic) {
+        self.0.lock().unwrap().push(ModuleDiagnostic {
+            path: path.to_path_buf(),
+            diagnostic: diagnostic.clone(),
+        });
+    }
+}
 
 pub struct Compiler {
-    pub db: Database
+    pub db: Database,
+    warnings: WarningCollector,
+    /// The text each module was last compiled from.
+    sources: HashMap<PathBuf, Arc<String>>,
+    artifacts: Vec<Artifact>,
+    diagnostics: Vec<ModuleDiagnostic>,
 }
 
 impl Compiler {
     pub fn new() -> Self {
-        let db = Database::default();
-        Compiler { db }
+        let warnings = WarningCollector::default();
+        let db = Database::with_event_sink(warnings.clone());
+        Compiler {
+            db,
+            warnings,
+            sources: HashMap::new(),
+            artifacts: vec![],
+            diagnostics: vec![],
+        }
+    }
+
+    /// Compile `source` as the module `name`, like `main.ws`. Modules it
+    /// imports are found relative to `name`.
+    pub fn compile_str(&mut self, name: &str, source: &str) -> Result<&Artifact> {
+        self.compile(PathBuf::from(name), Arc::new(source.to_string()))
+    }
+
+    /// Compile the module at `path`.
+    pub fn compile_path(&mut self, path: impl AsRef<Path>) -> Result<&Artifact> {
+        let path = path.as_ref();
+        let source = std::fs::read_to_string(path)?;
+        self.compile(path.to_path_buf(), Arc::new(source))
+    }
+
+    /// The modules that compiled, as of the last time each was compiled.
+    pub fn artifacts(&self) -> &[Artifact] {
+        &self.artifacts
+    }
+
+    /// The errors and warnings from the last time each module was compiled.
+    pub fn diagnostics(&self) -> &[ModuleDiagnostic] {
+        &self.diagnostics
+    }
+
+    fn compile(&mut self, path: PathBuf, source: Arc<String>) -> Result<&Artifact> {
+        // Unchanged modules aren't compiled again, so their warnings are kept.
+        if self.sources.get(&path) != Some(&source) {
+            self.db.set_file_text(path.clone(), source.clone());
+            self.sources.insert(path.clone(), source);
+            self.diagnostics.retain(|reported| reported.path != path);
+        } else {
+            self.diagnostics.retain(|reported| {
+                reported.path != path || reported.diagnostic.severity() != Severity::Error
+            });
+        }
+        let compiled = self.db.compile_module(path.clone());
+        let warnings = std::mem::take(&mut *self.warnings.0.lock().unwrap());
+        {
+            // Modules are compiled along with the modules that import them,
+            // which replaces their warnings too.
+            let compiled: HashSet<&PathBuf> =
+                warnings.iter().map(|warning| &warning.path).collect();
+            self.diagnostics
+                .retain(|reported| !compiled.contains(&reported.path));
+        }
+        self.diagnostics.extend(warnings);
+        self.artifacts.retain(|artifact| artifact.path != path);
+        let module = match compiled {
+            Ok(module) => module,
+            Err(error) => {
+                for diagnostic in error.diagnostics() {
+                    self.diagnostics.push(ModuleDiagnostic {
+                        path: path.clone(),
+                        diagnostic: diagnostic.clone(),
+                    });
+                }
+                return Err(error);
+            }
+        };
+        let module_name = path.with_extension("");
+        let module_name = module_name.to_string_lossy();
+        let code = render_module(&module_name, &self.db.codegen_options(), &module.code)?;
+        self.artifacts.push(Artifact {
+            path,
+            code,
+            exports: module.exports.clone(),
+        });
+        Ok(self.artifacts.last().unwrap())
+    }
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Compiler::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Compiler;
+    use db::{write_library, CodegenOptions, CompiledModule, Feature, Features, Parser};
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    #[test]
+    fn compile_strings() {
+        let mut compiler = Compiler::new();
+        let artifact = compiler
+            .compile_str("math.ws", "pub fn double(a: number) { return a * 2 }")
+            .unwrap();
+        assert_eq!(artifact.exports, vec!["double".to_string()]);
+        assert!(artifact.code.contains("export function double(a)"));
+        assert!(compiler.diagnostics().is_empty());
+
+        assert!(compiler.compile_str("main.ws", "fn a() { b }").is_err());
+        assert_eq!(compiler.artifacts().len(), 1);
+        assert_eq!(compiler.diagnostics().len(), 1);
+        let error = &compiler.diagnostics()[0];
+        assert_eq!(error.path.to_str(), Some("main.ws"));
+        assert_eq!(error.diagnostic.message(), "Unknown Reference");
+
+        // Fixing the module replaces its error
+        compiler.compile_str("main.ws", "fn a() { return 1 }").unwrap();
+        assert_eq!(compiler.artifacts().len(), 2);
+        assert!(compiler.diagnostics().is_empty());
+    }
+
+    #[test]
+    fn debug_comments_name_the_module() {
+        let mut compiler = Compiler::new();
+        compiler.db.set_codegen_options(CodegenOptions {
+            debug_comments: true,
+            ..CodegenOptions::default()
+        });
+        let source =
+            "pub fn sign(a: number): number {\n  if a > 0 {\n    return 1\n  }\n  return 0\n}\n";
+        let artifact = compiler.compile_str("ui/button.ws", source).unwrap();
+        assert!(artifact.code.contains("/* ui/button.ws:2 */ if (a > 0)"));
+        assert!(artifact.code.contains("//# sourceURL=ui/button.ws"));
+        assert!(!artifact.code.contains("main.ws"));
+    }
+
+    #[test]
+    fn errors_in_every_definition() {
+        let mut compiler = Compiler::new();
+        let source = "fn a() { b }\nfn c() { d }\npub fn e() { return a() }\nfn f() { g }\n";
+        let error = compiler.compile_str("main.ws", source).unwrap_err();
+        let messages: Vec<&str> = error
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| diagnostic.primary_message().unwrap())
+            .collect();
+        assert_eq!(
+            messages,
+            vec!["Cannot resolve 'b'", "Cannot resolve 'd'", "Cannot resolve 'g'"]
+        );
+
+        compiler.db.set_codegen_options(CodegenOptions {
+            max_errors: Some(2),
+            ..Default::default()
+        });
+        let error = compiler.compile_str("main.ws", source).unwrap_err();
+        let messages: Vec<&str> = error
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| diagnostic.message())
+            .collect();
+        assert_eq!(
+            messages,
+            vec!["Unknown Reference", "Unknown Reference", "Too Many Errors"]
+        );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn type_aliases_between_definitions() {
+        let mut compiler = Compiler::new();
+        let source = "type Score = number\n\npub fn double(a: Score) { return a * 2 }\n";
+        let artifact = compiler.compile_str("scores.ws", source).unwrap();
+        assert_eq!(artifact.exports, vec!["double".to_string()]);
+    }
+
+    #[test]
+    fn if_else_chains() {
+        let mut compiler = Compiler::new();
+        let source = "pub fn sign(a: number): number {
+  if a > 0 {
+    log(\"positive\")
+  } else if a < 0 {
+    return 2
+  } else {
+    log(\"zero\")
+  }
+  return 1
+}
+";
+        let artifact = compiler.compile_str("sign.ws", source).unwrap();
+        assert!(artifact.code.contains(
+            "if (a > 0) {
+console.log(\"positive\");
+} else if (a < 0) {
+return 2;
+} else {
+console.log(\"zero\");
+}
+return 1;"
+        ));
+    }
+
+    #[test]
+    fn attribute_shorthand() {
+        let mut compiler = Compiler::new();
+        let source = "pub component Form(name: string, busy: bool) {
+  return <form>
+    <input {name} disabled />
+    <input required={false} />
+    <button disabled={busy}>Send</button>
+  </form>
+}
+";
+        let artifact = compiler.compile_str("form.ws", source).unwrap();
+        assert!(artifact.code.contains(
+            "$2.setAttribute(\"name\", name);
+$2.setAttribute(\"disabled\", \"\");"
+        ));
+        assert!(!artifact.code.contains("required"));
+        assert!(artifact
+            .code
+            .contains("$4.toggleAttribute(\"disabled\", busy);"));
+    }
+
+    #[test]
+    fn constant_attributes() {
+        let mut compiler = Compiler::new();
+        let source = "const SIZE = 10
+pub component Card(name: string) {
+  let label = \"Count\"
+  return <div title={label} width={SIZE * 2} id={name}></div>
+}
+";
+        let artifact = compiler.compile_str("card.ws", source).unwrap();
+        assert!(artifact.code.contains(
+            "$1.setAttribute(\"title\", \"Count\");
+$1.setAttribute(\"width\", 20);
+$1.setAttribute(\"id\", name);"
+        ));
+        // Only attributes that aren't constant are passed to the template
+        assert!(artifact.code.contains("create_fragment_0(name)"));
+    }
+
+    #[test]
+    fn numbers_keep_their_literals() {
+        let mut compiler = Compiler::new();
+        let source = "pub fn numbers(): [number] {\n  return [1_000_000, 0.10, 9007199254740993, 1__0, 2 * 3]\n}\n";
+        let artifact = compiler.compile_str("numbers.ws", source).unwrap();
+        assert!(artifact
+            .code
+            .contains("[1_000_000, 0.10, 9007199254740993, 10, 6]"));
+    }
+
+    #[test]
+    fn stable_template_names() {
+        let app = "pub component App {\n  return (\n    <p>1</p>\n  )\n}\n";
+        let mut compiler = Compiler::new();
+        let before = compiler.compile_str("main.ws", app).unwrap().code.clone();
+        assert!(before.contains("main$App$create_fragment_0"));
+
+        // Templates in other definitions, and names from the source that look
+        // like generated ones, don't change what App's functions are called
+        let source = format!(
+            "pub fn create_fragment_0() {{ 1 }}\npub component Other {{\n  return (\n    <b>2</b>\n  )\n}}\n{}",
+            app
+        );
+        let after = compiler
+            .compile_str("main.ws", &source)
+            .unwrap()
+            .code
+            .clone();
+        assert!(after.contains("main$Other$create_fragment_0"));
+        let fragment = |code: &str| {
+            let start = code.find("function main$App$create_fragment_0").unwrap();
+            code[start..].split("\n}").next().unwrap().to_string()
+        };
+        assert_eq!(fragment(&before), fragment(&after));
+    }
+
+    #[test]
+    fn private_imports() {
+        let directory =
+            std::env::temp_dir().join(format!("private_imports_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        std::fs::write(
+            directory.join("utils.ws"),
+            "pub fn double(a: number) { a * 2 }\n\nfn helper() { 1 }\n",
+        )
+        .unwrap();
+        let main = directory.join("main.ws");
+        let mut compiler = Compiler::new();
+        std::fs::write(
+            &main,
+            "import utils.{double, helper}\nfn a() { helper() }\n",
+        )
+        .unwrap();
+        assert!(compiler.compile_path(&main).is_err());
+        let error = &compiler.diagnostics()[0].diagnostic;
+        assert_eq!(error.message(), "Private Definition");
+        assert!(error.to_json("").contains("utils.ws:3:1"));
+
+        std::fs::write(&main, "import utils.{double}\nfn a() { double(1) }\n").unwrap();
+        assert!(compiler.compile_path(&main).is_ok());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn write_libraries() {
+        let directory =
+            std::env::temp_dir().join(format!("write_libraries_{}", std::process::id()));
+        let root = directory.join("src");
+        let output = directory.join("lib");
+        let manifest = directory.join(".ws-manifest");
+        std::fs::create_dir_all(root.join("ui")).unwrap();
+        std::fs::write(
+            root.join("math.ws"),
+            "pub fn double(a: number) { return a * 2 }\n",
+        )
+        .unwrap();
+        std::fs::write(
+            root.join("ui/button.ws"),
+            "pub fn label() { return \"ok\" }\n",
+        )
+        .unwrap();
+        let mut compiler = Compiler::new();
+        let compile = |compiler: &mut Compiler, names: &[&str]| {
+            names
+                .iter()
+                .map(|name| {
+                    let path = root.join(name);
+                    compiler.compile_path(&path).unwrap();
+                    let module = compiler.db.compile_module(path.clone()).unwrap();
+                    (path, module)
+                })
+                .collect::<Vec<(PathBuf, Arc<CompiledModule>)>>()
+        };
+        let modules = compile(&mut compiler, &["math.ws", "ui/button.ws"]);
+        write_library(&compiler.db, &root, &modules, &output, &manifest).unwrap();
+        let index = std::fs::read_to_string(output.join("index.js")).unwrap();
+        assert!(index.contains("export {double} from './math.js';"));
+        assert!(index.contains("export {label} from './ui/button.js';"));
+        assert!(output.join("ui/button.js").exists());
+
+        // A module named `index` would be replaced by the index, and the
+        // index can't re-export the same name from two modules
+        std::fs::write(root.join("index.ws"), "pub fn main() { return 1 }\n").unwrap();
+        std::fs::write(
+            root.join("other.ws"),
+            "pub fn double(a: number) { return a + a }\n",
+        )
+        .unwrap();
+        let modules = compile(&mut compiler, &["math.ws", "index.ws", "other.ws"]);
+        let error = write_library(&compiler.db, &root, &modules, &output, &manifest).unwrap_err();
+        let messages: Vec<&str> = error
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| diagnostic.message())
+            .collect();
+        assert_eq!(
+            messages,
+            vec!["Module Conflicts With Index", "Duplicate Library Export"]
+        );
+        assert!(!output.join("other.js").exists());
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn feature_gates() {
+        let mut compiler = Compiler::new();
+        let source = "pub fn double(a: number) { return a * 2 }\npub fn twice(a: number) { return a |> double }\n";
+        let error = compiler.compile_str("main.ws", source).unwrap_err();
+        let diagnostic = &error.diagnostics()[0];
+        assert_eq!(diagnostic.message(), "Feature Not Enabled");
+        assert!(diagnostic.to_json("").contains("#feature(pipeline)"));
+
+        let enabled = format!("#feature(pipeline)\n{}", source);
+        let artifact = compiler.compile_str("main.ws", &enabled).unwrap();
+        assert!(artifact.code.contains("return double(a)"));
+
+        // Features can be enabled for every module too
+        let mut features = Features::default();
+        features.enable(Feature::Pipeline);
+        compiler.db.set_codegen_options(CodegenOptions {
+            features,
+            ..Default::default()
+        });
+        assert!(compiler.compile_str("main.ws", source).is_ok());
+
+        let error = compiler
+            .compile_str("main.ws", "#feature(pipelines)\nfn a() { return 1 }\n")
+            .unwrap_err();
+        assert_eq!(error.diagnostics()[0].message(), "Unknown Feature");
+    }
+
+    #[test]
+    fn constants_across_modules() {
+        let directory =
+            std::env::temp_dir().join(format!("constant_cycles_{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let main = directory.join("main.ws");
+        std::fs::write(directory.join("sizes.ws"), "pub const BASE = 4\n").unwrap();
+        std::fs::write(
+            &main,
+            "import sizes.{BASE}\npub const LARGE = BASE * 2\nconst HUGE = LARGE * 2\npub fn huge() { return HUGE }\n",
+        )
+        .unwrap();
+        let mut compiler = Compiler::new();
+        let code = &compiler.compile_path(&main).unwrap().code;
+        // Constants are defined after the ones they read
+        assert!(code.contains(
+            "import {BASE} from './sizes.js';
+export const LARGE = BASE * 2;
+const HUGE = LARGE * 2;"
+        ));
+
+        std::fs::write(
+            directory.join("sizes.ws"),
+            "import main.{LARGE}\npub const BASE = LARGE / 2\n",
+        )
+        .unwrap();
+        std::fs::write(&main, "import sizes.{BASE}\npub const LARGE = BASE * 2\n").unwrap();
+        assert!(compiler.compile_path(&main).is_err());
+        let error = &compiler.diagnostics()[0].diagnostic;
+        assert_eq!(error.message(), "Constant Cycle");
+        assert!(error
+            .to_json("")
+            .contains("It reads 'BASE' from sizes.ws, which reads 'LARGE'"));
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,73 @@
+//! Compares compiling a module's definitions one after the other with
+//! compiling them across threads, for modules with more and more components.
+//!
+//! Run with `cargo bench -p db --bench parallel`.
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use db::{Database, FileSystem, Parser};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+/// Components that each render a list with some state in it.
+fn many_components(components: usize) -> String {
+    let mut source = String::new();
+    for component in 0..components {
+        writeln!(
+            source,
+            "pub component Row{} {{\n  state count = {}\n  return (\n<ul>",
+            component, component
+        )
+        .unwrap();
+        for item in 0..20 {
+            writeln!(
+                source,
+                "<li class=\"item\"><span>{{count}}</span> item {} <b>{}</b></li>",
+                item, component
+            )
+            .unwrap();
+        }
+        source.push_str("</ul>\n  )\n}\n\n");
+    }
+    source
+}
+
+/// A database with nothing compiled yet, holding `source` at `path`.
+fn database(path: &Path, source: &str) -> Database {
+    let mut db = Database::default();
+    db.set_file_text(path.to_path_buf(), Arc::new(source.to_string()));
+    db
+}
+
+fn parallel(c: &mut Criterion) {
+    let path = PathBuf::from("bench.ws");
+    let mut group = c.benchmark_group("compile_module");
+    for components in [10, 50, 200] {
+        let source = many_components(components);
+        group.bench_with_input(
+            BenchmarkId::new("serial", components),
+            &source,
+            |b, source| {
+                b.iter_batched(
+                    || database(&path, source),
+                    |db| db.compile_module(path.clone()).unwrap(),
+                    BatchSize::SmallInput,
+                )
+            },
+        );
+        group.bench_with_input(
+            BenchmarkId::new("parallel", components),
+            &source,
+            |b, source| {
+                b.iter_batched(
+                    || database(&path, source),
+                    |db| db.compile_module_parallel(path.clone()).unwrap(),
+                    BatchSize::SmallInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parallel);
+criterion_main!(benches);
@@ -1,14 +1,39 @@
 use diagnostics::result::Result;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
-use parser::parser_::ParserDatabase;
+use common::symbol::SymbolScope;
+use parser::parser::ParserDatabase;
+use syntax::arena_pool::arena_stats;
 use vfs::FileSystemDatabase;
 
+pub mod project;
+
 ///////////////
 
 // Re-export traits
-pub use parser::parser_::Parser;
-pub use vfs::{FileId, FileSystem, Files};
+pub use codegen::{
+    clean_artifacts, format_graph, format_report_json, format_report_table, optimization_passes,
+    render_module, Cleaned, CodegenOptions, ComponentReport, GraphFormat, ReactivityGraph,
+};
+pub use common::passes::OptLevel;
+pub use common::target::Target;
+pub use diagnostics::events::{CompilerEventSink, HasEventSink, NullEventSink, Phase, WatchEvent};
+pub use lexer::{attribute_name_at, close_tag, indent_closing_brace};
+pub use parser::parser::{
+    write_library, CompiledModule, Parser, LIBRARY_OUTPUT_PATH, MANIFEST_PATH, OUTPUT_PATH,
+};
+pub use parser::lenses::{function_lenses, FunctionLens};
+pub use parser::ranges::{folding_ranges, selection_ranges, Fold, FoldKind};
+pub use parser::signature_help::{signature_help, Signature};
+pub use project::{ProjectConfig, PROJECT_FILE};
+pub use syntax::arena_pool::ArenaStats;
+pub use syntax::attributes::{Attribute, KNOWN_ATTRIBUTES};
+pub use syntax::events::{EventHandler, EVENT_HANDLERS};
+pub use syntax::features::{Edition, Feature, Features};
+pub use syntax::span::{utf16_line_column, utf16_offset, LineColumn};
+pub use vfs::{disk_changed, is_module, FileId, FileSystem, Files, Overlay};
+pub use vfs::paths;
 
 #[salsa::query_group(CompilerDatabase)]
 pub trait Compiler: Parser + FileSystem {
@@ -23,12 +48,89 @@ fn compile(db: &dyn Compiler, path: PathBuf) -> Result<Vec<usize>> {
 #[salsa::database(FileSystemDatabase, CompilerDatabase, ParserDatabase)]
 pub struct Database {
     storage: salsa::Storage<Self>,
+    event_sink: Arc<dyn CompilerEventSink>,
+}
+
+impl Database {
+    /// Create a database that reports compiler events to `event_sink`.
+    pub fn with_event_sink(event_sink: impl CompilerEventSink + 'static) -> Self {
+        let storage = salsa::Storage::default();
+        let mut db = Database {
+            storage,
+            event_sink: Arc::new(event_sink),
+        };
+        db.set_codegen_options(CodegenOptions::default());
+        db.set_overlay(Arc::new(Overlay::default()));
+        db
+    }
+
+    /// Use `text` for the file at `path` in place of what's on disk, like an
+    /// unsaved buffer in an editor, or go back to reading it from disk for
+    /// `None`.
+    pub fn set_overlay_file(&mut self, path: PathBuf, text: Option<Arc<String>>) {
+        let mut overlay = (*self.overlay()).clone();
+        match text {
+            Some(text) => overlay.insert(path, text),
+            None => overlay.remove(&path),
+        }
+        self.set_overlay(Arc::new(overlay));
+    }
+
+    /// Free the symbols interned while compiling `path`, like when a file is
+    /// closed or before it's rebuilt. Compiled output doesn't hold onto symbols,
+    /// so this doesn't invalidate anything, but it can't happen while the file
+    /// is being compiled.
+    pub fn release_symbols(&self, path: &Path) -> usize {
+        SymbolScope::new(path).release()
+    }
+
+    pub fn compilation_stats(&self) -> CompilationStats {
+        CompilationStats {
+            arenas: arena_stats(),
+        }
+    }
+
+    /// Compile each definition in `path` on its own thread, then put them
+    /// together with `compile_module`. Definitions are compiled separately,
+    /// so modules with many components compile faster this way, and the
+    /// output is the same since they're still put together in the order
+    /// they're written.
+    pub fn compile_module_parallel(&self, path: PathBuf) -> Result<Arc<CompiledModule>> {
+        use rayon::prelude::*;
+        use salsa::ParallelDatabase;
+        let chunks = self.definition_chunks(path.clone());
+        let mut names: Vec<&String> = chunks.iter().map(|chunk| &chunk.name).collect();
+        names.sort();
+        names.dedup();
+        let snapshots: Vec<_> = names.iter().map(|_| self.snapshot()).collect();
+        snapshots.into_par_iter().zip(names).for_each(|(db, name)| {
+            // Errors are reported once `compile_module` puts the
+            // definitions together
+            let _ = db.compile_definition(path.clone(), name.clone());
+        });
+        self.compile_module(path)
+    }
+}
+
+/// Numbers about the work the compiler's done, for watch mode and the
+/// language server to log.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompilationStats {
+    /// How the arenas modules are parsed into have been reused. Arenas are
+    /// kept for each thread rather than each database, so these are for the
+    /// whole process.
+    pub arenas: ArenaStats,
 }
 
 impl Default for Database {
     fn default() -> Self {
-        let storage = salsa::Storage::default();
-        Database { storage }
+        Database::with_event_sink(NullEventSink)
+    }
+}
+
+impl HasEventSink for Database {
+    fn event_sink(&self) -> &dyn CompilerEventSink {
+        &*self.event_sink
     }
 }
 
@@ -38,6 +140,39 @@ impl salsa::ParallelDatabase for Database {
     fn snapshot(&self) -> salsa::Snapshot<Self> {
         salsa::Snapshot::new(Database {
             storage: self.storage.snapshot(),
+            event_sink: self.event_sink.clone(),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Database, FileSystem, Parser};
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    #[test]
+    fn compile_definitions_in_parallel() {
+        let source: String = (0..20)
+            .map(|index| {
+                format!(
+                    "pub component Row{} {{\n  state count = {}\n  return <p class=\"row\">{{count}}</p>\n}}\n\n",
+                    index, index
+                )
+            })
+            .collect();
+        let path = PathBuf::from("rows.ws");
+        let compile = |parallel: bool| {
+            let mut db = Database::default();
+            db.set_file_text(path.clone(), Arc::new(source.clone()));
+            let compiled = if parallel {
+                db.compile_module_parallel(path.clone())
+            } else {
+                db.compile_module(path.clone())
+            };
+            compiled.unwrap()
+        };
+        // Definitions are put together in the order they're written
+        assert_eq!(compile(true), compile(false));
+    }
+}
@@ -0,0 +1,212 @@
+//! The `ws.toml` at the root of a project, which names the project and says
+//! how it's built.
+use codegen::CodegenOptions;
+use syntax::features::Feature;
+
+/// The file a project's configuration is read from, in its root directory.
+pub const PROJECT_FILE: &str = "ws.toml";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProjectConfig {
+    pub name: Option<String>,
+    /// The module to start compiling from, relative to the root
+    pub entry: Option<String>,
+    /// The options from the `[build]` section, over the defaults. The
+    /// edition and the `[features]` section are in `options.features`.
+    pub options: CodegenOptions,
+}
+
+impl ProjectConfig {
+    /// Read a project's configuration from the text of its `ws.toml`. Only
+    /// the part of TOML it needs is supported: sections, comments, and keys
+    /// set to strings, integers or booleans.
+    pub fn parse(text: &str) -> Result<ProjectConfig, String> {
+        let mut config = ProjectConfig::default();
+        let mut section = String::new();
+        for (index, line) in text.lines().enumerate() {
+            let error =
+                |message: String| format!("{}, line {}: {}", PROJECT_FILE, index + 1, message);
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(name) = line
+                .strip_prefix('[')
+                .and_then(|line| line.strip_suffix(']'))
+            {
+                section = name.trim().to_string();
+                continue;
+            }
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), parse_value(value.trim()).map_err(error)?),
+                None => return Err(error(format!("expected a key and value, found '{}'", line))),
+            };
+            config.set(&section, key, value).map_err(error)?;
+        }
+        Ok(config)
+    }
+
+    fn set(&mut self, section: &str, key: &str, value: Value) -> Result<(), String> {
+        let options = &mut self.options;
+        match (section, key) {
+            ("project", "name") => self.name = Some(value.string()?),
+            ("project", "entry") => self.entry = Some(value.string()?),
+            ("project", "edition") => options.features.edition = value.string()?.parse()?,
+            ("build", "target") => options.target = value.string()?.parse()?,
+            ("build", "opt_level") => options.opt_level = value.integer()?.to_string().parse()?,
+            ("build", "strip_logs") => options.strip_logs = value.boolean()?,
+            ("build", "allow_unused_parameters") => {
+                options.allow_unused_parameters = value.boolean()?
+            }
+            ("build", "warn_constant_conditions") => {
+                options.warn_constant_conditions = value.boolean()?
+            }
+            ("build", "deny_escaping_state_writes") => {
+                options.deny_escaping_state_writes = value.boolean()?
+            }
+            ("build", "hash_filenames") => options.hash_filenames = value.boolean()?,
+            // Like --max-errors, 0 reports every error
+            ("build", "max_errors") => {
+                options.max_errors = Some(value.integer()?).filter(|max| *max > 0)
+            }
+            ("features", name) => {
+                let feature = Feature::from_name(name)
+                    .ok_or_else(|| format!("unknown feature '{}'", name))?;
+                if value.boolean()? {
+                    options.features.enable(feature);
+                }
+            }
+            ("", key) => return Err(format!("'{}' isn't in a section", key)),
+            (section, key) => return Err(format!("unknown key '{}' in [{}]", key, section)),
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    String(String),
+    Integer(usize),
+    Boolean(bool),
+}
+
+impl Value {
+    fn string(self) -> Result<String, String> {
+        match self {
+            Value::String(value) => Ok(value),
+            value => Err(format!("expected a string, found {}", value.describe())),
+        }
+    }
+
+    fn integer(self) -> Result<usize, String> {
+        match self {
+            Value::Integer(value) => Ok(value),
+            value => Err(format!("expected an integer, found {}", value.describe())),
+        }
+    }
+
+    fn boolean(self) -> Result<bool, String> {
+        match self {
+            Value::Boolean(value) => Ok(value),
+            value => Err(format!(
+                "expected true or false, found {}",
+                value.describe()
+            )),
+        }
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            Value::String(value) => format!("\"{}\"", value),
+            Value::Integer(value) => value.to_string(),
+            Value::Boolean(value) => value.to_string(),
+        }
+    }
+}
+
+fn parse_value(value: &str) -> Result<Value, String> {
+    match value {
+        "true" => return Ok(Value::Boolean(true)),
+        "false" => return Ok(Value::Boolean(false)),
+        _ => {}
+    }
+    if let Some(string) = value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    {
+        return Ok(Value::String(string.to_string()));
+    }
+    value
+        .replace('_', "")
+        .parse()
+        .map(Value::Integer)
+        .map_err(|_| format!("unsupported value '{}'", value))
+}
+
+/// The part of a line before a `#` that's outside of a string.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (index, ch) in line.char_indices() {
+        match ch {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..index],
+            _ => {}
+        }
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProjectConfig;
+    use common::target::Target;
+    use syntax::features::{Edition, Feature};
+
+    #[test]
+    fn parse_project_config() {
+        let config = ProjectConfig::parse(
+            "# The project
+[project]
+name = \"counter\"
+edition = \"2021\"
+entry = \"src/app.ws\" # relative to the root
+
+[build]
+target = \"node\"
+warn_constant_conditions = true
+deny_escaping_state_writes = true
+max_errors = 5
+
+[features]
+pipeline = true
+effects = false
+",
+        )
+        .unwrap();
+        assert_eq!(config.name.as_deref(), Some("counter"));
+        assert_eq!(config.entry.as_deref(), Some("src/app.ws"));
+        assert_eq!(config.options.target, Target::Node);
+        assert!(config.options.warn_constant_conditions);
+        assert!(config.options.deny_escaping_state_writes);
+        assert_eq!(config.options.max_errors, Some(5));
+        assert_eq!(config.options.features.edition, Edition::Edition2021);
+        assert_eq!(config.options.features.enabled(), &[Feature::Pipeline]);
+
+        assert_eq!(
+            ProjectConfig::parse("[build]\nstrip_logs = \"yes\"").unwrap_err(),
+            "ws.toml, line 2: expected true or false, found \"yes\""
+        );
+        assert_eq!(
+            ProjectConfig::parse("[build]\ntarget = \"deno\"").unwrap_err(),
+            "ws.toml, line 2: unknown target 'deno', expected 'browser' or 'node'"
+        );
+        assert_eq!(
+            ProjectConfig::parse("[project]\nversion = 1").unwrap_err(),
+            "ws.toml, line 2: unknown key 'version' in [project]"
+        );
+        assert_eq!(
+            ProjectConfig::parse("[features]\nmacros = true").unwrap_err(),
+            "ws.toml, line 2: unknown feature 'macros'"
+        );
+    }
+}
@@ -0,0 +1,75 @@
+//! Deciding whether terminal output should have colors.
+use std::ffi::OsString;
+use std::io::{self, IsTerminal};
+use std::str::FromStr;
+
+/// When to use colors, like from `--color`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ColorMode {
+    Always,
+    Never,
+    /// Use colors when writing to a terminal, unless `NO_COLOR` is set.
+    #[default]
+    Auto,
+}
+
+impl FromStr for ColorMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "always" => Ok(ColorMode::Always),
+            "never" => Ok(ColorMode::Never),
+            "auto" => Ok(ColorMode::Auto),
+            _ => Err(format!(
+                "unknown color mode '{}', expected always, never or auto",
+                mode
+            )),
+        }
+    }
+}
+
+impl ColorMode {
+    /// Whether output written to stderr should have colors.
+    pub fn for_stderr(self) -> bool {
+        self.enabled(io::stderr().is_terminal())
+    }
+
+    /// Whether output written to stdout should have colors.
+    pub fn for_stdout(self) -> bool {
+        self.enabled(io::stdout().is_terminal())
+    }
+
+    /// Whether output written to a stream should have colors, given whether
+    /// the stream is a terminal.
+    pub fn enabled(self, is_terminal: bool) -> bool {
+        self.enabled_with(is_terminal, std::env::var_os("NO_COLOR"))
+    }
+
+    fn enabled_with(self, is_terminal: bool, no_color: Option<OsString>) -> bool {
+        match self {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            // An empty `NO_COLOR` doesn't count as being set
+            ColorMode::Auto => is_terminal && no_color.is_none_or(|value| value.is_empty()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ColorMode;
+
+    #[test]
+    fn decide_colors() {
+        let no_color = || Some("1".into());
+        assert!(ColorMode::Auto.enabled_with(true, None));
+        assert!(!ColorMode::Auto.enabled_with(false, None));
+        assert!(!ColorMode::Auto.enabled_with(true, no_color()));
+        assert!(ColorMode::Auto.enabled_with(true, Some("".into())));
+        // Asking for colors overrides `NO_COLOR`
+        assert!(ColorMode::Always.enabled_with(false, no_color()));
+        assert!(!ColorMode::Never.enabled_with(true, None));
+        assert_eq!("never".parse(), Ok(ColorMode::Never));
+    }
+}
@@ -4,6 +4,7 @@ use std::io;
 
 use codespan_reporting::diagnostic::LabelStyle;
 
+use crate::color::ColorMode;
 use crate::result::Result;
 use std::fmt::Display;
 use std::ops::Range;
@@ -13,6 +14,7 @@ const ILLEGAL_FUNCTION_CALLEE_TITLE: &str = "Illegal Function Call";
 const UNEXPECTED_CHARACTER_ERROR_TITLE: &str = "Unexpected Character";
 const EMPTY_TYPE_PARAMETERS: &str = "Type parameters cannot be empty";
 const UNKNOWN_REFERENCE: &str = "Unknown Reference";
+const INVALID_CONSTANT_OPERATION: &str = "Invalid Constant Operation";
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Label {
@@ -21,11 +23,20 @@ pub struct Label {
     style: LabelStyle,
 }
 
+/// How severe a diagnostic is. Errors stop compilation, warnings are
+/// reported but compilation continues.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Diagnostic {
     message: String,
     labels: Vec<Label>,
     notes: Option<Vec<String>>,
+    severity: Severity,
 }
 
 impl Diagnostic {
@@ -34,29 +45,164 @@ impl Diagnostic {
             message,
             labels,
             notes: None,
+            severity: Severity::Error,
+        }
+    }
+
+    pub fn warning(message: String, labels: Vec<Label>) -> Diagnostic {
+        Self {
+            message,
+            labels,
+            notes: None,
+            severity: Severity::Warning,
+        }
+    }
+
+    pub fn severity(&self) -> Severity {
+        self.severity
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Where the diagnostic's primary label points.
+    pub fn primary_range(&self) -> Option<Range<usize>> {
+        self.labels
+            .iter()
+            .find(|label| label.style == LabelStyle::Primary)
+            .map(|label| label.range.clone())
+    }
+
+    /// The message on the diagnostic's primary label.
+    pub fn primary_message(&self) -> Option<&str> {
+        self.labels
+            .iter()
+            .find(|label| label.style == LabelStyle::Primary)
+            .map(|label| label.message.as_str())
+    }
+
+    /// Where each of the diagnostic's labels points, in the order they
+    /// were added.
+    pub fn ranges(&self) -> Vec<Range<usize>> {
+        self.labels
+            .iter()
+            .map(|label| label.range.clone())
+            .collect()
+    }
+
+    /// Move every label with `map`, for diagnostics reported against a
+    /// different text than the one they'll be shown with.
+    pub fn map_ranges(mut self, map: impl Fn(Range<usize>) -> Range<usize>) -> Self {
+        for label in &mut self.labels {
+            label.range = map(label.range.clone());
         }
+        self
+    }
+
+    /// Encode the diagnostic as a JSON object, with the line and column
+    /// each label starts and ends on in `source`. Lines and columns start
+    /// at 1, and ranges are byte offsets with an exclusive end.
+    pub fn to_json(&self, source: &str) -> String {
+        use crate::events::json_string;
+        let position = |offset: usize| {
+            let before = &source[..offset.min(source.len())];
+            let line = before.matches('\n').count() + 1;
+            let column = before.rsplit('\n').next().unwrap().chars().count() + 1;
+            format!("{{\"line\": {}, \"column\": {}}}", line, column)
+        };
+        let labels: Vec<String> = self
+            .labels
+            .iter()
+            .map(|label| {
+                format!(
+                    "{{\"message\": {}, \"primary\": {}, \"range\": [{}, {}], \"start\": {}, \"end\": {}}}",
+                    json_string(&label.message),
+                    label.style == LabelStyle::Primary,
+                    label.range.start,
+                    label.range.end,
+                    position(label.range.start),
+                    position(label.range.end)
+                )
+            })
+            .collect();
+        let notes: Vec<String> = self
+            .notes
+            .iter()
+            .flatten()
+            .map(|note| json_string(note))
+            .collect();
+        let severity = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        format!(
+            "{{\"severity\": \"{}\", \"message\": {}, \"labels\": [{}], \"notes\": [{}]}}",
+            severity,
+            json_string(&self.message),
+            labels.join(", "),
+            notes.join(", ")
+        )
     }
 
     fn with_note(self, note: impl Into<String>) -> Self {
         let mut notes = self.notes.unwrap_or_default();
         notes.push(note.into());
         Self {
-            message: self.message,
-            labels: self.labels,
             notes: Some(notes),
+            ..self
         }
     }
 }
 
 /// Takes an instance of our own `Diagnostic` and converts it to the `codespan_reporting` variant
-/// so we can report the error in the terminal.
-pub fn report_diagnostic_to_term(diagnostic: Diagnostic, file_name: &str, file_source: &str) {
+/// so we can report the error in the terminal. `color` decides whether it has colors.
+pub fn report_diagnostic_to_term(
+    diagnostic: Diagnostic,
+    file_name: &str,
+    file_source: &str,
+    color: ColorMode,
+) {
+    use codespan_reporting::term::termcolor::{ColorChoice, StandardStream};
+    let choice = if color.for_stderr() {
+        ColorChoice::Always
+    } else {
+        ColorChoice::Never
+    };
+    let writer = StandardStream::stderr(choice);
+    let mut writer = writer.lock();
+    emit_diagnostic(diagnostic, file_name, file_source, &mut writer)
+}
+
+/// Render a diagnostic the way `report_diagnostic_to_term` prints it, with
+/// colors if `color` is set.
+pub fn render_diagnostic(
+    diagnostic: Diagnostic,
+    file_name: &str,
+    file_source: &str,
+    color: bool,
+) -> String {
+    use codespan_reporting::term::termcolor::Buffer;
+    let mut buffer = if color {
+        Buffer::ansi()
+    } else {
+        Buffer::no_color()
+    };
+    emit_diagnostic(diagnostic, file_name, file_source, &mut buffer);
+    String::from_utf8_lossy(buffer.as_slice()).into_owned()
+}
+
+fn emit_diagnostic(
+    diagnostic: Diagnostic,
+    file_name: &str,
+    file_source: &str,
+    writer: &mut dyn codespan_reporting::term::termcolor::WriteColor,
+) {
     use codespan_reporting::diagnostic::{
         Diagnostic as CodespanDiagnostic, Label as CodespanLabel,
     };
     use codespan_reporting::files::SimpleFiles;
     use codespan_reporting::term;
-    use codespan_reporting::term::termcolor::{ColorChoice, StandardStream};
     let mut files = SimpleFiles::new();
     // Map our diagnostic to the codespan structures
     let diagnostic = {
@@ -65,15 +211,15 @@ pub fn report_diagnostic_to_term(diagnostic: Diagnostic, file_name: &str, file_s
             .labels
             .iter()
             .map(|label| {
-                // We track ranges as fully inclusive as that is easier for lexing,
-                // but technically `std::ops::Range` in Rust is only inclusive for
-                // the start of the range. We shift the end of the range out by one
-                // to account for this.
-                let range = label.range.start..label.range.end + 1;
-                CodespanLabel::new(label.style, id, range).with_message(label.message.clone())
+                CodespanLabel::new(label.style, id, label.range.clone())
+                    .with_message(label.message.clone())
             })
             .collect();
-        let mut csp_diagnostic = CodespanDiagnostic::error()
+        let csp_diagnostic = match diagnostic.severity {
+            Severity::Error => CodespanDiagnostic::error(),
+            Severity::Warning => CodespanDiagnostic::warning(),
+        };
+        let mut csp_diagnostic = csp_diagnostic
             .with_message(diagnostic.message)
             .with_labels(labels);
         if let Some(notes) = diagnostic.notes {
@@ -81,34 +227,54 @@ pub fn report_diagnostic_to_term(diagnostic: Diagnostic, file_name: &str, file_s
         }
         csp_diagnostic
     };
-    let writer = StandardStream::stderr(ColorChoice::Always);
-    let mut writer = writer.lock();
     let config = codespan_reporting::term::Config::default();
-    term::emit(&mut writer, &config, &files, &diagnostic).unwrap()
+    term::emit(writer, &config, &files, &diagnostic).unwrap()
 }
 
 /// Report an unexpected token error for the parser
+/// Report the token at `span`, where `expected` should have been. `prev_span`
+/// is the token before it, if there is one.
 pub fn unexpected_token_error<T>(
     span: impl Into<Range<usize>>,
-    prev_span: impl Into<Range<usize>>,
+    prev_span: Option<impl Into<Range<usize>>>,
     expected: impl Display,
     found: impl Display,
+) -> Result<T> {
+    let mut labels = vec![Label {
+        message: format!("Expected '{}' but found '{}'", expected, found),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    }];
+    if let Some(prev_span) = prev_span {
+        labels.push(Label {
+            message: format!("'{}' should come after this", expected),
+            range: prev_span.into(),
+            style: LabelStyle::Secondary,
+        });
+    }
+    let diagnostic = Diagnostic::error(UNEXPECTED_TOKEN_ERROR_TITLE.into(), labels);
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
+/// Report a statement that starts on the same line another one ends on
+pub fn missing_line_break<T>(
+    span: impl Into<Range<usize>>,
+    prev_span: impl Into<Range<usize>>,
 ) -> Result<T> {
     let label = Label {
-        message: format!("but found '{}' instead", found),
+        message: "Expected this to start a new line".into(),
         range: span.into(),
-        style: LabelStyle::Secondary,
+        style: LabelStyle::Primary,
     };
-
     let prev_label = Label {
-        message: format!("Expected '{}' after this", expected),
+        message: "The statement before it ends here".into(),
         range: prev_span.into(),
-        style: LabelStyle::Primary,
+        style: LabelStyle::Secondary,
     };
-
-    let diagnostic =
-        Diagnostic::error(UNEXPECTED_TOKEN_ERROR_TITLE.into(), vec![prev_label, label]);
-    Err(crate::error::Error::Diagnostic(diagnostic))
+    Err(Error::Diagnostic(
+        Diagnostic::error("Missing Line Break".into(), vec![label, prev_label])
+            .with_note("Statements end at the end of their line, or at a ';'"),
+    ))
 }
 
 /// Report an unexpected token error for the parser
@@ -183,6 +349,112 @@ pub fn expected_identifier<T>(span: impl Into<Range<usize>>, found: impl Display
     Err(crate::error::Error::Diagnostic(diagnostic))
 }
 
+/// Report a keyword that's used where a name was expected, like `let type = 1`
+pub fn reserved_keyword<T>(span: impl Into<Range<usize>>, keyword: impl Display) -> Result<T> {
+    let label = Label {
+        message: format!(
+            "'{}' is a reserved keyword, so it can't be used as a name",
+            keyword
+        ),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    let diagnostic = Diagnostic::error("Reserved Keyword".into(), vec![label]).with_note(format!(
+        "Try another name, like '{}_' or a more specific one",
+        keyword
+    ));
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
+/// Report a type annotation that isn't a type
+pub fn expected_type<T>(span: impl Into<Range<usize>>, found: impl Display) -> Result<T> {
+    let label = Label {
+        message: format!("Expected a type but found '{}'", found),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    let diagnostic = Diagnostic::error(UNEXPECTED_TOKEN_ERROR_TITLE.into(), vec![label]);
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
+pub fn recursive_type_alias<T>(
+    span: impl Into<Range<usize>>,
+    name: impl Display,
+    alias_span: impl Into<Range<usize>>,
+) -> Result<T> {
+    let label = Label {
+        message: format!("'{}' refers to itself here", name),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    let alias_label = Label {
+        message: format!("While defining '{}'", name),
+        range: alias_span.into(),
+        style: LabelStyle::Secondary,
+    };
+    let diagnostic = Diagnostic::error("Recursive Type Alias".into(), vec![label, alias_label])
+        .with_note("An alias is expanded where it's used, so it can't contain itself");
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
+/// Report syntax that parses but isn't supported yet, like enums
+pub fn unsupported_syntax<T>(span: impl Into<Range<usize>>, name: impl Display) -> Result<T> {
+    let label = Label {
+        message: format!("{} aren't supported yet", name),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    let diagnostic = Diagnostic::error("Unsupported Syntax".into(), vec![label]);
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
+/// Report experimental syntax in a module that doesn't enable its feature
+pub fn feature_not_enabled<T>(
+    span: impl Into<Range<usize>>,
+    description: impl Display,
+    feature: impl Display,
+) -> Result<T> {
+    let label = Label {
+        message: format!("{} are experimental", description),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    let diagnostic = Diagnostic::error("Feature Not Enabled".into(), vec![label]).with_note(
+        format!(
+            "Add '#feature({})' to the top of the module, or '{} = true' to the [features] section of ws.toml",
+            feature, feature
+        ),
+    );
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
+/// Report a `#feature(...)` line naming a feature that doesn't exist
+pub fn unknown_feature(
+    span: impl Into<Range<usize>>,
+    name: impl Display,
+    features: &[&str],
+) -> Diagnostic {
+    let label = Label {
+        message: format!("There's no feature named '{}'", name),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    Diagnostic::error("Unknown Feature".into(), vec![label])
+        .with_note(format!("The features are {}", features.join(", ")))
+}
+
+/// Report code that's nested too deeply for the parser to handle
+pub fn nesting_too_deep<T>(span: impl Into<Range<usize>>) -> Result<T> {
+    let label = Label {
+        message: "This is nested too deeply".into(),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    let diagnostic = Diagnostic::error("Nesting Too Deep".into(), vec![label])
+        .with_note("Try moving some of it into a separate function or component");
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
 /// Report an invalid character
 pub fn invalid_character<T>(span: impl Into<Range<usize>>) -> Result<T> {
     let label = Label {
@@ -263,6 +535,47 @@ pub fn duplicate_wildcard_error<T>(
     Err(crate::error::Error::Diagnostic(diagnostic))
 }
 
+/// Report a parameter with the same name as one before it
+pub fn duplicate_parameter<T>(
+    first: impl Into<Range<usize>>,
+    second: impl Into<Range<usize>>,
+    name: impl Display,
+) -> Result<T> {
+    let primary = Label {
+        message: format!("There's already a parameter named '{}'", name),
+        range: second.into(),
+        style: LabelStyle::Primary,
+    };
+    let secondary = Label {
+        message: "It's defined here".into(),
+        range: first.into(),
+        style: LabelStyle::Secondary,
+    };
+    let diagnostic = Diagnostic::error("Duplicate Parameter".into(), vec![primary, secondary])
+        .with_note("Each parameter needs its own name");
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
+/// Report an enum variant with the same name as one before it
+pub fn duplicate_variant<T>(
+    first: impl Into<Range<usize>>,
+    second: impl Into<Range<usize>>,
+    name: impl Display,
+) -> Result<T> {
+    let primary = Label {
+        message: format!("There's already a variant named '{}'", name),
+        range: second.into(),
+        style: LabelStyle::Primary,
+    };
+    let secondary = Label {
+        message: "It's defined here".into(),
+        range: first.into(),
+        style: LabelStyle::Secondary,
+    };
+    let diagnostic = Diagnostic::error("Duplicate Variant".into(), vec![primary, secondary]);
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
 pub fn unreachable_match_case<T>(
     span: impl Into<Range<usize>>,
     wildcard_span: impl Into<Range<usize>>,
@@ -285,128 +598,878 @@ pub fn unreachable_match_case<T>(
     Err(crate::error::Error::Diagnostic(diagnostic))
 }
 
-pub fn invalid_effect_reference<T>(span: impl Into<Range<usize>>, name: impl Display) -> Result<T> {
+pub fn non_exhaustive_match<T>(
+    span: impl Into<Range<usize>>,
+    missing: Vec<impl Display>,
+) -> Result<T> {
+    let missing = missing
+        .iter()
+        .map(|pattern| format!("'{}'", pattern))
+        .collect::<Vec<String>>()
+        .join(", ");
     let label = Label {
-        message: format!("'{}' is an effect, but is being referenced as type", name),
+        message: format!("This match doesn't handle {}", missing),
         range: span.into(),
         style: LabelStyle::Primary,
     };
-    let diagnostic = Diagnostic::error("Invalid Effect Reference".into(), vec![label]);
+    let diagnostic = Diagnostic::error("Non-exhaustive Match".into(), vec![label])
+        .with_note("Add the missing cases, or a wildcard '_' case to handle them");
     Err(crate::error::Error::Diagnostic(diagnostic))
 }
 
-pub fn invalid_await<T>(span: impl Into<Range<usize>>) -> Result<T> {
+pub fn variant_pattern_arity<T>(
+    span: impl Into<Range<usize>>,
+    name: impl Display,
+    expected: usize,
+    found: usize,
+) -> Result<T> {
     let label = Label {
-        message: "You can only use 'await' in an async function".into(),
+        message: format!(
+            "'{}' has {} value(s), but this pattern binds {}",
+            name, expected, found
+        ),
         range: span.into(),
         style: LabelStyle::Primary,
     };
-    let diagnostic = Diagnostic::error("Invalid Await".into(), vec![label]);
+    let diagnostic = Diagnostic::error("Invalid Variant Pattern".into(), vec![label]);
     Err(crate::error::Error::Diagnostic(diagnostic))
 }
 
-/// Report an empty type parameter list
-pub fn empty_type_parameters<T>(span: impl Into<Range<usize>>) -> Result<T> {
+/// Report a variant that's created with the wrong number of values
+pub fn variant_arity<T>(
+    span: impl Into<Range<usize>>,
+    name: impl Display,
+    expected: usize,
+    found: usize,
+) -> Result<T> {
     let label = Label {
-        message: "".into(),
+        message: format!(
+            "'{}' has {} value(s), but this gives it {}",
+            name, expected, found
+        ),
         range: span.into(),
         style: LabelStyle::Primary,
     };
-    Err(Error::Diagnostic(Diagnostic::error(
-        EMPTY_TYPE_PARAMETERS.into(),
-        vec![label],
-    )))
+    let diagnostic = Diagnostic::error("Invalid Variant".into(), vec![label]);
+    Err(crate::error::Error::Diagnostic(diagnostic))
 }
 
-pub fn dot_after_import_list<T>(span: impl Into<Range<usize>>) -> Result<T> {
+pub fn missing_else_branch<T>(span: impl Into<Range<usize>>) -> Result<T> {
     let label = Label {
-        message: "".into(),
+        message: "This if-expression needs an else branch".into(),
         range: span.into(),
         style: LabelStyle::Primary,
     };
-    Err(Error::Diagnostic(Diagnostic::error(
-        "Cannot use identifier imports after lists".into(),
-        vec![label],
-    )))
+    let diagnostic = Diagnostic::error("Missing Else Branch".into(), vec![label])
+        .with_note("An if-expression must produce a value no matter which branch is taken");
+    Err(crate::error::Error::Diagnostic(diagnostic))
 }
 
-pub fn positional_argument_after_named<T>(
+pub fn mismatched_branch_types<T>(
+    then_span: impl Into<Range<usize>>,
+    then_type: impl Display,
+    else_span: impl Into<Range<usize>>,
+    else_type: impl Display,
+) -> Result<T> {
+    let then_label = Label {
+        message: format!("This branch is a {}", then_type),
+        range: then_span.into(),
+        style: LabelStyle::Primary,
+    };
+    let else_label = Label {
+        message: format!("But this branch is a {}", else_type),
+        range: else_span.into(),
+        style: LabelStyle::Primary,
+    };
+    let diagnostic = Diagnostic::error(
+        "Mismatched Branch Types".into(),
+        vec![then_label, else_label],
+    );
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
+pub fn mismatched_array_elements<T>(
+    first_span: impl Into<Range<usize>>,
+    first_type: impl Display,
     span: impl Into<Range<usize>>,
-    last_arg_span: impl Into<Range<usize>>,
+    type_: impl Display,
 ) -> Result<T> {
     let label = Label {
-        message: "this is using a positional argument".into(),
+        message: format!("This element is a {}", type_),
         range: span.into(),
         style: LabelStyle::Primary,
     };
-    let arg_span = Label {
-        message: "a named argument was already used".into(),
-        range: last_arg_span.into(),
+    let first_label = Label {
+        message: format!("But the first element is a {}", first_type),
+        range: first_span.into(),
         style: LabelStyle::Secondary,
     };
-    Err(Error::Diagnostic(Diagnostic::error(
-        "Positional arguments cannot be mixed with named arguments".into(),
-        vec![label, arg_span],
-    )))
+    let diagnostic =
+        Diagnostic::error("Mismatched Array Elements".into(), vec![label, first_label])
+            .with_note("Every element of an array has to be the same type");
+    Err(crate::error::Error::Diagnostic(diagnostic))
 }
 
-pub fn named_argument_after_positional<T>(
+pub fn assignment_to_immutable<T>(
     span: impl Into<Range<usize>>,
-    last_arg_span: impl Into<Range<usize>>,
+    name: impl Display,
+    definition_span: impl Into<Range<usize>>,
 ) -> Result<T> {
     let label = Label {
-        message: "this is using a named argument".into(),
+        message: format!("Can't assign to '{}' because it's immutable", name),
         range: span.into(),
         style: LabelStyle::Primary,
     };
-    let arg_span = Label {
-        message: "a positional argument was already used".into(),
-        range: last_arg_span.into(),
+    let definition_label = Label {
+        message: format!("'{}' is defined here", name),
+        range: definition_span.into(),
         style: LabelStyle::Secondary,
     };
-    Err(Error::Diagnostic(Diagnostic::error(
-        "Named arguments cannot be mixed with positional arguments".into(),
-        vec![label, arg_span],
-    )))
+    let diagnostic = Diagnostic::error(
+        "Assignment To Immutable Binding".into(),
+        vec![label, definition_label],
+    )
+    .with_note("Only 'state' and 'let mut' bindings can be assigned to");
+    Err(crate::error::Error::Diagnostic(diagnostic))
 }
 
-pub fn unexpected_token_for_expression<T>(
+pub fn expected_context<T>(
     span: impl Into<Range<usize>>,
-    prev_span: impl Into<Range<usize>>,
+    name: impl Display,
+    definition_span: impl Into<Range<usize>>,
 ) -> Result<T> {
     let label = Label {
-        message: format!(
-            "Tried to parse an expression starting here, but this token isn't allowed",
-        ),
+        message: format!("Expected a context, but '{}' isn't one", name),
         range: span.into(),
         style: LabelStyle::Primary,
     };
-    let prev_label = Label {
-        message: "Something might be missing after this?".into(),
-        range: prev_span.into(),
+    let definition_label = Label {
+        message: format!("'{}' is defined here", name),
+        range: definition_span.into(),
         style: LabelStyle::Secondary,
     };
-    Err(Error::Diagnostic(Diagnostic::error(
-        "Unexpected token for expression".into(),
-        vec![label, prev_label],
-    )))
+    let diagnostic = Diagnostic::error("Expected Context".into(), vec![label, definition_label])
+        .with_note("Contexts are defined at the top level, like 'context Theme = \"light\"'");
+    Err(crate::error::Error::Diagnostic(diagnostic))
 }
 
-pub fn unused_function<T>(span: impl Into<Range<usize>>) -> Result<T> {
+pub fn context_reference<T>(span: impl Into<Range<usize>>, name: impl Display) -> Result<T> {
     let label = Label {
-        message: "This function is unused".into(),
+        message: format!(
+            "'{}' is a context, so it can't be referenced directly",
+            name
+        ),
         range: span.into(),
-        style: LabelStyle::Secondary,
+        style: LabelStyle::Primary,
     };
-    Err(Error::Diagnostic(Diagnostic::error(
-        "Unused Function".into(),
-        vec![label],
-    )))
+    let diagnostic = Diagnostic::error("Context Used As Value".into(), vec![label])
+        .with_note(format!("Read the value of the context with 'use {}'", name));
+    Err(crate::error::Error::Diagnostic(diagnostic))
 }
 
-pub fn unreachable_code<T>(span: impl Into<Range<usize>>) -> Result<T> {
+pub fn global_unavailable<T>(
+    span: impl Into<Range<usize>>,
+    name: impl Display,
+    target: impl Display,
+    available_in: impl Display,
+) -> Result<T> {
     let label = Label {
-        message: "This code is unreachable".into(),
+        message: format!("'{}' isn't defined when compiling for {}", name, target),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    let diagnostic =
+        Diagnostic::error("Global Not Available".into(), vec![label]).with_note(format!(
+            "'{}' is only defined for {}, build with '--target {}' to use it",
+            name, available_in, available_in
+        ));
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
+pub fn component_without_dom<T>(
+    span: impl Into<Range<usize>>,
+    name: impl Display,
+    target: impl Display,
+) -> Result<T> {
+    let label = Label {
+        message: format!("'{}' can't be compiled for {}", name, target),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    let diagnostic = Diagnostic::error("Component Needs A DOM".into(), vec![label]).with_note(
+        format!(
+            "Components render to the DOM, which {} doesn't have. Build for the browser with '--target browser'",
+            target
+        ),
+    );
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
+pub fn named_log_argument<T>(span: impl Into<Range<usize>>, name: impl Display) -> Result<T> {
+    let label = Label {
+        message: format!("'{}' is named, but log arguments can't be", name),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    let diagnostic = Diagnostic::error("Named Log Argument".into(), vec![label]);
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
+pub fn log_format_arguments<T>(
+    span: impl Into<Range<usize>>,
+    placeholders: usize,
+    arguments: usize,
+) -> Result<T> {
+    let plural = |count: usize, noun: &str| match count {
+        1 => format!("1 {}", noun),
+        count => format!("{} {}s", count, noun),
+    };
+    let label = Label {
+        message: format!(
+            "This has {}, but it's followed by {}",
+            plural(placeholders, "placeholder"),
+            plural(arguments, "argument")
+        ),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    let diagnostic = Diagnostic::error("Wrong Number Of Log Arguments".into(), vec![label])
+        .with_note("Each '{}' in a format string is replaced with the next argument");
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
+pub fn invalid_log_format<T>(span: impl Into<Range<usize>>) -> Result<T> {
+    let label = Label {
+        message: "This format string has a brace that isn't part of a placeholder".into(),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    let diagnostic = Diagnostic::error("Invalid Log Format".into(), vec![label])
+        .with_note("Placeholders are written '{}', and braces are escaped as '{{' and '}}'");
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
+pub fn context_outside_component<T>(
+    span: impl Into<Range<usize>>,
+    keyword: impl Display,
+) -> Result<T> {
+    let label = Label {
+        message: format!("'{}' can only be used in the body of a component", keyword),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    let diagnostic = Diagnostic::error("Context Outside Component".into(), vec![label])
+        .with_note("Contexts are passed down through the components that are mounted, so they aren't available in functions");
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
+pub fn state_outside_component<T>(span: impl Into<Range<usize>>) -> Result<T> {
+    let label = Label {
+        message: "State can only be declared in the body of a component".into(),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    let diagnostic = Diagnostic::error("State Outside Component".into(), vec![label])
+        .with_note("Use 'let' for values that don't need to update what a component renders");
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
+pub fn unused_value(span: impl Into<Range<usize>>, type_: impl Display) -> Diagnostic {
+    let label = Label {
+        message: format!("This {} is never used", type_),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    Diagnostic::warning("Unused Value".into(), vec![label])
+        .with_note("Assign it to '_' if it's unused on purpose, like '_ = value'")
+}
+
+pub fn unrendered_template(span: impl Into<Range<usize>>) -> Diagnostic {
+    let label = Label {
+        message: "This template is never rendered".into(),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    Diagnostic::warning("Unrendered Template".into(), vec![label])
+        .with_note("Components only render the template they return, or the one they end with")
+}
+
+pub fn unreachable_template(span: impl Into<Range<usize>>) -> Diagnostic {
+    let label = Label {
+        message: "This template can't be rendered".into(),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    Diagnostic::warning("Unreachable Template".into(), vec![label])
+        .with_note("The component has already returned on every path before it")
+}
+
+/// Warn about an if statement whose condition is always `value`. `removed`
+/// is the code in the branches that never run, when there is any.
+pub fn constant_condition(
+    span: impl Into<Range<usize>>,
+    value: bool,
+    removed: Option<Range<usize>>,
+) -> Diagnostic {
+    let mut labels = vec![Label {
+        message: format!("This condition is always {}", value),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    }];
+    if let Some(removed) = removed {
+        labels.push(Label {
+            message: "So this never runs, and is left out of the output".into(),
+            range: removed,
+            style: LabelStyle::Secondary,
+        });
+    }
+    Diagnostic::warning("Constant Condition".into(), labels)
+        .with_note("The condition is decided when compiling, so only one branch is compiled")
+}
+
+pub fn state_never_read(span: impl Into<Range<usize>>, name: impl Display) -> Diagnostic {
+    let label = Label {
+        message: format!("'{}' is never read", name),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    Diagnostic::warning("Unread State".into(), vec![label]).with_note(
+        "Nothing depends on this state, so updating it doesn't change what the component renders",
+    )
+}
+
+pub fn state_never_written(span: impl Into<Range<usize>>, name: impl Display) -> Diagnostic {
+    let label = Label {
+        message: format!("'{}' is never written to", name),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    Diagnostic::warning("Unwritten State".into(), vec![label])
+        .with_note("Use 'let' for values that don't need to update what a component renders")
+}
+
+pub fn unused_parameter(span: impl Into<Range<usize>>, name: impl Display) -> Diagnostic {
+    let label = Label {
+        message: format!("'{}' is never used", name),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    Diagnostic::warning("Unused Parameter".into(), vec![label])
+        .with_note(format!("Name it '_{}' if it's unused on purpose", name))
+}
+
+/// `function` is the top-level function the template is in, if there is
+/// one, which could be made into a component instead.
+pub fn template_outside_component<T>(
+    span: impl Into<Range<usize>>,
+    function: Option<(impl Display, Range<usize>)>,
+) -> Result<T> {
+    let mut labels = vec![Label {
+        message: "Templates can only be used in the body of a component".into(),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    }];
+    let note = match function {
+        Some((name, function_span)) => {
+            labels.push(Label {
+                message: format!("'{}' is a function, not a component", name),
+                range: function_span,
+                style: LabelStyle::Secondary,
+            });
+            format!(
+                "Declare it with 'component {}' instead of 'fn {}' to render the template",
+                name, name
+            )
+        }
+        None => "Templates are mounted by the component that renders them, so functions can't return them".into(),
+    };
+    let diagnostic = Diagnostic::error("Template Outside Component".into(), labels).with_note(note);
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
+/// Unlike most diagnostics this can be reported as a warning, so it's
+/// returned directly instead of as an `Err`.
+pub fn escaping_state_write(
+    span: impl Into<Range<usize>>,
+    name: impl Display,
+    function_span: impl Into<Range<usize>>,
+    severity: Severity,
+) -> Diagnostic {
+    let label = Label {
+        message: format!("'{}' is written to here", name),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    let function_label = Label {
+        message: "But this function isn't an event handler, so it may run outside of the component"
+            .into(),
+        range: function_span.into(),
+        style: LabelStyle::Secondary,
+    };
+    let labels = vec![label, function_label];
+    let message = "State Written Outside Of Component".to_string();
+    let diagnostic = match severity {
+        Severity::Error => Diagnostic::error(message, labels),
+        Severity::Warning => Diagnostic::warning(message, labels),
+    };
+    diagnostic.with_note(
+        "State should only be written in the component body or in event handlers declared in it",
+    )
+}
+
+pub fn void_element_children<T>(
+    open_tag_span: impl Into<Range<usize>>,
+    close_tag_span: impl Into<Range<usize>>,
+    name: impl Display,
+) -> Result<T> {
+    let label = Label {
+        message: format!("'{}' is a void element, so it can't have children", name),
+        range: open_tag_span.into(),
+        style: LabelStyle::Primary,
+    };
+    let close_tag_label = Label {
+        message: "But it has children before it's closed here".into(),
+        range: close_tag_span.into(),
+        style: LabelStyle::Secondary,
+    };
+    let diagnostic = Diagnostic::error(
+        "Children In Void Element".into(),
+        vec![label, close_tag_label],
+    )
+    .with_note(format!("Try a self-closing tag instead, like <{} />", name));
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
+pub fn mismatched_close_tag<T>(
+    open_tag_span: impl Into<Range<usize>>,
+    open_tag_name: impl Display,
+    close_tag_span: impl Into<Range<usize>>,
+    close_tag_name: impl Display,
+) -> Result<T> {
+    let open_tag_label = Label {
+        message: format!("'{}' is opened here", open_tag_name),
+        range: open_tag_span.into(),
+        style: LabelStyle::Secondary,
+    };
+    let close_tag_label = Label {
+        message: format!(
+            "But it's closed with '{}' instead of '{}'",
+            close_tag_name, open_tag_name
+        ),
+        range: close_tag_span.into(),
+        style: LabelStyle::Primary,
+    };
+    let diagnostic = Diagnostic::error(
+        "Mismatched Close Tag".into(),
+        vec![close_tag_label, open_tag_label],
+    );
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
+pub fn missing_close_tag<T>(
+    open_tag_span: impl Into<Range<usize>>,
+    open_tag_name: impl Display,
+    span: impl Into<Range<usize>>,
+) -> Result<T> {
+    let open_tag_label = Label {
+        message: format!("'{}' is opened here", open_tag_name),
+        range: open_tag_span.into(),
+        style: LabelStyle::Primary,
+    };
+    let label = Label {
+        message: format!("Expected a '</{}>' close tag before this", open_tag_name),
+        range: span.into(),
+        style: LabelStyle::Secondary,
+    };
+    let diagnostic = Diagnostic::error("Missing Close Tag".into(), vec![open_tag_label, label])
+        .with_note(format!(
+            "Close the element with '</{}>', or use a self-closing tag like <{} />",
+            open_tag_name, open_tag_name
+        ));
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
+/// Report a `{` in a template that isn't closed before the template continues.
+pub fn unclosed_template_expression<T>(
+    open_brace_span: impl Into<Range<usize>>,
+    span: impl Into<Range<usize>>,
+) -> Result<T> {
+    let open_brace_label = Label {
+        message: "This '{' is never closed".into(),
+        range: open_brace_span.into(),
+        style: LabelStyle::Primary,
+    };
+    let label = Label {
+        message: "Expected a '}' before this".into(),
+        range: span.into(),
+        style: LabelStyle::Secondary,
+    };
+    let diagnostic = Diagnostic::error(
+        "Unclosed Template Expression".into(),
+        vec![open_brace_label, label],
+    );
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
+pub fn invalid_attribute_value<T>(
+    span: impl Into<Range<usize>>,
+    attribute_name: impl Display,
+    found: impl Display,
+) -> Result<T> {
+    let label = Label {
+        message: format!(
+            "'{}' can't be used as the value of '{}'",
+            found, attribute_name
+        ),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    let diagnostic =
+        Diagnostic::error("Invalid Attribute Value".into(), vec![label]).with_note(format!(
+            "Use a string, true or false, or wrap an expression in braces like {}={{value}}",
+            attribute_name
+        ));
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
+pub fn incompatible_event_handler<T>(
+    span: impl Into<Range<usize>>,
+    event: impl Display,
+    expected: impl Display,
+    found: impl Display,
+) -> Result<T> {
+    let expected = expected.to_string();
+    let label = Label {
+        message: format!(
+            "This handler takes {}, but '{}' events are {}",
+            with_article(found),
+            event,
+            with_article(&expected)
+        ),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    let note = if expected == "Event" {
+        "Handlers can take an Event, or nothing at all".to_string()
+    } else {
+        format!(
+            "Handlers can take {} or an Event, or nothing at all",
+            with_article(&expected)
+        )
+    };
+    let diagnostic =
+        Diagnostic::error("Incompatible Event Handler".into(), vec![label]).with_note(note);
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
+/// `name` after "a" or "an", like "an Event" or "a number".
+fn with_article(name: impl Display) -> String {
+    let name = name.to_string();
+    let vowel = name
+        .chars()
+        .next()
+        .is_some_and(|ch| "aeiouAEIOU".contains(ch));
+    format!("{} {}", if vowel { "an" } else { "a" }, name)
+}
+
+pub fn event_handler_arity<T>(
+    span: impl Into<Range<usize>>,
+    event: impl Display,
+    count: usize,
+) -> Result<T> {
+    let label = Label {
+        message: format!(
+            "This handler takes {} parameters, but '{}' handlers are only called with the event",
+            count, event
+        ),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    let diagnostic = Diagnostic::error("Incompatible Event Handler".into(), vec![label]);
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
+pub fn template_nesting_too_deep<T>(span: impl Into<Range<usize>>, max_depth: usize) -> Result<T> {
+    let label = Label {
+        message: format!("Templates can only be nested {} deep", max_depth),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    let diagnostic = Diagnostic::error("Template Nested Too Deeply".into(), vec![label])
+        .with_note("Try moving some of the template into a separate component");
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
+pub fn invalid_effect_reference<T>(span: impl Into<Range<usize>>, name: impl Display) -> Result<T> {
+    let label = Label {
+        message: format!("'{}' is an effect, but is being referenced as type", name),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    let diagnostic = Diagnostic::error("Invalid Effect Reference".into(), vec![label]);
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
+pub fn unknown_effect<T>(span: impl Into<Range<usize>>, name: impl Display) -> Result<T> {
+    let label = Label {
+        message: format!("There's no effect named '{}'", name),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    let diagnostic = Diagnostic::error("Unknown Effect".into(), vec![label])
+        .with_note("Effects are defined at the top level, like 'effect Log'");
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
+pub fn undeclared_effect(
+    span: impl Into<Range<usize>>,
+    effect: impl Display,
+    function_name: impl Display,
+    function_span: impl Into<Range<usize>>,
+) -> Diagnostic {
+    let label = Label {
+        message: format!("This call performs the '{}' effect", effect),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    let function_label = Label {
+        message: format!("But '{}' doesn't declare it", function_name),
+        range: function_span.into(),
+        style: LabelStyle::Secondary,
+    };
+    Diagnostic::error("Undeclared Effect".into(), vec![label, function_label]).with_note(format!(
+        "Add it to the effects '{}' declares, like 'fn {}(): number + {}'",
+        function_name, function_name, effect
+    ))
+}
+
+pub fn missing_return(
+    span: impl Into<Range<usize>>,
+    function_name: impl Display,
+    return_type: impl Display,
+    function_span: impl Into<Range<usize>>,
+) -> Diagnostic {
+    let label = Label {
+        message: "Missing return on this path".into(),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    let function_label = Label {
+        message: format!(
+            "'{}' is declared to return a {}",
+            function_name, return_type
+        ),
+        range: function_span.into(),
+        style: LabelStyle::Secondary,
+    };
+    Diagnostic::error("Missing Return".into(), vec![label, function_label])
+        .with_note("Every path through a function with a return type has to return a value")
+}
+
+pub fn invalid_await<T>(span: impl Into<Range<usize>>) -> Result<T> {
+    let label = Label {
+        message: "You can only use 'await' in an async function".into(),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    let diagnostic = Diagnostic::error("Invalid Await".into(), vec![label]);
+    Err(crate::error::Error::Diagnostic(diagnostic))
+}
+
+/// Report an empty type parameter list
+pub fn empty_type_parameters<T>(span: impl Into<Range<usize>>) -> Result<T> {
+    let label = Label {
+        message: "".into(),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    Err(Error::Diagnostic(Diagnostic::error(
+        EMPTY_TYPE_PARAMETERS.into(),
+        vec![label],
+    )))
+}
+
+pub fn dot_after_import_list<T>(span: impl Into<Range<usize>>) -> Result<T> {
+    let label = Label {
+        message: "".into(),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    Err(Error::Diagnostic(Diagnostic::error(
+        "Cannot use identifier imports after lists".into(),
+        vec![label],
+    )))
+}
+
+pub fn module_not_found(
+    span: impl Into<Range<usize>>,
+    module: impl Display,
+    looked_in: &[std::path::PathBuf],
+) -> Diagnostic {
+    let label = Label {
+        message: format!("There's no file for the module '{}'", module),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    let paths: Vec<String> = looked_in
+        .iter()
+        .map(|path| format!("  {}", path.display()))
+        .collect();
+    Diagnostic::error("Module Not Found".into(), vec![label])
+        .with_note(format!("Looked in these paths:\n{}", paths.join("\n")))
+}
+
+/// An import of a definition that isn't `pub`. `defined_at` is where the
+/// definition is in the other module, like `utils.ws:3:1`.
+pub fn private_import(
+    span: impl Into<Range<usize>>,
+    name: impl Display,
+    module: impl Display,
+    defined_at: impl Display,
+) -> Diagnostic {
+    let label = Label {
+        message: format!("'{}' is private to the module '{}'", name, module),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    Diagnostic::error("Private Definition".into(), vec![label])
+        .with_note(format!(
+            "'{}' is defined without 'pub' at {}",
+            name, defined_at
+        ))
+        .with_note("Add 'pub' to the definition to use it from other modules")
+}
+
+/// A constant that reads itself by way of constants in other modules. Each
+/// of `cycle` is a constant along the way, like `b.ws: B`, ending back at
+/// the constant the span is on.
+pub fn constant_cycle(
+    span: impl Into<Range<usize>>,
+    name: impl Display,
+    cycle: &[String],
+) -> Diagnostic {
+    let label = Label {
+        message: format!("'{}' depends on its own value", name),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    Diagnostic::error("Constant Cycle".into(), vec![label])
+        .with_note(format!("It reads {}", cycle.join(", which reads ")))
+        .with_note(
+            "Modules are initialized after the modules they import, so one of these would be read before it's initialized",
+        )
+}
+
+/// Reported when a module in a library would be written over the library's index.
+pub fn library_index_conflict(module: impl Display) -> Diagnostic {
+    Diagnostic::error("Module Conflicts With Index".into(), vec![])
+        .with_note(format!(
+            "'{}' would be written to 'index.js', which is where the library's index goes",
+            module
+        ))
+        .with_note("Rename the module so the index doesn't replace it")
+}
+
+/// Reported when two modules in a library export the same name, which the
+/// library's index can't re-export twice.
+pub fn duplicate_library_export(
+    name: impl Display,
+    first: impl Display,
+    second: impl Display,
+) -> Diagnostic {
+    Diagnostic::error("Duplicate Library Export".into(), vec![]).with_note(format!(
+        "Both '{}' and '{}' export '{}', and the library's index can only re-export one of them",
+        first, second, name
+    ))
+}
+
+/// Reported in place of the errors after the first `max` in a module.
+pub fn too_many_errors(max: usize) -> Diagnostic {
+    Diagnostic::error("Too Many Errors".into(), vec![]).with_note(format!(
+        "Stopped after {} errors, fix these to see the rest",
+        max
+    ))
+}
+
+pub fn positional_argument_after_named<T>(
+    span: impl Into<Range<usize>>,
+    last_arg_span: impl Into<Range<usize>>,
+) -> Result<T> {
+    let label = Label {
+        message: "this is using a positional argument".into(),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    let arg_span = Label {
+        message: "a named argument was already used".into(),
+        range: last_arg_span.into(),
+        style: LabelStyle::Secondary,
+    };
+    Err(Error::Diagnostic(Diagnostic::error(
+        "Positional arguments cannot be mixed with named arguments".into(),
+        vec![label, arg_span],
+    )))
+}
+
+pub fn named_argument_after_positional<T>(
+    span: impl Into<Range<usize>>,
+    last_arg_span: impl Into<Range<usize>>,
+) -> Result<T> {
+    let label = Label {
+        message: "this is using a named argument".into(),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    let arg_span = Label {
+        message: "a positional argument was already used".into(),
+        range: last_arg_span.into(),
+        style: LabelStyle::Secondary,
+    };
+    Err(Error::Diagnostic(Diagnostic::error(
+        "Named arguments cannot be mixed with positional arguments".into(),
+        vec![label, arg_span],
+    )))
+}
+
+pub fn unexpected_token_for_expression<T>(
+    span: impl Into<Range<usize>>,
+    prev_span: Option<impl Into<Range<usize>>>,
+) -> Result<T> {
+    let mut labels = vec![Label {
+        message: format!(
+            "Tried to parse an expression starting here, but this token isn't allowed",
+        ),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    }];
+    if let Some(prev_span) = prev_span {
+        labels.push(Label {
+            message: "Something might be missing after this?".into(),
+            range: prev_span.into(),
+            style: LabelStyle::Secondary,
+        });
+    }
+    Err(Error::Diagnostic(Diagnostic::error(
+        "Unexpected token for expression".into(),
+        labels,
+    )))
+}
+
+pub fn unused_function<T>(span: impl Into<Range<usize>>) -> Result<T> {
+    let label = Label {
+        message: "This function is unused".into(),
+        range: span.into(),
+        style: LabelStyle::Secondary,
+    };
+    Err(Error::Diagnostic(Diagnostic::error(
+        "Unused Function".into(),
+        vec![label],
+    )))
+}
+
+pub fn unreachable_code<T>(span: impl Into<Range<usize>>) -> Result<T> {
+    let label = Label {
+        message: "This code is unreachable".into(),
         range: span.into(),
         style: LabelStyle::Primary,
     };
@@ -416,12 +1479,82 @@ pub fn unreachable_code<T>(span: impl Into<Range<usize>>) -> Result<T> {
     )))
 }
 
+/// Report an operation that fails when it's evaluated at compile time,
+/// like dividing a constant by zero.
+pub fn incompatible_comparison<T>(
+    span: impl Into<Range<usize>>,
+    left: impl Display,
+    right: impl Display,
+) -> Result<T> {
+    let label = Label {
+        message: format!("This compares a {} with a {}", left, right),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    Err(Error::Diagnostic(
+        Diagnostic::error("Incompatible Comparison".into(), vec![label])
+            .with_note("Values of different types can never be equal"),
+    ))
+}
+
+pub fn invalid_constant_operation<T>(
+    span: impl Into<Range<usize>>,
+    message: impl Display,
+) -> Result<T> {
+    let label = Label {
+        message: message.to_string(),
+        range: span.into(),
+        style: LabelStyle::Primary,
+    };
+    Err(Error::Diagnostic(Diagnostic::error(
+        INVALID_CONSTANT_OPERATION.into(),
+        vec![label],
+    )))
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Error {
     IO(String),
     Fmt,
     Lexing,
     Diagnostic(Diagnostic),
+    /// More than one diagnostic, from a parse that recovered from errors
+    /// to report as many of them as it could.
+    Diagnostics(Vec<Diagnostic>),
+}
+
+impl Error {
+    /// Combine diagnostics that were recovered from into a single error.
+    pub fn from_diagnostics(mut diagnostics: Vec<Diagnostic>) -> Self {
+        if diagnostics.len() == 1 {
+            Error::Diagnostic(diagnostics.pop().unwrap())
+        } else {
+            Error::Diagnostics(diagnostics)
+        }
+    }
+
+    /// The diagnostics to report for this error, if it has any.
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        match self {
+            Error::Diagnostic(diagnostic) => std::slice::from_ref(diagnostic),
+            Error::Diagnostics(diagnostics) => diagnostics,
+            _ => &[],
+        }
+    }
+
+    /// Move the labels of every diagnostic, like `Diagnostic::map_ranges`.
+    pub fn map_ranges(self, map: impl Fn(Range<usize>) -> Range<usize>) -> Self {
+        match self {
+            Error::Diagnostic(diagnostic) => Error::Diagnostic(diagnostic.map_ranges(map)),
+            Error::Diagnostics(diagnostics) => Error::Diagnostics(
+                diagnostics
+                    .into_iter()
+                    .map(|diagnostic| diagnostic.map_ranges(&map))
+                    .collect(),
+            ),
+            error => error,
+        }
+    }
 }
 
 impl From<io::Error> for Error {
@@ -0,0 +1,176 @@
+//! Events the compiler reports while it runs. Library crates never write
+//! to stdout themselves; they report through a `CompilerEventSink` and the
+//! tool driving the compiler (the CLI, the language server) decides how
+//! to present them.
+use std::ops::Range;
+use std::path::Path;
+
+use crate::error::Diagnostic;
+
+/// A phase of compiling a single module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Parse,
+    StateWrites,
+    Evaluate,
+    ControlFlow,
+    Codegen,
+}
+
+/// Receives events from the compiler. Every method defaults to doing
+/// nothing, so sinks only implement the events they care about.
+pub trait CompilerEventSink: Send + Sync {
+    /// A phase has started for the module at `path`.
+    fn phase_started(&self, _path: &Path, _phase: Phase) {}
+
+    /// The definition `name` in the module at `path` is about to be
+    /// compiled. `range` is where it is in the module.
+    fn definition_started(&self, _path: &Path, _name: &str, _range: Range<usize>) {}
+
+    /// A diagnostic that didn't stop compilation, like a warning.
+    /// `source` is the text of the module the diagnostic points into.
+    fn diagnostic(&self, _path: &Path, _source: &str, _diagnostic: &Diagnostic) {}
+
+    /// The compiled module at `path` was written to `output`.
+    fn output_written(&self, _path: &Path, _output: &Path) {}
+
+    /// Part of the module at `path`, like a block, as it was after the
+    /// optimization pass `pass` ran, for `--print-after`.
+    fn pass_printed(&self, _path: &Path, _pass: &str, _part: &str) {}
+}
+
+/// A sink that ignores every event.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NullEventSink;
+
+impl CompilerEventSink for NullEventSink {}
+
+/// Implemented by databases so queries can report events.
+pub trait HasEventSink {
+    fn event_sink(&self) -> &dyn CompilerEventSink;
+}
+
+/// What happens while watching a project, for tools that show the state of
+/// the build, like a dev server that overlays errors in the browser. Each
+/// event is encoded as a single line of JSON.
+#[derive(Debug, Clone, Copy)]
+pub enum WatchEvent<'a> {
+    FileChanged {
+        path: &'a Path,
+    },
+    BuildStarted {
+        path: &'a Path,
+    },
+    BuildSucceeded {
+        path: &'a Path,
+    },
+    /// The build stopped, because of `diagnostics` in the module at `path`
+    /// whose text is `source`.
+    BuildFailed {
+        path: &'a Path,
+        source: &'a str,
+        diagnostics: &'a [Diagnostic],
+    },
+    /// A diagnostic that didn't stop the build, like a warning.
+    Diagnostic {
+        path: &'a Path,
+        source: &'a str,
+        diagnostic: &'a Diagnostic,
+    },
+}
+
+impl<'a> WatchEvent<'a> {
+    pub fn to_json(&self) -> String {
+        let path = |path: &Path| json_string(&path.to_string_lossy());
+        match self {
+            WatchEvent::FileChanged { path: changed } => {
+                format!(
+                    "{{\"event\": \"file_changed\", \"path\": {}}}",
+                    path(changed)
+                )
+            }
+            WatchEvent::BuildStarted { path: entry } => {
+                format!(
+                    "{{\"event\": \"build_started\", \"path\": {}}}",
+                    path(entry)
+                )
+            }
+            WatchEvent::BuildSucceeded { path: entry } => {
+                format!(
+                    "{{\"event\": \"build_succeeded\", \"path\": {}}}",
+                    path(entry)
+                )
+            }
+            WatchEvent::BuildFailed {
+                path: failed,
+                source,
+                diagnostics,
+            } => {
+                let diagnostics: Vec<String> = diagnostics
+                    .iter()
+                    .map(|diagnostic| diagnostic.to_json(source))
+                    .collect();
+                format!(
+                    "{{\"event\": \"build_failed\", \"path\": {}, \"diagnostics\": [{}]}}",
+                    path(failed),
+                    diagnostics.join(", ")
+                )
+            }
+            WatchEvent::Diagnostic {
+                path: reported,
+                source,
+                diagnostic,
+            } => format!(
+                "{{\"event\": \"diagnostic\", \"path\": {}, \"diagnostic\": {}}}",
+                path(reported),
+                diagnostic.to_json(source)
+            ),
+        }
+    }
+}
+
+/// Quote and escape `value` as a JSON string.
+pub(crate) fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for ch in value.chars() {
+        match ch {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            ch if ch.is_control() => escaped.push_str(&format!("\\u{:04x}", ch as u32)),
+            ch => escaped.push(ch),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::WatchEvent;
+    use crate::error::unknown_reference_error;
+    use std::path::Path;
+
+    #[test]
+    fn encode_watch_events() {
+        let path = Path::new("src/main.ws");
+        assert_eq!(
+            WatchEvent::BuildStarted { path }.to_json(),
+            r#"{"event": "build_started", "path": "src/main.ws"}"#
+        );
+        let source = "fn a() {\n  return \"b\" + c\n}";
+        let error =
+            unknown_reference_error::<()>(23..24, "c", None::<std::ops::Range<usize>>).unwrap_err();
+        let event = WatchEvent::BuildFailed {
+            path,
+            source,
+            diagnostics: error.diagnostics(),
+        };
+        let json = event.to_json();
+        assert!(json.starts_with(r#"{"event": "build_failed", "path": "src/main.ws", "diagnostics": [{"severity": "error", "message": "Unknown Reference""#));
+        assert!(json.contains(r#""range": [23, 24], "start": {"line": 2, "column": 15}, "end": {"line": 2, "column": 16}"#));
+    }
+}
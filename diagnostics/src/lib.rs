@@ -1,3 +1,5 @@
+pub mod color;
 pub mod error;
+pub mod events;
 pub mod result;
 pub use codespan_reporting::term::termcolor;
\ No newline at end of file
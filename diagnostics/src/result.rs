@@ -1,4 +1,137 @@
-use crate::error::Error;
+use crate::error::{too_many_errors, Diagnostic, Error};
 use std::result::Result as StdResult;
 
 pub type Result<T> = StdResult<T, Error>;
+
+/// Collects the diagnostics from passes that keep going after an error, so
+/// every independent error can be reported in one run instead of one at a
+/// time.
+#[derive(Debug, Clone, Default)]
+pub struct DiagnosticBag {
+    diagnostics: Vec<Diagnostic>,
+    /// How many diagnostics to keep. There's no limit if it's `None`.
+    limit: Option<usize>,
+    /// Whether diagnostics were left out because of the limit.
+    overflowed: bool,
+}
+
+impl DiagnosticBag {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A bag that keeps at most `limit` diagnostics. The ones after that are
+    /// replaced with a single "Too Many Errors" diagnostic.
+    pub fn with_limit(limit: Option<usize>) -> Self {
+        Self {
+            limit,
+            ..Self::default()
+        }
+    }
+
+    /// Add a diagnostic, unless the same one was already added.
+    pub fn push(&mut self, diagnostic: Diagnostic) {
+        if self.diagnostics.contains(&diagnostic) {
+            return;
+        }
+        if self.limit == Some(self.diagnostics.len()) {
+            self.overflowed = true;
+            return;
+        }
+        self.diagnostics.push(diagnostic);
+    }
+
+    pub fn extend(&mut self, diagnostics: impl IntoIterator<Item = Diagnostic>) {
+        for diagnostic in diagnostics {
+            self.push(diagnostic);
+        }
+    }
+
+    /// Collect the diagnostics of `result` if it failed, or return its value
+    /// if it didn't. Errors without diagnostics, like failing to read a
+    /// file, can't be reported along with the others, so they're returned.
+    pub fn report<T>(&mut self, result: Result<T>) -> Result<Option<T>> {
+        match result {
+            Ok(value) => Ok(Some(value)),
+            Err(error) if !error.diagnostics().is_empty() => {
+                self.extend(error.diagnostics().iter().cloned());
+                Ok(None)
+            }
+            Err(error) => Err(error),
+        }
+    }
+
+    /// Whether diagnostics were left out because the bag was full, in which
+    /// case there's no point in collecting more.
+    pub fn overflowed(&self) -> bool {
+        self.overflowed
+    }
+
+    pub fn len(&self) -> usize {
+        self.diagnostics.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    /// Fail with every diagnostic collected, if there are any.
+    pub fn into_result(self) -> Result<()> {
+        self.finish(Ok(()))
+    }
+
+    /// Finish with the result of the last pass, failing if it did or if any
+    /// diagnostics were collected before it.
+    pub fn finish<T>(mut self, result: Result<T>) -> Result<T> {
+        let value = self.report(result)?;
+        let mut diagnostics = self.diagnostics;
+        if let (true, Some(limit)) = (self.overflowed, self.limit) {
+            diagnostics.push(too_many_errors(limit));
+        }
+        match value {
+            Some(value) if diagnostics.is_empty() => Ok(value),
+            _ => Err(Error::from_diagnostics(diagnostics)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DiagnosticBag;
+    use crate::error::{nesting_too_deep, unknown_reference_error, Error};
+
+    #[test]
+    fn collect_diagnostics() {
+        let mut bag = DiagnosticBag::with_limit(Some(2));
+        assert_eq!(bag.report(Ok::<_, Error>(1)).unwrap(), Some(1));
+        bag.report(nesting_too_deep::<()>(0..1)).unwrap();
+        // The same error is only reported once
+        bag.report(nesting_too_deep::<()>(0..1)).unwrap();
+        assert!(bag.report(Err::<(), _>(Error::Fmt)).is_err());
+        assert!(!bag.overflowed());
+        bag.report(unknown_reference_error::<()>(
+            2..3,
+            "a",
+            None::<std::ops::Range<usize>>,
+        ))
+        .unwrap();
+        bag.report(unknown_reference_error::<()>(
+            4..5,
+            "b",
+            None::<std::ops::Range<usize>>,
+        ))
+        .unwrap();
+        assert!(bag.overflowed());
+        let error = bag.finish(Ok(())).unwrap_err();
+        let messages: Vec<&str> = error
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| diagnostic.message())
+            .collect();
+        assert_eq!(
+            messages,
+            vec!["Nesting Too Deep", "Unknown Reference", "Too Many Errors"]
+        );
+        assert_eq!(DiagnosticBag::new().finish(Ok(3)).unwrap(), 3);
+    }
+}
@@ -1,5 +1,76 @@
+use common::symbol::Symbol;
+
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Value {
     Boolean(bool),
     Number(f64),
+    /// Strings are interned, so comparing two of them is comparing symbols
+    String(Symbol),
+    /// The result of an operation that has no meaningful value, like
+    /// dividing by zero. Folding stops when one of these is produced.
+    Error(EvaluationError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EvaluationError {
+    DivisionByZero,
+    Overflow,
+    NotANumber,
+    /// Comparing values of different types, which are never equal
+    IncompatibleComparison {
+        left: &'static str,
+        right: &'static str,
+    },
+}
+
+impl EvaluationError {
+    pub fn description(&self) -> &'static str {
+        match self {
+            EvaluationError::DivisionByZero => "This divides by zero",
+            EvaluationError::Overflow => "This overflows the range of a number",
+            EvaluationError::NotANumber => "This doesn't produce a valid number",
+            EvaluationError::IncompatibleComparison { .. } => {
+                "This compares values of different types"
+            }
+        }
+    }
+}
+
+impl Value {
+    /// The name of this value's type, as it's written in the source.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Boolean(_) => "bool",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Error(_) => "error",
+        }
+    }
+
+    /// Compare two values with `==`. Values can only be compared with values
+    /// of the same type, anything else is an error instead of `false`.
+    pub fn equals(&self, other: &Value) -> Value {
+        match (self, other) {
+            (Value::Error(error), _) | (_, Value::Error(error)) => Value::Error(*error),
+            (Value::Boolean(left), Value::Boolean(right)) => Value::Boolean(left == right),
+            (Value::Number(left), Value::Number(right)) => Value::Boolean(left == right),
+            (Value::String(left), Value::String(right)) => Value::Boolean(left == right),
+            (left, right) => Value::Error(EvaluationError::IncompatibleComparison {
+                left: left.type_name(),
+                right: right.type_name(),
+            }),
+        }
+    }
+}
+
+/// Check the result of an arithmetic operation on two numbers, turning
+/// results that JavaScript would silently accept (`Infinity`, `NaN`) into errors.
+pub fn checked_number(left: f64, right: f64, result: f64) -> Value {
+    if result.is_nan() {
+        Value::Error(EvaluationError::NotANumber)
+    } else if result.is_infinite() && left.is_finite() && right.is_finite() {
+        Value::Error(EvaluationError::Overflow)
+    } else {
+        Value::Number(result)
+    }
 }
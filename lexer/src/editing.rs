@@ -0,0 +1,130 @@
+//! Edits to make while a module is being typed. They only need the source
+//! to lex, not parse, since it's rarely complete while it's being written.
+use crate::Lexer;
+use std::ops::Range;
+use syntax::token::TokenKind;
+
+/// The rest of the close tag for `</` just before `offset`, like `p>`, for
+/// the innermost element that's still open there.
+pub fn close_tag(source: &str, offset: usize) -> Option<String> {
+    let start = offset.checked_sub(2)?;
+    if source.get(start..offset)? != "</" {
+        return None;
+    }
+    let mut lexer = Lexer::new(&source[..start]);
+    loop {
+        if lexer.next_token().ok()?.kind == TokenKind::EOF {
+            break;
+        }
+    }
+    let name = lexer.open_element()?;
+    // It's already been closed
+    if source[offset..].starts_with(name) {
+        return None;
+    }
+    Some(format!("{}>", name))
+}
+
+/// The edit that lines up the `}` just before `offset` with the line its
+/// `{` is on, if it's the first thing on its line and isn't lined up yet.
+pub fn indent_closing_brace(source: &str, offset: usize) -> Option<(Range<usize>, String)> {
+    let brace = offset.checked_sub(1)?;
+    if source.get(brace..offset)? != "}" {
+        return None;
+    }
+    let line_start = source[..brace].rfind('\n').map_or(0, |newline| newline + 1);
+    if !source[line_start..brace].trim().is_empty() {
+        return None;
+    }
+    // Braces in template text and strings aren't tokens, so they're skipped
+    let mut lexer = Lexer::new(&source[..offset]);
+    let mut open = vec![];
+    let mut matching = None;
+    loop {
+        let token = lexer.next_token().ok()?;
+        match token.kind {
+            TokenKind::LBrace => open.push(token.span),
+            TokenKind::RBrace => matching = open.pop(),
+            TokenKind::EOF => break,
+            _ => {}
+        }
+    }
+    let open: Range<usize> = matching?.into();
+    let open_line = source[..open.start]
+        .rfind('\n')
+        .map_or(0, |newline| newline + 1);
+    let indent: String = source[open_line..]
+        .chars()
+        .take_while(|char| *char == ' ' || *char == '\t')
+        .collect();
+    if source[line_start..brace] == indent {
+        return None;
+    }
+    Some((line_start..brace, indent))
+}
+
+/// Where the attribute name being typed at `offset` is, if it's in an open
+/// tag, so it can be completed. It's empty when nothing's been typed yet.
+pub fn attribute_name_at(source: &str, offset: usize) -> Option<Range<usize>> {
+    let typed = source.get(..offset)?;
+    let start = typed
+        .rfind(|char: char| !(char.is_alphanumeric() || char == '-' || char == '_'))
+        .map_or(0, |index| index + 1);
+    // Attributes follow the tag's name and a space
+    if !typed[..start].ends_with(char::is_whitespace) {
+        return None;
+    }
+    let mut lexer = Lexer::new(&typed[..start]);
+    loop {
+        if lexer.next_token().ok()?.kind == TokenKind::EOF {
+            break;
+        }
+    }
+    if lexer.in_open_tag() {
+        Some(start..offset)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{attribute_name_at, close_tag, indent_closing_brace};
+
+    #[test]
+    fn close_open_tags() {
+        let close = |source: &str| close_tag(source, source.len());
+        assert_eq!(
+            close("return (\n  <div>\n    <p>a</p>\n  </"),
+            Some("div>".into())
+        );
+        assert_eq!(
+            close("return <div class={a}><p>{a < b}</"),
+            Some("p>".into())
+        );
+        assert_eq!(close("return <div><br /></"), Some("div>".into()));
+        assert_eq!(close("let a = b </"), None);
+    }
+
+    #[test]
+    fn line_up_closing_braces() {
+        let source = "fn a() {\n  if b {\n    return \"}\"\n      }";
+        assert_eq!(
+            indent_closing_brace(source, source.len()),
+            Some((source.len() - 7..source.len() - 1, "  ".to_string()))
+        );
+        let source = "fn a() {\n  return 1\n}";
+        assert_eq!(indent_closing_brace(source, source.len()), None);
+    }
+
+    #[test]
+    fn find_attribute_names() {
+        let at = |source: &str| attribute_name_at(source, source.len());
+        assert_eq!(at("return <button onCl"), Some(15..19));
+        assert_eq!(at("return <div class={a} "), Some(22..22));
+        assert_eq!(at("return <div class={a"), None);
+        assert_eq!(at("return <di"), None);
+        assert_eq!(at("return <div>text "), None);
+        assert_eq!(at("let a = b "), None);
+    }
+}
@@ -1,26 +1,50 @@
-use crate::TokenStream;
-use common::symbol::Symbol;
+use crate::{Lexeme, TokenStream};
 use diagnostics::error::{invalid_character, multiple_decimal_in_number};
 use diagnostics::result::Result;
 use std::collections::VecDeque;
 use std::iter::{Iterator, Peekable};
 use std::str::CharIndices;
+use syntax::ast::is_void_element;
 use syntax::span::Span;
 use syntax::token::{Token, TokenKind};
 use unicode_xid::UnicodeXID;
 
+/// Where the lexer is within a template. Template text is lexed differently
+/// from code, so the lexer keeps track of templates itself instead of having
+/// the parser switch modes. That lets a file be lexed before it's parsed.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum LexingMode {
-    Normal,
-    TemplateTag,
-    TemplateText,
+enum TemplateContext {
+    /// Inside an open or close tag. `braces` counts the `{` left open by
+    /// attribute values, and `name` is where the tag's name is once it's
+    /// been lexed.
+    Tag {
+        name: Option<Span>,
+        braces: usize,
+        closing: bool,
+        self_closing: bool,
+    },
+    /// Between an open tag's `>` and the close tag, for the element whose
+    /// name is at `tag`.
+    Text { tag: Option<Span> },
+    /// An expression in `{}` within template text.
+    Expression { braces: usize },
+}
+
+/// Whether the `<` at `span` is followed by a `/`, starting a close tag.
+fn starts_close_tag(source: &str, span: Span) -> bool {
+    let range: std::ops::Range<usize> = span.into();
+    source[range.end..].trim_start().starts_with('/')
 }
 
 pub struct Lexer<'s> {
     source: &'s str,
     chars: Peekable<CharIndices<'s>>,
     lookahead: VecDeque<Token>,
-    mode: LexingMode,
+    /// The templates being lexed, innermost last.
+    templates: Vec<TemplateContext>,
+    /// Whether the last token can end an expression. A `<` after one is a
+    /// comparison, otherwise it starts a template.
+    ends_expression: bool,
 }
 
 impl<'s> Lexer<'s> {
@@ -30,25 +54,24 @@ impl<'s> Lexer<'s> {
             chars,
             source,
             lookahead: VecDeque::with_capacity(2),
-            mode: LexingMode::Normal,
+            templates: vec![],
+            ends_expression: false,
         }
     }
 
-    pub fn set_mode(&mut self, mode: LexingMode) {
-        self.mode = mode;
-    }
-
-    pub fn lex(mut self) -> Result<TokenStream> {
-        let mut tokens = TokenStream::for_source(&self.source);
+    /// Lex the whole source. Lexing stops at the first error, which the
+    /// stream reports after the tokens before it.
+    pub fn lex(mut self) -> TokenStream {
+        let mut tokens = TokenStream::for_source(self.source);
         loop {
-            let token = self.next_token()?;
-            if token.kind == TokenKind::EOF {
-                break;
-            } else {
-                tokens.push(token)
+            match self.next_lexeme() {
+                Ok((Lexeme::Token(TokenKind::EOF), span)) => return tokens.finish(span, None),
+                Ok((lexeme, span)) => tokens.push(lexeme, span),
+                Err(error) => {
+                    return tokens.finish(self.end_span(), Some(error));
+                }
             }
         }
-        Ok(tokens)
     }
 
     fn skip(&mut self) {
@@ -73,20 +96,137 @@ impl<'s> Lexer<'s> {
         self.skip_while(|char| char != &'\n' && char.is_whitespace());
     }
 
-    fn skip_newlines(&mut self) {
-        self.skip_while(|char| char.is_whitespace());
-    }
-
     pub fn next_token(&mut self) -> Result<Token> {
-        use TokenKind::*;
         // Read from the lookahead if its populated.
         if let Some(token) = self.lookahead.pop_front() {
             return Ok(token);
         }
-        if self.mode == LexingMode::TemplateText {
-            self.skip_newlines();
-            return self.template_text();
+        let (lexeme, span) = self.next_lexeme()?;
+        let kind = lexeme.kind(self.source, span);
+        Ok(Token::new(kind, span))
+    }
+
+    fn next_lexeme(&mut self) -> Result<(Lexeme, Span)> {
+        // Whitespace in template text is kept as-is, since it can be significant.
+        let (lexeme, span) = match self.templates.last() {
+            Some(TemplateContext::Text { .. }) => self.template_text()?,
+            _ => self.lexeme()?,
+        };
+        self.track_templates(&lexeme, span);
+        Ok((lexeme, span))
+    }
+
+    /// Update the template context for a token that was just lexed.
+    fn track_templates(&mut self, lexeme: &Lexeme, span: Span) {
+        use TokenKind::*;
+        let kind = match lexeme {
+            Lexeme::Token(Newline) => return,
+            Lexeme::Token(kind) => kind,
+            Lexeme::Identifier => {
+                // The first identifier in a tag is its name
+                if let Some(TemplateContext::Tag { name, .. }) = self.templates.last_mut() {
+                    name.get_or_insert(span);
+                }
+                self.ends_expression = true;
+                return;
+            }
+            _ => {
+                self.ends_expression = true;
+                return;
+            }
+        };
+        let in_prefix_position = !self.ends_expression;
+        self.ends_expression = matches!(kind, True | False | RParen | RBracket);
+        match (self.templates.last_mut(), kind) {
+            (Some(TemplateContext::Text { .. }), LBrace) => self
+                .templates
+                .push(TemplateContext::Expression { braces: 0 }),
+            (Some(TemplateContext::Text { .. }), LessThan) => {
+                let closing = starts_close_tag(self.source, span);
+                self.templates.push(TemplateContext::Tag {
+                    name: None,
+                    braces: 0,
+                    closing,
+                    self_closing: false,
+                });
+            }
+            // `</` can't be in an expression, so this is the close tag after
+            // an expression that was never closed. Lex it like the parser
+            // will recover from it: by ending the expression.
+            (Some(TemplateContext::Expression { .. }), LessThan)
+                if starts_close_tag(self.source, span) =>
+            {
+                self.templates.pop();
+                self.templates.push(TemplateContext::Tag {
+                    name: None,
+                    braces: 0,
+                    closing: true,
+                    self_closing: false,
+                });
+            }
+            (Some(TemplateContext::Text { .. }), _) => {}
+            (
+                Some(TemplateContext::Tag {
+                    braces: 0,
+                    closing,
+                    self_closing,
+                    ..
+                }),
+                Slash,
+            ) => {
+                *self_closing = !*closing;
+            }
+            (
+                Some(TemplateContext::Tag {
+                    name,
+                    braces: 0,
+                    closing,
+                    self_closing,
+                }),
+                GreaterThan,
+            ) => {
+                let (name, closing, self_closing) = (*name, *closing, *self_closing);
+                // Void elements like `<br>` never have text of their own, and
+                // the close tag some are written with doesn't end any
+                let void = name.is_some_and(|name| {
+                    let range: std::ops::Range<usize> = name.into();
+                    is_void_element(&self.source[range])
+                });
+                self.templates.pop();
+                if closing && !void {
+                    // The close tag ends the text it follows too.
+                    self.templates.pop();
+                }
+                if closing || self_closing || void {
+                    self.ends_expression = true;
+                } else {
+                    self.templates.push(TemplateContext::Text { tag: name });
+                }
+            }
+            (Some(TemplateContext::Tag { braces, .. }), LBrace)
+            | (Some(TemplateContext::Expression { braces }), LBrace) => *braces += 1,
+            (Some(TemplateContext::Expression { braces: 0 }), RBrace) => {
+                self.templates.pop();
+            }
+            (Some(TemplateContext::Tag { braces, .. }), RBrace)
+            | (Some(TemplateContext::Expression { braces }), RBrace) => {
+                *braces = braces.saturating_sub(1)
+            }
+            (Some(TemplateContext::Tag { braces: 0, .. }), _) => {}
+            (_, LessThan) if in_prefix_position => {
+                self.templates.push(TemplateContext::Tag {
+                    name: None,
+                    braces: 0,
+                    closing: false,
+                    self_closing: false,
+                });
+            }
+            _ => {}
         }
+    }
+
+    fn lexeme(&mut self) -> Result<(Lexeme, Span)> {
+        use TokenKind::*;
         self.skip_whitespace();
         let char = self.chars.peek();
         match char {
@@ -104,31 +244,75 @@ impl<'s> Lexer<'s> {
             Some((_, '}')) => self.punc(RBrace),
             Some((_, '[')) => self.punc(LBracket),
             Some((_, ']')) => self.punc(RBracket),
-            Some((_, '+')) => self.punc(Plus),
-            Some((_, '-')) => self.punc(Minus),
-            Some((_, '/')) => self.punc(Slash),
-            Some((_, '*')) => self.punc(Star),
+            Some((_, '+')) => self.operator(Plus, PlusEquals),
+            Some((_, '-')) => self.operator(Minus, MinusEquals),
+            Some((_, '/')) => self.operator(Slash, SlashEquals),
+            Some((_, '*')) => self.operator(Star, StarEquals),
             Some((_, ':')) => self.punc(Colon),
+            Some((_, ';')) => self.punc(Semicolon),
             Some((_, '<')) => self.less_than(),
             Some((_, '>')) => self.greater_than(),
-            Some((_, '|')) => self.punc(Pipe),
+            Some((_, '|')) => self.pipe(),
+            Some((_, '?')) => self.punc(Question),
+            // `_` on its own is a wildcard, but it can start a name too
+            Some((i, '_'))
+                if self.source[i + 1..]
+                    .chars()
+                    .next()
+                    .is_some_and(|ch| ch.is_xid_continue()) =>
+            {
+                self.identifier()
+            }
             Some((_, '_')) => self.punc(Underscore),
             Some((_, '\n')) => self.punc(Newline),
-            None => {
-                let index = self.source.len() - 1;
-                let span = Span::new(index as u32, index as u32);
-                Ok(Token {
-                    span,
-                    kind: TokenKind::EOF,
-                })
-            }
-            Some((i, _)) => {
-                let span = Span::from(*i);
+            None => Ok((Lexeme::Token(TokenKind::EOF), self.end_span())),
+            Some((i, ch)) => {
+                let span = Span::char(*i, *ch);
                 invalid_character(span)
             }
         }
     }
 
+    /// The name of the innermost element whose open tag has been lexed but
+    /// whose close tag hasn't.
+    pub fn open_element(&self) -> Option<&'s str> {
+        let tag = self
+            .templates
+            .iter()
+            .rev()
+            .find_map(|context| match context {
+                TemplateContext::Text { tag } => Some(*tag),
+                _ => None,
+            })??;
+        let range: std::ops::Range<usize> = tag.into();
+        Some(&self.source[range])
+    }
+
+    /// Whether the lexer is between attributes in an open tag, after the
+    /// tag's name.
+    pub fn in_open_tag(&self) -> bool {
+        matches!(
+            self.templates.last(),
+            Some(TemplateContext::Tag {
+                name: Some(_),
+                braces: 0,
+                closing: false,
+                self_closing: false,
+            })
+        )
+    }
+
+    /// The span of the source's last character, which is where errors at
+    /// the end of the file are reported.
+    fn end_span(&self) -> Span {
+        let start = self
+            .source
+            .char_indices()
+            .next_back()
+            .map_or(0, |(index, _)| index);
+        Span::from(start..self.source.len())
+    }
+
     pub fn peek(&mut self) -> Result<&Token> {
         if self.lookahead.is_empty() {
             let token = self.next_token()?;
@@ -137,34 +321,30 @@ impl<'s> Lexer<'s> {
         Ok(self.lookahead.front().unwrap())
     }
 
-    fn template_text(&mut self) -> Result<Token> {
+    fn template_text(&mut self) -> Result<(Lexeme, Span)> {
         match self.chars.peek() {
             Some((_, '<')) => self.punc(TokenKind::LessThan),
             Some((_, '>')) => self.punc(TokenKind::GreaterThan),
             Some((_, '{')) => self.punc(TokenKind::LBrace),
             Some((_, '}')) => self.punc(TokenKind::RBrace),
-            _ => {
-                let (start, _) = self.chars.next().unwrap();
-                let mut end = start;
+            // Templates left open at the end of the file are reported by the parser.
+            None => self.lexeme(),
+            Some(_) => {
+                let (start, ch) = self.chars.next().unwrap();
+                let mut end = start + ch.len_utf8();
                 while let Some((i, ch)) = self.chars.peek() {
                     match ch {
                         '{' | '}' | '<' | '>' => {
                             break;
                         }
                         _ => {
-                            end = *i;
+                            end = i + ch.len_utf8();
                             self.skip();
                         }
                     }
                 }
-                let span = Span::new(start as u32, end as u32);
-                let word = &self.source[start..end + 1];
-                // TODO dont think this is the right way to handle whitespace
-                // let word = word.trim();
-                let symbol = Symbol::intern(word);
-                let kind = TokenKind::TemplateString(symbol);
-                let token = Token::new(kind, span);
-                Ok(token)
+                let span = Span::from(start..end);
+                Ok((Lexeme::TemplateString, span))
             }
         }
     }
@@ -172,147 +352,160 @@ impl<'s> Lexer<'s> {
     /// We don't create tokens for comments at the moment. This
     /// method will just skip all the characters it sees until it encounters
     /// a newline and then attempt to return the next token
-    fn comment(&mut self) -> Result<Token> {
+    fn comment(&mut self) -> Result<(Lexeme, Span)> {
         self.skip_while(|ch| ch != &'\n');
-        self.next_token()
+        self.lexeme()
     }
 
-    fn string(&mut self) -> Result<Token> {
-        let (start, _) = self.chars.next().unwrap();
-        let mut end = start;
+    fn string(&mut self) -> Result<(Lexeme, Span)> {
+        let (start, quote) = self.chars.next().unwrap();
+        let mut end = None;
         while let Some((i, ch)) = self.chars.next() {
             if ch == '"' {
-                end = i;
+                end = Some(i + 1);
                 break;
             } else if ch == '\n' {
                 break;
             }
         }
-        if start == end {
-            use diagnostics::error::unterminated_string;
-            let span = Span::from(start);
-            return unterminated_string(span);
-        }
-        let span = Span::new(start as u32, end as u32);
-        let word = &self.source[start + 1..end];
-        let symbol = Symbol::intern(word);
-        let kind = TokenKind::String(symbol);
-        let token = Token::new(kind, span);
-        Ok(token)
+        let end = match end {
+            Some(end) => end,
+            None => {
+                use diagnostics::error::unterminated_string;
+                return unterminated_string(Span::char(start, quote));
+            }
+        };
+        let span = Span::from(start..end);
+        Ok((Lexeme::String, span))
     }
 
     // Equals can be either the '=' or '=>' operators.
-    fn equals(&mut self) -> Result<Token> {
+    fn equals(&mut self) -> Result<(Lexeme, Span)> {
         let (start, _) = self.chars.next().unwrap();
         let (span, kind) = match self.chars.peek() {
             Some((_, '>')) => {
                 let (end, _) = self.chars.next().unwrap();
-                let span = Span::new(start as u32, end as u32);
+                let span = Span::from(start..end + 1);
                 (span, TokenKind::Arrow)
             }
             // Support == as well
             Some((_, '=')) => {
                 let (end, _) = self.chars.next().unwrap();
-                let span = Span::new(start as u32, end as u32);
+                let span = Span::from(start..end + 1);
                 (span, TokenKind::DoubleEquals)
             }
             _ => {
-                let end = start;
-                (Span::new(start as u32, end as u32), TokenKind::Equals)
+                let end = start + 1;
+                (Span::from(start..end), TokenKind::Equals)
             }
         };
-        let token = Token::new(kind, span);
-        Ok(token)
+        Ok((Lexeme::Token(kind), span))
     }
 
-    fn dot(&mut self) -> Result<Token> {
+    fn dot(&mut self) -> Result<(Lexeme, Span)> {
         let (start, _) = self.chars.next().unwrap();
         let (span, kind) = match self.chars.peek() {
             // Range
             Some((_, '.')) => {
                 let (end, _) = self.chars.next().unwrap();
-                (Span::new(start as u32, end as u32), TokenKind::Range)
+                (Span::from(start..end + 1), TokenKind::Range)
             }
             // Decimal
             _ => {
-                let end = start;
-                (Span::new(start as u32, end as u32), TokenKind::Dot)
+                let end = start + 1;
+                (Span::from(start..end), TokenKind::Dot)
             }
         };
-        let token = Token::new(kind, span);
-        Ok(token)
+        Ok((Lexeme::Token(kind), span))
     }
 
-    fn and(&mut self) -> Result<Token> {
+    fn and(&mut self) -> Result<(Lexeme, Span)> {
         let (start, _) = self.chars.next().unwrap();
         let (span, kind) = match self.chars.peek() {
             Some((_, '&')) => {
                 let (end, _) = self.chars.next().unwrap();
-                (Span::new(start as u32, end as u32), TokenKind::BinAnd)
+                (Span::from(start..end + 1), TokenKind::BinAnd)
             }
             _ => {
-                let end = start;
-                (Span::new(start as u32, end as u32), TokenKind::And)
+                let end = start + 1;
+                (Span::from(start..end), TokenKind::And)
             }
         };
-        let token = Token::new(kind, span);
-        Ok(token)
+        Ok((Lexeme::Token(kind), span))
     }
 
-    fn greater_than(&mut self) -> Result<Token> {
+    // Arithmetic operators can be followed by '=' for compound assignment, like '+='.
+    fn operator(&mut self, kind: TokenKind, assignment_kind: TokenKind) -> Result<(Lexeme, Span)> {
         let (start, _) = self.chars.next().unwrap();
         let (span, kind) = match self.chars.peek() {
             Some((_, '=')) => {
                 let (end, _) = self.chars.next().unwrap();
-                (
-                    Span::new(start as u32, end as u32),
-                    TokenKind::GreaterThanEquals,
-                )
+                (Span::from(start..end + 1), assignment_kind)
+            }
+            _ => (Span::from(start..start + 1), kind),
+        };
+        Ok((Lexeme::Token(kind), span))
+    }
+
+    fn greater_than(&mut self) -> Result<(Lexeme, Span)> {
+        let (start, _) = self.chars.next().unwrap();
+        let (span, kind) = match self.chars.peek() {
+            Some((_, '=')) => {
+                let (end, _) = self.chars.next().unwrap();
+                (Span::from(start..end + 1), TokenKind::GreaterThanEquals)
             }
             _ => {
-                let end = start;
-                (Span::new(start as u32, end as u32), TokenKind::GreaterThan)
+                let end = start + 1;
+                (Span::from(start..end), TokenKind::GreaterThan)
             }
         };
-        let token = Token::new(kind, span);
-        Ok(token)
+        Ok((Lexeme::Token(kind), span))
     }
 
-    fn less_than(&mut self) -> Result<Token> {
+    /// Lex `|`, or the pipeline operator `|>`.
+    fn pipe(&mut self) -> Result<(Lexeme, Span)> {
+        let (start, _) = self.chars.next().unwrap();
+        let (span, kind) = match self.chars.peek() {
+            Some((_, '>')) => {
+                let (end, _) = self.chars.next().unwrap();
+                (Span::from(start..end + 1), TokenKind::Pipeline)
+            }
+            _ => (Span::from(start..start + 1), TokenKind::Pipe),
+        };
+        Ok((Lexeme::Token(kind), span))
+    }
+
+    fn less_than(&mut self) -> Result<(Lexeme, Span)> {
         let (start, _) = self.chars.next().unwrap();
         let (span, kind) = match self.chars.peek() {
             Some((_, '=')) => {
                 let (end, _) = self.chars.next().unwrap();
-                (
-                    Span::new(start as u32, end as u32),
-                    TokenKind::LessThanEquals,
-                )
+                (Span::from(start..end + 1), TokenKind::LessThanEquals)
             }
             _ => {
-                let end = start;
-                (Span::new(start as u32, end as u32), TokenKind::LessThan)
+                let end = start + 1;
+                (Span::from(start..end), TokenKind::LessThan)
             }
         };
-        let token = Token::new(kind, span);
-        Ok(token)
+        Ok((Lexeme::Token(kind), span))
     }
 
-    fn number(&mut self) -> Result<Token> {
+    fn number(&mut self) -> Result<(Lexeme, Span)> {
         let (start, _) = self.chars.next().unwrap();
-        let mut end = start;
+        let mut end = start + 1;
         let mut is_float = false;
         loop {
             match self.chars.peek() {
                 Some((i, ch)) => {
                     if ch.is_digit(10) || ch == &'_' {
-                        end = *i;
+                        end = i + 1;
                     } else if ch == &'.' {
                         if is_float {
                             // Check if the next char is a
-                            return multiple_decimal_in_number(Span::new(start as u32, end as u32));
+                            return multiple_decimal_in_number(Span::from(start..end));
                         }
                         is_float = true;
-                        end = *i;
+                        end = i + 1;
                     } else {
                         break;
                     }
@@ -321,32 +514,30 @@ impl<'s> Lexer<'s> {
             }
             self.chars.next();
         }
-        let span = Span::new(start as u32, end as u32);
-        let word = &self.source[start..end + 1];
-        let symbol = Symbol::intern(word);
-        let kind = TokenKind::Number(symbol);
-        let token = Token::new(kind, span);
-        Ok(token)
+        let span = Span::from(start..end);
+        Ok((Lexeme::Number, span))
     }
 
-    fn identifier(&mut self) -> Result<Token> {
-        let (start, _) = self.chars.next().unwrap();
-        let mut end = start;
+    fn identifier(&mut self) -> Result<(Lexeme, Span)> {
+        let (start, ch) = self.chars.next().unwrap();
+        // Where the last character ends, since it can be more than one byte
+        let mut end = start + ch.len_utf8();
         while let Some((i, ch)) = self.chars.peek() {
             if ch.is_xid_continue() {
-                end = *i;
+                end = i + ch.len_utf8();
                 self.chars.next();
                 continue;
             } else {
                 break;
             }
         }
-        let span = Span::new(start as u32, end as u32);
-        let word = &self.source[start..end + 1];
-        let kind = {
+        let span = Span::from(start..end);
+        let word = &self.source[start..end];
+        let lexeme = {
             use TokenKind::*;
-            match word {
+            let kind = match word {
                 "import" => Import,
+                "as" => As,
                 "if" => If,
                 "else" => Else,
                 "fn" => Fn,
@@ -358,11 +549,15 @@ impl<'s> Lexer<'s> {
                 "true" => True,
                 "false" => False,
                 "let" => Let,
+                "mut" => Mut,
                 "state" => State,
                 "component" => Component,
                 "enum" => Enum,
                 "struct" => Struct,
                 "const" => Const,
+                "context" => Context,
+                "provide" => Provide,
+                "use" => Use,
                 "pub" => Pub,
                 "return" => Return,
                 "type" => Type,
@@ -373,18 +568,17 @@ impl<'s> Lexer<'s> {
                 "number" => NumberType,
                 "string" => StringType,
                 "bool" => Boolean,
-                _ => {
-                    let symbol = Symbol::intern(word);
-                    Identifier(symbol)
-                }
-            }
+                // Identifiers are interned when they're read.
+                _ => return Ok((Lexeme::Identifier, span)),
+            };
+            Lexeme::Token(kind)
         };
-        Ok(Token::new(kind, span))
+        Ok((lexeme, span))
     }
 
-    fn punc(&mut self, kind: TokenKind) -> Result<Token> {
-        let (index, _) = self.chars.next().unwrap();
-        let span = Span::new(index as u32, index as u32);
-        Ok(Token { kind, span })
+    fn punc(&mut self, kind: TokenKind) -> Result<(Lexeme, Span)> {
+        let (index, ch) = self.chars.next().unwrap();
+        let span = Span::char(index, ch);
+        Ok((Lexeme::Token(kind), span))
     }
 }
@@ -1,4 +1,6 @@
+mod editing;
 mod lexer;
 mod token_stream;
+pub use editing::{attribute_name_at, close_tag, indent_closing_brace};
 pub use lexer::*;
-pub use token_stream::TokenStream;
\ No newline at end of file
+pub use token_stream::{Lexeme, TokenReader, TokenStream};
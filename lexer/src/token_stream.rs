@@ -1,29 +1,344 @@
-use syntax::token::Token;
+use crate::Lexer;
+use common::symbol::Symbol;
+use diagnostics::error::Error;
+use diagnostics::result::Result;
+use std::borrow::Cow;
+use std::collections::VecDeque;
+use std::ops::Range;
+use std::sync::Arc;
+use syntax::span::Span;
+use syntax::token::{Token, TokenKind};
 
+/// A token as it's kept in a `TokenStream`. Identifiers and literals don't
+/// hold a symbol, just their span, and are interned when they're read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lexeme {
+    Token(TokenKind),
+    Identifier,
+    String,
+    TemplateString,
+    Number,
+}
+
+/// The text of `span` in `source`.
+fn span_text(source: &str, span: Span) -> &str {
+    let range: Range<usize> = span.into();
+    &source[range]
+}
+
+impl Lexeme {
+    /// The kind of the token at `span` in `source`, interning its text if it has any.
+    pub fn kind(&self, source: &str, span: Span) -> TokenKind {
+        let range: Range<usize> = span.into();
+        let text = || span_text(source, span);
+        match self {
+            Lexeme::Token(kind) => kind.clone(),
+            Lexeme::Identifier => TokenKind::Identifier(Symbol::intern(text())),
+            // The span of a string includes its quotes.
+            Lexeme::String => {
+                TokenKind::String(Symbol::intern(&source[range.start + 1..range.end - 1]))
+            }
+            Lexeme::TemplateString => TokenKind::TemplateString(Symbol::intern(text())),
+            Lexeme::Number => TokenKind::Number(Symbol::intern(text())),
+        }
+    }
+}
+
+/// All the tokens in a source, lexed before parsing so they can be cached.
+/// Like comments, newlines are left out, but which tokens end a line is
+/// kept since that's where statements end.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TokenStream {
-    tokens: Vec<Token>,
+    /// Identifiers and literals are read from here instead of being copied.
+    source: Arc<str>,
+    lexemes: Vec<Lexeme>,
+    spans: Vec<Span>,
+    /// Whether each token is the last one on its line.
+    ends_line: Vec<bool>,
+    /// Where the stream ends: either the end of the source, or the error
+    /// that stopped lexing.
+    end: Span,
+    error: Option<Error>,
 }
 
 impl TokenStream {
+    pub fn lex(source: &str) -> Self {
+        Lexer::new(source).lex()
+    }
+
     /// Allocate a TokenStream with an estimated capacity
     /// for the source string.
     pub fn for_source(string: &str) -> Self {
         let len = string.len();
         let capacity = (len / 3).next_power_of_two();
-        let tokens = Vec::with_capacity(capacity);
-        TokenStream { tokens }
+        TokenStream {
+            source: string.into(),
+            lexemes: Vec::with_capacity(capacity),
+            spans: Vec::with_capacity(capacity),
+            ends_line: Vec::with_capacity(capacity),
+            end: Span::empty(0),
+            error: None,
+        }
+    }
+
+    pub fn push(&mut self, lexeme: Lexeme, span: Span) {
+        if lexeme == Lexeme::Token(TokenKind::Newline) {
+            if let Some(ends_line) = self.ends_line.last_mut() {
+                *ends_line = true;
+            }
+            return;
+        }
+        self.lexemes.push(lexeme);
+        self.spans.push(span);
+        self.ends_line.push(false);
+    }
+
+    /// End the stream at `end`, or at `error` if lexing failed.
+    pub fn finish(self, end: Span, error: Option<Error>) -> Self {
+        let end = match &error {
+            Some(Error::Diagnostic(diagnostic)) => match diagnostic.primary_range() {
+                Some(range) => Span::from(range),
+                None => end,
+            },
+            _ => end,
+        };
+        TokenStream { end, error, ..self }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lexemes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lexemes.is_empty()
+    }
+
+    fn span(&self, index: usize) -> Span {
+        self.spans.get(index).copied().unwrap_or(self.end)
+    }
+
+    fn text(&self, span: Span) -> &str {
+        span_text(&self.source, span)
+    }
+
+    /// The indices of the tokens that start within `range` of the source.
+    pub fn token_range(&self, range: Range<usize>) -> Range<usize> {
+        let starting_before = |offset: usize| {
+            self.spans.partition_point(|span| {
+                let span: Range<usize> = (*span).into();
+                span.start < offset
+            })
+        };
+        starting_before(range.start)..starting_before(range.end)
+    }
+
+    /// Map a range taken from the spans of an indexed reader back to the
+    /// source. The range covers the tokens from `range.start` up to
+    /// `range.end`, and an empty one maps to the start of its token.
+    pub fn source_range(&self, range: Range<usize>) -> Range<usize> {
+        let start = self.span(range.start);
+        if range.is_empty() {
+            return start.start()..start.start();
+        }
+        start.start()..self.span(range.end - 1).end()
+    }
+
+    /// The one-based line each token is on, followed by the line the stream ends on.
+    pub fn lines(&self) -> Vec<usize> {
+        let mut line = 1;
+        let mut offset = 0;
+        self.spans
+            .iter()
+            .chain(std::iter::once(&self.end))
+            .map(|span| {
+                let range: Range<usize> = (*span).into();
+                let start = range.start.min(self.source.len()).max(offset);
+                line += self.source[offset..start].matches('\n').count();
+                offset = start;
+                line
+            })
+            .collect()
     }
 
-    pub fn push(&mut self, token: Token) {
-        self.tokens.push(token)
+    /// Whether both streams have the same tokens, wherever they are in the
+    /// source. Editing whitespace or comments doesn't change the tokens,
+    /// unless it moves one onto another line.
+    pub fn same_tokens(&self, other: &TokenStream) -> bool {
+        self.lexemes == other.lexemes
+            && self.ends_line == other.ends_line
+            && self.error == other.error
+            && self
+                .lexemes
+                .iter()
+                .zip(self.spans.iter().zip(&other.spans))
+                .all(|(lexeme, (span, other_span))| match lexeme {
+                    Lexeme::Token(_) => true,
+                    _ => self.text(*span) == other.text(*other_span),
+                })
+    }
+
+    /// Read the tokens, spanned by where they are in the source.
+    pub fn into_reader(self) -> TokenReader<'static> {
+        TokenReader::new(Cow::Owned(self), false)
+    }
+
+    /// Read the tokens, spanned by their index in the stream. Anything built
+    /// from them then doesn't depend on where the tokens are, and
+    /// `source_range` maps the spans back to the source.
+    pub fn indexed_reader(&self) -> TokenReader<'_> {
+        TokenReader::new(Cow::Borrowed(self), true)
     }
 }
 
-impl IntoIterator for TokenStream {
-    type Item = Token;
-    type IntoIter = std::vec::IntoIter<Self::Item>;
-    fn into_iter(self) -> Self::IntoIter {
-        self.tokens.into_iter()
+/// Reads the tokens in a `TokenStream` one at a time, like a `Lexer`.
+pub struct TokenReader<'a> {
+    stream: Cow<'a, TokenStream>,
+    position: usize,
+    /// Tokens that were peeked, and whether each one starts a line
+    lookahead: VecDeque<(Token, bool)>,
+    indexed: bool,
+}
+
+impl<'a> TokenReader<'a> {
+    fn new(stream: Cow<'a, TokenStream>, indexed: bool) -> Self {
+        TokenReader {
+            stream,
+            position: 0,
+            lookahead: VecDeque::with_capacity(2),
+            indexed,
+        }
+    }
+
+    pub fn next_token(&mut self) -> Result<Token> {
+        match self.lookahead.pop_front() {
+            Some((token, _)) => Ok(token),
+            None => Ok(self.read()?.0),
+        }
+    }
+
+    pub fn peek(&mut self) -> Result<&Token> {
+        self.peek_nth(0)
+    }
+
+    /// Look `n` tokens past the next one without consuming any.
+    pub fn peek_nth(&mut self, n: usize) -> Result<&Token> {
+        while self.lookahead.len() <= n {
+            let token = self.read()?;
+            self.lookahead.push_back(token);
+        }
+        Ok(&self.lookahead[n].0)
+    }
+
+    /// Whether the next token is the first one on its line. The end of the
+    /// stream always is.
+    pub fn next_starts_line(&mut self) -> Result<bool> {
+        self.peek()?;
+        Ok(self.lookahead[0].1)
     }
-}
\ No newline at end of file
+
+    /// Read the next token, and whether it starts a line.
+    fn read(&mut self) -> Result<(Token, bool)> {
+        let index = self.position;
+        let stream = &*self.stream;
+        let span = if self.indexed {
+            Span::from(index..index + 1)
+        } else {
+            stream.span(index)
+        };
+        match (stream.lexemes.get(index), &stream.error) {
+            (Some(lexeme), _) => {
+                self.position += 1;
+                let kind = lexeme.kind(&stream.source, stream.spans[index]);
+                let starts_line = index == 0 || stream.ends_line[index - 1];
+                Ok((Token::new(kind, span), starts_line))
+            }
+            (None, Some(Error::Diagnostic(diagnostic))) if self.indexed => {
+                let diagnostic = diagnostic.clone().map_ranges(|_| span.into());
+                Err(Error::Diagnostic(diagnostic))
+            }
+            (None, Some(error)) => Err(error.clone()),
+            (None, None) => Ok((Token::new(TokenKind::EOF, span), true)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Lexeme, TokenStream};
+
+    fn lexemes(source: &str) -> Vec<Lexeme> {
+        TokenStream::lex(source).lexemes
+    }
+
+    #[test]
+    fn lex_templates_without_parser() {
+        use syntax::token::TokenKind::*;
+        let tokens = lexemes("a < b\nreturn <p class={a > b}>{a} and b</p>");
+        let expected = vec![
+            Lexeme::Identifier,
+            Lexeme::Token(LessThan),
+            Lexeme::Identifier,
+            Lexeme::Token(Return),
+            Lexeme::Token(LessThan),
+            Lexeme::Identifier,
+            Lexeme::Identifier,
+            Lexeme::Token(Equals),
+            Lexeme::Token(LBrace),
+            Lexeme::Identifier,
+            Lexeme::Token(GreaterThan),
+            Lexeme::Identifier,
+            Lexeme::Token(RBrace),
+            Lexeme::Token(GreaterThan),
+            Lexeme::Token(LBrace),
+            Lexeme::Identifier,
+            Lexeme::Token(RBrace),
+            Lexeme::TemplateString,
+            Lexeme::Token(LessThan),
+            Lexeme::Token(Slash),
+            Lexeme::Identifier,
+            Lexeme::Token(GreaterThan),
+        ];
+        assert_eq!(tokens, expected);
+    }
+
+    #[test]
+    fn trivia_keeps_same_tokens() {
+        let tokens = TokenStream::lex("let a = \"b\" # comment\nlet c = 1");
+        let moved = TokenStream::lex("let  a = \"b\"\n\n  let c = 1 # comment");
+        let changed = TokenStream::lex("let a = \"c\"\nlet c = 1");
+        assert_ne!(tokens, moved);
+        assert!(tokens.same_tokens(&moved));
+        assert!(!tokens.same_tokens(&changed));
+        let joined = TokenStream::lex("let a = \"b\" let c = 1");
+        assert!(!tokens.same_tokens(&joined));
+    }
+
+    #[test]
+    fn spans_end_after_last_character() {
+        let source = "let café = \"crème\" >= 1.5\n<p>héllo</p>";
+        let tokens = TokenStream::lex(source);
+        let text: Vec<&str> = tokens.spans.iter().map(|span| tokens.text(*span)).collect();
+        assert_eq!(
+            text,
+            vec![
+                "let",
+                "café",
+                "=",
+                "\"crème\"",
+                ">=",
+                "1.5",
+                "<",
+                "p",
+                ">",
+                "héllo",
+                "<",
+                "/",
+                "p",
+                ">"
+            ]
+        );
+        // Indexed spans cover one token each, and map back to the source
+        assert_eq!(&source[tokens.source_range(1..4)], "café = \"crème\"");
+        assert_eq!(tokens.source_range(2..2), 10..10);
+    }
+}
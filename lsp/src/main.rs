@@ -1,37 +1,60 @@
-use log::info;
-use lsp_server::Connection;
-use lsp_types::ServerCapabilities;
+use db::{
+    utf16_line_column, utf16_offset, CompilerEventSink, FileSystem, FoldKind, LineColumn, Parser,
+    Phase,
+};
+use diagnostics::error::{Diagnostic, Severity};
+use log::{debug, info};
+use lsp_server::{Connection, Message, Notification, Request, Response};
+use lsp_types::notification::{Notification as _, PublishDiagnostics, ShowMessage};
+use lsp_types::{
+    CodeLens, CodeLensOptions, Command, CompletionItem, CompletionItemKind, CompletionOptions,
+    CompletionResponse, DiagnosticSeverity, DocumentOnTypeFormattingOptions, FoldingRange,
+    FoldingRangeKind, FoldingRangeProviderCapability, InitializeParams, Location, MessageType,
+    ParameterInformation, ParameterLabel, Position, PublishDiagnosticsParams, Range,
+    SelectionRange, SelectionRangeProviderCapability, ServerCapabilities, ShowMessageParams,
+    SignatureHelp, SignatureHelpOptions, SignatureInformation, TextDocumentContentChangeEvent,
+    TextDocumentSyncCapability, TextDocumentSyncKind, TextEdit, Url, WorkspaceCapability,
+    WorkspaceFolderCapability, WorkspaceFolderCapabilityChangeNotifications,
+};
+use std::collections::HashMap;
 use std::error::Error;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use workspace::{Settings, Workspaces};
+
+mod workspace;
 
 type Result<T> = std::result::Result<T, Box<dyn Error + Sync + Send>>;
 
 fn server_capabilities() -> serde_json::Value {
     // use lsp_types::{
     //     HoverProviderCapability,
-    //     SelectionRangeProviderCapability,
-    //     TextDocumentSyncCapability,
     //     CompletionCapability,
-    //     SignatureHelpCapability,
     //     TypeDefinitionProviderCapability,
     //     ImplementationProviderCapability,
     //     CodeActionProviderCapability,
-    //     CodeLensOptions,
-    //     DocumentOnTypeFormattingOptions,
     //     RenameProviderCapability,
     //     DocumentLinkOptions,
     //     ColorProviderCapability,
-    //     FoldingRangeProviderCapability,
     //     ExecuteCommandOptions,
-    //     WorkspaceCapability,
     //     SemanticHighlightingServerCapability,
     //     CallHierarchyServerCapability
     // };
     let capabilities = ServerCapabilities {
-        text_document_sync: None,
-        selection_range_provider: None,
+        text_document_sync: Some(TextDocumentSyncCapability::Kind(
+            TextDocumentSyncKind::Incremental,
+        )),
+        selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
         hover_provider: None,
-        completion_provider: None,
-        signature_help_provider: None,
+        completion_provider: Some(CompletionOptions {
+            trigger_characters: Some(vec![" ".to_string()]),
+            ..Default::default()
+        }),
+        signature_help_provider: Some(SignatureHelpOptions {
+            trigger_characters: Some(vec!["(".to_string(), ",".to_string()]),
+            retrigger_characters: None,
+            work_done_progress_options: Default::default(),
+        }),
         definition_provider: None,
         type_definition_provider: None,
         implementation_provider: None,
@@ -40,17 +63,29 @@ fn server_capabilities() -> serde_json::Value {
         document_symbol_provider: None,
         workspace_symbol_provider: None,
         code_action_provider: None,
-        code_lens_provider: None,
+        code_lens_provider: Some(CodeLensOptions {
+            resolve_provider: Some(false),
+        }),
         document_formatting_provider: None,
         document_range_formatting_provider: None,
-        document_on_type_formatting_provider: None,
+        document_on_type_formatting_provider: Some(DocumentOnTypeFormattingOptions {
+            first_trigger_character: "}".to_string(),
+            more_trigger_character: Some(vec!["/".to_string()]),
+        }),
         rename_provider: None,
         document_link_provider: None,
         color_provider: None,
-        folding_range_provider: None,
+        folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
         declaration_provider: None,
         execute_command_provider: None,
-        workspace: None,
+        workspace: Some(WorkspaceCapability {
+            workspace_folders: Some(WorkspaceFolderCapability {
+                supported: Some(true),
+                change_notifications: Some(WorkspaceFolderCapabilityChangeNotifications::Bool(
+                    true,
+                )),
+            }),
+        }),
         experimental: None,
         semantic_highlighting: None,
         call_hierarchy_provider: None,
@@ -59,6 +94,23 @@ fn server_capabilities() -> serde_json::Value {
     serde_json::to_value(capabilities).unwrap()
 }
 
+/// Keeps the warnings reported while compiling, so they can be published
+/// along with any errors.
+#[derive(Clone, Default)]
+pub struct LspEventSink {
+    warnings: Arc<Mutex<Vec<Diagnostic>>>,
+}
+
+impl CompilerEventSink for LspEventSink {
+    fn phase_started(&self, path: &Path, phase: Phase) {
+        info!("{:?}: {}", phase, path.display());
+    }
+
+    fn diagnostic(&self, _path: &Path, _source: &str, diagnostic: &Diagnostic) {
+        self.warnings.lock().unwrap().push(diagnostic.clone());
+    }
+}
+
 fn main() -> Result<()> {
     flexi_logger::Logger::with_str("info").start().unwrap();
     info!("Starting LSP server");
@@ -70,8 +122,549 @@ fn main() -> Result<()> {
     Ok(())
 }
 
-fn main_loop(_connection: &Connection, _params: serde_json::Value) -> Result<()> {
+fn main_loop(connection: &Connection, params: serde_json::Value) -> Result<()> {
     info!("Starting LSP server loop");
+    let mut workspaces = Workspaces::default();
+    let mut settings = Settings::default();
+    if let Ok(params) = serde_json::from_value::<InitializeParams>(params) {
+        if let Some(options) = &params.initialization_options {
+            settings = Settings::from_json(options);
+        }
+        // Clients that don't support workspace folders send a single root
+        let folders: Vec<Url> = match params.workspace_folders {
+            Some(folders) => folders.into_iter().map(|folder| folder.uri).collect(),
+            None => params.root_uri.into_iter().collect(),
+        };
+        for uri in folders {
+            if let Err(error) = workspaces.add(&uri) {
+                show_error(connection, error)?;
+            }
+        }
+    }
+    // The text of each open document, kept up to date with every change
+    let mut documents: HashMap<Url, String> = HashMap::new();
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    return Ok(());
+                }
+                if let Some(response) = handle_request(&documents, &settings, request) {
+                    connection.sender.send(Message::Response(response))?;
+                }
+            }
+            Message::Notification(notification) => {
+                let notification = match handle_workspace_notification(
+                    connection,
+                    &mut workspaces,
+                    &mut settings,
+                    notification,
+                )? {
+                    Some(notification) => notification,
+                    // Every open document is compiled again with the new
+                    // folders or settings
+                    None => {
+                        for (uri, source) in &documents {
+                            publish_diagnostics(
+                                connection,
+                                &mut workspaces,
+                                &settings,
+                                uri,
+                                source,
+                            )?;
+                        }
+                        continue;
+                    }
+                };
+                if let Some(uri) =
+                    handle_notification(&mut documents, &mut workspaces, notification)
+                {
+                    let source = &documents[&uri];
+                    publish_diagnostics(connection, &mut workspaces, &settings, &uri, source)?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Compile a document and send the client its errors and warnings.
+fn publish_diagnostics(
+    connection: &Connection,
+    workspaces: &mut Workspaces,
+    settings: &Settings,
+    uri: &Url,
+    source: &str,
+) -> Result<()> {
+    let diagnostics = compile(workspaces, settings, uri, source);
+    let params = PublishDiagnosticsParams::new(uri.clone(), diagnostics, None);
+    let notification = Notification::new(PublishDiagnostics::METHOD.into(), params);
+    connection
+        .sender
+        .send(Message::Notification(notification))?;
     Ok(())
-    // ...
+}
+
+/// Show an error to the user, like one in a `ws.toml`.
+fn show_error(connection: &Connection, message: String) -> Result<()> {
+    let params = ShowMessageParams {
+        typ: MessageType::Error,
+        message,
+    };
+    let notification = Notification::new(ShowMessage::METHOD.into(), params);
+    connection
+        .sender
+        .send(Message::Notification(notification))?;
+    Ok(())
+}
+
+/// Compile a document in the workspace it's in, returning its errors and
+/// warnings. Only the definitions whose tokens changed since it was last
+/// compiled are compiled again, so this stays fast while typing.
+fn compile(
+    workspaces: &mut Workspaces,
+    settings: &Settings,
+    uri: &Url,
+    source: &str,
+) -> Vec<lsp_types::Diagnostic> {
+    let path = match uri.to_file_path() {
+        Ok(path) => path,
+        Err(_) => return vec![],
+    };
+    let workspace = workspaces.get_mut(&path);
+    let source_text = Arc::new(source.to_string());
+    workspace
+        .db
+        .set_file_text(path.clone(), source_text.clone());
+    // Modules that import this one see the unsaved text too
+    workspace
+        .db
+        .set_overlay_file(path.clone(), Some(source_text));
+    let compiled = workspace.db.compile_module(path);
+    debug!("{:?}", workspace.db.compilation_stats());
+    let mut diagnostics = std::mem::take(&mut *workspace.events.warnings.lock().unwrap());
+    if let Err(error) = compiled {
+        diagnostics.extend(error.diagnostics().iter().cloned());
+    }
+    diagnostics
+        .iter()
+        .filter_map(|diagnostic| {
+            let range = diagnostic.primary_range().unwrap_or(0..0);
+            let end = range.end.min(source.len());
+            let start = range.start.min(end);
+            let severity = match diagnostic.severity() {
+                Severity::Error => DiagnosticSeverity::Error,
+                Severity::Warning => settings.warning_severity?,
+            };
+            let message = match diagnostic.primary_message() {
+                Some(label) if !label.is_empty() => {
+                    format!("{}: {}", diagnostic.message(), label)
+                }
+                _ => diagnostic.message().to_string(),
+            };
+            let range = Range::new(position(source, start), position(source, end));
+            Some(lsp_types::Diagnostic {
+                severity: Some(severity),
+                ..lsp_types::Diagnostic::new_simple(range, message)
+            })
+        })
+        .collect()
+}
+
+/// Apply a change from the client to the text of a document. Changes with
+/// a range replace just that range, others replace the whole text.
+fn apply_change(text: &mut String, change: TextDocumentContentChangeEvent) {
+    match change.range {
+        Some(range) => {
+            let start = offset(text, range.start);
+            let end = offset(text, range.end).max(start);
+            text.replace_range(start..end, &change.text);
+        }
+        None => *text = change.text,
+    }
+}
+
+/// Keep the text of open documents up to date, returning the document that
+/// changed, if one did. Closed documents are read from disk again.
+fn handle_notification(
+    documents: &mut HashMap<Url, String>,
+    workspaces: &mut Workspaces,
+    notification: Notification,
+) -> Option<Url> {
+    use lsp_types::notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _,
+    };
+    let notification = match notification
+        .extract::<lsp_types::DidOpenTextDocumentParams>(DidOpenTextDocument::METHOD)
+    {
+        Ok(params) => {
+            let document = params.text_document;
+            documents.insert(document.uri.clone(), document.text);
+            return Some(document.uri);
+        }
+        Err(notification) => notification,
+    };
+    let notification = match notification
+        .extract::<lsp_types::DidChangeTextDocumentParams>(DidChangeTextDocument::METHOD)
+    {
+        // Changes are made one after the other, each to the text as the
+        // ones before it left it
+        Ok(params) => {
+            let uri = params.text_document.uri;
+            let text = documents.get_mut(&uri)?;
+            for change in params.content_changes {
+                apply_change(text, change);
+            }
+            return Some(uri);
+        }
+        Err(notification) => notification,
+    };
+    if let Ok(params) =
+        notification.extract::<lsp_types::DidCloseTextDocumentParams>(DidCloseTextDocument::METHOD)
+    {
+        let uri = params.text_document.uri;
+        documents.remove(&uri);
+        if let Ok(path) = uri.to_file_path() {
+            let db = &mut workspaces.get_mut(&path).db;
+            // Nothing from the file is needed until it's opened again
+            db.release_symbols(&path);
+            db.set_overlay_file(path, None);
+        }
+    }
+    None
+}
+
+/// Follow changes to the workspace folders and settings, handing back any
+/// other notification.
+fn handle_workspace_notification(
+    connection: &Connection,
+    workspaces: &mut Workspaces,
+    settings: &mut Settings,
+    notification: Notification,
+) -> Result<Option<Notification>> {
+    use lsp_types::notification::{DidChangeConfiguration, DidChangeWorkspaceFolders};
+    let notification = match notification
+        .extract::<lsp_types::DidChangeConfigurationParams>(DidChangeConfiguration::METHOD)
+    {
+        Ok(params) => {
+            *settings = Settings::from_json(&params.settings);
+            // Clients aren't asked to watch `ws.toml`, so changing the
+            // settings is also when it's read again
+            for error in workspaces.reload() {
+                show_error(connection, error)?;
+            }
+            return Ok(None);
+        }
+        Err(notification) => notification,
+    };
+    match notification
+        .extract::<lsp_types::DidChangeWorkspaceFoldersParams>(DidChangeWorkspaceFolders::METHOD)
+    {
+        Ok(params) => {
+            for folder in params.event.removed {
+                workspaces.remove(&folder.uri);
+            }
+            for folder in params.event.added {
+                if let Err(error) = workspaces.add(&folder.uri) {
+                    show_error(connection, error)?;
+                }
+            }
+            Ok(None)
+        }
+        Err(notification) => Ok(Some(notification)),
+    }
+}
+
+fn handle_request(
+    documents: &HashMap<Url, String>,
+    settings: &Settings,
+    request: Request,
+) -> Option<Response> {
+    use lsp_types::request::{
+        CodeLensRequest, Completion, FoldingRangeRequest, OnTypeFormatting, Request as _,
+        SelectionRangeRequest, SignatureHelpRequest,
+    };
+    let request =
+        match request.extract::<lsp_types::FoldingRangeParams>(FoldingRangeRequest::METHOD) {
+            Ok((id, params)) => {
+                let ranges = documents
+                    .get(&params.text_document.uri)
+                    .and_then(|source| folding_ranges(source));
+                return Some(Response::new_ok(id, ranges));
+            }
+            Err(request) => request,
+        };
+    let request =
+        match request.extract::<lsp_types::SelectionRangeParams>(SelectionRangeRequest::METHOD) {
+            Ok((id, params)) => {
+                let ranges = documents.get(&params.text_document.uri).and_then(|source| {
+                    params
+                        .positions
+                        .iter()
+                        .map(|position| selection_range(source, *position))
+                        .collect::<Option<Vec<_>>>()
+                });
+                return Some(Response::new_ok(id, ranges));
+            }
+            Err(request) => request,
+        };
+    let request =
+        match request.extract::<lsp_types::SignatureHelpParams>(SignatureHelpRequest::METHOD) {
+            Ok((id, params)) => {
+                let params = params.text_document_position_params;
+                let help = documents
+                    .get(&params.text_document.uri)
+                    .and_then(|source| signature_help(source, params.position));
+                return Some(Response::new_ok(id, help));
+            }
+            Err(request) => request,
+        };
+    let request = match request.extract::<lsp_types::CompletionParams>(Completion::METHOD) {
+        Ok((id, params)) => {
+            let params = params.text_document_position;
+            let completions = documents
+                .get(&params.text_document.uri)
+                .and_then(|source| attribute_completions(source, params.position));
+            return Some(Response::new_ok(id, completions));
+        }
+        Err(request) => request,
+    };
+    let request = match request.extract::<lsp_types::CodeLensParams>(CodeLensRequest::METHOD) {
+        Ok((id, params)) => {
+            let uri = params.text_document.uri;
+            let lenses = documents
+                .get(&uri)
+                .and_then(|source| code_lenses(&uri, source));
+            return Some(Response::new_ok(id, lenses));
+        }
+        Err(request) => request,
+    };
+    match request.extract::<lsp_types::DocumentOnTypeFormattingParams>(OnTypeFormatting::METHOD) {
+        Ok((id, params)) => {
+            let params = params.text_document_position;
+            let edits = documents
+                .get(&params.text_document.uri)
+                .and_then(|source| on_type_edit(source, settings, params.position))
+                .map(|edit| vec![edit]);
+            Some(Response::new_ok(id, edits))
+        }
+        Err(_) => None,
+    }
+}
+
+/// Completes the close tag after a `</`, or lines up a `}` with its `{`,
+/// after one was typed just before `cursor`, unless the settings turn
+/// that off.
+fn on_type_edit(source: &str, settings: &Settings, cursor: Position) -> Option<TextEdit> {
+    let offset = offset(source, cursor);
+    if settings.close_tags {
+        if let Some(close_tag) = db::close_tag(source, offset) {
+            return Some(TextEdit::new(Range::new(cursor, cursor), close_tag));
+        }
+    }
+    if !settings.align_braces {
+        return None;
+    }
+    let (range, indent) = db::indent_closing_brace(source, offset)?;
+    let range = Range::new(position(source, range.start), position(source, range.end));
+    Some(TextEdit::new(range, indent))
+}
+
+/// The attributes, and then the event handlers, that could be what's being
+/// typed at `cursor`, if it's in an open tag.
+fn attribute_completions(source: &str, cursor: Position) -> Option<CompletionResponse> {
+    let range = db::attribute_name_at(source, offset(source, cursor))?;
+    let typed = &source[range];
+    let attributes = db::KNOWN_ATTRIBUTES.iter().map(|attribute| CompletionItem {
+        label: attribute.name.to_string(),
+        kind: Some(CompletionItemKind::Property),
+        detail: Some(if attribute.boolean {
+            "boolean attribute".to_string()
+        } else {
+            "attribute".to_string()
+        }),
+        ..Default::default()
+    });
+    let handlers = db::EVENT_HANDLERS.iter().map(|handler| CompletionItem {
+        label: handler.attribute.to_string(),
+        kind: Some(CompletionItemKind::Event),
+        detail: Some(format!("{} handler", handler.type_.name())),
+        ..Default::default()
+    });
+    let items = attributes
+        .chain(handlers)
+        .filter(|item| item.label.starts_with(typed))
+        .collect();
+    Some(CompletionResponse::Array(items))
+}
+
+/// Lenses above each function, with how many times it's referenced and the
+/// value it always returns, if it does.
+fn code_lenses(uri: &Url, source: &str) -> Option<Vec<CodeLens>> {
+    let range = |range: std::ops::Range<usize>| {
+        Range::new(position(source, range.start), position(source, range.end))
+    };
+    let mut lenses = vec![];
+    for lens in db::function_lenses(source).ok()? {
+        let name = range(lens.name);
+        let locations: Vec<Location> = lens
+            .references
+            .into_iter()
+            .map(|reference| Location::new(uri.clone(), range(reference)))
+            .collect();
+        let title = match locations.len() {
+            1 => "1 reference".to_string(),
+            count => format!("{} references", count),
+        };
+        // Clients show references with this command, given where to show
+        // them and the locations to list
+        let arguments = vec![
+            serde_json::to_value(uri).ok()?,
+            serde_json::to_value(name.start).ok()?,
+            serde_json::to_value(locations).ok()?,
+        ];
+        lenses.push(CodeLens {
+            range: name,
+            command: Some(Command::new(
+                title,
+                "editor.action.showReferences".to_string(),
+                Some(arguments),
+            )),
+            data: None,
+        });
+        if let Some(value) = lens.returns {
+            // Lenses without a command to run are only labels
+            lenses.push(CodeLens {
+                range: name,
+                command: Some(Command::new(
+                    format!("always returns {}", value),
+                    String::new(),
+                    None,
+                )),
+                data: None,
+            });
+        }
+    }
+    Some(lenses)
+}
+
+/// Help with the signature of the function being called at `cursor`.
+fn signature_help(source: &str, cursor: Position) -> Option<SignatureHelp> {
+    let signature = db::signature_help(source, offset(source, cursor)).ok()??;
+    // Parameters are found by their UTF-16 offsets in the label
+    let utf16 = |offset: usize| signature.label[..offset].encode_utf16().count();
+    let parameters = signature
+        .parameters
+        .iter()
+        .map(|range| ParameterInformation {
+            label: ParameterLabel::LabelOffsets([utf16(range.start) as _, utf16(range.end) as _]),
+            documentation: None,
+        })
+        .collect();
+    Some(SignatureHelp {
+        active_parameter: signature.active_parameter.map(|index| index as _),
+        signatures: vec![SignatureInformation {
+            label: signature.label.clone(),
+            documentation: None,
+            parameters: Some(parameters),
+        }],
+        active_signature: Some(0),
+    })
+}
+
+/// Folding ranges for a document, or none if it doesn't parse.
+fn folding_ranges(source: &str) -> Option<Vec<FoldingRange>> {
+    let folds = db::folding_ranges(source).ok()?;
+    let ranges = folds
+        .into_iter()
+        .map(|fold| {
+            let start = position(source, fold.range.start);
+            let end = position(source, fold.range.end);
+            let kind = match fold.kind {
+                FoldKind::Imports => FoldingRangeKind::Imports,
+                FoldKind::Region => FoldingRangeKind::Region,
+            };
+            FoldingRange {
+                start_line: start.line,
+                start_character: Some(start.character),
+                end_line: end.line,
+                end_character: Some(end.character),
+                kind: Some(kind),
+            }
+        })
+        .collect();
+    Some(ranges)
+}
+
+/// The selection ranges around `cursor`, each one the parent of the one
+/// inside it.
+fn selection_range(source: &str, cursor: Position) -> Option<SelectionRange> {
+    let ranges = db::selection_ranges(source, offset(source, cursor)).ok()?;
+    let range = ranges
+        .into_iter()
+        .rev()
+        .fold(None, |parent, range| {
+            Some(SelectionRange {
+                range: Range::new(position(source, range.start), position(source, range.end)),
+                parent: parent.map(Box::new),
+            })
+        })
+        .unwrap_or(SelectionRange {
+            range: Range::new(cursor, cursor),
+            parent: None,
+        });
+    Some(range)
+}
+
+/// The line and UTF-16 character of a byte offset in `source`.
+fn position(source: &str, offset: usize) -> Position {
+    let LineColumn { line, column } = utf16_line_column(source, offset);
+    Position::new(line as _, column as _)
+}
+
+/// The byte offset of a line and UTF-16 character in `source`.
+fn offset(source: &str, position: Position) -> usize {
+    let position = LineColumn {
+        line: position.line as usize,
+        column: position.character as usize,
+    };
+    utf16_offset(source, position)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::apply_change;
+    use lsp_types::{Position, Range, TextDocumentContentChangeEvent};
+
+    fn edit(text: &str, range: Option<Range>, new_text: &str) -> String {
+        let mut text = text.to_string();
+        let change = TextDocumentContentChangeEvent {
+            range,
+            range_length: None,
+            text: new_text.to_string(),
+        };
+        apply_change(&mut text, change);
+        text
+    }
+
+    #[test]
+    fn apply_changes() {
+        let source = "let a = 1\nlet b = 2";
+        // Inserting at a position
+        let insert = Range::new(Position::new(1, 4), Position::new(1, 4));
+        assert_eq!(edit(source, Some(insert), "c"), "let a = 1\nlet cb = 2");
+        // Deleting a range
+        let delete = Range::new(Position::new(0, 3), Position::new(1, 3));
+        assert_eq!(edit(source, Some(delete), ""), "let b = 2");
+        // Changes without a range replace the whole text
+        assert_eq!(edit(source, None, "let c = 3"), "let c = 3");
+        // Characters are UTF-16 code units, so the emoji counts for two
+        let source = "let 😀 = \"é\"\nlet b = 2";
+        let replace = Range::new(Position::new(0, 10), Position::new(0, 11));
+        assert_eq!(
+            edit(source, Some(replace), "e"),
+            "let 😀 = \"e\"\nlet b = 2"
+        );
+    }
 }
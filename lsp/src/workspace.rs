@@ -0,0 +1,178 @@
+//! The folders open in the client, each compiled in its own database with
+//! the options from its `ws.toml`, and the settings the client sends.
+use crate::LspEventSink;
+use db::{CodegenOptions, Database, Parser, ProjectConfig, PROJECT_FILE};
+use log::warn;
+use lsp_types::{DiagnosticSeverity, Url};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+
+/// A folder open in the client.
+pub struct Workspace {
+    /// Where the folder is, or `None` for the workspace that documents
+    /// outside of every folder are compiled in.
+    pub root: Option<PathBuf>,
+    pub db: Database,
+    pub events: LspEventSink,
+}
+
+impl Workspace {
+    fn new(root: Option<PathBuf>) -> Workspace {
+        let events = LspEventSink::default();
+        let db = Database::with_event_sink(events.clone());
+        Workspace { root, db, events }
+    }
+
+    /// Read the options in the root's `ws.toml` into the database. Folders
+    /// without one, or with one that can't be read, use the defaults.
+    fn load_config(&mut self) -> Result<(), String> {
+        let path = match &self.root {
+            Some(root) => root.join(PROJECT_FILE),
+            None => return Ok(()),
+        };
+        let config = match std::fs::read_to_string(&path) {
+            Ok(text) => ProjectConfig::parse(&text),
+            Err(_) => Ok(ProjectConfig::default()),
+        };
+        match config {
+            Ok(config) => {
+                self.db.set_codegen_options(config.options);
+                Ok(())
+            }
+            Err(error) => {
+                self.db.set_codegen_options(CodegenOptions::default());
+                Err(format!("{}: {}", path.display(), error))
+            }
+        }
+    }
+}
+
+/// Every open folder, and a workspace for the documents outside of them.
+pub struct Workspaces {
+    folders: Vec<Workspace>,
+    fallback: Workspace,
+}
+
+impl Default for Workspaces {
+    fn default() -> Self {
+        Workspaces {
+            folders: vec![],
+            fallback: Workspace::new(None),
+        }
+    }
+}
+
+impl Workspaces {
+    /// Open the folder at `uri`, returning the error from its `ws.toml` if
+    /// there is one. Folders that aren't on disk are ignored.
+    pub fn add(&mut self, uri: &Url) -> Result<(), String> {
+        let root = match uri.to_file_path() {
+            Ok(root) => root,
+            Err(_) => return Ok(()),
+        };
+        if self
+            .folders
+            .iter()
+            .any(|folder| folder.root.as_ref() == Some(&root))
+        {
+            return Ok(());
+        }
+        let mut folder = Workspace::new(Some(root));
+        let loaded = folder.load_config();
+        self.folders.push(folder);
+        loaded
+    }
+
+    /// Close the folder at `uri`. Its open documents move to whichever
+    /// workspace contains them next, or the fallback.
+    pub fn remove(&mut self, uri: &Url) {
+        if let Ok(root) = uri.to_file_path() {
+            self.folders
+                .retain(|folder| folder.root.as_ref() != Some(&root));
+        }
+    }
+
+    /// Read every folder's `ws.toml` again, returning the errors in them.
+    pub fn reload(&mut self) -> Vec<String> {
+        self.folders
+            .iter_mut()
+            .filter_map(|folder| folder.load_config().err())
+            .collect()
+    }
+
+    /// The workspace `path` is compiled in: the innermost folder it's in,
+    /// since folders can be nested.
+    pub fn get_mut(&mut self, path: &Path) -> &mut Workspace {
+        let folder = self
+            .folders
+            .iter_mut()
+            .filter(|folder| {
+                folder
+                    .root
+                    .as_ref()
+                    .map_or(false, |root| path.starts_with(root))
+            })
+            .max_by_key(|folder| {
+                folder
+                    .root
+                    .as_ref()
+                    .map_or(0, |root| root.components().count())
+            });
+        match folder {
+            Some(folder) => folder,
+            None => &mut self.fallback,
+        }
+    }
+}
+
+/// Settings the client sends under `ws`, when starting and with
+/// `workspace/didChangeConfiguration`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    /// How warnings are shown, or `None` to leave them out.
+    pub warning_severity: Option<DiagnosticSeverity>,
+    /// Whether typing `</` completes the close tag.
+    pub close_tags: bool,
+    /// Whether typing `}` lines it up with its `{`.
+    pub align_braces: bool,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            warning_severity: Some(DiagnosticSeverity::Warning),
+            close_tags: true,
+            align_braces: true,
+        }
+    }
+}
+
+impl Settings {
+    /// The settings in `settings`, with the defaults for any that are
+    /// missing or aren't valid. Clients send either an object with a `ws`
+    /// key or just what's under it.
+    pub fn from_json(settings: &Value) -> Settings {
+        let settings = settings.get("ws").unwrap_or(settings);
+        let mut parsed = Settings::default();
+        if let Some(severity) = settings.get("warningSeverity").and_then(Value::as_str) {
+            parsed.warning_severity = match severity {
+                "error" => Some(DiagnosticSeverity::Error),
+                "warning" => Some(DiagnosticSeverity::Warning),
+                "information" => Some(DiagnosticSeverity::Information),
+                "hint" => Some(DiagnosticSeverity::Hint),
+                "off" => None,
+                _ => {
+                    warn!("Unknown warning severity '{}'", severity);
+                    parsed.warning_severity
+                }
+            };
+        }
+        if let Some(close_tags) = settings.get("closeTags").and_then(Value::as_bool) {
+            parsed.close_tags = close_tags;
+        }
+        if let Some(align_braces) = settings.get("alignBraces").and_then(Value::as_bool) {
+            parsed.align_braces = align_braces;
+        }
+        parsed
+    }
+}
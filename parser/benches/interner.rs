@@ -0,0 +1,45 @@
+//! Measures how parsing scales as more threads parse at once. Every thread
+//! interns into the same symbol table, so this is mostly a measure of
+//! contention on its lock.
+//!
+//! Run with `cargo bench -p parser --bench interner`.
+use common::symbol::SymbolScope;
+use parser::parser::ParserImpl;
+use std::thread;
+use std::time::{Duration, Instant};
+use syntax::ast::AstArena;
+
+const SOURCE: &str = include_str!("../../fixtures/main.ws");
+const PARSES_PER_THREAD: u32 = 2_000;
+
+/// Parse `SOURCE` over and over on `threads` threads, each as its own file,
+/// and return the average time per parse.
+fn parse_in_parallel(threads: u32) -> Duration {
+    let start = Instant::now();
+    let handles: Vec<_> = (0..threads)
+        .map(|thread| {
+            thread::spawn(move || {
+                let scope = SymbolScope::new(("interner bench", thread));
+                for _ in 0..PARSES_PER_THREAD {
+                    let _symbols = scope.enter();
+                    let mut arena = AstArena::default();
+                    ParserImpl::new(SOURCE, &mut arena)
+                        .parse_module()
+                        .expect("benchmark source should parse");
+                }
+                scope.release();
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    start.elapsed() / (threads * PARSES_PER_THREAD)
+}
+
+fn main() {
+    for threads in [1, 2, 4, 8] {
+        let per_parse = parse_in_parallel(threads);
+        println!("{} thread(s): {:?} per parse", threads, per_parse);
+    }
+}
@@ -0,0 +1,162 @@
+//! Measures each phase of the compiler on a few representative programs, to
+//! catch regressions in compile throughput. Each phase is timed on its own,
+//! starting from the output of the phases before it.
+//!
+//! Run with `cargo bench -p parser --bench phases`.
+use codegen::Codegen;
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use lexer::TokenStream;
+use parser::control_flow::ControlFlowAnalysis;
+use parser::evaluate::ExpressionEvaluator;
+use parser::parser::ParserImpl;
+use std::fmt::Write;
+use syntax::ast::{AstArena, ModuleId};
+use syntax::visit::Visitor;
+
+/// Functions made of ifs nested `depth` deep.
+fn nested_ifs(functions: usize, depth: usize) -> String {
+    let mut source = String::new();
+    for function in 0..functions {
+        writeln!(source, "fn branch{}(a: number) {{", function).unwrap();
+        for level in 0..depth {
+            writeln!(source, "if a > {} {{", level).unwrap();
+            writeln!(source, "let b{} = a * {}", level, level).unwrap();
+        }
+        writeln!(source, "return a").unwrap();
+        source.push_str(&"}\n".repeat(depth));
+        writeln!(source, "return 0\n}}").unwrap();
+    }
+    source
+}
+
+/// A component with one large template.
+fn big_template(rows: usize) -> String {
+    let mut source = String::from("pub component App {\n  state count = 0\n  return (\n<ul>\n");
+    for row in 0..rows {
+        writeln!(
+            source,
+            "<li class=\"row\"><span>{{count}}</span> row {} <b>{}</b></li>",
+            row, row
+        )
+        .unwrap();
+    }
+    source.push_str("</ul>\n  )\n}\n");
+    source
+}
+
+/// Many small functions, each calling the one before it.
+fn many_functions(functions: usize) -> String {
+    let mut source = String::from("fn f0(a: number) {\n  return a\n}\n");
+    for function in 1..functions {
+        writeln!(
+            source,
+            "fn f{}(a: number) {{\n  let b = a * {}\n  return f{}(b) + 1\n}}",
+            function,
+            function,
+            function - 1
+        )
+        .unwrap();
+    }
+    source
+}
+
+/// A function whose `let`s each use the one before twice, called with a
+/// constant so its return value can be folded. Without caching, evaluating
+/// the call evaluates the first `let` `2^depth` times.
+fn heavy_folding(depth: usize) -> String {
+    let mut source = String::from("fn grow(a: number) {\n  let b0 = a\n");
+    for level in 1..=depth {
+        writeln!(
+            source,
+            "  let b{} = b{} + b{} - a",
+            level,
+            level - 1,
+            level - 1
+        )
+        .unwrap();
+    }
+    writeln!(source, "  return b{}\n}}", depth).unwrap();
+    source.push_str("fn main() {\n  return grow(1) + grow(2)\n}\n");
+    source
+}
+
+fn programs() -> Vec<(&'static str, String)> {
+    vec![
+        (
+            "fixture",
+            include_str!("../../fixtures/main.ws").to_string(),
+        ),
+        ("nested_ifs", nested_ifs(20, 20)),
+        ("big_template", big_template(200)),
+        ("many_functions", many_functions(500)),
+        ("heavy_folding", heavy_folding(16)),
+    ]
+}
+
+fn parse(source: &str) -> (AstArena, ModuleId) {
+    let mut arena = AstArena::default();
+    let module_id = ParserImpl::new(source, &mut arena)
+        .parse_module()
+        .expect("benchmark program should parse");
+    (arena, module_id)
+}
+
+fn evaluate(source: &str) -> (AstArena, ModuleId) {
+    let (mut arena, module_id) = parse(source);
+    ExpressionEvaluator::new(&mut arena)
+        .visit_module(module_id)
+        .expect("benchmark program should evaluate");
+    (arena, module_id)
+}
+
+fn phases(c: &mut Criterion) {
+    for (name, source) in programs() {
+        let mut group = c.benchmark_group(name);
+        group.bench_function("lex", |b| b.iter(|| TokenStream::lex(&source)));
+        group.bench_function("parse", |b| b.iter(|| parse(&source)));
+        group.bench_function("evaluate", |b| {
+            b.iter_batched(
+                || parse(&source),
+                |(mut arena, module_id)| {
+                    ExpressionEvaluator::new(&mut arena)
+                        .visit_module(module_id)
+                        .unwrap();
+                    arena
+                },
+                BatchSize::SmallInput,
+            )
+        });
+        group.bench_function("control_flow", |b| {
+            b.iter_batched(
+                || evaluate(&source),
+                |(mut arena, module_id)| {
+                    let analysis = ControlFlowAnalysis::new(&mut arena);
+                    analysis.visit_module(module_id).unwrap();
+                    analysis.finish()
+                },
+                BatchSize::SmallInput,
+            )
+        });
+        group.bench_function("codegen", |b| {
+            b.iter_batched(
+                || {
+                    let (mut arena, module_id) = evaluate(&source);
+                    let analysis = ControlFlowAnalysis::new(&mut arena);
+                    analysis.visit_module(module_id).unwrap();
+                    let cfg_map = analysis.finish();
+                    (arena, module_id, cfg_map)
+                },
+                |(mut arena, module_id, cfg_map)| {
+                    let codegen = Codegen::new("main".to_string(), &mut arena, cfg_map);
+                    codegen.codegen_module(module_id).unwrap();
+                    codegen.rendered_definitions().unwrap()
+                },
+                BatchSize::SmallInput,
+            )
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, phases);
+criterion_main!(benches);
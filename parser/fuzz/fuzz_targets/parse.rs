@@ -0,0 +1,11 @@
+//! Feeds arbitrary input to the parser, which should never panic.
+//!
+//! Run with `cargo fuzz run parse` from the `parser` directory.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(source) = std::str::from_utf8(data) {
+        let _ = parser::parser::parse_source_for_fuzzing(source);
+    }
+});
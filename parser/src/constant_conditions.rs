@@ -0,0 +1,100 @@
+use common::control_flow_graph::ControlFlowMap;
+use diagnostics::error::{constant_condition, Diagnostic};
+use syntax::ast::*;
+
+/// Reports if statements whose condition was evaluated while building the
+/// control flow graph. Only the branch the condition decided on is in the
+/// graph, so the others are never compiled.
+pub struct ConstantConditionAnalysis<'a, V> {
+    arena: &'a AstArena,
+    cfg_map: &'a ControlFlowMap<FunctionId, ComponentId, StatementId, ExpressionId, V>,
+}
+
+impl<'a, V> ConstantConditionAnalysis<'a, V> {
+    pub fn new(
+        arena: &'a AstArena,
+        cfg_map: &'a ControlFlowMap<FunctionId, ComponentId, StatementId, ExpressionId, V>,
+    ) -> Self {
+        Self { arena, cfg_map }
+    }
+
+    /// Run the analysis, returning a warning for each constant condition.
+    pub fn analyze(&self) -> Vec<Diagnostic> {
+        let mut warnings = vec![];
+        for cfg in self.cfg_map.values() {
+            for constant in &cfg.constant_conditions {
+                let span = self.arena.expression_span(constant.condition).unwrap();
+                let removed = constant
+                    .removed
+                    .iter()
+                    .filter_map(|statement_id| self.arena.statement_span(*statement_id))
+                    .reduce(|removed, span| removed.merge(span));
+                warnings.push(constant_condition(
+                    span,
+                    constant.value,
+                    removed.map(Into::into),
+                ));
+            }
+        }
+        warnings.sort_by_key(|warning| warning.primary_range().map(|range| range.start));
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ConstantConditionAnalysis;
+    use crate::control_flow::ControlFlowAnalysis;
+    use crate::test_utils::parse_module;
+    use syntax::visit::Visitor;
+
+    #[test]
+    fn report_constant_conditions() {
+        let source = "
+const DEBUG = false
+
+fn describe(n: number) {
+  if DEBUG {
+    log(\"describing {}\", n)
+  }
+  if n > 10 {
+    return 2
+  }
+  return 1
+}
+
+component Badge(count: number) {
+  if true {
+    return <b>{count}</b>
+  } else if count > 1 {
+    return <i>{count}</i>
+  }
+  <b>Nothing</b>
+}
+";
+        let (mut arena, module_id) = parse_module(source);
+        let analysis = ControlFlowAnalysis::new(&mut arena);
+        analysis.visit_module(module_id).unwrap();
+        let cfg_map = analysis.finish();
+        let warnings = ConstantConditionAnalysis::new(&arena, &cfg_map).analyze();
+        let warnings: Vec<(&str, &str, &str)> = warnings
+            .iter()
+            .map(|warning| {
+                let ranges = warning.ranges();
+                let text = |index: usize| &source[ranges[index].clone()];
+                (warning.primary_message().unwrap(), text(0), text(1))
+            })
+            .collect();
+        assert_eq!(
+            warnings,
+            vec![
+                (
+                    "This condition is always false",
+                    "DEBUG",
+                    "log(\"describing {}\", n)"
+                ),
+                ("This condition is always true", "true", "<i>{count}</i>"),
+            ]
+        );
+    }
+}
@@ -1,19 +1,17 @@
 use diagnostics::result::Result;
 use evaluate::Value;
-use log::debug;
 use std::cell::RefCell;
-use std::{
-    collections::{HashMap, HashSet},
-    ops::Deref,
-};
-use syntax::ast_::*;
-use syntax::visit_::{walk_component, walk_function, Visitor};
+use std::{collections::HashMap, ops::Deref};
+use syntax::ast::*;
+use syntax::visit::{walk_component, walk_function, Visitor};
+use tracing::{debug_span, trace, trace_span};
 
 use common::control_flow_graph::{
-    BasicBlock, BlockIndex, ControlFlowEdge, ControlFlowGraph, ControlFlowMap, ControlFlowMapKey,
+    BasicBlock, ConstantCondition, ConsumeOptions, ControlFlowEdge, ControlFlowGraph,
+    ControlFlowMap, ControlFlowMapKey,
 };
 
-use crate::evaluate::{evaluate_expression, CallContext};
+use crate::evaluate::{evaluate, CallContext};
 
 pub struct ControlFlowAnalysis<'a, T, E, V> {
     ast: &'a mut AstArena,
@@ -45,7 +43,7 @@ impl<'a> Visitor for ControlFlowAnalysis<'a, StatementId, ExpressionId, evaluate
     }
 
     fn visit_function(&self, function_id: FunctionId) -> Result<()> {
-        println!("Visiting function {:?}", function_id);
+        let _span = debug_span!("cfg_function", ?function_id).entered();
         let arena = self.context();
         let function = arena.functions.get(function_id).unwrap();
         let function = function.borrow();
@@ -58,13 +56,13 @@ impl<'a> Visitor for ControlFlowAnalysis<'a, StatementId, ExpressionId, evaluate
     }
 
     fn visit_component(&self, component_id: ComponentId) -> Result<()> {
-        println!("Visiting component {:?}", component_id);
+        let _span = debug_span!("cfg_component", ?component_id).entered();
         let arena = self.context();
         let component = arena.components.get(component_id).unwrap();
         let component = component.borrow();
         let body = arena.blocks.get(component.body.unwrap()).unwrap();
         let cfg = constrct_cfg_from_block(body, arena, None);
-        // cfg.print();
+        trace!(cfg = %cfg.format());
         self.cfg_map
             .borrow_mut()
             .insert(ControlFlowMapKey::Component(component_id), cfg);
@@ -77,8 +75,7 @@ pub fn constrct_cfg_from_block(
     ast: &AstArena,
     call_context: Option<&CallContext>,
 ) -> ControlFlowGraph<StatementId, ExpressionId, evaluate::Value> {
-    debug!("constrct_cfg_from_block:start");
-    let mut loop_indicies = HashSet::<BlockIndex>::default();
+    let _span = trace_span!("constrct_cfg_from_block").entered();
 
     let mut cfg = ControlFlowGraph::default();
     let mut basic_block = BasicBlock::new();
@@ -89,20 +86,20 @@ pub fn constrct_cfg_from_block(
             Statement::Let { .. }
             | Statement::State { .. }
             | Statement::Expression(_)
-            | Statement::Assignment { .. } => {
+            | Statement::Discard(_)
+            | Statement::Assignment { .. }
+            | Statement::Provide { .. } => {
                 basic_block.statements.push(*statement_id);
             }
             Statement::Return(expression_id) => {
-                let value_expr = ast.expressions.get(*expression_id).unwrap();
-                let value_expr = value_expr.borrow();
-                let value = evaluate_expression(ast, &value_expr, call_context);
+                let value = evaluate(ast, *expression_id, call_context);
                 if cfg.value.is_none() {
                     cfg.value = value;
                 }
-                cfg.set_has_early_return(true);
                 basic_block.statements.push(*statement_id);
                 let block_index = cfg.add_block(basic_block);
                 cfg.add_edge_to_exit(block_index, ControlFlowEdge::Return);
+                cfg.set_has_early_return(true);
                 basic_block = BasicBlock::new();
             }
             Statement::If(if_) => {
@@ -112,17 +109,10 @@ pub fn constrct_cfg_from_block(
                 }
                 // The edge queue here should be flushed to the NEW entry node
                 // for the consumed if statement.
-                debug!("edge_queue before if: {:?}", cfg.edge_queue);
-                debug!("last_index before if: {:?}", cfg.last_index());
+                trace!(edge_queue = ?cfg.edge_queue, last_index = ?cfg.last_index(), "before if");
 
                 let if_cfg = construct_cfg_from_if(if_, ast, call_context);
-
-                let if_cfg_has_early_return = if_cfg.has_early_return();
-
-                if if_cfg_has_early_return {
-                    cfg.set_has_early_return(true);
-                }
-                cfg.consume_subgraph(if_cfg, None, cfg.last_index(), true);
+                cfg.consume_subgraph(if_cfg, ConsumeOptions::sequential());
             }
             Statement::While { condition, body } => {
                 if !basic_block.is_empty() {
@@ -130,34 +120,15 @@ pub fn constrct_cfg_from_block(
                     basic_block = BasicBlock::new();
                 }
 
-                let last_index = cfg.last_index();
                 let loop_condition_index = cfg.add_loop_condition(*condition);
-                cfg.add_edge(last_index, loop_condition_index, ControlFlowEdge::Normal);
-
                 let body = ast.blocks.get(*body).unwrap();
-                let mut while_body_cfg = constrct_cfg_from_block(body, ast, call_context);
-                let while_body_has_early_return = while_body_cfg.has_early_return();
-
-                // Delete the normal flow edge from the last block to the exit node
-                while_body_cfg
-                    .delete_normal_edge(while_body_cfg.last_index(), while_body_cfg.exit_index());
-
-                let true_edge = ControlFlowEdge::ConditionTrue;
-                let false_edge = ControlFlowEdge::ConditionFalse;
-
-                cfg.consume_subgraph(while_body_cfg, Some(true_edge), loop_condition_index, true);
-
-                loop_indicies.insert(cfg.last_index());
-
-                if !while_body_has_early_return {
-                    cfg.add_edge(
-                        cfg.last_index(),
-                        loop_condition_index,
-                        ControlFlowEdge::Normal,
-                    );
-                }
-
-                cfg.enqueue_edge(loop_condition_index, false_edge);
+                let while_body_cfg = constrct_cfg_from_block(body, ast, call_context);
+                cfg.consume_subgraph(
+                    while_body_cfg,
+                    ConsumeOptions::loop_body(loop_condition_index),
+                );
+                // Whatever comes after the loop runs once the condition is false
+                cfg.enqueue_edge(loop_condition_index, ControlFlowEdge::ConditionFalse);
             }
         }
     }
@@ -166,15 +137,9 @@ pub fn constrct_cfg_from_block(
         cfg.add_block(basic_block);
     }
 
-    // Don't automatically add an edge to the exit node if there is an early return
-    // in this block, or the last block is a loop
-    if !cfg.has_early_return() && !loop_indicies.contains(&cfg.last_index()) {
-        cfg.add_edge(cfg.last_index(), cfg.exit_index(), ControlFlowEdge::Normal);
-    }
-
-    cfg.flush_edge_queue(cfg.exit_index());
+    // The end of the block falls through to the exit, unless it has returned
+    cfg.continue_to(cfg.exit_index());
 
-    debug!("constrct_cfg_from_block:end\n");
     cfg
 }
 
@@ -183,28 +148,43 @@ pub fn construct_cfg_from_if(
     ast: &AstArena,
     call_context: Option<&CallContext>,
 ) -> ControlFlowGraph<StatementId, ExpressionId, Value> {
-    debug!("construct_cfg_from_if:start");
-
-    let condition = ast.expressions.get(if_.condition).unwrap();
-    let condition = condition.borrow();
-
-    if let Some(value) = evaluate_expression(ast, &*condition, call_context) {
-        if let Value::Boolean(should_run_branch) = value {
-            if should_run_branch {
-                let body = ast.blocks.get(if_.body).unwrap();
-                return constrct_cfg_from_block(body, ast, call_context);
-            } else if let Some(else_) = &if_.alternate {
-                match &**else_ {
-                    Else::If(if_) => return construct_cfg_from_if(if_, ast, call_context),
-                    Else::Block(block_id) => {
-                        let block = ast.blocks.get(*block_id).unwrap();
-                        return constrct_cfg_from_block(block, ast, call_context);
-                    }
+    let _span = trace_span!("construct_cfg_from_if").entered();
+
+    if let Some(Value::Boolean(should_run_branch)) = evaluate(ast, if_.condition, call_context) {
+        let (mut cfg, removed) = if should_run_branch {
+            let body = ast.blocks.get(if_.body).unwrap();
+            let removed = match &if_.alternate {
+                Some(else_) => else_statements(else_, ast),
+                None => vec![],
+            };
+            (constrct_cfg_from_block(body, ast, call_context), removed)
+        } else {
+            let removed = ast.blocks[if_.body].statements.clone();
+            let cfg = match if_.alternate.as_deref() {
+                Some(Else::If(if_)) => construct_cfg_from_if(if_, ast, call_context),
+                Some(Else::Block(block_id)) => {
+                    let block = ast.blocks.get(*block_id).unwrap();
+                    constrct_cfg_from_block(block, ast, call_context)
                 }
-            } else {
-                return ControlFlowGraph::default();
-            }
+                None => {
+                    // The branch never runs, so control goes straight through
+                    let mut cfg = ControlFlowGraph::default();
+                    cfg.continue_to(cfg.exit_index());
+                    cfg
+                }
+            };
+            (cfg, removed)
+        };
+        // Conditions are only constant for every call when there aren't
+        // any arguments they could depend on.
+        if call_context.is_none() {
+            cfg.constant_conditions.push(ConstantCondition {
+                condition: if_.condition,
+                value: should_run_branch,
+                removed,
+            });
         }
+        return cfg;
     }
 
     let mut cfg = ControlFlowGraph::default();
@@ -226,7 +206,10 @@ pub fn construct_cfg_from_if(
     // Whether the `true` branch of the if statement has an early return
     let if_true_cfg_has_early_return = if_true_cfg.has_early_return();
 
-    cfg.consume_subgraph(if_true_cfg, Some(true_edge), branch_condition_index, false);
+    cfg.consume_subgraph(
+        if_true_cfg,
+        ConsumeOptions::branch(branch_condition_index, true_edge),
+    );
 
     if let Some(ref else_) = alternate {
         match else_.deref() {
@@ -235,7 +218,10 @@ pub fn construct_cfg_from_if(
                 let else_cfg = constrct_cfg_from_block(else_block, ast, call_context);
                 let else_cfg_has_early_return = else_cfg.has_early_return();
 
-                cfg.consume_subgraph(else_cfg, Some(false_edge), branch_condition_index, false);
+                cfg.consume_subgraph(
+                    else_cfg,
+                    ConsumeOptions::branch(branch_condition_index, false_edge),
+                );
 
                 if if_true_cfg_has_early_return && else_cfg_has_early_return {
                     // Both the `true` and `false` branches of the if statement have an early return.
@@ -253,7 +239,10 @@ pub fn construct_cfg_from_if(
                     cfg.set_has_early_return(true)
                 }
 
-                cfg.consume_subgraph(else_if_cfg, Some(false_edge), branch_condition_index, false);
+                cfg.consume_subgraph(
+                    else_if_cfg,
+                    ConsumeOptions::branch(branch_condition_index, false_edge),
+                );
             }
         }
     } else {
@@ -264,6 +253,20 @@ pub fn construct_cfg_from_if(
     if !cfg.has_early_return() {
         cfg.flush_edge_queue(cfg.exit_index());
     }
-    debug!("construct_cfg_from_if:end\n");
     cfg
 }
+
+/// The statements in an else branch, and in the branches of any else-ifs it
+/// chains on to.
+fn else_statements(else_: &Else, ast: &AstArena) -> Vec<StatementId> {
+    match else_ {
+        Else::Block(block_id) => ast.blocks[*block_id].statements.clone(),
+        Else::If(if_) => {
+            let mut statements = ast.blocks[if_.body].statements.clone();
+            if let Some(else_) = &if_.alternate {
+                statements.extend(else_statements(else_, ast));
+            }
+            statements
+        }
+    }
+}
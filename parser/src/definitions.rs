@@ -0,0 +1,328 @@
+//! Splits a module's text into its top-level definitions without parsing it,
+//! so each definition can be compiled by its own query. Editing a definition
+//! then only recompiles it and the definitions that depend on it.
+use lexer::TokenStream;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Keywords that start a top-level definition when they're the first word on a line.
+const DEFINITION_KEYWORDS: &[&str] = &[
+    "pub",
+    "async",
+    "fn",
+    "component",
+    "const",
+    "context",
+    "effect",
+    "enum",
+    "struct",
+    "type",
+    "import",
+];
+
+/// The text of a single top-level definition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DefinitionChunk {
+    /// The name being defined. Text before the first definition, like
+    /// leading comments, and imports have an empty name.
+    pub name: String,
+    /// Whether the definition is declared `pub`, so other modules can import it.
+    pub public: bool,
+    /// Where the chunk is in the module. Chunks cover the whole module,
+    /// so each one runs up to the start of the next.
+    pub range: Range<usize>,
+    /// The names of other definitions this one refers to.
+    pub dependencies: Vec<String>,
+}
+
+/// The text needed to compile a single definition: its own chunk, plus every
+/// chunk it depends on. Other chunks are replaced by the newlines they contain,
+/// so line numbers match the module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DefinitionText {
+    pub text: String,
+    /// Where the chunks for the definition itself are in `text`.
+    pub own: Vec<Range<usize>>,
+}
+
+impl DefinitionText {
+    /// Whether `offset` is inside one of the definition's own chunks.
+    pub fn is_own(&self, offset: usize) -> bool {
+        self.own.iter().any(|range| range.contains(&offset))
+    }
+}
+
+/// The tokens a definition is compiled from. Two of these are equal when
+/// their tokens are, wherever the tokens are in the text, so editing only
+/// whitespace or comments doesn't recompile the definition.
+#[derive(Debug, Clone)]
+pub struct DefinitionTokens {
+    pub tokens: Arc<TokenStream>,
+    /// The indices of the tokens in the definition's own chunks.
+    own: Vec<Range<usize>>,
+}
+
+impl DefinitionTokens {
+    pub fn new(text: &DefinitionText, tokens: Arc<TokenStream>) -> Self {
+        let own = text
+            .own
+            .iter()
+            .map(|range| tokens.token_range(range.clone()))
+            .collect();
+        Self { tokens, own }
+    }
+
+    /// Whether the token at `index` is in one of the definition's own chunks.
+    pub fn is_own(&self, index: usize) -> bool {
+        self.own.iter().any(|range| range.contains(&index))
+    }
+}
+
+impl PartialEq for DefinitionTokens {
+    fn eq(&self, other: &Self) -> bool {
+        self.own == other.own && self.tokens.same_tokens(&other.tokens)
+    }
+}
+
+impl Eq for DefinitionTokens {}
+
+/// A `DefinitionText` along with where its chunks came from. This is kept
+/// apart from the text so that moving a definition around doesn't change
+/// the text it's compiled from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DefinitionSource {
+    pub text: DefinitionText,
+    /// Pairs of where each included chunk starts in the text and in the module.
+    offsets: Vec<(usize, usize)>,
+}
+
+impl DefinitionSource {
+    /// Build the source for the definition named `name` from the chunks of `module`.
+    pub fn new(module: &str, chunks: &[DefinitionChunk], name: &str) -> Self {
+        // Imports are in scope in every definition, so they're always included
+        let mut included = dependency_closure(chunks, name);
+        included.extend(dependency_closure(chunks, ""));
+        let mut text = String::new();
+        let mut own = vec![];
+        let mut offsets = vec![];
+        for chunk in chunks {
+            let chunk_text = &module[chunk.range.clone()];
+            if included.contains(chunk.name.as_str()) {
+                if chunk.name == name {
+                    own.push(text.len()..text.len() + chunk_text.len());
+                }
+                offsets.push((text.len(), chunk.range.start));
+                text.push_str(chunk_text);
+            } else {
+                text.extend(chunk_text.matches('\n'));
+            }
+        }
+        let text = DefinitionText { text, own };
+        Self { text, offsets }
+    }
+
+    /// Map an offset in the text to the module it was taken from.
+    pub fn module_offset(&self, offset: usize) -> usize {
+        match self
+            .offsets
+            .iter()
+            .rev()
+            .find(|(start, _)| *start <= offset)
+        {
+            Some((start, module_start)) => module_start + (offset - start),
+            None => offset,
+        }
+    }
+
+    /// Map a range in the text to the module. The end is mapped from the
+    /// last byte in the range, since a range can end right where the next
+    /// chunk starts.
+    pub fn module_range(&self, range: Range<usize>) -> Range<usize> {
+        let start = self.module_offset(range.start);
+        if range.is_empty() {
+            return start..start;
+        }
+        start..self.module_offset(range.end - 1) + 1
+    }
+}
+
+/// The names of `name` and every definition it depends on, directly or not.
+fn dependency_closure<'a>(chunks: &'a [DefinitionChunk], name: &'a str) -> HashSet<&'a str> {
+    let mut included = HashSet::new();
+    let mut pending = vec![name];
+    while let Some(name) = pending.pop() {
+        if !included.insert(name) {
+            continue;
+        }
+        for chunk in chunks.iter().filter(|chunk| chunk.name == name) {
+            pending.extend(chunk.dependencies.iter().map(String::as_str));
+        }
+    }
+    included
+}
+
+/// Split `source` into the chunks for each top-level definition. This only
+/// tracks strings, comments and nesting, so it works on modules that don't
+/// parse. The parser reports those errors when a chunk is compiled.
+pub fn split_definitions(source: &str) -> Vec<DefinitionChunk> {
+    // The start of each chunk, its name, whether it's public, and the words
+    // it contains.
+    let mut chunks: Vec<(usize, String, bool, HashSet<&str>)> =
+        vec![(0, String::new(), false, HashSet::new())];
+    // The chunk that defines each enum variant. Variants are referred to by
+    // their own name, so a chunk that uses one depends on its enum.
+    let mut variants: HashMap<&str, usize> = HashMap::new();
+    let mut depth = 0usize;
+    let mut line_start = true;
+    let mut expecting_name = false;
+    let mut in_enum = false;
+    let mut chars = source.char_indices().peekable();
+    while let Some((start, ch)) = chars.next() {
+        match ch {
+            '\n' => {
+                line_start = true;
+                continue;
+            }
+            '#' => while chars.next_if(|(_, ch)| *ch != '\n').is_some() {},
+            '"' => {
+                while let Some((_, ch)) = chars.next_if(|(_, ch)| *ch != '\n') {
+                    if ch == '"' {
+                        break;
+                    }
+                }
+            }
+            '{' | '(' | '[' => depth += 1,
+            '}' | ')' | ']' => depth = depth.saturating_sub(1),
+            ch if ch.is_alphabetic() || ch == '_' => {
+                let mut end = start + ch.len_utf8();
+                while let Some((index, ch)) =
+                    chars.next_if(|(_, ch)| ch.is_alphanumeric() || *ch == '_')
+                {
+                    end = index + ch.len_utf8();
+                }
+                let word = &source[start..end];
+                let chunk_index = chunks.len() - 1;
+                let (_, name, _, words) = chunks.last_mut().unwrap();
+                if depth == 0 && line_start && DEFINITION_KEYWORDS.contains(&word) {
+                    chunks.push((start, String::new(), word == "pub", HashSet::new()));
+                    // Imports can bind any number of names, so they don't get one
+                    expecting_name = word != "import";
+                    in_enum = word == "enum";
+                } else if expecting_name && !DEFINITION_KEYWORDS.contains(&word) {
+                    *name = word.to_string();
+                    expecting_name = false;
+                } else {
+                    in_enum |= depth == 0 && word == "enum";
+                    // The variants are the only names directly in an enum's
+                    // braces, the types of their values are in parentheses.
+                    if in_enum && depth == 1 {
+                        variants.insert(word, chunk_index);
+                    }
+                    words.insert(word);
+                }
+            }
+            _ => {}
+        }
+        if !ch.is_whitespace() {
+            line_start = false;
+        }
+    }
+
+    let names: HashSet<String> = chunks.iter().map(|(_, name, _, _)| name.clone()).collect();
+    let ends = chunks
+        .iter()
+        .skip(1)
+        .map(|(start, _, _, _)| *start)
+        .chain(std::iter::once(source.len()));
+    chunks
+        .iter()
+        .zip(ends)
+        .filter(|((start, _, _, _), end)| start < end)
+        .map(|((start, name, public, words), end)| {
+            let mut dependencies: Vec<String> = words
+                .iter()
+                .map(|word| match variants.get(word) {
+                    Some(index) => chunks[*index].1.as_str(),
+                    None => word,
+                })
+                .filter(|word| *word != name.as_str() && names.contains(*word))
+                .map(|word| word.to_string())
+                .collect();
+            dependencies.sort();
+            dependencies.dedup();
+            DefinitionChunk {
+                name: name.clone(),
+                public: *public,
+                range: *start..end,
+                dependencies,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{split_definitions, DefinitionSource};
+
+    const SOURCE: &str = "# Leading comment
+fn double(a: number) {
+  a * 2
+}
+
+fn unused() { 0 }
+
+pub component App() {
+  let value = double(2)
+  view {
+    <p>{value} # not a definition
+    fn also not a definition</p>
+  }
+}
+";
+
+    #[test]
+    fn split_into_definitions() {
+        let chunks = split_definitions(SOURCE);
+        let names: Vec<&str> = chunks.iter().map(|chunk| chunk.name.as_str()).collect();
+        assert_eq!(names, vec!["", "double", "unused", "App"]);
+        assert_eq!(chunks[0].range, 0..18);
+        assert_eq!(chunks.last().unwrap().range.end, SOURCE.len());
+        assert_eq!(chunks[3].dependencies, vec!["double".to_string()]);
+        let public: Vec<bool> = chunks.iter().map(|chunk| chunk.public).collect();
+        assert_eq!(public, vec![false, false, false, true]);
+    }
+
+    #[test]
+    fn definition_source_keeps_lines() {
+        let chunks = split_definitions(SOURCE);
+        let source = DefinitionSource::new(SOURCE, &chunks, "App");
+        let text = &source.text.text;
+        assert_eq!(text.lines().count(), SOURCE.lines().count());
+        assert!(!text.contains("unused"));
+        let app = SOURCE.find("pub component").unwrap();
+        let own = source.text.own[0].start;
+        assert_eq!(&text[own..own + 13], "pub component");
+        assert_eq!(source.module_offset(own), app);
+    }
+
+    #[test]
+    fn imports_are_always_included() {
+        let source = "import utils.{helper as h}\nfn a() { h(1) }\nfn b() { 2 }\n";
+        let chunks = split_definitions(source);
+        let names: Vec<&str> = chunks.iter().map(|chunk| chunk.name.as_str()).collect();
+        assert_eq!(names, vec!["", "a", "b"]);
+        let text = DefinitionSource::new(source, &chunks, "b").text.text;
+        assert!(text.starts_with("import utils.{helper as h}"));
+    }
+
+    #[test]
+    fn variants_depend_on_their_enum() {
+        let source =
+            "enum Shape {\n  Circle(number)\n  Empty\n}\nasync fn area(s: number) { Circle(s) }\n";
+        let chunks = split_definitions(source);
+        let names: Vec<&str> = chunks.iter().map(|chunk| chunk.name.as_str()).collect();
+        assert_eq!(names, vec!["Shape", "area"]);
+        assert_eq!(chunks[1].dependencies, vec!["Shape".to_string()]);
+    }
+}
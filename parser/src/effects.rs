@@ -0,0 +1,215 @@
+use diagnostics::error::{undeclared_effect, Diagnostic};
+use diagnostics::result::Result;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use syntax::ast::*;
+use syntax::visit::{walk_expression, walk_function, Visitor};
+use syntax::Span;
+
+/// Checks that functions only perform the effects they declare. A function
+/// that declares effects, like `fn log(): number + Log`, performs them. One
+/// that doesn't performs whatever the functions it calls do, so effects
+/// propagate through the call graph until they reach a function that
+/// declares them, which has to declare all of them.
+pub struct EffectAnalysis<'a> {
+    arena: &'a AstArena,
+    // The functions called directly from each function, and where
+    calls: RefCell<HashMap<FunctionId, Vec<(FunctionId, Span)>>>,
+    // The functions we're currently inside of, innermost last
+    function_stack: RefCell<Vec<FunctionId>>,
+}
+
+impl<'a> EffectAnalysis<'a> {
+    pub fn new(arena: &'a AstArena) -> Self {
+        Self {
+            arena,
+            calls: Default::default(),
+            function_stack: Default::default(),
+        }
+    }
+
+    /// Run the analysis, returning an error for each call that performs an
+    /// effect the function it's in doesn't declare.
+    pub fn analyze(self, module_id: ModuleId) -> Result<Vec<Diagnostic>> {
+        self.visit_module(module_id)?;
+        let calls = self.calls.take();
+        let effects = self.performed_effects(&calls);
+        let mut errors = vec![];
+        for (function_id, calls) in &calls {
+            let function = self.arena.functions[*function_id].borrow();
+            let declared = match &function.effects {
+                Some(declared) => declared,
+                None => continue,
+            };
+            for (callee, span) in calls {
+                let mut undeclared: Vec<EffectId> = effects[callee]
+                    .iter()
+                    .filter(|effect| !declared.contains(effect))
+                    .copied()
+                    .collect();
+                undeclared.sort_by_key(|effect_id| effect_id.index());
+                for effect_id in undeclared {
+                    let effect = &self.arena.effects[effect_id];
+                    errors.push(undeclared_effect(
+                        *span,
+                        effect.name.symbol,
+                        function.name.symbol,
+                        function.name.span,
+                    ));
+                }
+            }
+        }
+        errors.sort_by_key(|error| error.primary_range().map(|range| range.start));
+        Ok(errors)
+    }
+
+    /// The effects each function performs, found by growing the effects of
+    /// functions without declared ones until they stop changing.
+    fn performed_effects(
+        &self,
+        calls: &HashMap<FunctionId, Vec<(FunctionId, Span)>>,
+    ) -> HashMap<FunctionId, HashSet<EffectId>> {
+        let mut effects: HashMap<FunctionId, HashSet<EffectId>> = self
+            .arena
+            .functions
+            .iter()
+            .map(|(function_id, function)| {
+                let declared = function.borrow().effects.clone().unwrap_or_default();
+                (function_id, declared.into_iter().collect())
+            })
+            .collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (function_id, calls) in calls {
+                if self.arena.functions[*function_id]
+                    .borrow()
+                    .effects
+                    .is_some()
+                {
+                    continue;
+                }
+                let called: Vec<EffectId> = calls
+                    .iter()
+                    .flat_map(|(callee, _)| effects[callee].iter().copied())
+                    .collect();
+                let performed = effects.get_mut(function_id).unwrap();
+                for effect_id in called {
+                    changed |= performed.insert(effect_id);
+                }
+            }
+        }
+        effects
+    }
+}
+
+impl<'a> Visitor for EffectAnalysis<'a> {
+    fn context(&self) -> &AstArena {
+        self.arena
+    }
+
+    fn visit_function(&self, function_id: FunctionId) -> Result<()> {
+        self.function_stack.borrow_mut().push(function_id);
+        let result = walk_function(self, function_id);
+        self.function_stack.borrow_mut().pop();
+        result
+    }
+
+    fn visit_expression(&self, expression_id: ExpressionId) -> Result<()> {
+        let expression = self.arena.expressions[expression_id].borrow();
+        if let Expression::Call { callee, .. } = &*expression {
+            let callee = self.arena.expressions[*callee].borrow();
+            let caller = self.function_stack.borrow().last().copied();
+            if let (Expression::Reference(Binding::Function(callee_id)), Some(caller)) =
+                (&*callee, caller)
+            {
+                let span = self.arena.expression_span(expression_id);
+                if let Some(span) = span {
+                    self.calls
+                        .borrow_mut()
+                        .entry(caller)
+                        .or_default()
+                        .push((*callee_id, span));
+                }
+            }
+        }
+        drop(expression);
+        walk_expression(self, expression_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EffectAnalysis;
+    use crate::parser::ParserImpl;
+    use syntax::ast::AstArena;
+    use syntax::features::{Feature, Features};
+
+    fn check(source: &str) -> Vec<String> {
+        let mut arena = AstArena::default();
+        let mut features = Features::default();
+        features.enable(Feature::Effects);
+        let module_id = ParserImpl::new(source, &mut arena)
+            .with_features(&features)
+            .parse_module()
+            .expect("source should parse");
+        EffectAnalysis::new(&arena)
+            .analyze(module_id)
+            .unwrap()
+            .iter()
+            .map(|error| format!("{:?}", error))
+            .collect()
+    }
+
+    #[test]
+    fn effects_propagate_through_calls() {
+        let source = "
+effect Log
+effect Fetch
+
+fn log(message: string): number + Log {
+  return 0
+}
+
+fn helper() {
+  return log(\"hi\")
+}
+
+fn declared(): number + Log {
+  return helper()
+}
+
+fn undeclared(): number + Fetch {
+  return helper()
+}
+";
+        let errors = check(source);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("Undeclared Effect"));
+        assert!(errors[0].contains("'Log'"));
+    }
+
+    #[test]
+    fn recursive_functions_finish() {
+        let source = "
+effect Log
+effect Fetch
+
+fn log(): number + Log {
+  return 0
+}
+
+fn countdown(a: number) {
+  log()
+  return countdown(a - 1)
+}
+
+fn fetch(): number + Fetch {
+  return countdown(10)
+}
+";
+        let errors = check(source);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("'Log'"));
+    }
+}
@@ -1,15 +1,21 @@
+use std::cell::RefCell;
+use std::collections::hash_map::DefaultHasher;
 use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
 
 use diagnostics::result::Result;
+use tracing::debug;
+
 use syntax::{
     ast::BinOp,
-    ast_::*,
-    visit_::{walk_expression, Visitor},
+    ast::*,
+    visit::{walk_expression, Visitor},
+    Span,
 };
 
 use crate::control_flow::constrct_cfg_from_block;
 
-use evaluate::Value;
+use evaluate::{checked_number, EvaluationError, Value};
 
 pub struct ExpressionEvaluator<'a> {
     arena: &'a mut AstArena,
@@ -24,6 +30,80 @@ impl<'a> ExpressionEvaluator<'a> {
 #[derive(Debug, Clone)]
 pub struct CallContext {
     pub arguments: HashMap<ParameterId, ExpressionId>,
+    /// A hash of the arguments, which evaluations in this context are
+    /// cached by. It's zero without arguments, like when there's no context.
+    key: u64,
+}
+
+impl CallContext {
+    pub fn new(arguments: HashMap<ParameterId, ExpressionId>) -> Self {
+        if arguments.is_empty() {
+            return CallContext { arguments, key: 0 };
+        }
+        let mut sorted: Vec<(ParameterId, ExpressionId)> = arguments
+            .iter()
+            .map(|(parameter, argument)| (*parameter, *argument))
+            .collect();
+        sorted.sort();
+        let mut hasher = DefaultHasher::new();
+        sorted.hash(&mut hasher);
+        CallContext {
+            arguments,
+            key: hasher.finish(),
+        }
+    }
+}
+
+thread_local! {
+    static EVALUATIONS: RefCell<EvaluationCache> = RefCell::new(EvaluationCache::default());
+}
+
+/// The values already worked out for the expressions in one arena, by
+/// expression and call context.
+#[derive(Default)]
+struct EvaluationCache {
+    /// The arena the values are for, and its generation.
+    arena: Option<(*const AstArena, u64)>,
+    values: HashMap<(ExpressionId, u64), Option<Value>>,
+}
+
+impl EvaluationCache {
+    /// The cached value for `key` in `arena`, emptying the cache first if
+    /// it's for another arena or an earlier generation of this one. Ids are
+    /// never shared between arenas, so that's only to keep the cache from
+    /// growing across compiles.
+    fn get(&mut self, arena: &AstArena, key: (ExpressionId, u64)) -> Option<Option<Value>> {
+        let current = Some((arena as *const AstArena, arena.generation()));
+        if self.arena != current {
+            self.arena = current;
+            self.values.clear();
+        }
+        self.values.get(&key).copied()
+    }
+}
+
+/// The value of the expression `expression_id`, if it's known at compile
+/// time. Values are cached, so each expression is only evaluated once in
+/// each call context, no matter how many references or calls lead to it.
+/// That holds up because expressions are only ever changed into the value
+/// they evaluated to.
+pub fn evaluate(
+    arena: &AstArena,
+    expression_id: ExpressionId,
+    call_context: Option<&CallContext>,
+) -> Option<Value> {
+    // No context and one without arguments evaluate the same way, so
+    // they share a key
+    let key = (expression_id, call_context.map_or(0, |context| context.key));
+    if let Some(value) = EVALUATIONS.with(|cache| cache.borrow_mut().get(arena, key)) {
+        return value;
+    }
+    let value = {
+        let expression = arena.expressions[expression_id].borrow();
+        evaluate_expression(arena, &expression, call_context)
+    };
+    EVALUATIONS.with(|cache| cache.borrow_mut().values.insert(key, value));
+    value
 }
 
 pub fn evaluate_expression(
@@ -51,44 +131,62 @@ pub fn evaluate_expression(
                     for (parameter, argument) in params_and_arguments {
                         arguments.insert(*parameter, argument.value);
                     }
-                    Some(CallContext { arguments })
+                    Some(CallContext::new(arguments))
                 } else {
                     None
                 };
                 let cfg = constrct_cfg_from_block(body, arena, call_context.as_ref());
-                println!(
-                    "Call to '{}' expression evaluated to: {:?}",
-                    function.name.symbol, cfg.value
-                );
+                debug!(function = %function.name.symbol, value = ?cfg.value, "evaluated call");
                 cfg.value
             } else {
                 None
             }
         }
         Expression::Binary { left, right, op } => {
-            let left_expr = {
-                let left_expr_cell = arena.expressions.get(*left).unwrap();
-                left_expr_cell.borrow()
-            };
-            let right_expr = {
-                let right_expr_cell = arena.expressions.get(*right).unwrap();
-                right_expr_cell.borrow()
-            };
-
-            let left_value = evaluate_expression(arena, &left_expr, call_context);
-            let right_value = evaluate_expression(arena, &right_expr, call_context);
+            let left_value = evaluate(arena, *left, call_context);
+            let right_value = evaluate(arena, *right, call_context);
 
             match (left_value, right_value) {
                 (Some(left_value), Some(right_value)) => match (left_value, right_value) {
+                    // If either side already failed, the failure propagates up
+                    // so we can report it once for the whole expression.
+                    (Value::Error(error), _) | (_, Value::Error(error)) => {
+                        Some(Value::Error(error))
+                    }
+                    // Any two values can be compared, but only equal if they're the same type
+                    (left_value, right_value) if *op == BinOp::DoubleEquals => {
+                        Some(left_value.equals(&right_value))
+                    }
                     // Two numeric values!
                     (Value::Number(left_value), Value::Number(right_value)) => match op {
-                        BinOp::DoubleEquals => Some(Value::Boolean(left_value == right_value)),
-                        BinOp::Add => Some(Value::Number(left_value + right_value)),
-                        BinOp::Sub => Some(Value::Number(left_value - right_value)),
-                        BinOp::Sum => Some(Value::Number(left_value + right_value)),
-                        BinOp::Mul => Some(Value::Number(left_value * right_value)),
-                        BinOp::Div => Some(Value::Number(left_value / right_value)),
-                        BinOp::Mod => Some(Value::Number(left_value % right_value)),
+                        BinOp::Add | BinOp::Sum => Some(checked_number(
+                            left_value,
+                            right_value,
+                            left_value + right_value,
+                        )),
+                        BinOp::Sub => Some(checked_number(
+                            left_value,
+                            right_value,
+                            left_value - right_value,
+                        )),
+                        BinOp::Mul => Some(checked_number(
+                            left_value,
+                            right_value,
+                            left_value * right_value,
+                        )),
+                        BinOp::Div | BinOp::Mod if right_value == 0.0 => {
+                            Some(Value::Error(EvaluationError::DivisionByZero))
+                        }
+                        BinOp::Div => Some(checked_number(
+                            left_value,
+                            right_value,
+                            left_value / right_value,
+                        )),
+                        BinOp::Mod => Some(checked_number(
+                            left_value,
+                            right_value,
+                            left_value % right_value,
+                        )),
                         BinOp::GreaterThan => Some(Value::Boolean(left_value > right_value)),
                         BinOp::GreaterThanEquals => Some(Value::Boolean(left_value >= right_value)),
                         BinOp::LessThan => Some(Value::Boolean(left_value < right_value)),
@@ -99,7 +197,6 @@ pub fn evaluate_expression(
                     (Value::Boolean(left_value), Value::Boolean(right_value)) => match op {
                         BinOp::And => Some(Value::Boolean(left_value && right_value)),
                         BinOp::Or => Some(Value::Boolean(left_value || right_value)),
-                        BinOp::DoubleEquals => Some(Value::Boolean(left_value == right_value)),
                         _ => None,
                     },
                     _ => None,
@@ -107,30 +204,27 @@ pub fn evaluate_expression(
                 _ => None,
             }
         }
-        Expression::Number(value) => Some(Value::Number(*value)),
+        Expression::Number { value, .. } => Some(Value::Number(*value)),
         Expression::Boolean(value) => Some(Value::Boolean(*value)),
+        Expression::String(symbol) => Some(Value::String(*symbol)),
         Expression::Reference(binding) => match binding {
             Binding::Let(statement_id) => {
                 let statement = arena.statements.get(*statement_id).unwrap();
                 match statement {
-                    Statement::Let { value, .. } => {
-                        let expression = arena.expressions.get(*value).unwrap().borrow();
-                        evaluate_expression(arena, &expression, call_context)
-                    }
+                    // A mutable binding might not hold its initial value anymore
+                    Statement::Let { mutable: true, .. } => None,
+                    Statement::Let { value, .. } => evaluate(arena, *value, call_context),
                     _ => None,
                 }
             }
             Binding::Const(const_id) => {
                 let const_ = arena.consts.get(*const_id).unwrap();
-                let expression = arena.expressions.get(const_.value).unwrap().borrow();
-                evaluate_expression(arena, &expression, call_context)
+                evaluate(arena, const_.value, call_context)
             }
             Binding::Parameter(parameter_id) => {
                 if let Some(call_context) = call_context {
                     if let Some(value) = call_context.arguments.get(parameter_id) {
-                        let value_expression = arena.expressions.get(*value).unwrap();
-                        let value_expression = value_expression.borrow();
-                        evaluate_expression(arena, &value_expression, Some(call_context))
+                        evaluate(arena, *value, Some(call_context))
                     } else {
                         None
                     }
@@ -160,6 +254,11 @@ impl<'a> Visitor for ExpressionEvaluator<'a> {
     fn visit_expression(&self, expression_id: ExpressionId) -> Result<()> {
         let expression = self.arena.expressions.get(expression_id).unwrap();
         let expression = expression.borrow();
+        // Number literals are already as folded as they get, and are left
+        // alone so they keep the way they were written
+        if let Expression::Number { .. } = &*expression {
+            return Ok(());
+        }
         let call_context = if let Expression::Call { callee, arguments } = &*expression {
             let callee_expr = self
                 .arena
@@ -179,12 +278,9 @@ impl<'a> Visitor for ExpressionEvaluator<'a> {
                             for (parameter, argument) in params_and_arguments {
                                 arguments.insert(*parameter, argument.value);
                             }
-                            let call_context = CallContext { arguments };
-                            Some(call_context)
+                            Some(CallContext::new(arguments))
                         }
-                        None => Some(CallContext {
-                            arguments: HashMap::new(),
-                        }),
+                        None => Some(CallContext::new(HashMap::new())),
                     }
                 } else {
                     None
@@ -196,23 +292,67 @@ impl<'a> Visitor for ExpressionEvaluator<'a> {
             None
         };
 
-        if let Some(value) = evaluate_expression(self.arena, &expression, call_context.as_ref()) {
+        // Strings are only evaluated for comparisons. References to them are
+        // left alone, since string literals can mean more than their value,
+        // like format strings.
+        let value = evaluate(self.arena, expression_id, call_context.as_ref())
+            .filter(|value| !matches!(value, Value::String(_)));
+        if let Some(value) = value {
             drop(expression);
+            if let Value::Error(error) = value {
+                use diagnostics::error::{incompatible_comparison, invalid_constant_operation};
+                let span = self
+                    .arena
+                    .expression_span(expression_id)
+                    .unwrap_or_else(|| Span::empty(0));
+                if let EvaluationError::IncompatibleComparison { left, right } = error {
+                    return incompatible_comparison(span, left, right);
+                }
+                return invalid_constant_operation(span, error.description());
+            }
             let expression = self.arena.expressions.get(expression_id).unwrap();
             let mut expression = expression.borrow_mut();
             *expression = value_to_expression(value);
         } else {
             walk_expression(self, expression_id)?;
-            // ...
+            if let Expression::Template(template_id) = &*expression {
+                self.fold_attributes(*template_id);
+            }
         }
         Ok(())
     }
 }
 
+impl<'a> ExpressionEvaluator<'a> {
+    /// Attributes are only ever set to their value, so strings are folded
+    /// into them too, unlike other expressions. Constant attributes are set
+    /// right where the element is created, instead of being passed to the
+    /// template.
+    fn fold_attributes(&self, template_id: TemplateId) {
+        let template = self.arena.templates[template_id].borrow();
+        for attribute in &template.open_tag.attributes {
+            let value = evaluate(self.arena, attribute.value, None);
+            if let Some(Value::String(symbol)) = value {
+                *self.arena.expressions[attribute.value].borrow_mut() = Expression::String(symbol);
+            }
+        }
+        for child in template.children.iter().flatten() {
+            if let TemplateChild::Template(child_id) = child {
+                self.fold_attributes(*child_id);
+            }
+        }
+    }
+}
+
 pub fn value_to_expression(value: Value) -> Expression {
     match value {
         Value::Boolean(value) => Expression::Boolean(value),
-        Value::Number(value) => Expression::Number(value),
+        Value::Number(value) => Expression::Number {
+            value,
+            literal: None,
+        },
+        Value::String(symbol) => Expression::String(symbol),
+        Value::Error(_) => unreachable!("failed evaluations are never folded"),
     }
 }
 
@@ -220,16 +360,95 @@ pub fn value_to_expression(value: Value) -> Expression {
 fn evaluate_simple_expr_test() {
     let mut arena = AstArena::default();
 
-    let mut expression = {
-        let left = arena.alloc_expression(Expression::Number(5.0));
-        let right = arena.alloc_expression(Expression::Number(10.0));
+    let expression = {
+        let left = arena.alloc_expression(value_to_expression(Value::Number(5.0)));
+        let right = arena.alloc_expression(value_to_expression(Value::Number(10.0)));
         let op = BinOp::Add;
-        Expression::Binary { left, right, op }
+        arena.alloc_expression(Expression::Binary { left, right, op })
+    };
+
+    let evaluate = ExpressionEvaluator::new(&mut arena);
+
+    evaluate.visit_expression(expression).unwrap();
+
+    assert!(*arena.expressions[expression].borrow() == value_to_expression(Value::Number(15.0)));
+}
+
+#[test]
+fn evaluate_division_by_zero_test() {
+    let mut arena = AstArena::default();
+
+    let expression = {
+        let left = arena.alloc_expression(value_to_expression(Value::Number(5.0)));
+        let right = arena.alloc_expression(value_to_expression(Value::Number(0.0)));
+        let op = BinOp::Div;
+        arena.alloc_expression(Expression::Binary { left, right, op })
+    };
+
+    let evaluate = ExpressionEvaluator::new(&mut arena);
+
+    assert!(evaluate.visit_expression(expression).is_err());
+}
+
+#[test]
+fn evaluate_incompatible_comparison_test() {
+    let mut arena = AstArena::default();
+
+    let expression = {
+        let left = arena.alloc_expression(value_to_expression(Value::Number(1.0)));
+        let right = arena.alloc_expression(value_to_expression(Value::Boolean(true)));
+        let op = BinOp::DoubleEquals;
+        arena.alloc_expression(Expression::Binary { left, right, op })
+    };
+
+    let evaluate = ExpressionEvaluator::new(&mut arena);
+
+    let error = evaluate.visit_expression(expression).unwrap_err();
+    let diagnostic = &error.diagnostics()[0];
+    assert_eq!(diagnostic.message(), "Incompatible Comparison");
+}
+
+#[test]
+fn evaluate_string_equality_test() {
+    let mut arena = AstArena::default();
+
+    let expression = {
+        let left =
+            arena.alloc_expression(Expression::String(common::symbol::Symbol::intern("hello")));
+        let right =
+            arena.alloc_expression(Expression::String(common::symbol::Symbol::intern("hello")));
+        let op = BinOp::DoubleEquals;
+        arena.alloc_expression(Expression::Binary { left, right, op })
     };
 
     let evaluate = ExpressionEvaluator::new(&mut arena);
 
-    evaluate.visit_expression(&mut expression).unwrap();
+    evaluate.visit_expression(expression).unwrap();
+
+    assert!(*arena.expressions[expression].borrow() == Expression::Boolean(true));
+}
 
-    assert!(expression == Expression::Number(15.0));
+#[test]
+fn evaluate_calls_in_their_own_context() {
+    let mut arena = AstArena::default();
+    let source = "fn double(a: number) {
+  return a + a
+}
+fn main() {
+  return double(1) + double(2)
+}
+";
+    let module_id = crate::parser::ParserImpl::new(source, &mut arena)
+        .parse_module()
+        .unwrap();
+    ExpressionEvaluator::new(&mut arena)
+        .visit_module(module_id)
+        .unwrap();
+    // `a + a` is cached separately for each call
+    let folded = arena.expressions.iter().any(|(_, expression)| {
+        matches!(*expression.borrow(), Expression::Number { value, .. } if value == 6.0)
+    });
+    assert!(folded);
+    // But a call without arguments is cached like no call at all
+    assert_eq!(CallContext::new(HashMap::new()).key, 0);
 }
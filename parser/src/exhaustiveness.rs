@@ -0,0 +1,161 @@
+use diagnostics::result::Result;
+use syntax::ast::{AstArena, EnumId, Expression, MatchCase, MatchPattern, VariantId};
+use syntax::Span;
+
+/// The set of values a match is able to distinguish between, inferred
+/// from the patterns used in its cases.
+enum PatternSpace {
+    /// Matches against `true` and `false` literals
+    Boolean { true_: bool, false_: bool },
+    /// Matches against the variants of a single enum
+    Enum {
+        enum_id: EnumId,
+        covered: Vec<VariantId>,
+    },
+}
+
+impl PatternSpace {
+    fn missing(&self, arena: &AstArena) -> Vec<String> {
+        match self {
+            PatternSpace::Boolean { true_, false_ } => {
+                let mut missing = vec![];
+                if !true_ {
+                    missing.push("true".into());
+                }
+                if !false_ {
+                    missing.push("false".into());
+                }
+                missing
+            }
+            PatternSpace::Enum { enum_id, covered } => arena.enums[*enum_id]
+                .variants
+                .iter()
+                .filter(|variant_id| !covered.contains(variant_id))
+                .map(|variant_id| arena.variants[*variant_id].name.symbol.to_string())
+                .collect(),
+        }
+    }
+}
+
+/// Verifies that a match handles every possible value. A match is
+/// exhaustive if it has a wildcard case, covers both boolean values, or
+/// covers every variant of the enum it destructures. Matches over other
+/// values can't be checked and must rely on a wildcard at runtime.
+pub fn check_match_exhaustiveness(arena: &AstArena, cases: &[MatchCase], span: Span) -> Result<()> {
+    let mut space: Option<PatternSpace> = None;
+    for case in cases {
+        match &case.pattern {
+            MatchPattern::Wildcard => return Ok(()),
+            MatchPattern::Expression(expression_id) => {
+                if let Expression::Boolean(value) = *arena.expressions[*expression_id].borrow() {
+                    let space = space.get_or_insert(PatternSpace::Boolean {
+                        true_: false,
+                        false_: false,
+                    });
+                    if let PatternSpace::Boolean { true_, false_ } = space {
+                        if value {
+                            *true_ = true;
+                        } else {
+                            *false_ = true;
+                        }
+                    }
+                }
+            }
+            MatchPattern::Variant { variant, .. } => {
+                let space = space.get_or_insert(PatternSpace::Enum {
+                    enum_id: arena.variants[*variant].enum_id,
+                    covered: vec![],
+                });
+                if let PatternSpace::Enum { covered, .. } = space {
+                    covered.push(*variant);
+                }
+            }
+        }
+    }
+    if let Some(space) = space {
+        let missing = space.missing(arena);
+        if !missing.is_empty() {
+            use diagnostics::error::non_exhaustive_match;
+            return non_exhaustive_match(span, missing);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parser::parse_source_for_fuzzing;
+
+    /// The label on the first diagnostic parsing `source` reports.
+    fn label(source: &str) -> Option<String> {
+        let error = parse_source_for_fuzzing(source).err()?;
+        error.diagnostics()[0].primary_message().map(String::from)
+    }
+
+    const SHAPE: &str = "enum Shape { Circle(number) Square(number, number) Empty }\n";
+
+    #[test]
+    fn destructure_variants() {
+        let area = |cases: &str| {
+            format!(
+                "{}fn area(s: Shape): number {{ return match s {{ {} }} }}",
+                SHAPE, cases
+            )
+        };
+        // Each case can use the values its variant holds
+        assert_eq!(
+            label(&area("Circle(r) => r * r Square(w, h) => w * h Empty => 0")),
+            None
+        );
+        // But not the ones another case binds
+        assert_eq!(
+            label(&area("Circle(r) => r Square(w, h) => r Empty => 0")),
+            Some("Cannot resolve 'r'".to_string())
+        );
+        // Every missing variant is listed, in the order they're declared
+        assert_eq!(
+            label(&area("Square(w, h) => w")),
+            Some("This match doesn't handle 'Circle', 'Empty'".to_string())
+        );
+        assert_eq!(
+            label(&area("Empty => 0")),
+            Some("This match doesn't handle 'Circle', 'Square'".to_string())
+        );
+        // Covering a variant twice doesn't cover the rest
+        assert_eq!(
+            label(&area("Circle(r) => r Circle(d) => d Square(w, h) => w")),
+            Some("This match doesn't handle 'Empty'".to_string())
+        );
+    }
+
+    #[test]
+    fn cover_booleans_and_wildcards() {
+        let describe = |value: &str, cases: &str| {
+            format!(
+                "fn describe(b: bool, n: number): number {{ return match {} {{ {} }} }}",
+                value, cases
+            )
+        };
+        assert_eq!(label(&describe("b", "true => 1 false => 0")), None);
+        assert_eq!(label(&describe("b", "false => 0 true => 1")), None);
+        assert_eq!(
+            label(&describe("b", "true => 1")),
+            Some("This match doesn't handle 'false'".to_string())
+        );
+        assert_eq!(
+            label(&describe("b", "false => 0 false => 1")),
+            Some("This match doesn't handle 'true'".to_string())
+        );
+        // A wildcard handles whatever's left
+        assert_eq!(label(&describe("b", "true => 1 _ => 0")), None);
+        assert_eq!(label(&describe("b", "_ => 0")), None);
+        let area = format!(
+            "{}fn area(s: Shape): number {{ return match s {{ Empty => 0 _ => 1 }} }}",
+            SHAPE
+        );
+        assert_eq!(label(&area), None);
+        // Other values can't be checked, so they're left to the wildcard
+        assert_eq!(label(&describe("n", "1 => 1 2 => 0")), None);
+        assert_eq!(label(&describe("n", "1 => 1 _ => 0")), None);
+    }
+}
@@ -0,0 +1,128 @@
+//! What editors show above each function: where it's used, and the value it
+//! returns when that never changes.
+use crate::control_flow::ControlFlowAnalysis;
+use crate::evaluate::evaluate;
+use crate::parser::ParserImpl;
+use common::control_flow_graph::{ControlFlowMapKey, ControlFlowNode};
+use diagnostics::result::Result;
+use evaluate::Value;
+use std::collections::HashMap;
+use std::ops::Range;
+use syntax::arena_pool::with_arena;
+use syntax::ast::*;
+use syntax::visit::Visitor;
+
+/// What's known about a function in a module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionLens {
+    /// Where the function's name is.
+    pub name: Range<usize>,
+    /// Where the function is referenced in the module.
+    pub references: Vec<Range<usize>>,
+    /// The value the function always returns, as it's written in the
+    /// source, if it can be found without calling it.
+    pub returns: Option<String>,
+}
+
+/// A lens for each function in `source`, in the order they're defined.
+pub fn function_lenses(source: &str) -> Result<Vec<FunctionLens>> {
+    with_arena(|arena| {
+        let module_id = ParserImpl::new(source, arena).parse_module()?;
+        let analysis = ControlFlowAnalysis::new(arena);
+        analysis.visit_module(module_id)?;
+        let cfg_map = analysis.finish();
+
+        let mut references: HashMap<FunctionId, Vec<Range<usize>>> = HashMap::new();
+        for (expression_id, expression) in arena.expressions.iter() {
+            if let Expression::Reference(Binding::Function(function_id)) = *expression.borrow() {
+                if let Some(span) = arena.expression_span(expression_id) {
+                    references.entry(function_id).or_default().push(span.into());
+                }
+            }
+        }
+
+        let mut lenses: Vec<FunctionLens> = arena
+            .functions
+            .iter()
+            .map(|(function_id, function)| {
+                let cfg = cfg_map.get(&ControlFlowMapKey::Function(function_id));
+                // A function that can end without returning doesn't always
+                // return anything
+                let returns = cfg
+                    .filter(|cfg| cfg.find_fall_through_blocks().is_empty())
+                    .and_then(|cfg| {
+                        let statements = cfg.graph.node_weights().flat_map(|node| match node {
+                            ControlFlowNode::BasicBlock(block) => block.statements.clone(),
+                            _ => vec![],
+                        });
+                        let mut returns = statements.filter_map(|statement_id| {
+                            match arena.statements.get(statement_id).unwrap() {
+                                Statement::Return(expression_id) => {
+                                    Some(evaluate(arena, *expression_id, None))
+                                }
+                                _ => None,
+                            }
+                        });
+                        let first = returns.next()??;
+                        returns.all(|value| value == Some(first)).then_some(first)
+                    })
+                    .and_then(format_value);
+                FunctionLens {
+                    name: function.borrow().name.span.into(),
+                    references: references.remove(&function_id).unwrap_or_default(),
+                    returns,
+                }
+            })
+            .collect();
+        lenses.sort_by_key(|lens| lens.name.start);
+        Ok(lenses)
+    })
+}
+
+/// A value as it would be written in the source, unless it's an error.
+fn format_value(value: Value) -> Option<String> {
+    match value {
+        Value::Boolean(boolean) => Some(boolean.to_string()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::String(symbol) => Some(format!("\"{}\"", symbol)),
+        Value::Error(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::function_lenses;
+
+    #[test]
+    fn references_and_constant_returns() {
+        let source = "fn answer(): number {
+  return 40 + 2
+}
+
+fn sign(a: number): number {
+  if a > 0 {
+    return 1
+  }
+  return answer()
+}
+
+fn main() {
+  _ = answer()
+  _ = sign(answer())
+}
+";
+        let lenses: Vec<(&str, usize, Option<String>)> = function_lenses(source)
+            .unwrap()
+            .into_iter()
+            .map(|lens| (&source[lens.name], lens.references.len(), lens.returns))
+            .collect();
+        assert_eq!(
+            lenses,
+            vec![
+                ("answer", 3, Some("42".to_string())),
+                ("sign", 1, None),
+                ("main", 0, None),
+            ]
+        );
+    }
+}
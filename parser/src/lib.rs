@@ -1,6 +1,19 @@
 pub mod parser;
+pub mod ranges;
+pub mod signature_help;
 
 pub mod test_utils;
-mod control_flow;
-mod evaluate;
-pub mod parser_;
\ No newline at end of file
+pub mod control_flow;
+pub mod definitions;
+mod constant_conditions;
+mod effects;
+pub mod evaluate;
+pub mod lenses;
+mod exhaustiveness;
+mod modules;
+mod renders;
+mod returns;
+mod state_writes;
+mod unused_parameters;
+mod unused_state;
+mod unused_values;
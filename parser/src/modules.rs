@@ -0,0 +1,316 @@
+//! Finds the files imports refer to. An import path like `ui.button` is a
+//! path from the directory of the module importing it, to either the file
+//! `ui/button.ws` or, for a directory, its `ui/button/mod.ws`.
+use crate::definitions::split_definitions;
+use crate::parser::ParserImpl;
+use common::target::Target;
+use diagnostics::error::{constant_cycle, module_not_found, private_import, Diagnostic};
+use diagnostics::result::{DiagnosticBag, Result};
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+use syntax::arena_pool::with_arena;
+use syntax::ast::{
+    AstArena, Binding, DefinitionKind, Expression, ExpressionId, Import, ImportId,
+    ImportSpecifierId,
+};
+use syntax::features::Features;
+use syntax::span::{line_column, LineColumn, Span};
+use syntax::visit::{walk_expression, Visitor};
+use vfs::FileSystem;
+
+/// The files a module path could refer to from the module at `from`, in
+/// the order they're tried.
+pub fn candidate_paths(from: &Path, path: &[String]) -> Vec<PathBuf> {
+    let module: PathBuf = path.iter().collect();
+    let module = match from.parent() {
+        Some(directory) => directory.join(module),
+        None => module,
+    };
+    vec![module.with_extension("ws"), module.join("mod.ws")]
+}
+
+/// Find the file for each import, returning the path the compiled module
+/// imports it from. Every import that doesn't have a file, or that names a
+/// definition the file doesn't make public, is reported.
+pub fn resolve_imports(
+    db: &dyn FileSystem,
+    from: &Path,
+    arena: &AstArena,
+    imports: &[ImportId],
+) -> Result<HashMap<ImportId, String>> {
+    let mut resolved = HashMap::new();
+    let mut errors = DiagnosticBag::new();
+    for import_id in imports {
+        let import = &arena.imports[*import_id];
+        let path: Vec<String> = import
+            .path
+            .iter()
+            .map(|part| part.symbol.to_string())
+            .collect();
+        let candidates = candidate_paths(from, &path);
+        match candidates
+            .iter()
+            .find(|candidate| db.file_exists(candidate.to_path_buf()))
+        {
+            Some(file) => {
+                errors.extend(check_visibility(db, arena, import, &path, file));
+                resolved.insert(*import_id, output_path(from, file));
+            }
+            None => {
+                let first = import.path[0].span;
+                let span = first.merge(import.path.last().unwrap().span);
+                errors.push(module_not_found(span, path.join("."), &candidates));
+            }
+        }
+    }
+    errors.finish(Ok(resolved))
+}
+
+/// Report every name `import` brings in from `file` that's defined there
+/// without `pub`. Names the file doesn't define at all aren't checked here.
+fn check_visibility(
+    db: &dyn FileSystem,
+    arena: &AstArena,
+    import: &Import,
+    path: &[String],
+    file: &Path,
+) -> Vec<Diagnostic> {
+    let source = match db.read_file(file.to_path_buf()) {
+        Some(source) => source,
+        None => return vec![],
+    };
+    let chunks = split_definitions(&source);
+    let mut errors = vec![];
+    for specifier_id in &import.specifiers {
+        let name = match &arena.import_specifiers[*specifier_id].name {
+            Some(name) => name,
+            None => continue,
+        };
+        let symbol = name.symbol.to_string();
+        let private = chunks
+            .iter()
+            .find(|chunk| chunk.name == symbol && !chunk.public);
+        if let Some(chunk) = private {
+            let LineColumn { line, column } = line_column(&source, chunk.range.start);
+            let defined_at = format!("{}:{}:{}", file.display(), line + 1, column + 1);
+            errors.push(private_import(
+                name.span,
+                &symbol,
+                path.join("."),
+                defined_at,
+            ));
+        }
+    }
+    errors
+}
+
+/// A constant in a module, and the constants it reads while it's initialized,
+/// as the file each is in and its name there.
+struct ModuleConstant {
+    name: String,
+    span: Span,
+    reads: Vec<(PathBuf, String)>,
+}
+
+/// Report every constant in the module at `path` that reads its own value
+/// through constants in other modules. Modules are initialized after the
+/// modules they import, in the order they're imported, so one constant in a
+/// cycle like that is always read before it's initialized.
+pub fn check_constant_cycles(
+    db: &dyn FileSystem,
+    path: &Path,
+    source: &str,
+    target: Target,
+    features: &Features,
+) -> Result<()> {
+    let own = module_constants(db, path, source, target, features);
+    let mut modules: HashMap<PathBuf, Vec<ModuleConstant>> = HashMap::new();
+    let mut errors = DiagnosticBag::new();
+    for constant in &own {
+        let start = (path.to_path_buf(), constant.name.clone());
+        // Each path through the constants it reads, searched depth-first
+        let mut chains: Vec<Vec<(PathBuf, String)>> = constant
+            .reads
+            .iter()
+            .map(|read| vec![read.clone()])
+            .collect();
+        let mut visited = HashSet::new();
+        while let Some(chain) = chains.pop() {
+            let (file, name) = chain.last().unwrap();
+            if (file, name) == (&start.0, &start.1) {
+                let cycle: Vec<String> = chain
+                    .iter()
+                    .map(|(file, name)| match file.file_name() {
+                        Some(file_name) if file != path => {
+                            format!("'{}' from {}", name, file_name.to_string_lossy())
+                        }
+                        _ => format!("'{}'", name),
+                    })
+                    .collect();
+                errors.push(constant_cycle(constant.span, &constant.name, &cycle));
+                break;
+            }
+            if !visited.insert((file.clone(), name.clone())) {
+                continue;
+            }
+            let constants = if file == path {
+                &own
+            } else {
+                modules.entry(file.clone()).or_insert_with(|| {
+                    db.read_file(file.clone()).map_or(vec![], |source| {
+                        module_constants(db, file, &source, target, features)
+                    })
+                })
+            };
+            if let Some(read) = constants.iter().find(|constant| constant.name == *name) {
+                for next in &read.reads {
+                    let mut chain = chain.clone();
+                    chain.push(next.clone());
+                    chains.push(chain);
+                }
+            }
+        }
+    }
+    errors.into_result()
+}
+
+/// The constants the module at `path` defines. Modules that don't parse
+/// don't have any here, since they report their own errors when they're
+/// compiled.
+fn module_constants(
+    db: &dyn FileSystem,
+    path: &Path,
+    source: &str,
+    target: Target,
+    features: &Features,
+) -> Vec<ModuleConstant> {
+    with_arena(|arena| {
+        let module_id = match ParserImpl::new(source, arena)
+            .with_target(target)
+            .with_features(features)
+            .parse_module()
+        {
+            Ok(module_id) => module_id,
+            Err(_) => return vec![],
+        };
+        let mut constants = vec![];
+        for definition in &arena.modules[module_id].definitions {
+            let const_id = match definition.kind {
+                DefinitionKind::Const(const_id) => const_id,
+                _ => continue,
+            };
+            let const_ = &arena.consts[const_id];
+            let collector = ReferenceCollector {
+                arena,
+                bindings: RefCell::default(),
+            };
+            collector.visit_expression(const_.value).unwrap();
+            let reads = collector
+                .bindings
+                .into_inner()
+                .into_iter()
+                .filter_map(|binding| match binding {
+                    Binding::Const(const_id) => Some((
+                        path.to_path_buf(),
+                        arena.consts[const_id].name.symbol.to_string(),
+                    )),
+                    Binding::Import(specifier_id) => imported_name(db, path, arena, specifier_id),
+                    _ => None,
+                })
+                .collect();
+            constants.push(ModuleConstant {
+                name: const_.name.symbol.to_string(),
+                span: const_.name.span,
+                reads,
+            });
+        }
+        constants
+    })
+}
+
+/// The file an import specifier imports from, and the name it imports.
+fn imported_name(
+    db: &dyn FileSystem,
+    from: &Path,
+    arena: &AstArena,
+    specifier_id: ImportSpecifierId,
+) -> Option<(PathBuf, String)> {
+    let name = arena.import_specifiers[specifier_id].name?;
+    let (_, import) = arena
+        .imports
+        .iter()
+        .find(|(_, import)| import.specifiers.contains(&specifier_id))?;
+    let path: Vec<String> = import
+        .path
+        .iter()
+        .map(|part| part.symbol.to_string())
+        .collect();
+    let file = candidate_paths(from, &path)
+        .into_iter()
+        .find(|candidate| db.file_exists(candidate.to_path_buf()))?;
+    Some((file, name.symbol.to_string()))
+}
+
+/// Collects the bindings an expression reads when it's evaluated. Functions
+/// aren't called while they're defined, so references in them aren't read.
+struct ReferenceCollector<'a> {
+    arena: &'a AstArena,
+    bindings: RefCell<Vec<Binding>>,
+}
+
+impl<'a> Visitor for ReferenceCollector<'a> {
+    fn context(&self) -> &AstArena {
+        self.arena
+    }
+
+    fn visit_expression(&self, expression_id: ExpressionId) -> Result<()> {
+        match &*self.arena.expressions[expression_id].borrow() {
+            Expression::Reference(binding) => self.bindings.borrow_mut().push(*binding),
+            Expression::Function(_) => return Ok(()),
+            _ => {}
+        }
+        walk_expression(self, expression_id)
+    }
+}
+
+/// The path the module at `from` imports `file` from once they're compiled,
+/// like `./ui/button/mod.js`.
+pub(crate) fn output_path(from: &Path, file: &Path) -> String {
+    let file = match from.parent() {
+        Some(directory) => file.strip_prefix(directory).unwrap_or(file),
+        None => file,
+    };
+    let parts: Vec<String> = file
+        .with_extension("js")
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy().into_owned()),
+            _ => None,
+        })
+        .collect();
+    format!("./{}", parts.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{candidate_paths, output_path};
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn module_paths() {
+        let from = Path::new("/app/main.ws");
+        let path = vec!["ui".to_string(), "button".to_string()];
+        assert_eq!(
+            candidate_paths(from, &path),
+            vec![
+                PathBuf::from("/app/ui/button.ws"),
+                PathBuf::from("/app/ui/button/mod.ws")
+            ]
+        );
+        assert_eq!(
+            output_path(from, Path::new("/app/ui/button/mod.ws")),
+            "./ui/button/mod.js"
+        );
+    }
+}
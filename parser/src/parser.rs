@@ -1,505 +1,1203 @@
-use core::panic;
-use diagnostics::result::Result;
-use lexer::Lexer;
-use std::path::PathBuf;
-use std::sync::Arc;
-use std::vec;
-use syntax::arena::alloc_expression;
-use syntax::{
-    arena::{AstArena, FunctionId, StatementId},
-    ast::*,
-    Precedence, Span, Token, TokenKind,
+use common::{
+    scope_map::ScopeMap,
+    symbol::{Symbol, SymbolScope},
+    target::Target,
 };
+use diagnostics::error::{Diagnostic, Error};
+use diagnostics::events::{HasEventSink, Phase};
+use diagnostics::result::{DiagnosticBag, Result};
+use lexer::{TokenReader, TokenStream};
+use syntax::arena_pool::with_arena;
+use syntax::builtins::{parse_format, Builtin, FormatPart};
+use syntax::events::{event_type, handled_event, EventType};
+use syntax::features::{Feature, Features};
+use syntax::{ast::*, visit::Visitor, Precedence, Span, Token, TokenKind};
+use tracing::{debug, info, info_span};
+
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 use vfs::FileSystem;
 
-use common::scope_map::ScopeMap;
-use common::symbol::Symbol;
-use log::debug;
-use types::Type;
+use crate::constant_conditions::ConstantConditionAnalysis;
+use crate::effects::EffectAnalysis;
+use crate::evaluate::ExpressionEvaluator;
+use crate::exhaustiveness::check_match_exhaustiveness;
+use crate::modules::{check_constant_cycles, output_path, resolve_imports};
+use crate::renders::RenderAnalysis;
+use crate::returns::ReturnAnalysis;
+use crate::state_writes::StateWriteAnalysis;
+use crate::unused_parameters::UnusedParameterAnalysis;
+use crate::unused_state::UnusedStateAnalysis;
+use crate::unused_values::UnusedValueAnalysis;
+
+use crate::control_flow::ControlFlowAnalysis;
+use crate::definitions::{
+    split_definitions, DefinitionChunk, DefinitionSource, DefinitionText, DefinitionTokens,
+};
+use common::control_flow_graph::ControlFlowMapKey;
+
+use codegen::{
+    content_hash, hashed_path, output_hash, render_asset_manifest, render_index, render_module,
+    short_hash, Codegen, CodegenOptions, ComponentReport, LineIndex, Manifest, ReactivityGraph,
+    ASSET_MANIFEST_FILE,
+};
 
 #[salsa::query_group(ParserDatabase)]
-pub trait Parser: FileSystem {
-    fn parse(&self, path: PathBuf) -> Result<Module>;
+pub trait Parser: FileSystem + HasEventSink {
+    #[salsa::input]
+    fn codegen_options(&self) -> CodegenOptions;
+    /// The features a file can use, from the options and the `#feature(...)`
+    /// lines at its top.
+    fn module_features(&self, path: PathBuf) -> Result<Features>;
+    /// The top-level definitions in a file, found by scanning its text.
+    fn definition_chunks(&self, path: PathBuf) -> Arc<Vec<DefinitionChunk>>;
+    /// The text a single definition is compiled from. This only changes when
+    /// the definition or one it depends on does.
+    fn definition_text(&self, path: PathBuf, name: String) -> Arc<DefinitionText>;
+    /// A definition's text lexed into tokens.
+    fn lexed_definition(&self, path: PathBuf, name: String) -> Arc<TokenStream>;
+    /// The tokens a definition is compiled from. Unlike `lexed_definition`,
+    /// this doesn't change when only whitespace or comments are edited.
+    fn definition_tokens(&self, path: PathBuf, name: String) -> Arc<DefinitionTokens>;
+    fn compile_definition(&self, path: PathBuf, name: String) -> Result<Arc<CompiledDefinition>>;
+    /// Every definition in a file, compiled. Warnings are reported as it's compiled.
+    fn compile_module(&self, path: PathBuf) -> Result<Arc<CompiledModule>>;
+    fn parse(&self, path: PathBuf) -> Result<()>;
+    /// What the templates of each component in a file render, and which
+    /// state updates them.
+    fn template_report(&self, path: PathBuf) -> Result<Arc<Vec<ComponentReport>>>;
+    /// Which expressions in each component's templates read each piece of
+    /// state, and what they update when it changes.
+    fn reactivity_graph(&self, path: PathBuf) -> Result<Arc<Vec<ReactivityGraph>>>;
 }
 
-/// Database query for parsing a path.
-fn parse(db: &dyn Parser, path: PathBuf) -> Result<Module> {
-    let source = db.file_text(path);
-    let mut ast_arena = AstArena::default();
-    let parser = ParserImpl::new(&source, &mut ast_arena);
-    let module = parser.parse_module()?;
-    // let mut cfg_analysis = ControlFlowAnalysis::new(&mut ast_arena);
-    // cfg_analysis.visit_module(&mut module)?;
-    // let cfg_map = cfg_analysis.finish();
-    Ok(module)
+/// The output for a single top-level definition. Spans are the indices of
+/// tokens in the definition's `lexed_definition`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompiledDefinition {
+    /// The module-level JavaScript definitions it compiled to, including any
+    /// private definitions it references.
+    pub code: Vec<String>,
+    /// Warnings for the definition itself.
+    pub warnings: Vec<Diagnostic>,
+    /// The names of the public definitions it compiled to.
+    pub exports: Vec<String>,
 }
 
-/// Core data structure for the parser, creates the `Lexer` instance
-/// and lazily creates and consumes tokens as it parses.
-pub struct ParserImpl<'s> {
-    lexer: Lexer<'s>,
-    prev_span: Span,
-    span: Span,
-    is_newline_significant: bool,
-    is_async_context: bool,
-    is_component_context: bool,
-    scope_map: ScopeMap<Symbol, Binding>,
-    type_scope_map: ScopeMap<Symbol, TypeBinding>,
-    allow_effect_reference: bool,
-    pub ast_arena: &'s mut AstArena,
-    reference_tracker: std::collections::HashSet<FunctionId>,
+/// The output for a whole file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompiledModule {
+    /// The module-level JavaScript definitions, in the order they're defined.
+    pub code: Vec<String>,
+    /// The names the module exports.
+    pub exports: Vec<String>,
 }
 
-impl<'s> ParserImpl<'s> {
-    /// Create a new instance of `ParseImpl`
-    pub fn new(source: &'s str, ast_arena: &'s mut AstArena) -> Self {
-        let lexer = Lexer::new(source);
-        let scope_map = ScopeMap::default();
-        let type_scope_map = ScopeMap::default();
-        let span = Span::new(0, 0);
-        Self {
-            lexer,
-            prev_span: span,
-            span,
-            is_newline_significant: false,
-            is_async_context: false,
-            is_component_context: false,
-            scope_map,
-            type_scope_map,
-            allow_effect_reference: false,
-            ast_arena,
-            reference_tracker: std::collections::HashSet::new(),
-        }
+fn module_features(db: &dyn Parser, path: PathBuf) -> Result<Features> {
+    let source = db.file_text(path);
+    let mut features = db.codegen_options().features;
+    let unknown = features.enable_directives(&source);
+    if unknown.is_empty() {
+        return Ok(features);
     }
+    use diagnostics::error::unknown_feature;
+    let names: Vec<&str> = Feature::ALL.iter().map(|feature| feature.name()).collect();
+    let diagnostics = unknown
+        .into_iter()
+        .map(|(name, span)| unknown_feature(span, name, &names))
+        .collect();
+    Err(Error::Diagnostics(diagnostics))
+}
 
-    /// Skip the next token and do nothing with it.
-    fn skip(&mut self) -> Result<()> {
-        self.next()?;
-        Ok(())
-    }
+fn definition_chunks(db: &dyn Parser, path: PathBuf) -> Arc<Vec<DefinitionChunk>> {
+    let source = db.file_text(path);
+    Arc::new(split_definitions(&source))
+}
 
-    /// Consume a token if it matches the provided `kind`,
-    /// otherwise do nothing. Returns whether the token was consumed.
-    fn eat(&mut self, kind: TokenKind) -> Result<bool> {
-        debug!("eat {:?}", kind);
-        if self.peek()? == &kind {
-            self.skip()?;
-            Ok(true)
+fn definition_text(db: &dyn Parser, path: PathBuf, name: String) -> Arc<DefinitionText> {
+    let source = db.file_text(path.clone());
+    let chunks = db.definition_chunks(path);
+    Arc::new(DefinitionSource::new(&source, &chunks, &name).text)
+}
+
+fn lexed_definition(db: &dyn Parser, path: PathBuf, name: String) -> Arc<TokenStream> {
+    let text = db.definition_text(path, name);
+    Arc::new(TokenStream::lex(&text.text))
+}
+
+fn definition_tokens(db: &dyn Parser, path: PathBuf, name: String) -> Arc<DefinitionTokens> {
+    let text = db.definition_text(path.clone(), name.clone());
+    let tokens = db.lexed_definition(path, name);
+    Arc::new(DefinitionTokens::new(&text, tokens))
+}
+
+/// Database query for compiling a single definition. This only reads the
+/// definition's tokens, so editing other definitions, or just the whitespace
+/// and comments in this one, won't recompute it.
+fn compile_definition(
+    db: &dyn Parser,
+    path: PathBuf,
+    name: String,
+) -> Result<Arc<CompiledDefinition>> {
+    let _span = info_span!("compile_definition", %name).entered();
+    // Nothing this query returns holds onto symbols, so they can be freed
+    // along with the rest of the file's symbols.
+    let _symbols = SymbolScope::new(&path).enter();
+    with_arena(|arena| compile_definition_in(db, path, name, arena))
+}
+
+/// Compile a single definition, parsing it into `arena`.
+fn compile_definition_in(
+    db: &dyn Parser,
+    path: PathBuf,
+    name: String,
+    arena: &mut AstArena,
+) -> Result<Arc<CompiledDefinition>> {
+    let events = db.event_sink();
+    let tokens = db.definition_tokens(path.clone(), name.clone());
+    let target = db.codegen_options().target;
+    let features = db.module_features(path.clone())?;
+    let mut parser = ParserImpl::with_tokens(&tokens.tokens, arena)
+        .with_target(target)
+        .with_features(&features);
+    events.phase_started(&path, Phase::Parse);
+    let module_id = info_span!("parse").in_scope(|| parser.parse_module())?;
+    // Diagnostics are only reported for the definition's own chunk, the
+    // chunks it depends on report their own.
+    let is_own = |diagnostic: &Diagnostic| match diagnostic.primary_range() {
+        Some(range) => tokens.is_own(range.start),
+        None => true,
+    };
+    // Writing state from callbacks is allowed, but it's likely a mistake so
+    // we warn about it, unless it's been made an error.
+    let mut warnings: Vec<Diagnostic> = {
+        use diagnostics::error::Severity;
+        let _span = info_span!("state_writes").entered();
+        events.phase_started(&path, Phase::StateWrites);
+        let severity = if db.codegen_options().deny_escaping_state_writes {
+            Severity::Error
         } else {
-            Ok(false)
+            Severity::Warning
+        };
+        let state_writes = StateWriteAnalysis::new(arena, severity);
+        state_writes
+            .analyze(module_id)?
+            .into_iter()
+            .filter(is_own)
+            .collect()
+    };
+    // State that's never read or never written still compiles, but it's
+    // likely left over from an edit.
+    {
+        let _span = info_span!("unused_state").entered();
+        let unused = UnusedStateAnalysis::new(arena).analyze(module_id)?;
+        warnings.extend(unused.into_iter().filter(is_own));
+    }
+    // So are values that are computed and then thrown away
+    {
+        let _span = info_span!("unused_values").entered();
+        let unused = UnusedValueAnalysis::new(arena).analyze(module_id)?;
+        warnings.extend(unused.into_iter().filter(is_own));
+    }
+    // And parameters that are never used, unless that's been allowed
+    if !db.codegen_options().allow_unused_parameters {
+        let _span = info_span!("unused_parameters").entered();
+        let unused = UnusedParameterAnalysis::new(arena).analyze(module_id)?;
+        warnings.extend(unused.into_iter().filter(is_own));
+    }
+    // Functions can only perform the effects they declare
+    {
+        let _span = info_span!("effects").entered();
+        let errors: Vec<Diagnostic> = EffectAnalysis::new(arena)
+            .analyze(module_id)?
+            .into_iter()
+            .filter(is_own)
+            .collect();
+        if !errors.is_empty() {
+            return Err(Error::from_diagnostics(errors));
         }
     }
+    // The chunk for this definition comes along with the ones it depends on,
+    // but only the definitions in its own chunk are compiled here.
+    let own: Vec<DefinitionKind> = arena.modules[module_id]
+        .definitions
+        .iter()
+        .filter(|definition| is_own_definition(arena, &tokens, definition.kind))
+        .map(|definition| definition.kind)
+        .collect();
+    let imports: Vec<ImportId> = own
+        .iter()
+        .filter_map(|kind| match kind {
+            DefinitionKind::Import(import_id) => Some(*import_id),
+            _ => None,
+        })
+        .collect();
+    let import_paths = resolve_imports(db, &path, arena, &imports)?;
+    // Evaluate step
+    let (code, exports) = {
+        let evaluate = ExpressionEvaluator::new(arena);
+
+        events.phase_started(&path, Phase::Evaluate);
+        info_span!("evaluate").in_scope(|| evaluate.visit_module(module_id))?;
+        // We want to do constant propagation before we do control flow analysis.
+        // That way we can populate known values in call expressions and generate
+        // control flow graphs that have annotated return value data.
+        // That way we support constant functions, where we can statically determine
+        // the return value of a function and inline.
+
+        let cfg_analysis = ControlFlowAnalysis::new(arena);
+        events.phase_started(&path, Phase::ControlFlow);
+        info_span!("control_flow").in_scope(|| cfg_analysis.visit_module(module_id))?;
+        let cfg_map = cfg_analysis.finish();
+
+        // Functions with a return type have to return on every path
+        let errors: Vec<Diagnostic> = ReturnAnalysis::new(arena, &cfg_map)
+            .analyze()
+            .into_iter()
+            .filter(is_own)
+            .collect();
+        if !errors.is_empty() {
+            return Err(Error::from_diagnostics(errors));
+        }
+        // Templates in components that are never rendered are likely a mistake
+        let unrendered = RenderAnalysis::new(arena, &cfg_map).analyze();
+        warnings.extend(unrendered.into_iter().filter(is_own));
+        if db.codegen_options().warn_constant_conditions {
+            let constant = ConstantConditionAnalysis::new(arena, &cfg_map).analyze();
+            warnings.extend(constant.into_iter().filter(is_own));
+        }
 
-    /// Consume the next token, if it has the provided `kind`.
-    /// If not, we throw an unexpected token error.
-    fn expect(&mut self, kind: TokenKind) -> Result<Token> {
-        let token = self.next()?;
-        let span = self.span;
-        let prev_span = self.prev_span;
-        if token != kind {
-            use diagnostics::error::unexpected_token_error;
-            unexpected_token_error(span, prev_span, kind, token.kind)
-        } else {
-            Ok(token)
+        let options = db.codegen_options();
+        let debug_comments = options.debug_comments;
+        let mut codegen = Codegen::new("main".to_string(), arena, cfg_map)
+            .with_options(options)
+            .with_import_paths(import_paths);
+        // Debug comments need the line each token is on, which makes them
+        // the one thing here that depends on whitespace.
+        if debug_comments {
+            let lines = db.lexed_definition(path.clone(), name).lines();
+            codegen = codegen
+                .with_line_index(LineIndex::from_lines(lines))
+                .with_source_path(path.to_string_lossy().into_owned());
         }
-    }
 
-    /// Consume the next token from the lexer
-    fn next(&mut self) -> Result<Token> {
-        let token = self.lexer.next_token()?;
-        debug!("next {:?}", token.kind);
-        // Ignore newlines when they are not considered significant
-        if token.is_newline() && !self.is_newline_significant {
-            debug!("skipping newline");
-            self.next()
-        } else {
-            self.prev_span = self.span;
-            self.span = token.span;
-            Ok(token)
+        events.phase_started(&path, Phase::Codegen);
+        codegen.codegen_definitions(module_id, |definition| own.contains(&definition.kind))?;
+        if let Some(pass) = &db.codegen_options().print_after {
+            for part in codegen.take_printed_passes() {
+                events.pass_printed(&path, pass, &part);
+            }
+        }
+        (codegen.rendered_definitions()?, codegen.exported_names())
+    };
+    Ok(Arc::new(CompiledDefinition {
+        code,
+        warnings,
+        exports,
+    }))
+}
+
+/// Whether a definition is in the chunk `tokens` were lexed for, and not one
+/// of the chunks it depends on.
+fn is_own_definition(arena: &AstArena, tokens: &DefinitionTokens, kind: DefinitionKind) -> bool {
+    match arena.definition_span(kind) {
+        Some(span) => {
+            let range: Range<usize> = span.into();
+            tokens.is_own(range.start)
         }
+        None => false,
     }
+}
 
-    /// Look at the next token without consuming it
-    fn peek(&mut self) -> Result<&Token> {
-        let token_kind = &self.lexer.peek()?.kind;
-        debug!("peek {:#?}", token_kind);
-        // Ignore newlines when they are not considered significant
-        if token_kind == &TokenKind::Newline && !self.is_newline_significant {
-            self.lexer.next_token()?;
-            self.peek()
-        } else {
-            self.lexer.peek()
+/// Database query for compiling every definition in a path.
+fn compile_module(db: &dyn Parser, path: PathBuf) -> Result<Arc<CompiledModule>> {
+    let _span = info_span!("compile", path = %path.display()).entered();
+    let events = db.event_sink();
+    let source = db.file_text(path.clone());
+    let chunks = db.definition_chunks(path.clone());
+    // Unknown features are reported once for the module, rather than by
+    // each definition
+    db.module_features(path.clone())?;
+    {
+        let _symbols = SymbolScope::new(&path).enter();
+        let options = db.codegen_options();
+        check_constant_cycles(db, &path, &source, options.target, &options.features)?;
+    }
+    let max_errors = db.codegen_options().max_errors;
+    let mut definitions = vec![];
+    let mut exports = vec![];
+    let mut errors = DiagnosticBag::with_limit(max_errors);
+    let mut compiled = HashSet::new();
+    for chunk in chunks.iter() {
+        if !compiled.insert(&chunk.name) {
+            continue;
+        }
+        events.definition_started(&path, &chunk.name, chunk.range.clone());
+        // Map spans in the definition's tokens back to the file.
+        let definition_source = DefinitionSource::new(&source, &chunks, &chunk.name);
+        let tokens = db.lexed_definition(path.clone(), chunk.name.clone());
+        let module_range =
+            |range: Range<usize>| definition_source.module_range(tokens.source_range(range));
+        // Keep compiling the other definitions, so their errors are reported
+        // too. Definitions are parsed with the ones they depend on, so an
+        // error in one can be reported by each of them, but the bag only
+        // keeps it once.
+        let result = db
+            .compile_definition(path.clone(), chunk.name.clone())
+            .map_err(|error| error.map_ranges(module_range));
+        let definition = match errors.report(result)? {
+            Some(definition) => definition,
+            None if errors.overflowed() => break,
+            None => continue,
+        };
+        for warning in &definition.warnings {
+            let warning = warning.clone().map_ranges(module_range);
+            events.diagnostic(&path, &source, &warning);
         }
+        definitions.extend(definition.code.iter().cloned());
+        exports.extend(definition.exports.iter().cloned());
     }
+    errors.finish(Ok(Arc::new(CompiledModule {
+        code: definitions,
+        exports,
+    })))
+}
 
-    /// Primary public API for the `ParseImpl`. Parses all
-    /// imports and definitions in a module, which is currently assumed
-    /// to be a single file.
-    pub fn parse_module(mut self) -> Result<Module> {
-        let imports = self.imports()?;
-        let definitions = self.definitions()?;
-        let module = Module::new(imports, definitions);
-        Ok(module)
+/// Database query for parsing a path.
+fn parse(db: &dyn Parser, path: PathBuf) -> Result<()> {
+    let module = db.compile_module(path.clone())?;
+
+    // Path should be fixtures/output.js from the project root, absolute
+    let mut output = PathBuf::from(OUTPUT_PATH);
+    let options = db.codegen_options();
+    let module_name = path.with_extension("");
+    let contents = render_module(&module_name.to_string_lossy(), &options, &module.code)?;
+    let source = db.file_text(path.clone());
+
+    let mut manifest = Manifest::load(Path::new(MANIFEST_PATH));
+    if options.hash_filenames {
+        output = hashed_path(&output, output_hash(&contents));
+        let file = output.file_name().unwrap().to_string_lossy().into_owned();
+        let assets = render_asset_manifest(&[("main".to_string(), file)]);
+        manifest.write(&output.with_file_name(ASSET_MANIFEST_FILE), None, &assets)?;
+    }
+    info!(path = %output.display(), "writing output");
+    if manifest.write(&output, Some((&path, &source)), &contents)? {
+        db.event_sink().output_written(&path, &output);
     }
+    manifest.save(Path::new(MANIFEST_PATH))?;
+    Ok(())
+}
 
-    /// Parses all imports at the top of a module. We currently require
-    /// that all imports are grouped together at the top of the module.
-    fn imports(&mut self) -> Result<Vec<Import>> {
-        let mut imports = vec![];
-        loop {
-            match self.peek()?.kind {
-                TokenKind::Import => {
-                    let import = self.import()?;
-                    imports.push(import)
+/// Database query for reporting on the templates in a path. Definitions are
+/// only parsed, so this works for files that don't compile for other reasons.
+fn template_report(db: &dyn Parser, path: PathBuf) -> Result<Arc<Vec<ComponentReport>>> {
+    let preserve_whitespace = db.codegen_options().preserve_whitespace;
+    let reports = report_definitions(db, path, |arena, module_id, include| {
+        codegen::template_report(arena, module_id, preserve_whitespace, include)
+    })?;
+    Ok(Arc::new(reports))
+}
+
+/// Database query for graphing the state in a path's templates. Like
+/// `template_report`, definitions are only parsed.
+fn reactivity_graph(db: &dyn Parser, path: PathBuf) -> Result<Arc<Vec<ReactivityGraph>>> {
+    let preserve_whitespace = db.codegen_options().preserve_whitespace;
+    let graphs = report_definitions(db, path, |arena, module_id, include| {
+        codegen::reactivity_graph(arena, module_id, preserve_whitespace, include)
+    })?;
+    Ok(Arc::new(graphs))
+}
+
+/// Parse each definition in a path on its own and `report` on it. `report`
+/// is given a filter for the definitions that belong to the one being
+/// parsed, and not the private definitions it pulled in.
+fn report_definitions<T>(
+    db: &dyn Parser,
+    path: PathBuf,
+    report: impl Fn(&AstArena, ModuleId, &dyn Fn(&Definition) -> bool) -> Result<Vec<T>>,
+) -> Result<Vec<T>> {
+    let _symbols = SymbolScope::new(&path).enter();
+    let source = db.file_text(path.clone());
+    let chunks = db.definition_chunks(path.clone());
+    let mut reports = vec![];
+    let mut reported = HashSet::new();
+    for chunk in chunks.iter() {
+        if !reported.insert(&chunk.name) {
+            continue;
+        }
+        let tokens = db.definition_tokens(path.clone(), chunk.name.clone());
+        let target = db.codegen_options().target;
+        let features = db.module_features(path.clone())?;
+        let reported = with_arena(|arena| {
+            let parsed = ParserImpl::with_tokens(&tokens.tokens, arena)
+                .with_target(target)
+                .with_features(&features)
+                .parse_module();
+            let module_id = match parsed {
+                Ok(module_id) => module_id,
+                Err(error) => {
+                    let definition_source = DefinitionSource::new(&source, &chunks, &chunk.name);
+                    let lexed = db.lexed_definition(path.clone(), chunk.name.clone());
+                    return Err(error.map_ranges(|range| {
+                        definition_source.module_range(lexed.source_range(range))
+                    }));
                 }
-                _ => break,
-            }
+            };
+            report(arena, module_id, &|definition| {
+                is_own_definition(arena, &tokens, definition.kind)
+            })
+        })?;
+        reports.extend(reported);
+    }
+    Ok(reports)
+}
+
+/// Write compiled modules as a library: each module is written to the
+/// same place under `output` as it is under `root`, along with an index
+/// that re-exports everything they export. Files that wouldn't change
+/// aren't written again, and `manifest_path` records what was written.
+/// Nothing is written if the index can't be generated.
+pub fn write_library(
+    db: &dyn Parser,
+    root: &Path,
+    modules: &[(PathBuf, Arc<CompiledModule>)],
+    output: &Path,
+    manifest_path: &Path,
+) -> Result<()> {
+    let options = db.codegen_options();
+    let mut files = vec![];
+    for (path, module) in modules {
+        let relative = path.strip_prefix(root).unwrap_or(path).with_extension("");
+        // Module names are written the same way on every platform, since
+        // they're also used in import paths.
+        let module_name = relative
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let contents = render_module(&module_name, &options, &module.code)?;
+        files.push(LibraryFile {
+            filename: format!("{}.js", module_name),
+            module_name,
+            contents,
+        });
+    }
+    if options.hash_filenames {
+        hash_library_filenames(modules, &mut files);
+    }
+    check_library_index(modules, &files)?;
+    let mut manifest = Manifest::load(manifest_path);
+    if options.hash_filenames {
+        let assets: Vec<(String, String)> = files
+            .iter()
+            .map(|file| (file.module_name.clone(), file.filename.clone()))
+            .collect();
+        let assets = render_asset_manifest(&assets);
+        manifest.write(&output.join(ASSET_MANIFEST_FILE), None, &assets)?;
+    }
+    let mut index = vec![];
+    for ((path, module), file) in modules.iter().zip(&files) {
+        let module_output = output.join(&file.filename);
+        let source = db.file_text(path.clone());
+        info!(path = %module_output.display(), "writing output");
+        if manifest.write(&module_output, Some((path, &source)), &file.contents)? {
+            db.event_sink().output_written(path, &module_output);
         }
-        Ok(imports)
+        index.push((file.filename.clone(), module.exports.clone()));
     }
+    manifest.write(&output.join("index.js"), None, &render_index(&index)?)?;
+    manifest.save(manifest_path)?;
+    Ok(())
+}
 
-    /// Parse a single import statement
-    fn import(&mut self) -> Result<Import> {
-        self.expect(TokenKind::Import)?;
-        let mut parts = vec![];
-        loop {
-            match self.peek()?.kind {
-                // Single import or module
-                TokenKind::Identifier(_) => {
-                    let ident = self.identifier()?;
-                    let part = ImportPart::Module(ident);
-                    parts.push(part)
+/// Make sure the library's index can be written: no module can be written
+/// where the index goes, and no two modules can export the same name, since
+/// the index would re-export both.
+fn check_library_index(
+    modules: &[(PathBuf, Arc<CompiledModule>)],
+    files: &[LibraryFile],
+) -> Result<()> {
+    use diagnostics::error::{duplicate_library_export, library_index_conflict};
+    let mut diagnostics = vec![];
+    let mut exported_by: HashMap<&str, &str> = HashMap::new();
+    for ((_, module), file) in modules.iter().zip(files) {
+        if file.filename == "index.js" {
+            diagnostics.push(library_index_conflict(&file.module_name));
+        }
+        for name in &module.exports {
+            match exported_by.get(name.as_str()) {
+                Some(first) => {
+                    diagnostics.push(duplicate_library_export(name, first, &file.module_name))
                 }
-                // Collection of imports
-                TokenKind::LBrace => {
-                    self.skip()?;
-                    let mut collection = vec![];
-                    loop {
-                        match self.peek()?.kind {
-                            TokenKind::Identifier(_) => {
-                                let ident = self.identifier()?;
-                                collection.push(ident);
-                                self.eat(TokenKind::Comma)?;
-                            }
-                            _ => break,
-                        }
-                    }
-                    self.expect(TokenKind::RBrace)?;
-                    if self.eat(TokenKind::Dot)? {
-                        let next_token = self.next()?;
-                        use diagnostics::error::dot_after_import_list;
-                        return dot_after_import_list(self.span.merge(next_token.span));
-                    }
-                    let part = ImportPart::Collection(collection);
-                    parts.push(part);
-                    // arly here as collections must occur at the end
-                    // of an import path.
-                    break;
+                None => {
+                    exported_by.insert(name, &file.module_name);
                 }
-                _ => break,
-            }
-            if !self.eat(TokenKind::Dot)? {
-                break;
             }
         }
-        debug!("import parts {:#?}", parts);
-        // Add the imported values to the local scope
-        match parts.last().unwrap() {
-            ImportPart::Module(ident) => {
-                let binding = Binding::Import(ident.span);
-                self.scope_map.define(ident.symbol, binding);
-            }
-            ImportPart::Collection(idents) => {
-                for ident in idents {
-                    let binding = Binding::Import(ident.span);
-                    self.scope_map.define(ident.symbol, binding);
+    }
+    if diagnostics.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::from_diagnostics(diagnostics))
+    }
+}
+
+/// A module of a library, rendered and ready to be written.
+struct LibraryFile {
+    module_name: String,
+    /// Where it's written, relative to the library.
+    filename: String,
+    contents: String,
+}
+
+/// Name each file of a library after a hash of its code, and of the code of
+/// every module it imports directly or through others, then point the
+/// imports between them at the new names. A file's name changes whenever
+/// anything it loads does, even for modules that import each other.
+fn hash_library_filenames(modules: &[(PathBuf, Arc<CompiledModule>)], files: &mut [LibraryFile]) {
+    // How the module at `from` imports `to` once they're compiled
+    let import_of = |from: usize, to: usize| output_path(&modules[from].0, &modules[to].0);
+    let imports: Vec<Vec<usize>> = (0..files.len())
+        .map(|from| {
+            (0..files.len())
+                .filter(|to| {
+                    let import = format!(" from '{}';", import_of(from, *to));
+                    *to != from && files[from].contents.contains(&import)
+                })
+                .collect()
+        })
+        .collect();
+    let hashes: Vec<u64> = (0..files.len())
+        .map(|start| {
+            let mut loaded = BTreeSet::new();
+            let mut stack = vec![start];
+            while let Some(module) = stack.pop() {
+                if loaded.insert(module) {
+                    stack.extend(&imports[module]);
                 }
             }
-        };
-        Ok(Import::new(parts))
+            let hashes: Vec<String> = loaded
+                .into_iter()
+                .map(|module| format!("{:016x}", output_hash(&files[module].contents)))
+                .collect();
+            content_hash(&hashes.join(""))
+        })
+        .collect();
+    for from in 0..files.len() {
+        for to in &imports[from] {
+            let import = import_of(from, *to);
+            let hashed = format!(
+                "{}.{}.js",
+                import.trim_end_matches(".js"),
+                short_hash(hashes[*to])
+            );
+            files[from].contents = files[from].contents.replace(
+                &format!(" from '{}';", import),
+                &format!(" from '{}';", hashed),
+            );
+        }
+    }
+    for (file, hash) in files.iter_mut().zip(&hashes) {
+        file.filename = format!("{}.{}.js", file.module_name, short_hash(*hash));
     }
+}
 
-    /// Parse an identifier
-    fn identifier(&mut self) -> Result<Identifier> {
-        let token = self.next()?;
-        match token.kind {
-            TokenKind::Identifier(symbol) => Ok(Identifier::new(symbol, token.span)),
-            _ => {
-                use diagnostics::error::expected_identifier;
-                expected_identifier(token.span, token.kind)
-            }
+/// Where compiled output is written, relative to the directory the compiler
+/// is run from.
+pub const OUTPUT_PATH: &str = "fixtures/output/app.compiled.js";
+
+/// Where libraries are written, relative to the directory the compiler is
+/// run from.
+pub const LIBRARY_OUTPUT_PATH: &str = "fixtures/output/lib";
+
+/// Where the manifest of every file the compiler has written is kept, for
+/// `ws clean`, relative to the directory the compiler is run from.
+pub const MANIFEST_PATH: &str = "fixtures/output/.ws-manifest";
+
+/// Parse `source` as a module and throw the result away. This is the entry
+/// point for fuzzing the parser, which should return an error for any input
+/// it can't parse instead of panicking.
+pub fn parse_source_for_fuzzing(source: &str) -> Result<()> {
+    // Free the symbols each input interns, so fuzzing doesn't run out of memory.
+    let symbols = SymbolScope::new(source);
+    let result = {
+        let _symbols = symbols.enter();
+        let mut arena = AstArena::default();
+        ParserImpl::new(source, &mut arena)
+            .parse_module()
+            .map(|_| ())
+    };
+    symbols.release();
+    result
+}
+
+/// How deeply expressions, blocks and types can be nested. Deeper
+/// code is reported instead of overflowing the stack.
+const MAX_NESTING_DEPTH: usize = 128;
+
+/// How deeply templates can be nested in each other. They're parsed without
+/// recursing, but the rest of the compiler walks them recursively.
+const MAX_TEMPLATE_DEPTH: usize = 64;
+
+/// An element whose children are being parsed, before its close tag.
+struct OpenElement {
+    open_tag: TemplateOpenTag,
+    children: Vec<TemplateChild>,
+}
+
+pub struct ParserImpl<'source, 'ctx> {
+    lexer: TokenReader<'source>,
+    ctx: &'ctx mut AstArena,
+    /// The span of the last token consumed, which is empty until one is.
+    /// Errors about the next token should use its own span from `peek`.
+    span: Span,
+    scope_map: ScopeMap<Symbol, Binding>,
+    /// Whether we're directly in a component body, and not a function
+    /// declared inside of it. Contexts, state and templates can only be
+    /// used here.
+    in_component_body: bool,
+    /// The top-level function we're in, if we're in one. Templates in it are
+    /// reported with a suggestion to make it a component.
+    top_level_function: Option<Identifier>,
+    /// Whether we're directly in an async function, where `await` can be used.
+    in_async_function: bool,
+    /// How many nested expressions, blocks and types we're in.
+    depth: usize,
+    /// How many nested templates we're in.
+    template_depth: usize,
+    /// Errors in templates that parsing recovered from. They're reported
+    /// when the module is done parsing.
+    errors: DiagnosticBag,
+    /// The effects defined so far. Effects are only defined at the top
+    /// level, so they don't need a scope.
+    effects: HashMap<Symbol, EffectId>,
+    /// The type aliases defined so far. Like effects, they're only defined
+    /// at the top level, and have to be defined before they're used.
+    type_aliases: HashMap<Symbol, TypeAliasId>,
+    /// The type alias we're parsing the type of, which can't refer to
+    /// itself. Aliases can only refer to ones defined before them, so that's
+    /// the only way one could be recursive.
+    defining_alias: Option<Identifier>,
+    /// The enums defined so far, which can be used as types once they're
+    /// defined.
+    enums: HashMap<Symbol, EnumId>,
+    /// Where the module will run, which decides the globals it can use.
+    target: Target,
+    /// The experimental syntax the module can use.
+    features: Features,
+    /// The globals referenced so far, so each one is only allocated once.
+    globals: HashMap<Symbol, GlobalId>,
+}
+
+impl<'source, 'ctx> ParserImpl<'source, 'ctx> {
+    /// Parse a whole module, which can enable features itself with
+    /// `#feature(...)` lines. Unknown features are reported by
+    /// `module_features`.
+    pub fn new(source: &'source str, ctx: &'ctx mut AstArena) -> Self {
+        let mut parser = Self::from_reader(TokenStream::lex(source).into_reader(), ctx);
+        parser.features.enable_directives(source);
+        parser
+    }
+
+    /// Parse tokens that were already lexed. Spans are the index of each
+    /// token, which `TokenStream::source_range` maps back to the source.
+    pub fn with_tokens(tokens: &'source TokenStream, ctx: &'ctx mut AstArena) -> Self {
+        Self::from_reader(tokens.indexed_reader(), ctx)
+    }
+
+    fn from_reader(lexer: TokenReader<'source>, ctx: &'ctx mut AstArena) -> Self {
+        let start_span = Span::empty(0);
+        Self {
+            lexer,
+            ctx,
+            span: start_span,
+            scope_map: ScopeMap::default(),
+            in_component_body: false,
+            top_level_function: None,
+            in_async_function: false,
+            depth: 0,
+            template_depth: 0,
+            errors: DiagnosticBag::new(),
+            effects: HashMap::new(),
+            type_aliases: HashMap::new(),
+            defining_alias: None,
+            enums: HashMap::new(),
+            target: Target::default(),
+            features: Features::default(),
+            globals: HashMap::new(),
+        }
+    }
+
+    pub fn with_target(self, target: Target) -> Self {
+        Self { target, ..self }
+    }
+
+    /// Enable `features` along with any the module enables itself.
+    pub fn with_features(mut self, features: &Features) -> Self {
+        self.features.extend(features);
+        self
+    }
+
+    /// Report experimental syntax at `span` unless its feature is enabled.
+    fn require_feature(&self, feature: Feature, span: Span) -> Result<()> {
+        if self.features.is_enabled(feature) {
+            return Ok(());
         }
+        use diagnostics::error::feature_not_enabled;
+        feature_not_enabled(span, feature.description(), feature.name())
     }
 
-    /// Parse a single definition in a module
-    fn definition(&mut self, is_public: bool) -> Result<Definition> {
-        // Public exports
-        if self.eat(TokenKind::Pub)? {
-            return self.definition(true);
+    pub fn parse_module(&mut self) -> Result<ModuleId> {
+        let module = self.parse_definitions();
+        // Report the errors that were recovered from, along with the one
+        // that stopped parsing if there was one.
+        std::mem::take(&mut self.errors).finish(module)
+    }
+
+    fn parse_definitions(&mut self) -> Result<ModuleId> {
+        let mut definitions = vec![];
+
+        while self.peek()?.kind != TokenKind::EOF {
+            let definition = self.parse_definition()?;
+            definitions.push(definition);
+            // Definitions don't need one, but it's harmless
+            self.eat(TokenKind::Semicolon)?;
         }
 
+        let module = Module { definitions };
+        let module_id = self.ctx.modules.alloc(module);
+        Ok(module_id)
+    }
+
+    fn parse_definition(&mut self) -> Result<Definition> {
+        let public = self.eat(TokenKind::Pub)?;
+        // let is_public = self.eat(TokenKind::Pub)?;
         let kind = match self.peek()?.kind {
-            TokenKind::Async => {
-                self.eat(TokenKind::Async)?;
-                match self.peek()?.kind {
-                    TokenKind::Fn => {
-                        let function = self.parse_function(true)?;
-                        DefinitionKind::Function(function)
-                        // ...
-                    }
-                    TokenKind::Component => {
-                        let component = self.component(true)?;
-                        DefinitionKind::Component(component)
-                    }
-                    _ => {
-                        let token = self.next()?;
-                        use diagnostics::error::unexpected_token_error;
-                        return unexpected_token_error(
-                            self.span,
-                            self.prev_span,
-                            TokenKind::Fn,
-                            token.kind,
-                        );
-                    }
-                }
+            TokenKind::Fn | TokenKind::Async => {
+                let function_id = self.parse_function()?;
+                DefinitionKind::Function(function_id)
             }
-            TokenKind::Fn => {
-                let function = self.parse_function(false)?;
-                DefinitionKind::Function(function)
+            TokenKind::Const => {
+                let const_id = self.parse_const()?;
+                DefinitionKind::Const(const_id)
             }
             TokenKind::Component => {
-                let component = self.component(false)?;
-                DefinitionKind::Component(component)
+                let component_id = self.parse_component()?;
+                DefinitionKind::Component(component_id)
             }
-            TokenKind::Enum => {
-                let enum_ = self.enum_()?;
-                DefinitionKind::Enum(enum_)
+            TokenKind::Context => {
+                let context_id = self.parse_context()?;
+                DefinitionKind::Context(context_id)
             }
-            TokenKind::Struct => {
-                let struct_ = self.struct_()?;
-                DefinitionKind::Struct(struct_)
+            TokenKind::Effect => {
+                let effect_id = self.parse_effect()?;
+                DefinitionKind::Effect(effect_id)
             }
-            TokenKind::Const => {
-                let const_ = self.const_()?;
-                DefinitionKind::Const(const_)
+            TokenKind::Import => {
+                let import_id = self.parse_import()?;
+                DefinitionKind::Import(import_id)
             }
-            TokenKind::Type => {
-                let type_def = self.type_def()?;
-                DefinitionKind::Type(type_def)
+            TokenKind::Enum => {
+                let enum_id = self.parse_enum()?;
+                DefinitionKind::Enum(enum_id)
             }
-            TokenKind::Effect => {
-                let effect = self.effect_def()?;
-                DefinitionKind::Effect(effect)
+            TokenKind::Type => {
+                let alias_id = self.parse_type_alias()?;
+                DefinitionKind::TypeAlias(alias_id)
             }
             _ => {
-                use diagnostics::error::unexpected_token_error_with_multiple_options;
+                let prev_span = self.last_span();
                 let token = self.next()?;
-                return unexpected_token_error_with_multiple_options(
-                    token.span,
-                    vec![
-                        TokenKind::Fn,
-                        TokenKind::Component,
-                        TokenKind::Enum,
-                        TokenKind::Struct,
-                    ],
-                    token.kind,
-                )
-                .map_err(|err| err);
+                use diagnostics::error::unexpected_token_error;
+                return unexpected_token_error(token.span, prev_span, TokenKind::Fn, token.kind);
             }
         };
-        Ok(Definition { is_public, kind })
+        let definition = Definition { public, kind };
+        Ok(definition)
     }
 
-    /// Parse all definitions in a module
-    fn definitions(&mut self) -> Result<Vec<Definition>> {
-        let mut definitions = vec![];
-        loop {
-            if let TokenKind::EOF = self.peek()?.kind {
+    fn parse_const(&mut self) -> Result<ConstId> {
+        self.expect(TokenKind::Const)?;
+        let name = self.identifier()?;
+        let symbol = name.symbol;
+        self.expect(TokenKind::Equals)?;
+        let value = self.parse_expression(Precedence::None)?;
+        let const_ = Const { name, value };
+        let const_ = self.ctx.consts.alloc(const_);
+        self.scope_map.define(symbol, Binding::Const(const_));
+        Ok(const_)
+    }
+
+    fn parse_context(&mut self) -> Result<ContextId> {
+        self.expect(TokenKind::Context)?;
+        let name = self.identifier()?;
+        let symbol = name.symbol;
+        self.expect(TokenKind::Equals)?;
+        let value = self.parse_expression(Precedence::None)?;
+        let context = Context { name, value };
+        let context_id = self.ctx.contexts.alloc(context);
+        self.scope_map.define(symbol, Binding::Context(context_id));
+        Ok(context_id)
+    }
+
+    /// Parse an import like `import utils.{helper as h, other}`, or
+    /// `import long.module.name as m` to import the whole module.
+    fn parse_import(&mut self) -> Result<ImportId> {
+        self.expect(TokenKind::Import)?;
+        let mut path = vec![self.identifier()?];
+        let mut names = vec![];
+        while self.eat(TokenKind::Dot)? {
+            if self.eat(TokenKind::LBrace)? {
+                // A list of names has to be the end of the path
+                loop {
+                    if self.peek()?.kind == TokenKind::RBrace {
+                        break;
+                    }
+                    let name = self.identifier()?;
+                    names.push((Some(name), self.parse_import_alias()?));
+                    if !self.eat(TokenKind::Comma)? {
+                        break;
+                    }
+                }
+                self.expect(TokenKind::RBrace)?;
                 break;
-            } else {
-                // TODO support checking for visibility modifiers here
-                let definition = self.definition(false)?;
-                definitions.push(definition)
             }
+            path.push(self.identifier()?);
+        }
+        if names.is_empty() {
+            names.push((None, self.parse_import_alias()?));
         }
-        Ok(definitions)
+        let module = *path.last().unwrap();
+        let mut specifiers = vec![];
+        for (name, alias) in names {
+            let local = alias.or(name).unwrap_or(module);
+            let specifier = ImportSpecifier { name, local };
+            let specifier_id = self.ctx.import_specifiers.alloc(specifier);
+            self.scope_map
+                .define(local.symbol, Binding::Import(specifier_id));
+            specifiers.push(specifier_id);
+        }
+        let import = Import { path, specifiers };
+        Ok(self.ctx.imports.alloc(import))
     }
 
-    fn type_parameters(&mut self) -> Result<Option<TypeParameters>> {
-        use TokenKind::{Comma, GreaterThan, Identifier, LessThan};
-        if self.eat(LessThan)? {
-            let mut identifiers = vec![];
-            let lo = self.span;
-            loop {
-                match self.peek()?.kind {
-                    Identifier(_) => {
-                        let identifier = self.identifier()?;
-                        let smybol = identifier.symbol;
-                        let type_param = TypeParameter { name: identifier };
-                        let type_param = Arc::new(type_param);
-                        self.type_scope_map
-                            .define(smybol, TypeBinding::TypeParameter(type_param.clone()));
-                        identifiers.push(type_param);
-                        self.eat(Comma)?;
+    /// Parse the alias in `as alias`, if there is one.
+    fn parse_import_alias(&mut self) -> Result<Option<Identifier>> {
+        if self.eat(TokenKind::As)? {
+            Ok(Some(self.identifier()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn parse_effect(&mut self) -> Result<EffectId> {
+        self.expect(TokenKind::Effect)?;
+        self.require_feature(Feature::Effects, self.span)?;
+        let name = self.identifier()?;
+        let effect_id = self.ctx.effects.alloc(Effect { name });
+        self.effects.insert(name.symbol, effect_id);
+        Ok(effect_id)
+    }
+
+    /// Parse a type alias, like `type Handler = (event: string) => number`.
+    fn parse_type_alias(&mut self) -> Result<TypeAliasId> {
+        self.expect(TokenKind::Type)?;
+        let name = self.identifier()?;
+        self.expect(TokenKind::Equals)?;
+        self.defining_alias = Some(name);
+        let type_ = self.parse_type();
+        self.defining_alias = None;
+        let alias = TypeAlias {
+            name,
+            type_: type_?,
+        };
+        let alias_id = self.ctx.type_aliases.alloc(alias);
+        self.type_aliases.insert(name.symbol, alias_id);
+        Ok(alias_id)
+    }
+
+    /// Parse an enum, like `enum Shape { Circle(number) Empty }`, where the
+    /// variants can also be separated by commas. Its variants
+    /// are defined in the module's scope, so they're used by their own name.
+    fn parse_enum(&mut self) -> Result<EnumId> {
+        self.expect(TokenKind::Enum)?;
+        let name = self.identifier()?;
+        let enum_id = self.ctx.enums.alloc(Enum {
+            name,
+            variants: vec![],
+        });
+        // Defined before its variants, which can hold values of the enum
+        self.enums.insert(name.symbol, enum_id);
+        self.expect(TokenKind::LBrace)?;
+        let mut variants: Vec<VariantId> = vec![];
+        while let TokenKind::Identifier(symbol) = self.peek()?.kind {
+            let name = self.identifier()?;
+            let duplicate = variants
+                .iter()
+                .map(|variant_id| self.ctx.variants[*variant_id].name)
+                .find(|variant| variant.symbol == symbol);
+            if let Some(first) = duplicate {
+                use diagnostics::error::duplicate_variant;
+                return duplicate_variant(first.span, name.span, symbol);
+            }
+            let types = if self.eat(TokenKind::LParen)? {
+                let mut types = vec![];
+                while self.peek()?.kind != TokenKind::RParen {
+                    types.push(self.parse_type()?);
+                    if !self.eat(TokenKind::Comma)? {
+                        break;
                     }
-                    _ => break,
                 }
+                self.expect(TokenKind::RParen)?;
+                Some(types)
+            } else {
+                None
+            };
+            let variant = Variant {
+                name,
+                enum_id,
+                types,
+            };
+            let variant_id = self.ctx.variants.alloc(variant);
+            self.scope_map.define(symbol, Binding::Variant(variant_id));
+            variants.push(variant_id);
+            // Variants can be separated by commas too
+            self.eat(TokenKind::Comma)?;
+        }
+        // A keyword would otherwise be reported as the enum not being closed
+        if self.peek()?.kind.is_keyword() {
+            self.identifier()?;
+        }
+        self.expect(TokenKind::RBrace)?;
+        self.ctx.enums[enum_id].variants = variants;
+        Ok(enum_id)
+    }
+
+    /// Parse what a function returns, like `: number + Log + Fetch`. The
+    /// effects are `None` when none are listed.
+    fn parse_return_annotation(&mut self) -> Result<(Option<Type>, Option<Vec<EffectId>>)> {
+        if !self.eat(TokenKind::Colon)? {
+            return Ok((None, None));
+        }
+        let return_type = self.parse_type()?;
+        let mut effects = vec![];
+        while self.eat(TokenKind::Plus)? {
+            let name = self.identifier()?;
+            match self.effects.get(&name.symbol) {
+                Some(effect_id) => effects.push(*effect_id),
+                None => {
+                    use diagnostics::error::unknown_effect;
+                    return unknown_effect(name.span, name.symbol);
+                }
+            }
+        }
+        let effects = if effects.is_empty() {
+            None
+        } else {
+            Some(effects)
+        };
+        Ok((Some(return_type), effects))
+    }
+
+    /// Resolve the name of a context that's being provided or used.
+    fn resolve_context(&mut self, keyword: TokenKind) -> Result<ContextId> {
+        let keyword_span = self.peek()?.span;
+        self.expect(keyword.clone())?;
+        if !self.in_component_body {
+            use diagnostics::error::context_outside_component;
+            return context_outside_component(keyword_span, keyword);
+        }
+        let name = self.identifier()?;
+        match self.scope_map.resolve(&name.symbol) {
+            Some((Binding::Context(context_id), _)) => Ok(*context_id),
+            Some((binding, _)) => {
+                use diagnostics::error::expected_context;
+                let definition = binding.identifier(self.ctx);
+                expected_context(name.span, name.symbol, definition.span)
             }
-            self.expect(GreaterThan)?;
-            let span = lo.merge(self.span);
-            if identifiers.is_empty() {
-                use diagnostics::error::empty_type_parameters;
-                return empty_type_parameters(span);
+            None => {
+                use diagnostics::error::unknown_reference_error;
+                unknown_reference_error(name.span, name.symbol, None::<Span>)
             }
-            Ok(Some(TypeParameters { identifiers, span }))
+        }
+    }
+
+    fn parse_provide(&mut self) -> Result<StatementId> {
+        let context = self.resolve_context(TokenKind::Provide)?;
+        self.expect(TokenKind::Equals)?;
+        let value = self.parse_expression(Precedence::None)?;
+        let statement = Statement::Provide { context, value };
+        Ok(self.ctx.statements.alloc(statement))
+    }
+
+    /// Parse a type, which is optional when it's followed by a `?`.
+    fn parse_type(&mut self) -> Result<Type> {
+        let type_ = self.parse_required_type()?;
+        if self.eat(TokenKind::Question)? {
+            Ok(Type::Optional(type_.into()))
         } else {
-            Ok(None)
+            Ok(type_)
         }
     }
 
-    fn type_(&mut self) -> Result<TypeExpression> {
-        // Parse function parameters for types like (a: string, b: int) => int
+    fn parse_required_type(&mut self) -> Result<Type> {
+        // Parse array types like [number]
+        if self.eat(TokenKind::LBracket)? {
+            let element = self.nested(Self::parse_type)?;
+            self.expect(TokenKind::RBracket)?;
+            return Ok(Type::Array(element.into()));
+        }
+        // Parse function types like (a: string, number) => number + Log,
+        // where naming the parameters is optional
         if self.eat(TokenKind::LParen)? {
-            let span = self.span;
             let mut parameters = vec![];
+            let mut names: Vec<Identifier> = vec![];
             loop {
                 if self.peek()?.kind == TokenKind::RParen {
                     break;
                 }
-                let type_ = self.type_()?;
-                parameters.push(type_);
-                if self.eat(TokenKind::Comma)? {
-                    continue;
+                // A name is followed by a colon, otherwise it's an alias
+                let is_named = matches!(self.peek()?.kind, TokenKind::Identifier(_))
+                    && self.lexer.peek_nth(1)?.kind == TokenKind::Colon;
+                let name = if is_named {
+                    let name = self.identifier()?;
+                    if let Some(first) = names.iter().find(|first| first.symbol == name.symbol) {
+                        use diagnostics::error::duplicate_parameter;
+                        return duplicate_parameter(first.span, name.span, name.symbol);
+                    }
+                    names.push(name);
+                    self.expect(TokenKind::Colon)?;
+                    Some(name.symbol)
+                } else {
+                    None
+                };
+                let type_ = self.nested(Self::parse_type)?;
+                parameters.push(FunctionTypeParameter { name, type_ });
+                if self.eat(TokenKind::Comma)? {
+                    continue;
                 } else {
                     break;
                 }
             }
             self.expect(TokenKind::RParen)?;
             self.expect(TokenKind::Arrow)?;
-            let return_type = self.type_()?.into();
-            let span = span.merge(self.span);
-            let kind = TypeExpressionKind::Function {
+            let return_type = self.nested(Self::parse_type)?.into();
+            // Effects after the return type belong to the innermost function type
+            let mut effects = vec![];
+            while self.eat(TokenKind::Plus)? {
+                let name = self.identifier()?;
+                if !self.effects.contains_key(&name.symbol) {
+                    use diagnostics::error::unknown_effect;
+                    return unknown_effect(name.span, name.symbol);
+                }
+                effects.push(name.symbol);
+            }
+            effects.sort();
+            effects.dedup();
+            return Ok(Type::Function {
                 parameters,
                 return_type,
-            };
-            return Ok(TypeExpression {
-                kind,
-                span,
-                type_: None,
+                effects,
             });
         }
-        let type_ = self.reference_type()?;
-        let span = type_.span;
-        // Parse a function type
-        if self.eat(TokenKind::Arrow)? {
-            let parameters = vec![type_];
-            let return_type = self.type_()?.into();
-            let kind = TypeExpressionKind::Function {
-                parameters,
-                return_type,
-            };
-            let span = self.span.merge(span);
-            let type_ = TypeExpression {
-                kind,
-                span,
-                type_: None,
-            };
-            Ok(type_)
-        } else {
-            Ok(type_)
-        }
-    }
 
-    /// Parses a type reference, like what you would see in type
-    /// annotations. It does not parse type _definitions_.
-    fn reference_type(&mut self) -> Result<TypeExpression> {
-        let name = self.identifier()?;
-        let span = self.span;
-        // How can we represent user types at this point? We don't want the types crate to depend on the AST
-        let type_ = match self.type_scope_map.resolve(&name.symbol) {
-            None => match name.symbol.to_string().as_str() {
-                "bool" => Type::Boolean,
-                "number" => Type::Number,
-                "string" => Type::String,
-                "void" => Type::Unit,
-                _ => {
-                    use diagnostics::error::unknown_type;
-                    use edit_distance::edit_distance;
-                    let symbol_str = format!("{}", name.symbol);
-                    let mut maybe_reference_span: Option<Span> = None;
-                    for scope in self.type_scope_map.scope_iter() {
-                        for (binding_symbol, (binding, _)) in &scope.bindings {
-                            let binding_str = format!("{}", binding_symbol);
-                            let distance = edit_distance(&binding_str, &symbol_str);
-                            if distance <= 3 {
-                                maybe_reference_span = Some(binding.span());
-                            }
+        match self.peek()?.kind {
+            TokenKind::Boolean => {
+                self.expect(TokenKind::Boolean)?;
+                return Ok(Type::Boolean);
+            }
+            TokenKind::NumberType => {
+                self.expect(TokenKind::NumberType)?;
+                return Ok(Type::Number);
+            }
+            TokenKind::StringType => {
+                self.expect(TokenKind::StringType)?;
+                return Ok(Type::String);
+            }
+            TokenKind::Identifier(symbol) => {
+                let name = self.identifier()?;
+                // These are only names in type position, so they aren't keywords
+                match &*symbol.to_string() {
+                    "void" => return Ok(Type::Void),
+                    "never" => return Ok(Type::Never),
+                    name if !self.type_aliases.contains_key(&symbol) => {
+                        if let Some(enum_id) = self.enums.get(&symbol) {
+                            return Ok(Type::Enum {
+                                name: symbol,
+                                enum_id: *enum_id,
+                            });
+                        }
+                        if let Some(event) = EventType::from_name(name) {
+                            return Ok(Type::Event(event));
                         }
                     }
-                    return unknown_type(span, &name.symbol, maybe_reference_span);
+                    _ => {}
                 }
-            },
-            Some((binding, _)) => match binding {
-                TypeBinding::Struct(struct_) => Type::Struct(struct_.clone()),
-                TypeBinding::Effect(effect) => {
-                    if self.allow_effect_reference {
-                        Type::Effect(effect.clone())
-                    } else {
-                        use diagnostics::error::invalid_effect_reference;
-                        return invalid_effect_reference(span, effect.name.symbol);
+                match self.defining_alias {
+                    Some(alias) if alias.symbol == symbol => {
+                        use diagnostics::error::recursive_type_alias;
+                        return recursive_type_alias(name.span, symbol, alias.span);
                     }
+                    _ => {}
                 }
-                TypeBinding::TypeParameter(type_param) => Type::Parameter(type_param.clone()),
-            },
-        };
-        let arguments = if self.eat(TokenKind::LessThan)? {
-            let mut arguments = vec![];
-            loop {
-                if let TokenKind::Identifier(_) = self.peek()?.kind {
-                    let type_ = self.type_()?;
-                    arguments.push(type_);
-                    self.eat(TokenKind::Comma)?;
-                } else {
-                    break;
+                match self.type_aliases.get(&symbol) {
+                    Some(alias_id) => Ok(Type::Alias {
+                        name: symbol,
+                        type_: self.ctx.type_aliases[*alias_id].type_.clone().into(),
+                    }),
+                    None => {
+                        use diagnostics::error::unknown_type;
+                        use edit_distance::edit_distance;
+                        let name_str = symbol.to_string();
+                        let similar = self
+                            .type_aliases
+                            .values()
+                            .map(|alias_id| self.ctx.type_aliases[*alias_id].name)
+                            .find(|alias| edit_distance(&alias.symbol.to_string(), &name_str) <= 2);
+                        unknown_type(name.span, symbol, similar.map(|alias| alias.span))
+                    }
                 }
             }
-            self.expect(TokenKind::GreaterThan)?;
-            Some(arguments)
-        } else {
-            None
-        };
-        let span = self.span.merge(span);
-        let kind = TypeExpressionKind::Reference { name, arguments };
-        let type_ = TypeExpression {
-            kind,
-            span,
-            type_: Some(type_),
-        };
-        Ok(type_)
+            _ => {
+                use diagnostics::error::expected_type;
+                let token = self.next()?;
+                expected_type(token.span, token.kind)
+            }
+        }
     }
 
-    /// Parses a function or component parameter.
     fn parameter(&mut self) -> Result<Parameter> {
         let name = self.identifier()?;
         let type_ = if self.eat(TokenKind::Colon)? {
-            Some(self.type_()?)
+            Some(self.parse_type()?)
         } else {
             None
         };
         Ok(Parameter { name, type_ })
     }
 
-    fn parameters(&mut self) -> Result<Option<Vec<Arc<Parameter>>>> {
+    fn parse_parameters(&mut self) -> Result<Option<Vec<ParameterId>>> {
         use TokenKind::{Comma, LParen, RParen};
         if self.eat(LParen)? {
             let mut parameters = vec![];
             loop {
                 if let TokenKind::Identifier(symbol) = self.peek()?.kind {
                     let parameter = self.parameter()?;
-                    let parameter = Arc::new(parameter);
+                    let duplicate = parameters
+                        .iter()
+                        .map(|parameter_id| self.ctx.parameters[*parameter_id].name)
+                        .find(|first: &Identifier| first.symbol == symbol);
+                    if let Some(first) = duplicate {
+                        use diagnostics::error::duplicate_parameter;
+                        return duplicate_parameter(first.span, parameter.name.span, symbol);
+                    }
+                    let parameter_id = self.ctx.parameters.alloc(parameter);
                     self.scope_map
-                        .define(symbol, Binding::Parameter(parameter.clone()));
-                    parameters.push(parameter);
+                        .define(symbol, Binding::Parameter(parameter_id));
+                    parameters.push(parameter_id);
                     if self.eat(Comma)? {
                         // Another parameter, continue
                         continue;
@@ -511,6 +1209,10 @@ impl<'s> ParserImpl<'s> {
                     break;
                 }
             }
+            // A keyword would otherwise be reported as the list not being closed
+            if self.peek()?.kind.is_keyword() {
+                self.identifier()?;
+            }
             self.expect(RParen)?;
             if parameters.is_empty() {
                 Ok(None)
@@ -522,226 +1224,507 @@ impl<'s> ParserImpl<'s> {
         }
     }
 
-    fn parse_expression_from_identifier(
-        &mut self,
-        symbol: Symbol,
-        span: Span,
-    ) -> Result<Expression> {
-        match self.scope_map.resolve(&symbol) {
-            Some((binding, _unique_reference)) => {
-                if let Binding::Function(function_id) = binding {
-                    self.reference_tracker.remove(&function_id);
+    fn parse_function(&mut self) -> Result<FunctionId> {
+        debug!("parse_function");
+        let is_async = self.eat(TokenKind::Async)?;
+        if is_async && self.peek()?.kind == TokenKind::Component {
+            let span = self.span.merge(self.peek()?.span);
+            self.require_feature(Feature::AsyncComponents, span)?;
+            use diagnostics::error::unsupported_syntax;
+            return unsupported_syntax(span, "Async components");
+        }
+        self.expect(TokenKind::Fn)?;
+        let name = self.identifier()?;
+        let symbol = name.symbol;
+        let parameters = self.parse_parameters()?;
+        let (return_type, effects) = self.parse_return_annotation()?;
+        let function = Function {
+            body: None,
+            name,
+            parameters,
+            return_type,
+            effects,
+            is_async,
+        };
+        let function_id = self.ctx.alloc_function(function);
+        self.scope_map
+            .define(symbol, Binding::Function(function_id));
+        // Functions in blocks or expressions are always nested in something
+        let top_level_function = self.top_level_function;
+        if self.depth == 0 {
+            self.top_level_function = Some(name);
+        }
+        let in_component_body = std::mem::replace(&mut self.in_component_body, false);
+        let in_async_function = std::mem::replace(&mut self.in_async_function, is_async);
+        let body = self.parse_block()?;
+        self.in_component_body = in_component_body;
+        self.in_async_function = in_async_function;
+        self.top_level_function = top_level_function;
+        let function = self.ctx.functions.get_mut(function_id).unwrap();
+        let mut function = function.borrow_mut();
+        function.body = Some(body);
+
+        Ok(function_id)
+    }
+
+    fn parse_component(&mut self) -> Result<ComponentId> {
+        self.expect(TokenKind::Component)?;
+        let name = self.identifier()?;
+        let symbol = name.symbol;
+        let parameters = self.parse_parameters()?;
+        let component = Component {
+            body: None,
+            name,
+            parameters,
+        };
+        let component_id = self.ctx.alloc_component(component);
+        self.scope_map
+            .define(symbol, Binding::Component(component_id));
+        self.in_component_body = true;
+        let in_async_function = std::mem::replace(&mut self.in_async_function, false);
+        let body = self.parse_block()?;
+        self.in_async_function = in_async_function;
+        self.in_component_body = false;
+        // The template a component ends with is what it renders, just like
+        // if it were returned
+        if let Some(&statement_id) = self.ctx.blocks[body].statements.last() {
+            if let Statement::Expression(expression_id) = self.ctx.statements[statement_id] {
+                if let Expression::Template(_) = *self.ctx.expressions[expression_id].borrow() {
+                    self.ctx.statements[statement_id] = Statement::Return(expression_id);
                 }
-                let expr = Expression {
-                    kind: ExpressionKind::Reference(binding.clone()),
-                    span: span,
-                    type_: None,
-                };
-                self.infix_expression(expr)
             }
-            None => {
-                // TODO move edit distance check into scope_map
-                use edit_distance::edit_distance;
-                let symbol_str = format!("{}", symbol);
-                let mut maybe_reference_span: Option<Span> = None;
-                let max_edit_distance = 2;
-                for scope in self.scope_map.scope_iter() {
-                    for (binding_symbol, (binding, _)) in &scope.bindings {
-                        let binding_str = format!("{}", binding_symbol);
-                        let distance = edit_distance(&binding_str, &symbol_str);
-                        if distance <= max_edit_distance {
-                            maybe_reference_span = Some(binding.span(&self.ast_arena));
-                        }
-                    }
+        }
+        let component = self.ctx.components.get_mut(component_id).unwrap();
+        let mut component = component.borrow_mut();
+        component.body = Some(body);
+        Ok(component_id)
+    }
+
+    fn parse_block(&mut self) -> Result<BlockId> {
+        self.nested(|parser| {
+            parser.expect(TokenKind::LBrace)?;
+            let span = parser.span;
+            let mut statements = vec![];
+            parser.scope_map.extend();
+            while !parser.peek()?.follows_statement() {
+                let statement = parser.parse_statement()?;
+                statements.push(statement);
+                // Statements end at the end of their line, unless the block
+                // ends there too. A `;` can end them anywhere.
+                if parser.eat(TokenKind::Semicolon)? {
+                    continue;
+                }
+                if !parser.peek()?.follows_statement() && !parser.at_line_start()? {
+                    use diagnostics::error::missing_line_break;
+                    let span = parser.peek()?.span;
+                    return missing_line_break(span, parser.span);
                 }
-                return diagnostics::error::unknown_reference_error(
-                    span,
-                    symbol,
-                    maybe_reference_span,
-                );
             }
-        }
+
+            parser.expect(TokenKind::RBrace)?;
+            parser.scope_map.pop();
+            let block = Block { statements };
+            let block_id = parser.ctx.blocks.alloc(block);
+            parser
+                .ctx
+                .block_spans
+                .insert(block_id, span.merge(parser.span));
+            Ok(block_id)
+        })
     }
 
-    fn prefix_expression(&mut self) -> Result<Expression> {
+    fn parse_statement(&mut self) -> Result<StatementId> {
         match self.peek()?.kind {
-            TokenKind::Number(symbol) => {
-                let token = self.next()?;
-                Ok(Expression {
-                    kind: ExpressionKind::Number {
-                        raw: symbol,
-                        value: None,
-                    },
-                    span: token.span,
-                    type_: Some(Type::Number),
-                })
-            }
-            TokenKind::String(symbol) => {
-                let token = self.next()?;
-                Ok(Expression {
-                    kind: ExpressionKind::String { raw: symbol },
-                    span: token.span,
-                    type_: Some(Type::String),
-                })
-            }
-            TokenKind::True | TokenKind::False => {
-                let token = self.next()?;
-                Ok(Expression {
-                    kind: ExpressionKind::Boolean(token.kind == TokenKind::True),
-                    span: token.span,
-                    type_: Some(Type::Boolean),
-                })
+            TokenKind::Let => self.parse_let(),
+            TokenKind::State => self.parse_state(),
+            TokenKind::Return => self.parse_return(),
+            TokenKind::If => self.parse_if(),
+            TokenKind::While => self.parse_while(),
+            TokenKind::Provide => self.parse_provide(),
+            TokenKind::Underscore => self.parse_discard(),
+            TokenKind::Identifier(symbol) => self.parse_statement_for_identifier(),
+            _ => {
+                let expression = self.parse_expression(Precedence::None)?;
+                Ok(self.ctx.statements.alloc(Statement::Expression(expression)))
             }
-            TokenKind::Identifier(symbol) => {
-                let token = self.next()?;
-                self.parse_expression_from_identifier(symbol, token.span)
+        }
+    }
+
+    /// Parse a value that's deliberately unused, like `_ = save()`.
+    fn parse_discard(&mut self) -> Result<StatementId> {
+        self.expect(TokenKind::Underscore)?;
+        self.expect(TokenKind::Equals)?;
+        let value = self.parse_expression(Precedence::None)?;
+        Ok(self.ctx.statements.alloc(Statement::Discard(value)))
+    }
+
+    fn parse_statement_for_identifier(&mut self) -> Result<StatementId> {
+        let ident = self.identifier()?;
+        if self.eat(TokenKind::Equals)? {
+            let binding = self.resolve_assignment_target(ident)?;
+            let expression = self.parse_expression(Precedence::None)?;
+            let statement = Statement::Assignment {
+                name: binding,
+                value: expression,
+            };
+            Ok(self.ctx.statements.alloc(statement))
+        } else if let Some(op) = self.compound_assignment_op()? {
+            self.next()?;
+            let binding = self.resolve_assignment_target(ident)?;
+            // `count += 1` is desugared to `count = count + 1`
+            let right = self.parse_expression(Precedence::None)?;
+            let left = self
+                .ctx
+                .alloc_spanned_expression(Expression::Reference(binding), ident.span);
+            let span = ident.span.merge(self.span_of(right));
+            let value = self
+                .ctx
+                .alloc_spanned_expression(Expression::Binary { left, op, right }, span);
+            let statement = Statement::Assignment {
+                name: binding,
+                value,
+            };
+            Ok(self.ctx.statements.alloc(statement))
+        } else {
+            // We've already consumed the identifier, so continue parsing the
+            // expression it starts, like the value of a block `{ x }`.
+            let expression = self.parse_expression_from_identifier(ident.symbol, ident.span)?;
+            let expression = self.parse_infix_expressions(expression, Precedence::None)?;
+            Ok(self.ctx.statements.alloc(Statement::Expression(expression)))
+        }
+    }
+
+    /// Resolve the binding being assigned to, which must be mutable.
+    fn resolve_assignment_target(&mut self, ident: Identifier) -> Result<Binding> {
+        let binding = match self.scope_map.resolve(&ident.symbol) {
+            Some((binding, _)) => *binding,
+            None => {
+                use diagnostics::error::unknown_reference_error;
+                return unknown_reference_error(ident.span, ident.symbol, None::<Span>);
             }
-            TokenKind::LBracket => self.array_expression(),
-            TokenKind::LBrace => self.block_expression(),
-            TokenKind::Match => self.match_expression(),
-            TokenKind::LParen => {
-                self.expect(TokenKind::LParen)?;
-                let expression = self.expression(Precedence::None)?;
-                self.expect(TokenKind::RParen)?;
-                Ok(expression)
+        };
+        if !binding.is_mutable(self.ctx) {
+            use diagnostics::error::assignment_to_immutable;
+            let definition = binding.identifier(self.ctx);
+            return assignment_to_immutable(ident.span, ident.symbol, definition.span);
+        }
+        Ok(binding)
+    }
+
+    /// The binary operator for the next token, if it's a compound assignment like `+=`.
+    fn compound_assignment_op(&mut self) -> Result<Option<BinOp>> {
+        let op = match self.peek()?.kind {
+            TokenKind::PlusEquals => Some(BinOp::Add),
+            TokenKind::MinusEquals => Some(BinOp::Sub),
+            TokenKind::StarEquals => Some(BinOp::Mul),
+            TokenKind::SlashEquals => Some(BinOp::Div),
+            _ => None,
+        };
+        Ok(op)
+    }
+
+    fn parse_while(&mut self) -> Result<StatementId> {
+        self.expect(TokenKind::While)?;
+        let condition = self.parse_expression(Precedence::None)?;
+        let body = self.parse_block()?;
+        let statement_id = self
+            .ctx
+            .statements
+            .alloc(Statement::While { condition, body });
+        Ok(statement_id)
+    }
+
+    fn parse_if(&mut self) -> Result<StatementId> {
+        let if_ = self.parse_if_impl()?;
+        let statement = Statement::If(if_);
+        let statement_id = self.ctx.statements.alloc(statement);
+        Ok(statement_id)
+    }
+
+    fn parse_if_impl(&mut self) -> Result<If> {
+        self.expect(TokenKind::If)?;
+        let condition = self.parse_expression(Precedence::None)?;
+        let body = self.parse_block()?;
+        let alternate = if self.eat(TokenKind::Else)? {
+            if TokenKind::If == self.peek()?.kind {
+                let if_ = self.nested(Self::parse_if_impl)?;
+                let alternate = Else::If(if_);
+                Some(alternate.into())
+            } else {
+                let block = self.parse_block()?;
+                let alternate = Else::Block(block);
+                Some(alternate.into())
             }
-            TokenKind::Await => {
-                self.expect(TokenKind::Await)?;
-                if !self.is_async_context {
-                    use diagnostics::error::invalid_await;
-                    return invalid_await(self.span);
+        } else {
+            None
+        };
+        let if_ = If {
+            condition,
+            body,
+            alternate,
+        };
+        Ok(if_)
+    }
+
+    fn parse_if_expression(&mut self) -> Result<ExpressionId> {
+        self.expect(TokenKind::If)?;
+        let span = self.span;
+        let condition = self.parse_expression(Precedence::None)?;
+        let then_branch = self.parse_block()?;
+        if !self.eat(TokenKind::Else)? {
+            use diagnostics::error::missing_else_branch;
+            return missing_else_branch(span.merge(self.span));
+        }
+        let else_branch = if TokenKind::If == self.peek()?.kind {
+            // An `else if` is a nested if-expression as the sole value of the else block
+            let if_ = self.nested(Self::parse_if_expression)?;
+            let statement = self.ctx.statements.alloc(Statement::Expression(if_));
+            self.ctx.blocks.alloc(Block {
+                statements: vec![statement],
+            })
+        } else {
+            self.parse_block()?
+        };
+        self.check_branch_types(then_branch, else_branch)?;
+        let span = span.merge(self.span);
+        let expression = Expression::If {
+            condition,
+            then_branch,
+            else_branch: Some(else_branch),
+        };
+        Ok(self.ctx.alloc_spanned_expression(expression, span))
+    }
+
+    /// Parse an array literal, like `[1, 2, 3]`. Every element has to have
+    /// the same type.
+    fn parse_array(&mut self) -> Result<ExpressionId> {
+        self.expect(TokenKind::LBracket)?;
+        let span = self.span;
+        let mut elements: Vec<ExpressionId> = vec![];
+        // The first element with a known type, which the rest have to match
+        let mut first: Option<(ExpressionId, Type)> = None;
+        while self.peek()?.kind != TokenKind::RBracket {
+            let element = self.nested(|parser| parser.parse_expression(Precedence::None))?;
+            if let Some(type_) = self.ctx.expression_type(element) {
+                match &first {
+                    Some((first, first_type)) if *first_type != type_ => {
+                        use diagnostics::error::mismatched_array_elements;
+                        return mismatched_array_elements(
+                            self.span_of(*first),
+                            first_type,
+                            self.span_of(element),
+                            type_,
+                        );
+                    }
+                    Some(_) => {}
+                    None => first = Some((element, type_)),
                 }
-                let span = self.span;
-                let expression = self.expression(Precedence::None)?;
-                let span = span.merge(expression.span);
-                let kind = ExpressionKind::Await(expression.into());
-                Ok(Expression {
-                    kind,
-                    span,
-                    type_: None,
-                })
             }
-            _ => {
-                let token = self.next()?;
-                use diagnostics::error::unexpected_token_for_expression;
-                return unexpected_token_for_expression(token.span, self.prev_span);
+            elements.push(element);
+            if !self.eat(TokenKind::Comma)? {
+                break;
+            }
+        }
+        self.expect(TokenKind::RBracket)?;
+        let span = span.merge(self.span);
+        Ok(self
+            .ctx
+            .alloc_spanned_expression(Expression::Array(elements), span))
+    }
+
+    /// Both branches of an if-expression have to produce the same type of
+    /// value, unless one of them never produces one.
+    fn check_branch_types(&self, then_branch: BlockId, else_branch: BlockId) -> Result<()> {
+        let (then_value, else_value) = match (
+            self.ctx.block_value(then_branch),
+            self.ctx.block_value(else_branch),
+        ) {
+            (Some(then_value), Some(else_value)) => (then_value, else_value),
+            _ => return Ok(()),
+        };
+        if let (Some(then_type), Some(else_type)) = (
+            self.ctx.block_type(then_branch),
+            self.ctx.block_type(else_branch),
+        ) {
+            let never = then_type == Type::Never || else_type == Type::Never;
+            if !never && then_type != else_type {
+                use diagnostics::error::mismatched_branch_types;
+                return mismatched_branch_types(
+                    self.span_of(then_value),
+                    then_type,
+                    self.span_of(else_value),
+                    else_type,
+                );
             }
         }
+        Ok(())
     }
 
-    fn match_expression(&mut self) -> Result<Expression> {
-        self.expect(TokenKind::Match)?;
-        let span = self.span;
-        let value = self.expression(Precedence::None)?;
-        let cases = self.match_cases()?;
-        let span = self.span.merge(span);
-        Ok(Expression {
-            kind: ExpressionKind::Match {
-                value: value.into(),
-                cases,
-            },
-            span,
-            type_: None,
+    fn parse_return(&mut self) -> Result<StatementId> {
+        self.expect(TokenKind::Return)?;
+        let value = self.parse_expression(Precedence::None)?;
+        let return_ = Statement::Return(value);
+        let return_id = self.ctx.statements.alloc(return_);
+        Ok(return_id)
+    }
+
+    fn parse_let(&mut self) -> Result<StatementId> {
+        self.expect(TokenKind::Let)?;
+        let mutable = self.eat(TokenKind::Mut)?;
+        let name = self.identifier()?;
+        let symbol = name.symbol;
+        self.expect(TokenKind::Equals)?;
+        let value = self.parse_expression(Precedence::None)?;
+        let let_ = Statement::Let {
+            name,
+            value,
+            mutable,
+        };
+        let let_id = self.ctx.statements.alloc(let_);
+        self.define_unique(symbol, Binding::Let(let_id));
+        Ok(let_id)
+    }
+
+    fn parse_state(&mut self) -> Result<StatementId> {
+        if !self.in_component_body {
+            use diagnostics::error::state_outside_component;
+            return state_outside_component(self.peek()?.span);
+        }
+        self.expect(TokenKind::State)?;
+        let name = self.identifier()?;
+        let symbol = name.symbol;
+        self.expect(TokenKind::Equals)?;
+        let value = self.parse_expression(Precedence::None)?;
+        let state = State { name, value };
+        let state_id = self.ctx.states.alloc(state);
+        let state_id = self.ctx.statements.alloc(Statement::State(state_id));
+        self.define_unique(symbol, Binding::State(state_id));
+        Ok(state_id)
+    }
+
+    /// Define a binding, giving it a unique name if it shadows another binding.
+    fn define_unique(&mut self, symbol: Symbol, binding: Binding) {
+        let unique_name = self.scope_map.unique_name(&symbol);
+        if unique_name > 0 {
+            self.ctx.unique_names.insert(binding, unique_name);
+        }
+        self.scope_map.define(symbol, binding);
+    }
+
+    fn parse_expression(&mut self, precedence: Precedence) -> Result<ExpressionId> {
+        self.nested(|parser| {
+            let prefix = parser.parse_prefix_expression()?;
+            parser.parse_infix_expressions(prefix, precedence)
         })
     }
 
-    fn match_cases(&mut self) -> Result<Vec<MatchCase>> {
-        self.expect(TokenKind::LBrace)?;
-        let mut cases = vec![];
-        let mut wildcard_span = None;
-        loop {
-            if let TokenKind::RBrace = self.peek()?.kind {
+    /// Parse the infix expressions following `prefix` that bind tighter than `precedence`.
+    fn parse_infix_expressions(
+        &mut self,
+        prefix: ExpressionId,
+        precedence: Precedence,
+    ) -> Result<ExpressionId> {
+        let mut expression = prefix;
+        while precedence < self.peek()?.precedence() {
+            let infix = self.parse_infix_expression(expression)?;
+            // Some tokens with a precedence, like `.`, aren't handled as infix
+            // operators yet. Leave them for the caller to report.
+            if infix == expression {
                 break;
             }
-            let pattern = match self.peek()?.kind {
-                TokenKind::Underscore => {
-                    self.skip()?;
-                    if let Some(span) = wildcard_span {
-                        use diagnostics::error::duplicate_wildcard_error;
-                        return duplicate_wildcard_error(span, self.span);
-                    }
-                    wildcard_span = Some(self.span);
-                    MatchPattern::Wildcard
-                }
-                _ => {
-                    let expression = self.expression(Precedence::None)?;
-                    // Check if a wildcard has already been used and warn
-                    // about it since further cases will be unreachable.
-                    if let Some(span) = wildcard_span {
-                        use diagnostics::error::unreachable_match_case;
-                        return unreachable_match_case(self.span, span);
-                    }
-                    MatchPattern::Expression(expression.into())
-                }
-            };
-            self.expect(TokenKind::Arrow)?;
-            let body = self.expression(Precedence::None)?.into();
-            cases.push(MatchCase { pattern, body });
+            expression = infix;
         }
-        self.expect(TokenKind::RBrace)?;
-        Ok(cases)
+        Ok(expression)
     }
 
-    fn binary_expression(&mut self, left: Expression) -> Result<Expression> {
+    fn binary_expression(&mut self, left: ExpressionId) -> Result<ExpressionId> {
         let (op, precedence) = {
             let token = self.next()?;
             let precedence = token.precedence();
             let op: BinOp = token.into();
             (op, precedence)
         };
-        let right = self.expression(precedence)?;
-        let span = left.span.merge(right.span);
-        Ok(Expression {
-            span,
-            kind: ExpressionKind::Binary {
-                left: left.into(),
-                right: right.into(),
-                op,
-            },
-            type_: None,
-        })
-    }
-
-    fn member_expression(&mut self, left: Expression) -> Result<Expression> {
-        self.expect(TokenKind::Dot)?;
-        let name = self.identifier()?;
-        let span = left.span.merge(name.span);
-        Ok(Expression {
-            span,
-            kind: ExpressionKind::Member {
-                object: left.into(),
-                property: name,
-            },
-            type_: None,
-        })
+        let right = self.parse_expression(precedence)?;
+        let span = self.span_between(left, right);
+        let expression = Expression::Binary { left, op, right };
+        Ok(self.ctx.alloc_spanned_expression(expression, span))
     }
 
-    fn _dot(&mut self, left: Expression) -> Result<Expression> {
-        self.expect(TokenKind::Dot)?;
-        let right = self.expression(Precedence::Prefix)?;
-        match (left.kind, right.kind) {
-            (
-                ExpressionKind::Number { raw: left_raw, .. },
-                ExpressionKind::Number { raw: right_raw, .. },
-            ) => {
-                let raw = Symbol::intern(&format!("{:?}.{:?}", left_raw, right_raw));
-                let span = left.span.merge(right.span);
-                let expression = Expression {
-                    span,
-                    kind: ExpressionKind::Number { raw, value: None },
-                    type_: Some(Type::Number),
+    fn call_expression(&mut self, callee_id: ExpressionId) -> Result<ExpressionId> {
+        let callee = self.ctx.expressions.get(callee_id).unwrap();
+        let callee = callee.borrow();
+        match *callee {
+            Expression::Reference(_) => {
+                std::mem::drop(callee);
+                let arguments = self.parse_arguments()?;
+                let span = self.span_of(callee_id).merge(self.span);
+                if let Some(Builtin::Log) = self.builtin(callee_id) {
+                    self.check_log_arguments(&arguments)?;
+                }
+                let expression = Expression::Call {
+                    callee: callee_id,
+                    arguments,
                 };
-                Ok(expression)
+                let expression_id = self.ctx.alloc_spanned_expression(expression, span);
+                Ok(expression_id)
             }
             _ => {
-                todo!("Other dot expressions")
-                // ...
+                use diagnostics::error::illegal_function_callee;
+                illegal_function_callee(self.span_of(callee_id))
+            }
+        }
+        // TODO
+        // - call graph
+        // - evaluate to see if we can inline
+    }
+
+    /// The builtin an expression refers to, if it's a reference to one.
+    fn builtin(&self, expression_id: ExpressionId) -> Option<Builtin> {
+        match &*self.ctx.expressions[expression_id].borrow() {
+            Expression::Reference(Binding::Global(global_id)) => {
+                self.ctx.globals[*global_id].builtin
+            }
+            _ => None,
+        }
+    }
+
+    /// Logs can't have named arguments, and a format string with
+    /// placeholders needs exactly as many arguments after it as it has.
+    fn check_log_arguments(&self, arguments: &[Argument]) -> Result<()> {
+        if let Some(name) = arguments.iter().find_map(|argument| argument.name) {
+            use diagnostics::error::named_log_argument;
+            return named_log_argument(name.span, name.symbol);
+        }
+        let format = match arguments.first() {
+            Some(argument) => argument.value,
+            None => return Ok(()),
+        };
+        let parts = match &*self.ctx.expressions[format].borrow() {
+            Expression::String(format) => parse_format(&format.to_string()),
+            _ => return Ok(()),
+        };
+        let span = self.span_of(format);
+        match parts {
+            Ok(parts) => {
+                let placeholders = parts
+                    .iter()
+                    .filter(|part| **part == FormatPart::Placeholder)
+                    .count();
+                if placeholders > 0 && placeholders != arguments.len() - 1 {
+                    use diagnostics::error::log_format_arguments;
+                    return log_format_arguments(span, placeholders, arguments.len() - 1);
+                }
+                Ok(())
+            }
+            Err(_) => {
+                use diagnostics::error::invalid_log_format;
+                invalid_log_format(span)
             }
         }
     }
 
-    fn arguments(&mut self) -> Result<Vec<Argument>> {
-        debug!("Arguments");
+    fn parse_arguments(&mut self) -> Result<Vec<Argument>> {
+        self.expect(TokenKind::LParen)?;
         // Arguments can be positional like foo(bar) or named
         // like foo(bar: baz).
         #[derive(Debug, PartialEq, Eq)]
@@ -770,18 +1753,21 @@ impl<'s> ParserImpl<'s> {
                     if call_format == CallFormat::Positional {
                         use diagnostics::error::named_argument_after_positional;
                         // Parse the next expression to include it in the error reporting
-                        let expr = self.expression(Precedence::None)?;
-                        let span = name.span.merge(expr.span);
-                        return named_argument_after_positional(
-                            span,
-                            arguments.last().unwrap().span,
-                        );
+                        let expr = self.parse_expression(Precedence::None)?;
+                        let span = name.span.merge(self.span_of(expr));
+                        let last = self.last_argument_span(&arguments);
+                        return named_argument_after_positional(span, last);
                     }
                     call_format = CallFormat::Named;
-                    let value = self.expression(Precedence::None)?;
-                    let span = name.span.merge(self.span);
+                    // `foo(bar:)` is short for `foo(bar: bar)`
+                    let value = match self.peek()?.kind {
+                        TokenKind::Comma | TokenKind::RParen => {
+                            self.parse_expression_from_identifier(name.symbol, name.span)?
+                        }
+                        _ => self.parse_expression(Precedence::None)?,
+                    };
+                    // let span = name.span.merge(self.span);
                     let argument = Argument {
-                        span,
                         name: Some(name),
                         value,
                     };
@@ -791,32 +1777,25 @@ impl<'s> ParserImpl<'s> {
                     let expr = self.parse_expression_from_identifier(name.symbol, name.span)?;
                     if call_format == CallFormat::Named {
                         use diagnostics::error::positional_argument_after_named;
-                        return positional_argument_after_named(
-                            expr.span,
-                            arguments.last().unwrap().span,
-                        );
+                        let last = self.last_argument_span(&arguments);
+                        return positional_argument_after_named(self.span_of(expr), last);
                     }
                     call_format = CallFormat::Positional;
-                    let expr = self.parse_expression_from_identifier(name.symbol, name.span)?;
                     let argument = Argument {
-                        span: expr.span,
                         name: None,
                         value: expr,
                     };
                     arguments.push(argument);
                 }
             } else {
-                let expr = self.expression(Precedence::None)?;
+                let expr = self.parse_expression(Precedence::None)?;
                 if call_format == CallFormat::Named {
                     use diagnostics::error::positional_argument_after_named;
-                    return positional_argument_after_named(
-                        expr.span,
-                        arguments.last().unwrap().span,
-                    );
+                    let last = self.last_argument_span(&arguments);
+                    return positional_argument_after_named(self.span_of(expr), last);
                 }
                 call_format = CallFormat::Positional;
                 let argument = Argument {
-                    span: expr.span,
                     name: None,
                     value: expr,
                 };
@@ -828,525 +1807,1666 @@ impl<'s> ParserImpl<'s> {
         Ok(arguments)
     }
 
-    fn call_expression(&mut self, left: Expression) -> Result<Expression> {
-        let span = left.span;
-        self.expect(TokenKind::LParen)?;
-        match left.kind {
-            // Function call with a reference
-            ExpressionKind::Reference(_) | ExpressionKind::Member { .. } => {
-                let arguments = self.arguments()?;
-                let span = span.merge(self.span);
-                let call = Call {
-                    callee: left.into(),
-                    arguments,
-                };
-                if let TokenKind::LBrace = self.peek()?.kind {
-                    // A bracket right after a function call is parsed as a view expression
-                    let block = self.block()?;
-                    let view = View {
-                        constructor: call,
-                        body: block,
-                    };
-                    Ok(Expression {
-                        span,
-                        kind: ExpressionKind::View(view.into()),
-                        type_: None,
-                    })
-                } else {
-                    let kind = ExpressionKind::Call(call);
-                    let span = self.span.merge(span);
-                    Ok(Expression {
-                        kind,
-                        span,
-                        type_: None,
-                    })
-                }
-            }
-            _ => {
-                use diagnostics::error::illegal_function_callee;
-                illegal_function_callee(left.span)
-            }
+    fn parse_infix_expression(&mut self, prefix: ExpressionId) -> Result<ExpressionId> {
+        debug!("parse_infix_expression {}", self.peek()?.kind);
+        // `</` is never an operator, it's the close tag after an expression
+        // in a template that's missing its `}`.
+        if self.at_close_tag()? {
+            return Ok(prefix);
         }
-    }
-
-    fn infix_expression(&mut self, prefix: Expression) -> Result<Expression> {
         use TokenKind::*;
+        // Other operators can continue an expression on the next line, but
+        // `<` and `(` there start a template or a parenthesized expression
+        if matches!(self.peek()?.kind, LessThan | LParen) && self.at_line_start()? {
+            return Ok(prefix);
+        }
         match self.peek()?.kind {
             Plus | Minus | Star | Slash | LessThan | LessThanEquals | GreaterThan
-            | GreaterThanEquals | DoubleEquals | And | BinAnd => self.binary_expression(prefix),
-            Equals => self.assignment_expression(prefix),
+            | GreaterThanEquals | DoubleEquals | And | Or | BinAnd => {
+                self.binary_expression(prefix)
+            }
+            Pipeline => {
+                let span = self.peek()?.span;
+                self.require_feature(Feature::Pipeline, span)?;
+                self.binary_expression(prefix)
+            }
             LParen => self.call_expression(prefix),
-            Dot => self.member_expression(prefix),
-            Range => self.range_expression(prefix),
+            // Equals => self.assignment_expression(prefix),
+            // Dot => self.member_expression(prefix),
+            // Range => self.range_expression(prefix),
             _ => Ok(prefix),
         }
     }
 
-    fn block_expression(&mut self) -> Result<Expression> {
-        let span = self.span;
-        let block = self.block()?;
-        let kind = ExpressionKind::Block(block);
-        Ok(Expression {
-            kind,
-            span,
-            type_: None,
-        })
+    fn parse_function_expression(&mut self, prefix: ExpressionId) -> Result<ExpressionId> {
+        let function_id = self.parse_function()?;
+        let expression = Expression::Function(function_id);
+        let expression_id = self.ctx.alloc_expression(expression);
+        Ok(expression_id)
     }
 
-    fn assignment_expression(&mut self, left: Expression) -> Result<Expression> {
-        self.expect(TokenKind::Equals)?;
-        match left.kind {
-            ExpressionKind::Reference(_) | ExpressionKind::Member { .. } => {
-                let right = self.expression(Precedence::Assignment)?;
-                let span = left.span.merge(right.span);
-                let kind = ExpressionKind::Assignment {
-                    left: left.into(),
-                    right: right.into(),
+    fn parse_prefix_expression(&mut self) -> Result<ExpressionId> {
+        debug!("parse_prefix_expression {}", self.peek()?.kind);
+        match self.peek()?.kind {
+            // Boolean expressions
+            TokenKind::True | TokenKind::False => {
+                let token = self.next()?;
+                let value = TokenKind::True == token.kind;
+                let expression_id = self
+                    .ctx
+                    .alloc_spanned_expression(Expression::Boolean(value), token.span);
+                Ok(expression_id)
+            }
+            // Numeric expressions
+            TokenKind::Number(raw_value) => {
+                self.next()?;
+                let expression = Expression::Number {
+                    value: raw_value.into(),
+                    literal: Some(raw_value),
                 };
-                Ok(Expression {
-                    kind,
-                    span,
-                    type_: None,
-                })
+                let expression_id = self.ctx.alloc_spanned_expression(expression, self.span);
+                Ok(expression_id)
+            }
+            TokenKind::String(symbol) => {
+                self.next()?;
+                let expression_id = self
+                    .ctx
+                    .alloc_spanned_expression(Expression::String(symbol), self.span);
+                Ok(expression_id)
+            }
+            // References
+            TokenKind::Identifier(symbol) => {
+                let token = self.next()?;
+                self.parse_expression_from_identifier(symbol, token.span)
+            }
+            TokenKind::LessThan => {
+                self.expect(TokenKind::LessThan)?;
+                let span = self.span;
+                if !self.in_component_body {
+                    use diagnostics::error::template_outside_component;
+                    let function = self
+                        .top_level_function
+                        .map(|function| (function.symbol, function.span.into()));
+                    return template_outside_component(span, function);
+                }
+                let template = self.parse_template()?;
+                let span = span.merge(self.span);
+                let expression_id = self
+                    .ctx
+                    .alloc_spanned_expression(Expression::Template(template), span);
+                Ok(expression_id)
+            }
+            TokenKind::LParen => {
+                self.next()?;
+                let span = self.span;
+                let expression_id = self.parse_expression(Precedence::None)?;
+                self.expect(TokenKind::RParen)?;
+                let span = span.merge(self.span);
+                self.ctx.expression_spans.insert(expression_id, span);
+                Ok(expression_id)
+            }
+            TokenKind::LBracket => self.parse_array(),
+            TokenKind::If => self.parse_if_expression(),
+            TokenKind::Match => self.parse_match(),
+            TokenKind::Await => {
+                let span = self.peek()?.span;
+                self.expect(TokenKind::Await)?;
+                if !self.in_async_function {
+                    use diagnostics::error::invalid_await;
+                    return invalid_await(span);
+                }
+                let value = self.parse_expression(Precedence::Prefix)?;
+                let span = span.merge(self.span);
+                let expression_id = self
+                    .ctx
+                    .alloc_spanned_expression(Expression::Await(value), span);
+                Ok(expression_id)
+            }
+            TokenKind::Use => {
+                let span = self.peek()?.span;
+                let context = self.resolve_context(TokenKind::Use)?;
+                let span = span.merge(self.span);
+                let expression_id = self
+                    .ctx
+                    .alloc_spanned_expression(Expression::UseContext(context), span);
+                Ok(expression_id)
+            }
+            TokenKind::Fn | TokenKind::Async => {
+                let span = self.peek()?.span;
+                let function = self.parse_function()?;
+                let span = span.merge(self.span);
+                let expression_id = self
+                    .ctx
+                    .alloc_spanned_expression(Expression::Function(function), span);
+                Ok(expression_id)
             }
             _ => {
-                use diagnostics::error::illegal_assignment_target;
-                illegal_assignment_target(left.span)
+                use diagnostics::error::unexpected_token_for_expression;
+                let span = self.peek()?.span;
+                unexpected_token_for_expression(span, self.last_span())
             }
         }
     }
 
-    // range_expression parses a range expression like `1..10` or `1..20`
-    fn range_expression(&mut self, start: Expression) -> Result<Expression> {
-        self.expect(TokenKind::Range)?;
-        let end = self.expression(Precedence::None)?;
-        let span = start.span.merge(end.span);
-        let kind = ExpressionKind::Range {
-            start: start.into(),
-            end: end.into(),
-        };
-        Ok(Expression {
-            kind,
-            span,
-            type_: None,
-        })
+    /// Parse a template, after its `<`. Elements are parsed with a stack of
+    /// the ones that are still open instead of recursively, so nesting them
+    /// doesn't use up the stack.
+    fn parse_template(&mut self) -> Result<TemplateId> {
+        let template_depth = self.template_depth;
+        let template = self.parse_template_elements();
+        self.template_depth = template_depth;
+        template
     }
 
-    fn expression(&mut self, precedence: Precedence) -> Result<Expression> {
-        let mut expression = self.prefix_expression()?;
-        while precedence < self.peek()?.precedence() {
-            expression = self.infix_expression(expression)?;
+    fn parse_template_elements(&mut self) -> Result<TemplateId> {
+        let mut open: Vec<OpenElement> = vec![];
+        loop {
+            if self.template_depth == MAX_TEMPLATE_DEPTH {
+                use diagnostics::error::template_nesting_too_deep;
+                return template_nesting_too_deep(self.span, MAX_TEMPLATE_DEPTH);
+            }
+            self.template_depth += 1;
+            let open_tag = self.parse_template_open_tag()?;
+            debug!("parse_template: open_tag = {:#?}", open_tag);
+            let mut finished = if self.peek()?.kind == TokenKind::Slash {
+                debug!("parse_template: self-closing tag");
+                self.next()?;
+                self.expect(TokenKind::GreaterThan)?;
+                self.template_depth -= 1;
+                let template = Template {
+                    open_tag,
+                    close_tag: None,
+                    children: None,
+                };
+                Some(self.ctx.alloc_template(template))
+            } else if is_void_element(&open_tag.name.symbol.to_string()) {
+                // Void elements never have children, so they're finished at
+                // their `>` like a self-closing tag
+                self.expect(TokenKind::GreaterThan)?;
+                self.template_depth -= 1;
+                // Ones in other elements can be closed explicitly too, which
+                // `parse_template_children` handles
+                let close_tag = if open.is_empty() {
+                    self.eat_void_close_tag(open_tag.name)?
+                } else {
+                    None
+                };
+                let template = Template {
+                    open_tag,
+                    close_tag,
+                    children: None,
+                };
+                Some(self.ctx.alloc_template(template))
+            } else {
+                self.expect(TokenKind::GreaterThan)?;
+                open.push(OpenElement {
+                    open_tag,
+                    children: vec![],
+                });
+                None
+            };
+            // Finish as many elements as are closed before the next one opens
+            loop {
+                let template_id = match finished.take() {
+                    Some(template_id) => template_id,
+                    None => {
+                        let element = open.last_mut().unwrap();
+                        let name = element.open_tag.name;
+                        match self.parse_template_children(name, &mut element.children)? {
+                            Some(close_tag) => {
+                                self.template_depth -= 1;
+                                let element = open.pop().unwrap();
+                                self.finish_template_element(element, close_tag)?
+                            }
+                            // A nested element was opened
+                            None => break,
+                        }
+                    }
+                };
+                match open.last_mut() {
+                    Some(parent) => parent.children.push(TemplateChild::Template(template_id)),
+                    None => return Ok(template_id),
+                }
+            }
         }
-        Ok(expression)
     }
 
-    fn array_expression(&mut self) -> Result<Expression> {
-        self.expect(TokenKind::RBracket)?;
-        let mut elements = vec![];
-        let span = self.span;
+    fn finish_template_element(
+        &mut self,
+        element: OpenElement,
+        close_tag: TemplateCloseTag,
+    ) -> Result<TemplateId> {
+        let OpenElement {
+            open_tag,
+            children: template_children,
+        } = element;
+        debug!(
+            "parse_template: template_children = {:#?}",
+            template_children
+        );
+        debug!("parse_template: close_tag = {:#?}", close_tag);
+        let template = Template {
+            open_tag,
+            close_tag: Some(close_tag),
+            children: Some(template_children),
+        };
+        let template_id = self.ctx.alloc_template(template);
+        Ok(template_id)
+    }
+
+    /// Parse the children of the element named `open_tag_name` into
+    /// `children`, up to its close tag. If another element is opened first,
+    /// its `<` is parsed and `None` is returned.
+    fn parse_template_children(
+        &mut self,
+        open_tag_name: Identifier,
+        children: &mut Vec<TemplateChild>,
+    ) -> Result<Option<TemplateCloseTag>> {
         loop {
             match self.peek()?.kind {
-                TokenKind::RBracket => break,
-                _ => {
-                    let element = self.expression(Precedence::None)?;
-                    elements.push(element);
-                    if self.eat(TokenKind::Comma)? {
+                TokenKind::TemplateString(symbol) => {
+                    debug!("parse_template_children: TemplateString({})", symbol);
+                    self.skip()?;
+                    // Text is kept with its original whitespace, which is
+                    // collapsed during codegen.
+                    let child = TemplateChild::String(symbol);
+                    children.push(child);
+                }
+                TokenKind::LBrace => {
+                    let open_brace = self.expect(TokenKind::LBrace)?.span;
+                    match self.parse_template_expression(open_brace) {
+                        Ok(expression) => {
+                            let child = TemplateChild::Expression(expression);
+                            children.push(child);
+                        }
+                        Err(error) => self.recover_in_template(error)?,
+                    }
+                }
+                TokenKind::LessThan => {
+                    self.expect(TokenKind::LessThan)?;
+                    if !self.eat(TokenKind::Slash)? {
+                        debug!("parse_template_children: nested template");
+                        return Ok(None);
+                    }
+                    // This is a close tag, not a nested template
+                    let name = self.parse_template_name(Self::identifier)?;
+                    debug!(
+                        "parse_template_children: closing tag for </{}>",
+                        name.symbol
+                    );
+                    self.expect(TokenKind::GreaterThan)?;
+                    if name.symbol != open_tag_name.symbol
+                        && is_void_element(&name.symbol.to_string())
+                    {
+                        self.close_void_element(open_tag_name, children, name)?;
                         continue;
-                    } else {
-                        break;
                     }
+                    if name.symbol != open_tag_name.symbol {
+                        use diagnostics::error::mismatched_close_tag;
+                        return mismatched_close_tag(
+                            open_tag_name.span,
+                            open_tag_name.symbol,
+                            name.span,
+                            name.symbol,
+                        );
+                    }
+                    return Ok(Some(TemplateCloseTag { name }));
+                }
+                _ => {
+                    use diagnostics::error::missing_close_tag;
+                    let span = self.peek()?.span;
+                    return missing_close_tag(open_tag_name.span, open_tag_name.symbol, span);
                 }
             }
         }
-        self.expect(TokenKind::RBracket)?;
-        let kind = ExpressionKind::Array(elements);
-        let span = self.span.merge(span);
-        Ok(Expression {
-            kind,
-            span,
-            type_: None,
-        })
     }
 
-    fn let_(&mut self) -> Result<Statement> {
-        self.expect(TokenKind::Let)?;
-        let span = self.span;
-        let name = self.identifier()?;
-        self.expect(TokenKind::Equals)?;
-        let value = self.expression(Precedence::None)?;
-        let symbol = name.symbol;
-        let span = span.merge(value.span);
-        // let unique_name = self.scope_map.unique_name();
-        let let_ = Let {
-            name,
-            value,
-            // TODO
-            unique_name: UniqueName::from(0),
+    /// Close the void element among `children` that `close_tag` is for, like
+    /// the `</br>` in `<br></br>`. Void elements are finished at their `>`,
+    /// so anything between that and the close tag would be its children.
+    fn close_void_element(
+        &mut self,
+        open_tag_name: Identifier,
+        children: &[TemplateChild],
+        close_tag: Identifier,
+    ) -> Result<()> {
+        let is_blank = |child: &TemplateChild| match child {
+            TemplateChild::String(text) => text.to_string().trim().is_empty(),
+            _ => false,
         };
-        let let_ = Arc::new(let_);
-        let binding = Binding::Let(let_.clone());
-        self.scope_map.define(symbol, binding);
-        Ok(Statement {
-            kind: StatementKind::Let(let_),
-            span,
-        })
+        let mut has_children = false;
+        for child in children.iter().rev() {
+            if let TemplateChild::Template(template_id) = child {
+                let mut template = self.ctx.templates[*template_id].borrow_mut();
+                let open_tag = template.open_tag.name;
+                if open_tag.symbol == close_tag.symbol && template.close_tag.is_none() {
+                    if has_children {
+                        use diagnostics::error::void_element_children;
+                        return void_element_children(
+                            open_tag.span,
+                            close_tag.span,
+                            open_tag.symbol,
+                        );
+                    }
+                    template.close_tag = Some(TemplateCloseTag { name: close_tag });
+                    return Ok(());
+                }
+            }
+            has_children |= !is_blank(child);
+        }
+        use diagnostics::error::mismatched_close_tag;
+        mismatched_close_tag(
+            open_tag_name.span,
+            open_tag_name.symbol,
+            close_tag.span,
+            close_tag.symbol,
+        )
     }
 
-    fn state(&mut self) -> Result<Statement> {
-        self.expect(TokenKind::State)?;
+    /// Parse the close tag right after a void element named `name` that's
+    /// written like `<br></br>`, if there is one.
+    fn eat_void_close_tag(&mut self, name: Identifier) -> Result<Option<TemplateCloseTag>> {
+        if !self.at_close_tag()?
+            || self.lexer.peek_nth(2)?.kind != TokenKind::Identifier(name.symbol)
+        {
+            return Ok(None);
+        }
+        self.expect(TokenKind::LessThan)?;
+        self.expect(TokenKind::Slash)?;
         let name = self.identifier()?;
-        self.expect(TokenKind::Equals)?;
-        let value = self.expression(Precedence::None)?;
-        let symbol = name.symbol;
-        let span = name.span.merge(value.span);
-        // let unique_name = self.scope_map.unique_name();
-        let state = State {
-            name,
-            value,
-            // TODO
-            unique_name: UniqueName::from(0),
-        };
-        let state = Arc::new(state);
-        let binding = Binding::State(state.clone());
-        self.scope_map.define(symbol, binding);
-        Ok(Statement {
-            kind: StatementKind::State(state),
-            span,
-        })
+        self.expect(TokenKind::GreaterThan)?;
+        Ok(Some(TemplateCloseTag { name }))
     }
 
-    fn return_(&mut self) -> Result<Statement> {
-        self.expect(TokenKind::Return)?;
-        let span = self.span;
-        let value = self.expression(Precedence::None)?;
-        let span = span.merge(value.span);
-        Ok(Statement {
-            kind: StatementKind::Return(value),
-            span,
-        })
+    /// Parse an expression in a template, after its `{`.
+    fn parse_template_expression(&mut self, open_brace: Span) -> Result<ExpressionId> {
+        // An expression that's missing its `}` runs into the close tag.
+        if !self.at_close_tag()? {
+            let expression = self.parse_expression(Precedence::None)?;
+            if self.eat(TokenKind::RBrace)? {
+                return Ok(expression);
+            }
+        }
+        use diagnostics::error::unclosed_template_expression;
+        let span = self.peek()?.span;
+        unclosed_template_expression(open_brace, span)
     }
 
-    fn statement(&mut self) -> Result<StatementId> {
-        let statement = match self.peek()?.kind {
-            TokenKind::Let => self.let_(),
-            TokenKind::State => self.state(),
-            TokenKind::Return => self.return_(),
-            TokenKind::If => self.if_(),
-            TokenKind::For => self.for_(),
-            TokenKind::While => self.while_(),
-            _ => self.expression_statement(),
-        }?;
-        let statement_id = self.ast_arena.statements.alloc(statement);
-        Ok(statement_id)
+    /// Whether the next tokens start a close tag, like `</div>`.
+    fn at_close_tag(&mut self) -> Result<bool> {
+        Ok(self.peek()?.kind == TokenKind::LessThan
+            && self.lexer.peek_nth(1)?.kind == TokenKind::Slash)
     }
 
-    fn expression_statement(&mut self) -> Result<Statement> {
-        let expression = self.expression(Precedence::None)?;
-        let span = expression.span;
-        Ok(Statement {
-            kind: StatementKind::Expression(expression),
-            span,
-        })
+    /// Keep an error to report once the module is parsed, so parsing can
+    /// continue. Errors that aren't diagnostics can't be recovered from.
+    fn recover(&mut self, error: Error) -> Result<()> {
+        match error {
+            Error::Diagnostic(diagnostic) => {
+                self.errors.push(diagnostic);
+                Ok(())
+            }
+            error => Err(error),
+        }
     }
 
-    // Parse a for-in statement like for x in y { ... }
-    fn for_(&mut self) -> Result<Statement> {
-        self.expect(TokenKind::For)?;
-        let span = self.span;
-        let iterator = self.identifier()?;
-        let symbol = iterator.symbol;
-        self.scope_map
-            .define(symbol, Binding::Iterator(iterator.clone()));
-        self.expect(TokenKind::In)?;
-        let iterable = self.expression(Precedence::None)?;
-        let body = self.block()?;
-        let for_ = For {
-            iterator,
-            iterable,
-            body,
-        };
-        let span = self.span.merge(span);
-        Ok(Statement {
-            kind: StatementKind::For(for_),
-            span,
-        })
+    /// Recover from an error in a template's children by skipping past
+    /// the next `}`, or up to the next close tag.
+    fn recover_in_template(&mut self, error: Error) -> Result<()> {
+        self.recover(error)?;
+        while !self.at_close_tag()? {
+            match self.next()?.kind {
+                TokenKind::EOF | TokenKind::RBrace => break,
+                _ => {}
+            }
+        }
+        Ok(())
     }
 
-    fn if_impl(&mut self) -> Result<If> {
-        self.expect(TokenKind::If)?;
-        let span = self.span;
-        let condition = self.expression(Precedence::None)?;
-        let body = self.block()?;
-        let alternate = if self.eat(TokenKind::Else)? {
-            if TokenKind::If == self.peek()?.kind {
-                let if_ = self.if_impl()?;
-                let alternate = Else::If(if_);
-                Some(alternate.into())
+    fn parse_template_open_tag(&mut self) -> Result<TemplateOpenTag> {
+        let name = self.parse_template_name(Self::identifier)?;
+        // Check if first letter of name is uppercase
+        let name_string = name.symbol.to_string();
+        let is_component = name_string.chars().next().unwrap().is_uppercase();
+        let attributes = self.parse_template_attributes(is_component)?;
+        let reference = if is_component {
+            if let Some((binding, _)) = self.scope_map.resolve(&name.symbol) {
+                Some(*binding)
             } else {
-                let block = self.block()?;
-                let alternate = Else::Block(block);
-                Some(alternate.into())
+                // Parse the rest of the template as an element, since it
+                // isn't a component.
+                use diagnostics::error::unknown_reference_error;
+                if let Err(error) =
+                    unknown_reference_error::<()>(name.span, name.symbol, None::<Span>)
+                {
+                    self.recover(error)?;
+                }
+                None
             }
         } else {
             None
         };
-        let span = self.span.merge(span);
-        let condition = alloc_expression(condition);
-        let if_ = If {
-            span,
-            condition,
-            body,
-            alternate,
+        let open_tag = TemplateOpenTag {
+            name,
+            reference,
+            attributes,
         };
-        Ok(if_)
+        Ok(open_tag)
+    }
+
+    /// Parse the attributes of an open tag. A component's attributes are its
+    /// props, so the ones that look like event handlers aren't checked as
+    /// DOM events.
+    fn parse_template_attributes(&mut self, is_component: bool) -> Result<Vec<TemplateAttribute>> {
+        let mut attributes = vec![];
+        loop {
+            match self.peek()?.kind {
+                TokenKind::GreaterThan | TokenKind::Slash | TokenKind::EOF => break,
+                _ => {}
+            }
+            let attribute = self.parse_template_attribute().and_then(|attribute| {
+                if !is_component {
+                    self.check_event_handler(&attribute)?;
+                }
+                Ok(attribute)
+            });
+            match attribute {
+                Ok(template_attribute) => attributes.push(template_attribute),
+                Err(error) => self.recover_in_attributes(error)?,
+            }
+        }
+        Ok(attributes)
+    }
+
+    /// Recover from an error in an attribute by skipping to the next
+    /// attribute, or to the end of the tag.
+    fn recover_in_attributes(&mut self, error: Error) -> Result<()> {
+        self.recover(error)?;
+        loop {
+            let starts_attribute = self.lexer.peek_nth(1)?.kind == TokenKind::Equals;
+            match self.peek()?.kind {
+                TokenKind::EOF | TokenKind::GreaterThan | TokenKind::Slash => return Ok(()),
+                TokenKind::Identifier(_) if starts_attribute => return Ok(()),
+                TokenKind::RBrace => return self.skip(),
+                _ => self.skip()?,
+            }
+        }
+    }
+
+    /// Parse an attribute, like `class="a"` or `value={value}`. That can be
+    /// shortened to `{value}`, and `disabled` on its own is `disabled={true}`.
+    fn parse_template_attribute(&mut self) -> Result<TemplateAttribute> {
+        if self.eat(TokenKind::LBrace)? {
+            let name = self.identifier()?;
+            let value = self.parse_expression_from_identifier(name.symbol, name.span)?;
+            self.expect(TokenKind::RBrace)?;
+            return Ok(TemplateAttribute { name, value });
+        }
+        // We allow keywords here
+        let name = self.parse_template_name(Self::identifier_loose)?;
+        if !self.eat(TokenKind::Equals)? {
+            let value = Expression::Boolean(true);
+            let value = self.ctx.alloc_spanned_expression(value, name.span);
+            return Ok(TemplateAttribute { name, value });
+        }
+        // TODO I don't think this is the right precedence
+        match self.peek()?.kind {
+            TokenKind::String(_) | TokenKind::True | TokenKind::False => {
+                let value = self.parse_expression(Precedence::Prefix)?;
+                let template_attribute = TemplateAttribute { name, value: value };
+                Ok(template_attribute)
+            }
+            TokenKind::LBrace => {
+                self.skip()?;
+                let value = self.parse_expression(Precedence::None)?;
+                self.expect(TokenKind::RBrace)?;
+                let template_attribute = TemplateAttribute { name, value };
+                Ok(template_attribute)
+            }
+            _ => {
+                use diagnostics::error::invalid_attribute_value;
+                let token = self.peek()?.clone();
+                invalid_attribute_value(token.span, name.symbol, token.kind)
+            }
+        }
+    }
+
+    /// Check that an event handler, like `onClick={handler}`, can take the
+    /// event it's sent. Handlers can take the event, or nothing at all.
+    fn check_event_handler(&self, attribute: &TemplateAttribute) -> Result<()> {
+        let name = attribute.name.symbol.to_string();
+        let event = match handled_event(&name) {
+            Some(event) => event,
+            None => return Ok(()),
+        };
+        let parameters = match self.handler_parameters(attribute.value) {
+            Some(parameters) => parameters,
+            None => return Ok(()),
+        };
+        let expected = event_type(&event);
+        let span = self.span_of(attribute.value);
+        match parameters.as_slice() {
+            [] | [None] => Ok(()),
+            [Some(type_)] => match type_.resolved() {
+                Type::Event(handler) if expected.accepts(*handler) => Ok(()),
+                _ => {
+                    use diagnostics::error::incompatible_event_handler;
+                    incompatible_event_handler(span, event, expected.name(), type_)
+                }
+            },
+            _ => {
+                use diagnostics::error::event_handler_arity;
+                event_handler_arity(span, event, parameters.len())
+            }
+        }
+    }
+
+    /// The types of the parameters a handler takes, if it's known to be a
+    /// function. Parameters without a type are `None`.
+    fn handler_parameters(&self, handler: ExpressionId) -> Option<Vec<Option<Type>>> {
+        let function_id = match *self.ctx.expressions[handler].borrow() {
+            Expression::Reference(Binding::Function(function_id))
+            | Expression::Function(function_id) => function_id,
+            _ => {
+                return match self.ctx.expression_type(handler)?.resolved() {
+                    Type::Function { parameters, .. } => Some(
+                        parameters
+                            .iter()
+                            .map(|parameter| Some(parameter.type_.clone()))
+                            .collect(),
+                    ),
+                    _ => None,
+                }
+            }
+        };
+        let function = self.ctx.functions[function_id].borrow();
+        let parameters = function.parameters.iter().flatten();
+        Some(
+            parameters
+                .map(|parameter_id| self.ctx.parameters[*parameter_id].type_.clone())
+                .collect(),
+        )
     }
 
-    fn if_(&mut self) -> Result<Statement> {
-        let if_ = self.if_impl()?;
-        Ok(Statement {
-            span: if_.span,
-            kind: StatementKind::If(if_),
+    /// Parse a tag or attribute name, which can have a namespace prefix like
+    /// `svg:circle` or `xlink:href`. The prefix is kept as part of the name.
+    fn parse_template_name(
+        &mut self,
+        identifier: fn(&mut Self) -> Result<Identifier>,
+    ) -> Result<Identifier> {
+        let prefix = identifier(self)?;
+        if !self.eat(TokenKind::Colon)? {
+            return Ok(prefix);
+        }
+        let name = identifier(self)?;
+        Ok(Identifier {
+            symbol: Symbol::intern(&format!("{}:{}", prefix.symbol, name.symbol)),
+            span: prefix.span.merge(name.span),
         })
     }
 
-    fn while_(&mut self) -> Result<Statement> {
-        self.expect(TokenKind::While)?;
-        let span = self.span;
-        let condition = self.expression(Precedence::None)?;
-        let condition = self.ast_arena.expressions.alloc(condition);
-        let body = self.block()?;
-        let span = self.span.merge(span);
-        let while_ = While { condition, body };
-        Statement::new(StatementKind::While(while_), span)
+    fn parse_expression_from_identifier(
+        &mut self,
+        symbol: Symbol,
+        span: Span,
+    ) -> Result<ExpressionId> {
+        if let Some((binding, _)) = self.scope_map.resolve(&symbol) {
+            if let Binding::Context(_) = binding {
+                use diagnostics::error::context_reference;
+                return context_reference(span, symbol);
+            }
+            if let Binding::Variant(variant_id) = *binding {
+                return self.parse_variant(variant_id, span);
+            }
+            let expression = Expression::Reference(*binding);
+            let expression_id = self.ctx.alloc_spanned_expression(expression, span);
+            self.parse_infix_expression(expression_id)
+        } else if let Some(binding) = self.resolve_global(symbol, span)? {
+            let expression = Expression::Reference(binding);
+            let expression_id = self.ctx.alloc_spanned_expression(expression, span);
+            self.parse_infix_expression(expression_id)
+        } else {
+            // TODO move edit distance check into scope_map
+            use edit_distance::edit_distance;
+            let symbol_str = format!("{}", symbol);
+            let maybe_reference_span: Option<Span> = None;
+            let max_edit_distance = 2;
+            for scope in self.scope_map.scope_iter() {
+                for (binding_symbol, (_, _)) in &scope.bindings {
+                    let binding_str = format!("{}", binding_symbol);
+                    let distance = edit_distance(&binding_str, &symbol_str);
+                    if distance <= max_edit_distance {
+                        // maybe_reference_span = match binding {
+                        // }
+                    }
+                }
+            }
+            return diagnostics::error::unknown_reference_error(span, symbol, maybe_reference_span);
+        }
     }
 
-    fn block(&mut self) -> Result<Block> {
+    /// Parse the values a variant is created with, like the `(1)` in `Some(1)`.
+    fn parse_variant(&mut self, variant_id: VariantId, span: Span) -> Result<ExpressionId> {
+        let mut arguments = vec![];
+        if self.eat(TokenKind::LParen)? {
+            while self.peek()?.kind != TokenKind::RParen {
+                arguments.push(self.parse_expression(Precedence::None)?);
+                if !self.eat(TokenKind::Comma)? {
+                    break;
+                }
+            }
+            self.expect(TokenKind::RParen)?;
+        }
+        let span = span.merge(self.span);
+        let variant = &self.ctx.variants[variant_id];
+        if arguments.len() != variant.arity() {
+            use diagnostics::error::variant_arity;
+            return variant_arity(span, variant.name.symbol, variant.arity(), arguments.len());
+        }
+        let expression = Expression::Variant {
+            variant: variant_id,
+            arguments,
+        };
+        Ok(self.ctx.alloc_spanned_expression(expression, span))
+    }
+
+    /// Parse a match-expression, like `match shape { Circle(r) => r * r _ => 0 }`.
+    /// Cases after a wildcard can never match, so they're reported.
+    fn parse_match(&mut self) -> Result<ExpressionId> {
+        self.expect(TokenKind::Match)?;
+        let span = self.span;
+        let value = self.parse_expression(Precedence::None)?;
         self.expect(TokenKind::LBrace)?;
-        let mut statements = vec![];
-        self.scope_map.extend();
-        while !self.peek()?.follows_statement() {
-            let statement = self.statement()?;
-            statements.push(statement);
+        let mut cases = vec![];
+        let mut wildcard_span = None;
+        while self.peek()?.kind != TokenKind::RBrace {
+            let pattern_span = self.peek()?.span;
+            let pattern = self.nested(Self::parse_match_pattern)?;
+            let is_wildcard = pattern == MatchPattern::Wildcard;
+            match wildcard_span {
+                Some(wildcard_span) if is_wildcard => {
+                    use diagnostics::error::duplicate_wildcard_error;
+                    return duplicate_wildcard_error(wildcard_span, pattern_span);
+                }
+                Some(wildcard_span) => {
+                    use diagnostics::error::unreachable_match_case;
+                    return unreachable_match_case(pattern_span.merge(self.span), wildcard_span);
+                }
+                None if is_wildcard => wildcard_span = Some(pattern_span),
+                None => {}
+            }
+            self.expect(TokenKind::Arrow)?;
+            // Variant patterns bind their values in the case's body
+            self.scope_map.extend();
+            if let MatchPattern::Variant { bindings, .. } = &pattern {
+                for binding_id in bindings {
+                    let symbol = self.ctx.pattern_bindings[*binding_id].name.symbol;
+                    self.define_unique(symbol, Binding::Pattern(*binding_id));
+                }
+            }
+            let body = self.parse_expression(Precedence::None);
+            self.scope_map.pop();
+            cases.push(MatchCase {
+                pattern,
+                body: body?,
+            });
+            self.eat(TokenKind::Comma)?;
         }
         self.expect(TokenKind::RBrace)?;
-        self.scope_map.pop();
-        Ok(Block { statements })
-    }
-
-    /// Functions and components can be annotated with a return value type as well
-    /// as an effect type.
-    fn type_and_effect_annotation(&mut self) -> Result<(Option<TypeExpression>, Option<Effect>)> {
-        let annotations = if self.eat(TokenKind::Colon)? {
-            let type_ = self.type_()?;
-            let effect = if self.eat(TokenKind::Plus)? {
-                let allow_effect_reference = self.allow_effect_reference;
-                self.allow_effect_reference = true;
-                let effect_type = self.type_()?;
-                self.allow_effect_reference = allow_effect_reference;
-                let effect = Effect(effect_type);
-                Some(effect)
-            } else {
-                None
-            };
-            (Some(type_), effect)
-        } else {
-            (None, None)
-        };
-        Ok(annotations)
+        let span = span.merge(self.span);
+        check_match_exhaustiveness(self.ctx, &cases, span)?;
+        let expression = Expression::Match { value, cases };
+        Ok(self.ctx.alloc_spanned_expression(expression, span))
     }
 
-    fn parse_function(&mut self, is_async: bool) -> Result<FunctionId> {
-        let prev_is_async_context = self.is_async_context;
-        self.is_async_context = is_async;
-        self.expect(TokenKind::Fn)?;
-        self.scope_map.extend();
+    fn parse_match_pattern(&mut self) -> Result<MatchPattern> {
+        match self.peek()?.kind {
+            TokenKind::Underscore => {
+                self.skip()?;
+                Ok(MatchPattern::Wildcard)
+            }
+            TokenKind::Identifier(symbol) => match self.scope_map.resolve(&symbol) {
+                Some((Binding::Variant(variant_id), _)) => {
+                    let variant_id = *variant_id;
+                    let span = self.peek()?.span;
+                    self.require_feature(Feature::MatchPatterns, span)?;
+                    self.parse_variant_pattern(variant_id)
+                }
+                _ => Ok(MatchPattern::Expression(
+                    self.parse_expression(Precedence::None)?,
+                )),
+            },
+            _ => Ok(MatchPattern::Expression(
+                self.parse_expression(Precedence::None)?,
+            )),
+        }
+    }
+
+    /// Parse a variant pattern like `Circle(radius)` or `Empty`, which has to
+    /// bind every value the variant holds.
+    fn parse_variant_pattern(&mut self, variant_id: VariantId) -> Result<MatchPattern> {
         let name = self.identifier()?;
-        let symbol = name.symbol;
+        let mut bindings = vec![];
+        if self.eat(TokenKind::LParen)? {
+            while let TokenKind::Identifier(_) = self.peek()?.kind {
+                let name = self.identifier()?;
+                bindings.push(self.ctx.pattern_bindings.alloc(PatternBinding { name }));
+                if !self.eat(TokenKind::Comma)? {
+                    break;
+                }
+            }
+            self.expect(TokenKind::RParen)?;
+        }
+        let arity = self.ctx.variants[variant_id].arity();
+        if arity != bindings.len() {
+            use diagnostics::error::variant_pattern_arity;
+            let span = name.span.merge(self.span);
+            return variant_pattern_arity(span, name.symbol, arity, bindings.len());
+        }
+        Ok(MatchPattern::Variant {
+            variant: variant_id,
+            bindings,
+        })
+    }
 
-        let type_parameters = self.type_parameters()?;
-        let parameters = self.parameters()?;
-        let (return_type, effect_type) = self.type_and_effect_annotation()?;
+    /// Resolve a name that isn't in scope to a builtin, or a global the
+    /// target defines. Globals that only other targets define are reported.
+    fn resolve_global(&mut self, symbol: Symbol, span: Span) -> Result<Option<Binding>> {
+        let name = symbol.to_string();
+        let builtin = Builtin::from_name(&name);
+        if builtin.is_some() || self.target.has_global(&name) {
+            let ctx = &mut self.ctx;
+            let global_id = *self.globals.entry(symbol).or_insert_with(|| {
+                ctx.globals.alloc(Global {
+                    name: Identifier { span, symbol },
+                    builtin,
+                })
+            });
+            return Ok(Some(Binding::Global(global_id)));
+        }
+        match Target::with_global(&name).first() {
+            Some(available_in) => {
+                use diagnostics::error::global_unavailable;
+                global_unavailable(span, symbol, self.target, available_in)
+            }
+            None => Ok(None),
+        }
+    }
 
-        let function = Function {
-            name,
-            is_async,
-            type_parameters,
-            parameters,
-            return_type,
-            effect_type,
-            body: None,
-        };
+    /// The span recorded for an expression we've already parsed.
+    fn span_of(&self, expression_id: ExpressionId) -> Span {
+        self.ctx.expression_span(expression_id).unwrap_or(self.span)
+    }
 
-        let function_id = self.ast_arena.functions.alloc(function);
+    /// The span of the last argument we've parsed, if there is one.
+    fn last_argument_span(&self, arguments: &[Argument]) -> Span {
+        match arguments.last() {
+            Some(argument) => {
+                let span = self.span_of(argument.value);
+                match argument.name {
+                    Some(name) => name.span.merge(span),
+                    None => span,
+                }
+            }
+            None => self.span,
+        }
+    }
 
-        self.scope_map
-            .define(symbol, Binding::Function(function_id));
+    /// The span covering two expressions we've already parsed.
+    fn span_between(&self, start: ExpressionId, end: ExpressionId) -> Span {
+        self.span_of(start).merge(self.span_of(end))
+    }
+
+    /// Parse an identifier
+    fn identifier(&mut self) -> Result<Identifier> {
+        let token = self.next()?;
+        match token.kind {
+            TokenKind::Identifier(symbol) => Ok(Identifier {
+                symbol,
+                span: token.span,
+            }),
+            kind if kind.is_keyword() => {
+                use diagnostics::error::reserved_keyword;
+                reserved_keyword(token.span, kind)
+            }
+            _ => {
+                use diagnostics::error::expected_identifier;
+                expected_identifier(token.span, token.kind)
+            }
+        }
+    }
+
+    // Allows identifiers that are keywords
+    fn identifier_loose(&mut self) -> Result<Identifier> {
+        let token = self.next()?;
+        match token.kind {
+            TokenKind::Identifier(symbol) => Ok(Identifier {
+                symbol,
+                span: token.span,
+            }),
+            TokenKind::Type => Ok(Identifier {
+                symbol: Symbol::intern("type"),
+                span: token.span,
+            }),
+            _ => {
+                use diagnostics::error::expected_identifier;
+                expected_identifier(token.span, token.kind)
+            }
+        }
+    }
 
-        self.reference_tracker.insert(function_id);
+    /// Consume a token if it matches the provided `kind`,
+    /// otherwise do nothing. Returns whether the token was consumed.
+    fn eat(&mut self, kind: TokenKind) -> Result<bool> {
+        if self.peek()? == &kind {
+            self.skip()?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
 
-        let body = self.block()?;
+    /// Skip the next token and do nothing with it.
+    fn skip(&mut self) -> Result<()> {
+        self.next()?;
+        Ok(())
+    }
 
-        self.ast_arena.functions.get_mut(function_id).unwrap().body = Some(body);
+    /// Consume the next token, if it has the provided `kind`.
+    /// If not, we throw an unexpected token error.
+    fn expect(&mut self, kind: TokenKind) -> Result<Token> {
+        let prev_span = self.last_span();
+        let token = self.next()?;
+        if token != kind {
+            use diagnostics::error::unexpected_token_error;
+            unexpected_token_error(token.span, prev_span, kind, token.kind)
+        } else {
+            Ok(token)
+        }
+    }
 
-        self.scope_map.pop();
-        self.is_async_context = prev_is_async_context;
+    /// The span of the last token consumed, if there's been one. Tokens
+    /// always have a span, so it's only empty at the start.
+    fn last_span(&self) -> Option<Span> {
+        Some(self.span).filter(|span| !span.is_empty())
+    }
 
-        Ok(function_id)
+    /// Run `parse` one level deeper, failing if it's nested too deeply.
+    fn nested<T>(&mut self, parse: impl FnOnce(&mut Self) -> Result<T>) -> Result<T> {
+        if self.depth == MAX_NESTING_DEPTH {
+            use diagnostics::error::nesting_too_deep;
+            let span = self.peek()?.span;
+            return nesting_too_deep(span);
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
     }
 
-    fn component(&mut self, is_async: bool) -> Result<Arc<Component>> {
-        self.is_component_context = true;
-        let prev_is_async_context = self.is_async_context;
-        self.is_async_context = is_async;
-        self.expect(TokenKind::Component)?;
-        self.scope_map.extend();
-        let name = self.identifier()?;
-        let symbol = name.symbol;
-        let type_parameters = self.type_parameters()?;
-        let parameters = self.parameters()?;
-        let (return_type, effect_type) = self.type_and_effect_annotation()?;
-        let body = self.block()?;
-        self.scope_map.pop();
-        self.is_async_context = prev_is_async_context;
-        let component = Component {
-            name,
-            is_async,
-            type_parameters,
-            parameters,
-            return_type,
-            effect_type,
-            body,
-        };
-        let component = Arc::new(component);
-        self.scope_map
-            .define(symbol, Binding::Component(component.clone()));
-        self.is_component_context = false;
-        Ok(component)
+    /// Whether the next token is the first one on its line. Newlines aren't
+    /// tokens, but they're where statements end.
+    fn at_line_start(&mut self) -> Result<bool> {
+        self.peek()?;
+        self.lexer.next_starts_line()
     }
 
-    fn enum_variant(&mut self) -> Result<Variant> {
-        let name = self.identifier()?;
-        let types = if self.eat(TokenKind::LParen)? {
-            let mut types = vec![];
-            loop {
-                if let TokenKind::Identifier(_) = self.peek()?.kind {
-                    let type_ = self.type_()?;
-                    types.push(type_);
-                    self.eat(TokenKind::Comma)?;
-                } else {
-                    break;
-                }
-            }
-            self.expect(TokenKind::RParen)?;
-            Some(types)
+    /// Look at the next token without consuming it
+    fn peek(&mut self) -> Result<&Token> {
+        let token_kind = &self.lexer.peek()?.kind;
+        // Ignore newlines when they are not considered significant
+        if token_kind == &TokenKind::Newline {
+            self.lexer.next_token()?;
+            self.peek()
         } else {
-            None
+            self.lexer.peek()
+        }
+    }
+
+    /// Consume the next token from the lexer
+    fn next(&mut self) -> Result<Token> {
+        let token = self.lexer.next_token()?;
+        // Ignore newlines when they are not considered significant
+        if token.is_newline() {
+            self.next()
+        } else {
+            self.span = token.span;
+            Ok(token)
+        }
+    }
+}
+
+//
+
+#[cfg(test)]
+mod tests {
+    use super::{hash_library_filenames, parse_source_for_fuzzing, CompiledModule, LibraryFile};
+
+    /// The message of the first diagnostic for `source`, if there is one.
+    fn first_error(source: &str) -> Option<String> {
+        match parse_source_for_fuzzing(source) {
+            Ok(_) => None,
+            Err(error) => Some(error.diagnostics()[0].message().to_string()),
+        }
+    }
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    #[test]
+    fn report_invalid_input_without_panicking() {
+        let deeply_nested = format!("fn a() {{ {}1{} }}", "(".repeat(1000), ")".repeat(1000));
+        let deeply_nested_templates = nested_templates(1000);
+        let inputs = [
+            "enum Color { Red(1) }",
+            "fn a(b: ) { b }",
+            "fn a() { 1(2) }",
+            "fn a(b: number) { a(c: b, 2) }",
+            "fn a() { (1 . 2) }",
+            "fn a() { = }",
+            "component A { return <B /> }",
+            "component A { return <p>",
+            "fn é() { ü",
+            &deeply_nested,
+            &deeply_nested_templates,
+        ];
+        for input in inputs.iter() {
+            assert!(parse_source_for_fuzzing(input).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn hash_library_filenames_with_imports() {
+        let hashed = |utils: &str| {
+            let modules: Vec<(PathBuf, Arc<CompiledModule>)> = ["main", "utils", "other"]
+                .iter()
+                .map(|name| {
+                    let module = CompiledModule {
+                        code: vec![],
+                        exports: vec![],
+                    };
+                    (PathBuf::from(format!("/lib/{}.ws", name)), Arc::new(module))
+                })
+                .collect();
+            let mut files: Vec<LibraryFile> = [
+                "import {double} from './utils.js';\nexport const a = double(1);\n",
+                utils,
+                "export const b = 2;\n",
+            ]
+            .iter()
+            .zip(["main", "utils", "other"].iter())
+            .map(|(contents, name)| LibraryFile {
+                module_name: name.to_string(),
+                filename: format!("{}.js", name),
+                contents: contents.to_string(),
+            })
+            .collect();
+            hash_library_filenames(&modules, &mut files);
+            files
         };
-        Ok(Variant { name, types })
+        let before = hashed("export function double(a) { return a * 2; }\n");
+        let after = hashed("export function double(a) { return a + a; }\n");
+        assert!(before[0].filename.starts_with("main.") && before[0].filename.ends_with(".js"));
+        assert!(before[0]
+            .contents
+            .contains(&format!(" from './{}';", before[1].filename)));
+        // Files change names along with the ones they import, and no others
+        assert_ne!(before[1].filename, after[1].filename);
+        assert_ne!(before[0].filename, after[0].filename);
+        assert_eq!(before[2].filename, after[2].filename);
     }
 
-    fn enum_(&mut self) -> Result<Arc<Enum>> {
-        use TokenKind::{LBrace, RBrace};
-        self.expect(TokenKind::Enum)?;
-        let name = self.identifier()?;
-        let symbol = name.symbol;
-        let type_parameters = self.type_parameters()?;
-        let mut variants = vec![];
-        self.expect(LBrace)?;
-        loop {
-            if let TokenKind::Identifier(_) = self.peek()?.kind {
-                let variant = self.enum_variant()?;
-                variants.push(variant);
-            } else {
-                break;
-            }
+    fn nested_templates(depth: usize) -> String {
+        format!(
+            "component A() {{ return ({}{}) }}",
+            "<div>".repeat(depth),
+            "</div>".repeat(depth)
+        )
+    }
+
+    #[test]
+    fn parse_nested_templates() {
+        assert!(parse_source_for_fuzzing(&nested_templates(64)).is_ok());
+        let error = parse_source_for_fuzzing(&nested_templates(65)).unwrap_err();
+        assert_eq!(
+            error.diagnostics()[0].message(),
+            "Template Nested Too Deeply"
+        );
+    }
+
+    #[test]
+    fn report_reserved_keywords() {
+        let inputs = [
+            "fn a() { let type = 1 }",
+            "fn match() {}",
+            "fn a(if: number) {}",
+            "component A(string: string) {}",
+            "enum A { B true }",
+        ];
+        for input in inputs.iter() {
+            let error = parse_source_for_fuzzing(input).unwrap_err();
+            assert_eq!(
+                error.diagnostics()[0].message(),
+                "Reserved Keyword",
+                "{}",
+                input
+            );
         }
-        self.expect(RBrace)?;
-        let enum_ = Enum {
-            name,
-            type_parameters,
-            variants,
+    }
+
+    #[test]
+    fn report_duplicate_names() {
+        let inputs = [
+            ("fn a(b: number, b: number) {}", "Duplicate Parameter"),
+            (
+                "component A(b: string, c: string, b: string) {}",
+                "Duplicate Parameter",
+            ),
+            (
+                "type A = (b: number, b: string) => number",
+                "Duplicate Parameter",
+            ),
+            ("enum A { B C B(number) }", "Duplicate Variant"),
+        ];
+        for (input, message) in inputs.iter() {
+            let error = parse_source_for_fuzzing(input).unwrap_err();
+            assert_eq!(error.diagnostics()[0].message(), *message, "{}", input);
+        }
+    }
+
+    #[test]
+    fn unexpected_token_spans() {
+        // The text of each label, primary label first
+        let labels = |source: &str| -> Vec<String> {
+            let error = parse_source_for_fuzzing(source).unwrap_err();
+            error.diagnostics()[0]
+                .ranges()
+                .into_iter()
+                .map(|range| source[range].to_string())
+                .collect()
         };
-        let enum_ = Arc::new(enum_);
-        self.scope_map.define(symbol, Binding::Enum(enum_.clone()));
-        Ok(enum_)
+        assert_eq!(labels("fn a(b: number { b }"), vec!["{", "number"]);
+        assert_eq!(labels("fn a() { 1 }\n} fn b() {}"), vec!["}", "}"]);
+        // Nothing comes before the first token
+        assert_eq!(labels("} fn b() {}"), vec!["}"]);
+        assert_eq!(labels("fn a() { let b = }"), vec!["}", "="]);
+        // The `<` of the element that's nested too deeply
+        let nested = format!("component A() {{ {} }}", "<div>".repeat(65));
+        let error = parse_source_for_fuzzing(&nested).unwrap_err();
+        let range = error.diagnostics()[0].primary_range().unwrap();
+        assert_eq!(&nested[range.clone()], "<");
+        assert_eq!(range.start, 16 + 64 * 5);
     }
 
-    fn struct_(&mut self) -> Result<Arc<Struct>> {
-        self.expect(TokenKind::Struct)?;
-        let name = self.identifier()?;
-        let symbol = name.symbol;
-        let type_parameters = self.type_parameters()?;
-        self.expect(TokenKind::LBrace)?;
-        let fields = self.struct_fields()?;
-        self.expect(TokenKind::RBrace)?;
-        let struct_ = Struct {
-            name,
-            type_parameters,
-            fields,
+    #[test]
+    fn recover_from_template_errors() {
+        let source = "component A(a: string) {
+  return (
+    <div>
+      <p>{a</p>
+      <a href=5 class=\"link\">{a +}</a>
+    </div>
+  )
+}
+fn b() { let c = }";
+        let error = parse_source_for_fuzzing(source).unwrap_err();
+        let messages: Vec<&str> = error
+            .diagnostics()
+            .iter()
+            .map(|diagnostic| diagnostic.message())
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                "Unclosed Template Expression",
+                "Invalid Attribute Value",
+                "Unexpected token for expression",
+                "Unexpected token for expression",
+            ]
+        );
+    }
+
+    #[test]
+    fn void_elements() {
+        let message = |template: &str| {
+            let source = format!(
+                "component A {{\n return {}\n}}\nfn b() {{ return 1 }}",
+                template
+            );
+            first_error(&source)
         };
-        let struct_ = Arc::new(struct_);
-        self.type_scope_map
-            .define(symbol, TypeBinding::Struct(struct_.clone()));
-        Ok(struct_)
+        // Void elements don't need to be closed
+        assert_eq!(message("<p>Hello<br>world</p>"), None);
+        assert_eq!(message("<div><img src=\"a.png\"><hr></div>"), None);
+        assert_eq!(message("<input>"), None);
+        // But they can be
+        assert_eq!(message("<p>Hello<br />world<br></br></p>"), None);
+        assert_eq!(message("<p><br> </br></p>"), None);
+        assert_eq!(message("<br></br>"), None);
+        assert_eq!(
+            message("<p><img>text</img></p>").as_deref(),
+            Some("Children In Void Element")
+        );
+        assert_eq!(
+            message("<p>text</img></p>").as_deref(),
+            Some("Mismatched Close Tag")
+        );
     }
 
-    fn const_(&mut self) -> Result<Arc<Const>> {
-        self.expect(TokenKind::Const)?;
-        let name = self.identifier()?;
-        let symbol = name.symbol;
-        let type_ = if self.eat(TokenKind::Colon)? {
-            Some(self.type_()?)
-        } else {
+    #[test]
+    fn close_tags() {
+        let diagnostic = |template: &str| {
+            let source = format!("component A {{\n return {}", template);
+            let error = parse_source_for_fuzzing(&source).unwrap_err();
+            let diagnostic = &error.diagnostics()[0];
+            (
+                diagnostic.message().to_string(),
+                diagnostic.primary_message().unwrap().to_string(),
+            )
+        };
+        assert_eq!(
+            diagnostic("<div><p></div>\n}"),
+            (
+                "Mismatched Close Tag".to_string(),
+                "But it's closed with 'div' instead of 'p'".to_string()
+            )
+        );
+        assert_eq!(
+            diagnostic("<div>"),
+            (
+                "Missing Close Tag".to_string(),
+                "'div' is opened here".to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn function_types() {
+        use super::ParserImpl;
+        use syntax::ast::{AstArena, Type};
+        let source = "#feature(effects)
+effect Log
+effect Fetch
+fn a(f: (value: number, string) => number + Log + Fetch, g: (number, label: string) => number + Fetch + Log) { 1 }
+fn b(h: (number) => number) { 1 }";
+        let mut arena = AstArena::default();
+        ParserImpl::new(source, &mut arena).parse_module().unwrap();
+        let types: Vec<Type> = arena
+            .parameters
+            .iter()
+            .map(|(_, parameter)| parameter.type_.clone().unwrap())
+            .collect();
+        assert_eq!(
+            types[0].to_string(),
+            "fn(value: number, string) -> number + Log + Fetch"
+        );
+        // Parameter names and the order of effects don't matter
+        assert_eq!(types[0], types[1]);
+        assert_ne!(types[0], types[2]);
+        assert!(
+            ParserImpl::new("fn c(f: () => number + Log) { 1 }", &mut arena)
+                .parse_module()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn type_aliases() {
+        use super::ParserImpl;
+        use syntax::ast::{AstArena, Type};
+        let source = "type Score = number
+type Handler = (score: Score) => Score
+fn apply(f: Handler, g: (number) => number) { 1 }";
+        let mut arena = AstArena::default();
+        ParserImpl::new(source, &mut arena).parse_module().unwrap();
+        let types: Vec<Type> = arena
+            .parameters
+            .iter()
+            .map(|(_, parameter)| parameter.type_.clone().unwrap())
+            .collect();
+        assert_eq!(
+            types[0].to_string(),
+            "Handler (fn(score: Score (number)) -> Score (number))"
+        );
+        // Aliases are the same type as what they expand to
+        assert_eq!(types[0], types[1]);
+
+        let error = |source: &str| {
+            let mut arena = AstArena::default();
+            let error = ParserImpl::new(source, &mut arena)
+                .parse_module()
+                .unwrap_err();
+            format!("{:?}", error)
+        };
+        assert!(error("type Loop = (Loop) => number").contains("Recursive Type Alias"));
+        assert!(error("type Score = number\nfn a(s: Scor) { 1 }").contains("Unknown Type"));
+    }
+
+    #[test]
+    fn array_and_optional_types() {
+        use super::ParserImpl;
+        use syntax::ast::{AstArena, Type};
+        let source = "fn a(f: ([number]) => number?, g: [(number) => bool]?) { 1 }
+fn b() { [1, 2 * 3, 4] }";
+        let mut arena = AstArena::default();
+        ParserImpl::new(source, &mut arena).parse_module().unwrap();
+        let types: Vec<String> = arena
+            .parameters
+            .iter()
+            .map(|(_, parameter)| parameter.type_.clone().unwrap().to_string())
+            .collect();
+        assert_eq!(
+            types,
+            vec!["fn([number]) -> number?", "[fn(number) -> boolean]?"]
+        );
+        let (array, _) = arena.expressions.iter().last().unwrap();
+        assert_eq!(
+            arena.expression_type(array),
+            Some(Type::Array(Type::Number.into()))
+        );
+
+        let mut arena = AstArena::default();
+        let error = ParserImpl::new("fn c() { [1, \"two\"] }", &mut arena)
+            .parse_module()
+            .unwrap_err();
+        assert!(format!("{:?}", error).contains("Mismatched Array Elements"));
+    }
+
+    #[test]
+    fn import_aliases() {
+        use super::ParserImpl;
+        use syntax::ast::AstArena;
+        let source = "import utils.{helper as h, other}
+import long.module.name as m
+fn a() { return h(other(m)) }";
+        let mut arena = AstArena::default();
+        ParserImpl::new(source, &mut arena).parse_module().unwrap();
+        let specifiers: Vec<(Option<String>, String)> = arena
+            .import_specifiers
+            .iter()
+            .map(|(_, specifier)| {
+                let name = specifier.name.map(|name| name.symbol.to_string());
+                (name, specifier.local.symbol.to_string())
+            })
+            .collect();
+        assert_eq!(
+            specifiers,
+            vec![
+                (Some("helper".to_string()), "h".to_string()),
+                (Some("other".to_string()), "other".to_string()),
+                (None, "m".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn state_and_templates_only_in_components() {
+        let message = |source: &str| {
+            let error = parse_source_for_fuzzing(source).unwrap_err();
+            error.diagnostics()[0].message().to_string()
+        };
+        assert_eq!(message("fn a() { state b = 1 }"), "State Outside Component");
+        assert_eq!(
+            message("component A { fn b() { state c = 1 } }"),
+            "State Outside Component"
+        );
+        assert_eq!(
+            message("fn a() { return <p /> }"),
+            "Template Outside Component"
+        );
+        assert_eq!(
+            message("component A { fn b() { return <p /> } }"),
+            "Template Outside Component"
+        );
+        // Only top-level functions can be made into components
+        let suggestion = |source: &str| {
+            let error = parse_source_for_fuzzing(source).unwrap_err();
+            format!("{:?}", error.diagnostics()[0]).contains("'component a'")
+        };
+        assert!(suggestion("fn a() { return <p /> }"));
+        assert!(!suggestion("component a { fn b() { return <p /> } }"));
+        assert!(parse_source_for_fuzzing(
+            "component A { state b = 1\n if b > 0 { state c = 2 }\n return <p>{b}</p> }"
+        )
+        .is_ok());
+    }
+
+    #[test]
+    fn globals_depend_on_target() {
+        use super::ParserImpl;
+        use common::target::Target;
+        use syntax::ast::AstArena;
+        let parse = |source: &str, target: Target| {
+            let mut arena = AstArena::default();
+            let result = ParserImpl::new(source, &mut arena)
+                .with_target(target)
+                .parse_module();
+            result.map(|_| arena.globals.len()).map_err(|error| {
+                let diagnostic = &error.diagnostics()[0];
+                (
+                    diagnostic.message().to_string(),
+                    format!("{:?}", diagnostic),
+                )
+            })
+        };
+        // References to the same global share it
+        let source = "fn a() { console(1)\n return console(2) }";
+        assert_eq!(parse(source, Target::Node), Ok(1));
+        assert_eq!(parse("fn a() { return document }", Target::Browser), Ok(1));
+        let (message, diagnostic) = parse("fn a() { return document }", Target::Node).unwrap_err();
+        assert_eq!(message, "Global Not Available");
+        assert!(diagnostic.contains("'--target browser'"));
+        // Bindings shadow globals
+        assert_eq!(
+            parse(
+                "fn a() { let document = 1\n return document }",
+                Target::Node
+            ),
+            Ok(0)
+        );
+    }
+
+    #[test]
+    fn template_reports() {
+        use super::ParserImpl;
+        use codegen::{template_report, StateBindings};
+        use syntax::ast::AstArena;
+        let source = r#"
+component Label(text: string) {
+  return <span>{text}</span>
+}
+
+pub component App {
+  state count = 0
+  state active = false
+  return (
+    <div class={active}>
+      <h1>Count</h1>
+      <p>{count}</p>
+      <Label text={count} />
+    </div>
+  )
+}"#;
+        let mut arena = AstArena::default();
+        let module_id = ParserImpl::new(source, &mut arena).parse_module().unwrap();
+        let reports = template_report(&arena, module_id, false, |_| true).unwrap();
+        let app = &reports[1];
+        assert_eq!(app.name, "App");
+        // `<h1>`, its text and `<p>` are static
+        assert_eq!((app.static_nodes, app.dynamic_nodes), (3, 3));
+        assert_eq!(
+            app.state_bindings,
+            vec![
+                StateBindings {
+                    state: "active".to_string(),
+                    bindings: vec!["`class` on <div>".to_string()],
+                },
+                StateBindings {
+                    state: "count".to_string(),
+                    bindings: vec!["text in <p>".to_string(), "`text` of <Label>".to_string()],
+                },
+            ]
+        );
+        assert_eq!(app.update_cost(), 3);
+    }
+
+    #[test]
+    fn reactivity_graphs() {
+        use super::ParserImpl;
+        use codegen::{reactivity_graph, ReactiveBinding};
+        use syntax::ast::AstArena;
+        let source = r#"
+fn format(value: number, unit: string): string {
+  return "{value}{unit}"
+}
+
+pub component App {
+  state count = 0
+  state step = 1
+  return (
+    <div class={count}>
+      <h1>Count</h1>
+      <p>{format(value: count + step, unit: "px")}</p>
+    </div>
+  )
+}"#;
+        let mut arena = AstArena::default();
+        let module_id = ParserImpl::new(source, &mut arena).parse_module().unwrap();
+        let graphs = reactivity_graph(&arena, module_id, false, |_| true).unwrap();
+        assert_eq!(graphs[0].component, "App");
+        assert_eq!(graphs[0].states, vec!["count", "step"]);
+        assert_eq!(
+            graphs[0].bindings,
+            vec![
+                ReactiveBinding {
+                    expression: "count".to_string(),
+                    states: vec![0],
+                    target: "`class` on <div>".to_string(),
+                },
+                ReactiveBinding {
+                    expression: "format(value: count + step, unit: \"px\")".to_string(),
+                    states: vec![0, 1],
+                    target: "text in <p>".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn log_format_arguments() {
+        assert_eq!(
+            first_error("fn a(b: number) { log(\"{} and {}\", b, b) }"),
             None
+        );
+        assert_eq!(first_error("fn a(b: number) { print(b, b) }"), None);
+        // Format strings without placeholders are followed by the values
+        assert_eq!(first_error("fn a(b: number) { log(\"{{b}}\", b) }"), None);
+        assert_eq!(
+            first_error("fn a(b: number) { log(\"{} and {}\", b) }"),
+            Some("Wrong Number Of Log Arguments".to_string())
+        );
+        assert_eq!(
+            first_error("fn a(b: number) { log(\"{b}\", b) }"),
+            Some("Invalid Log Format".to_string())
+        );
+        assert_eq!(
+            first_error("fn a(b: number) { log(b:) }"),
+            Some("Named Log Argument".to_string())
+        );
+    }
+
+    #[test]
+    fn enums_match_and_await() {
+        let shape = "enum Shape { Circle(number) Empty }\n";
+        let area = |cases: &str| format!("{}fn a(s) {{ return match s {{ {} }} }}", shape, cases);
+        assert_eq!(first_error(&area("Circle(r) => r Empty => 0")), None);
+        assert_eq!(first_error(&area("Empty => 0 _ => 1")), None);
+        assert_eq!(
+            first_error(&area("Circle(r) => r")),
+            Some("Non-exhaustive Match".to_string())
+        );
+        assert_eq!(
+            first_error(&area("Circle => 1 _ => 0")),
+            Some("Invalid Variant Pattern".to_string())
+        );
+        assert_eq!(
+            first_error(&area("_ => 0 Empty => 1")),
+            Some("Unreachable Match Pattern".to_string())
+        );
+        assert_eq!(
+            first_error(&format!("{}fn a() {{ return Circle() }}", shape)),
+            Some("Invalid Variant".to_string())
+        );
+        assert_eq!(first_error("async fn a(b) { return await b }"), None);
+        assert_eq!(
+            first_error("fn a(b) { return await b }"),
+            Some("Invalid Await".to_string())
+        );
+        assert_eq!(
+            first_error("async fn a(b) { fn c() { return await b } }"),
+            Some("Invalid Await".to_string())
+        );
+    }
+
+    #[test]
+    fn enum_types() {
+        use super::ParserImpl;
+        use syntax::ast::{AstArena, Statement, Type};
+        let color = "enum Color { Red, Green }\n";
+        assert_eq!(
+            first_error(&format!("{}pub fn f(c: Color) {{ return c }}", color)),
+            None
+        );
+        assert_eq!(
+            first_error(&format!(
+                "{}fn f(c: [Color]?): Color {{ return Red }}",
+                color
+            )),
+            None
+        );
+        // Variants can hold values of the enum they're in
+        assert_eq!(first_error("enum List { Cons(number, List) Nil }"), None);
+        assert_eq!(
+            first_error(&format!("{}fn f(c: Colour) {{ return c }}", color)),
+            Some("Unknown Type".to_string())
+        );
+
+        let mut arena = AstArena::default();
+        let source = format!("{}fn f(c: Color) {{ return Green }}", color);
+        ParserImpl::new(&source, &mut arena).parse_module().unwrap();
+        let (_, function) = arena.functions.iter().next().unwrap();
+        let function = function.borrow();
+        let parameter = function.parameters.as_ref().unwrap()[0];
+        let parameter = arena.parameters[parameter].type_.clone();
+        assert_eq!(
+            parameter.as_ref().map(Type::to_string).as_deref(),
+            Some("Color")
+        );
+        let body = arena.blocks.get(function.body.unwrap()).unwrap();
+        let returned = match arena.statements.get(body.statements[0]).unwrap() {
+            Statement::Return(value) => *value,
+            _ => unreachable!(),
         };
-        self.expect(TokenKind::Equals)?;
-        let value = self.expression(Precedence::None)?;
-        let const_ = Arc::new(Const { name, type_, value });
-        self.scope_map
-            .define(symbol, Binding::Const(const_.clone()));
-        Ok(const_)
+        assert_eq!(arena.expression_type(returned), parameter);
     }
 
-    fn type_def(&mut self) -> Result<Arc<TypeDef>> {
-        debug!("type_def");
-        self.expect(TokenKind::Type)?;
-        let span = self.span;
-        let name = self.identifier()?;
-        debug!("type_def {:?}", name);
-        self.expect(TokenKind::Equals)?;
-        let type_ = self.type_()?;
-        let span = span.merge(self.span);
-        let type_def = Arc::new(TypeDef { name, type_, span });
-        Ok(type_def)
+    #[test]
+    fn statements_end_at_line_breaks() {
+        assert_eq!(first_error("fn a() { let b = 1\n let c = b }"), None);
+        assert_eq!(
+            first_error("fn a() { let b = 1 let c = b }"),
+            Some("Missing Line Break".to_string())
+        );
+        // Operators continue an expression on the next line
+        assert_eq!(first_error("fn a(b: number) { return b\n + 1 }"), None);
+        // But a template there is the next statement
+        assert_eq!(
+            first_error("component A(b: bool) {\n if b {\n return <p>Hi</p>\n }\n <p>Bye</p>\n}"),
+            None
+        );
+        assert_eq!(
+            first_error("component A(b: string) { let c = b\n <p>{c}</p> }"),
+            None
+        );
     }
 
-    fn effect_def(&mut self) -> Result<Arc<EffectDef>> {
-        debug!("effect_def");
-        self.expect(TokenKind::Effect)?;
-        let span = self.span;
-        let name = self.identifier()?;
-        let symbol = name.symbol;
-        debug!("effect_def {:?}", name);
-        let effect_def = Arc::new(EffectDef { name, span });
-        self.type_scope_map
-            .define(symbol, TypeBinding::Effect(effect_def.clone()));
-        Ok(effect_def)
+    #[test]
+    fn semicolons_end_statements() {
+        assert_eq!(first_error("fn a() { let b = 1; let c = b; }"), None);
+        assert_eq!(first_error("fn a() { let b = 1;\n return b; };"), None);
     }
 
-    fn struct_fields(&mut self) -> Result<Vec<StructField>> {
-        let mut fields = vec![];
-        loop {
-            if let TokenKind::Identifier(_) = self.peek()?.kind {
-                let name = self.identifier()?;
-                self.expect(TokenKind::Colon)?;
-                let type_ = self.type_()?;
-                let field = StructField { name, type_ };
-                fields.push(field);
-            } else {
-                break;
-            }
+    #[test]
+    fn trailing_commas() {
+        let inputs = [
+            // Parameters and arguments
+            "fn a(b: number, c: number,) { return a(b, c,) }",
+            "fn a(b: number, c: number) { return a(b: b, c:,) }",
+            // Arrays
+            "fn a() { return [1, 2,] }",
+            // Enum variants, the values they hold, and patterns for them
+            "enum Shape { Circle(number, number,), Empty, }",
+            "enum Shape { Circle(number, number) Empty }\n\
+             fn a() { return Circle(1, 2,) }",
+            "enum Shape { Circle(number, number) Empty }\n\
+             fn a(s) { return match s { Circle(r, q,) => r, Empty => 0, } }",
+            // Imports
+            "import a.{b, c as d,}",
+            // Function types
+            "fn a(b: (number, c: string,) => number) { return b(1, \"c\") }",
+        ];
+        for input in inputs.iter() {
+            assert_eq!(first_error(input), None, "{}", input);
         }
-        Ok(fields)
+    }
+
+    #[test]
+    fn event_handler_types() {
+        let message = |handler: &str, attribute: &str| {
+            let source = format!(
+                "component A {{\n {}\n return <input {}={{handle}} />\n}}",
+                handler, attribute
+            );
+            first_error(&source)
+        };
+        let incompatible = Some("Incompatible Event Handler".to_string());
+        assert_eq!(message("fn handle() {}", "onClick"), None);
+        assert_eq!(message("fn handle(e) {}", "onClick"), None);
+        assert_eq!(message("fn handle(e: Event) {}", "onKeyDown"), None);
+        assert_eq!(message("fn handle(e: KeyboardEvent) {}", "onKeyDown"), None);
+        assert_eq!(message("fn handle(e: FocusEvent) {}", "onBlur"), None);
+        assert_eq!(message("fn handle(e: MouseEvent) {}", "onclick"), None);
+        assert_eq!(
+            message("fn handle(e: KeyboardEvent) {}", "onFocus"),
+            incompatible
+        );
+        assert_eq!(message("fn handle(e: number) {}", "onClick"), incompatible);
+        assert_eq!(message("fn handle(e, f) {}", "onClick"), incompatible);
+        // Only events that are known have a more specific type than `Event`
+        assert_eq!(
+            message("fn handle(e: MouseEvent) {}", "onSwipe"),
+            incompatible
+        );
+
+        let source =
+            "component A {\n fn handle(e: number) {}\n return <input onSwipe={handle} />\n}";
+        let error = parse_source_for_fuzzing(source).unwrap_err();
+        let diagnostic = &error.diagnostics()[0];
+        assert_eq!(
+            diagnostic.primary_message(),
+            Some("This handler takes a number, but 'swipe' events are an Event")
+        );
+        assert!(diagnostic
+            .to_json("")
+            .contains("Handlers can take an Event, or nothing at all"));
+
+        // Props of components aren't DOM events
+        let source = "component Picker {\n return <p>pick</p>\n}\ncomponent A {\n fn choose(value: number) {}\n return <Picker onPick={choose} />\n}";
+        assert!(parse_source_for_fuzzing(source).is_ok());
+    }
+
+    #[test]
+    fn assign_only_to_mutable_bindings() {
+        // The assignment target, then where the binding was defined
+        let labels = |source: &str| -> Vec<String> {
+            let error = parse_source_for_fuzzing(source).unwrap_err();
+            let diagnostic = &error.diagnostics()[0];
+            assert_eq!(diagnostic.message(), "Assignment To Immutable Binding");
+            diagnostic
+                .ranges()
+                .into_iter()
+                .map(|range| source[range].to_string())
+                .collect()
+        };
+        assert_eq!(labels("fn a() { let b = 1\n b = 2 }"), vec!["b", "b"]);
+        assert_eq!(labels("fn a() { let b = 1\n b += 2 }"), vec!["b", "b"]);
+        assert_eq!(labels("const B = 1\nfn a() { B = 2 }"), vec!["B", "B"]);
+        assert_eq!(labels("fn a(b: number) { b = 2 }"), vec!["b", "b"]);
+        assert_eq!(labels("fn a() { a = 2 }"), vec!["a", "a"]);
+        // The secondary label points at the definition, not another assignment
+        let source = "fn a() { let b = 1\n b = 2 }";
+        let error = parse_source_for_fuzzing(source).unwrap_err();
+        assert_eq!(error.diagnostics()[0].ranges()[1], 13..14);
+        assert!(parse_source_for_fuzzing("fn a() { let mut b = 1\n b = 2\n b += 1 }").is_ok());
+        assert!(parse_source_for_fuzzing("component A { state b = 1\n fn c { b = 2 } }").is_ok());
     }
 }
@@ -0,0 +1,196 @@
+//! Ranges of a module's source that editors work with, like the parts that
+//! can be folded away, found from the spans the parser records.
+use crate::parser::ParserImpl;
+use diagnostics::result::Result;
+use std::ops::Range;
+use syntax::arena_pool::with_arena;
+use syntax::ast::*;
+use syntax::Span;
+
+/// What a folding range covers, so editors can fold some kinds by default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FoldKind {
+    /// A group of imports.
+    Imports,
+    /// A function or component body, or a template.
+    Region,
+}
+
+/// A range of the source an editor can fold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fold {
+    pub range: Range<usize>,
+    pub kind: FoldKind,
+}
+
+/// The ranges in `source` that can be folded: function and component
+/// bodies, templates, and runs of imports. Ranges on a single line are left
+/// out, since there's nothing to fold.
+pub fn folding_ranges(source: &str) -> Result<Vec<Fold>> {
+    with_arena(|arena| {
+        let module_id = ParserImpl::new(source, arena).parse_module()?;
+        let mut folds = vec![];
+        let mut region = |start: Span, end: Option<Span>| {
+            if let Some(end) = end {
+                folds.push(Fold {
+                    range: start.merge(end).into(),
+                    kind: FoldKind::Region,
+                });
+            }
+        };
+        for (_, function) in arena.functions.iter() {
+            let function = function.borrow();
+            let body = function.body.and_then(|body| arena.block_spans.get(&body));
+            region(function.name.span, body.copied());
+        }
+        for (_, component) in arena.components.iter() {
+            let component = component.borrow();
+            let body = component.body.and_then(|body| arena.block_spans.get(&body));
+            region(component.name.span, body.copied());
+        }
+        for (expression_id, expression) in arena.expressions.iter() {
+            if let Expression::Template(_) = *expression.borrow() {
+                if let Some(span) = arena.expression_span(expression_id) {
+                    region(span, Some(span));
+                }
+            }
+        }
+        // Imports are folded together when nothing else comes between them
+        let mut imports: Option<Span> = None;
+        for definition in &arena.modules[module_id].definitions {
+            match definition.kind {
+                DefinitionKind::Import(import_id) => {
+                    let import = &arena.imports[import_id];
+                    let mut span = import.path[0].span.merge(import.path.last().unwrap().span);
+                    for specifier_id in &import.specifiers {
+                        span = span.merge(arena.import_specifiers[*specifier_id].local.span);
+                    }
+                    imports = Some(imports.map_or(span, |imports| imports.merge(span)));
+                }
+                _ => folds.extend(imports.take().map(|span| import_fold(source, span))),
+            }
+        }
+        folds.extend(imports.map(|span| import_fold(source, span)));
+        folds.retain(|fold| {
+            source[fold.range.start..fold.range.end.min(source.len())].contains('\n')
+        });
+        folds.sort_by_key(|fold| fold.range.start);
+        Ok(folds)
+    })
+}
+
+/// Imports fold to the end of the last one's line, past the braces around
+/// its specifiers.
+fn import_fold(source: &str, span: Span) -> Fold {
+    let Range { start, end } = span.into();
+    let end = source[end..]
+        .find('\n')
+        .map_or(source.len(), |line| end + line);
+    Fold {
+        range: start..end,
+        kind: FoldKind::Imports,
+    }
+}
+
+/// The ranges around `offset` in `source` that a selection can grow to,
+/// from the innermost outwards: the expressions it's in, then the blocks,
+/// then the definition.
+pub fn selection_ranges(source: &str, offset: usize) -> Result<Vec<Range<usize>>> {
+    with_arena(|arena| {
+        ParserImpl::new(source, arena).parse_module()?;
+        let mut spans: Vec<Span> = arena
+            .expression_spans
+            .values()
+            .chain(arena.block_spans.values())
+            .copied()
+            .collect();
+        for (_, function) in arena.functions.iter() {
+            let function = function.borrow();
+            if let Some(body) = function.body.and_then(|body| arena.block_spans.get(&body)) {
+                spans.push(function.name.span.merge(*body));
+            }
+        }
+        for (_, component) in arena.components.iter() {
+            let component = component.borrow();
+            if let Some(body) = component.body.and_then(|body| arena.block_spans.get(&body)) {
+                spans.push(component.name.span.merge(*body));
+            }
+        }
+        let mut ranges: Vec<Range<usize>> = spans
+            .into_iter()
+            .map(Range::from)
+            .filter(|range| range.contains(&offset))
+            .collect();
+        // Spans nest, so the smaller a range is the further in it is
+        ranges.sort_by_key(|range| range.end - range.start);
+        ranges.dedup();
+        Ok(ranges)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{folding_ranges, selection_ranges, FoldKind};
+
+    const SOURCE: &str = "import a.{one}
+import b.{two}
+
+fn double(x: number): number {
+  return x * 2
+}
+
+pub component App {
+  return (
+    <p>
+      {double(1)}
+    </p>
+  )
+}
+";
+
+    #[test]
+    fn fold_bodies_templates_and_imports() {
+        let folds: Vec<(&str, FoldKind)> = folding_ranges(SOURCE)
+            .unwrap()
+            .into_iter()
+            .map(|fold| (&SOURCE[fold.range], fold.kind))
+            .collect();
+        assert_eq!(
+            folds,
+            vec![
+                ("a.{one}\nimport b.{two}", FoldKind::Imports),
+                (
+                    "double(x: number): number {\n  return x * 2\n}",
+                    FoldKind::Region
+                ),
+                (
+                    "App {\n  return (\n    <p>\n      {double(1)}\n    </p>\n  )\n}",
+                    FoldKind::Region
+                ),
+                (
+                    "(\n    <p>\n      {double(1)}\n    </p>\n  )",
+                    FoldKind::Region
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn select_outwards() {
+        let offset = SOURCE.find("x * 2").unwrap();
+        let ranges: Vec<&str> = selection_ranges(SOURCE, offset)
+            .unwrap()
+            .into_iter()
+            .map(|range| &SOURCE[range])
+            .collect();
+        assert_eq!(
+            ranges,
+            vec![
+                "x",
+                "x * 2",
+                "{\n  return x * 2\n}",
+                "double(x: number): number {\n  return x * 2\n}",
+            ]
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use common::control_flow_graph::{BlockIndex, ControlFlowMap, ControlFlowMapKey};
+use diagnostics::error::{unreachable_template, unrendered_template, Diagnostic};
+use syntax::ast::*;
+
+/// Checks that the templates in components can be rendered. A component
+/// renders the template it returns, and one it ends with counts as returned,
+/// so templates anywhere else in its body are thrown away. Templates after
+/// every path has returned can't be rendered either.
+pub struct RenderAnalysis<'a, V> {
+    arena: &'a AstArena,
+    cfg_map: &'a ControlFlowMap<FunctionId, ComponentId, StatementId, ExpressionId, V>,
+}
+
+impl<'a, V> RenderAnalysis<'a, V> {
+    pub fn new(
+        arena: &'a AstArena,
+        cfg_map: &'a ControlFlowMap<FunctionId, ComponentId, StatementId, ExpressionId, V>,
+    ) -> Self {
+        Self { arena, cfg_map }
+    }
+
+    /// Run the analysis, returning a warning for each template that's never
+    /// rendered.
+    pub fn analyze(&self) -> Vec<Diagnostic>
+    where
+        V: std::fmt::Debug + Clone,
+    {
+        let mut warnings = vec![];
+        for (key, cfg) in self.cfg_map {
+            if let ControlFlowMapKey::Function(_) = key {
+                continue;
+            }
+            let unreachable: Vec<StatementId> = cfg
+                .find_unreachable_blocks()
+                .into_iter()
+                .filter_map(|block_index| cfg.get_block(block_index))
+                .flat_map(|block| block.statements.iter().copied())
+                .collect();
+            for block_index in cfg.graph.node_indices() {
+                let block = match cfg.get_block(BlockIndex(block_index)) {
+                    Some(block) => block,
+                    None => continue,
+                };
+                for statement_id in &block.statements {
+                    let (expression_id, is_returned) = match self.arena.statements[*statement_id] {
+                        Statement::Return(expression_id) => (expression_id, true),
+                        Statement::Expression(expression_id) => (expression_id, false),
+                        _ => continue,
+                    };
+                    if !matches!(
+                        *self.arena.expressions[expression_id].borrow(),
+                        Expression::Template(_)
+                    ) {
+                        continue;
+                    }
+                    let span = self.arena.expression_span(expression_id).unwrap();
+                    if unreachable.contains(statement_id) {
+                        warnings.push(unreachable_template(span));
+                    } else if !is_returned {
+                        warnings.push(unrendered_template(span));
+                    }
+                }
+            }
+        }
+        warnings.sort_by_key(|warning| warning.primary_range().map(|range| range.start));
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RenderAnalysis;
+    use crate::control_flow::ControlFlowAnalysis;
+    use crate::test_utils::parse_module;
+    use syntax::visit::Visitor;
+
+    #[test]
+    fn report_templates_that_are_never_rendered() {
+        let source = "
+component Greeting(name: string, formal: bool) {
+  <h1>Welcome</h1>
+  if formal {
+    return <p>Good day {name}</p>
+  }
+  <p>Hi {name}</p>
+}
+
+component Farewell(name: string, formal: bool) {
+  if formal {
+    return <p>Goodbye {name}</p>
+  } else {
+    return <p>Bye {name}</p>
+  }
+  <p>See you {name}</p>
+}
+";
+        let (mut arena, module_id) = parse_module(source);
+        let analysis = ControlFlowAnalysis::new(&mut arena);
+        analysis.visit_module(module_id).unwrap();
+        let cfg_map = analysis.finish();
+        let warnings = RenderAnalysis::new(&arena, &cfg_map).analyze();
+        let warnings: Vec<(&str, &str)> = warnings
+            .iter()
+            .map(|warning| {
+                let range = warning.primary_range().unwrap();
+                (warning.message(), &source[range])
+            })
+            .collect();
+        assert_eq!(
+            warnings,
+            vec![
+                ("Unrendered Template", "<h1>Welcome</h1>"),
+                ("Unreachable Template", "<p>See you {name}</p>"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use common::control_flow_graph::{ControlFlowMap, ControlFlowMapKey, ControlFlowNode};
+use diagnostics::error::{missing_return, Diagnostic};
+use syntax::ast::*;
+
+/// Checks that functions with a return type return on every path. Each path
+/// that falls off the end of a function is reported at the last thing it
+/// runs, using the function's control flow graph.
+pub struct ReturnAnalysis<'a, V> {
+    arena: &'a AstArena,
+    cfg_map: &'a ControlFlowMap<FunctionId, ComponentId, StatementId, ExpressionId, V>,
+}
+
+impl<'a, V> ReturnAnalysis<'a, V> {
+    pub fn new(
+        arena: &'a AstArena,
+        cfg_map: &'a ControlFlowMap<FunctionId, ComponentId, StatementId, ExpressionId, V>,
+    ) -> Self {
+        Self { arena, cfg_map }
+    }
+
+    /// Run the analysis, returning an error for each path that's missing a return.
+    pub fn analyze(&self) -> Vec<Diagnostic>
+    where
+        V: std::fmt::Debug + Clone,
+    {
+        let mut errors = vec![];
+        for (key, cfg) in self.cfg_map {
+            let function_id = match key {
+                ControlFlowMapKey::Function(function_id) => *function_id,
+                ControlFlowMapKey::Component(_) => continue,
+            };
+            let function = self.arena.functions[function_id].borrow();
+            // Functions that don't return a value, or might not, can end anywhere
+            let return_type = match function.return_type.as_ref().map(Type::resolved) {
+                Some(Type::Void) | Some(Type::Optional(_)) | None => continue,
+                Some(return_type) => return_type,
+            };
+            for block_index in cfg.find_fall_through_blocks() {
+                // Where the path ends, falling back to the function's name
+                // when there's nothing more specific, like an empty body.
+                let span = match &cfg.graph[block_index.0] {
+                    ControlFlowNode::BasicBlock(block) => block
+                        .statements
+                        .last()
+                        .and_then(|statement_id| self.arena.statement_span(*statement_id)),
+                    ControlFlowNode::BranchCondition(condition)
+                    | ControlFlowNode::LoopCondition(condition) => {
+                        self.arena.expression_span(*condition)
+                    }
+                    ControlFlowNode::Entry | ControlFlowNode::Exit => None,
+                };
+                errors.push(missing_return(
+                    span.unwrap_or(function.name.span),
+                    function.name.symbol,
+                    return_type,
+                    function.name.span,
+                ));
+            }
+        }
+        errors.sort_by_key(|error| error.primary_range().map(|range| range.start));
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ReturnAnalysis;
+    use crate::control_flow::ControlFlowAnalysis;
+    use crate::parser::ParserImpl;
+    use syntax::ast::AstArena;
+    use syntax::visit::Visitor;
+
+    /// The lines each missing return is reported on.
+    fn missing_returns(source: &str) -> Vec<&str> {
+        let mut arena = AstArena::default();
+        let module_id = ParserImpl::new(source, &mut arena)
+            .parse_module()
+            .expect("source should parse");
+        let analysis = ControlFlowAnalysis::new(&mut arena);
+        analysis.visit_module(module_id).unwrap();
+        let cfg_map = analysis.finish();
+        ReturnAnalysis::new(&arena, &cfg_map)
+            .analyze()
+            .iter()
+            .map(|error| {
+                let start = error.primary_range().unwrap().start;
+                let line_start = source[..start].rfind('\n').map_or(0, |index| index + 1);
+                source[line_start..].lines().next().unwrap().trim()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn report_paths_without_return() {
+        let source = "
+fn sign(a: number): number {
+  if a > 0 {
+    return 1
+  } else if a < 0 {
+    let b = a * 2
+  }
+}
+
+fn count(a: number): number {
+  while a > 0 {
+    return a
+  }
+}
+
+fn always(a: number): number {
+  if a > 0 {
+    return 1
+  }
+  return 0
+}
+
+fn unannotated(a: number) {
+  let b = a
+}
+
+fn maybe(a: number): number? {
+  if a > 0 {
+    return a
+  }
+}
+";
+        assert_eq!(
+            missing_returns(source),
+            vec!["} else if a < 0 {", "let b = a * 2", "while a > 0 {"]
+        );
+    }
+}
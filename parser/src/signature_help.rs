@@ -0,0 +1,235 @@
+//! The signature of the function being called around a point in the
+//! source, for showing its parameters while writing the arguments.
+use crate::parser::ParserImpl;
+use common::symbol::Symbol;
+use diagnostics::result::Result;
+use std::ops::Range;
+use syntax::arena_pool::with_arena;
+use syntax::ast::*;
+
+/// A function's signature, like `double(x: number): number`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Signature {
+    pub label: String,
+    /// Where each parameter is in the label.
+    pub parameters: Vec<Range<usize>>,
+    /// The parameter the argument being written is for, if there is one.
+    pub active_parameter: Option<usize>,
+}
+
+/// A parameter's name, if it has one, and its type, if it's declared.
+type SignatureParameter = (Option<Symbol>, Option<Type>);
+
+/// The signature of the function called by the innermost call whose
+/// parentheses `offset` is between, if it calls a function whose parameters
+/// are known.
+pub fn signature_help(source: &str, offset: usize) -> Result<Option<Signature>> {
+    with_arena(|arena| {
+        ParserImpl::new(source, arena).parse_module()?;
+        let call = arena
+            .expressions
+            .iter()
+            .filter_map(|(expression_id, expression)| {
+                let call: Range<usize> = arena.expression_span(expression_id)?.into();
+                match &*expression.borrow() {
+                    Expression::Call { callee, .. } => {
+                        let callee: Range<usize> = arena.expression_span(*callee)?.into();
+                        if callee.end < offset && offset < call.end {
+                            return Some((expression_id, call));
+                        }
+                        None
+                    }
+                    _ => None,
+                }
+            })
+            .min_by_key(|(_, call)| call.end - call.start)
+            .map(|(expression_id, _)| expression_id);
+        let call = match call {
+            Some(call) => arena.expressions[call].borrow(),
+            None => return Ok(None),
+        };
+        let (callee, arguments) = match &*call {
+            Expression::Call { callee, arguments } => (*callee, arguments),
+            _ => unreachable!(),
+        };
+        let (name, parameters, return_type) = match signature_of(arena, callee) {
+            Some(signature) => signature,
+            None => return Ok(None),
+        };
+        let mut label = format!("{}(", name);
+        let mut ranges = vec![];
+        for (index, (name, type_)) in parameters.iter().enumerate() {
+            if index > 0 {
+                label.push_str(", ");
+            }
+            let start = label.len();
+            match (name, type_) {
+                (Some(name), Some(type_)) => label.push_str(&format!("{}: {}", name, type_)),
+                (Some(name), None) => label.push_str(&format!("{}", name)),
+                (None, Some(type_)) => label.push_str(&format!("{}", type_)),
+                (None, None) => {}
+            }
+            ranges.push(start..label.len());
+        }
+        label.push(')');
+        if let Some(return_type) = return_type {
+            label.push_str(&format!(": {}", return_type));
+        }
+        Ok(Some(Signature {
+            label,
+            parameters: ranges,
+            active_parameter: active_parameter(arena, source, arguments, &parameters, offset),
+        }))
+    })
+}
+
+/// The name, parameters and return type of what `callee` refers to, when
+/// it's a function or has a function type.
+fn signature_of(
+    arena: &AstArena,
+    callee: ExpressionId,
+) -> Option<(Symbol, Vec<SignatureParameter>, Option<Type>)> {
+    let binding = match *arena.expressions[callee].borrow() {
+        Expression::Reference(binding) => binding,
+        _ => return None,
+    };
+    let name = binding.identifier(arena).symbol;
+    let function_id = match binding {
+        Binding::Function(function_id) => Some(function_id),
+        Binding::Let(statement_id) => match arena.statements.get(statement_id).unwrap() {
+            Statement::Let { value, .. } => match *arena.expressions[*value].borrow() {
+                Expression::Function(function_id) => Some(function_id),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    };
+    if let Some(function_id) = function_id {
+        let function = arena.functions[function_id].borrow();
+        let parameters = function
+            .parameters
+            .iter()
+            .flatten()
+            .map(|parameter_id| {
+                let parameter = &arena.parameters[*parameter_id];
+                (Some(parameter.name.symbol), parameter.type_.clone())
+            })
+            .collect();
+        return Some((name, parameters, function.return_type.clone()));
+    }
+    // Parameters with function types, like callbacks
+    if let Binding::Parameter(parameter_id) = binding {
+        let type_ = arena.parameters[parameter_id].type_.as_ref()?;
+        if let Type::Function {
+            parameters,
+            return_type,
+            ..
+        } = type_.resolved()
+        {
+            let parameters = parameters
+                .iter()
+                .map(|parameter| (parameter.name, Some(parameter.type_.clone())))
+                .collect();
+            return Some((name, parameters, Some((**return_type).clone())));
+        }
+    }
+    None
+}
+
+/// The parameter for the argument at `offset`. Named arguments are for the
+/// parameter with their name, and a new argument after named ones is for
+/// the first parameter that hasn't been named yet.
+fn active_parameter(
+    arena: &AstArena,
+    source: &str,
+    arguments: &[Argument],
+    parameters: &[SignatureParameter],
+    offset: usize,
+) -> Option<usize> {
+    // Arguments the cursor is past the comma after
+    let index = arguments
+        .iter()
+        .take_while(|argument| {
+            arena
+                .expression_span(argument.value)
+                .map(Range::from)
+                .is_some_and(|value| {
+                    value.end <= offset && source[value.end..offset].trim_start().starts_with(',')
+                })
+        })
+        .count();
+    let position_of = |name: Symbol| {
+        parameters
+            .iter()
+            .position(|(parameter, _)| *parameter == Some(name))
+    };
+    if let Some(name) = arguments.get(index).and_then(|argument| argument.name) {
+        return position_of(name.symbol);
+    }
+    if arguments.iter().any(|argument| argument.name.is_some()) {
+        return parameters.iter().position(|(parameter, _)| {
+            !arguments
+                .iter()
+                .any(|argument| argument.name.map(|name| name.symbol) == *parameter)
+        });
+    }
+    Some(index).filter(|index| *index < parameters.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::signature_help;
+
+    const SOURCE: &str = "fn add(first: number, second: number): number {
+  return first + second
+}
+
+fn apply(value: number, callback: (input: number) => number) {
+  return callback(value)
+}
+
+fn main() {
+  let first = 1
+  let second = 2
+  _ = add(1, add(2, 3))
+  _ = add(second: second, first: first)
+}
+";
+
+    fn active(text: &str, at: usize) -> (String, Option<usize>) {
+        let offset = SOURCE.find(text).unwrap() + at;
+        let signature = signature_help(SOURCE, offset).unwrap().unwrap();
+        (signature.label, signature.active_parameter)
+    }
+
+    #[test]
+    fn signatures_of_calls() {
+        let add = "add(first: number, second: number): number".to_string();
+        assert_eq!(active("add(1, add", 4), (add.clone(), Some(0)));
+        assert_eq!(active("add(1, add", 6), (add.clone(), Some(1)));
+        // The innermost call
+        assert_eq!(active("add(2, 3)", 7), (add.clone(), Some(1)));
+        assert_eq!(active("second: second", 0), (add.clone(), Some(1)));
+        assert_eq!(active("first: first", 3), (add, Some(0)));
+        assert_eq!(
+            active("callback(value)", 9),
+            ("callback(input: number): number".to_string(), Some(0))
+        );
+        // Outside of the parentheses
+        assert_eq!(
+            signature_help(SOURCE, SOURCE.find("add(1").unwrap()).unwrap(),
+            None
+        );
+
+        let signature = signature_help(SOURCE, SOURCE.find("3)").unwrap())
+            .unwrap()
+            .unwrap();
+        let parameters: Vec<&str> = signature
+            .parameters
+            .iter()
+            .map(|range| &signature.label[range.clone()])
+            .collect();
+        assert_eq!(parameters, vec!["first: number", "second: number"]);
+    }
+}
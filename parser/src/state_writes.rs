@@ -0,0 +1,210 @@
+use diagnostics::error::{escaping_state_write, Diagnostic, Error, Severity};
+use diagnostics::result::Result;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use syntax::ast::*;
+use syntax::events::handled_event;
+use syntax::visit::{walk_component, walk_expression, walk_function, walk_statement, Visitor};
+
+/// Checks where state is written to inside of components. State can be written
+/// in the component body or in event handlers, which are functions declared
+/// directly in the component, either on their own, bound with `let`, or as the
+/// value of an event attribute. Any other function, like a callback passed to
+/// another function or declared inside a handler, might run after the component
+/// is gone, so writing state from it is reported.
+pub struct StateWriteAnalysis<'a> {
+    arena: &'a AstArena,
+    severity: Severity,
+    in_component: RefCell<bool>,
+    // Functions declared directly in a component body or its templates
+    handlers: RefCell<HashSet<FunctionId>>,
+    // The functions we're currently inside of, innermost last
+    function_stack: RefCell<Vec<FunctionId>>,
+    warnings: RefCell<Vec<Diagnostic>>,
+}
+
+impl<'a> StateWriteAnalysis<'a> {
+    pub fn new(arena: &'a AstArena, severity: Severity) -> Self {
+        Self {
+            arena,
+            severity,
+            in_component: RefCell::new(false),
+            handlers: Default::default(),
+            function_stack: Default::default(),
+            warnings: Default::default(),
+        }
+    }
+
+    /// Run the analysis, returning any writes reported as warnings. If the
+    /// analysis is configured to report errors, the first one is returned.
+    pub fn analyze(self, module_id: ModuleId) -> Result<Vec<Diagnostic>> {
+        self.visit_module(module_id)?;
+        Ok(self.warnings.into_inner())
+    }
+
+    fn check_state_write(&self, binding: Binding, value: ExpressionId) -> Result<()> {
+        if !matches!(binding, Binding::State(_)) || !*self.in_component.borrow() {
+            return Ok(());
+        }
+        let function_stack = self.function_stack.borrow();
+        let function_id = match function_stack.as_slice() {
+            [] => return Ok(()),
+            [handler] if self.handlers.borrow().contains(handler) => return Ok(()),
+            [.., function_id] => *function_id,
+        };
+        let function = self.arena.functions[function_id].borrow();
+        let name = binding.identifier(self.arena);
+        let span = self.arena.expression_span(value).unwrap_or(name.span);
+        let diagnostic = escaping_state_write(span, name.symbol, function.name.span, self.severity);
+        match self.severity {
+            Severity::Error => Err(Error::Diagnostic(diagnostic)),
+            Severity::Warning => {
+                self.warnings.borrow_mut().push(diagnostic);
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether we're directly in a component body, outside of any function.
+    fn in_component_body(&self) -> bool {
+        *self.in_component.borrow() && self.function_stack.borrow().is_empty()
+    }
+
+    fn define_handler(&self, expression_id: ExpressionId) {
+        let expression = self.arena.expressions[expression_id].borrow();
+        if let Expression::Function(function_id) = *expression {
+            self.handlers.borrow_mut().insert(function_id);
+        }
+    }
+
+    /// Functions written as the value of an event attribute, like
+    /// `onClick={fn reset() { count = 0 }}`, are handlers too.
+    fn define_template_handlers(&self, template_id: TemplateId) {
+        let template = self.arena.templates[template_id].borrow();
+        for attribute in &template.open_tag.attributes {
+            if handled_event(&attribute.name.symbol.to_string()).is_some() {
+                self.define_handler(attribute.value);
+            }
+        }
+        for child in template.children.iter().flatten() {
+            if let TemplateChild::Template(template_id) = child {
+                self.define_template_handlers(*template_id);
+            }
+        }
+    }
+}
+
+impl<'a> Visitor for StateWriteAnalysis<'a> {
+    fn context(&self) -> &AstArena {
+        self.arena
+    }
+
+    fn visit_component(&self, component_id: ComponentId) -> Result<()> {
+        *self.in_component.borrow_mut() = true;
+        let result = walk_component(self, component_id);
+        *self.in_component.borrow_mut() = false;
+        result
+    }
+
+    fn visit_function(&self, function_id: FunctionId) -> Result<()> {
+        self.function_stack.borrow_mut().push(function_id);
+        let result = walk_function(self, function_id);
+        self.function_stack.borrow_mut().pop();
+        result
+    }
+
+    fn visit_statement(&self, statement_id: StatementId) -> Result<()> {
+        match self.arena.statements.get(statement_id).unwrap() {
+            Statement::Assignment { name, value } => self.check_state_write(*name, *value)?,
+            // A function declared as its own statement in the component
+            // body is a handler, and so is one that's bound to a name there
+            Statement::Expression(value) | Statement::Let { value, .. }
+                if self.in_component_body() =>
+            {
+                self.define_handler(*value)
+            }
+            _ => {}
+        }
+        walk_statement(self, statement_id)
+    }
+
+    fn visit_expression(&self, expression_id: ExpressionId) -> Result<()> {
+        if let Expression::Template(template_id) = *self.arena.expressions[expression_id].borrow() {
+            if self.in_component_body() {
+                self.define_template_handlers(template_id);
+            }
+        }
+        walk_expression(self, expression_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::StateWriteAnalysis;
+    use crate::test_utils::parse_module;
+    use diagnostics::error::Severity;
+
+    const SOURCE: &str = "
+fn later(callback: () => void) {
+  callback()
+}
+
+pub component App {
+  state count = 0
+  count = 1
+  fn increment() {
+    count = count + 1
+    fn reset() {
+      count = 0
+    }
+    later(reset)
+  }
+  let decrement = fn decrement() {
+    count = count - 1
+  }
+  later(fn tick() {
+    count = count + 2
+  })
+  return (
+    <div>
+      <button onClick={increment}>+</button>
+      <button onClick={decrement}>-</button>
+      <p><button onClick={fn clear() { count = 0 }}>Clear</button></p>
+    </div>
+  )
+}
+";
+
+    #[test]
+    fn report_writes_outside_of_handlers() {
+        let (arena, module_id) = parse_module(SOURCE);
+        let warnings = StateWriteAnalysis::new(&arena, Severity::Warning)
+            .analyze(module_id)
+            .unwrap();
+        // Handlers declared on their own, bound with `let`, or written in an
+        // event attribute can all write state
+        let warnings: Vec<(&str, &str)> = warnings
+            .iter()
+            .map(|warning| {
+                let ranges = warning.ranges();
+                (&SOURCE[ranges[0].clone()], &SOURCE[ranges[1].clone()])
+            })
+            .collect();
+        assert_eq!(warnings, vec![("0", "reset"), ("count + 2", "tick")]);
+    }
+
+    #[test]
+    fn report_writes_as_errors() {
+        let (arena, module_id) = parse_module(SOURCE);
+        let error = StateWriteAnalysis::new(&arena, Severity::Error)
+            .analyze(module_id)
+            .unwrap_err();
+        let diagnostics = error.diagnostics();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message(),
+            "State Written Outside Of Component"
+        );
+        assert_eq!(diagnostics[0].severity(), Severity::Error);
+    }
+}
@@ -1,12 +1,21 @@
 use crate::control_flow::constrct_cfg_from_block;
-use crate::parser_::ParserImpl;
+use crate::parser::ParserImpl;
 use common::control_flow_graph::ControlFlowGraph;
 use diagnostics::result::Result;
 use evaluate::Value;
 
 use std::cell::{RefCell};
-use syntax::ast_::*;
-use syntax::visit_::Visitor;
+use syntax::ast::*;
+use syntax::visit::Visitor;
+
+/// Parse `source` as a module, for tests of the analyses that run on one.
+pub fn parse_module(source: &str) -> (AstArena, ModuleId) {
+    let mut arena = AstArena::default();
+    let module_id = ParserImpl::new(source, &mut arena)
+        .parse_module()
+        .expect("source should parse");
+    (arena, module_id)
+}
 
 pub fn parse_cfg_from_statements(stmts: &str) -> String {
     let source = format!("fn test() {{ {} }}", stmts);
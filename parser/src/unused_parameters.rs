@@ -0,0 +1,107 @@
+use diagnostics::error::{unused_parameter, Diagnostic};
+use diagnostics::result::Result;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use syntax::ast::*;
+use syntax::visit::{walk_component, walk_expression, walk_function, Visitor};
+
+/// Finds function parameters and component props that are never used.
+/// Parameters named with a leading underscore, like `_event`, are unused on
+/// purpose and aren't reported.
+pub struct UnusedParameterAnalysis<'a> {
+    arena: &'a AstArena,
+    // The parameters of every function and component, in the order they're declared
+    parameters: RefCell<Vec<ParameterId>>,
+    used: RefCell<HashSet<ParameterId>>,
+}
+
+impl<'a> UnusedParameterAnalysis<'a> {
+    pub fn new(arena: &'a AstArena) -> Self {
+        Self {
+            arena,
+            parameters: Default::default(),
+            used: Default::default(),
+        }
+    }
+
+    /// Run the analysis, returning a warning for each unused parameter.
+    pub fn analyze(self, module_id: ModuleId) -> Result<Vec<Diagnostic>> {
+        self.visit_module(module_id)?;
+        let used = self.used.borrow();
+        let warnings = self
+            .parameters
+            .borrow()
+            .iter()
+            .filter(|parameter_id| !used.contains(parameter_id))
+            .map(|parameter_id| &self.arena.parameters[*parameter_id].name)
+            .filter(|name| !name.symbol.to_string().starts_with('_'))
+            .map(|name| unused_parameter(name.span, name.symbol))
+            .collect();
+        Ok(warnings)
+    }
+}
+
+impl<'a> Visitor for UnusedParameterAnalysis<'a> {
+    fn context(&self) -> &AstArena {
+        self.arena
+    }
+
+    fn visit_function(&self, function_id: FunctionId) -> Result<()> {
+        let function = self.arena.functions[function_id].borrow();
+        if let Some(parameters) = &function.parameters {
+            self.parameters.borrow_mut().extend(parameters);
+        }
+        walk_function(self, function_id)
+    }
+
+    fn visit_component(&self, component_id: ComponentId) -> Result<()> {
+        let component = self.arena.components[component_id].borrow();
+        if let Some(parameters) = &component.parameters {
+            self.parameters.borrow_mut().extend(parameters);
+        }
+        walk_component(self, component_id)
+    }
+
+    fn visit_expression(&self, expression_id: ExpressionId) -> Result<()> {
+        let expression = self.arena.expressions[expression_id].borrow();
+        if let Expression::Reference(Binding::Parameter(parameter_id)) = *expression {
+            self.used.borrow_mut().insert(parameter_id);
+        }
+        walk_expression(self, expression_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UnusedParameterAnalysis;
+    use crate::test_utils::parse_module;
+
+    #[test]
+    fn unused_parameters_and_props() {
+        let source = "
+fn add(a: number, b: number, c: number): number {
+  return a + b
+}
+
+fn handle(_event: string) {
+  log(\"clicked\")
+}
+
+pub component Greeting(name: string, greeting: string) {
+  return (
+    <p>Hello {name}</p>
+  )
+}
+";
+        let (arena, module_id) = parse_module(source);
+        let warnings: Vec<String> = UnusedParameterAnalysis::new(&arena)
+            .analyze(module_id)
+            .unwrap()
+            .iter()
+            .map(|warning| format!("{:?}", warning))
+            .collect();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("'c' is never used"));
+        assert!(warnings[1].contains("'greeting' is never used"));
+    }
+}
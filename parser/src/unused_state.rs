@@ -0,0 +1,124 @@
+use diagnostics::error::{state_never_read, state_never_written, Diagnostic};
+use diagnostics::result::Result;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use syntax::ast::*;
+use syntax::visit::{walk_expression, walk_statement, Visitor};
+
+/// Finds state that's never read, so writing it doesn't update anything, or
+/// that's never written, so it could be a plain `let`. Reading state only to
+/// write it back, like `count = count + 1`, doesn't count as reading it.
+pub struct UnusedStateAnalysis<'a> {
+    arena: &'a AstArena,
+    // The statement declaring each state, in the order they're declared
+    states: RefCell<Vec<StatementId>>,
+    reads: RefCell<HashSet<StatementId>>,
+    writes: RefCell<HashSet<StatementId>>,
+    // The state being assigned to while visiting the value assigned to it,
+    // innermost last
+    assigning: RefCell<Vec<StatementId>>,
+}
+
+impl<'a> UnusedStateAnalysis<'a> {
+    pub fn new(arena: &'a AstArena) -> Self {
+        Self {
+            arena,
+            states: Default::default(),
+            reads: Default::default(),
+            writes: Default::default(),
+            assigning: Default::default(),
+        }
+    }
+
+    /// Run the analysis, returning a warning for each state that's never
+    /// read or never written.
+    pub fn analyze(self, module_id: ModuleId) -> Result<Vec<Diagnostic>> {
+        self.visit_module(module_id)?;
+        let reads = self.reads.borrow();
+        let writes = self.writes.borrow();
+        let warnings = self
+            .states
+            .borrow()
+            .iter()
+            .filter_map(|statement_id| {
+                let name = Binding::State(*statement_id).identifier(self.arena);
+                if !reads.contains(statement_id) {
+                    Some(state_never_read(name.span, name.symbol))
+                } else if !writes.contains(statement_id) {
+                    Some(state_never_written(name.span, name.symbol))
+                } else {
+                    None
+                }
+            })
+            .collect();
+        Ok(warnings)
+    }
+}
+
+impl<'a> Visitor for UnusedStateAnalysis<'a> {
+    fn context(&self) -> &AstArena {
+        self.arena
+    }
+
+    fn visit_expression(&self, expression_id: ExpressionId) -> Result<()> {
+        let expression = self.arena.expressions[expression_id].borrow();
+        if let Expression::Reference(Binding::State(state_id)) = *expression {
+            if !self.assigning.borrow().contains(&state_id) {
+                self.reads.borrow_mut().insert(state_id);
+            }
+        }
+        walk_expression(self, expression_id)
+    }
+
+    fn visit_statement(&self, statement_id: StatementId) -> Result<()> {
+        match self.arena.statements.get(statement_id).unwrap() {
+            Statement::State(_) => self.states.borrow_mut().push(statement_id),
+            Statement::Assignment {
+                name: Binding::State(state_id),
+                value,
+            } => {
+                self.writes.borrow_mut().insert(*state_id);
+                self.assigning.borrow_mut().push(*state_id);
+                let result = self.visit_expression(*value);
+                self.assigning.borrow_mut().pop();
+                return result;
+            }
+            _ => {}
+        }
+        walk_statement(self, statement_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UnusedStateAnalysis;
+    use crate::test_utils::parse_module;
+
+    #[test]
+    fn unread_and_unwritten_state() {
+        let source = "
+pub component App {
+  state count = 0
+  state clicks = 0
+  state label = \"Count\"
+  fn increment() {
+    count = count + 1
+    clicks = clicks + 1
+  }
+  return (
+    <button onClick={increment}>{label} {count}</button>
+  )
+}
+";
+        let (arena, module_id) = parse_module(source);
+        let warnings: Vec<String> = UnusedStateAnalysis::new(&arena)
+            .analyze(module_id)
+            .unwrap()
+            .iter()
+            .map(|warning| format!("{:?}", warning))
+            .collect();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("'clicks' is never read"));
+        assert!(warnings[1].contains("'label' is never written to"));
+    }
+}
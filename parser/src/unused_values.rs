@@ -0,0 +1,107 @@
+use diagnostics::error::{unused_value, Diagnostic};
+use diagnostics::result::Result;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use syntax::ast::*;
+use syntax::visit::{walk_expression, walk_statement, Visitor};
+
+/// Finds expression statements whose value is thrown away, like calling a
+/// function that returns a number without using it. Only values whose type
+/// is known are reported, and `_ = value` discards one on purpose.
+pub struct UnusedValueAnalysis<'a> {
+    arena: &'a AstArena,
+    // The trailing statements of if-expression branches, which are the
+    // value of the if-expression rather than being thrown away
+    branch_values: RefCell<HashSet<StatementId>>,
+    warnings: RefCell<Vec<Diagnostic>>,
+}
+
+impl<'a> UnusedValueAnalysis<'a> {
+    pub fn new(arena: &'a AstArena) -> Self {
+        Self {
+            arena,
+            branch_values: Default::default(),
+            warnings: Default::default(),
+        }
+    }
+
+    pub fn analyze(self, module_id: ModuleId) -> Result<Vec<Diagnostic>> {
+        self.visit_module(module_id)?;
+        Ok(self.warnings.into_inner())
+    }
+}
+
+impl<'a> Visitor for UnusedValueAnalysis<'a> {
+    fn context(&self) -> &AstArena {
+        self.arena
+    }
+
+    fn visit_expression(&self, expression_id: ExpressionId) -> Result<()> {
+        let expression = self.arena.expressions[expression_id].borrow();
+        if let Expression::If {
+            then_branch,
+            else_branch,
+            ..
+        } = &*expression
+        {
+            for block_id in std::iter::once(then_branch).chain(else_branch) {
+                if let Some(statement_id) = self.arena.blocks[*block_id].statements.last() {
+                    self.branch_values.borrow_mut().insert(*statement_id);
+                }
+            }
+        }
+        walk_expression(self, expression_id)
+    }
+
+    fn visit_statement(&self, statement_id: StatementId) -> Result<()> {
+        if let Statement::Expression(expression_id) = self.arena.statements[statement_id] {
+            let type_ = match self.arena.expression_type(expression_id) {
+                Some(type_) if !self.branch_values.borrow().contains(&statement_id) => type_,
+                _ => return walk_statement(self, statement_id),
+            };
+            if type_ != Type::Void && type_ != Type::Never {
+                let span = self.arena.expression_span(expression_id).unwrap();
+                self.warnings.borrow_mut().push(unused_value(span, type_));
+            }
+        }
+        walk_statement(self, statement_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UnusedValueAnalysis;
+    use crate::test_utils::parse_module;
+
+    #[test]
+    fn unused_values() {
+        let source = "
+fn total(): number {
+  return 1
+}
+
+fn log_total(): void {
+  print(\"{}\", total())
+}
+
+fn main(a: number) {
+  total()
+  _ = total()
+  log_total()
+  let b = if a > 1 { total() } else { return 0 }
+  a > b
+  return b
+}
+";
+        let (arena, module_id) = parse_module(source);
+        let warnings: Vec<String> = UnusedValueAnalysis::new(&arena)
+            .analyze(module_id)
+            .unwrap()
+            .iter()
+            .map(|warning| format!("{:?}", warning))
+            .collect();
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("This number is never used"));
+        assert!(warnings[1].contains("This boolean is never used"));
+    }
+}
@@ -0,0 +1,578 @@
+//! Compiles components and runs the output against a stubbed DOM, checking
+//! the elements they build rather than the code that's generated.
+use codegen::{render_module, Codegen, CodegenOptions};
+use parser::control_flow::ControlFlowAnalysis;
+use parser::evaluate::ExpressionEvaluator;
+use parser::parser::ParserImpl;
+use rquickjs::{CatchResultExt, Context, Runtime};
+use syntax::ast::AstArena;
+use syntax::visit::Visitor;
+
+const RUNTIME: &str = include_str!("e2e/runtime.js");
+
+fn compile(source: &str, options: CodegenOptions) -> String {
+    let mut arena = AstArena::default();
+    let module_id = ParserImpl::new(source, &mut arena)
+        .parse_module()
+        .expect("source should parse");
+    ExpressionEvaluator::new(&mut arena)
+        .visit_module(module_id)
+        .expect("source should evaluate");
+    let analysis = ControlFlowAnalysis::new(&mut arena);
+    analysis.visit_module(module_id).unwrap();
+    let cfg_map = analysis.finish();
+    let codegen =
+        Codegen::new("main".to_string(), &mut arena, cfg_map).with_options(options.clone());
+    codegen.codegen_module(module_id).unwrap();
+    let definitions = codegen.rendered_definitions().unwrap();
+    render_module("main", &options, &definitions).unwrap()
+}
+
+/// A compiled module running in a JavaScript engine, with one component
+/// mounted into `document.body`.
+struct Page {
+    context: Context,
+}
+
+impl Page {
+    fn mount(source: &str, component: &str) -> Self {
+        Self::mount_with(source, component, CodegenOptions::default())
+    }
+
+    fn mount_with(source: &str, component: &str, options: CodegenOptions) -> Self {
+        // The runtime stands in for the module's imports, so it runs as a
+        // script instead of a module.
+        let script: String = compile(source, options)
+            .lines()
+            .filter(|line| !line.trim_start().starts_with("import "))
+            .map(|line| line.strip_prefix("export ").unwrap_or(line))
+            .collect::<Vec<_>>()
+            .join("\n");
+        let runtime = Runtime::new().unwrap();
+        let mut page = Page {
+            context: Context::full(&runtime).unwrap(),
+        };
+        page.run(RUNTIME);
+        page.run(&script);
+        page.run(&format!(
+            "const app = new {}();
+            app.create();
+            app.mount(document.body);",
+            component
+        ));
+        page
+    }
+
+    fn run(&mut self, code: &str) -> String {
+        self.context.with(
+            |ctx| match ctx.eval::<Option<String>, _>(code).catch(&ctx) {
+                Ok(value) => value.unwrap_or_default(),
+                Err(error) => panic!("{}\n\n{}", error, code),
+            },
+        )
+    }
+
+    fn html(&mut self) -> String {
+        self.run("serialize(document.body)")
+    }
+
+    fn click(&mut self, tag_name: &str) {
+        self.run(&format!(
+            "dispatch(find(document.body, {:?}), \"click\")",
+            tag_name
+        ));
+    }
+}
+
+#[test]
+fn update_text_from_state() {
+    let mut page = Page::mount(include_str!("../../fixtures/main.ws"), "App");
+    assert_eq!(
+        page.html(),
+        "<body><div><span>0</span><button>Click me</button><div><h1>Hello, \
+         <span style=\"color: red\">0</span></h1></div></div></body>"
+    );
+    page.click("button");
+    page.click("button");
+    assert_eq!(
+        page.html(),
+        "<body><div><span>2</span><button>Click me</button><div><h1>Hello, \
+         <span style=\"color: red\">2</span></h1></div></div></body>"
+    );
+}
+
+#[test]
+fn render_component_children() {
+    let source = r#"
+component Card(title: string) {
+  return (
+    <section class="card"><h2>{title}</h2><slot /></section>
+  )
+}
+
+pub component App {
+  let label = "Count"
+  return (
+    <Card title={label}><p>Inside the card</p></Card>
+  )
+}
+"#;
+    let mut page = Page::mount(source, "App");
+    assert_eq!(
+        page.html(),
+        "<body><section class=\"card\"><h2>Count</h2><p>Inside the card</p></section></body>"
+    );
+}
+
+#[test]
+fn render_slot_fallback_and_reactive_children() {
+    let source = r#"
+component Card(title: string) {
+  return (
+    <section><h2>{title}</h2><slot><p>Nothing here</p></slot></section>
+  )
+}
+
+pub component App {
+  state count = 0
+  fn increment() {
+    count = count + 1
+  }
+  return (
+    <div>
+      <Card title="Empty" />
+      <Card title="Counter"><button onClick={increment}>{count}</button></Card>
+    </div>
+  )
+}
+"#;
+    // A slot shows its own content when there aren't any children, and
+    // children keep updating from the component that wrote them
+    let mut page = Page::mount(source, "App");
+    assert_eq!(
+        page.html(),
+        "<body><div><section><h2>Empty</h2><p>Nothing here</p></section>\
+         <section><h2>Counter</h2><button>0</button></section></div></body>"
+    );
+    page.click("button");
+    assert_eq!(
+        page.html(),
+        "<body><div><section><h2>Empty</h2><p>Nothing here</p></section>\
+         <section><h2>Counter</h2><button>1</button></section></div></body>"
+    );
+}
+
+#[test]
+fn provide_context() {
+    let source = r#"
+context Theme = "light"
+
+component Label {
+  return <span>{use Theme}</span>
+}
+
+component Inner {
+  provide Theme = "blue"
+  return <Label />
+}
+
+component Panel {
+  provide Theme = "dark"
+  return (
+    <div><Label /><Inner /><Label /></div>
+  )
+}
+
+pub component App {
+  return (
+    <main><Label /><Panel /></main>
+  )
+}
+"#;
+    // Components without a provider above them get the default, and each
+    // provider only reaches the components inside it
+    let mut page = Page::mount(source, "App");
+    assert_eq!(
+        page.html(),
+        "<body><main><span>light</span><div><span>dark</span><span>blue</span>\
+         <span>dark</span></div></main></body>"
+    );
+}
+
+#[test]
+fn update_provided_state() {
+    let source = r#"
+context Theme = "light"
+
+component Label {
+  return <span>{use Theme}</span>
+}
+
+pub component App {
+  state theme = "dark"
+  provide Theme = theme
+  fn toggle() {
+    theme = "light"
+  }
+  return (
+    <div><Label /><button onClick={toggle}>Toggle</button></div>
+  )
+}
+"#;
+    let mut page = Page::mount(source, "App");
+    assert_eq!(
+        page.html(),
+        "<body><div><span>dark</span><button>Toggle</button></div></body>"
+    );
+    page.click("button");
+    assert_eq!(
+        page.html(),
+        "<body><div><span>light</span><button>Toggle</button></div></body>"
+    );
+}
+
+#[test]
+fn rename_reserved_bindings() {
+    let source = r#"
+fn new(signal: number) {
+  return signal + 1
+}
+
+pub component App {
+  let document = "Shadowed"
+  state computed = new(1)
+  return (
+    <p>{document} {computed}</p>
+  )
+}
+"#;
+    let mut page = Page::mount(source, "App");
+    assert_eq!(page.html(), "<body><p>Shadowed 2</p></body>");
+}
+
+#[test]
+fn format_logs() {
+    let source = r#"
+pub component App {
+  state count = 2
+  let double = count * 2
+  log("count: {}, double: {}", count, double)
+  print("{{braces}}", count)
+  return (
+    <p>{count}</p>
+  )
+}
+"#;
+    let mut page = Page::mount(source, "App");
+    assert_eq!(
+        page.run("console.lines.join(\"\\n\")"),
+        "count: 2, double: 4\n{braces} 2"
+    );
+}
+
+#[test]
+fn share_signal_loads() {
+    let source = r#"
+pub component App {
+  state count = 2
+  let total = count + count * 2
+  return (
+    <p>{total}</p>
+  )
+}
+"#;
+    let mut page = Page::mount(source, "App");
+    assert_eq!(page.html(), "<body><p>6</p></body>");
+}
+
+#[test]
+fn logical_operators() {
+    let source = r#"
+fn either(a: bool, b: bool): bool {
+  return (a or b) and a
+}
+
+pub component App {
+  state a = false
+  state b = true
+  let first = a or (a and b)
+  let second = b or a
+  return (
+    <p>{either(true, false)} {either(false, true)} {first} {second}</p>
+  )
+}
+"#;
+    let code = compile(source, CodegenOptions::default());
+    assert!(code.contains("(($a$1) => $a$1 || $a$1 && b$1.value)(a$1.value)"));
+    let mut page = Page::mount(source, "App");
+    assert_eq!(page.html(), "<body><p>true false false true</p></body>");
+}
+
+#[test]
+fn read_captured_state_at_call_time() {
+    let source = r#"
+pub component App {
+  state count = 0
+  fn double(n: number): number {
+    return n * 2
+  }
+  fn next(): number {
+    let step = double(1)
+    return count + step
+  }
+  fn handleClick {
+    count = next()
+  }
+  return (
+    <div>
+      <p>{count}</p>
+      <button onClick={handleClick}>Add</button>
+    </div>
+  )
+}
+"#;
+    let code = compile(source, CodegenOptions::default());
+    // `next` closes over `count`, so it stays in the component and reads
+    // the signal each time it's called
+    assert!(code.contains("function next() {\nlet step = 2;\nreturn count.value + 2;"));
+    let mut page = Page::mount(source, "App");
+    page.click("button");
+    assert_eq!(
+        page.html(),
+        "<body><div><p>2</p><button>Add</button></div></body>"
+    );
+    page.click("button");
+    assert_eq!(
+        page.html(),
+        "<body><div><p>4</p><button>Add</button></div></body>"
+    );
+}
+
+#[test]
+fn keep_mutable_bindings_out_of_constant_folding() {
+    let source = r#"
+fn bump(): number {
+  let mut total = 1
+  total = total + 2
+  return total * 2
+}
+
+pub component App {
+  let result = bump()
+  return (
+    <p>{result}</p>
+  )
+}
+"#;
+    let code = compile(source, CodegenOptions::default());
+    // `total` is reassigned, so its initial value can't be folded into uses
+    assert!(code.contains("total = total + 2;\nreturn total * 2;"));
+    let mut page = Page::mount(source, "App");
+    assert_eq!(page.html(), "<body><p>6</p></body>");
+}
+
+#[test]
+fn compound_assignment() {
+    let source = r#"
+fn scale(n: number): number {
+  let mut total = n
+  total -= 1
+  total /= 2
+  return total
+}
+
+pub component App {
+  state count = 1
+  fn handleClick {
+    count += 2
+    count *= scale(5)
+  }
+  return (
+    <div>
+      <p>{count}</p>
+      <button onClick={handleClick}>Add</button>
+    </div>
+  )
+}
+"#;
+    let code = compile(source, CodegenOptions::default());
+    // Each operator is desugared to a plain assignment of a binary expression
+    assert!(code.contains("total = total - 1;\ntotal = total / 2;"));
+    assert!(code.contains("count.value + 2"));
+    let mut page = Page::mount(source, "App");
+    page.click("button");
+    assert_eq!(
+        page.html(),
+        "<body><div><p>6</p><button>Add</button></div></body>"
+    );
+    page.click("button");
+    assert_eq!(
+        page.html(),
+        "<body><div><p>16</p><button>Add</button></div></body>"
+    );
+}
+
+#[test]
+fn rename_shadowing_bindings() {
+    let source = r#"
+fn total(n: number): number {
+  let x = n + 1
+  let y = if n > 0 {
+    let x = n * 10
+    x + 1
+  } else {
+    0
+  }
+  return x + y
+}
+
+pub component App {
+  let result = total(2)
+  return (
+    <p>{result}</p>
+  )
+}
+"#;
+    let code = compile(source, CodegenOptions::default());
+    // The inner `x` shadows the outer one, so it's emitted with its own name
+    assert!(code.contains("let x$1 = n * 10; return x$1 + 1;"));
+    assert!(code.contains("let x = n + 1;"));
+    let mut page = Page::mount(source, "App");
+    assert_eq!(page.html(), "<body><p>24</p></body>");
+}
+
+#[test]
+fn hoist_loop_invariants() {
+    let source = r#"
+fn scale(x: number): number {
+  return x * 3
+}
+
+fn sum(n: number, k: number): number {
+  let mut total = 0
+  let mut i = 0
+  while i < n + 1 {
+    total = total + scale(k) * 2
+    i = i + 1
+  }
+  return total
+}
+
+pub component App {
+  let result = sum(4, 2)
+  return (
+    <p>{result}</p>
+  )
+}
+"#;
+    let mut page = Page::mount(source, "App");
+    assert_eq!(page.html(), "<body><p>60</p></body>");
+}
+
+#[test]
+fn match_enum_variants() {
+    let source = r#"
+enum Shape {
+  Circle(number)
+  Square(number)
+  Empty
+}
+
+fn area(shape) {
+  return match shape {
+    Circle(r) => r * r * 3
+    Square(s) => s * s
+    Empty => 0
+  }
+}
+
+pub component App {
+  let circle = Circle(2)
+  let square = Square(3)
+  let total = area(circle) + area(square) + area(Empty)
+  return (
+    <p>{total}</p>
+  )
+}
+"#;
+    let mut page = Page::mount(source, "App");
+    assert_eq!(page.html(), "<body><p>21</p></body>");
+}
+
+#[test]
+fn dev_checks() {
+    let source = r#"
+pub component App {
+  state count = 0
+  fn increment() {
+    count = count + 1
+  }
+  return (
+    <div><button onClick={increment}>Add</button><p>{count}</p></div>
+  )
+}
+"#;
+    let options = CodegenOptions {
+        dev: true,
+        ..Default::default()
+    };
+    let mut page = Page::mount_with(source, "App", options);
+    page.run("var button = find(document.body, \"button\")");
+    page.click("button");
+    page.run("void document.body.removeChild(document.body.childNodes[0])");
+    page.run("dispatch(button, \"click\")");
+    assert_eq!(
+        page.run("console.lines.join(\"\\n\")"),
+        "[dev] count = 1\n\
+         [dev] 'count' was updated after its template was removed from the document\n\
+         [dev] count = 2"
+    );
+    let error = page.run("try { new App().mount(null) } catch (error) { error.message }");
+    assert_eq!(
+        error,
+        "[dev] Templates can only be mounted to a DOM node, not null"
+    );
+}
+
+#[test]
+fn return_from_if_expressions() {
+    let source = r#"
+fn f(a: number): number {
+  let b = if a > 1 {
+    let c = a * 2
+    c + 1
+  } else {
+    return 0
+  }
+  return b + 100
+}
+
+fn g(a: number): number {
+  return if a > 1 { a + 10 } else if a > 0 { return 1 } else { 10 }
+}
+
+fn h(a: number): number {
+  let b = if a > 0 {
+    if a > 1 {
+      return 1
+    }
+    2
+  } else {
+    3
+  }
+  return b + 100
+}
+
+pub component App {
+  let small = f(0)
+  let large = f(2)
+  let chained = g(0) + g(1) + g(2)
+  let nested = h(0) + h(1) + h(2)
+  return (
+    <p>{small} {large} {chained} {nested}</p>
+  )
+}
+"#;
+    let mut page = Page::mount(source, "App");
+    assert_eq!(page.html(), "<body><p>0 105 23 206</p></body>");
+}
@@ -0,0 +1,172 @@
+// Just enough of the DOM and @preact/signals-core to run compiled components.
+// Elements keep their children and attributes so tests can check the tree
+// that was built, instead of the code that built it.
+
+class Node {
+  constructor() {
+    this.parentNode = null;
+    this.childNodes = [];
+  }
+
+  appendChild(child) {
+    child.parentNode = this;
+    this.childNodes.push(child);
+    return child;
+  }
+
+  removeChild(child) {
+    child.parentNode = null;
+    this.childNodes = this.childNodes.filter((node) => node !== child);
+    return child;
+  }
+
+  // The body stands in for the document
+  get isConnected() {
+    return this === document.body || (this.parentNode?.isConnected ?? false);
+  }
+}
+
+class Element extends Node {
+  constructor(tagName, namespaceURI = null) {
+    super();
+    this.tagName = tagName;
+    this.namespaceURI = namespaceURI;
+    this.attributes = [];
+    this.listeners = {};
+  }
+
+  setAttribute(name, value) {
+    const attribute = this.attributes.find((attribute) => attribute.name === name);
+    if (attribute) {
+      attribute.value = String(value);
+    } else {
+      this.attributes.push({ name, value: String(value) });
+    }
+  }
+
+  addEventListener(type, listener) {
+    (this.listeners[type] ??= []).push(listener);
+  }
+
+  set textContent(text) {
+    this.childNodes = [];
+    this.appendChild(new Text(text));
+  }
+}
+
+class Text extends Node {
+  constructor(data) {
+    super();
+    this.data = String(data);
+  }
+
+  set textContent(text) {
+    this.data = String(text);
+  }
+}
+
+const document = {
+  body: new Element("body"),
+  createElement: (tagName) => new Element(tagName),
+  createElementNS: (namespaceURI, tagName) => new Element(tagName, namespaceURI),
+  createTextNode: (data) => new Text(data),
+};
+
+// Logs are kept as the lines they'd print.
+const console = {
+  lines: [],
+  log: (...values) => console.lines.push(values.join(" ")),
+  debug: (...values) => console.log(...values),
+  warn: (...values) => console.log(...values),
+};
+
+// Called with each signal read while a computed value is worked out, so it
+// can subscribe to them.
+let tracking = null;
+
+function signal(value) {
+  const subscribers = [];
+  const signal = {
+    get value() {
+      if (tracking) tracking(signal);
+      return value;
+    },
+    set value(next) {
+      value = next;
+      subscribers.forEach((subscriber) => subscriber(value));
+    },
+    peek: () => value,
+    // Like preact, subscribers are called right away with the current value.
+    subscribe(subscriber) {
+      subscribers.push(subscriber);
+      subscriber(value);
+    },
+  };
+  return signal;
+}
+
+function computed(compute) {
+  const sources = new Set();
+  const subscribers = [];
+  const run = () => {
+    const previous = tracking;
+    tracking = (source) => {
+      if (sources.has(source)) return;
+      sources.add(source);
+      // Subscribing calls back right away, which isn't a change
+      let subscribed = false;
+      source.subscribe(() => subscribed && notify());
+      subscribed = true;
+    };
+    try {
+      return compute();
+    } finally {
+      tracking = previous;
+    }
+  };
+  const notify = () => {
+    const value = run();
+    subscribers.forEach((subscriber) => subscriber(value));
+  };
+  const computed = {
+    get value() {
+      if (tracking) tracking(computed);
+      return run();
+    },
+    peek: compute,
+    subscribe(subscriber) {
+      subscribers.push(subscriber);
+      subscriber(run());
+    },
+  };
+  return computed;
+}
+
+function serialize(node) {
+  if (node instanceof Text) {
+    return node.data;
+  }
+  const attributes = node.attributes
+    .map((attribute) => ` ${attribute.name}="${attribute.value}"`)
+    .join("");
+  const children = node.childNodes.map(serialize).join("");
+  return `<${node.tagName}${attributes}>${children}</${node.tagName}>`;
+}
+
+// The first element named `tagName` under `node`, in document order.
+function find(node, tagName) {
+  for (const child of node.childNodes) {
+    if (child.tagName === tagName) {
+      return child;
+    }
+    const found = child instanceof Element && find(child, tagName);
+    if (found) {
+      return found;
+    }
+  }
+  return null;
+}
+
+function dispatch(node, type) {
+  (node.listeners[type] ?? []).forEach((listener) => listener({ type, target: node }));
+}
@@ -0,0 +1,101 @@
+//! Arenas kept between compiles so they can be reused. The language server
+//! reparses on every keystroke and watch mode on every save, and each parse
+//! used to start from an empty arena that grew from nothing.
+use crate::ast::AstArena;
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+thread_local! {
+    /// The arena this thread last finished with, if it isn't in use.
+    static POOL: RefCell<Option<AstArena>> = const { RefCell::new(None) };
+}
+
+static CREATED: AtomicUsize = AtomicUsize::new(0);
+static REUSED: AtomicUsize = AtomicUsize::new(0);
+static SHRUNK: AtomicUsize = AtomicUsize::new(0);
+static LAST_NODES: AtomicUsize = AtomicUsize::new(0);
+static PEAK_NODES: AtomicUsize = AtomicUsize::new(0);
+
+/// How the arenas have been used, across every thread.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ArenaStats {
+    /// Arenas created because there wasn't one to reuse.
+    pub created: usize,
+    pub reused: usize,
+    /// Times an arena's tables were shrunk when it was reset.
+    pub shrunk: usize,
+    /// Nodes in the arena that was finished with last.
+    pub last_nodes: usize,
+    /// The most nodes any arena has held.
+    pub peak_nodes: usize,
+}
+
+/// Run `f` with an empty arena, reusing the one this thread last finished
+/// with when it's free. Nothing `f` returns can borrow from the arena,
+/// since it's reset for the next use once `f` is done.
+pub fn with_arena<R>(f: impl FnOnce(&mut AstArena) -> R) -> R {
+    // Arenas in use further up the stack, like when compiling one module
+    // needs another parsed, leave the pool empty
+    let mut arena = match POOL.with(|pool| pool.borrow_mut().take()) {
+        Some(arena) => {
+            REUSED.fetch_add(1, Ordering::Relaxed);
+            arena
+        }
+        None => {
+            CREATED.fetch_add(1, Ordering::Relaxed);
+            AstArena::default()
+        }
+    };
+    let result = f(&mut arena);
+    let nodes = arena.node_count();
+    LAST_NODES.store(nodes, Ordering::Relaxed);
+    PEAK_NODES.fetch_max(nodes, Ordering::Relaxed);
+    if arena.reset() {
+        SHRUNK.fetch_add(1, Ordering::Relaxed);
+    }
+    POOL.with(|pool| {
+        pool.borrow_mut().get_or_insert(arena);
+    });
+    result
+}
+
+pub fn arena_stats() -> ArenaStats {
+    ArenaStats {
+        created: CREATED.load(Ordering::Relaxed),
+        reused: REUSED.load(Ordering::Relaxed),
+        shrunk: SHRUNK.load(Ordering::Relaxed),
+        last_nodes: LAST_NODES.load(Ordering::Relaxed),
+        peak_nodes: PEAK_NODES.load(Ordering::Relaxed),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Expression;
+    use crate::span::Span;
+
+    #[test]
+    fn reuse_arenas() {
+        let before = arena_stats();
+        let generation = with_arena(|arena| {
+            for _ in 0..100 {
+                arena.alloc_spanned_expression(Expression::Boolean(true), Span::from(0..4));
+            }
+            arena.generation()
+        });
+        let (next_generation, nodes) = with_arena(|arena| (arena.generation(), arena.node_count()));
+        assert_eq!(next_generation, generation + 1);
+        assert_eq!(nodes, 0);
+        // The pool's arena is in use, so the inner one is new
+        let inner = with_arena(|_| with_arena(|arena| arena.generation()));
+        assert_eq!(inner, 0);
+
+        let after = arena_stats();
+        assert_eq!(after.reused - before.reused, 2);
+        assert!(after.peak_nodes >= 100);
+        // The span table had room for 100 spans after the second parse
+        // didn't use any
+        assert!(after.shrunk > before.shrunk);
+    }
+}
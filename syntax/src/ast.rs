@@ -1,292 +1,480 @@
-use crate::arena::{self, ExpressionId, FunctionId, StatementId};
-use crate::span::Span;
-use common::scope_map::Referant;
+use crate::{builtins::Builtin, events::EventType, span::Span};
+use common::scope_map::{Referant, Reference};
 use common::symbol::Symbol;
-use diagnostics::result::Result;
+use id_arena::{Arena, Id};
+use std::cell::RefCell;
+use std::collections::HashMap;
 
-use id_arena::Id;
-
-use std::fmt::Display;
-use std::sync::Arc;
+#[derive(Default)]
+pub struct AstArena {
+    pub modules: Arena<Module>,
+    pub blocks: Arena<Block>,
+    pub structs: Arena<Struct>,
+    pub expressions: Arena<RefCell<Expression>>,
+    pub functions: Arena<RefCell<Function>>,
+    pub components: Arena<RefCell<Component>>,
+    pub statements: Arena<Statement>,
+    pub consts: Arena<Const>,
+    pub contexts: Arena<Context>,
+    pub effects: Arena<Effect>,
+    pub type_aliases: Arena<TypeAlias>,
+    pub enums: Arena<Enum>,
+    pub variants: Arena<Variant>,
+    pub pattern_bindings: Arena<PatternBinding>,
+    pub imports: Arena<Import>,
+    pub import_specifiers: Arena<ImportSpecifier>,
+    pub globals: Arena<Global>,
+    pub parameters: Arena<Parameter>,
+    pub templates: Arena<RefCell<Template>>,
+    pub states: Arena<State>,
+    pub expression_spans: HashMap<ExpressionId, Span>,
+    /// Where each block is, from its opening brace to its closing one.
+    /// Blocks the parser creates itself, like the one around an `else if`,
+    /// don't have one.
+    pub block_spans: HashMap<BlockId, Span>,
+    /// Unique names for bindings that shadow another binding with the same
+    /// name. Bindings without an entry keep their original name.
+    pub unique_names: HashMap<Binding, u32>,
+    /// How many times the arena has been reset. Ids from one generation
+    /// don't refer to anything in the next.
+    generation: u64,
+}
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq, PartialOrd, Ord)]
-pub struct UniqueName(u32);
+/// Tables are shrunk when they're reset if they have room for this many
+/// times what they last held, so one large module doesn't keep the memory
+/// it needed around for every small one after it.
+const SHRINK_FACTOR: usize = 4;
 
-type Type = types::Type<Arc<Struct>, Arc<EffectDef>, Arc<TypeParameter>>;
+/// Calls `$with!` with `$arena` and the name of each of its node arenas.
+macro_rules! with_node_arenas {
+    ($with:ident, $arena:expr) => {
+        $with!(
+            $arena;
+            modules,
+            blocks,
+            structs,
+            expressions,
+            functions,
+            components,
+            statements,
+            consts,
+            contexts,
+            effects,
+            type_aliases,
+            enums,
+            variants,
+            pattern_bindings,
+            imports,
+            import_specifiers,
+            globals,
+            parameters,
+            templates,
+            states
+        )
+    };
+}
 
-impl From<u32> for UniqueName {
-    fn from(id: u32) -> Self {
-        UniqueName(id)
+/// Empty `map`, keeping the room it had unless it has much more than it
+/// needed. Returns whether it was shrunk.
+fn reset_table<K: Eq + std::hash::Hash, V>(map: &mut HashMap<K, V>) -> bool {
+    let used = map.len();
+    map.clear();
+    if map.capacity() > SHRINK_FACTOR * used.max(1) {
+        map.shrink_to(used);
+        return true;
     }
+    false
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Module {
-    pub imports: Vec<Import>,
-    pub definitions: Vec<Definition>,
-}
+impl AstArena {
+    /// How many nodes of every kind have been allocated.
+    pub fn node_count(&self) -> usize {
+        macro_rules! count {
+            ($arena:expr; $($field:ident),*) => {
+                0 $(+ $arena.$field.len())*
+            };
+        }
+        with_node_arenas!(count, self)
+    }
 
-impl Module {
-    pub fn new(imports: Vec<Import>, definitions: Vec<Definition>) -> Self {
-        Self {
-            imports,
-            definitions,
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Empty the arena so another module can be parsed into it, starting a
+    /// new generation. Each kind of node starts with room for as many as it
+    /// had, since the next module is usually about the same size, and the
+    /// span and name tables keep the room they had unless it's far more
+    /// than they used. Returns whether any of them were shrunk.
+    pub fn reset(&mut self) -> bool {
+        macro_rules! presize {
+            ($arena:expr; $($field:ident),*) => {
+                $($arena.$field = Arena::with_capacity($arena.$field.len());)*
+            };
         }
+        with_node_arenas!(presize, self);
+        let shrunk = [
+            reset_table(&mut self.expression_spans),
+            reset_table(&mut self.block_spans),
+            reset_table(&mut self.unique_names),
+        ];
+        self.generation += 1;
+        shrunk.contains(&true)
     }
-}
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Definition {
-    pub is_public: bool,
-    pub kind: DefinitionKind,
-}
+    pub fn alloc_expression(&mut self, expression: Expression) -> ExpressionId {
+        self.expressions.alloc(RefCell::new(expression))
+    }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum DefinitionKind {
-    Struct(Arc<Struct>),
-    Const(Arc<Const>),
-    Type(Arc<TypeDef>),
-    Effect(Arc<EffectDef>),
-    Enum(Arc<Enum>),
-    Function(FunctionId),
-    Component(Arc<Component>),
-}
+    pub fn alloc_spanned_expression(&mut self, expression: Expression, span: Span) -> ExpressionId {
+        let expression_id = self.alloc_expression(expression);
+        self.expression_spans.insert(expression_id, span);
+        expression_id
+    }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Import {
-    parts: Vec<ImportPart>,
-}
+    /// The source span for an expression, if the parser recorded one.
+    pub fn expression_span(&self, expression_id: ExpressionId) -> Option<Span> {
+        self.expression_spans.get(&expression_id).copied()
+    }
 
-impl Import {
-    pub fn new(parts: Vec<ImportPart>) -> Self {
-        Self { parts }
+    /// The source span for a statement. Statements don't record spans of
+    /// their own, so this is the span of the expression they're built around.
+    pub fn statement_span(&self, statement_id: StatementId) -> Option<Span> {
+        let expression_id = match self.statements.get(statement_id).unwrap() {
+            Statement::Expression(value)
+            | Statement::Discard(value)
+            | Statement::Return(value)
+            | Statement::Let { value, .. }
+            | Statement::Assignment { value, .. }
+            | Statement::Provide { value, .. } => *value,
+            Statement::State(state_id) => self.states[*state_id].value,
+            Statement::If(if_) => if_.condition,
+            Statement::While { condition, .. } => *condition,
+        };
+        self.expression_span(expression_id)
     }
-}
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum ImportPart {
-    Module(Identifier),
-    /// Collections can only occur at the end of
-    /// an import path.
-    Collection(Vec<Identifier>),
-}
+    /// The name a top-level definition is declared with.
+    pub fn definition_name(&self, kind: DefinitionKind) -> Option<Identifier> {
+        match kind {
+            DefinitionKind::Function(function_id) => {
+                Some(self.functions[function_id].borrow().name)
+            }
+            DefinitionKind::Component(component_id) => {
+                Some(self.components[component_id].borrow().name)
+            }
+            DefinitionKind::Const(const_id) => Some(self.consts[const_id].name),
+            DefinitionKind::Context(context_id) => Some(self.contexts[context_id].name),
+            DefinitionKind::Effect(effect_id) => Some(self.effects[effect_id].name),
+            DefinitionKind::Enum(enum_id) => Some(self.enums[enum_id].name),
+            DefinitionKind::TypeAlias(alias_id) => Some(self.type_aliases[alias_id].name),
+            DefinitionKind::Import(_) | DefinitionKind::Struct(_) => None,
+        }
+    }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct TypeParameter {
-    pub name: Identifier,
-}
+    /// Where a top-level definition is declared, which is its name. Imports
+    /// don't have a name, so it's the start of the module path instead.
+    pub fn definition_span(&self, kind: DefinitionKind) -> Option<Span> {
+        match kind {
+            DefinitionKind::Import(import_id) => Some(self.imports[import_id].path[0].span),
+            kind => self.definition_name(kind).map(|name| name.span),
+        }
+    }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct TypeParameters {
-    pub span: Span,
-    pub identifiers: Vec<Arc<TypeParameter>>,
-}
+    /// The expression a block evaluates to, which is its trailing
+    /// expression statement.
+    pub fn block_value(&self, block_id: BlockId) -> Option<ExpressionId> {
+        let block = self.blocks.get(block_id).unwrap();
+        match self.statements.get(*block.statements.last()?).unwrap() {
+            Statement::Expression(expression_id) => Some(*expression_id),
+            _ => None,
+        }
+    }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Parameter {
-    pub name: Identifier,
-    pub type_: Option<TypeExpression>,
-}
+    /// The type of a block's value. A block that ends by returning never
+    /// produces a value, so any other type can be used in its place.
+    pub fn block_type(&self, block_id: BlockId) -> Option<Type> {
+        let block = self.blocks.get(block_id).unwrap();
+        match self.statements.get(*block.statements.last()?).unwrap() {
+            Statement::Return(_) => Some(Type::Never),
+            _ => self
+                .block_value(block_id)
+                .and_then(|value| self.expression_type(value)),
+        }
+    }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct TypeExpression {
-    pub kind: TypeExpressionKind,
-    pub type_: Option<Type>,
-    pub span: Span,
-}
+    /// The type of an expression, when it can be known without a full type check.
+    pub fn expression_type(&self, expression_id: ExpressionId) -> Option<Type> {
+        let expression = self.expressions.get(expression_id).unwrap().borrow();
+        match &*expression {
+            Expression::Number { .. } => Some(Type::Number),
+            Expression::Boolean(_) => Some(Type::Boolean),
+            Expression::String(_) => Some(Type::String),
+            Expression::Binary {
+                op:
+                    BinOp::DoubleEquals
+                    | BinOp::GreaterThan
+                    | BinOp::GreaterThanEquals
+                    | BinOp::LessThan
+                    | BinOp::LessThanEquals,
+                ..
+            } => Some(Type::Boolean),
+            Expression::If {
+                then_branch,
+                else_branch,
+                ..
+            } => match self.block_type(*then_branch) {
+                Some(Type::Never) => else_branch.and_then(|branch| self.block_type(branch)),
+                type_ => type_,
+            },
+            Expression::Call { callee, .. } => {
+                let callee = self.expressions.get(*callee).unwrap().borrow();
+                match &*callee {
+                    Expression::Reference(Binding::Function(function_id)) => {
+                        self.functions[*function_id].borrow().return_type.clone()
+                    }
+                    _ => None,
+                }
+            }
+            Expression::Reference(Binding::Let(statement_id)) => {
+                match self.statements.get(*statement_id).unwrap() {
+                    Statement::Let { value, .. } => self.expression_type(*value),
+                    _ => None,
+                }
+            }
+            Expression::Reference(Binding::Parameter(parameter_id)) => {
+                self.parameters[*parameter_id].type_.clone()
+            }
+            Expression::UseContext(context_id) => {
+                self.expression_type(self.contexts[*context_id].value)
+            }
+            Expression::Variant { variant, .. }
+            | Expression::Reference(Binding::Variant(variant)) => {
+                let enum_id = self.variants[*variant].enum_id;
+                Some(Type::Enum {
+                    name: self.enums[enum_id].name.symbol,
+                    enum_id,
+                })
+            }
+            // Elements all have the same type, which the parser checks
+            Expression::Array(elements) => elements
+                .iter()
+                .find_map(|element| self.expression_type(*element))
+                .map(|element| Type::Array(element.into())),
+            _ => None,
+        }
+    }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum TypeExpressionKind {
-    Number,
-    String,
-    Boolean,
-    Unit,
-    Reference {
-        name: Identifier,
-        arguments: Option<Vec<TypeExpression>>,
-    },
-    Function {
-        parameters: Vec<TypeExpression>,
-        return_type: Box<TypeExpression>,
-    },
-}
+    pub fn alloc_template(&mut self, template: Template) -> TemplateId {
+        self.templates.alloc(RefCell::new(template))
+    }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Effect(pub TypeExpression);
+    pub fn alloc_function(&mut self, function: Function) -> FunctionId {
+        self.functions.alloc(RefCell::new(function))
+    }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct TypeDef {
-    pub span: Span,
-    pub name: Identifier,
-    pub type_: TypeExpression,
+    pub fn alloc_component(&mut self, component: Component) -> ComponentId {
+        self.components.alloc(RefCell::new(component))
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct EffectDef {
-    pub span: Span,
-    pub name: Identifier,
+pub type ModuleId = Id<Module>;
+pub type BlockId = Id<Block>;
+pub type StructId = Id<Struct>;
+pub type ExpressionId = Id<RefCell<Expression>>;
+pub type TemplateId = Id<RefCell<Template>>;
+pub type FunctionId = Id<RefCell<Function>>;
+pub type ComponentId = Id<RefCell<Component>>;
+pub type StatementId = Id<Statement>;
+pub type ConstId = Id<Const>;
+pub type ContextId = Id<Context>;
+pub type EffectId = Id<Effect>;
+pub type TypeAliasId = Id<TypeAlias>;
+pub type ImportId = Id<Import>;
+pub type ImportSpecifierId = Id<ImportSpecifier>;
+pub type GlobalId = Id<Global>;
+pub type ParameterId = Id<Parameter>;
+pub type EnumId = Id<Enum>;
+pub type VariantId = Id<Variant>;
+pub type PatternBindingId = Id<PatternBinding>;
+pub type StateId = Id<State>;
+
+pub struct Module {
+    pub definitions: Vec<Definition>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Struct {
-    pub name: Identifier,
-    pub type_parameters: Option<TypeParameters>,
-    pub fields: Vec<StructField>,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Definition {
+    pub kind: DefinitionKind,
+    pub public: bool,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct StructField {
-    pub name: Identifier,
-    pub type_: TypeExpression,
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DefinitionKind {
+    Function(FunctionId),
+    Component(ComponentId),
+    Const(ConstId),
+    Context(ContextId),
+    Effect(EffectId),
+    Import(ImportId),
+    Struct(StructId),
+    Enum(EnumId),
+    TypeAlias(TypeAliasId),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Const {
     pub name: Identifier,
-    pub type_: Option<TypeExpression>,
-    pub value: Expression,
+    pub value: ExpressionId,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Enum {
+/// Shared state that components can `provide` to all of the components they
+/// mount, without passing it through each of them. Components that `use` a
+/// context without a provider get the default value.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Context {
     pub name: Identifier,
-    pub type_parameters: Option<TypeParameters>,
-    pub variants: Vec<Variant>,
+    pub value: ExpressionId,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Variant {
+/// An effect a function can perform, like `effect Log`. Functions declare
+/// the effects they perform after their return type, like `fn f(): number + Log`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Effect {
     pub name: Identifier,
-    pub types: Option<Vec<TypeExpression>>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum Binding {
-    Let(Arc<Let>),
-    State(Arc<State>),
-    Enum(Arc<Enum>),
-    Function(Id<Function>),
-    Component(Arc<Component>),
-    Parameter(Arc<Parameter>),
-    Const(Arc<Const>),
-    Iterator(Identifier),
-    // TODO make this reference something that can be resolved
-    Import(Span),
+/// A name for a type, like `type Handler = (event: string) => number`.
+/// Aliases are structural, so an alias and the type it names can be used
+/// interchangeably.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TypeAlias {
+    pub name: Identifier,
+    pub type_: Type,
 }
 
-impl Binding {
-    pub fn span(&self, arena: &arena::AstArena) -> Span {
-        match self {
-            Binding::Let(let_) => (&**let_).name.span,
-            Binding::State(state) => (&**state).name.span,
-            Binding::Enum(enum_) => (&**enum_).name.span,
-            Binding::Function(func) => {
-                let func = arena.functions.get(*func).expect("function not found");
-                func.name.span
-            }
-            Binding::Component(component) => (&**component).name.span,
-            Binding::Parameter(param) => (&**param).name.span,
-            Binding::Const(const_) => (&**const_).name.span,
-            Binding::Iterator(iter) => iter.span,
-            Binding::Import(span) => *span,
-        }
-    }
+/// An import, like `import utils.{helper as h}` for some of a module's
+/// exports, or `import long.module.name as m` for the whole module.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Import {
+    /// The path to the module, like `utils` or `long.module.name`.
+    pub path: Vec<Identifier>,
+    pub specifiers: Vec<ImportSpecifierId>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum TypeBinding {
-    Struct(Arc<Struct>),
-    Effect(Arc<EffectDef>),
-    TypeParameter(Arc<TypeParameter>),
+/// A name that's defined without being declared: either a global the
+/// target defines, like `console`, or a builtin function, like `log`.
+/// References to the same global in a module share one, which is spanned to
+/// the first of them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Global {
+    pub name: Identifier,
+    pub builtin: Option<Builtin>,
 }
 
-impl TypeBinding {
-    pub fn span(&self) -> Span {
-        match self {
-            TypeBinding::Struct(struct_) => (&**struct_).name.span,
-            TypeBinding::Effect(effect) => (&**effect).name.span,
-            TypeBinding::TypeParameter(param) => (&**param).name.span,
-        }
-    }
+/// A name an import brings into scope.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ImportSpecifier {
+    /// The export being imported, or `None` for the whole module.
+    pub name: Option<Identifier>,
+    /// The name it's bound to in this module, which is its alias if it has one.
+    pub local: Identifier,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct EffectBinding(pub Arc<EffectDef>);
+pub struct Struct {}
 
-impl Referant for Binding {}
-impl Referant for TypeBinding {}
-impl Referant for EffectBinding {}
-
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum ExpressionKind {
-    // TODO(aweary) don't use u32 for the value representation
-    Number {
-        raw: Symbol,
-        value: Option<u32>,
-    },
-    String {
-        raw: Symbol,
-        // TODO(aweary) evaluate strings
+#[derive(Clone, Debug, PartialEq)]
+pub enum Expression {
+    /// An if-expression like `if a { b } else { c }`, which evaluates to
+    /// the value of whichever branch is taken.
+    If {
+        condition: ExpressionId,
+        then_branch: BlockId,
+        else_branch: Option<BlockId>,
     },
     Binary {
-        left: Box<Expression>,
-        right: Box<Expression>,
+        left: ExpressionId,
+        right: ExpressionId,
         op: BinOp,
     },
-    Call(Call),
+    Unary {
+        op: BinOp,
+        operand: ExpressionId,
+    },
+    /// A number, along with the text it was written as, like `1_000`.
+    /// Numbers made by folding other expressions don't have any text.
+    Number {
+        value: f64,
+        literal: Option<Symbol>,
+    },
     Boolean(bool),
+    String(Symbol),
     Reference(Binding),
-    Array(Vec<Expression>),
-    Member {
-        object: Box<Expression>,
-        property: Identifier,
-    },
-    Range {
-        start: Box<Expression>,
-        end: Box<Expression>,
+    Call {
+        callee: ExpressionId,
+        arguments: Vec<Argument>,
     },
-    Assignment {
-        left: Box<Expression>,
-        right: Box<Expression>,
+    Template(TemplateId),
+    Function(FunctionId),
+    /// Reading the value of a context, like `use Theme`
+    UseContext(ContextId),
+    /// Creating a value of an enum, like `Some(1)` or `None`
+    Variant {
+        variant: VariantId,
+        arguments: Vec<ExpressionId>,
     },
+    /// A match-expression, which evaluates to the body of the first case
+    /// whose pattern matches `value`.
     Match {
-        value: Box<Expression>,
+        value: ExpressionId,
         cases: Vec<MatchCase>,
     },
-    Block(Block),
-    Await(Box<Expression>),
-    View(Box<View>),
-}
-
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Call {
-    pub callee: Box<Expression>,
-    pub arguments: Vec<Argument>,
+    /// Waiting on a promise in an async function, like `await load()`
+    Await(ExpressionId),
+    /// A list of values, like `[1, 2, 3]`.
+    Array(Vec<ExpressionId>),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct View {
-    pub constructor: Call,
-    pub body: Block,
-}
-
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Argument {
-    pub span: Span,
-    pub name: Option<Identifier>,
-    pub value: Expression,
+impl Expression {
+    pub fn is_constant(&self) -> bool {
+        match self {
+            Expression::Number { .. } => true,
+            Expression::Boolean(_) => true,
+            Expression::String(_) => true,
+            _ => false,
+        }
+    }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct MatchCase {
     pub pattern: MatchPattern,
-    pub body: Box<Expression>,
+    pub body: ExpressionId,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum MatchPattern {
+    /// `_`, which matches anything
     Wildcard,
-    Expression(Box<Expression>),
+    /// A value the matched value is compared to, like `1` or `true`
+    Expression(ExpressionId),
+    /// A variant of an enum, binding the values it holds, like `Some(x)`
+    Variant {
+        variant: VariantId,
+        bindings: Vec<PatternBindingId>,
+    },
+}
+
+/// A name a variant pattern binds one of the variant's values to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PatternBinding {
+    pub name: Identifier,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Argument {
+    pub name: Option<Identifier>,
+    pub value: ExpressionId,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -310,7 +498,33 @@ pub enum BinOp {
     BinAnd,
 }
 
-impl Display for BinOp {
+impl BinOp {
+    /// The JavaScript operator this compiles to. Equality is always strict,
+    /// and the pipeline operator has no JavaScript equivalent, so it has to
+    /// be lowered to a call instead.
+    pub fn to_js(&self) -> Option<&'static str> {
+        match self {
+            BinOp::Equals => Some("="),
+            BinOp::DoubleEquals => Some("==="),
+            BinOp::Add | BinOp::Sum => Some("+"),
+            BinOp::Sub => Some("-"),
+            BinOp::Mul => Some("*"),
+            BinOp::Div => Some("/"),
+            BinOp::Mod => Some("%"),
+            BinOp::And => Some("&&"),
+            BinOp::Or => Some("||"),
+            BinOp::GreaterThan => Some(">"),
+            BinOp::GreaterThanEquals => Some(">="),
+            BinOp::LessThan => Some("<"),
+            BinOp::LessThanEquals => Some("<="),
+            BinOp::Pipeline => None,
+            BinOp::BinOr => Some("|"),
+            BinOp::BinAnd => Some("&"),
+        }
+    }
+}
+
+impl std::fmt::Display for BinOp {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             BinOp::Equals => write!(f, "="),
@@ -334,133 +548,551 @@ impl Display for BinOp {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum UnOp {
-    Not,
-    Increment,
-    Decrement,
-}
-
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum Value {
-    Boolean(bool),
-    Number(u32),
+#[derive(Debug)]
+pub enum Statement {
+    Expression(ExpressionId),
+    Let {
+        name: Identifier,
+        value: ExpressionId,
+        /// Whether this was declared with `let mut`
+        mutable: bool,
+    },
+    State(StateId),
+    /// A value that's deliberately unused, like `_ = save()`.
+    Discard(ExpressionId),
+    Return(ExpressionId),
+    If(If),
+    While {
+        condition: ExpressionId,
+        body: BlockId,
+    },
+    Assignment {
+        name: Binding,
+        value: ExpressionId,
+    },
+    /// Providing a value for a context, like `provide Theme = "dark"`
+    Provide {
+        context: ContextId,
+        value: ExpressionId,
+    },
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Expression {
-    pub span: Span,
-    pub kind: ExpressionKind,
-    /// The evaluated type for this expression. Populated
-    /// during the type check pass.
-    pub type_: Option<Type>,
+#[derive(Debug)]
+pub struct State {
+    pub name: Identifier,
+    pub value: ExpressionId,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum StatementKind {
-    Let(Arc<Let>),
-    State(Arc<State>),
-    For(For),
-    While(While),
-    If(If),
-    Return(Expression),
-    Expression(Expression),
+impl Statement {
+    pub fn is_state_assignment(&self) -> bool {
+        match self {
+            Statement::Assignment { name, .. } => match name {
+                Binding::State(_) => true,
+                _ => false,
+            },
+            _ => false,
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct If {
-    pub span: Span,
     pub condition: ExpressionId,
-    pub body: Block,
+    pub body: BlockId,
     pub alternate: Option<Box<Else>>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Else {
     If(If),
-    Block(Block),
+    Block(BlockId),
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct While {
-    pub condition: ExpressionId,
-    pub body: Block,
+pub struct Block {
+    pub statements: Vec<StatementId>,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct For {
-    pub iterator: Identifier,
-    pub iterable: Expression,
-    pub body: Block,
+#[derive(Debug, Clone)]
+pub enum Type {
+    Number,
+    String,
+    Boolean,
+    /// A list of values that all have the same type, like `[number]`.
+    Array(Box<Type>),
+    /// A value that might not be there, like `number?`. A function that
+    /// returns one can end without returning.
+    Optional(Box<Type>),
+    /// The type of a function that doesn't return a value.
+    Void,
+    /// The type of an expression that never produces a value, like a block
+    /// that always returns. It can be used in place of any other type.
+    Never,
+    /// A function type, like `(a: number, string) => number + Log`.
+    Function {
+        parameters: Vec<FunctionTypeParameter>,
+        return_type: Box<Type>,
+        /// The effects a function of this type can perform, sorted so the
+        /// order they're written in doesn't matter.
+        effects: Vec<Symbol>,
+    },
+    /// An event sent to a handler, like `KeyboardEvent`.
+    Event(EventType),
+    /// A value of an enum, like `Color`. Enums are compared by which one
+    /// they are, since two enums can have the same name in different modules.
+    Enum {
+        name: Symbol,
+        enum_id: EnumId,
+    },
+    /// A reference to a type alias. It keeps the alias's name so it can be
+    /// shown along with the type it expands to.
+    Alias {
+        name: Symbol,
+        type_: Box<Type>,
+    },
+}
+
+impl Type {
+    /// The type with any aliases expanded, which is what's compared.
+    pub fn resolved(&self) -> &Type {
+        match self {
+            Type::Alias { type_, .. } => type_.resolved(),
+            type_ => type_,
+        }
+    }
+}
+
+impl PartialEq for Type {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.resolved(), other.resolved()) {
+            (Type::Number, Type::Number)
+            | (Type::String, Type::String)
+            | (Type::Boolean, Type::Boolean)
+            | (Type::Void, Type::Void)
+            | (Type::Never, Type::Never) => true,
+            (Type::Array(element), Type::Array(other_element)) => element == other_element,
+            (Type::Optional(value), Type::Optional(other_value)) => value == other_value,
+            (Type::Event(event), Type::Event(other_event)) => event == other_event,
+            (Type::Enum { enum_id, .. }, Type::Enum { enum_id: other, .. }) => enum_id == other,
+            (
+                Type::Function {
+                    parameters,
+                    return_type,
+                    effects,
+                },
+                Type::Function {
+                    parameters: other_parameters,
+                    return_type: other_return_type,
+                    effects: other_effects,
+                },
+            ) => {
+                parameters == other_parameters
+                    && return_type == other_return_type
+                    && effects == other_effects
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Type {}
+
+impl std::hash::Hash for Type {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        let resolved = self.resolved();
+        std::mem::discriminant(resolved).hash(state);
+        match resolved {
+            Type::Array(type_) | Type::Optional(type_) => type_.hash(state),
+            Type::Event(event) => event.hash(state),
+            Type::Enum { enum_id, .. } => enum_id.hash(state),
+            Type::Function {
+                parameters,
+                return_type,
+                effects,
+            } => {
+                parameters.hash(state);
+                return_type.hash(state);
+                effects.hash(state);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Number => write!(f, "number"),
+            Type::String => write!(f, "string"),
+            Type::Boolean => write!(f, "boolean"),
+            Type::Array(element) => write!(f, "[{}]", element),
+            Type::Optional(value) => write!(f, "{}?", value),
+            Type::Void => write!(f, "void"),
+            Type::Never => write!(f, "never"),
+            Type::Function {
+                parameters,
+                return_type,
+                effects,
+            } => {
+                let parameters: Vec<String> = parameters.iter().map(|p| p.to_string()).collect();
+                write!(f, "fn({}) -> {}", parameters.join(", "), return_type)?;
+                for effect in effects {
+                    write!(f, " + {}", effect)?;
+                }
+                Ok(())
+            }
+            Type::Event(event) => write!(f, "{}", event.name()),
+            Type::Enum { name, .. } => write!(f, "{}", name),
+            Type::Alias { name, type_ } => write!(f, "{} ({})", name, type_),
+        }
+    }
+}
+
+/// A parameter in a function type. Its name is only there to document it,
+/// so function types with different parameter names are the same type.
+#[derive(Debug, Clone)]
+pub struct FunctionTypeParameter {
+    pub name: Option<Symbol>,
+    pub type_: Type,
+}
+
+impl PartialEq for FunctionTypeParameter {
+    fn eq(&self, other: &Self) -> bool {
+        self.type_ == other.type_
+    }
+}
+
+impl Eq for FunctionTypeParameter {}
+
+impl std::hash::Hash for FunctionTypeParameter {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.type_.hash(state)
+    }
+}
+
+impl std::fmt::Display for FunctionTypeParameter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.name {
+            Some(name) => write!(f, "{}: {}", name, self.type_),
+            None => write!(f, "{}", self.type_),
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Let {
+pub struct Parameter {
+    pub name: Identifier,
+    pub type_: Option<Type>,
+}
+
+pub struct Function {
+    pub name: Identifier,
+    pub body: Option<BlockId>,
+    pub parameters: Option<Vec<ParameterId>>,
+    pub return_type: Option<Type>,
+    /// The effects the function declares it performs, if it declares any.
+    /// Functions that don't have their effects inferred from what they call.
+    pub effects: Option<Vec<EffectId>>,
+    /// Whether this is an `async fn`, which can `await`.
+    pub is_async: bool,
+}
+
+pub struct Component {
     pub name: Identifier,
-    pub unique_name: UniqueName,
-    pub value: Expression,
+    pub body: Option<BlockId>,
+    pub parameters: Option<Vec<ParameterId>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Identifier {
+    pub span: Span,
+    pub symbol: Symbol,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Binding {
+    Let(StatementId),
+    State(StatementId),
+    Const(ConstId),
+    Function(FunctionId),
+    Parameter(ParameterId),
+    Component(ComponentId),
+    Context(ContextId),
+    Import(ImportSpecifierId),
+    Global(GlobalId),
+    Variant(VariantId),
+    Pattern(PatternBindingId),
+}
+
+/// A stable, unique reference to a binding, for telling bindings apart
+/// without going by their names. It's made from the kind of binding and
+/// where it was allocated in the arena, so no two bindings share one, even
+/// when they have the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BindingRef {
+    kind: char,
+    index: usize,
+}
+
+impl std::fmt::Display for BindingRef {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.kind, self.index)
+    }
+}
+
+impl Binding {
+    /// The reference that identifies this binding.
+    pub fn reference(&self) -> BindingRef {
+        let (kind, index) = match self {
+            Binding::Let(statement_id) => ('l', statement_id.index()),
+            Binding::State(statement_id) => ('s', statement_id.index()),
+            Binding::Const(const_id) => ('k', const_id.index()),
+            Binding::Function(function_id) => ('f', function_id.index()),
+            Binding::Parameter(parameter_id) => ('p', parameter_id.index()),
+            Binding::Component(component_id) => ('c', component_id.index()),
+            Binding::Context(context_id) => ('x', context_id.index()),
+            Binding::Import(specifier_id) => ('i', specifier_id.index()),
+            Binding::Global(global_id) => ('g', global_id.index()),
+            Binding::Variant(variant_id) => ('v', variant_id.index()),
+            Binding::Pattern(binding_id) => ('m', binding_id.index()),
+        };
+        BindingRef { kind, index }
+    }
+
+    /// The name this binding has in its scope. Bindings that shadow another
+    /// binding with the same name get a number appended.
+    pub fn to_string(&self, arena: &AstArena) -> String {
+        match arena.unique_names.get(self) {
+            Some(unique_name) => format!("{}${}", self.source_name(arena), unique_name),
+            None => self.source_name(arena),
+        }
+    }
+
+    /// The name this binding was given in the source.
+    fn source_name(&self, arena: &AstArena) -> String {
+        match self {
+            Binding::Let(statent_id) => {
+                let statement = &arena.statements[*statent_id];
+                match statement {
+                    Statement::Let { name, .. } => name.symbol.to_string(),
+                    _ => unreachable!(),
+                }
+            }
+            Binding::State(statement_id) => {
+                let statement = &arena.statements[*statement_id];
+                match statement {
+                    Statement::State(state_id) => {
+                        let state = &arena.states[*state_id];
+                        state.name.symbol.to_string()
+                    }
+                    _ => unreachable!(),
+                }
+            }
+            Binding::Function(function_id) => {
+                let function = &arena.functions[*function_id].borrow();
+                function.name.symbol.to_string()
+            }
+            Binding::Const(const_id) => arena.consts[*const_id].name.symbol.to_string(),
+            Binding::Component(component_id) => {
+                let component = &arena.components[*component_id].borrow();
+                component.name.symbol.to_string()
+            }
+            Binding::Context(context_id) => arena.contexts[*context_id].name.symbol.to_string(),
+            Binding::Import(specifier_id) => arena.import_specifiers[*specifier_id]
+                .local
+                .symbol
+                .to_string(),
+            Binding::Global(global_id) => arena.globals[*global_id].name.symbol.to_string(),
+            Binding::Variant(variant_id) => arena.variants[*variant_id].name.symbol.to_string(),
+            Binding::Pattern(binding_id) => {
+                arena.pattern_bindings[*binding_id].name.symbol.to_string()
+            }
+            Binding::Parameter(parameter_id) => {
+                let parameter = &arena.parameters[*parameter_id];
+                parameter.name.symbol.to_string()
+            }
+        }
+    }
+
+    /// The identifier this binding was defined with.
+    pub fn identifier(&self, arena: &AstArena) -> Identifier {
+        match self {
+            Binding::Let(statement_id) => match &arena.statements[*statement_id] {
+                Statement::Let { name, .. } => *name,
+                _ => unreachable!(),
+            },
+            Binding::State(statement_id) => match &arena.statements[*statement_id] {
+                Statement::State(state_id) => arena.states[*state_id].name,
+                _ => unreachable!(),
+            },
+            Binding::Const(const_id) => arena.consts[*const_id].name,
+            Binding::Function(function_id) => arena.functions[*function_id].borrow().name,
+            Binding::Parameter(parameter_id) => arena.parameters[*parameter_id].name,
+            Binding::Component(component_id) => arena.components[*component_id].borrow().name,
+            Binding::Context(context_id) => arena.contexts[*context_id].name,
+            Binding::Import(specifier_id) => arena.import_specifiers[*specifier_id].local,
+            Binding::Global(global_id) => arena.globals[*global_id].name,
+            Binding::Variant(variant_id) => arena.variants[*variant_id].name,
+            Binding::Pattern(binding_id) => arena.pattern_bindings[*binding_id].name,
+        }
+    }
+
+    /// Whether this binding can be assigned to. Only `state` and `let mut`
+    /// bindings are mutable.
+    pub fn is_mutable(&self, arena: &AstArena) -> bool {
+        match self {
+            Binding::State(_) => true,
+            Binding::Let(statement_id) => match &arena.statements[*statement_id] {
+                Statement::Let { mutable, .. } => *mutable,
+                _ => unreachable!(),
+            },
+            Binding::Const(_)
+            | Binding::Function(_)
+            | Binding::Parameter(_)
+            | Binding::Component(_)
+            | Binding::Context(_)
+            | Binding::Import(_)
+            | Binding::Global(_)
+            | Binding::Variant(_)
+            | Binding::Pattern(_) => false,
+        }
+    }
+
+    pub fn to_state(&self, arena: &AstArena) -> Option<StateId> {
+        match self {
+            Binding::State(state_id) => {
+                let statement = &arena.statements[*state_id];
+                match statement {
+                    Statement::State(state_id) => Some(*state_id),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Into<ComponentId> for Binding {
+    fn into(self) -> ComponentId {
+        match self {
+            Binding::Component(id) => id,
+            _ => unreachable!(),
+        }
+    }
 }
 
+impl Referant for Binding {}
+
+/// An enum, like `enum Shape { Circle(number) Square(number) Empty }`. Its
+/// variants are in scope wherever the enum is.
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct State {
+pub struct Enum {
     pub name: Identifier,
-    pub unique_name: UniqueName,
-    pub value: Expression,
+    pub variants: Vec<VariantId>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Statement {
-    pub span: Span,
-    pub kind: StatementKind,
+pub struct Variant {
+    pub name: Identifier,
+    /// The enum this is a variant of
+    pub enum_id: EnumId,
+    /// The types of the values the variant holds, if it holds any.
+    pub types: Option<Vec<Type>>,
 }
 
-impl Statement {
-    pub fn new(kind: StatementKind, span: Span) -> Result<Self> {
-        Ok(Statement { span, kind })
+impl Variant {
+    /// How many values the variant holds.
+    pub fn arity(&self) -> usize {
+        self.types.as_ref().map_or(0, |types| types.len())
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Block {
-    pub statements: Vec<StatementId>,
+
+pub struct TemplateOpenTag {
+    pub name: Identifier,
+    pub reference: Option<Binding>,
+    pub attributes: Vec<TemplateAttribute>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Function {
+pub struct TemplateAttribute {
     pub name: Identifier,
-    pub is_async: bool,
-    pub type_parameters: Option<TypeParameters>,
-    pub parameters: Option<Vec<Arc<Parameter>>>,
-    pub return_type: Option<TypeExpression>,
-    pub effect_type: Option<Effect>,
-    pub body: Option<Block>,
+    pub value: ExpressionId,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Component {
+pub struct TemplateCloseTag {
     pub name: Identifier,
-    pub is_async: bool,
-    pub type_parameters: Option<TypeParameters>,
-    pub parameters: Option<Vec<Arc<Parameter>>>,
-    pub return_type: Option<TypeExpression>,
-    pub effect_type: Option<Effect>,
-    pub body: Block,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub struct Identifier {
-    pub span: Span,
-    pub symbol: Symbol,
+pub struct Template {
+    pub open_tag: TemplateOpenTag,
+    pub children: Option<Vec<TemplateChild>>,
+    pub close_tag: Option<TemplateCloseTag>,
 }
 
-impl Identifier {
-    pub fn new(symbol: Symbol, span: Span) -> Self {
-        Self { symbol, span }
+impl Template {
+    /// Whether this is a void element like `<img>`, which can't have children.
+    pub fn is_void(&self) -> bool {
+        is_void_element(&self.open_tag.name.symbol.to_string())
     }
 }
 
-impl std::cmp::PartialEq<&str> for Identifier {
-    fn eq(&self, other: &&str) -> bool {
-        format!("{}", self.symbol) == *other
+/// The HTML elements that never have children.
+pub fn is_void_element(name: &str) -> bool {
+    matches!(
+        name,
+        "area"
+            | "base"
+            | "br"
+            | "col"
+            | "embed"
+            | "hr"
+            | "img"
+            | "input"
+            | "link"
+            | "meta"
+            | "source"
+            | "track"
+            | "wbr"
+    )
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TemplateChild {
+    String(Symbol),
+    Expression(ExpressionId),
+    Template(TemplateId),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BinOp;
+
+    #[test]
+    fn binop_to_js() {
+        let cases = [
+            (BinOp::Equals, Some("=")),
+            (BinOp::DoubleEquals, Some("===")),
+            (BinOp::Add, Some("+")),
+            (BinOp::Sub, Some("-")),
+            (BinOp::Sum, Some("+")),
+            (BinOp::Mul, Some("*")),
+            (BinOp::Div, Some("/")),
+            (BinOp::Mod, Some("%")),
+            (BinOp::And, Some("&&")),
+            (BinOp::Or, Some("||")),
+            (BinOp::GreaterThan, Some(">")),
+            (BinOp::GreaterThanEquals, Some(">=")),
+            (BinOp::LessThan, Some("<")),
+            (BinOp::LessThanEquals, Some("<=")),
+            (BinOp::Pipeline, None),
+            (BinOp::BinOr, Some("|")),
+            (BinOp::BinAnd, Some("&")),
+        ];
+        for (op, expected) in cases {
+            assert_eq!(op.to_js(), expected, "{:?}", op);
+        }
     }
 }
@@ -0,0 +1,61 @@
+//! The attributes elements in templates are known to have, which the
+//! language server offers as completions.
+use crate::events::EVENT_HANDLERS;
+
+/// An attribute that isn't an event handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Attribute {
+    pub name: &'static str,
+    /// Whether it's set by being there at all, like `disabled`, rather than
+    /// by its value.
+    pub boolean: bool,
+}
+
+const fn attribute(name: &'static str) -> Attribute {
+    Attribute {
+        name,
+        boolean: false,
+    }
+}
+
+const fn boolean(name: &'static str) -> Attribute {
+    Attribute {
+        name,
+        boolean: true,
+    }
+}
+
+/// The attributes that are known, other than event handlers.
+pub const KNOWN_ATTRIBUTES: &[Attribute] = &[
+    attribute("id"),
+    attribute("class"),
+    attribute("style"),
+    attribute("title"),
+    attribute("href"),
+    attribute("src"),
+    attribute("alt"),
+    attribute("type"),
+    attribute("name"),
+    attribute("value"),
+    attribute("placeholder"),
+    attribute("for"),
+    attribute("role"),
+    attribute("tabindex"),
+    boolean("checked"),
+    boolean("disabled"),
+    boolean("hidden"),
+    boolean("readonly"),
+    boolean("required"),
+    boolean("selected"),
+    boolean("autofocus"),
+    boolean("multiple"),
+    boolean("open"),
+];
+
+/// The name of every known attribute, event handlers last.
+pub fn attribute_names() -> impl Iterator<Item = &'static str> {
+    KNOWN_ATTRIBUTES
+        .iter()
+        .map(|attribute| attribute.name)
+        .chain(EVENT_HANDLERS.iter().map(|handler| handler.attribute))
+}
@@ -0,0 +1,82 @@
+//! Functions every module can call without declaring or importing them.
+
+/// A builtin function. They're referenced like the target's globals, but
+/// they're part of the language, so they're compiled the same way for every
+/// target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Builtin {
+    /// `log(...)`, or `print(...)`, which writes its arguments to the
+    /// console. When the first argument is a string, it's a format string
+    /// where each `{}` is replaced with one of the arguments after it. One
+    /// without placeholders is followed by the arguments, separated by spaces.
+    Log,
+}
+
+impl Builtin {
+    pub fn from_name(name: &str) -> Option<Builtin> {
+        match name {
+            "log" | "print" => Some(Builtin::Log),
+            _ => None,
+        }
+    }
+}
+
+/// A piece of a format string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatPart {
+    Text(String),
+    Placeholder,
+}
+
+/// Split a format string like `"count: {}"` into its text and placeholders.
+/// Braces are written twice to include them in the text, like `{{`. The
+/// error is the offset of a brace that isn't part of a placeholder.
+pub fn parse_format(format: &str) -> Result<Vec<FormatPart>, usize> {
+    let mut parts = vec![];
+    let mut text = String::new();
+    let mut chars = format.char_indices().peekable();
+    while let Some((offset, char)) = chars.next() {
+        match char {
+            '{' | '}' if chars.peek().map(|(_, next)| *next) == Some(char) => {
+                chars.next();
+                text.push(char);
+            }
+            '{' if chars.peek().map(|(_, next)| *next) == Some('}') => {
+                chars.next();
+                if !text.is_empty() {
+                    parts.push(FormatPart::Text(std::mem::take(&mut text)));
+                }
+                parts.push(FormatPart::Placeholder);
+            }
+            '{' | '}' => return Err(offset),
+            _ => text.push(char),
+        }
+    }
+    if !text.is_empty() {
+        parts.push(FormatPart::Text(text));
+    }
+    Ok(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_format, FormatPart};
+
+    #[test]
+    fn format_strings() {
+        assert_eq!(
+            parse_format("{{count}}: {}!"),
+            Ok(vec![
+                FormatPart::Text("{count}: ".into()),
+                FormatPart::Placeholder,
+                FormatPart::Text("!".into()),
+            ])
+        );
+        assert_eq!(
+            parse_format("{}{}"),
+            Ok(vec![FormatPart::Placeholder, FormatPart::Placeholder])
+        );
+        assert_eq!(parse_format("count: {count}"), Err(7));
+        assert_eq!(parse_format("done}"), Err(4));
+    }
+}
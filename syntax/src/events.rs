@@ -0,0 +1,154 @@
+//! The events DOM elements send to handlers like `onClick={handler}`, and
+//! the type of each one.
+use std::borrow::Cow;
+
+/// The type of an event, which is what a handler for it takes. Every event
+/// is an `Event`, and the rest are for events with more to them, like the
+/// key that was pressed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EventType {
+    Event,
+    MouseEvent,
+    KeyboardEvent,
+    FocusEvent,
+    InputEvent,
+    SubmitEvent,
+}
+
+impl EventType {
+    /// The event type named `name` in type position, like `KeyboardEvent`.
+    pub fn from_name(name: &str) -> Option<EventType> {
+        match name {
+            "Event" => Some(EventType::Event),
+            "MouseEvent" => Some(EventType::MouseEvent),
+            "KeyboardEvent" => Some(EventType::KeyboardEvent),
+            "FocusEvent" => Some(EventType::FocusEvent),
+            "InputEvent" => Some(EventType::InputEvent),
+            "SubmitEvent" => Some(EventType::SubmitEvent),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EventType::Event => "Event",
+            EventType::MouseEvent => "MouseEvent",
+            EventType::KeyboardEvent => "KeyboardEvent",
+            EventType::FocusEvent => "FocusEvent",
+            EventType::InputEvent => "InputEvent",
+            EventType::SubmitEvent => "SubmitEvent",
+        }
+    }
+
+    /// Whether a handler that takes `handler` can be sent events of this type.
+    pub fn accepts(&self, handler: EventType) -> bool {
+        handler == EventType::Event || handler == *self
+    }
+}
+
+/// An event handler attribute, like `onClick` for `click` events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EventHandler {
+    /// The attribute the handler is set with, like `onClick`
+    pub attribute: &'static str,
+    /// The event it handles, like `click`
+    pub event: &'static str,
+    pub type_: EventType,
+}
+
+/// Each event handler is listed once, and looked up both by its attribute,
+/// when templates are checked and emitted, and by its event.
+macro_rules! event_handlers {
+    ($($attribute:literal => $event:literal: $type_:ident,)*) => {
+        /// Every event handler that's known, in the order they're offered as
+        /// completions.
+        pub const EVENT_HANDLERS: &[EventHandler] = &[$(EventHandler {
+            attribute: $attribute,
+            event: $event,
+            type_: EventType::$type_,
+        }),*];
+
+        /// The known event handler set with `attribute`, like `onClick`.
+        pub fn known_event_handler(attribute: &str) -> Option<&'static EventHandler> {
+            match attribute {
+                $($attribute => Some(&EventHandler {
+                    attribute: $attribute,
+                    event: $event,
+                    type_: EventType::$type_,
+                }),)*
+                _ => None,
+            }
+        }
+
+        /// The type of the events named `event`, like `click` for `onClick`.
+        /// Events that aren't known are only known to be an `Event`.
+        pub fn event_type(event: &str) -> EventType {
+            match event {
+                $($event => EventType::$type_,)*
+                _ => EventType::Event,
+            }
+        }
+    };
+}
+
+event_handlers! {
+    "onClick" => "click": MouseEvent,
+    "onDblClick" => "dblclick": MouseEvent,
+    "onContextMenu" => "contextmenu": MouseEvent,
+    "onMouseDown" => "mousedown": MouseEvent,
+    "onMouseUp" => "mouseup": MouseEvent,
+    "onMouseMove" => "mousemove": MouseEvent,
+    "onMouseEnter" => "mouseenter": MouseEvent,
+    "onMouseLeave" => "mouseleave": MouseEvent,
+    "onMouseOver" => "mouseover": MouseEvent,
+    "onMouseOut" => "mouseout": MouseEvent,
+    "onKeyDown" => "keydown": KeyboardEvent,
+    "onKeyUp" => "keyup": KeyboardEvent,
+    "onKeyPress" => "keypress": KeyboardEvent,
+    "onFocus" => "focus": FocusEvent,
+    "onBlur" => "blur": FocusEvent,
+    "onFocusIn" => "focusin": FocusEvent,
+    "onFocusOut" => "focusout": FocusEvent,
+    "onInput" => "input": InputEvent,
+    "onBeforeInput" => "beforeinput": InputEvent,
+    "onSubmit" => "submit": SubmitEvent,
+    "onChange" => "change": Event,
+    "onReset" => "reset": Event,
+    "onScroll" => "scroll": Event,
+    "onLoad" => "load": Event,
+    "onError" => "error": Event,
+}
+
+/// The event the attribute `attribute` handles, if it's an event handler.
+/// Known handlers don't need the event worked out from the name; the rest,
+/// like handlers for custom events, handle their name lowercased without
+/// the `on`.
+pub fn handled_event(attribute: &str) -> Option<Cow<'static, str>> {
+    if let Some(handler) = known_event_handler(attribute) {
+        return Some(Cow::Borrowed(handler.event));
+    }
+    match attribute.strip_prefix("on") {
+        Some(event) if !event.is_empty() => Some(Cow::Owned(event.to_lowercase())),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn look_up_event_handlers() {
+        for handler in EVENT_HANDLERS {
+            assert_eq!(known_event_handler(handler.attribute), Some(handler));
+            assert_eq!(event_type(handler.event), handler.type_);
+            // The table agrees with working the event out from the name
+            assert_eq!(handler.attribute[2..].to_lowercase(), handler.event);
+        }
+        assert_eq!(handled_event("onClick").as_deref(), Some("click"));
+        assert_eq!(handled_event("onWidgetOpen").as_deref(), Some("widgetopen"));
+        assert_eq!(handled_event("on"), None);
+        assert_eq!(handled_event("class"), None);
+        assert_eq!(event_type("widgetopen"), EventType::Event);
+    }
+}
@@ -0,0 +1,228 @@
+//! Language features that are still experimental, and the editions that make
+//! features stable. Experimental syntax only parses in modules that enable
+//! it, either with a `#feature(pipeline)` line at the top of the module or
+//! with `pipeline = true` in the `[features]` section of `ws.toml`.
+use crate::span::Span;
+use std::fmt;
+use std::str::FromStr;
+
+/// A version of the language. Features that are stable in an edition can be
+/// used without enabling them.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Edition {
+    #[default]
+    Edition2021,
+}
+
+impl Edition {
+    pub const ALL: [Edition; 1] = [Edition::Edition2021];
+}
+
+impl fmt::Display for Edition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Edition::Edition2021 => write!(f, "2021"),
+        }
+    }
+}
+
+impl FromStr for Edition {
+    type Err = String;
+
+    fn from_str(edition: &str) -> Result<Self, Self::Err> {
+        match edition {
+            "2021" => Ok(Edition::Edition2021),
+            _ => Err(format!("unknown edition '{}', expected '2021'", edition)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Feature {
+    /// Effect definitions and the effects a function declares, like
+    /// `effect Log` and `fn f(): number + Log`
+    Effects,
+    /// The pipeline operator, `|>`
+    Pipeline,
+    /// Components declared with `async`
+    AsyncComponents,
+    /// Variant patterns in `match` cases, like `Some(x)`
+    MatchPatterns,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 4] = [
+        Feature::Effects,
+        Feature::Pipeline,
+        Feature::AsyncComponents,
+        Feature::MatchPatterns,
+    ];
+
+    /// The feature enabled with `#feature(name)`.
+    pub fn from_name(name: &str) -> Option<Feature> {
+        Feature::ALL
+            .iter()
+            .copied()
+            .find(|feature| feature.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::Effects => "effects",
+            Feature::Pipeline => "pipeline",
+            Feature::AsyncComponents => "async_components",
+            Feature::MatchPatterns => "match_patterns",
+        }
+    }
+
+    /// What the feature adds, for diagnostics, like "The pipeline operator".
+    pub fn description(self) -> &'static str {
+        match self {
+            Feature::Effects => "Effects",
+            Feature::Pipeline => "The pipeline operator",
+            Feature::AsyncComponents => "Async components",
+            Feature::MatchPatterns => "Match patterns",
+        }
+    }
+
+    /// The first edition the feature is stable in, or `None` while it's
+    /// experimental.
+    pub fn stable_since(self) -> Option<Edition> {
+        match self {
+            Feature::MatchPatterns => Some(Edition::Edition2021),
+            Feature::Effects | Feature::Pipeline | Feature::AsyncComponents => None,
+        }
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// The edition a module is compiled with and the experimental features it
+/// enables.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Features {
+    pub edition: Edition,
+    /// Sorted, and each feature is only in it once.
+    enabled: Vec<Feature>,
+}
+
+impl Features {
+    pub fn new(edition: Edition) -> Self {
+        Features {
+            edition,
+            enabled: vec![],
+        }
+    }
+
+    pub fn enable(&mut self, feature: Feature) {
+        if let Err(index) = self.enabled.binary_search(&feature) {
+            self.enabled.insert(index, feature);
+        }
+    }
+
+    /// Enable the features `other` enables, using the later of the two
+    /// editions.
+    pub fn extend(&mut self, other: &Features) {
+        self.edition = self.edition.max(other.edition);
+        for feature in &other.enabled {
+            self.enable(*feature);
+        }
+    }
+
+    /// Whether `feature` can be used, because it was enabled or it's stable
+    /// in this edition.
+    pub fn is_enabled(&self, feature: Feature) -> bool {
+        match feature.stable_since() {
+            Some(edition) if edition <= self.edition => true,
+            _ => self.enabled.binary_search(&feature).is_ok(),
+        }
+    }
+
+    /// The features enabled explicitly, rather than by the edition.
+    pub fn enabled(&self) -> &[Feature] {
+        &self.enabled
+    }
+
+    /// Enable the features named in the `#feature(...)` lines at the top of
+    /// `source`, handing back the names that aren't features.
+    pub fn enable_directives(&mut self, source: &str) -> Vec<(String, Span)> {
+        let mut unknown = vec![];
+        for (name, span) in feature_directives(source) {
+            match Feature::from_name(&name) {
+                Some(feature) => self.enable(feature),
+                None => unknown.push((name, span)),
+            }
+        }
+        unknown
+    }
+}
+
+/// The names in the `#feature(...)` lines at the top of `source`, along with
+/// where each one is. Lines starting with `#` are comments, so directives
+/// are only looked for before the first line of code.
+pub fn feature_directives(source: &str) -> Vec<(String, Span)> {
+    let mut directives = vec![];
+    let mut line_start = 0;
+    for line in source.split_inclusive('\n') {
+        let start = line_start;
+        line_start += line.len();
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        if !trimmed.starts_with('#') {
+            break;
+        }
+        let names = match trimmed
+            .strip_prefix("#feature(")
+            .and_then(|rest| rest.strip_suffix(')'))
+        {
+            Some(names) => names,
+            None => continue,
+        };
+        // Offsets of the names are found from the line, since trimming
+        // moved them
+        let names_start = start + line.find("#feature(").unwrap() + "#feature(".len();
+        let mut offset = names_start;
+        for name in names.split(',') {
+            let name_start = offset + (name.len() - name.trim_start().len());
+            offset += name.len() + 1;
+            let name = name.trim();
+            if name.is_empty() {
+                continue;
+            }
+            let span = Span::from(name_start..name_start + name.len());
+            directives.push((name.to_string(), span));
+        }
+    }
+    directives
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn enable_features() {
+        let source = "# A counter\n#feature(pipeline, effects)\n\n#feature(nope)\nfn a() {}\n#feature(async_components)\n";
+        let mut features = Features::default();
+        let unknown = features.enable_directives(source);
+        assert!(features.is_enabled(Feature::Pipeline));
+        assert!(features.is_enabled(Feature::Effects));
+        // Directives after code are just comments
+        assert!(!features.is_enabled(Feature::AsyncComponents));
+        // Stable in the default edition
+        assert!(features.is_enabled(Feature::MatchPatterns));
+        assert_eq!(unknown.len(), 1);
+        let range: std::ops::Range<usize> = unknown[0].1.into();
+        assert_eq!(&source[range], "nope");
+
+        let directives = feature_directives(source);
+        let range: std::ops::Range<usize> = directives[1].1.into();
+        assert_eq!(&source[range], "effects");
+    }
+}
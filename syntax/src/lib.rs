@@ -3,10 +3,12 @@ pub mod span;
 pub mod ast;
 pub mod visit;
 pub mod precedence;
-pub mod arena;
+pub mod builtins;
+pub mod events;
+pub mod attributes;
+pub mod arena_pool;
+pub mod features;
 
 pub use token::*;
 pub use span::*;
 pub use precedence::*;
-pub mod ast_;
-pub mod visit_;
\ No newline at end of file
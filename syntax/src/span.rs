@@ -1,47 +1,164 @@
-use std::ops::{Deref, DerefMut, Range};
 use std::fmt::Debug;
+use std::ops::{Deref, DerefMut, Range};
 
+/// A range of bytes in a source. Like `std::ops::Range`, it includes
+/// `start` but not `end`, so an empty span has `start == end`.
 #[derive(Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Span {
     start: u32,
-    end: u32
-}
-
-impl From<usize> for Span {
-    fn from(n: usize) -> Self {
-        Span::new(n as u32, n as u32)
-    }
+    end: u32,
 }
 
 impl Debug for Span {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}..{}", self.start, self.end)
     }
-
 }
 
+impl From<Range<usize>> for Span {
+    fn from(range: Range<usize>) -> Self {
+        Span::new(range.start as u32, range.end as u32)
+    }
+}
 
-
-impl Into<Range<usize>> for Span {
-    fn into(self) -> Range<usize> {
-        self.start as usize..self.end as usize
+impl From<Span> for Range<usize> {
+    fn from(span: Span) -> Self {
+        span.start as usize..span.end as usize
     }
 }
 
 impl Span {
-
+    /// The span from `start` up to, but not including, `end`.
     pub fn new(start: u32, end: u32) -> Span {
+        debug_assert!(start <= end, "span starts after it ends");
         Span { start, end }
     }
 
+    /// An empty span at `offset`, for positions between characters.
+    pub fn empty(offset: u32) -> Span {
+        Span::new(offset, offset)
+    }
+
+    /// The span of `ch`, which starts at `offset`.
+    pub fn char(offset: usize, ch: char) -> Span {
+        Span::from(offset..offset + ch.len_utf8())
+    }
+
+    pub fn start(self) -> usize {
+        self.start as usize
+    }
+
+    pub fn end(self) -> usize {
+        self.end as usize
+    }
+
+    pub fn len(self) -> usize {
+        (self.end - self.start) as usize
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.start == self.end
+    }
+
+    /// Whether the byte at `offset` is in the span.
+    pub fn contains(self, offset: usize) -> bool {
+        self.start() <= offset && offset < self.end()
+    }
+
+    /// The smallest span covering both spans, and anything between them.
     pub fn merge(self, other: Span) -> Span {
-        use std::cmp::{min, max};
+        use std::cmp::{max, min};
         let start = min(self.start, other.start);
         let end = max(self.end, other.end);
         Span::new(start, end)
     }
 }
 
+/// A zero-based line, and column within it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineColumn {
+    pub line: usize,
+    pub column: usize,
+}
+
+/// The closest offset at or before `offset` that's on a character boundary
+/// in `source`. Offsets past the end are clamped to it.
+pub fn floor_char_boundary(source: &str, offset: usize) -> usize {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+/// The closest offset at or after `offset` that's on a character boundary
+/// in `source`. Offsets past the end are clamped to it.
+pub fn ceil_char_boundary(source: &str, offset: usize) -> usize {
+    let mut offset = offset.min(source.len());
+    while !source.is_char_boundary(offset) {
+        offset += 1;
+    }
+    offset
+}
+
+impl Span {
+    /// Widen the span to cover every character it overlaps in `source`, so
+    /// it can be used to slice it.
+    pub fn align_to_chars(self, source: &str) -> Span {
+        let start = floor_char_boundary(source, self.start());
+        let end = ceil_char_boundary(source, self.end()).max(start);
+        Span::from(start..end)
+    }
+}
+
+/// The line `offset` is on in `source`, with the column counted in
+/// characters.
+pub fn line_column(source: &str, offset: usize) -> LineColumn {
+    let (line, line_start) = line_start(source, offset);
+    let column = source[line_start..floor_char_boundary(source, offset)]
+        .chars()
+        .count();
+    LineColumn { line, column }
+}
+
+/// The line `offset` is on in `source`, with the column counted in UTF-16
+/// code units. This is how editors using the language server protocol
+/// count them.
+pub fn utf16_line_column(source: &str, offset: usize) -> LineColumn {
+    let (line, line_start) = line_start(source, offset);
+    let column = source[line_start..floor_char_boundary(source, offset)]
+        .encode_utf16()
+        .count();
+    LineColumn { line, column }
+}
+
+/// The byte offset in `source` of a line and a column counted in UTF-16
+/// code units. Columns past the end of the line, or in the middle of a
+/// character, are moved back to the end of it or the start of the character.
+pub fn utf16_offset(source: &str, position: LineColumn) -> usize {
+    let line_start = source
+        .split_inclusive('\n')
+        .take(position.line)
+        .map(str::len)
+        .sum::<usize>();
+    let mut column = 0;
+    for (offset, ch) in source[line_start..].char_indices() {
+        column += ch.len_utf16();
+        if column > position.column || ch == '\n' {
+            return line_start + offset;
+        }
+    }
+    source.len()
+}
+
+/// The zero-based line `offset` is on, and the offset that line starts at.
+fn line_start(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..floor_char_boundary(source, offset)];
+    let line = before.matches('\n').count();
+    let start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    (line, start)
+}
+
 pub struct Spanned<T> {
     value: T,
     span: Span,
@@ -54,7 +171,6 @@ impl<T> Spanned<T> {
     }
 }
 
-
 impl<T> Deref for Spanned<T> {
     type Target = T;
     fn deref(&self) -> &Self::Target {
@@ -64,6 +180,59 @@ impl<T> Deref for Spanned<T> {
 
 impl<T> DerefMut for Spanned<T> {
     fn deref_mut(&mut self) -> &mut Self::Target {
-       &mut self.value 
+        &mut self.value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn convert_offsets_in_multibyte_text() {
+        // `é` is 2 bytes and 1 UTF-16 unit, `🎉` is 4 bytes and 2 units
+        let source = "let é = \"🎉\"\nlet b = é";
+        let offset = source.find("\"").unwrap() + 1;
+        assert_eq!(
+            line_column(source, offset),
+            LineColumn { line: 0, column: 9 }
+        );
+        assert_eq!(
+            utf16_line_column(source, offset + 4),
+            LineColumn {
+                line: 0,
+                column: 11
+            }
+        );
+        let b = source.rfind('b').unwrap();
+        assert_eq!(
+            utf16_line_column(source, b),
+            LineColumn { line: 1, column: 4 }
+        );
+        assert_eq!(utf16_offset(source, LineColumn { line: 1, column: 4 }), b);
+        // Between the two units of the emoji
+        assert_eq!(
+            utf16_offset(
+                source,
+                LineColumn {
+                    line: 0,
+                    column: 10
+                }
+            ),
+            offset
+        );
+        assert_eq!(
+            utf16_offset(
+                source,
+                LineColumn {
+                    line: 0,
+                    column: 50
+                }
+            ),
+            source.find('\n').unwrap()
+        );
+        // The middle of the emoji
+        let span = Span::from(offset + 1..offset + 2).align_to_chars(source);
+        assert_eq!(Range::<usize>::from(span), offset..offset + 4);
     }
-}
\ No newline at end of file
+}
@@ -70,8 +70,7 @@ impl Token {
         match &self.kind {
             LParen => Prefix,
             Dot => Prefix,
-            Equals => Assignment,
-            // PlusEquals => ASSIGNMENT,
+            Equals | PlusEquals | MinusEquals | StarEquals | SlashEquals => Assignment,
             // QuestionDot => ASSIGNMENT,
             // Question => CONDITIONAL,
             Plus => Sum,
@@ -106,8 +105,12 @@ pub enum TokenKind {
     Match,
     /// The 'import' keyword
     Import,
+    /// The 'as' keyword
+    As,
     /// The 'let' keyword
     Let,
+    /// The 'mut' keyword
+    Mut,
     /// The 'fn' keyword
     Fn,
     /// The 'state' keyword
@@ -122,6 +125,12 @@ pub enum TokenKind {
     Type,
     /// The 'const' keyword
     Const,
+    /// The 'context' keyword
+    Context,
+    /// The 'provide' keyword
+    Provide,
+    /// The 'use' keyword
+    Use,
     /// The 'for' keyword
     For,
     /// The 'if' keyword
@@ -186,8 +195,18 @@ pub enum TokenKind {
     Minus,
     /// The '/' character
     Slash,
+    /// The '+=' characters
+    PlusEquals,
+    /// The '-=' characters
+    MinusEquals,
+    /// The '*=' characters
+    StarEquals,
+    /// The '/=' characters
+    SlashEquals,
     /// The ':' character
     Colon,
+    /// The ';' character, which can end a statement
+    Semicolon,
     /// The '<' character
     LessThan,
     /// The '<=' character
@@ -198,6 +217,8 @@ pub enum TokenKind {
     GreaterThanEquals,
     /// The '|' character
     Pipe,
+    /// The '?' character
+    Question,
     /// The '_' character
     Underscore,
     /// The range operator, '..'S
@@ -220,17 +241,65 @@ pub enum TokenKind {
     StringType,
 }
 
+impl TokenKind {
+    /// Whether this is a keyword, which is a word that can't be used as an
+    /// identifier. `and` and `or` are left out, since they're also written
+    /// as `&&` and `||`.
+    pub fn is_keyword(&self) -> bool {
+        use TokenKind::*;
+        matches!(
+            self,
+            Effect
+                | Match
+                | Import
+                | As
+                | Let
+                | Mut
+                | Fn
+                | State
+                | Component
+                | Enum
+                | Struct
+                | Type
+                | Const
+                | Context
+                | Provide
+                | Use
+                | For
+                | If
+                | Else
+                | In
+                | While
+                | Await
+                | Async
+                | True
+                | False
+                | Interface
+                | Pub
+                | Return
+                | Boolean
+                | NumberType
+                | StringType
+        )
+    }
+}
+
 impl Display for TokenKind {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             TokenKind::Import => write!(f, "import"),
+            TokenKind::As => write!(f, "as"),
             TokenKind::Let => write!(f, "let"),
+            TokenKind::Mut => write!(f, "mut"),
             TokenKind::Fn => write!(f, "fn"),
             TokenKind::State => write!(f, "state"),
             TokenKind::Component => write!(f, "component"),
             TokenKind::Enum => write!(f, "enum"),
             TokenKind::Struct => write!(f, "struct"),
             TokenKind::Const => write!(f, "const"),
+            TokenKind::Context => write!(f, "context"),
+            TokenKind::Provide => write!(f, "provide"),
+            TokenKind::Use => write!(f, "use"),
             TokenKind::For => write!(f, "for"),
             TokenKind::If => write!(f, "if"),
             TokenKind::Else => write!(f, "else"),
@@ -263,12 +332,18 @@ impl Display for TokenKind {
             TokenKind::Plus => write!(f, "+"),
             TokenKind::Minus => write!(f, "-"),
             TokenKind::Slash => write!(f, "/"),
+            TokenKind::PlusEquals => write!(f, "+="),
+            TokenKind::MinusEquals => write!(f, "-="),
+            TokenKind::StarEquals => write!(f, "*="),
+            TokenKind::SlashEquals => write!(f, "/="),
             TokenKind::Colon => write!(f, ":"),
+            TokenKind::Semicolon => write!(f, ";"),
             TokenKind::LessThan => write!(f, "<"),
             TokenKind::LessThanEquals => write!(f, "<="),
             TokenKind::GreaterThan => write!(f, ">"),
             TokenKind::GreaterThanEquals => write!(f, ">="),
             TokenKind::Pipe => write!(f, "|"),
+            TokenKind::Question => write!(f, "?"),
             TokenKind::Range => write!(f, ".."),
             TokenKind::EOF => write!(f, "EOF"),
             TokenKind::Or => write!(f, "||"),
@@ -280,7 +355,7 @@ impl Display for TokenKind {
             TokenKind::Match => write!(f, "match"),
             TokenKind::Underscore => write!(f, "_"),
             TokenKind::Effect => write!(f, "effect"),
-            TokenKind::Boolean => write!(f, "boolean"),
+            TokenKind::Boolean => write!(f, "bool"),
             TokenKind::NumberType => write!(f, "number"),
             TokenKind::StringType => write!(f, "string"),
         }
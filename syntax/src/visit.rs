@@ -1,83 +1,220 @@
-use std::sync::Arc;
-
-use crate::{
-    arena::{with_mut_function, FunctionId},
-    ast::*,
-};
-use diagnostics::result::Result;
+use crate::ast::*;
+use diagnostics::result::{DiagnosticBag, Result};
 
 pub trait Visitor: Sized {
-    fn visit_module(&mut self, module: &mut Module) -> Result<()> {
-        walk_module(self, module)
+    fn context_mut(&mut self) -> &mut AstArena {
+        unimplemented!()
     }
 
-    fn visit_enum(&mut self, _enum: &mut Arc<Enum>) -> Result<()> {
-        Ok(())
-    }
+    fn context(&self) -> &AstArena;
 
-    fn visit_effect(&mut self, _enum: &mut Arc<EffectDef>) -> Result<()> {
-        Ok(())
+    fn visit_module(&self, module_id: ModuleId) -> Result<()> {
+        walk_module(self, module_id)
     }
 
-    fn visit_struct(&mut self, _struct: &mut Arc<Struct>) -> Result<()> {
-        Ok(())
+    fn visit_function(&self, function_id: FunctionId) -> Result<()> {
+        walk_function(self, function_id)
     }
 
-    fn visit_const(&mut self, _const: &mut Arc<Const>) -> Result<()> {
-        Ok(())
+    fn visit_component(&self, component_id: ComponentId) -> Result<()> {
+        walk_component(self, component_id)
     }
 
-    fn visit_function(&mut self, function_id: &mut FunctionId) -> Result<()> {
-        with_mut_function(*function_id, |function| {
-            walk_function(self, function).unwrap()
-        });
-        Ok(())
+    fn visit_expression(&self, expression: ExpressionId) -> Result<()> {
+        walk_expression(self, expression)
     }
 
-    fn visit_component(&mut self, _component: &mut Arc<Component>) -> Result<()> {
-        Ok(())
+    fn visit_statement(&self, statement_id: StatementId) -> Result<()> {
+        walk_statement(self, statement_id)
     }
 
-    fn visit_import(&mut self, _import: &mut Import) -> Result<()> {
+    fn visit_const(&self, const_id: ConstId) -> Result<()> {
+        let arena = self.context();
+        let const_ = arena.consts.get(const_id).unwrap();
+        // let value = arena.expressions.get(const_.value).unwrap();
+        // let mut value = value.borrow_mut();
+        self.visit_expression(const_.value);
         Ok(())
     }
 }
 
-pub fn walk_function(_visitor: &mut impl Visitor, _function: &mut Function) -> Result<()> {
- Ok(())
+fn walk_module(visitor: &impl Visitor, module_id: ModuleId) -> Result<()> {
+    let module = visitor.context().modules.get(module_id).unwrap();
+    // Definitions are checked independently, so an error in one doesn't stop
+    // the others from being checked.
+    let mut errors = DiagnosticBag::new();
+    for definition in &module.definitions {
+        let result = match definition.kind {
+            DefinitionKind::Function(function_id) => visitor.visit_function(function_id),
+            DefinitionKind::Component(component_id) => visitor.visit_component(component_id),
+            DefinitionKind::Const(const_) => {
+                let arena = visitor.context();
+                let const_ = arena.consts.get(const_).unwrap();
+                visitor.visit_expression(const_.value)
+            }
+            DefinitionKind::Context(context_id) => {
+                let arena = visitor.context();
+                let context = arena.contexts.get(context_id).unwrap();
+                visitor.visit_expression(context.value)
+            }
+            DefinitionKind::Effect(_)
+            | DefinitionKind::Import(_)
+            | DefinitionKind::Enum(_)
+            | DefinitionKind::TypeAlias(_) => Ok(()),
+            DefinitionKind::Struct(_) => todo!(),
+        };
+        errors.report(result)?;
+    }
+    errors.into_result()
 }
 
-pub fn walk_module(visitor: &mut impl Visitor, module: &mut Module) -> Result<()> {
-    for import in &mut module.imports {
-        visitor.visit_import(import)?;
+fn walk_template(visitor: &impl Visitor, template_id: TemplateId) -> Result<()> {
+    let template = visitor.context().templates.get(template_id).unwrap();
+    let template = template.borrow();
+    let open_tag = &template.open_tag;
+
+    for TemplateAttribute { value, .. } in &open_tag.attributes {
+        visitor.visit_expression(*value)?;
     }
 
-    for definition in &mut module.definitions {
-        match &mut definition.kind {
-            DefinitionKind::Enum(enum_) => {
-                visitor.visit_enum(enum_)?;
+    if let Some(children) = &template.children {
+        for child in children {
+            match child {
+                TemplateChild::String(_) => {}
+                TemplateChild::Expression(expression_id) => {
+                    visitor.visit_expression(*expression_id)?;
+                }
+                TemplateChild::Template(template_id) => walk_template(visitor, *template_id)?,
             }
-            DefinitionKind::Effect(effect_) => {
-                visitor.visit_effect(effect_)?;
-            }
-            DefinitionKind::Function(function_id) => {
-                visitor.visit_function(function_id)?;
+        }
+    }
+    Ok(())
+}
+
+pub fn walk_expression(visitor: &impl Visitor, expression: ExpressionId) -> Result<()> {
+    let arena = visitor.context();
+    let expression = arena.expressions.get(expression).unwrap();
+    let expression = expression.borrow();
+    match &*expression {
+        Expression::Template(template_id) => {
+            walk_template(visitor, *template_id)?;
+        }
+        Expression::Function(function_id) => {
+            visitor.visit_function(*function_id)?;
+        }
+        Expression::Binary { left, right, .. } => {
+            visitor.visit_expression(*left)?;
+            visitor.visit_expression(*right)?;
+        }
+        Expression::Unary { operand, .. } => {
+            visitor.visit_expression(*operand)?;
+        }
+        Expression::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            visitor.visit_expression(*condition)?;
+            walk_block(visitor, *then_branch)?;
+            if let Some(else_branch) = else_branch {
+                walk_block(visitor, *else_branch)?;
             }
-            // DefinitionKind::Struct(_) => {}
-            DefinitionKind::Struct(struct_) => {
-                visitor.visit_struct(struct_)?;
+        }
+        Expression::Call { callee, arguments } => {
+            visitor.visit_expression(*callee)?;
+            for argument in arguments {
+                visitor.visit_expression(argument.value)?;
             }
-            DefinitionKind::Component(component) => {
-                visitor.visit_component(component)?;
+        }
+        Expression::Variant { arguments, .. } => {
+            for argument in arguments {
+                visitor.visit_expression(*argument)?;
             }
-            DefinitionKind::Const(const_) => {
-                visitor.visit_const(const_)?;
+        }
+        Expression::Array(elements) => {
+            for element in elements {
+                visitor.visit_expression(*element)?;
             }
-            DefinitionKind::Type(_type_) => {
-                // visitor.visit_type(type_)?;
+        }
+        Expression::Match { value, cases } => {
+            visitor.visit_expression(*value)?;
+            for case in cases {
+                if let MatchPattern::Expression(pattern) = case.pattern {
+                    visitor.visit_expression(pattern)?;
+                }
+                visitor.visit_expression(case.body)?;
             }
         }
+        Expression::Await(value) => {
+            visitor.visit_expression(*value)?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+pub fn walk_function(visitor: &impl Visitor, function_id: FunctionId) -> Result<()> {
+    let arena = visitor.context();
+    let function = arena.functions.get(function_id).unwrap();
+    let function = function.borrow();
+    walk_block(visitor, function.body.unwrap())
+}
+
+pub fn walk_component(visitor: &impl Visitor, component_id: ComponentId) -> Result<()> {
+    let arena = visitor.context();
+    let component = arena.components.get(component_id).unwrap();
+    let component = component.borrow();
+    walk_block(visitor, component.body.unwrap())
+}
+
+fn walk_block(visitor: &impl Visitor, block_id: BlockId) -> Result<()> {
+    let arena = visitor.context();
+    let block = arena.blocks.get(block_id).unwrap();
+    for statement_id in &block.statements {
+        visitor.visit_statement(*statement_id)?;
+    }
+    Ok(())
+}
+
+pub fn walk_statement(visitor: &impl Visitor, statement_id: StatementId) -> Result<()> {
+    let arena = visitor.context();
+    let statement = arena.statements.get(statement_id).unwrap();
+    match statement {
+        Statement::Expression(expression_id) | Statement::Discard(expression_id) => {
+            visitor.visit_expression(*expression_id)?;
+        }
+        Statement::Let { value, .. } => {
+            visitor.visit_expression(*value)?;
+        }
+        Statement::State(state_id) => {
+            let state = arena.states.get(*state_id).unwrap();
+            visitor.visit_expression(state.value)?;
+        }
+        Statement::Return(expression_id) => {
+            visitor.visit_expression(*expression_id)?;
+        }
+        Statement::If(if_) => {
+            walk_if(visitor, if_)?;
+        }
+        Statement::While { condition, body } => {
+            visitor.visit_expression(*condition)?;
+            walk_block(visitor, *body)?;
+        }
+        Statement::Assignment { value, .. } | Statement::Provide { value, .. } => {
+            visitor.visit_expression(*value)?;
+        }
     }
+    Ok(())
+}
 
+fn walk_if(visitor: &impl Visitor, if_: &If) -> Result<()> {
+    visitor.visit_expression(if_.condition)?;
+    walk_block(visitor, if_.body)?;
+    if let Some(else_) = &if_.alternate {
+        match &**else_ {
+            Else::If(if_) => walk_if(visitor, if_)?,
+            Else::Block(block_id) => walk_block(visitor, *block_id)?,
+        }
+    }
     Ok(())
 }
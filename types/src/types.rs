@@ -1,4 +1,3 @@
-
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub enum Type<Struct, Effect, Parameter> {
     Unknown,
@@ -9,4 +8,8 @@ pub enum Type<Struct, Effect, Parameter> {
     Struct(Struct),
     Effect(Effect),
     Parameter(Parameter),
+    /// A list of values that all have the same type, like `[number]`.
+    Array(Box<Self>),
+    /// A value that might not be there, like `number?`.
+    Optional(Box<Self>),
 }
@@ -1,6 +1,10 @@
-use std::path::PathBuf;
+pub mod paths;
+
+pub use codespan_reporting::files::Files;
+use salsa::Durability;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-pub use codespan_reporting::files::{Files};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct FileId(usize);
@@ -9,5 +13,191 @@ pub struct FileId(usize);
 pub trait FileSystem {
     #[salsa::input]
     fn file_text(&self, path: PathBuf) -> Arc<String>;
+    /// The files that take the place of what's on disk.
+    #[salsa::input]
+    fn overlay(&self) -> Arc<Overlay>;
+    /// Whether there's a file at `path`, like a module an import refers to.
+    fn file_exists(&self, path: PathBuf) -> bool;
+    /// The text of the file at `path`, from the overlay or else from disk,
+    /// for files that aren't inputs, like the modules a module imports.
+    fn read_file(&self, path: PathBuf) -> Option<Arc<String>>;
+    /// Every module under `root`, in order.
+    fn module_files(&self, root: PathBuf) -> Arc<Vec<PathBuf>>;
+}
+
+/// Files that take the place of the ones on disk, like the unsaved buffers
+/// in an editor. File systems that are only in memory, like in tests, don't
+/// read from disk at all.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Overlay {
+    files: BTreeMap<PathBuf, Arc<String>>,
+    disk: bool,
+}
+
+impl Default for Overlay {
+    /// An empty overlay over the disk.
+    fn default() -> Self {
+        Overlay {
+            files: BTreeMap::new(),
+            disk: true,
+        }
+    }
+}
+
+impl Overlay {
+    /// A file system with only the files in the overlay.
+    pub fn in_memory() -> Self {
+        Overlay {
+            files: BTreeMap::new(),
+            disk: false,
+        }
+    }
+
+    pub fn insert(&mut self, path: PathBuf, text: Arc<String>) {
+        self.files.insert(path, text);
+    }
+
+    /// Stop overlaying `path`, so it's read from disk again.
+    pub fn remove(&mut self, path: &Path) {
+        self.files.remove(path);
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&Arc<String>> {
+        self.files.get(path)
+    }
+}
+
+/// Whether `path` is a module, rather than a directory of them.
+pub fn is_module(path: &Path) -> bool {
+    path.extension().and_then(|extension| extension.to_str()) == Some("ws")
+}
+
+/// Start a new revision after files changed on disk, like when the watcher
+/// sees one, so what was read from disk is read again.
+pub fn disk_changed(db: &mut dyn FileSystem) {
+    db.salsa_runtime_mut().synthetic_write(Durability::LOW);
+}
+
+fn file_exists(db: &dyn FileSystem, path: PathBuf) -> bool {
+    let overlay = db.overlay();
+    if overlay.get(&path).is_some() {
+        return true;
+    }
+    if !overlay.disk {
+        return false;
+    }
+    // This reads the file system instead of an input, so it has to be
+    // checked again whenever anything changes.
+    db.salsa_runtime().report_untracked_read();
+    path.is_file()
+}
+
+fn read_file(db: &dyn FileSystem, path: PathBuf) -> Option<Arc<String>> {
+    let overlay = db.overlay();
+    if let Some(text) = overlay.get(&path) {
+        return Some(text.clone());
+    }
+    if !overlay.disk {
+        return None;
+    }
+    db.salsa_runtime().report_untracked_read();
+    std::fs::read_to_string(path).ok().map(Arc::new)
+}
+
+fn module_files(db: &dyn FileSystem, root: PathBuf) -> Arc<Vec<PathBuf>> {
+    let overlay = db.overlay();
+    let mut files: Vec<PathBuf> = overlay
+        .files
+        .keys()
+        .filter(|path| path.starts_with(&root) && is_module(path))
+        .cloned()
+        .collect();
+    if overlay.disk {
+        db.salsa_runtime().report_untracked_read();
+        // A module on its own is the only module under it
+        if root.is_file() {
+            files.push(root.clone());
+        } else {
+            walk_modules(&root, &mut files);
+        }
+    }
+    files.sort();
+    files.dedup();
+    Arc::new(files)
 }
 
+/// Add the modules in `directory` and the directories under it to `files`.
+/// Directories that can't be read are skipped.
+fn walk_modules(directory: &Path, files: &mut Vec<PathBuf>) {
+    let entries = match std::fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let path = entry.path();
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => walk_modules(&path, files),
+            Ok(_) if is_module(&path) && path.is_file() => files.push(path),
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[salsa::database(FileSystemDatabase)]
+    #[derive(Default)]
+    struct TestDatabase {
+        storage: salsa::Storage<Self>,
+    }
+
+    impl salsa::Database for TestDatabase {}
+
+    #[test]
+    fn overlay_files() {
+        let mut db = TestDatabase::default();
+        let mut overlay = Overlay::in_memory();
+        overlay.insert("/app/main.ws".into(), Arc::new("a".to_string()));
+        overlay.insert("/app/ui/button.ws".into(), Arc::new("b".to_string()));
+        overlay.insert("/app/notes.txt".into(), Arc::new("c".to_string()));
+        db.set_overlay(Arc::new(overlay));
+        assert_eq!(
+            *db.module_files("/app".into()),
+            vec![
+                PathBuf::from("/app/main.ws"),
+                PathBuf::from("/app/ui/button.ws")
+            ]
+        );
+        assert!(db.file_exists("/app/main.ws".into()));
+        assert_eq!(
+            db.read_file("/app/ui/button.ws".into())
+                .as_deref()
+                .map(String::as_str),
+            Some("b")
+        );
+        assert_eq!(db.read_file("/etc/hostname".into()), None);
+
+        // Unsaved text takes the place of what's on disk
+        let root = std::env::temp_dir().join(format!("ws-vfs-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("lib")).unwrap();
+        std::fs::write(root.join("main.ws"), "saved").unwrap();
+        std::fs::write(root.join("lib/util.ws"), "util").unwrap();
+        let mut overlay = Overlay::default();
+        overlay.insert(root.join("main.ws"), Arc::new("unsaved".to_string()));
+        db.set_overlay(Arc::new(overlay));
+        assert_eq!(
+            db.read_file(root.join("main.ws")).unwrap().as_str(),
+            "unsaved"
+        );
+        assert_eq!(
+            *db.module_files(root.clone()),
+            vec![root.join("lib/util.ws"), root.join("main.ws")]
+        );
+        std::fs::write(root.join("new.ws"), "new").unwrap();
+        disk_changed(&mut db);
+        assert_eq!(db.module_files(root.clone()).len(), 3);
+        std::fs::remove_dir_all(&root).ok();
+    }
+}
@@ -0,0 +1,130 @@
+//! Turning the paths people pass on the command line into the ones modules
+//! are stored under, the same way on every platform.
+use std::io;
+use std::path::{Component, Path, PathBuf, Prefix};
+
+/// Whether the file systems of this platform usually ignore case, so
+/// `Main.ws` and `main.ws` are the same file.
+pub const CASE_INSENSITIVE: bool = cfg!(any(windows, target_os = "macos"));
+
+/// `path` made absolute against the current directory, with `.` and `..`
+/// worked out from the path itself. Nothing is read from disk, so it works
+/// for directories that haven't been created yet, like an output directory.
+pub fn normalize(path: &Path) -> io::Result<PathBuf> {
+    let path = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()?.join(path)
+    };
+    Ok(normalize_components(&path))
+}
+
+fn normalize_components(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            // `..` at the root stays at the root
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// `path` with symlinks resolved, like `std::fs::canonicalize`, except that
+/// the parts of it that don't exist yet are kept as they're written, and
+/// Windows paths don't get the `\\?\` prefix that most tools can't read.
+pub fn canonicalize(path: &Path) -> io::Result<PathBuf> {
+    let path = normalize(path)?;
+    // The deepest ancestor that exists is resolved, and the rest is added
+    // back on
+    let mut existing = path.as_path();
+    let mut missing = vec![];
+    let resolved = loop {
+        match std::fs::canonicalize(existing) {
+            Ok(resolved) => break resolved,
+            Err(error) => match (existing.parent(), existing.file_name()) {
+                (Some(parent), Some(name)) => {
+                    missing.push(name.to_os_string());
+                    existing = parent;
+                }
+                _ => return Err(error),
+            },
+        }
+    };
+    let mut canonical = strip_verbatim(&resolved);
+    canonical.extend(missing.iter().rev());
+    Ok(canonical)
+}
+
+/// `path` without a verbatim prefix, like `C:\a` for `\\?\C:\a` and
+/// `\\server\share\a` for `\\?\UNC\server\share\a`. Other paths are left
+/// alone.
+pub fn strip_verbatim(path: &Path) -> PathBuf {
+    let mut components = path.components();
+    let prefix = match components.next() {
+        Some(Component::Prefix(prefix)) => prefix.kind(),
+        _ => return path.to_path_buf(),
+    };
+    let prefix = match prefix {
+        Prefix::VerbatimDisk(disk) => format!("{}:", disk as char),
+        Prefix::VerbatimUNC(server, share) => format!(
+            r"\\{}\{}",
+            server.to_string_lossy(),
+            share.to_string_lossy()
+        ),
+        _ => return path.to_path_buf(),
+    };
+    let mut stripped = PathBuf::from(prefix);
+    stripped.extend(components);
+    stripped
+}
+
+/// Whether `a` and `b` are the same path, ignoring case where the file
+/// system does. Neither is read from disk, so they should be normalized.
+pub fn same_path(a: &Path, b: &Path) -> bool {
+    if CASE_INSENSITIVE {
+        same_path_ignoring_case(a, b)
+    } else {
+        a == b
+    }
+}
+
+fn same_path_ignoring_case(a: &Path, b: &Path) -> bool {
+    let lowercase = |path: &Path| path.to_string_lossy().to_lowercase();
+    a == b || lowercase(a) == lowercase(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_paths() {
+        let root = std::env::temp_dir().join(format!("ws-paths-{}", std::process::id()));
+        let missing = root.join("a/./b/../c");
+        assert_eq!(normalize(&missing).unwrap(), root.join("a/c"));
+        assert!(normalize(Path::new("main.ws")).unwrap().is_absolute());
+
+        // Only the part that exists is resolved
+        std::fs::create_dir_all(&root).unwrap();
+        let canonical = canonicalize(&root.join("output/lib/../app.js")).unwrap();
+        assert_eq!(
+            canonical,
+            std::fs::canonicalize(&root).unwrap().join("output/app.js")
+        );
+        std::fs::remove_dir_all(&root).ok();
+
+        assert!(same_path_ignoring_case(
+            Path::new("/src/Main.ws"),
+            Path::new("/src/main.ws")
+        ));
+        assert!(!same_path_ignoring_case(
+            Path::new("/src/main.ws"),
+            Path::new("/src/app.ws")
+        ));
+    }
+}